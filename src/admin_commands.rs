@@ -1,9 +1,47 @@
 use crate::server::{HQMServer, MuteStatus, PlayerListExt, ServerPlayerData};
 
+use crate::ban::{Ban, BanTarget};
+use crate::commands::{format_duration, parse_duration, wrap_chat_lines};
 use crate::game::{PlayerId, PlayerIndex};
 use crate::gamemode::{ExitReason, GameMode};
+use crate::record::{AnnotationKind, Bookmark, GameSummary};
 use crate::ReplayRecording;
-use tracing::info;
+use std::fmt::Display;
+use tracing::{info, warn};
+
+/// Ticks a kicked or banned player can read the reason before they are removed.
+const BAN_KICK_DELAY: u32 = 300;
+
+/// How a player is removed by an admin.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct KickOptions<'a> {
+    /// If true, the IP address of the player is also banned.
+    pub ban: bool,
+    /// Minutes the ban lasts, or [`None`] for a permanent ban.
+    pub minutes: Option<u32>,
+    /// Reason that is shown to the player, and stored with the ban.
+    pub reason: Option<&'a str>,
+}
+
+impl<'a> KickOptions<'a> {
+    /// Parses the options of a remote kick or ban, which are like the arguments of /kick and /ban after the ID.
+    /// Like for /ban, a ban duration that starts with a digit but isn't valid is an error.
+    pub(crate) fn parse(ban: bool, s: &'a str) -> Result<Self, &'static str> {
+        let s = s.trim();
+        let (minutes, reason) = match s.split_once(' ').unwrap_or((s, "")) {
+            (first, rest) if ban && first.starts_with(|c: char| c.is_ascii_digit()) => (
+                Some(parse_duration(first).ok_or("Invalid duration")?),
+                rest.trim(),
+            ),
+            _ => (None, s),
+        };
+        Ok(KickOptions {
+            ban,
+            minutes,
+            reason: (!reason.is_empty()).then_some(reason),
+        })
+    }
+}
 
 impl HQMServer {
     pub(crate) fn set_allow_join(&mut self, admin_player_id: PlayerId, allowed: bool) {
@@ -30,10 +68,47 @@ impl HQMServer {
         }
     }
 
+    /// Saves the mute status of a player in the mute list, so that it's applied again if the player rejoins.
+    pub(crate) fn store_mute(
+        &mut self,
+        player_id: PlayerId,
+        status: MuteStatus,
+        minutes: Option<u32>,
+    ) {
+        let Some(player) = self.state.players.players.get_player(player_id) else {
+            return;
+        };
+        let ServerPlayerData::NetworkPlayer { data } = &player.data else {
+            return;
+        };
+        let ip = data.addr.ip();
+        let account = data.identity.as_ref().map(|x| x.account.clone());
+        let until = minutes.map(|m| self.now() + chrono::Duration::minutes(m as i64));
+        self.mutes.set(ip, account.as_deref(), status, until);
+    }
+
+    /// Updates the mute status of a connected player from the mute list, which unmutes the player if the mute has expired.
+    pub(crate) fn update_mute_status(&mut self, player_id: PlayerId) {
+        let now = self.now();
+        let Some(player) = self.state.players.players.get_player_mut(player_id) else {
+            return;
+        };
+        let ServerPlayerData::NetworkPlayer { data } = &player.data else {
+            return;
+        };
+        let account = data.identity.as_ref().map(|x| x.account.as_str());
+        let status = self.mutes.check(data.addr.ip(), account, now);
+        if player.is_muted == MuteStatus::Muted && status == MuteStatus::NotMuted {
+            info!("Mute of {} ({}) expired", player.player_name, player_id);
+        }
+        player.is_muted = status;
+    }
+
     pub(crate) fn mute_player(
         &mut self,
         admin_player_id: PlayerId,
         mute_player_index: PlayerIndex,
+        minutes: Option<u32>,
     ) {
         if let Some(admin_player) = self
             .state
@@ -54,8 +129,15 @@ impl HQMServer {
                     "{} ({}) muted {} ({})",
                     admin_player_name, admin_player_id, mute_player.player_name, mute_player_id
                 );
-                let msg = format!("{} muted by {}", mute_player.player_name, admin_player_name);
+                let msg = match minutes {
+                    Some(minutes) => format!(
+                        "{} muted by {} for {} min",
+                        mute_player.player_name, admin_player_name, minutes
+                    ),
+                    None => format!("{} muted by {}", mute_player.player_name, admin_player_name),
+                };
                 self.state.players.add_server_chat_message(msg);
+                self.store_mute(mute_player_id, MuteStatus::Muted, minutes);
             }
         }
     }
@@ -96,15 +178,16 @@ impl HQMServer {
                         .players
                         .add_directed_server_chat_message(msg, admin_player_id);
                 }
+                self.store_mute(mute_player_id, MuteStatus::NotMuted, None);
             }
         }
     }
 
-    #[allow(dead_code)]
     pub(crate) fn shadowmute_player(
         &mut self,
         admin_player_id: PlayerId,
         mute_player_index: PlayerIndex,
+        minutes: Option<u32>,
     ) {
         if let Some(admin_player) = self
             .state
@@ -143,6 +226,7 @@ impl HQMServer {
                 self.state
                     .players
                     .add_directed_server_chat_message(msg, admin_player_id);
+                self.store_mute(mute_player_id, MuteStatus::ShadowMuted, minutes);
             }
         }
     }
@@ -178,6 +262,34 @@ impl HQMServer {
         }
     }
 
+    pub(crate) fn reload_chat_words(&mut self, admin_player_id: PlayerId) {
+        let Some(admin_player) = self
+            .state
+            .players
+            .players
+            .check_admin_or_deny(admin_player_id)
+        else {
+            return;
+        };
+        let admin_name = admin_player.player_name.clone();
+        let msg = match self.chat_filter.reload_words() {
+            Ok(count) => {
+                info!(
+                    "{} ({}) reloaded the word list, {} words",
+                    admin_name, admin_player_id, count
+                );
+                format!("Word list reloaded, {} words", count)
+            }
+            Err(e) => {
+                warn!("Could not read word file: {}", e);
+                "Could not read the word list".to_owned()
+            }
+        };
+        self.state
+            .players
+            .add_directed_server_chat_message(msg, admin_player_id);
+    }
+
     pub(crate) fn admin_login(&mut self, player_id: PlayerId, password: &str) {
         if let Some(player) = self.state.players.players.get_player_mut(player_id) {
             let msg = if player.is_admin {
@@ -224,7 +336,22 @@ impl HQMServer {
         }
     }
 
-    pub(crate) fn kick_all_matching<B: GameMode>(
+    pub(crate) fn shutdown_server(&mut self, admin_player_id: PlayerId, seconds: Option<u32>) {
+        if let Some(player) = self
+            .state
+            .players
+            .players
+            .check_admin_or_deny(admin_player_id)
+        {
+            info!(
+                "{} ({}) started server shutdown",
+                player.player_name, admin_player_id
+            );
+            self.start_shutdown(seconds.unwrap_or(0));
+        }
+    }
+
+    pub(crate) fn kick_all_matching<B: GameMode + ?Sized>(
         &mut self,
         admin_player_id: PlayerId,
         kick_player_name: &str,
@@ -361,81 +488,325 @@ impl HQMServer {
         }
     }
 
-    pub(crate) fn kick_player<B: GameMode>(
+    pub(crate) fn kick_player(
         &mut self,
         admin_player_id: PlayerId,
         kick_player_index: PlayerIndex,
-        ban_player: bool,
-        behaviour: &mut B,
+        options: KickOptions,
     ) {
-        if let Some(player) = self
+        let Some(admin_player) = self
             .state
             .players
             .players
             .check_admin_or_deny(admin_player_id)
-        {
-            let admin_player_name = player.player_name.clone();
+        else {
+            return;
+        };
+        let admin_player_name = admin_player.player_name.clone();
+        if kick_player_index == admin_player_id.index {
+            let msg = if options.ban {
+                "You cannot ban yourself"
+            } else {
+                "You cannot kick yourself"
+            };
+            self.state
+                .players
+                .add_directed_server_chat_message(msg, admin_player_id);
+            return;
+        }
+        let Some((kick_player_id, _)) = self
+            .state
+            .players
+            .players
+            .get_player_by_index(kick_player_index)
+        else {
+            self.state
+                .players
+                .add_directed_server_chat_message("No player with this ID exists", admin_player_id);
+            return;
+        };
+        if let Err(e) = self.kick(
+            kick_player_id,
+            &admin_player_name,
+            &admin_player_id,
+            options,
+        ) {
+            self.state
+                .players
+                .add_directed_server_chat_message(e, admin_player_id);
+        }
+    }
 
-            if kick_player_index != admin_player_id.index {
-                if let Some((kick_player_id, kick_player)) = self
-                    .state
-                    .players
-                    .players
-                    .get_player_by_index(kick_player_index)
-                {
-                    if let ServerPlayerData::NetworkPlayer { data } = &kick_player.data {
-                        let kick_player_name = kick_player.player_name.clone();
-                        let kick_ip = data.addr.ip().clone();
-                        behaviour.before_player_exit(
-                            self.into(),
-                            kick_player_id,
-                            ExitReason::AdminKicked,
-                        );
-                        self.remove_player(kick_player_id, true);
+    /// Kicks a player on behalf of the admin `admin_name`, and bans the player if `options.ban` is set.
+    /// `admin` identifies the admin in the log, like their player ID or address.
+    ///
+    /// The player is told why, and is disconnected a few seconds later.
+    pub(crate) fn kick(
+        &mut self,
+        kick_player_id: PlayerId,
+        admin_name: &str,
+        admin: &dyn Display,
+        options: KickOptions,
+    ) -> Result<(), &'static str> {
+        let kick_player = self
+            .state
+            .players
+            .players
+            .get_player(kick_player_id)
+            .ok_or("No such player")?;
+        let ServerPlayerData::NetworkPlayer { data } = &kick_player.data else {
+            return Err("Player is not connected over the network");
+        };
+        let kick_player_name = kick_player.player_name.clone();
+        let kick_ip = data.addr.ip();
+
+        let action = if options.ban {
+            let ban = Ban {
+                target: BanTarget::Ip(kick_ip),
+                until: options
+                    .minutes
+                    .map(|minutes| self.now() + chrono::Duration::minutes(minutes as i64)),
+                reason: options.reason.map(str::to_owned),
+            };
+            if !self.ban.add_ban(ban) {
+                return Err("The ban list doesn't support this kind of ban");
+            }
+            "banned"
+        } else {
+            "kicked"
+        };
+        let duration = options
+            .minutes
+            .filter(|_| options.ban)
+            .map(|minutes| format!(" for {}", format_duration(minutes)))
+            .unwrap_or_default();
+        let reason = options
+            .reason
+            .map(|reason| format!(": {}", reason))
+            .unwrap_or_default();
+        info!(
+            "{} ({}) {} {} ({}){}{}",
+            admin_name, admin, action, kick_player_name, kick_player_id, duration, reason
+        );
+        let msg = if options.ban {
+            format!(
+                "{} {} by {}{}",
+                kick_player_name, action, admin_name, duration
+            )
+        } else {
+            format!(
+                "{} {} by {}{}",
+                kick_player_name, action, admin_name, reason
+            )
+        };
+        self.state.players.add_server_chat_message(msg);
+        let notice = format!("You have been {}{}{}", action, duration, reason);
+        self.remove_after_notice(kick_player_id, &notice, ExitReason::AdminKicked);
+        Ok(())
+    }
 
-                        if ban_player {
-                            self.ban.ban_ip(kick_ip);
+    /// Permanently bans a range of IP addresses or a name pattern, and removes the players it applies to.
+    pub(crate) fn ban_target(
+        &mut self,
+        admin_player_id: PlayerId,
+        target: BanTarget,
+        reason: Option<&str>,
+    ) {
+        let Some(admin_player) = self
+            .state
+            .players
+            .players
+            .check_admin_or_deny(admin_player_id)
+        else {
+            return;
+        };
+        let admin_player_name = admin_player.player_name.clone();
+        let ban = Ban {
+            target: target.clone(),
+            until: None,
+            reason: reason.map(str::to_owned),
+        };
+        if !self.ban.add_ban(ban) {
+            self.state.players.add_directed_server_chat_message(
+                "The ban list doesn't support this kind of ban",
+                admin_player_id,
+            );
+            return;
+        }
+        info!(
+            "{} ({}) banned {}",
+            admin_player_name, admin_player_id, target
+        );
+        let msg = format!("Banned {}", target);
+        self.state
+            .players
+            .add_directed_server_chat_message(msg, admin_player_id);
 
-                            info!(
-                                "{} ({}) banned {} ({})",
-                                admin_player_name,
-                                admin_player_id,
-                                kick_player_name,
-                                kick_player_id
-                            );
-                            let msg =
-                                format!("{} banned by {}", kick_player_name, admin_player_name);
-                            self.state.players.add_server_chat_message(msg);
-                        } else {
-                            info!(
-                                "{} ({}) kicked {} ({})",
-                                admin_player_name,
-                                admin_player_id,
-                                kick_player_name,
-                                kick_player_id
-                            );
-                            let msg =
-                                format!("{} kicked by {}", kick_player_name, admin_player_name);
-                            self.state.players.add_server_chat_message(msg);
-                        }
-                    }
-                }
-            } else {
-                if ban_player {
-                    self.state.players.add_directed_server_chat_message(
-                        "You cannot ban yourself",
-                        admin_player_id,
-                    );
-                } else {
-                    self.state.players.add_directed_server_chat_message(
-                        "You cannot kick yourself",
-                        admin_player_id,
-                    );
-                }
+        let banned_players: Vec<_> = self
+            .state
+            .players
+            .players
+            .iter_players()
+            .filter_map(|(player_id, player)| {
+                let ServerPlayerData::NetworkPlayer { data } = &player.data else {
+                    return None;
+                };
+                (player_id != admin_player_id
+                    && target.applies_to(data.addr.ip(), Some(&player.player_name)))
+                .then(|| (player_id, player.player_name.clone()))
+            })
+            .collect();
+        for (ban_player_id, ban_player_name) in banned_players {
+            info!(
+                "{} ({}) banned {} ({})",
+                admin_player_name, admin_player_id, ban_player_name, ban_player_id
+            );
+            let msg = format!("{} banned by {}", ban_player_name, admin_player_name);
+            self.state.players.add_server_chat_message(msg);
+            let notice = match reason {
+                Some(reason) => format!("You have been banned: {}", reason),
+                None => "You have been banned".to_owned(),
+            };
+            self.remove_after_notice(ban_player_id, &notice, ExitReason::AdminKicked);
+        }
+    }
+
+    /// Tells a player why they are being removed, and moves them to the spectators until they are disconnected
+    /// for `reason`.
+    pub(crate) fn remove_after_notice(
+        &mut self,
+        player_id: PlayerId,
+        notice: &str,
+        reason: ExitReason,
+    ) {
+        for line in wrap_chat_lines(notice) {
+            self.state
+                .players
+                .add_directed_server_chat_message(line, player_id);
+        }
+        self.state.players.move_to_spectator(player_id);
+        if let Some(player) = self.state.players.players.get_player_mut(player_id) {
+            player.input = Default::default();
+            if let ServerPlayerData::NetworkPlayer { data } = &mut player.data {
+                data.kick_timer = Some((BAN_KICK_DELAY, reason));
             }
         }
     }
 
+    /// Marks the current moment of the game with a label, in the recording, its summary and the log.
+    pub(crate) fn bookmark(&mut self, admin_player_id: PlayerId, label: &str) {
+        let Some(admin_player) = self
+            .state
+            .players
+            .players
+            .check_admin_or_deny(admin_player_id)
+        else {
+            return;
+        };
+        let recording =
+            self.config.recording_enabled != ReplayRecording::Off || self.state.summary.league;
+        let bookmark = Bookmark {
+            admin: admin_player.player_name.clone(),
+            label: label.to_owned(),
+            game_step: self.state.replay.game_step,
+            period: self.state.scoreboard.period,
+            time: self.state.scoreboard.time,
+            tick: recording.then_some(self.state.recording_ticks),
+            created: self.now(),
+        };
+        let seconds = bookmark.time / 100;
+        info!(
+            "Bookmark from {} ({}): {} (game {}, step {}, P{} {}:{:02})",
+            bookmark.admin,
+            admin_player_id,
+            bookmark.label,
+            self.game_id,
+            bookmark.game_step,
+            bookmark.period,
+            seconds / 60,
+            seconds % 60
+        );
+        let recording_annotations = self.config.recording_annotations;
+        self.state.players.add_annotation(
+            AnnotationKind::Bookmark,
+            None,
+            None,
+            bookmark.label.clone().into(),
+            recording_annotations,
+        );
+        let msg = format!(
+            "Bookmark added at P{} {}:{:02}",
+            bookmark.period,
+            seconds / 60,
+            seconds % 60
+        );
+        self.state.summary.bookmarks.push(bookmark);
+        self.state
+            .players
+            .add_directed_server_chat_message(msg, admin_player_id);
+    }
+
+    pub(crate) fn clip(&mut self, admin_player_id: PlayerId, seconds: Option<u32>) {
+        let Some(admin_player) = self
+            .state
+            .players
+            .players
+            .check_admin_or_deny(admin_player_id)
+        else {
+            return;
+        };
+        let admin_name = admin_player.player_name.clone();
+        let clip_max = self.config.clip_max;
+        if clip_max == 0 {
+            self.state
+                .players
+                .add_directed_server_chat_message("Clips are disabled", admin_player_id);
+            return;
+        }
+        let seconds = seconds.unwrap_or(10).min(clip_max);
+        let Some((data, ticks)) = self.clip_recording_data(seconds as usize * 100) else {
+            self.state
+                .players
+                .add_directed_server_chat_message("Nothing to clip", admin_player_id);
+            return;
+        };
+        let seconds = ticks.div_ceil(100);
+        info!(
+            "{} ({}) saved a clip of the last {} seconds of game {}",
+            admin_name, admin_player_id, seconds, self.game_id
+        );
+        let summary = GameSummary {
+            league: self.state.summary.league,
+            teams: self.config.team_names.clone(),
+            clip: true,
+            random_seed: self.state.summary.random_seed,
+            ..Default::default()
+        };
+        let now = self.now();
+        self.save_recording
+            .save_recording_data(&self.config, data, now, &summary);
+        let msg = format!("Saved a clip of the last {} seconds", seconds);
+        self.state
+            .players
+            .add_directed_server_chat_message(msg, admin_player_id);
+    }
+
+    pub(crate) fn show_random_seed(&mut self, admin_player_id: PlayerId) {
+        if self
+            .state
+            .players
+            .players
+            .check_admin_or_deny(admin_player_id)
+            .is_none()
+        {
+            return;
+        }
+        let msg = format!("Random seed of game {}: {}", self.game_id, self.rng.seed());
+        self.state
+            .players
+            .add_directed_server_chat_message(msg, admin_player_id);
+    }
+
     pub(crate) fn clear_bans(&mut self, admin_player_id: PlayerId) {
         if let Some(player) = self
             .state
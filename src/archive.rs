@@ -0,0 +1,193 @@
+//! Bundling of recordings, summaries and logs into a single compressed archive.
+//!
+//! The archive is a gzip-compressed tar file. The first entry is `manifest.json`, which lists all
+//! other files with their kind, size and modification time, so that the contents can be indexed
+//! without extracting everything.
+
+use crate::json::{JsonObjectWriter, ToJson};
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFileKind {
+    Recording,
+    /// Recording summaries and other exported statistics.
+    Stats,
+    Log,
+}
+
+impl ArchiveFileKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ArchiveFileKind::Recording => "recording",
+            ArchiveFileKind::Stats => "stats",
+            ArchiveFileKind::Log => "log",
+        }
+    }
+
+    fn directory_name(self) -> &'static str {
+        match self {
+            ArchiveFileKind::Recording => "recordings",
+            ArchiveFileKind::Stats => "stats",
+            ArchiveFileKind::Log => "logs",
+        }
+    }
+}
+
+/// A directory to include files from.
+#[derive(Debug, Clone)]
+pub struct ArchiveSource {
+    pub directory: PathBuf,
+    /// Only files whose names start with this prefix are included.
+    pub prefix: String,
+    /// Only files with this extension are included, or all files if [`None`].
+    pub extension: Option<&'static str>,
+    pub kind: ArchiveFileKind,
+}
+
+struct ArchiveEntry {
+    source: PathBuf,
+    name: String,
+    kind: ArchiveFileKind,
+    size: u64,
+    modified: DateTime<Utc>,
+}
+
+impl ToJson for ArchiveEntry {
+    fn write_json(&self, out: &mut String) {
+        JsonObjectWriter::new(out)
+            .field("path", &self.name)
+            .field("kind", self.kind.as_str())
+            .field("size", &self.size)
+            .field("modified", &self.modified.to_rfc3339())
+            .finish();
+    }
+}
+
+/// Writes all files from the sources that have been modified since `since` to a new archive at `output`.
+///
+/// Returns the number of files in the archive, not counting the manifest.
+pub fn create_archive(
+    sources: &[ArchiveSource],
+    since: DateTime<Utc>,
+    output: &Path,
+) -> io::Result<usize> {
+    let mut entries = vec![];
+    for source in sources {
+        let dir = match fs::read_dir(&source.directory) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for file in dir {
+            let file = file?;
+            let metadata = file.metadata()?;
+            let file_name = file.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            let extension_matches = source.extension.is_none_or(|extension| {
+                Path::new(file_name).extension().and_then(|x| x.to_str()) == Some(extension)
+            });
+            if !metadata.is_file() || !file_name.starts_with(&source.prefix) || !extension_matches {
+                continue;
+            }
+            let modified = DateTime::<Utc>::from(metadata.modified()?);
+            if modified < since {
+                continue;
+            }
+            entries.push(ArchiveEntry {
+                source: file.path(),
+                name: format!("{}/{}", source.kind.directory_name(), file_name),
+                kind: source.kind,
+                size: metadata.len(),
+                modified,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut manifest = String::new();
+    JsonObjectWriter::new(&mut manifest)
+        .field("since", &since.to_rfc3339())
+        .field("created", &Utc::now().to_rfc3339())
+        .field("files", &entries)
+        .finish();
+
+    let file = fs::File::create(output)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::best()));
+    builder.append_data(
+        &mut entry_header(manifest.len() as u64, Utc::now()),
+        "manifest.json",
+        manifest.as_bytes(),
+    )?;
+    for entry in entries.iter() {
+        let data = fs::read(&entry.source)?;
+        builder.append_data(
+            &mut entry_header(data.len() as u64, entry.modified),
+            &entry.name,
+            data.as_slice(),
+        )?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(entries.len())
+}
+
+fn entry_header(size: u64, modified: DateTime<Utc>) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(modified.timestamp().max(0) as u64);
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn archive_contents() {
+        let dir = std::env::temp_dir().join(format!("hqm-archive-{}", std::process::id()));
+        let logs = dir.join("logs");
+        fs::create_dir_all(&logs).unwrap();
+        // Longer than the 100 bytes the plain tar header has room for
+        let long_name = format!("server-{}.log", "x".repeat(120));
+        fs::write(logs.join(&long_name), "log line\n").unwrap();
+        fs::write(logs.join("other.txt"), "ignored").unwrap();
+
+        let sources = [ArchiveSource {
+            directory: logs,
+            prefix: "server".to_owned(),
+            extension: Some("log"),
+            kind: ArchiveFileKind::Log,
+        }];
+        let output = dir.join("season.tar.gz");
+        let since = DateTime::<Utc>::UNIX_EPOCH;
+        assert_eq!(create_archive(&sources, since, &output).unwrap(), 1);
+
+        let mut archive = tar::Archive::new(GzDecoder::new(fs::File::open(&output).unwrap()));
+        let mut files = vec![];
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            files.push((path, content));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, "manifest.json");
+        assert!(files[0].1.contains(&format!("logs/{}", long_name)));
+        assert_eq!(
+            files[1],
+            (format!("logs/{}", long_name), "log line\n".to_owned())
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Verification of player identities.
+//!
+//! Without authentication, players are only identified by the name they choose and their IP address.
+//! An [`AuthProvider`] decides whether a player may join, and can attach a verified [`PlayerIdentity`]
+//! to the player, which game modes can read with [`ServerPlayer::identity`](crate::gamemode::ServerPlayer::identity).
+//!
+//! The client only sends a name when joining, so credentials are passed in the name: a player who joins
+//! as `Name#token` is shown as `Name`, and the token is given to the provider.
+
+use cached::{Cached, TimedCache};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The verified identity of a player.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PlayerIdentity {
+    /// Account name or ID, as given by the provider.
+    pub account: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthResponse {
+    /// The player may join, with a verified identity or as a guest.
+    Allowed(Option<PlayerIdentity>),
+    Denied,
+    /// The answer is not known yet. The join request is ignored, the client will send it again.
+    Pending,
+}
+
+pub trait AuthProvider {
+    /// Called when a player tries to join. `name` is the shown name, without the token.
+    fn authenticate(&mut self, name: &str, token: Option<&str>, ip_addr: IpAddr) -> AuthResponse;
+}
+
+impl<T> AuthProvider for Box<T>
+where
+    T: AuthProvider + ?Sized,
+{
+    fn authenticate(&mut self, name: &str, token: Option<&str>, ip_addr: IpAddr) -> AuthResponse {
+        self.as_mut().authenticate(name, token, ip_addr)
+    }
+}
+
+/// Splits a joining player's name into the shown name and the token, if the name contains one.
+pub fn split_name_token(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once('#') {
+        Some((name, token)) if !token.is_empty() => (name.trim(), Some(token)),
+        Some((name, _)) => (name.trim(), None),
+        None => (name, None),
+    }
+}
+
+/// Accounts read from a text file, with one account per line: the player name followed by the token,
+/// separated by whitespace. Empty lines and lines starting with `#` are ignored.
+///
+/// Registered names can only be used with the right token. Other names can be used by guests, if allowed.
+pub struct FileAuthProvider {
+    accounts: HashMap<String, String>,
+    allow_guests: bool,
+}
+
+impl FileAuthProvider {
+    pub async fn new(path: &Path, allow_guests: bool) -> Result<Self, tokio::io::Error> {
+        let s = tokio::fs::read_to_string(path).await?;
+        let mut accounts = HashMap::new();
+        for line in s.lines().map(|x| x.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((name, token)) = line.rsplit_once(char::is_whitespace) {
+                accounts.insert(name.trim().to_owned(), token.to_owned());
+            }
+        }
+        Ok(Self {
+            accounts,
+            allow_guests,
+        })
+    }
+}
+
+impl AuthProvider for FileAuthProvider {
+    fn authenticate(&mut self, name: &str, token: Option<&str>, _ip_addr: IpAddr) -> AuthResponse {
+        // Tokens are compared in constant time, so that they can't be guessed from response times
+        let matches = |expected: &String, token: &str| {
+            ring::constant_time::verify_slices_are_equal(expected.as_bytes(), token.as_bytes())
+                .is_ok()
+        };
+        match (self.accounts.get(name), token) {
+            (Some(expected), Some(token)) if matches(expected, token) => {
+                AuthResponse::Allowed(Some(PlayerIdentity {
+                    account: name.to_owned(),
+                }))
+            }
+            (None, None) if self.allow_guests => AuthResponse::Allowed(None),
+            _ => AuthResponse::Denied,
+        }
+    }
+}
+
+pub trait ExternalAuthRequests {
+    /// Verifies a name and token, and returns the identity if they are valid.
+    fn req_authenticate(
+        &mut self,
+        name: &str,
+        token: &str,
+        ip_addr: IpAddr,
+    ) -> impl Future<Output = Option<PlayerIdentity>> + Send + 'static;
+}
+
+/// Verifies tokens against an external service. Answers are cached for a short time, so that the
+/// service is asked once even though the client repeats its join request.
+pub struct ExternalAuthProvider<E> {
+    cache: Arc<Mutex<TimedCache<(String, String), AuthResponse>>>,
+    allow_guests: bool,
+    req: E,
+}
+
+impl<E: ExternalAuthRequests> ExternalAuthProvider<E> {
+    pub fn new(req: E, allow_guests: bool) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(TimedCache::with_lifespan(10))),
+            allow_guests,
+            req,
+        }
+    }
+}
+
+impl<E: ExternalAuthRequests> AuthProvider for ExternalAuthProvider<E> {
+    fn authenticate(&mut self, name: &str, token: Option<&str>, ip_addr: IpAddr) -> AuthResponse {
+        let Some(token) = token else {
+            return if self.allow_guests {
+                AuthResponse::Allowed(None)
+            } else {
+                AuthResponse::Denied
+            };
+        };
+        let key = (name.to_owned(), token.to_owned());
+        {
+            let mut handle = self.cache.lock();
+            if let Some(res) = handle.cache_get(&key) {
+                return res.clone();
+            } else {
+                handle.cache_set(key.clone(), AuthResponse::Pending);
+            }
+        }
+
+        let req = self.req.req_authenticate(name, token, ip_addr);
+        let cache = self.cache.clone();
+        tokio::spawn(async move {
+            let res = match req.await {
+                Some(identity) => AuthResponse::Allowed(Some(identity)),
+                None => AuthResponse::Denied,
+            };
+            cache.lock().cache_set(key, res);
+        });
+
+        AuthResponse::Pending
+    }
+}
@@ -0,0 +1,38 @@
+use std::env;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use migo_hqm_server::loadtest::{run_load_test, LoadTestConfiguration};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 2 {
+        eprintln!("Usage: loadtest <address:port> [clients] [seconds]");
+        return Ok(());
+    }
+
+    let server: SocketAddr = args[1]
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Could not resolve {}", args[1]))?;
+    let clients = args.get(2).map_or(Ok(10), |x| x.parse::<usize>())?;
+    let seconds = args.get(3).map_or(Ok(30), |x| x.parse::<u64>())?;
+
+    let config = LoadTestConfiguration {
+        server,
+        clients,
+        duration: Duration::from_secs(seconds),
+        name_prefix: "Load".to_string(),
+    };
+
+    println!(
+        "Running {} clients against {} for {} seconds",
+        clients, server, seconds
+    );
+    let report = run_load_test(config).await?;
+    println!("{}", report);
+
+    Ok(())
+}
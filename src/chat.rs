@@ -0,0 +1,280 @@
+//! Chat moderation.
+//!
+//! Chat messages from players pass a filter before they are sent to the others. The filter limits how
+//! many messages a player can send in a short time, drops messages that repeat the last message of the
+//! player, and replaces words from a word list with asterisks. Each broken rule is an offence, and a player
+//! with too many offences in a row is muted, for twice as long each time. Offences are counted per account,
+//! or per IP address for players without an account, so that rejoining doesn't start over. Admins are not
+//! affected.
+//!
+//! Game modes can inspect and change the messages that pass the filter with
+//! [`GameMode::before_chat_message`](crate::gamemode::GameMode::before_chat_message).
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+use crate::game::PlayerId;
+
+/// Offences are forgotten after this long without new offences.
+const OFFENCE_MEMORY: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct ChatConfiguration {
+    /// Maximum number of messages a player can send within `rate_window`, or 0 for no limit.
+    pub rate_limit: usize,
+    pub rate_window: Duration,
+    /// A message that is the same as the previous message of the player, sent within this time, is dropped.
+    /// Zero allows repeated messages.
+    pub repeat_window: Duration,
+    /// If set, the words in this file, one per line, are replaced with asterisks.
+    pub word_file: Option<PathBuf>,
+    /// Number of offences after which a player is muted, or 0 to never mute players automatically.
+    pub mute_after: u32,
+    /// Minutes of the first automatic mute of a player. Each following mute is twice as long.
+    pub mute_minutes: u32,
+}
+
+impl Default for ChatConfiguration {
+    fn default() -> Self {
+        Self {
+            rate_limit: 0,
+            rate_window: Duration::from_secs(5),
+            repeat_window: Duration::ZERO,
+            word_file: None,
+            mute_after: 0,
+            mute_minutes: 5,
+        }
+    }
+}
+
+/// Result of filtering a chat message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FilteredMessage {
+    /// The message to send, which may have been censored, or [`None`] if it was dropped.
+    pub message: Option<String>,
+    /// Warning to the sender, if the message broke a rule.
+    pub warning: Option<&'static str>,
+    /// If set, the sender should be muted for this many minutes.
+    pub mute_minutes: Option<u32>,
+}
+
+/// Who the offences and command cooldowns of a player count against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Chatter {
+    Account(String),
+    Ip(IpAddr),
+}
+
+/// Recent messages of a connected player.
+#[derive(Default)]
+struct PlayerChatHistory {
+    sent: VecDeque<DateTime<Utc>>,
+    last_message: Option<(String, DateTime<Utc>)>,
+}
+
+/// Offences of an account or IP address, which are kept after the player leaves.
+#[derive(Default)]
+struct Offences {
+    offences: u32,
+    last_offence: Option<DateTime<Utc>>,
+    mutes: u32,
+}
+
+pub(crate) struct ChatFilter {
+    config: ChatConfiguration,
+    words: HashSet<String>,
+    players: HashMap<PlayerId, PlayerChatHistory>,
+    offences: HashMap<Chatter, Offences>,
+}
+
+impl ChatFilter {
+    /// Creates a chat filter, and loads the word list if there is one.
+    pub(crate) fn new(config: ChatConfiguration) -> Self {
+        let words = match &config.word_file {
+            Some(path) => read_word_file(path).unwrap_or_else(|e| {
+                warn!("Could not read word file {}: {}", path.display(), e);
+                HashSet::new()
+            }),
+            None => HashSet::new(),
+        };
+        Self {
+            config,
+            words,
+            players: HashMap::new(),
+            offences: HashMap::new(),
+        }
+    }
+
+    /// Reads the word list again, and returns the number of words. The old list is kept if the file can't be read.
+    pub(crate) fn reload_words(&mut self) -> std::io::Result<usize> {
+        let Some(path) = &self.config.word_file else {
+            return Ok(0);
+        };
+        self.words = read_word_file(path)?;
+        Ok(self.words.len())
+    }
+
+    /// Checks a chat message that `player_id` is sending. Offences count against `chatter`.
+    pub(crate) fn filter(
+        &mut self,
+        player_id: PlayerId,
+        chatter: Chatter,
+        msg: String,
+        now: DateTime<Utc>,
+    ) -> FilteredMessage {
+        let elapsed = |time: DateTime<Utc>| (now - time).to_std().unwrap_or_default();
+        let history = self.players.entry(player_id).or_default();
+        while history
+            .sent
+            .front()
+            .is_some_and(|time| elapsed(*time) >= self.config.rate_window)
+        {
+            history.sent.pop_front();
+        }
+        let (message, warning) =
+            if self.config.rate_limit > 0 && history.sent.len() >= self.config.rate_limit {
+                (None, Some("You are sending messages too fast"))
+            } else if history.last_message.as_ref().is_some_and(|(last, time)| {
+                *last == msg && elapsed(*time) < self.config.repeat_window
+            }) {
+                (None, Some("Please don't repeat your messages"))
+            } else {
+                history.sent.push_back(now);
+                history.last_message = Some((msg.clone(), now));
+                match censor(&msg, &self.words) {
+                    Some(censored) => (Some(censored), Some("Please watch your language")),
+                    None => (Some(msg), None),
+                }
+            };
+
+        let mut mute_minutes = None;
+        if warning.is_some() {
+            let offences = self.offences.entry(chatter).or_default();
+            if offences
+                .last_offence
+                .is_some_and(|time| elapsed(time) > OFFENCE_MEMORY)
+            {
+                offences.offences = 0;
+            }
+            offences.offences += 1;
+            offences.last_offence = Some(now);
+            if self.config.mute_after > 0 && offences.offences >= self.config.mute_after {
+                mute_minutes = Some(self.config.mute_minutes << offences.mutes.min(10));
+                offences.offences = 0;
+                offences.mutes += 1;
+            }
+        }
+        FilteredMessage {
+            message,
+            warning,
+            mute_minutes,
+        }
+    }
+
+    /// Replaces the words from the word list in a message that doesn't come from a player, like a message
+    /// relayed from another chat.
+    pub(crate) fn censor_words(&self, msg: &str) -> String {
+        censor(msg, &self.words).unwrap_or_else(|| msg.to_owned())
+    }
+
+    /// Forgets the recent messages of a player who left. Offences that have been forgotten are removed, but
+    /// accounts and addresses that have been muted are remembered, so that their next mute is longer.
+    pub(crate) fn player_left(&mut self, player_id: PlayerId, now: DateTime<Utc>) {
+        self.players.remove(&player_id);
+        self.offences.retain(|_, offences| {
+            offences.mutes > 0
+                || offences
+                    .last_offence
+                    .is_some_and(|time| (now - time).to_std().unwrap_or_default() <= OFFENCE_MEMORY)
+        });
+    }
+}
+
+fn read_word_file(path: &Path) -> std::io::Result<HashSet<String>> {
+    let s = std::fs::read_to_string(path)?;
+    Ok(s.lines()
+        .map(|line| line.trim().to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect())
+}
+
+/// Replaces the words of `msg` that are in `words` with asterisks, or returns [`None`] if there were none.
+/// Words are compared case-insensitively, without the punctuation around them.
+fn censor(msg: &str, words: &HashSet<String>) -> Option<String> {
+    if words.is_empty() {
+        return None;
+    }
+    let mut found = false;
+    let censored = msg
+        .split(' ')
+        .map(|part| {
+            let word = part.trim_matches(|c: char| !c.is_alphanumeric());
+            if !word.is_empty() && words.contains(&word.to_lowercase()) {
+                found = true;
+                part.replace(word, &"*".repeat(word.chars().count()))
+            } else {
+                part.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    found.then_some(censored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatConfiguration, ChatFilter, Chatter};
+    use crate::game::{PlayerId, PlayerIndex};
+    use chrono::{Duration, Utc};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn filter_messages() {
+        let mut filter = ChatFilter::new(ChatConfiguration {
+            rate_limit: 2,
+            repeat_window: std::time::Duration::from_secs(10),
+            mute_after: 2,
+            ..Default::default()
+        });
+        filter.words.insert("darn".to_owned());
+        let player_id = PlayerId {
+            index: PlayerIndex(0),
+            gen: 0,
+        };
+        let chatter = Chatter::Ip(Ipv4Addr::LOCALHOST.into());
+        let now = Utc::now();
+        let mut filter_message = |player_id, msg: &str, now| {
+            filter.filter(player_id, chatter.clone(), msg.to_owned(), now)
+        };
+
+        let res = filter_message(player_id, "Oh DARN, a post", now);
+        assert_eq!(res.message.as_deref(), Some("Oh ****, a post"));
+        assert!(res.warning.is_some());
+        let res = filter_message(player_id, "Oh DARN, a post", now);
+        assert_eq!(res.message, None);
+        assert_eq!(res.mute_minutes, Some(5));
+
+        let later = now + Duration::seconds(6);
+        let res = filter_message(player_id, "gg", later);
+        assert_eq!(res.message.as_deref(), Some("gg"));
+        assert_eq!(res.warning, None);
+        filter_message(player_id, "wp", later);
+        let res = filter_message(player_id, "glhf", later);
+        assert_eq!(res.message, None);
+
+        // Rejoining doesn't reset the offences and mutes of the address
+        filter.player_left(player_id, later);
+        let rejoined = PlayerId {
+            index: PlayerIndex(0),
+            gen: 1,
+        };
+        let mut filter_message =
+            |msg: &str| filter.filter(rejoined, chatter.clone(), msg.to_owned(), later);
+        assert_eq!(filter_message("darn").mute_minutes, Some(10));
+    }
+}
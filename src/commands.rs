@@ -0,0 +1,317 @@
+//! Chat commands.
+//!
+//! Every command is described by a [`ChatCommand`], with its name, aliases, arguments, permission and
+//! help text. The server uses the description to check the permission and arguments before the command is
+//! run, and to list the commands with `/help`.
+//!
+//! Game modes register their commands with [`ServerMut::register_command`](crate::gamemode::ServerMut::register_command),
+//! usually in [`GameMode::init`](crate::gamemode::GameMode::init). A registered command is passed to
+//! [`GameMode::handle_command`](crate::gamemode::GameMode::handle_command) with its name, even if the player
+//! used an alias. Commands that haven't been registered are passed to the game mode as they are, but they
+//! are not listed by `/help`.
+
+use smallvec::SmallVec;
+
+use crate::game::PlayerIndex;
+use crate::CommandPermission;
+
+/// The kind of value a command argument takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A player ID, as listed by `/list`.
+    Player,
+    /// A non-negative whole number.
+    Number,
+    /// A duration in minutes, or with a unit like 30m, 12h, 2d or 1w.
+    Duration,
+    /// A single word.
+    Word,
+    /// The rest of the message, including spaces. Only makes sense as the last argument.
+    Text,
+}
+
+/// An argument of a chat command.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandArg {
+    /// Name of the argument in the usage text, like "ID".
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+impl CommandArg {
+    pub const fn required(name: &'static str, kind: ArgKind) -> Self {
+        CommandArg {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        CommandArg {
+            name,
+            kind,
+            optional: true,
+        }
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        match self.kind {
+            ArgKind::Player => value.parse::<PlayerIndex>().is_ok(),
+            ArgKind::Number => value.parse::<u32>().is_ok(),
+            ArgKind::Duration => parse_duration(value).is_some(),
+            ArgKind::Word | ArgKind::Text => true,
+        }
+    }
+}
+
+/// Description of a chat command.
+#[derive(Debug, Clone, Copy)]
+pub struct ChatCommand {
+    /// Name of the command, without the slash.
+    pub name: &'static str,
+    /// Other names that run the same command.
+    pub aliases: &'static [&'static str],
+    pub args: &'static [CommandArg],
+    /// Who can use the command. Commands for everyone can be restricted to admins in the Commands
+    /// section of the configuration, but admin commands can't be opened to everyone.
+    pub permission: CommandPermission,
+    /// Short description of the command, shown by `/help`.
+    pub help: &'static str,
+}
+
+impl ChatCommand {
+    /// Creates a command without arguments that everyone can use.
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        ChatCommand {
+            name,
+            aliases: &[],
+            args: &[],
+            permission: CommandPermission::Everyone,
+            help,
+        }
+    }
+
+    pub const fn aliases(mut self, aliases: &'static [&'static str]) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    pub const fn args(mut self, args: &'static [CommandArg]) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Makes the command available to logged-in admins only.
+    pub const fn admin(mut self) -> Self {
+        self.permission = CommandPermission::Admin;
+        self
+    }
+
+    /// Returns true if `name` is the name or one of the aliases of this command.
+    pub fn is_called(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+
+    /// Returns the usage text of the command, like "/mute ID [MINUTES]".
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for arg in self.args {
+            if arg.optional {
+                usage.push_str(&format!(" [{}]", arg.name));
+            } else {
+                usage.push_str(&format!(" {}", arg.name));
+            }
+        }
+        usage
+    }
+
+    /// Splits the arguments of the command, or returns [`None`] if they don't match the arguments of the command.
+    ///
+    /// An optional argument that doesn't accept the next value is skipped, so that the value can be used by the
+    /// next argument, like the reason in "/ban ID [DURATION] [REASON]". A value that starts with a digit is
+    /// never skipped this way, so a mistyped duration like "5x" fails instead of becoming part of the reason.
+    pub fn parse_args<'a>(&self, arg: &'a str) -> Option<CommandArgs<'a>> {
+        let mut rest = arg.trim();
+        let mut values = SmallVec::new();
+        for command_arg in self.args {
+            if rest.is_empty() {
+                if command_arg.optional {
+                    values.push(None);
+                    continue;
+                }
+                return None;
+            }
+            let (value, remaining) = if command_arg.kind == ArgKind::Text {
+                (rest, "")
+            } else {
+                let (value, remaining) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                (value, remaining.trim_start())
+            };
+            if command_arg.accepts(value) {
+                values.push(Some(value));
+                rest = remaining;
+            } else if command_arg.optional && !value.starts_with(|c: char| c.is_ascii_digit()) {
+                values.push(None);
+            } else {
+                return None;
+            }
+        }
+        rest.is_empty().then_some(CommandArgs { values })
+    }
+}
+
+/// Arguments of a command, checked against the arguments of its [`ChatCommand`].
+#[derive(Debug, Clone, Default)]
+pub struct CommandArgs<'a> {
+    values: SmallVec<[Option<&'a str>; 4]>,
+}
+
+impl<'a> CommandArgs<'a> {
+    /// Returns the argument at position `i`, or [`None`] if an optional argument wasn't given.
+    pub fn get(&self, i: usize) -> Option<&'a str> {
+        self.values.get(i).copied().flatten()
+    }
+
+    pub fn player(&self, i: usize) -> Option<PlayerIndex> {
+        self.get(i).and_then(|x| x.parse().ok())
+    }
+
+    pub fn number(&self, i: usize) -> Option<u32> {
+        self.get(i).and_then(|x| x.parse().ok())
+    }
+
+    /// Returns a [`ArgKind::Duration`] argument in minutes.
+    pub fn duration(&self, i: usize) -> Option<u32> {
+        self.get(i).and_then(parse_duration)
+    }
+}
+
+/// Parses a duration in minutes, like "30", or with a unit, like "30m", "12h", "2d" or "1w".
+/// Durations of zero are not accepted.
+pub fn parse_duration(s: &str) -> Option<u32> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "m"),
+    };
+    let minutes = match unit {
+        "m" => 1,
+        "h" => 60,
+        "d" => 60 * 24,
+        "w" => 60 * 24 * 7,
+        _ => return None,
+    };
+    number
+        .parse::<u32>()
+        .ok()?
+        .checked_mul(minutes)
+        .filter(|&minutes| minutes > 0)
+}
+
+/// Formats a duration in minutes with the largest unit it is a whole number of, like "2d" or "90 min".
+pub fn format_duration(minutes: u32) -> String {
+    match minutes {
+        0 => "0 min".to_owned(),
+        _ if minutes.is_multiple_of(60 * 24 * 7) => format!("{}w", minutes / (60 * 24 * 7)),
+        _ if minutes.is_multiple_of(60 * 24) => format!("{}d", minutes / (60 * 24)),
+        _ if minutes.is_multiple_of(60) => format!("{}h", minutes / 60),
+        _ => format!("{} min", minutes),
+    }
+}
+
+/// Commands registered by the game mode.
+#[derive(Debug, Default)]
+pub(crate) struct CommandRegistry {
+    commands: Vec<ChatCommand>,
+}
+
+impl CommandRegistry {
+    /// Adds a command, replacing any earlier command with the same name.
+    pub(crate) fn register(&mut self, command: ChatCommand) {
+        self.commands.retain(|x| x.name != command.name);
+        self.commands.push(command);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub(crate) fn find(&self, name: &str) -> Option<&ChatCommand> {
+        self.commands.iter().find(|x| x.is_called(name))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &ChatCommand> {
+        self.commands.iter()
+    }
+}
+
+/// Splits a text into chat messages, breaking lines between words.
+pub(crate) fn wrap_chat_lines(text: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split(' ') {
+        if !line.is_empty() && line.len() + 1 + word.len() > 63 {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, parse_duration, ArgKind, ChatCommand, CommandArg};
+
+    const MUTE: ChatCommand = ChatCommand::new("mute", "Mutes a player").args(&[
+        CommandArg::required("ID", ArgKind::Player),
+        CommandArg::optional("MINUTES", ArgKind::Number),
+    ]);
+
+    const BAN: ChatCommand = ChatCommand::new("ban", "Bans a player").args(&[
+        CommandArg::required("ID", ArgKind::Player),
+        CommandArg::optional("DURATION", ArgKind::Duration),
+        CommandArg::optional("REASON", ArgKind::Text),
+    ]);
+
+    #[test]
+    fn parse_args() {
+        assert_eq!(MUTE.usage(), "/mute ID [MINUTES]");
+        assert!(MUTE.parse_args("").is_none());
+        assert!(MUTE.parse_args("x").is_none());
+        assert!(MUTE.parse_args("1 2 3").is_none());
+        let args = MUTE.parse_args(" 3  10 ").unwrap();
+        assert_eq!(args.player(0).map(|x| x.0), Some(3));
+        assert_eq!(args.number(1), Some(10));
+        let args = MUTE.parse_args("3").unwrap();
+        assert_eq!(args.number(1), None);
+
+        let args = BAN.parse_args("7 2d griefing again").unwrap();
+        assert_eq!(args.duration(1), Some(2 * 24 * 60));
+        assert_eq!(args.get(2), Some("griefing again"));
+        let args = BAN.parse_args("7 griefing").unwrap();
+        assert_eq!(args.duration(1), None);
+        assert_eq!(args.get(2), Some("griefing"));
+        assert!(BAN.parse_args("7 5x people").is_none());
+        assert!(BAN.parse_args("7 0 griefing").is_none());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("45"), Some(45));
+        assert_eq!(parse_duration("12h"), Some(720));
+        assert_eq!(parse_duration("1w"), Some(10080));
+        assert_eq!(parse_duration("2x"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(format_duration(2880), "2d");
+        assert_eq!(format_duration(90), "90 min");
+    }
+}
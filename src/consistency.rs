@@ -0,0 +1,129 @@
+//! Periodic checks of the server state, for catching bugs early.
+//!
+//! When enabled, the server checks every few ticks that the object slots of the skaters don't overlap
+//! with each other or with the puck slots, that the puck list matches the number of puck slots, and that
+//! the packet, game step and recording counters haven't gone backwards or skipped ahead since the last
+//! check. Violations are logged with the details needed to find out what happened, but the server keeps
+//! running.
+
+use tracing::error;
+
+use crate::game::{Puck, OBJECT_SLOTS};
+use crate::server::{PlayerListExt, ServerStatePlayerItem};
+
+/// Counters that only grow during a game.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Counters {
+    pub game_id: u32,
+    /// Packet number, incremented once per tick.
+    pub packet: u32,
+    /// Game step, incremented once per simulated tick.
+    pub game_step: u32,
+    pub recording_ticks: u32,
+    /// Number of messages written to the recording.
+    pub recording_msg_pos: usize,
+    /// Number of messages waiting to be written to the recording, including those already written.
+    pub recording_messages: usize,
+}
+
+pub(crate) struct ConsistencyChecker {
+    interval: u32,
+    ticks_until_check: u32,
+    last: Option<Counters>,
+}
+
+impl ConsistencyChecker {
+    /// Creates a checker that checks the state every `interval` ticks.
+    pub(crate) fn new(interval: u32) -> Self {
+        Self {
+            interval,
+            ticks_until_check: interval,
+            last: None,
+        }
+    }
+
+    /// Counts a tick, and checks the state if it is time for it.
+    pub(crate) fn tick(
+        &mut self,
+        players: &[ServerStatePlayerItem],
+        puck_slots: usize,
+        pucks: &[Option<Puck>],
+        counters: Counters,
+    ) {
+        self.ticks_until_check -= 1;
+        if self.ticks_until_check > 0 {
+            return;
+        }
+        self.ticks_until_check = self.interval;
+
+        check_slots(players, puck_slots, pucks);
+        if let Some(last) = self.last.filter(|x| x.game_id == counters.game_id) {
+            check_counters(&last, &counters, self.interval);
+        }
+        self.last = Some(counters);
+    }
+}
+
+fn check_slots(players: &[ServerStatePlayerItem], puck_slots: usize, pucks: &[Option<Puck>]) {
+    if pucks.len() != puck_slots {
+        error!(
+            "Consistency check: {} pucks in the puck list, but {} puck slots",
+            pucks.len(),
+            puck_slots
+        );
+    }
+
+    let mut owners = [None; OBJECT_SLOTS];
+    for (player_id, player) in players.iter_players() {
+        let Some((object_index, _, team)) = &player.object else {
+            continue;
+        };
+        let object_index = *object_index;
+        if object_index < puck_slots || object_index >= OBJECT_SLOTS {
+            error!(
+                "Consistency check: skater of {} ({}) in team {:?} has object slot {}, outside of the skater slots {}..{}",
+                player.player_name, player_id, team, object_index, puck_slots, OBJECT_SLOTS
+            );
+            continue;
+        }
+        if let Some((other_id, other_name)) = &owners[object_index] {
+            error!(
+                "Consistency check: skaters of {} ({}) and {} ({}) share object slot {}",
+                other_name, other_id, player.player_name, player_id, object_index
+            );
+        } else {
+            owners[object_index] = Some((player_id, &player.player_name));
+        }
+    }
+}
+
+fn check_counters(last: &Counters, current: &Counters, ticks: u32) {
+    // The counters wrap around at the start of the game, so they are compared by the difference.
+    for (name, last_value, value) in [
+        ("packet", last.packet, current.packet),
+        ("game step", last.game_step, current.game_step),
+        (
+            "recording tick",
+            last.recording_ticks,
+            current.recording_ticks,
+        ),
+    ] {
+        if value.wrapping_sub(last_value) > ticks {
+            error!(
+                "Consistency check: {} went from {} to {} in {} ticks of game {}",
+                name, last_value, value, ticks, current.game_id
+            );
+        }
+    }
+    if current.recording_msg_pos < last.recording_msg_pos
+        || current.recording_msg_pos > current.recording_messages
+    {
+        error!(
+            "Consistency check: recording message position went from {} to {}, with {} messages in game {}",
+            last.recording_msg_pos,
+            current.recording_msg_pos,
+            current.recording_messages,
+            current.game_id
+        );
+    }
+}
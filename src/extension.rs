@@ -0,0 +1,196 @@
+//! Custom messages for modified clients.
+//!
+//! Clients that announce support for extensions (by sending their updates with command byte `0x20`
+//! instead of `0x10`) can receive extension messages in the regular message stream. Each message has a
+//! 16-bit message type and a payload of at most [`MAX_PAYLOAD_LENGTH`] bytes, the meaning of both is up to
+//! the game mode and the client mod. Vanilla clients never receive these messages.
+//!
+//! Extension messages are sent with [`ServerPlayersMut::add_extension_message`](crate::gamemode::ServerPlayersMut::add_extension_message)
+//! and [`ServerPlayersMut::add_directed_extension_message`](crate::gamemode::ServerPlayersMut::add_directed_extension_message).
+//! They are not included in recordings.
+
+use crate::game::{CustomScoreboardValue, Rink, RinkArea, Team};
+
+/// Maximum number of payload bytes in an extension message. Longer payloads are truncated.
+pub const MAX_PAYLOAD_LENGTH: usize = 255;
+
+/// A message that can be sent to clients that support extensions.
+pub trait ExtensionMessage {
+    /// Identifies the kind of message, so that clients can tell messages from different mods apart.
+    fn message_type(&self) -> u16;
+
+    /// Writes the payload of the message.
+    fn write_payload(&self, payload: &mut Vec<u8>);
+}
+
+/// Extension message type of the custom scoreboard values, see [`ScoreboardDisplay`](crate::game::ScoreboardDisplay).
+pub(crate) const SCOREBOARD_MESSAGE_TYPE: u16 = 0xFFFE;
+
+/// Custom scoreboard values. For each value, the payload contains the length of the name as one byte,
+/// the name, and the value as a 32-bit little-endian integer.
+pub(crate) struct CustomScoreboardMessage<'a>(pub &'a [CustomScoreboardValue]);
+
+impl ExtensionMessage for CustomScoreboardMessage<'_> {
+    fn message_type(&self) -> u16 {
+        SCOREBOARD_MESSAGE_TYPE
+    }
+
+    fn write_payload(&self, payload: &mut Vec<u8>) {
+        for value in self.0 {
+            let name = &value.name.as_bytes()[..value.name.len().min(u8::MAX as usize)];
+            payload.push(name.len() as u8);
+            payload.extend_from_slice(name);
+            payload.extend_from_slice(&value.value.to_le_bytes());
+        }
+    }
+}
+
+/// Extension message type of the rink area, see [`RinkArea`].
+pub(crate) const RINK_AREA_MESSAGE_TYPE: u16 = 0xFFFB;
+
+/// The part of the rink used for play, so that client mods can draw the boards and nets where they are.
+/// Vanilla clients always show the full rink with the nets at the ends.
+///
+/// The payload is the area (0 full, 1 half, 2 cross), the width and length of the area, and the X and Z
+/// coordinates of the center of the red and blue nets, all as 32-bit little-endian floats.
+pub(crate) struct RinkAreaMessage<'a>(pub RinkArea, pub &'a Rink);
+
+impl ExtensionMessage for RinkAreaMessage<'_> {
+    fn message_type(&self) -> u16 {
+        RINK_AREA_MESSAGE_TYPE
+    }
+
+    fn write_payload(&self, payload: &mut Vec<u8>) {
+        let RinkAreaMessage(area, rink) = self;
+        payload.push(match area {
+            RinkArea::Full => 0,
+            RinkArea::HalfIce => 1,
+            RinkArea::CrossIce => 2,
+        });
+        let red_net = rink.net(Team::Red).center();
+        let blue_net = rink.net(Team::Blue).center();
+        for value in [
+            rink.width,
+            rink.length,
+            red_net.x,
+            red_net.z,
+            blue_net.x,
+            blue_net.z,
+        ] {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Extension message type of sound events, see [`SoundEvent`].
+pub(crate) const SOUND_EVENT_MESSAGE_TYPE: u16 = 0xFFFD;
+
+/// A notification that clients can signal with a sound, sent with
+/// [`ServerPlayersMut::play_sound_event`](crate::gamemode::ServerPlayersMut::play_sound_event).
+///
+/// The only sound vanilla clients can be made to play is the goal horn, which they play when they receive a goal
+/// message. Goal events therefore need to be accompanied by
+/// [`ServerPlayersMut::add_goal_message`](crate::gamemode::ServerPlayersMut::add_goal_message) to be heard by
+/// everyone, while the other events are only sent to clients that support extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundEvent {
+    /// A team scored a goal.
+    Goal(Team),
+    /// A period has ended.
+    PeriodEnd,
+    /// A player of the team was sent to the penalty box.
+    Penalty(Team),
+    /// Something needs the attention of the players, like a team that is about to forfeit.
+    Warning,
+}
+
+/// Sound events. The payload is the event (0 goal, 1 period end, 2 penalty, 3 warning) followed by the
+/// team (0 red, 1 blue, 255 none).
+pub(crate) struct SoundEventMessage(pub SoundEvent);
+
+impl ExtensionMessage for SoundEventMessage {
+    fn message_type(&self) -> u16 {
+        SOUND_EVENT_MESSAGE_TYPE
+    }
+
+    fn write_payload(&self, payload: &mut Vec<u8>) {
+        let (event, team) = match self.0 {
+            SoundEvent::Goal(team) => (0, Some(team)),
+            SoundEvent::PeriodEnd => (1, None),
+            SoundEvent::Penalty(team) => (2, Some(team)),
+            SoundEvent::Warning => (3, None),
+        };
+        payload.push(event);
+        payload.push(team.map_or(u8::MAX, |team| team.get_num() as u8));
+    }
+}
+
+/// Extension message type of announcements, see [`AnnouncementEvent`].
+pub(crate) const ANNOUNCEMENT_MESSAGE_TYPE: u16 = 0xFFFC;
+
+/// A game event that can be announced to the players, for example with a sound or a voice line.
+///
+/// What is sent for each event is set in the Announcements section of the configuration, events that
+/// are not configured are not announced. Announcements are sent with
+/// [`ServerPlayersMut::announce`](crate::gamemode::ServerPlayersMut::announce).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnnouncementEvent {
+    /// A team scored a goal.
+    Goal,
+    /// The puck was dropped to start a period.
+    PeriodStart,
+    /// Icing was called.
+    Icing,
+}
+
+impl AnnouncementEvent {
+    /// Parses the name of the event in the configuration, like "period_start".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "goal" => Some(AnnouncementEvent::Goal),
+            "period_start" => Some(AnnouncementEvent::PeriodStart),
+            "icing" => Some(AnnouncementEvent::Icing),
+            _ => None,
+        }
+    }
+}
+
+/// What is sent when an event is announced.
+///
+/// Clients that support extensions are sent the code, which the client mod can map to a sound.
+/// Other clients are sent the text as a chat message instead, if there is one.
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    pub code: u16,
+    pub text: Option<String>,
+}
+
+/// Announcements. The payload is the announcement code as a 16-bit little-endian integer.
+pub(crate) struct AnnouncementMessage(pub u16);
+
+impl ExtensionMessage for AnnouncementMessage {
+    fn message_type(&self) -> u16 {
+        ANNOUNCEMENT_MESSAGE_TYPE
+    }
+
+    fn write_payload(&self, payload: &mut Vec<u8>) {
+        payload.extend_from_slice(&self.0.to_le_bytes());
+    }
+}
+
+/// An extension message with an already encoded payload.
+#[derive(Debug, Clone)]
+pub struct RawExtensionMessage {
+    pub message_type: u16,
+    pub payload: Vec<u8>,
+}
+
+impl ExtensionMessage for RawExtensionMessage {
+    fn message_type(&self) -> u16 {
+        self.message_type
+    }
+
+    fn write_payload(&self, payload: &mut Vec<u8>) {
+        payload.extend_from_slice(&self.payload);
+    }
+}
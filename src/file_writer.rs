@@ -0,0 +1,90 @@
+//! Writes a file in the background, one write at a time.
+//!
+//! Every save sends the full contents of the file. The contents are written by a single task, which skips to the
+//! newest contents if several saves arrive while it is writing, so that an older save can never overwrite a
+//! newer one. The directory of the file is created if it doesn't exist.
+
+use std::path::PathBuf;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+pub(crate) struct FileWriter {
+    path: PathBuf,
+    description: &'static str,
+    sender: Option<mpsc::UnboundedSender<String>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl FileWriter {
+    /// Creates a writer for `path`. `description` is used in the warning if the file can't be written.
+    pub(crate) fn new(path: PathBuf, description: &'static str) -> Self {
+        Self {
+            path,
+            description,
+            sender: None,
+            task: None,
+        }
+    }
+
+    /// Replaces the contents of the file with `contents`.
+    pub(crate) fn write(&mut self, contents: String) {
+        let sender = self.sender.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            self.task = Some(tokio::spawn(write_task(
+                self.path.clone(),
+                self.description,
+                receiver,
+            )));
+            sender
+        });
+        let _ = sender.send(contents);
+    }
+
+    /// Returns the task that is writing the file, so that it can be waited for before the program exits.
+    /// Writes after this start a new task.
+    pub(crate) fn take_pending_save(&mut self) -> Option<JoinHandle<()>> {
+        self.sender = None;
+        self.task.take()
+    }
+}
+
+async fn write_task(
+    path: PathBuf,
+    description: &'static str,
+    mut receiver: mpsc::UnboundedReceiver<String>,
+) {
+    while let Some(mut contents) = receiver.recv().await {
+        while let Ok(newer) = receiver.try_recv() {
+            contents = newer;
+        }
+        let result = async {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            tokio::fs::write(&path, contents).await
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Could not save {} {:?}: {}", description, path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileWriter;
+
+    #[tokio::test]
+    async fn last_write_wins() {
+        let path = std::env::temp_dir().join(format!("file_writer_{}.txt", std::process::id()));
+        let mut writer = FileWriter::new(path.clone(), "test file");
+        for i in 0..100 {
+            writer.write(i.to_string());
+        }
+        writer.take_pending_save().unwrap().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "99");
+        let _ = std::fs::remove_file(&path);
+    }
+}
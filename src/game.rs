@@ -3,6 +3,7 @@ use nalgebra::{point, Matrix3, Point3, Rotation3, Unit, Vector2, Vector3};
 
 use crate::game::RinkSideOfLine::{BlueSide, On, RedSide};
 use crate::protocol::{PuckPacket, SkaterPacket};
+use smallvec::SmallVec;
 use std::f32::consts::PI;
 use std::fmt;
 use std::fmt::{Display, Formatter};
@@ -41,6 +42,25 @@ impl Default for ScoreboardValues {
     }
 }
 
+/// A value that is shown to clients that support extensions, in addition to the regular scoreboard.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CustomScoreboardValue {
+    pub name: &'static str,
+    pub value: i32,
+}
+
+/// The scoreboard as it is shown to players and in recordings.
+///
+/// Game modes that use the scoreboard for something else than a regular game, like attempts in a shootout,
+/// can change what is shown here without changing the [`ScoreboardValues`] used by the game logic.
+#[derive(Clone, Debug, Default)]
+pub struct ScoreboardDisplay {
+    /// Values that are sent to all clients. Copied from the [`ScoreboardValues`] each tick.
+    pub values: ScoreboardValues,
+    /// Extra values for clients that support extensions. Empty at the start of each tick.
+    pub custom: SmallVec<[CustomScoreboardValue; 4]>,
+}
+
 /// Physics properties that are used for player and puck movement in the physics engine.
 #[derive(Debug, Clone)]
 pub struct PhysicsConfiguration {
@@ -54,6 +74,18 @@ pub struct PhysicsConfiguration {
     pub player_shift_acceleration: f32,
     pub max_player_shift_speed: f32,
     pub player_shift_turning: f32,
+    /// Collision impulse on a skater, in meters per hundred of a second, that makes the skater lose balance.
+    /// If [`None`], skaters only fall the way they do in the original physics.
+    pub knockdown_impulse: Option<f32>,
+    /// Number of ticks a skater that has lost balance will not try to stay upright.
+    pub knockdown_recovery_time: u32,
+    /// If true, stick collisions of players with a high ping are checked against the puck position
+    /// the player saw, see [`LagOffset`](crate::physics::LagOffset).
+    pub lag_compensation: bool,
+    /// Largest stick rotation input applied in one tick, in radians, counting both the stick position and the
+    /// stick angle. Faster input, like that of macros, is applied over several ticks. If [`None`], the input is
+    /// applied right away.
+    pub max_stick_rotation: Option<f32>,
 }
 
 impl Default for PhysicsConfiguration {
@@ -69,6 +101,10 @@ impl Default for PhysicsConfiguration {
             player_shift_acceleration: 0.00027777,
             max_player_shift_speed: 0.0333333,
             player_shift_turning: 0.00038888888,
+            knockdown_impulse: None,
+            knockdown_recovery_time: 100,
+            lag_compensation: false,
+            max_stick_rotation: None,
         }
     }
 }
@@ -104,7 +140,7 @@ pub enum RinkSideOfLine {
 
 /// A rink net.
 #[derive(Debug, Clone)]
-pub(crate) struct RinkNet {
+pub struct RinkNet {
     pub(crate) posts: Vec<(Point3<f32>, Point3<f32>, f32)>,
     pub(crate) surfaces: Vec<(Point3<f32>, Point3<f32>, Point3<f32>, Point3<f32>)>,
     pub(crate) left_post: Point3<f32>,
@@ -115,6 +151,26 @@ pub(crate) struct RinkNet {
 }
 
 impl RinkNet {
+    /// Bottom of the left post, seen from in front of the net.
+    pub fn left_post(&self) -> Point3<f32> {
+        self.left_post
+    }
+
+    /// Bottom of the right post, seen from in front of the net.
+    pub fn right_post(&self) -> Point3<f32> {
+        self.right_post
+    }
+
+    /// The point on the goal line between the posts.
+    pub fn center(&self) -> Point3<f32> {
+        Point3::from((self.left_post.coords + self.right_post.coords) / 2.0)
+    }
+
+    /// Unit vector pointing out from the net, towards the rest of the rink.
+    pub fn normal(&self) -> Vector3<f32> {
+        self.normal
+    }
+
     fn new(pos: Point3<f32>, rot: Matrix3<f32>) -> Self {
         let front_width = 3.0;
         let back_width = 2.5;
@@ -193,6 +249,76 @@ impl RinkNet {
     }
 }
 
+/// A static obstacle standing on the ice, like a cone or a barrier, that pucks and skaters collide with.
+///
+/// Obstacles are vertical cylinders. The client has no way to show them, so they are invisible to players.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Obstacle {
+    /// Center of the bottom of the obstacle.
+    pub pos: Point3<f32>,
+    pub radius: f32,
+    pub height: f32,
+}
+
+impl Obstacle {
+    pub fn new(pos: Point3<f32>, radius: f32, height: f32) -> Self {
+        Obstacle {
+            pos: Point3::new(pos.x, 0.0, pos.z),
+            radius,
+            height,
+        }
+    }
+
+    /// A training cone, about the size of a puck-handling cone.
+    pub fn cone(pos: Point3<f32>) -> Self {
+        Self::new(pos, 0.15, 0.3)
+    }
+
+    /// A barrier that skaters have to skate around.
+    pub fn barrier(pos: Point3<f32>) -> Self {
+        Self::new(pos, 0.5, 1.0)
+    }
+
+    pub(crate) fn post(&self) -> (Point3<f32>, Point3<f32>, f32) {
+        (self.pos, self.pos + self.height * Vector3::y(), self.radius)
+    }
+}
+
+/// A part of the boards where pucks bounce and slide differently, to mimic the boards and glass of a specific arena.
+///
+/// A zone is a box, and applies where a puck touches the boards inside it. The ice is never part of a zone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardZone {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+    /// Multiplier of the stiffness of the boards, which is how hard they push back a puck that presses into them.
+    /// The damping stays the same. 1.0 is like the rest of the boards, higher values make the boards livelier and
+    /// lower values make them deader.
+    pub stiffness: f32,
+    /// Friction between the puck and the boards, or [`None`] to use the friction of the rest of the rink.
+    pub friction: Option<f32>,
+}
+
+impl BoardZone {
+    pub fn contains(&self, pos: &Point3<f32>) -> bool {
+        (0..3).all(|i| self.min[i] <= pos[i] && pos[i] <= self.max[i])
+    }
+}
+
+/// The part of the rink used for play.
+///
+/// Small-area games are played on part of the rink, with invisible boards separating it from the rest.
+/// The game is always played along the Z axis, so that rules and faceoffs work like on the full rink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RinkArea {
+    #[default]
+    Full,
+    /// The half of the rink closest to the blue net, with invisible boards at the center line.
+    HalfIce,
+    /// An area with the size of a third of the rink played across the ice, in the corner closest to the blue net.
+    CrossIce,
+}
+
 /// A rink, with collision boundaries and nets.
 ///
 /// In HQM, all coordinates are based in meters.
@@ -214,10 +340,42 @@ pub struct Rink {
     pub blue_zone_blue_line: RinkLine,
     pub width: f32,
     pub length: f32,
+    /// Static obstacles on the ice. Empty unless added by the game mode.
+    pub obstacles: Vec<Obstacle>,
+    /// Parts of the boards with their own bounce and friction. The first zone that contains a collision is used.
+    pub board_zones: Vec<BoardZone>,
 }
 
 impl Rink {
-    pub(crate) fn new(width: f32, length: f32, corner_radius: f32) -> Self {
+    /// Returns the net defended by a team.
+    pub fn net(&self, team: Team) -> &RinkNet {
+        match team {
+            Team::Red => &self.red_net,
+            Team::Blue => &self.blue_net,
+        }
+    }
+
+    /// Creates a rink for a part of a full rink. The nets and lines are moved to fit the area,
+    /// and only the corners at the real boards are rounded.
+    pub fn new_area(width: f32, length: f32, corner_radius: f32, area: RinkArea) -> Self {
+        match area {
+            RinkArea::Full => Self::new(width, length, corner_radius),
+            RinkArea::HalfIce => {
+                let mut rink = Self::new(width, length / 2.0, corner_radius);
+                rink.corners.retain(|(p, _, _)| p.z <= corner_radius);
+                rink
+            }
+            RinkArea::CrossIce => {
+                let mut rink = Self::new(length / 3.0, width, corner_radius);
+                rink.corners
+                    .retain(|(p, _, _)| p.x <= corner_radius && p.z <= corner_radius);
+                rink
+            }
+        }
+    }
+
+    /// Creates a rink with the given size. The server uses a 30 by 61 meter rink with a corner radius of 8.5 meters.
+    pub fn new(width: f32, length: f32, corner_radius: f32) -> Self {
         let zero = Point3::new(0.0, 0.0, 0.0);
         let planes = vec![
             (zero.clone(), Vector3::y_axis()),
@@ -255,7 +413,8 @@ impl Rink {
         let line_width = 0.3; // IIHF rule 17iii, 17iv
         let goal_line_distance = 4.0; // IIHF rule 17iv
 
-        let blue_line_distance_neutral_zone_edge = 22.86;
+        // Smaller rinks get their lines moved closer to the ends
+        let blue_line_distance_neutral_zone_edge = 22.86 * length / 61.0;
         let blue_line_distance_mid = blue_line_distance_neutral_zone_edge - line_width / 2.0; // IIHF rule 17v and 17vi
                                                                                               // IIHF specifies distance between end boards and edge closest to the neutral zone, but my code specifies middle of line
 
@@ -297,8 +456,15 @@ impl Rink {
             blue_zone_blue_line,
             width,
             length,
+            obstacles: vec![],
+            board_zones: vec![],
         }
     }
+
+    /// Returns the zone of the boards at `pos`, if any.
+    pub fn board_zone(&self, pos: &Point3<f32>) -> Option<&BoardZone> {
+        self.board_zones.iter().find(|zone| zone.contains(pos))
+    }
 }
 
 /// Represents a physical body (both players and pucks) with a position, rotation and linear and angular velocities.
@@ -331,8 +497,14 @@ pub struct SkaterObject {
     pub body_rot: f32, // Radians
     pub(crate) height: f32,
     pub(crate) jumped_last_frame: bool,
+    pub(crate) balance_timer: u32,
+    pub(crate) fallen: bool,
     pub stick_placement: Vector2<f32>, // Azimuth and inclination in radians
     pub stick_placement_delta: Vector2<f32>, // Change in azimuth and inclination per hundred of a second
+    /// Stick position and angle input applied in the previous tick, which can lag behind the input of the
+    /// player if [`PhysicsConfiguration::max_stick_rotation`] is set.
+    pub(crate) stick_input: Vector2<f32>,
+    pub(crate) stick_angle: f32,
     pub collision_balls: Vec<SkaterCollisionBall>,
     pub hand: SkaterHand,
 }
@@ -356,13 +528,22 @@ impl SkaterObject {
             body_rot: 0.0,
             height: 0.75,
             jumped_last_frame: false,
+            balance_timer: 0,
+            fallen: false,
             stick_placement: Vector2::new(0.0, 0.0),
             stick_placement_delta: Vector2::new(0.0, 0.0),
+            stick_input: Vector2::new(0.0, 0.0),
+            stick_angle: 0.0,
             hand,
             collision_balls,
         }
     }
 
+    /// Returns true if the skater is lying on the ice.
+    pub fn is_fallen(&self) -> bool {
+        self.fallen
+    }
+
     pub fn reset_collision_balls(&mut self) {
         self.collision_balls = Self::get_collision_balls(
             &self.body.pos,
@@ -483,7 +664,7 @@ impl SkaterCollisionBall {
 
 /// Key and mouse inputs sent from the client to the server.
 ///
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PlayerInput {
     /// Stick angle. Normal range is -1 to 1.
     pub stick_angle: f32,
@@ -550,6 +731,9 @@ pub enum SkaterHand {
     Right,
 }
 
+/// Number of object slots, shared by pucks and skaters.
+pub const OBJECT_SLOTS: usize = 32;
+
 /// Represents an HQM puck.
 #[derive(Debug, Clone)]
 pub struct Puck {
@@ -664,6 +848,34 @@ impl std::str::FromStr for PlayerIndex {
     }
 }
 
+impl PlayerIndex {
+    /// Returns the slot number, as shown by /list and used in chat commands.
+    pub fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl PlayerId {
+    /// Creates a player ID, for simulating skaters outside of a server with [`PhysicsWorld`](crate::physics::PhysicsWorld).
+    pub fn new(index: usize, gen: u32) -> Self {
+        PlayerId {
+            index: PlayerIndex(index),
+            gen,
+        }
+    }
+
+    /// Returns the player slot of this player. The slot may be reused by another player after this one leaves.
+    pub fn index(self) -> PlayerIndex {
+        self.index
+    }
+}
+
+impl From<PlayerId> for PlayerIndex {
+    fn from(player_id: PlayerId) -> Self {
+        player_id.index
+    }
+}
+
 impl std::fmt::Display for PlayerId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}.{}", self.index.0, self.gen)
@@ -714,4 +926,6 @@ pub enum PhysicsEvent {
     PuckEnteredNet { team: Team, puck: usize },
     PuckPassedGoalLine { team: Team, puck: usize },
     PuckTouchedNet { team: Team, puck: usize },
+
+    SkaterFell { player: PlayerId },
 }
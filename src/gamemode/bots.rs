@@ -0,0 +1,426 @@
+//! Computer-controlled players.
+//!
+//! Bots are added with [`ServerPlayersMut::add_bot`](crate::gamemode::ServerPlayersMut::add_bot), and are controlled
+//! by setting their input before the physics simulation, in [`GameMode::before_tick`](crate::gamemode::GameMode::before_tick).
+//! Skater bots that fill up the teams can also be run by the server itself, see [`ServerConfiguration::bots`](crate::ServerConfiguration::bots).
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use nalgebra::{Point3, Rotation3, Vector2, Vector3};
+
+use crate::game::{PhysicsEvent, PlayerId, PlayerInput, Team};
+use crate::gamemode::layers::GameModeLayer;
+use crate::gamemode::{Server, ServerMut};
+use reborrow::{Reborrow, ReborrowMut};
+
+#[derive(Debug, Clone)]
+pub struct GoalieBotConfiguration {
+    /// How many ticks old the puck position is when the goalie reacts to it.
+    pub reaction_delay: u32,
+    /// Lower bound of the reaction delay when the difficulty is adjusted.
+    pub min_reaction_delay: u32,
+    /// Upper bound of the reaction delay when the difficulty is adjusted.
+    pub max_reaction_delay: u32,
+    /// How far out from the goal line the goalie stands, in meters.
+    pub depth: f32,
+}
+
+impl Default for GoalieBotConfiguration {
+    fn default() -> Self {
+        Self {
+            reaction_delay: 15,
+            min_reaction_delay: 5,
+            max_reaction_delay: 40,
+            depth: 1.0,
+        }
+    }
+}
+
+/// A bot that plays goalie.
+///
+/// The goalie places itself on the line between the puck and the point where the puck is predicted to cross the goal line.
+/// It doesn't see the puck immediately, but with a reaction delay. The delay can be adjusted after each attempt with
+/// [`GoalieBot::goal_against`] and [`GoalieBot::save`], which makes the goalie better if it lets in goals and worse if it doesn't.
+#[derive(Debug, Clone)]
+pub struct GoalieBot {
+    pub player_id: PlayerId,
+    pub config: GoalieBotConfiguration,
+    reaction_delay: u32,
+    puck_history: VecDeque<(Point3<f32>, Vector3<f32>)>,
+}
+
+impl GoalieBot {
+    pub fn new(player_id: PlayerId, config: GoalieBotConfiguration) -> Self {
+        GoalieBot {
+            player_id,
+            reaction_delay: config.reaction_delay,
+            config,
+            puck_history: VecDeque::new(),
+        }
+    }
+
+    pub fn reaction_delay(&self) -> u32 {
+        self.reaction_delay
+    }
+
+    /// Makes the goalie react faster.
+    pub fn goal_against(&mut self) {
+        self.reaction_delay = self
+            .reaction_delay
+            .saturating_sub(2)
+            .max(self.config.min_reaction_delay);
+    }
+
+    /// Makes the goalie react slower.
+    pub fn save(&mut self) {
+        self.reaction_delay = (self.reaction_delay + 2).min(self.config.max_reaction_delay);
+    }
+
+    /// Forgets the puck history, should be called when the puck has been moved.
+    pub fn reset(&mut self) {
+        self.puck_history.clear();
+    }
+
+    /// Updates the input of the goalie. Should be called from [`GameMode::before_tick`](crate::gamemode::GameMode::before_tick).
+    pub fn update(&mut self, mut server: ServerMut) {
+        let skater = server.players().get(self.player_id).and_then(|player| {
+            player.skater().map(|(team, skater)| {
+                (
+                    team,
+                    skater.body.pos,
+                    skater.body.rot,
+                    skater.body.linear_velocity,
+                    skater.body.angular_velocity,
+                )
+            })
+        });
+        let Some((team, pos, rot, linear_velocity, angular_velocity)) = skater else {
+            self.puck_history.clear();
+            return;
+        };
+
+        let rink = server.rink();
+        let net = match team {
+            Team::Red => &rink.red_net,
+            Team::Blue => &rink.blue_net,
+        };
+        let net_center = Point3::from((net.left_post.coords + net.right_post.coords) / 2.0);
+        let normal = net.normal;
+        let goal_line = (net.right_post - net.left_post).normalize();
+        let half_width = (net.right_post - net.left_post).norm() / 2.0;
+
+        // Follow the puck closest to our net
+        let puck = server
+            .pucks()
+            .iter()
+            .flatten()
+            .min_by(|a, b| {
+                let a = (a.body.pos - net_center).norm();
+                let b = (b.body.pos - net_center).norm();
+                a.total_cmp(&b)
+            })
+            .map(|puck| (puck.body.pos, puck.body.linear_velocity));
+
+        let mut input = PlayerInput {
+            stick: Vector2::new(0.0, -PI / 4.0),
+            ..Default::default()
+        };
+
+        let target = if let Some(puck) = puck {
+            self.puck_history.push_front(puck);
+            self.puck_history
+                .truncate(self.config.max_reaction_delay as usize + 1);
+            let delay = (self.reaction_delay as usize).min(self.puck_history.len() - 1);
+            let (seen_pos, seen_velocity) = self.puck_history[delay];
+
+            // Where we think the puck is now
+            let puck_pos = seen_pos + seen_velocity * delay as f32;
+
+            // Where the puck will cross the goal line, if it keeps going
+            let approach_speed = -seen_velocity.dot(&normal);
+            let distance = (puck_pos - net_center).dot(&normal);
+            let aim = if approach_speed > 0.01 && distance > 0.0 {
+                let crossing = puck_pos + seen_velocity * (distance / approach_speed);
+                let lateral = (crossing - net_center)
+                    .dot(&goal_line)
+                    .clamp(-half_width, half_width);
+                net_center + goal_line * lateral
+            } else {
+                net_center
+            };
+
+            let mut direction = puck_pos - aim;
+            direction.y = 0.0;
+            if direction.norm() < 0.1 || direction.normalize().dot(&normal) < 0.2 {
+                direction = normal;
+            }
+            let depth = self.config.depth.min(distance.max(0.0) / 2.0);
+            let target = aim + direction.normalize() * depth;
+
+            // Face the puck
+            let mut facing = puck_pos - pos;
+            facing.y = 0.0;
+            if facing.norm() > 0.1 {
+                let forward = rot * -Vector3::z();
+                let angle = forward.cross(&facing).y.atan2(forward.dot(&facing));
+                input.turn = (2.0 * angle - 40.0 * angular_velocity.y).clamp(-1.0, 1.0);
+            }
+            target
+        } else {
+            self.puck_history.clear();
+            net_center + normal * self.config.depth
+        };
+
+        let mut error = target - pos;
+        error.y = 0.0;
+        let forward = rot * -Vector3::z();
+        let side = rot * Vector3::x();
+        let side_error = error.dot(&side);
+        let forward_error = error.dot(&forward);
+
+        input.fwbw = (3.0 * forward_error - 60.0 * linear_velocity.dot(&forward)).clamp(-1.0, 1.0);
+        if side_error.abs() > 0.3 {
+            // Shift to move sideways
+            input.keys |= 0x10;
+            input.turn = (3.0 * side_error - 60.0 * linear_velocity.dot(&side)).clamp(-1.0, 1.0);
+        }
+
+        if let Some(mut player) = server.players_mut().get_mut(self.player_id) {
+            *player.input_mut() = input;
+        }
+    }
+}
+
+/// Keeps a [`GoalieBot`] in the net of each of the given teams, so that any game mode can have bot goalies.
+///
+/// The layer is added with [`LayeredGameMode::with`](crate::gamemode::layers::LayeredGameMode::with).
+/// A goalie that is off the ice, for example because a new game has started, is put back in its net.
+/// The goalie reacts faster after each goal against it, and slower after each puck it stops.
+pub struct GoalieBotLayer {
+    config: GoalieBotConfiguration,
+    teams: Vec<Team>,
+    /// The goalie of each team, and whether it touched a puck in the previous tick.
+    goalies: Vec<(Team, GoalieBot, bool)>,
+}
+
+impl GoalieBotLayer {
+    pub fn new(config: GoalieBotConfiguration, teams: &[Team]) -> Self {
+        GoalieBotLayer {
+            config,
+            teams: teams.to_vec(),
+            goalies: vec![],
+        }
+    }
+
+    fn get_or_add_goalie(&mut self, mut server: ServerMut, team: Team) -> Option<&mut GoalieBot> {
+        // Goalies that have been removed, for example by a kick, are replaced
+        self.goalies
+            .retain(|(_, goalie, _)| server.players().get(goalie.player_id).is_some());
+        let index = match self.goalies.iter().position(|(x, _, _)| *x == team) {
+            Some(index) => index,
+            None => {
+                let player_id = server.players_mut().add_bot("Goalie bot")?;
+                let goalie = GoalieBot::new(player_id, self.config.clone());
+                self.goalies.push((team, goalie, false));
+                self.goalies.len() - 1
+            }
+        };
+        Some(&mut self.goalies[index].1)
+    }
+}
+
+impl GameModeLayer for GoalieBotLayer {
+    fn before_tick(&mut self, mut server: ServerMut) {
+        for team in self.teams.clone() {
+            let Some(goalie) = self.get_or_add_goalie(server.rb_mut(), team) else {
+                continue;
+            };
+            let on_ice = server
+                .players()
+                .get(goalie.player_id)
+                .and_then(|player| player.team())
+                == Some(team);
+            if !on_ice {
+                let net = server.rink().net(team);
+                let normal = net.normal();
+                let pos = net.center() + goalie.config.depth * normal;
+                let pos = Point3::new(pos.x, 1.5, pos.z);
+                let rot = Rotation3::from_euler_angles(0.0, (-normal.x).atan2(-normal.z), 0.0);
+                server
+                    .players_mut()
+                    .spawn_skater(goalie.player_id, team, pos, rot, false);
+                goalie.reset();
+            }
+            goalie.update(server.rb_mut());
+        }
+    }
+
+    fn after_tick(&mut self, _server: ServerMut, events: &[PhysicsEvent]) {
+        for (team, goalie, touching) in self.goalies.iter_mut() {
+            let mut touched = false;
+            for event in events {
+                match *event {
+                    PhysicsEvent::PuckEnteredNet { team: net_team, .. } if net_team == *team => {
+                        goalie.goal_against();
+                    }
+                    PhysicsEvent::PuckTouch { player, .. } if player == goalie.player_id => {
+                        touched = true;
+                    }
+                    _ => {}
+                }
+            }
+            // A puck that is stopped touches the goalie for several ticks in a row
+            if touched && !*touching {
+                goalie.save();
+            }
+            *touching = touched;
+        }
+    }
+}
+
+/// Decides what a skater bot does while it's on the ice.
+///
+/// Controllers are run by [`SkaterBot`] once each tick, before the physics simulation.
+pub trait BotController {
+    /// Fills in the input of a bot that is on the ice for `team`.
+    fn update(&mut self, server: Server, player_id: PlayerId, team: Team, input: &mut PlayerInput);
+}
+
+/// The built-in skater controller.
+///
+/// The bot closest to the puck in each team chases it and shoots at the opposing net,
+/// while its teammates stay between the puck and their own net.
+#[derive(Debug, Clone, Default)]
+pub struct ChaseDefendController;
+
+impl BotController for ChaseDefendController {
+    fn update(&mut self, server: Server, player_id: PlayerId, team: Team, input: &mut PlayerInput) {
+        let Some(skater) = server
+            .players()
+            .get(player_id)
+            .and_then(|p| p.skater().map(|(_, skater)| skater.body.clone()))
+        else {
+            return;
+        };
+        let pos = skater.pos;
+
+        let rink = server.rink();
+        let own_net = rink.net(team);
+        let opposing_net = rink.net(team.get_other_team());
+
+        let puck = server
+            .pucks()
+            .iter()
+            .flatten()
+            .map(|puck| puck.body.pos)
+            .min_by(|a, b| (a - pos).norm().total_cmp(&(b - pos).norm()));
+
+        // Only the teammate closest to the puck goes for it
+        let chasing = puck.is_some_and(|puck_pos| {
+            let distance = (puck_pos - pos).norm();
+            !server.players().iter().any(|p| {
+                p.id != player_id
+                    && p.skater().is_some_and(|(t, skater)| {
+                        t == team && (puck_pos - skater.body.pos).norm() < distance
+                    })
+            })
+        });
+
+        let (target, facing, has_puck) = match puck {
+            Some(puck_pos) if chasing && (puck_pos - pos).norm() < 1.5 => {
+                (opposing_net.center(), opposing_net.center(), true)
+            }
+            Some(puck_pos) if chasing => (puck_pos, puck_pos, false),
+            Some(puck_pos) => {
+                let net_center = own_net.center();
+                (net_center + (puck_pos - net_center) * 0.35, puck_pos, false)
+            }
+            None => (
+                own_net.center() + own_net.normal() * 3.0,
+                opposing_net.center(),
+                false,
+            ),
+        };
+
+        let mut direction = facing - pos;
+        direction.y = 0.0;
+        if direction.norm() > 0.1 {
+            let forward = skater.rot * -Vector3::z();
+            let angle = forward.cross(&direction).y.atan2(forward.dot(&direction));
+            input.turn = (2.0 * angle - 40.0 * skater.angular_velocity.y).clamp(-1.0, 1.0);
+            let mut error = target - pos;
+            error.y = 0.0;
+            let forward_error = error.dot(&forward);
+            input.fwbw = if chasing {
+                if angle.abs() < 1.0 {
+                    1.0
+                } else {
+                    0.3
+                }
+            } else {
+                // Defenders skate to their spot and stop there
+                (forward_error - 30.0 * skater.linear_velocity.dot(&forward)).clamp(-1.0, 1.0)
+            };
+        }
+
+        let step = server.replay().game_step();
+        input.stick = if has_puck {
+            // Sweep the stick from side to side to shoot
+            let phase = (step % 100) as f32 / 100.0;
+            Vector2::new(PI / 3.0 * (1.0 - 2.0 * phase), -PI / 5.0)
+        } else {
+            Vector2::new(0.0, -PI / 4.0)
+        };
+    }
+}
+
+/// A bot that joins a team and lets a [`BotController`] play for it.
+///
+/// It doesn't play well with the built-in [`ChaseDefendController`], but it's good enough to produce games without any human players.
+pub struct SkaterBot {
+    pub player_id: PlayerId,
+    pub team: Team,
+    pub controller: Box<dyn BotController>,
+}
+
+impl SkaterBot {
+    pub fn new(player_id: PlayerId, team: Team) -> Self {
+        Self::with_controller(player_id, team, Box::new(ChaseDefendController))
+    }
+
+    pub fn with_controller(
+        player_id: PlayerId,
+        team: Team,
+        controller: Box<dyn BotController>,
+    ) -> Self {
+        SkaterBot {
+            player_id,
+            team,
+            controller,
+        }
+    }
+
+    /// Updates the input of the bot. Should be called before the game mode's [`GameMode::before_tick`](crate::gamemode::GameMode::before_tick),
+    /// so that the game mode sees the join requests.
+    pub fn update(&mut self, mut server: ServerMut) {
+        let on_ice = server
+            .players()
+            .get(self.player_id)
+            .is_some_and(|player| player.has_skater());
+        let mut input = PlayerInput::default();
+        if on_ice {
+            self.controller
+                .update(server.rb(), self.player_id, self.team, &mut input);
+        } else {
+            input.keys = match self.team {
+                Team::Red => 0x4,
+                Team::Blue => 0x8,
+            };
+        }
+
+        if let Some(mut player) = server.players_mut().get_mut(self.player_id) {
+            *player.input_mut() = input;
+        }
+    }
+}
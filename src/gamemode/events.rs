@@ -0,0 +1,333 @@
+//! Automatic events, triggered by the score and the clock.
+//!
+//! Each [`EventRule`] has a condition, like `score_difference >= 5 and pucks < 2`, and an action that is
+//! run when the condition becomes true. A rule with an interval runs its action again every time that
+//! many seconds of game time have passed while the condition is still true. Rules are only checked
+//! during periods of a game that isn't over, so warmups are not affected.
+
+use std::str::FromStr;
+
+use nalgebra::{Point3, Rotation3};
+use reborrow::ReborrowMut;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::game::{PhysicsEvent, PlayerId, Puck, ScoreboardDisplay, ScoreboardValues};
+use crate::gamemode::{ExitReason, GameMode, InitialGameValues, PuckExt, Server, ServerMut};
+use crate::votes::VoteHandler;
+
+/// A value of the game that conditions can compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    RedScore,
+    BlueScore,
+    /// Difference between the scores of the leading and the trailing team.
+    ScoreDifference,
+    TotalScore,
+    Period,
+    /// Seconds left of the period.
+    TimeLeft,
+    /// Number of pucks in play.
+    Pucks,
+}
+
+impl Variable {
+    fn value(self, scoreboard: &ScoreboardValues, pucks: u32) -> u32 {
+        match self {
+            Variable::RedScore => scoreboard.red_score,
+            Variable::BlueScore => scoreboard.blue_score,
+            Variable::ScoreDifference => scoreboard.red_score.abs_diff(scoreboard.blue_score),
+            Variable::TotalScore => scoreboard.red_score + scoreboard.blue_score,
+            Variable::Period => scoreboard.period,
+            Variable::TimeLeft => scoreboard.time.div_ceil(100),
+            Variable::Pucks => pucks,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Comparand {
+    variable: Variable,
+    comparison: Comparison,
+    value: u32,
+}
+
+impl Comparand {
+    fn matches(&self, scoreboard: &ScoreboardValues, pucks: u32) -> bool {
+        let value = self.variable.value(scoreboard, pucks);
+        match self.comparison {
+            Comparison::Less => value < self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::Equal => value == self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+            Comparison::Greater => value > self.value,
+        }
+    }
+}
+
+impl FromStr for Comparand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let (Some(variable), Some(comparison), Some(value), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!(
+                "\"{}\" should be like \"score_difference >= 5\"",
+                s.trim()
+            ));
+        };
+        let variable = match variable {
+            "red_score" => Variable::RedScore,
+            "blue_score" => Variable::BlueScore,
+            "score_difference" => Variable::ScoreDifference,
+            "total_score" => Variable::TotalScore,
+            "period" => Variable::Period,
+            "time_left" => Variable::TimeLeft,
+            "pucks" => Variable::Pucks,
+            _ => return Err(format!("Unknown value {}", variable)),
+        };
+        let comparison = match comparison {
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            "=" | "==" => Comparison::Equal,
+            ">=" => Comparison::GreaterOrEqual,
+            ">" => Comparison::Greater,
+            _ => return Err(format!("Unknown comparison {}", comparison)),
+        };
+        let value = value
+            .parse::<u32>()
+            .map_err(|_| format!("{} is not a number", value))?;
+        Ok(Comparand {
+            variable,
+            comparison,
+            value,
+        })
+    }
+}
+
+/// Condition of a rule, a list of comparisons that all have to be true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    comparands: Vec<Comparand>,
+}
+
+impl Condition {
+    fn matches(&self, scoreboard: &ScoreboardValues, pucks: u32) -> bool {
+        self.comparands
+            .iter()
+            .all(|comparand| comparand.matches(scoreboard, pucks))
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let comparands = s
+            .split(" and ")
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Condition { comparands })
+    }
+}
+
+/// What happens when a rule is triggered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventAction {
+    /// Only sends the message of the rule.
+    Announce,
+    /// Drops a puck at center ice.
+    SpawnPuck,
+}
+
+impl FromStr for EventAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "announce" => Ok(EventAction::Announce),
+            "spawn_puck" => Ok(EventAction::SpawnPuck),
+            _ => Err(format!("Unknown action {}", s)),
+        }
+    }
+}
+
+/// A rule that runs an action when its condition becomes true.
+#[derive(Debug, Clone)]
+pub struct EventRule {
+    pub name: String,
+    pub condition: Condition,
+    pub action: EventAction,
+    /// Message sent to everyone when the rule is triggered. `{time}`, `{red}` and `{blue}` are
+    /// replaced with the time left of the period and the scores.
+    pub message: Option<String>,
+    /// If set, the action is repeated every this many seconds of game time while the condition is true.
+    pub interval: Option<u32>,
+}
+
+/// Game mode that runs event rules on top of another game mode.
+pub struct EventGameMode {
+    game_mode: Box<dyn GameMode>,
+    rules: Vec<EventRule>,
+    /// Game time when each rule was last triggered, or [`None`] if its condition is false.
+    triggered: Vec<Option<u32>>,
+    pending_pucks: usize,
+}
+
+impl EventGameMode {
+    pub fn new(game_mode: Box<dyn GameMode>, rules: Vec<EventRule>) -> Self {
+        let triggered = vec![None; rules.len()];
+        EventGameMode {
+            game_mode,
+            rules,
+            triggered,
+            pending_pucks: 0,
+        }
+    }
+
+    fn check_rules(&mut self, mut server: ServerMut) {
+        let scoreboard = *server.scoreboard();
+        if scoreboard.period == 0 || scoreboard.game_over {
+            return;
+        }
+        let pucks = server.pucks().iter().filter(|x| x.is_some()).count() as u32;
+        for (rule, triggered) in self.rules.iter().zip(self.triggered.iter_mut()) {
+            if !rule.condition.matches(&scoreboard, pucks) {
+                *triggered = None;
+                continue;
+            }
+            let run = match (*triggered, rule.interval) {
+                (None, _) => true,
+                (Some(time), Some(interval)) => {
+                    time.saturating_sub(scoreboard.time) >= interval * 100
+                }
+                (Some(_), None) => false,
+            };
+            if !run {
+                continue;
+            }
+            *triggered = Some(scoreboard.time);
+            info!("Event {} triggered", rule.name);
+            if rule.action == EventAction::SpawnPuck {
+                self.pending_pucks += 1;
+            }
+            if let Some(message) = &rule.message {
+                let time = scoreboard.time.div_ceil(100);
+                let msg = message
+                    .replace("{time}", &format!("{}:{:02}", time / 60, time % 60))
+                    .replace("{red}", &scoreboard.red_score.to_string())
+                    .replace("{blue}", &scoreboard.blue_score.to_string());
+                server.players_mut().add_server_chat_message(msg);
+            }
+        }
+    }
+
+    fn spawn_pending_pucks(&mut self, mut server: ServerMut) {
+        // Objects can't be spawned in after_tick, so the pucks are dropped at the start of the next tick
+        let rink = server.rink();
+        let pos = Point3::new(rink.width / 2.0, 1.5, rink.length / 2.0);
+        for _ in 0..std::mem::take(&mut self.pending_pucks) {
+            server
+                .pucks_mut()
+                .spawn_puck(Puck::new(pos, Rotation3::identity()));
+        }
+    }
+}
+
+impl GameMode for EventGameMode {
+    fn init(&mut self, server: ServerMut) {
+        self.game_mode.init(server);
+    }
+
+    fn before_tick(&mut self, mut server: ServerMut) {
+        self.spawn_pending_pucks(server.rb_mut());
+        self.game_mode.before_tick(server);
+    }
+
+    fn after_tick(&mut self, mut server: ServerMut, events: &[PhysicsEvent]) {
+        self.game_mode.after_tick(server.rb_mut(), events);
+        self.check_rules(server);
+    }
+
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        self.game_mode.handle_command(server, cmd, arg, player_id);
+    }
+
+    fn handle_remote_command(
+        &mut self,
+        server: ServerMut,
+        cmd: &str,
+        arg: &str,
+        admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        self.game_mode
+            .handle_remote_command(server, cmd, arg, admin_name)
+    }
+
+    fn before_chat_message(
+        &mut self,
+        server: ServerMut,
+        player_id: PlayerId,
+        message: &mut String,
+    ) -> bool {
+        self.game_mode
+            .before_chat_message(server, player_id, message)
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        self.game_mode.get_initial_game_values()
+    }
+
+    fn game_started(&mut self, server: ServerMut) {
+        self.triggered.fill(None);
+        self.pending_pucks = 0;
+        self.game_mode.game_started(server);
+    }
+
+    fn before_player_exit(&mut self, server: ServerMut, player_id: PlayerId, reason: ExitReason) {
+        self.game_mode.before_player_exit(server, player_id, reason);
+    }
+
+    fn after_player_join(&mut self, server: ServerMut, player_id: PlayerId) {
+        self.game_mode.after_player_join(server, player_id);
+    }
+
+    fn on_player_afk(&mut self, server: ServerMut, player_id: PlayerId) {
+        self.game_mode.on_player_afk(server, player_id);
+    }
+
+    fn hold_warmup(&mut self, server: ServerMut) {
+        self.game_mode.hold_warmup(server);
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        self.game_mode.server_list_team_size()
+    }
+
+    fn include_tick_in_recording(&self, server: Server) -> bool {
+        self.game_mode.include_tick_in_recording(server)
+    }
+
+    fn scoreboard_display(&self, server: Server, display: &mut ScoreboardDisplay) {
+        self.game_mode.scoreboard_display(server, display)
+    }
+
+    fn vote_handler(&mut self) -> Option<&mut dyn VoteHandler> {
+        self.game_mode.vote_handler()
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.game_mode.take_pending_saves()
+    }
+}
@@ -0,0 +1,366 @@
+//! Free-for-all, where every player plays for themselves.
+//!
+//! Players still join the red or the blue team, but the teams only decide the jersey colour. Each goal,
+//! in either net, is credited to the player who last touched the puck. The game ends when a player
+//! reaches the goal limit or the clock runs out, and the standings are printed in chat now and then.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use nalgebra::{Point3, Rotation3};
+use reborrow::ReborrowMut;
+use tracing::info;
+
+use crate::commands::{ArgKind, ChatCommand, CommandArg};
+use crate::game::{PhysicsEvent, PlayerId, PlayerIndex, Puck, ScoreboardValues};
+use crate::gamemode::util::{add_players, get_spawnpoint, SpawnPoint};
+use crate::gamemode::{
+    ExitReason, GameMode, InitialGameValues, PuckExt, Server, ServerMut, ServerMutParts,
+};
+
+/// Ticks between a goal and the next puck drop.
+const GOAL_PAUSE: u32 = 300;
+/// Ticks between the end of a game and the start of the next one.
+const GAME_OVER_PAUSE: u32 = 1000;
+/// Number of players listed when the standings are printed.
+const STANDINGS_SHOWN: usize = 5;
+
+pub struct FreeForAllGameMode {
+    goal_limit: u32,
+    /// Length of a game in seconds.
+    time_limit: u32,
+    /// Seconds between the standings being printed in chat. 0 only prints them when the game ends.
+    standings_interval: u32,
+    team_max: usize,
+    team_switch_timer: HashMap<PlayerId, u32>,
+    last_touch: HashMap<usize, PlayerId>,
+    /// Goals of each player by name, so that players keep their goals if they reconnect.
+    scores: HashMap<Rc<str>, u32>,
+    pause_timer: u32,
+    standings_timer: u32,
+}
+
+impl FreeForAllGameMode {
+    pub fn new(goal_limit: u32, time_limit: u32, standings_interval: u32, team_max: usize) -> Self {
+        FreeForAllGameMode {
+            goal_limit,
+            time_limit,
+            standings_interval,
+            team_max,
+            team_switch_timer: Default::default(),
+            last_touch: Default::default(),
+            scores: Default::default(),
+            pause_timer: 0,
+            standings_timer: standings_interval * 100,
+        }
+    }
+
+    fn drop_puck(&mut self, mut server: ServerMut) {
+        self.last_touch.clear();
+        server.pucks_mut().remove_all_pucks();
+        let rink = server.rink();
+        let pos = Point3::new(rink.width / 2.0, 1.5, rink.length / 2.0);
+        server
+            .pucks_mut()
+            .spawn_puck(Puck::new(pos, Rotation3::identity()));
+    }
+
+    fn handle_goal(&mut self, mut server: ServerMut, puck: usize) {
+        if self.pause_timer > 0 || server.scoreboard().game_over {
+            return;
+        }
+        self.pause_timer = GOAL_PAUSE;
+
+        let players = server.players();
+        let Some(scorer) = self
+            .last_touch
+            .get(&puck)
+            .and_then(|player_id| players.get(*player_id))
+            .map(|player| player.name())
+        else {
+            server
+                .players_mut()
+                .add_server_chat_message("Goal, but nobody touched the puck");
+            return;
+        };
+        let score = self.scores.entry(scorer.clone()).or_insert(0);
+        *score += 1;
+        let score = *score;
+        let goals = if score == 1 { "goal" } else { "goals" };
+        let msg = format!("Goal by {}! {} {}", scorer, score, goals);
+        server.players_mut().add_server_chat_message(msg);
+
+        if self.goal_limit > 0 && score >= self.goal_limit {
+            self.end_game(server);
+        }
+    }
+
+    fn end_game(&mut self, mut server: ServerMut) {
+        server.scoreboard_mut().game_over = true;
+        self.pause_timer = GAME_OVER_PAUSE;
+        let standings = standings(&self.scores);
+        let msg = match standings.as_slice() {
+            [] => "Game over, nobody scored".to_owned(),
+            [(winner, goals), ..] => {
+                let tied = standings.iter().filter(|(_, x)| x == goals).count();
+                if tied > 1 {
+                    format!("Game over, {} players tied with {} goals", tied, goals)
+                } else {
+                    format!("Game over, {} wins with {} goals", winner, goals)
+                }
+            }
+        };
+        info!("{}", msg);
+        server.players_mut().add_server_chat_message(msg);
+        self.print_standings(server);
+    }
+
+    fn print_standings(&self, mut server: ServerMut) {
+        let standings = standings(&self.scores);
+        if standings.is_empty() {
+            return;
+        }
+        let msg = standings
+            .iter()
+            .take(STANDINGS_SHOWN)
+            .enumerate()
+            .map(|(i, (name, goals))| format!("{}. {} {}", i + 1, name, goals))
+            .collect::<Vec<_>>()
+            .join(", ");
+        server.players_mut().add_server_chat_message(msg);
+    }
+
+    /// Handles /scores, which shows the goals of every player to the player who asked.
+    fn msg_scores(&self, mut server: ServerMut, receiver_id: PlayerId) {
+        let standings = standings(&self.scores);
+        if standings.is_empty() {
+            server
+                .players_mut()
+                .add_directed_server_chat_message("No goals scored yet", receiver_id);
+            return;
+        }
+        for (i, (name, goals)) in standings.iter().enumerate() {
+            let msg = format!("{}. {} {}", i + 1, name, goals);
+            server
+                .players_mut()
+                .add_directed_server_chat_message(msg, receiver_id);
+        }
+    }
+
+    fn reset_game(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let name = player.name();
+            info!("{} ({}) reset game", name, player_id);
+            let msg = format!("Game reset by {}", name);
+
+            server.new_game(self.get_initial_game_values());
+
+            server.players_mut().add_server_chat_message(msg);
+        }
+    }
+
+    fn force_player_off_ice(
+        &mut self,
+        mut server: ServerMut,
+        admin_player_id: PlayerId,
+        force_player_index: PlayerIndex,
+    ) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(admin_player_id) {
+            let admin_player_name = player.name();
+
+            if let Some(force_player) = server.players().get_by_index(force_player_index) {
+                let force_player_id = force_player.id;
+                let force_player_name = force_player.name();
+                if server.players_mut().move_to_spectator(force_player_id) {
+                    let msg = format!(
+                        "{} forced off ice by {}",
+                        force_player_name, admin_player_name
+                    );
+                    info!(
+                        "{} ({}) forced {} ({}) off ice",
+                        admin_player_name, admin_player_id, force_player_name, force_player_index
+                    );
+                    server.players_mut().add_server_chat_message(msg);
+                    self.team_switch_timer.insert(force_player_id, 500);
+                }
+            }
+        }
+    }
+
+    fn update_players(&mut self, mut server: ServerMut) {
+        let ServerMutParts {
+            players,
+            rink,
+            config,
+            ..
+        } = server.as_mut_parts();
+        let rink = &*rink;
+        add_players(
+            players,
+            self.team_max,
+            &mut self.team_switch_timer,
+            None,
+            &config.team_names,
+            |team, _| get_spawnpoint(rink, team, SpawnPoint::Center),
+            |_, _| Ok(()),
+            |_| {},
+            |_, _| {},
+        );
+    }
+}
+
+/// Returns the players sorted by goals, most goals first. Ties are sorted by name.
+fn standings(scores: &HashMap<Rc<str>, u32>) -> Vec<(Rc<str>, u32)> {
+    let mut standings = scores
+        .iter()
+        .filter(|(_, goals)| **goals > 0)
+        .map(|(name, goals)| (name.clone(), *goals))
+        .collect::<Vec<_>>();
+    standings.sort_by(|(a_name, a_goals), (b_name, b_goals)| {
+        b_goals.cmp(a_goals).then_with(|| a_name.cmp(b_name))
+    });
+    standings
+}
+
+const FREE_FOR_ALL_COMMANDS: &[ChatCommand] = &[
+    ChatCommand::new("scores", "Shows the goals of every player"),
+    ChatCommand::new("reset", "Resets the game")
+        .aliases(&["resetgame"])
+        .admin(),
+    ChatCommand::new("fs", "Forces the player off the ice")
+        .args(&[CommandArg::required("ID", ArgKind::Player)])
+        .admin(),
+];
+
+impl GameMode for FreeForAllGameMode {
+    fn init(&mut self, mut server: ServerMut) {
+        for command in FREE_FOR_ALL_COMMANDS {
+            server.register_command(*command);
+        }
+    }
+
+    fn before_tick(&mut self, mut server: ServerMut) {
+        self.update_players(server.rb_mut());
+
+        if self.pause_timer > 0 {
+            self.pause_timer -= 1;
+            if self.pause_timer == 0 {
+                if server.scoreboard().game_over {
+                    server.new_game(self.get_initial_game_values());
+                } else {
+                    self.drop_puck(server);
+                }
+            }
+        }
+    }
+
+    fn after_tick(&mut self, mut server: ServerMut, events: &[PhysicsEvent]) {
+        for event in events {
+            match *event {
+                PhysicsEvent::PuckTouch { player, puck } => {
+                    self.last_touch.insert(puck, player);
+                }
+                PhysicsEvent::PuckEnteredNet { puck, .. } => {
+                    self.handle_goal(server.rb_mut(), puck);
+                }
+                _ => {}
+            }
+        }
+        if server.scoreboard().game_over {
+            return;
+        }
+
+        // The clock only runs while someone is playing
+        let playing = server
+            .players()
+            .iter()
+            .any(|player| player.skater().is_some());
+        if playing && self.pause_timer == 0 {
+            let values = server.scoreboard_mut();
+            values.time = values.time.saturating_sub(1);
+            if values.time == 0 {
+                self.end_game(server.rb_mut());
+                return;
+            }
+        }
+
+        if self.standings_interval > 0 {
+            self.standings_timer = self.standings_timer.saturating_sub(1);
+            if self.standings_timer == 0 {
+                self.standings_timer = self.standings_interval * 100;
+                self.print_standings(server);
+            }
+        }
+    }
+
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        match cmd {
+            "scores" => {
+                self.msg_scores(server, player_id);
+            }
+            "reset" | "resetgame" => {
+                self.reset_game(server, player_id);
+            }
+            "fs" => {
+                if let Ok(force_player_index) = arg.parse::<PlayerIndex>() {
+                    self.force_player_off_ice(server, player_id, force_player_index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        InitialGameValues {
+            values: ScoreboardValues {
+                period: 1,
+                time: self.time_limit * 100,
+                ..Default::default()
+            },
+            puck_slots: 1,
+        }
+    }
+
+    fn game_started(&mut self, server: ServerMut) {
+        self.scores.clear();
+        self.pause_timer = 0;
+        self.standings_timer = self.standings_interval * 100;
+        self.drop_puck(server);
+    }
+
+    fn before_player_exit(&mut self, _server: ServerMut, player_id: PlayerId, _reason: ExitReason) {
+        self.team_switch_timer.remove(&player_id);
+        self.last_touch.retain(|_, touch| *touch != player_id);
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        self.team_max as u32
+    }
+
+    fn include_tick_in_recording(&self, _server: Server) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::standings;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn most_goals_first() {
+        let mut scores: HashMap<Rc<str>, u32> = HashMap::new();
+        assert!(standings(&scores).is_empty());
+
+        scores.insert("Carol".into(), 0);
+        scores.insert("Bob".into(), 2);
+        scores.insert("Alice".into(), 2);
+        scores.insert("Dave".into(), 3);
+        let names = standings(&scores)
+            .into_iter()
+            .map(|(name, goals)| format!("{} {}", name, goals))
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Dave 3", "Alice 2", "Bob 2"]);
+    }
+}
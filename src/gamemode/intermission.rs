@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use nalgebra::{Point3, Rotation3, Vector3};
+use tracing::info;
+
+use crate::game::{PhysicsEvent, PlayerId, Puck, Team};
+use crate::gamemode::{PuckExt, ServerMut};
+
+/// Ticks between the end of the period and the first attempt.
+const START_DELAY: u32 = 500;
+/// Time limit of a single attempt.
+const ATTEMPT_TIME: u32 = 800;
+/// Ticks between two attempts.
+const ATTEMPT_DELAY: u32 = 200;
+/// The contest is over when this much of the intermission is left.
+const FINISH_TIME: u32 = 300;
+
+#[derive(Debug, Clone)]
+struct ContestAttempt {
+    shooter: PlayerId,
+    team: Team,
+    timer: u32,
+}
+
+/// Shootout contest that volunteers can take part in during intermissions.
+///
+/// Each entrant gets one attempt at a time, in the order they entered, for as long as there is
+/// time left of the intermission. Goals in the contest don't affect the match score.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IntermissionContest {
+    entrants: Vec<PlayerId>,
+    goals: HashMap<PlayerId, u32>,
+    attempts_taken: u32,
+    next_entrant: usize,
+    attempt: Option<ContestAttempt>,
+    delay: u32,
+    finished: bool,
+}
+
+impl IntermissionContest {
+    /// Clears everything, including the entrants. Called when a new game starts.
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Prepares the contest for a new intermission.
+    pub(crate) fn begin(&mut self, mut server: ServerMut, intermission_time: u32) {
+        self.goals.clear();
+        self.attempts_taken = 0;
+        self.next_entrant = 0;
+        self.attempt = None;
+        self.delay = START_DELAY;
+        // Only run the contest if there's time for at least one attempt
+        self.finished = intermission_time < START_DELAY + ATTEMPT_TIME + FINISH_TIME;
+        if !self.finished {
+            server
+                .players_mut()
+                .add_server_chat_message("Shootout contest: type /enter to take part");
+        }
+    }
+
+    pub(crate) fn enter(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        if self.entrants.contains(&player_id) {
+            server.players_mut().add_directed_server_chat_message(
+                "You have already entered the shootout contest",
+                player_id,
+            );
+        } else if let Some(player) = server.players().get(player_id) {
+            let name = player.name();
+            self.entrants.push(player_id);
+            info!("{} ({}) entered shootout contest", name, player_id);
+            let msg = format!("{} entered the shootout contest", name);
+            server.players_mut().add_server_chat_message(msg);
+        }
+    }
+
+    pub(crate) fn remove_player(&mut self, player_id: PlayerId) {
+        if let Some(pos) = self.entrants.iter().position(|x| *x == player_id) {
+            self.entrants.remove(pos);
+            if pos < self.next_entrant {
+                self.next_entrant -= 1;
+            }
+        }
+        self.goals.remove(&player_id);
+        if matches!(&self.attempt, Some(attempt) if attempt.shooter == player_id) {
+            self.attempt = None;
+            self.delay = ATTEMPT_DELAY;
+        }
+    }
+
+    /// Called each tick of the intermission with the remaining intermission time.
+    pub(crate) fn update(
+        &mut self,
+        mut server: ServerMut,
+        events: &[PhysicsEvent],
+        pause_timer: u32,
+    ) {
+        if self.finished {
+            return;
+        }
+        if pause_timer <= FINISH_TIME {
+            self.finish(server);
+            return;
+        }
+
+        if let Some(attempt) = &mut self.attempt {
+            let defending_team = attempt.team.get_other_team();
+            let scored = events.iter().any(|event| {
+                matches!(event, PhysicsEvent::PuckEnteredNet { team, .. } if *team == defending_team)
+            });
+            attempt.timer = attempt.timer.saturating_sub(1);
+            if scored || attempt.timer == 0 {
+                let shooter = attempt.shooter;
+                let name = server
+                    .players()
+                    .get(shooter)
+                    .map(|player| player.name())
+                    .unwrap_or_else(|| "Unknown".into());
+                let msg = if scored {
+                    *self.goals.entry(shooter).or_insert(0) += 1;
+                    format!("{} scores!", name)
+                } else {
+                    format!("{} misses", name)
+                };
+                server.players_mut().add_server_chat_message(msg);
+                self.attempt = None;
+                self.delay = ATTEMPT_DELAY;
+            }
+        } else if self.delay > 0 {
+            self.delay -= 1;
+        } else if pause_timer > ATTEMPT_TIME + FINISH_TIME {
+            self.start_next_attempt(server);
+        }
+    }
+
+    fn start_next_attempt(&mut self, mut server: ServerMut) {
+        // Find the next entrant that is on the ice
+        let count = self.entrants.len();
+        let next = (0..count)
+            .map(|i| (self.next_entrant + i) % count)
+            .find_map(|i| {
+                let player_id = self.entrants[i];
+                server
+                    .players()
+                    .get(player_id)
+                    .and_then(|player| player.team())
+                    .map(|team| (i, player_id, team))
+            });
+        let Some((i, shooter, team)) = next else {
+            return;
+        };
+        self.next_entrant = i + 1;
+        self.attempts_taken += 1;
+        self.attempt = Some(ContestAttempt {
+            shooter,
+            team,
+            timer: ATTEMPT_TIME,
+        });
+
+        let rink = server.rink();
+        let center_pos = Point3::new(rink.width / 2.0, 1.5, rink.length / 2.0);
+        let puck_pos = Point3::new(rink.width / 2.0, 1.0, rink.length / 2.0);
+        let rot = match team {
+            Team::Red => Rotation3::identity(),
+            Team::Blue => Rotation3::from_euler_angles(0.0, PI, 0.0),
+        };
+        let pos = center_pos + rot * Vector3::new(0.0, 0.0, 3.0);
+
+        server.pucks_mut().remove_all_pucks();
+        server
+            .pucks_mut()
+            .spawn_puck(Puck::new(puck_pos, Rotation3::identity()));
+        server
+            .players_mut()
+            .spawn_skater(shooter, team, pos, rot, false);
+
+        if let Some(player) = server.players().get(shooter) {
+            let msg = format!("Shootout contest: {} shoots", player.name());
+            server.players_mut().add_server_chat_message(msg);
+        }
+    }
+
+    fn finish(&mut self, mut server: ServerMut) {
+        self.finished = true;
+        self.attempt = None;
+        if self.attempts_taken == 0 {
+            return;
+        }
+        let best = self.goals.values().copied().max().unwrap_or(0);
+        let msg = if best == 0 {
+            "Nobody scored in the shootout contest".to_owned()
+        } else {
+            let winners = self
+                .entrants
+                .iter()
+                .filter(|player_id| self.goals.get(player_id) == Some(&best))
+                .filter_map(|player_id| {
+                    server
+                        .players()
+                        .get(*player_id)
+                        .map(|player| player.name().to_string())
+                })
+                .collect::<Vec<_>>();
+            let goals = if best == 1 { "goal" } else { "goals" };
+            if winners.len() == 1 {
+                format!(
+                    "Shootout contest won by {} with {} {}",
+                    winners[0], best, goals
+                )
+            } else {
+                format!(
+                    "Shootout contest tied between {} with {} {}",
+                    winners.join(", "),
+                    best,
+                    goals
+                )
+            }
+        };
+        server.players_mut().add_server_chat_message(msg);
+    }
+}
@@ -0,0 +1,277 @@
+//! Capabilities that can be added around any game mode.
+//!
+//! A [`GameModeLayer`] implements one capability, like keeping the teams even, collecting statistics or
+//! filtering chat, without being a game mode of its own. [`LayeredGameMode`] wraps a game mode and calls its
+//! layers from each [`GameMode`] method, so the same layer can be used with every mode:
+//!
+//! ```
+//! use migo_hqm_server::game::PlayerId;
+//! use migo_hqm_server::gamemode::layers::{GameModeLayer, LayeredGameMode};
+//! use migo_hqm_server::gamemode::practice::PracticeGameMode;
+//! use migo_hqm_server::gamemode::util::SpawnPoint;
+//! use migo_hqm_server::gamemode::ServerMut;
+//!
+//! /// Greets every player that joins.
+//! struct Welcome;
+//!
+//! impl GameModeLayer for Welcome {
+//!     fn after_player_join(&mut self, mut server: ServerMut, player_id: PlayerId) {
+//!         server
+//!             .players_mut()
+//!             .add_directed_server_chat_message("Welcome!", player_id);
+//!     }
+//! }
+//!
+//! let game_mode = LayeredGameMode::new(PracticeGameMode::new(1, SpawnPoint::Center)).with(Welcome);
+//! ```
+//!
+//! Layers are called in the order they were added, before the wrapped game mode, so that they can take
+//! commands and chat messages before it. The exceptions are [`GameModeLayer::init`], where the game mode is
+//! initialized first so that layers can register commands after game modes that clear them, and
+//! [`GameModeLayer::scoreboard_display`], where layers can change what the game mode has set up.
+
+use crate::game::{PhysicsEvent, PlayerId, ScoreboardDisplay};
+use crate::gamemode::{ExitReason, GameMode, InitialGameValues, Server, ServerMut};
+use crate::votes::VoteHandler;
+use reborrow::{Reborrow, ReborrowMut};
+use tokio::task::JoinHandle;
+
+/// A capability added around a game mode with [`LayeredGameMode::with`].
+///
+/// The methods are called from the [`GameMode`] methods with the same names, and do nothing by default.
+pub trait GameModeLayer {
+    /// Called when the server starts, after the game mode has been initialized.
+    fn init(&mut self, _server: ServerMut) {}
+
+    fn before_tick(&mut self, _server: ServerMut) {}
+
+    fn after_tick(&mut self, _server: ServerMut, _events: &[PhysicsEvent]) {}
+
+    /// Called when a player sends a command. Returns `true` if the layer handled the command, in which case
+    /// it is not passed on to the next layers and the game mode.
+    fn handle_command(
+        &mut self,
+        _server: ServerMut,
+        _cmd: &str,
+        _arg: &str,
+        _player_id: PlayerId,
+    ) -> bool {
+        false
+    }
+
+    /// Called when a command is received from the web admin interface. Returns `true` if the layer handled
+    /// the command, in which case it is not passed on.
+    fn handle_remote_command(
+        &mut self,
+        _server: ServerMut,
+        _cmd: &str,
+        _arg: &str,
+        _admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        None
+    }
+
+    /// Called when a player sends a chat message that has passed the chat filter. The layer can change the
+    /// message, or return `false` to drop it before the next layers and the game mode see it.
+    fn before_chat_message(
+        &mut self,
+        _server: ServerMut,
+        _player_id: PlayerId,
+        _message: &mut String,
+    ) -> bool {
+        true
+    }
+
+    fn game_started(&mut self, _server: ServerMut) {}
+
+    fn before_player_exit(
+        &mut self,
+        _server: ServerMut,
+        _player_id: PlayerId,
+        _reason: ExitReason,
+    ) {
+    }
+
+    fn after_player_join(&mut self, _server: ServerMut, _player_id: PlayerId) {}
+
+    fn on_player_afk(&mut self, _server: ServerMut, _player_id: PlayerId) {}
+
+    /// Returns true if the tick should be recorded even if the game mode doesn't record it.
+    fn include_tick_in_recording(&self, _server: Server) -> bool {
+        false
+    }
+
+    /// Called after the game mode has set up the scoreboard display.
+    fn scoreboard_display(&self, _server: Server, _display: &mut ScoreboardDisplay) {}
+
+    /// Returns the handler for vote types added by the layer. Votes are handled by the game mode if it has a
+    /// handler, otherwise by the first layer that has one.
+    fn vote_handler(&mut self) -> Option<&mut dyn VoteHandler> {
+        None
+    }
+}
+
+/// A game mode with layers around it.
+pub struct LayeredGameMode<B: GameMode> {
+    base: B,
+    layers: Vec<Box<dyn GameModeLayer>>,
+}
+
+impl<B: GameMode> LayeredGameMode<B> {
+    pub fn new(base: B) -> Self {
+        LayeredGameMode {
+            base,
+            layers: vec![],
+        }
+    }
+
+    /// Adds a layer, which is called after the layers added before it.
+    pub fn with(mut self, layer: impl GameModeLayer + 'static) -> Self {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Returns the wrapped game mode.
+    pub fn base(&self) -> &B {
+        &self.base
+    }
+
+    pub fn base_mut(&mut self) -> &mut B {
+        &mut self.base
+    }
+}
+
+impl<B: GameMode> GameMode for LayeredGameMode<B> {
+    fn init(&mut self, mut server: ServerMut) {
+        self.base.init(server.rb_mut());
+        for layer in self.layers.iter_mut() {
+            layer.init(server.rb_mut());
+        }
+    }
+
+    fn before_tick(&mut self, mut server: ServerMut) {
+        for layer in self.layers.iter_mut() {
+            layer.before_tick(server.rb_mut());
+        }
+        self.base.before_tick(server);
+    }
+
+    fn after_tick(&mut self, mut server: ServerMut, events: &[PhysicsEvent]) {
+        for layer in self.layers.iter_mut() {
+            layer.after_tick(server.rb_mut(), events);
+        }
+        self.base.after_tick(server, events);
+    }
+
+    fn handle_command(&mut self, mut server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        for layer in self.layers.iter_mut() {
+            if layer.handle_command(server.rb_mut(), cmd, arg, player_id) {
+                return;
+            }
+        }
+        self.base.handle_command(server, cmd, arg, player_id);
+    }
+
+    fn handle_remote_command(
+        &mut self,
+        mut server: ServerMut,
+        cmd: &str,
+        arg: &str,
+        admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        for layer in self.layers.iter_mut() {
+            if let Some(res) = layer.handle_remote_command(server.rb_mut(), cmd, arg, admin_name) {
+                return Some(res);
+            }
+        }
+        self.base
+            .handle_remote_command(server, cmd, arg, admin_name)
+    }
+
+    fn before_chat_message(
+        &mut self,
+        mut server: ServerMut,
+        player_id: PlayerId,
+        message: &mut String,
+    ) -> bool {
+        for layer in self.layers.iter_mut() {
+            if !layer.before_chat_message(server.rb_mut(), player_id, message) {
+                return false;
+            }
+        }
+        self.base.before_chat_message(server, player_id, message)
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        self.base.get_initial_game_values()
+    }
+
+    fn game_started(&mut self, mut server: ServerMut) {
+        for layer in self.layers.iter_mut() {
+            layer.game_started(server.rb_mut());
+        }
+        self.base.game_started(server);
+    }
+
+    fn before_player_exit(
+        &mut self,
+        mut server: ServerMut,
+        player_id: PlayerId,
+        reason: ExitReason,
+    ) {
+        for layer in self.layers.iter_mut() {
+            layer.before_player_exit(server.rb_mut(), player_id, reason);
+        }
+        self.base.before_player_exit(server, player_id, reason);
+    }
+
+    fn after_player_join(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        for layer in self.layers.iter_mut() {
+            layer.after_player_join(server.rb_mut(), player_id);
+        }
+        self.base.after_player_join(server, player_id);
+    }
+
+    fn on_player_afk(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        for layer in self.layers.iter_mut() {
+            layer.on_player_afk(server.rb_mut(), player_id);
+        }
+        self.base.on_player_afk(server, player_id);
+    }
+
+    fn hold_warmup(&mut self, server: ServerMut) {
+        self.base.hold_warmup(server);
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        self.base.server_list_team_size()
+    }
+
+    fn include_tick_in_recording(&self, server: Server) -> bool {
+        self.base.include_tick_in_recording(server.rb())
+            || self
+                .layers
+                .iter()
+                .any(|layer| layer.include_tick_in_recording(server.rb()))
+    }
+
+    fn scoreboard_display(&self, server: Server, display: &mut ScoreboardDisplay) {
+        self.base.scoreboard_display(server.rb(), display);
+        for layer in self.layers.iter() {
+            layer.scoreboard_display(server.rb(), display);
+        }
+    }
+
+    fn vote_handler(&mut self) -> Option<&mut dyn VoteHandler> {
+        if let Some(handler) = self.base.vote_handler() {
+            return Some(handler);
+        }
+        self.layers
+            .iter_mut()
+            .find_map(|layer| layer.vote_handler())
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.base.take_pending_saves()
+    }
+}
@@ -0,0 +1,253 @@
+//! Named warmup layouts, saved with `/layout save NAME` and restored with `/layout load NAME`.
+//!
+//! A layout is where the pucks and the obstacles are. Each layout is saved in its own file `NAME.layout` in the
+//! layout directory, one object per line: `puck X Y Z` or `obstacle X Z RADIUS HEIGHT`.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::path::PathBuf;
+
+use nalgebra::{Point3, Rotation3};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::file_writer::FileWriter;
+use crate::game::{Obstacle, PlayerId, Puck};
+
+const EXTENSION: &str = "layout";
+
+/// Longest allowed layout name.
+const MAX_NAME_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Layout {
+    pub(crate) pucks: Vec<Point3<f32>>,
+    pub(crate) obstacles: Vec<Obstacle>,
+}
+
+impl Layout {
+    pub(crate) fn new<'a>(
+        pucks: impl IntoIterator<Item = &'a Puck>,
+        obstacles: &[Obstacle],
+    ) -> Self {
+        Layout {
+            pucks: pucks.into_iter().map(|puck| puck.body.pos).collect(),
+            obstacles: obstacles.to_vec(),
+        }
+    }
+
+    /// Creates the pucks of the layout, at rest.
+    pub(crate) fn create_pucks(&self) -> impl Iterator<Item = Puck> + '_ {
+        self.pucks
+            .iter()
+            .map(|pos| Puck::new(*pos, Rotation3::identity()))
+    }
+
+    fn to_text(&self) -> String {
+        let mut s = String::new();
+        for pos in self.pucks.iter() {
+            let _ = writeln!(s, "puck {} {} {}", pos.x, pos.y, pos.z);
+        }
+        for obstacle in self.obstacles.iter() {
+            let _ = writeln!(
+                s,
+                "obstacle {} {} {} {}",
+                obstacle.pos.x, obstacle.pos.z, obstacle.radius, obstacle.height
+            );
+        }
+        s
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let mut layout = Layout::default();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            let kind = parts.next()?;
+            let numbers = parts
+                .map(|x| x.parse::<f32>().ok().filter(|x| x.is_finite()))
+                .collect::<Option<Vec<_>>>()?;
+            match (kind, numbers.as_slice()) {
+                ("puck", [x, y, z]) => layout.pucks.push(Point3::new(*x, *y, *z)),
+                ("obstacle", [x, z, radius, height]) => {
+                    layout.obstacles.push(Obstacle::new(
+                        Point3::new(*x, 0.0, *z),
+                        *radius,
+                        *height,
+                    ));
+                }
+                _ => return None,
+            }
+        }
+        Some(layout)
+    }
+}
+
+/// A layout read by [`LayoutStore::load`] or the names listed by [`LayoutStore::list`].
+pub(crate) enum LayoutResult {
+    Loaded {
+        player_id: PlayerId,
+        /// Name of the player who loaded the layout.
+        player_name: String,
+        layout_name: String,
+        layout: io::Result<Layout>,
+    },
+    Listed {
+        player_id: PlayerId,
+        names: Vec<String>,
+    },
+}
+
+/// Layouts saved as files in a directory.
+///
+/// The files are read and written in the background. The results of [`LayoutStore::load`] and
+/// [`LayoutStore::list`] are returned by [`LayoutStore::results`] in a later tick.
+pub(crate) struct LayoutStore {
+    dir: PathBuf,
+    /// Layouts saved since the server started, so that they can be loaded before their file has been written.
+    saved: HashMap<String, Layout>,
+    writers: HashMap<String, FileWriter>,
+    sender: mpsc::UnboundedSender<LayoutResult>,
+    receiver: mpsc::UnboundedReceiver<LayoutResult>,
+}
+
+impl LayoutStore {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        LayoutStore {
+            dir,
+            saved: HashMap::new(),
+            writers: HashMap::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Returns the file of the layout, or [`None`] if the name isn't allowed.
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        let valid = !name.is_empty()
+            && name.len() <= MAX_NAME_LENGTH
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then(|| self.dir.join(name).with_extension(EXTENSION))
+    }
+
+    pub(crate) fn is_valid_name(&self, name: &str) -> bool {
+        self.path(name).is_some()
+    }
+
+    /// Saves the layout in the background.
+    pub(crate) fn save(&mut self, name: &str, layout: Layout) {
+        let Some(path) = self.path(name) else {
+            return;
+        };
+        self.writers
+            .entry(name.to_owned())
+            .or_insert_with(|| FileWriter::new(path, "layout file"))
+            .write(layout.to_text());
+        self.saved.insert(name.to_owned(), layout);
+    }
+
+    /// Loads a layout for `player_id`, named `player_name`.
+    pub(crate) fn load(&self, name: &str, player_id: PlayerId, player_name: &str) {
+        let sender = self.sender.clone();
+        let player_name = player_name.to_owned();
+        let layout_name = name.to_owned();
+        if let Some(layout) = self.saved.get(name) {
+            let _ = sender.send(LayoutResult::Loaded {
+                player_id,
+                player_name,
+                layout_name,
+                layout: Ok(layout.clone()),
+            });
+            return;
+        }
+        let path = self.path(name);
+        tokio::spawn(async move {
+            let layout = async {
+                let path = path.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "Invalid layout name")
+                })?;
+                let s = tokio::fs::read_to_string(path).await?;
+                Layout::parse(&s).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Invalid layout file")
+                })
+            }
+            .await;
+            let _ = sender.send(LayoutResult::Loaded {
+                player_id,
+                player_name,
+                layout_name,
+                layout,
+            });
+        });
+    }
+
+    /// Lists the names of the saved layouts for `player_id`, sorted.
+    pub(crate) fn list(&self, player_id: PlayerId) {
+        let sender = self.sender.clone();
+        let dir = self.dir.clone();
+        let mut names: Vec<String> = self.saved.keys().cloned().collect();
+        tokio::spawn(async move {
+            if let Ok(mut entries) = tokio::fs::read_dir(&dir).await {
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    let path = entry.path();
+                    if path.extension().is_some_and(|x| x == EXTENSION) {
+                        if let Some(name) = path.file_stem().and_then(|x| x.to_str()) {
+                            names.push(name.to_owned());
+                        }
+                    }
+                }
+            }
+            names.sort();
+            names.dedup();
+            let _ = sender.send(LayoutResult::Listed { player_id, names });
+        });
+    }
+
+    /// Returns the layouts and lists that have been read since the last call.
+    pub(crate) fn results(&mut self) -> Vec<LayoutResult> {
+        let mut results = vec![];
+        while let Ok(result) = self.receiver.try_recv() {
+            results.push(result);
+        }
+        results
+    }
+
+    /// Returns the saves that are still being written.
+    pub(crate) fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.writers
+            .values_mut()
+            .filter_map(FileWriter::take_pending_save)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use nalgebra::Point3;
+
+    use super::{Layout, LayoutStore};
+    use crate::game::Obstacle;
+
+    #[test]
+    fn layout_files() {
+        let layout = Layout {
+            pucks: vec![Point3::new(15.0, 0.5, 30.25)],
+            obstacles: vec![Obstacle::cone(Point3::new(10.0, 0.0, 20.0))],
+        };
+        let s = layout.to_text();
+        assert_eq!(s, "puck 15 0.5 30.25\nobstacle 10 20 0.15 0.3\n");
+        assert_eq!(Layout::parse(&s), Some(layout));
+        assert_eq!(Layout::parse("puck 1 2"), None);
+        assert_eq!(Layout::parse("cone 1 2 3 4"), None);
+
+        let store = LayoutStore::new(PathBuf::from("layouts"));
+        assert!(store.is_valid_name("pp-drill_2"));
+        assert!(!store.is_valid_name("../config"));
+        assert!(!store.is_valid_name(""));
+    }
+}
@@ -1,13 +1,40 @@
 use crate::game::PlayerId;
-use crate::game::Team;
+use crate::game::{PlayerIndex, Team};
 use crate::gamemode::ServerMut;
+use crate::live_state::GameSnapshot;
 
 use crate::gamemode::match_util::{
-    IcingConfiguration, Match, OffsideConfiguration, OffsideLineConfiguration,
-    TwoLinePassConfiguration, ALLOWED_POSITIONS,
+    IcingConfiguration, Match, OffsideConfiguration, OffsideLineConfiguration, PeriodRules,
+    RinkFaceoffSpot, TwoLinePassConfiguration, ALLOWED_POSITIONS,
 };
+use reborrow::ReborrowMut;
+use tokio::sync::oneshot;
 use tracing::info;
 
+/// Length of a penalty given with /penalty, in seconds, if no length is given.
+const DEFAULT_PENALTY_TIME: u32 = 60;
+
+const PENALTY_USAGE: &str = "Usage: /penalty ID [seconds], for a player on the ice during the game";
+
+/// A game to resume with /resume, whose file is being read.
+pub(crate) struct PendingResume {
+    path: String,
+    admin_id: PlayerId,
+    admin_name: String,
+    receiver: oneshot::Receiver<Option<GameSnapshot>>,
+}
+
+/// Reads the snapshot of a game from a live state file, ending with `.json`, or a recording.
+async fn read_snapshot(path: &str) -> Option<GameSnapshot> {
+    if path.ends_with(".json") {
+        let json = tokio::fs::read_to_string(path).await.ok()?;
+        GameSnapshot::from_live_state(&json)
+    } else {
+        let recording = tokio::fs::read(path).await.ok()?;
+        GameSnapshot::from_recording(&recording).ok()
+    }
+}
+
 impl Match {
     pub fn reset_game(&mut self, mut server: ServerMut, player_id: PlayerId) {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
@@ -21,7 +48,93 @@ impl Match {
         }
     }
 
-    pub fn start_game(&mut self, mut server: ServerMut, player_id: PlayerId) {
+    /// Resumes an abandoned game from a live state file or a recording.
+    ///
+    /// The file is read in the background. When it has been read, a new game is started with the score,
+    /// period and clock of the snapshot, tagged as a league game, so that it is recorded to a new file that
+    /// refers to the old one.
+    pub fn resume_game(&mut self, mut server: ServerMut, player_id: PlayerId, path: &str) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let (sender, receiver) = oneshot::channel();
+            let file = path.to_owned();
+            tokio::spawn(async move {
+                let _ = sender.send(read_snapshot(&file).await);
+            });
+            self.pending_resume = Some(PendingResume {
+                path: path.to_owned(),
+                admin_id: player_id,
+                admin_name: player.name().to_string(),
+                receiver,
+            });
+        }
+    }
+
+    /// Starts the resumed game when its file has been read. Returns true if a game was resumed.
+    pub(crate) fn update_resume(&mut self, mut server: ServerMut) -> bool {
+        let Some(pending) = &mut self.pending_resume else {
+            return false;
+        };
+        let snapshot = match pending.receiver.try_recv() {
+            Ok(snapshot) => snapshot,
+            Err(oneshot::error::TryRecvError::Empty) => return false,
+            Err(oneshot::error::TryRecvError::Closed) => None,
+        };
+        let Some(PendingResume {
+            path,
+            admin_id,
+            admin_name: name,
+            ..
+        }) = self.pending_resume.take()
+        else {
+            return false;
+        };
+        let Some(snapshot) = snapshot.filter(|x| x.period > 0 && x.time > 0) else {
+            server.players_mut().add_directed_server_chat_message(
+                "Could not read a game in progress from that file",
+                admin_id,
+            );
+            return false;
+        };
+
+        server.new_game(self.get_initial_game_values());
+        server.set_league_game(true);
+        server.game_summary_mut().resumed_from = Some(path.clone());
+        let values = server.scoreboard_mut();
+        values.red_score = snapshot.red_score;
+        values.blue_score = snapshot.blue_score;
+        values.period = snapshot.period;
+        values.time = snapshot.time;
+        self.paused = false;
+        self.pause_timer = 5 * 100;
+        self.next_faceoff_spot = RinkFaceoffSpot::Center;
+        self.apply_period_rules(server.rb_mut());
+
+        info!(
+            "{} ({}) resumed game from {}: {}-{}, period {}, time {}",
+            name,
+            admin_id,
+            path,
+            snapshot.red_score,
+            snapshot.blue_score,
+            snapshot.period,
+            snapshot.time
+        );
+        let seconds = snapshot.time / 100;
+        let msg = format!(
+            "Game resumed by {}: {}-{}, period {}, {}:{:02}",
+            name,
+            snapshot.red_score,
+            snapshot.blue_score,
+            snapshot.period,
+            seconds / 60,
+            seconds % 60
+        );
+        server.players_mut().add_server_chat_message(msg);
+        true
+    }
+
+    /// Ends the warmup. Returns true if the game was started.
+    pub fn start_game(&mut self, mut server: ServerMut, player_id: PlayerId) -> bool {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
             let name = player.name();
             let values = server.scoreboard_mut();
@@ -32,36 +145,47 @@ impl Match {
                 values.time = 1;
 
                 server.players_mut().add_server_chat_message(msg);
+                return true;
             }
         }
+        false
     }
 
     pub fn pause(&mut self, mut server: ServerMut, player_id: PlayerId) {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
-            self.paused = true;
-            if self.pause_timer > 0 && self.pause_timer < self.config.time_break {
-                // If we're currently in a break, with very little time left,
-                // we reset the timer
-                self.pause_timer = self.config.time_break;
-            }
             let name = player.name();
             info!("{} ({}) paused game", name, player_id);
-            let msg = format!("Game paused by {}", name);
-            server.players_mut().add_server_chat_message(msg);
+            self.pause_by(server, &name);
+        }
+    }
+
+    /// Pauses the game on behalf of an administrator that has already been authenticated.
+    pub fn pause_by(&mut self, mut server: ServerMut, admin_name: &str) {
+        self.paused = true;
+        if self.pause_timer > 0 && self.pause_timer < self.config.time_break {
+            // If we're currently in a break, with very little time left,
+            // we reset the timer
+            self.pause_timer = self.config.time_break;
         }
+        let msg = format!("Game paused by {}", admin_name);
+        server.players_mut().add_server_chat_message(msg);
     }
 
     pub fn unpause(&mut self, mut server: ServerMut, player_id: PlayerId) {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
-            self.paused = false;
             let name = player.name();
             info!("{} ({}) resumed game", name, player_id);
-            let msg = format!("Game resumed by {}", name);
-
-            server.players_mut().add_server_chat_message(msg);
+            self.unpause_by(server, &name);
         }
     }
 
+    /// Resumes the game on behalf of an administrator that has already been authenticated.
+    pub fn unpause_by(&mut self, mut server: ServerMut, admin_name: &str) {
+        self.paused = false;
+        let msg = format!("Game resumed by {}", admin_name);
+        server.players_mut().add_server_chat_message(msg);
+    }
+
     pub fn set_clock(&mut self, mut server: ServerMut, input_time: u32, player_id: PlayerId) {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
             let name = player.name();
@@ -99,7 +223,11 @@ impl Match {
                         "{} ({}) changed red score to {}",
                         name, player_id, input_score
                     );
-                    let msg = format!("Red score changed by {}", name);
+                    let msg = format!(
+                        "{} score changed by {}",
+                        server.config().team_names.name(Team::Red),
+                        name
+                    );
                     server.players_mut().add_server_chat_message(msg);
                 }
                 Team::Blue => {
@@ -109,7 +237,11 @@ impl Match {
                         "{} ({}) changed blue score to {}",
                         name, player_id, input_score
                     );
-                    let msg = format!("Blue score changed by {}", name);
+                    let msg = format!(
+                        "{} score changed by {}",
+                        server.config().team_names.name(Team::Blue),
+                        name
+                    );
                     server.players_mut().add_server_chat_message(msg);
                 }
             }
@@ -125,7 +257,8 @@ impl Match {
             info!("{} ({}) set period to {}", name, player_id, input_period);
             let msg = format!("Period set by {}", name);
             server.players_mut().add_server_chat_message(msg);
-            self.update_game_over(server);
+            self.update_game_over(server.rb_mut());
+            self.apply_period_rules(server);
         }
     }
 
@@ -155,21 +288,30 @@ impl Match {
 
             match rule {
                 "on" | "touch" => {
-                    self.config.icing = IcingConfiguration::Touch;
+                    self.set_rules_by_admin(PeriodRules {
+                        icing: Some(IcingConfiguration::Touch),
+                        ..Default::default()
+                    });
                     info!("{} ({}) enabled touch icing", name, player_id);
                     let msg = format!("Touch icing enabled by {}", name);
 
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "notouch" => {
-                    self.config.icing = IcingConfiguration::NoTouch;
+                    self.set_rules_by_admin(PeriodRules {
+                        icing: Some(IcingConfiguration::NoTouch),
+                        ..Default::default()
+                    });
                     info!("{} ({}) enabled no-touch icing", name, player_id);
                     let msg = format!("No-touch icing enabled by {}", name);
 
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "off" => {
-                    self.config.icing = IcingConfiguration::Off;
+                    self.set_rules_by_admin(PeriodRules {
+                        icing: Some(IcingConfiguration::Off),
+                        ..Default::default()
+                    });
                     info!("{} ({}) disabled icing", name, player_id);
                     let msg = format!("Icing disabled by {}", name);
 
@@ -186,14 +328,20 @@ impl Match {
 
             match rule {
                 "blue" => {
-                    self.config.offside_line = OffsideLineConfiguration::OffensiveBlue;
+                    self.set_rules_by_admin(PeriodRules {
+                        offside_line: Some(OffsideLineConfiguration::OffensiveBlue),
+                        ..Default::default()
+                    });
                     info!("{} ({}) set blue line as offside line", name, player_id);
                     let msg = format!("Blue line set as offside line by {}", name);
 
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "center" => {
-                    self.config.offside_line = OffsideLineConfiguration::Center;
+                    self.set_rules_by_admin(PeriodRules {
+                        offside_line: Some(OffsideLineConfiguration::Center),
+                        ..Default::default()
+                    });
                     info!("{} ({}) set center line as offside line", name, player_id);
                     let msg = format!("Center line set as offside line by {}", name);
 
@@ -208,7 +356,10 @@ impl Match {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
             match rule {
                 "off" => {
-                    self.config.twoline_pass = TwoLinePassConfiguration::Off;
+                    self.set_rules_by_admin(PeriodRules {
+                        twoline_pass: Some(TwoLinePassConfiguration::Off),
+                        ..Default::default()
+                    });
                     let name = player.name();
                     info!("{} ({}) disabled two-line pass rule", name, player_id);
                     let msg = format!("Two-line pass rule disabled by {}", name);
@@ -216,7 +367,10 @@ impl Match {
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "on" => {
-                    self.config.twoline_pass = TwoLinePassConfiguration::On;
+                    self.set_rules_by_admin(PeriodRules {
+                        twoline_pass: Some(TwoLinePassConfiguration::On),
+                        ..Default::default()
+                    });
                     let name = player.name();
 
                     info!(
@@ -228,7 +382,10 @@ impl Match {
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "forward" => {
-                    self.config.twoline_pass = TwoLinePassConfiguration::Forward;
+                    self.set_rules_by_admin(PeriodRules {
+                        twoline_pass: Some(TwoLinePassConfiguration::Forward),
+                        ..Default::default()
+                    });
                     let name = player.name();
 
                     info!(
@@ -240,7 +397,10 @@ impl Match {
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "double" | "both" => {
-                    self.config.twoline_pass = TwoLinePassConfiguration::Double;
+                    self.set_rules_by_admin(PeriodRules {
+                        twoline_pass: Some(TwoLinePassConfiguration::Double),
+                        ..Default::default()
+                    });
                     let name = player.name();
 
                     info!(
@@ -252,7 +412,10 @@ impl Match {
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "blue" | "three" | "threeline" => {
-                    self.config.twoline_pass = TwoLinePassConfiguration::ThreeLine;
+                    self.set_rules_by_admin(PeriodRules {
+                        twoline_pass: Some(TwoLinePassConfiguration::ThreeLine),
+                        ..Default::default()
+                    });
                     let name = player.name();
 
                     info!("{} ({}) enabled three-line pass rule", name, player_id);
@@ -269,7 +432,10 @@ impl Match {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
             match rule {
                 "on" | "delayed" => {
-                    self.config.offside = OffsideConfiguration::Delayed;
+                    self.set_rules_by_admin(PeriodRules {
+                        offside: Some(OffsideConfiguration::Delayed),
+                        ..Default::default()
+                    });
                     let name = player.name();
                     info!("{} ({}) enabled offside", name, player_id);
                     let msg = format!("Offside enabled by {}", name);
@@ -277,7 +443,10 @@ impl Match {
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "imm" | "immediate" => {
-                    self.config.offside = OffsideConfiguration::Immediate;
+                    self.set_rules_by_admin(PeriodRules {
+                        offside: Some(OffsideConfiguration::Immediate),
+                        ..Default::default()
+                    });
 
                     let name = player.name();
                     info!("{} ({}) enabled immediate offside", name, player_id);
@@ -286,7 +455,10 @@ impl Match {
                     server.players_mut().add_server_chat_message(msg);
                 }
                 "off" => {
-                    self.config.offside = OffsideConfiguration::Off;
+                    self.set_rules_by_admin(PeriodRules {
+                        offside: Some(OffsideConfiguration::Off),
+                        ..Default::default()
+                    });
 
                     let name = player.name();
                     info!("{} ({}) disabled offside", name, player_id);
@@ -316,7 +488,33 @@ impl Match {
                     let msg = format!("Goal replays disabled by {}", name);
                     server.players_mut().add_server_chat_message(msg);
                 }
-                _ => {}
+                _ => {
+                    let length = setting
+                        .split_once(' ')
+                        .filter(|(word, _)| *word == "length")
+                        .and_then(|(_, x)| x.trim().parse::<u32>().ok())
+                        .filter(|x| *x > 0);
+                    if let Some(length) = length {
+                        self.config.goal_replay_max = length;
+
+                        let name = player.name();
+                        server
+                            .replay_mut()
+                            .set_history_length(self.replay_history_length());
+                        info!(
+                            "{} ({}) set goal replay length to {} seconds",
+                            name, player_id, length
+                        );
+                        let msg =
+                            format!("Goal replay length set to {} seconds by {}", length, name);
+                        server.players_mut().add_server_chat_message(msg);
+                    } else {
+                        server.players_mut().add_directed_server_chat_message(
+                            "Usage: /goalreplay on, off or length SECONDS",
+                            player_id,
+                        );
+                    }
+                }
             }
         }
     }
@@ -378,17 +576,67 @@ impl Match {
     pub fn faceoff(&mut self, mut server: ServerMut, player_id: PlayerId) {
         if !server.scoreboard().game_over {
             if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
-                self.pause_timer = 5 * 100;
-                self.paused = false; // Unpause if it's paused as well
-
                 let name = player.name();
-                let msg = format!("Faceoff initiated by {}", name);
                 info!("{} ({}) initiated faceoff", name, player_id);
-                server.players_mut().add_server_chat_message(msg);
+                self.faceoff_by(server, &name);
+            }
+        }
+    }
+
+    /// Initiates a faceoff on behalf of an administrator that has already been authenticated.
+    pub fn faceoff_by(&mut self, mut server: ServerMut, admin_name: &str) {
+        if !server.scoreboard().game_over {
+            self.pause_timer = 5 * 100;
+            self.paused = false; // Unpause if it's paused as well
+
+            let msg = format!("Faceoff initiated by {}", admin_name);
+            server.players_mut().add_server_chat_message(msg);
+        }
+    }
+
+    pub fn penalty(&mut self, mut server: ServerMut, player_id: PlayerId, arg: &str) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let name = player.name();
+            match self.penalty_by(server.rb_mut(), &name, arg) {
+                Ok(()) => info!("{} ({}) gave penalty: {}", name, player_id, arg),
+                Err(usage) => server
+                    .players_mut()
+                    .add_directed_server_chat_message(usage, player_id),
             }
         }
     }
 
+    /// Gives a penalty on behalf of an administrator that has already been authenticated.
+    /// `arg` is the player index, optionally followed by the length of the penalty in seconds.
+    /// Returns the usage if the arguments are invalid, or the player doesn't exist or is not on the ice.
+    pub fn penalty_by(
+        &mut self,
+        mut server: ServerMut,
+        admin_name: &str,
+        arg: &str,
+    ) -> Result<(), &'static str> {
+        let mut args = arg.split_whitespace();
+        let player_index = args
+            .next()
+            .and_then(|x| x.parse::<PlayerIndex>().ok())
+            .ok_or(PENALTY_USAGE)?;
+        let duration = match args.next().map(|x| x.parse::<u32>()) {
+            None => DEFAULT_PENALTY_TIME,
+            Some(Ok(duration)) if duration > 0 => duration,
+            Some(_) => return Err(PENALTY_USAGE),
+        };
+        let penalized_id = server
+            .players()
+            .id_by_index(player_index)
+            .ok_or(PENALTY_USAGE)?;
+        if !self.assess_penalty(server.rb_mut(), penalized_id, duration) {
+            return Err(PENALTY_USAGE);
+        }
+        let msg = format!("Penalty given by {}", admin_name);
+        server.players_mut().add_server_chat_message(msg);
+        Ok(())
+    }
+
     pub fn set_preferred_faceoff_position(
         &mut self,
         mut server: ServerMut,
@@ -406,13 +654,84 @@ impl Match {
                 info!("{} ({}) set position {}", name, player_id, position);
                 let msg = format!("{} position {}", name, position);
 
-                self.preferred_positions.insert(player_id, position);
+                self.preferred_positions.set(&player, position);
                 server.players_mut().add_server_chat_message(msg);
             }
         }
     }
 
+    pub fn enter_intermission_contest(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        if self.config.intermission_shootout {
+            self.contest.enter(server, player_id);
+        } else {
+            server.players_mut().add_directed_server_chat_message(
+                "Intermission shootout contest is disabled",
+                player_id,
+            );
+        }
+    }
+
     pub fn msg_rules(&self, mut server: ServerMut, receiver_id: PlayerId) {
+        let msg = self.rules_str();
+        server
+            .players_mut()
+            .add_directed_server_chat_message(msg, receiver_id);
+        let twoline_str = self.twoline_str();
+        if !twoline_str.is_empty() {
+            server
+                .players_mut()
+                .add_directed_server_chat_message(twoline_str, receiver_id);
+        }
+
+        if self.config.mercy > 0 {
+            let msg = format!("Mercy rule when team leads by {} goals", self.config.mercy);
+            server
+                .players_mut()
+                .add_directed_server_chat_message(msg, receiver_id);
+        }
+        if self.config.first_to > 0 {
+            let msg = format!("Game ends when team scores {} goals", self.config.first_to);
+            server
+                .players_mut()
+                .add_directed_server_chat_message(msg, receiver_id);
+        }
+    }
+
+    pub fn msg_ice_time(&self, mut server: ServerMut, receiver_id: PlayerId) {
+        fn format_time(time: u32) -> String {
+            let seconds = time / 100;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        }
+        let period = server.scoreboard().period;
+        let team_names = &server.config().team_names;
+        let lines = server
+            .game_summary()
+            .ice_time
+            .iter()
+            .map(|x| {
+                format!(
+                    "{} {}: {} ({} this period)",
+                    team_names.name(x.team),
+                    x.player,
+                    format_time(x.total()),
+                    format_time(x.period(period))
+                )
+            })
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            server
+                .players_mut()
+                .add_directed_server_chat_message("No ice time recorded yet", receiver_id);
+        }
+        for line in lines {
+            server
+                .players_mut()
+                .add_directed_server_chat_message(line, receiver_id);
+        }
+    }
+
+    /// Describes the offside and icing rules.
+    pub(crate) fn rules_str(&self) -> String {
         let offside_str = match self.config.offside {
             OffsideConfiguration::Off => "Offside disabled",
             OffsideConfiguration::Delayed => "Offside enabled",
@@ -431,34 +750,17 @@ impl Match {
             IcingConfiguration::NoTouch => "No-touch icing enabled",
         };
 
-        let msg = format!("{}{}, {}", offside_str, offside_line_str, icing_str);
-        server
-            .players_mut()
-            .add_directed_server_chat_message(msg, receiver_id);
-        let twoline_str = match self.config.twoline_pass {
+        format!("{}{}, {}", offside_str, offside_line_str, icing_str)
+    }
+
+    /// Describes the two-line pass rule, or returns an empty string if it's disabled.
+    pub(crate) fn twoline_str(&self) -> &'static str {
+        match self.config.twoline_pass {
             TwoLinePassConfiguration::Off => "",
             TwoLinePassConfiguration::On => "Two-line pass rule enabled",
             TwoLinePassConfiguration::Forward => "Forward two-line pass rule enabled",
             TwoLinePassConfiguration::Double => "Forward and regular two-line pass rule enabled",
             TwoLinePassConfiguration::ThreeLine => "Three-line pass rule enabled",
-        };
-        if !twoline_str.is_empty() {
-            server
-                .players_mut()
-                .add_directed_server_chat_message(twoline_str, receiver_id);
-        }
-
-        if self.config.mercy > 0 {
-            let msg = format!("Mercy rule when team leads by {} goals", self.config.mercy);
-            server
-                .players_mut()
-                .add_directed_server_chat_message(msg, receiver_id);
-        }
-        if self.config.first_to > 0 {
-            let msg = format!("Game ends when team scores {} goals", self.config.first_to);
-            server
-                .players_mut()
-                .add_directed_server_chat_message(msg, receiver_id);
         }
     }
 
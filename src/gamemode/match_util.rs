@@ -1,22 +1,49 @@
 use crate::game::{PlayerId, Puck, Rink, RinkLine, RulesState, ScoreboardValues, Team};
 use crate::gamemode::{InitialGameValues, PuckExt, ServerPlayers};
 
+use crate::extension::{AnnouncementEvent, SoundEvent};
 use crate::game::PhysicsEvent;
 use crate::game::RinkSideOfLine::{BlueSide, RedSide};
+use crate::gamemode::intermission::IntermissionContest;
+use crate::gamemode::match_commands::PendingResume;
+use crate::gamemode::positions::PreferredPositions;
+use crate::gamemode::scheduled_start::StartTime;
 use crate::gamemode::{Server, ServerMut, ServerPlayer};
+use crate::record::{AnnotationKind, IceTime};
 
 use arraydeque::{ArrayDeque, Wrapping};
 use nalgebra::{Point3, Rotation3, Vector3};
 use reborrow::{Reborrow, ReborrowMut};
+use smallvec::SmallVec;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::f32::consts::PI;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::path::PathBuf;
+use tracing::info;
 
 pub const ALLOWED_POSITIONS: [&str; 18] = [
     "C", "LW", "RW", "LD", "RD", "G", "LM", "RM", "LLM", "RRM", "LLD", "RRD", "CM", "CD", "LW2",
     "RW2", "LLW", "RRW",
 ];
 
+/// Faceoff positions during overtime with a smaller team size, in the order they are given out.
+/// Preferred positions after the first four are ignored, so that the few skaters on the ice
+/// line up as a center and two defensemen.
+const OVERTIME_POSITIONS: [&str; 18] = [
+    "C", "LD", "RD", "G", "LW", "RW", "LM", "RM", "LLM", "RRM", "LLD", "RRD", "CM", "CD", "LW2",
+    "RW2", "LLW", "RRW",
+];
+
+/// Ticks of a goal replay before the scoring team left its own zone.
+const GOAL_REPLAY_LEAD_IN: u32 = 100;
+
+/// A frozen puck has to be closer than this to the boards, in meters.
+const BOARDS_DISTANCE: f32 = 0.75;
+/// A frozen puck has to be closer than this to the middle of the net, in meters.
+const NET_DISTANCE: f32 = 2.5;
+/// A frozen puck has to be closer than this to the body or the stick of a skater, in meters.
+const PINNING_DISTANCE: f32 = 1.5;
+
 #[derive(Debug, Clone)]
 pub struct FaceoffSpot {
     pub center_position: Point3<f32>,
@@ -50,12 +77,104 @@ pub struct MatchConfiguration {
     pub offside_line: OffsideLineConfiguration,
     pub twoline_pass: TwoLinePassConfiguration,
     pub warmup_pucks: usize,
+    /// Number of pucks dropped at each faceoff.
+    pub game_pucks: usize,
     pub use_mph: bool,
     pub goal_replay: bool,
+    /// Maximum length of a goal replay before the goal, in seconds.
+    pub goal_replay_max: u32,
     pub spawn_point_offset: f32,
     pub spawn_player_altitude: f32,
     pub spawn_puck_altitude: f32,
     pub spawn_keep_stick_position: bool,
+    pub intermission_shootout: bool,
+    /// Seconds the puck can be pinned against the boards or the net before play is stopped
+    /// with a faceoff at the closest faceoff spot. 0 disables the rule.
+    pub frozen_puck_time: u32,
+    /// The puck counts as pinned only while it is slower than this, in meters per second.
+    pub frozen_puck_speed: f32,
+    /// Seconds the players can vote for the MVP when the game is over. 0 disables the vote.
+    pub mvp_vote_time: u32,
+    /// If true, players that join during the warmup are put into teams automatically.
+    pub warmup_auto_teams: bool,
+    /// Number of players each team needs on the ice before the warmup can end. 0 disables the rule.
+    pub min_players: usize,
+    /// Seconds a team can have fewer than `min_players` players on the ice during the game before it forfeits.
+    /// 0 disables forfeits.
+    pub forfeit_time: u32,
+    /// If true, players can't join the team with more players.
+    pub force_team_size_parity: bool,
+    /// If true, the player who joined the larger team last is moved to the other team before the next faceoff,
+    /// when a team has at least two players more than the other.
+    pub force_team_balance: bool,
+    /// Rule changes for specific periods, by period number.
+    pub period_rules: HashMap<u32, PeriodRules>,
+    /// Rule changes for all overtime periods.
+    pub overtime_rules: Option<PeriodRules>,
+    /// What happens when the score is tied after the regular periods.
+    pub overtime: OvertimeConfiguration,
+    /// Length of overtime periods in seconds.
+    pub time_overtime: u32,
+    /// Maximum number of players in each team during overtime periods. 0 keeps the regular team size.
+    pub overtime_team_size: usize,
+    /// If set, preferred faceoff positions are saved in this file, so that they are kept across restarts.
+    pub positions_file: Option<PathBuf>,
+    /// If set, the warmup of each game lasts until this time of day.
+    pub scheduled_start: Option<StartTime>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OvertimeConfiguration {
+    /// Overtime periods are played until a goal is scored.
+    SuddenDeath,
+    /// One sudden death overtime period is played, and if it ends tied, a shootout decides the winner.
+    /// Without an overtime period, the shootout starts right after the regular periods.
+    Shootout,
+    /// The game ends tied.
+    None,
+}
+
+/// Rule settings that replace the regular ones during a period. Settings that are [`None`] are not changed.
+#[derive(Debug, Clone, Default)]
+pub struct PeriodRules {
+    pub offside: Option<OffsideConfiguration>,
+    pub icing: Option<IcingConfiguration>,
+    pub offside_line: Option<OffsideLineConfiguration>,
+    pub twoline_pass: Option<TwoLinePassConfiguration>,
+}
+
+impl PeriodRules {
+    /// Changes the settings in the configuration, and returns the replaced settings.
+    fn apply_to(&self, config: &mut MatchConfiguration) -> PeriodRules {
+        PeriodRules {
+            offside: self
+                .offside
+                .map(|x| std::mem::replace(&mut config.offside, x)),
+            icing: self.icing.map(|x| std::mem::replace(&mut config.icing, x)),
+            offside_line: self
+                .offside_line
+                .map(|x| std::mem::replace(&mut config.offside_line, x)),
+            twoline_pass: self
+                .twoline_pass
+                .map(|x| std::mem::replace(&mut config.twoline_pass, x)),
+        }
+    }
+
+    /// Removes the settings that are set in `other`.
+    fn remove(&mut self, other: &PeriodRules) {
+        if other.offside.is_some() {
+            self.offside = None;
+        }
+        if other.icing.is_some() {
+            self.icing = None;
+        }
+        if other.offside_line.is_some() {
+            self.offside_line = None;
+        }
+        if other.twoline_pass.is_some() {
+            self.twoline_pass = None;
+        }
+    }
 }
 
 impl Default for MatchConfiguration {
@@ -73,12 +192,30 @@ impl Default for MatchConfiguration {
             offside_line: OffsideLineConfiguration::OffensiveBlue,
             twoline_pass: TwoLinePassConfiguration::Off,
             warmup_pucks: 1,
+            game_pucks: 1,
             use_mph: false,
             goal_replay: false,
+            goal_replay_max: 6,
             spawn_point_offset: 2.75,
             spawn_player_altitude: 2.75,
             spawn_puck_altitude: 1.5,
             spawn_keep_stick_position: false,
+            intermission_shootout: false,
+            frozen_puck_time: 0,
+            frozen_puck_speed: 1.0,
+            mvp_vote_time: 0,
+            warmup_auto_teams: false,
+            min_players: 0,
+            forfeit_time: 0,
+            force_team_size_parity: false,
+            force_team_balance: false,
+            period_rules: HashMap::new(),
+            overtime_rules: None,
+            overtime: OvertimeConfiguration::SuddenDeath,
+            time_overtime: 300,
+            overtime_team_size: 0,
+            positions_file: None,
+            scheduled_start: None,
         }
     }
 }
@@ -93,6 +230,66 @@ pub enum MatchEvent {
         time: u32,
         period: u32,
     },
+    /// A player was sent to the penalty box for `duration` seconds.
+    Penalty {
+        team: Team,
+        player: PlayerId,
+        duration: u32,
+        time: u32,
+        period: u32,
+    },
+    /// A penalty ended, either because the time ran out or because the other team scored.
+    PenaltyEnded {
+        team: Team,
+        player: PlayerId,
+        goal_against: bool,
+    },
+    /// The clock ran out in period `period`.
+    PeriodEnded { period: u32 },
+    /// The puck was dropped at `spot`, after the faceoff hooks were called.
+    Faceoff { spot: RinkFaceoffSpot },
+}
+
+impl MatchEvent {
+    /// The sound event clients are sent for this match event, if any.
+    pub fn sound_event(&self) -> Option<SoundEvent> {
+        match self {
+            MatchEvent::Goal { team, .. } => Some(SoundEvent::Goal(*team)),
+            MatchEvent::Penalty { team, .. } => Some(SoundEvent::Penalty(*team)),
+            MatchEvent::PenaltyEnded { .. } => None,
+            MatchEvent::PeriodEnded { .. } => Some(SoundEvent::PeriodEnd),
+            MatchEvent::Faceoff { .. } => None,
+        }
+    }
+
+    /// The announcement players are sent for this match event, if any.
+    pub fn announcement(&self) -> Option<AnnouncementEvent> {
+        match self {
+            MatchEvent::Goal { .. } => Some(AnnouncementEvent::Goal),
+            _ => None,
+        }
+    }
+}
+
+/// Code that runs around each faceoff of a [`Match`], added with [`Match::add_faceoff_hook`].
+///
+/// Hooks let rules like line change locks or faceoff countdowns be added to a match without changing it.
+pub trait FaceoffHook {
+    /// Called before the puck and the players are placed for a faceoff at `spot`.
+    fn before_faceoff(&mut self, _server: ServerMut, _spot: RinkFaceoffSpot) {}
+
+    /// Called after the puck and the players have been placed, and penalized players have been
+    /// sent to the penalty box.
+    fn after_faceoff(&mut self, _server: ServerMut, _spot: RinkFaceoffSpot) {}
+}
+
+/// A player serving a penalty.
+#[derive(Debug, Clone, Copy)]
+pub struct Penalty {
+    pub player: PlayerId,
+    pub team: Team,
+    /// Game time left of the penalty, in hundredths of a second.
+    pub remaining: u32,
 }
 
 pub struct Match {
@@ -100,43 +297,85 @@ pub struct Match {
     pub paused: bool,
     pub(crate) pause_timer: u32,
     is_pause_goal: bool,
-    next_faceoff_spot: RinkFaceoffSpot,
-    icing_status: IcingStatus,
-    offside_status: OffsideStatus,
-    twoline_pass_status: TwoLinePassStatus,
-    pass: Option<Pass>,
-    pub(crate) preferred_positions: HashMap<PlayerId, &'static str>,
+    pub(crate) next_faceoff_spot: RinkFaceoffSpot,
+    /// Icing, offside and pass state of each puck, by puck index.
+    puck_states: HashMap<usize, PuckState>,
+    pub(crate) preferred_positions: PreferredPositions,
 
     pub started_as_goalie: Vec<PlayerId>,
     faceoff_game_step: u32,
+    /// Game step when the puck last left each team's defensive zone, red first.
+    zone_exit_game_step: [u32; 2],
     step_where_period_ended: u32,
     too_late_printed_this_period: bool,
     start_next_replay: Option<(u32, u32, Option<PlayerId>)>,
     puck_touches: HashMap<usize, ArrayDeque<PuckTouch, 16, Wrapping>>,
+    pub(crate) contest: IntermissionContest,
+    replaced_rules: PeriodRules,
+    /// Settings that an admin has changed during the game, which the period rules no longer change.
+    admin_rules: PeriodRules,
+    ice_time_index: HashMap<(PlayerId, Team), usize>,
+    /// True if the warmup clock is stopped because a team has too few players.
+    waiting_for_players: bool,
+    /// True if the warmup clock is stopped until the scheduled start of the game.
+    pub(crate) waiting_for_start: bool,
+    /// Ticks each team has been short of players during the game, red first.
+    short_handed_ticks: [u32; 2],
+    forfeited: Option<Team>,
+    penalties: Vec<Penalty>,
+    /// Events that happened outside of [`Match::after_tick`], returned by the next call to it.
+    pending_events: Vec<MatchEvent>,
+    faceoff_hooks: Vec<Box<dyn FaceoffHook>>,
+    /// Ticks the puck has been pinned against the boards or the net.
+    frozen_puck_ticks: u32,
+    /// Game that is being read to be resumed with /resume.
+    pub(crate) pending_resume: Option<PendingResume>,
 }
 
 impl Match {
     pub fn new(config: MatchConfiguration) -> Self {
+        let preferred_positions = PreferredPositions::new(config.positions_file.clone());
         Self {
             config,
             paused: false,
             pause_timer: 0,
             is_pause_goal: false,
             next_faceoff_spot: RinkFaceoffSpot::Center,
-            icing_status: IcingStatus::No,
-            offside_status: OffsideStatus::Neutral,
-            twoline_pass_status: TwoLinePassStatus::No,
-            pass: None,
-            preferred_positions: HashMap::new(),
+            puck_states: HashMap::new(),
+            preferred_positions,
             started_as_goalie: vec![],
             faceoff_game_step: 0,
+            zone_exit_game_step: [0; 2],
             too_late_printed_this_period: false,
             step_where_period_ended: 0,
             start_next_replay: None,
             puck_touches: Default::default(),
+            contest: Default::default(),
+            replaced_rules: Default::default(),
+            admin_rules: Default::default(),
+            ice_time_index: Default::default(),
+            waiting_for_players: false,
+            waiting_for_start: false,
+            short_handed_ticks: [0; 2],
+            forfeited: None,
+            penalties: vec![],
+            pending_events: vec![],
+            faceoff_hooks: vec![],
+            frozen_puck_ticks: 0,
+            pending_resume: None,
         }
     }
 
+    /// Number of ticks of replay history needed for the longest goal replay and the two seconds after the goal.
+    pub(crate) fn replay_history_length(&self) -> usize {
+        ((self.config.goal_replay_max as usize + 4) * 100).max(1000)
+    }
+
+    /// Adds a hook that is called around each faceoff, after the hooks added before it.
+    pub fn add_faceoff_hook(&mut self, hook: impl FaceoffHook + 'static) {
+        self.faceoff_hooks.push(Box::new(hook));
+    }
+
     pub fn clear_started_goalie(&mut self, player_index: PlayerId) {
         if let Some(x) = self
             .started_as_goalie
@@ -147,11 +386,28 @@ impl Match {
         }
     }
 
-    fn do_faceoff(&mut self, mut server: ServerMut) {
-        let positions = get_faceoff_positions(server.players(), &self.preferred_positions);
+    fn puck_state(&mut self, puck: usize) -> &mut PuckState {
+        self.puck_states.entry(puck).or_default()
+    }
+
+    fn do_faceoff(&mut self, mut server: ServerMut, match_events: &mut Vec<MatchEvent>) {
+        let spot = self.next_faceoff_spot;
+        for hook in self.faceoff_hooks.iter_mut() {
+            hook.before_faceoff(server.rb_mut(), spot);
+        }
+
+        let overtime_positions =
+            self.config.overtime_team_size > 0 && self.is_overtime(server.scoreboard().period);
+        let mut positions = get_faceoff_positions(
+            server.players(),
+            &self.preferred_positions,
+            overtime_positions,
+        );
+        positions.retain(|player_id, _| !self.is_penalized(*player_id));
 
         server.pucks_mut().remove_all_pucks();
         self.puck_touches.clear();
+        self.puck_states.clear();
 
         let next_faceoff_spot = get_faceoff_spot(
             &server.rink(),
@@ -160,12 +416,38 @@ impl Match {
             self.config.spawn_player_altitude,
         );
 
-        let puck_pos =
-            next_faceoff_spot.center_position + &(self.config.spawn_puck_altitude * Vector3::y());
-
-        server
-            .pucks_mut()
-            .spawn_puck(Puck::new(puck_pos, Rotation3::identity()));
+        // Extra pucks are lined up across the rink, centered on the faceoff dot
+        let game_pucks = self.config.game_pucks.max(1);
+        let puck_line_start = -0.4 * ((game_pucks - 1) as f32);
+        for i in 0..game_pucks {
+            let offset = Vector3::new(
+                puck_line_start + 0.8 * (i as f32),
+                self.config.spawn_puck_altitude,
+                0.0,
+            );
+            let puck_pos = next_faceoff_spot.center_position + &offset;
+            let rink = server.rink();
+            let offside_status =
+                if rink.blue_zone_blue_line.side_of_line(&puck_pos, 0.0) == BlueSide {
+                    OffsideStatus::InOffensiveZone(Team::Red)
+                } else if rink.red_zone_blue_line.side_of_line(&puck_pos, 0.0) == RedSide {
+                    OffsideStatus::InOffensiveZone(Team::Blue)
+                } else {
+                    OffsideStatus::Neutral
+                };
+            if let Some(puck) = server
+                .pucks_mut()
+                .spawn_puck(Puck::new(puck_pos, Rotation3::identity()))
+            {
+                self.puck_states.insert(
+                    puck,
+                    PuckState {
+                        offside_status,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
 
         self.started_as_goalie.clear();
         for (player_index, (team, faceoff_position)) in positions {
@@ -185,19 +467,15 @@ impl Match {
             }
         }
 
-        let rink = server.rink();
-        self.icing_status = IcingStatus::No;
-        self.offside_status = if rink.blue_zone_blue_line.side_of_line(&puck_pos, 0.0) == BlueSide {
-            OffsideStatus::InOffensiveZone(Team::Red)
-        } else if rink.red_zone_blue_line.side_of_line(&puck_pos, 0.0) == RedSide {
-            OffsideStatus::InOffensiveZone(Team::Blue)
-        } else {
-            OffsideStatus::Neutral
-        };
-        self.twoline_pass_status = TwoLinePassStatus::No;
-        self.pass = None;
+        self.frozen_puck_ticks = 0;
 
         self.faceoff_game_step = server.replay().game_step();
+        self.enforce_penalty_box(server.rb_mut());
+
+        for hook in self.faceoff_hooks.iter_mut() {
+            hook.after_faceoff(server.rb_mut(), spot);
+        }
+        match_events.push(MatchEvent::Faceoff { spot });
     }
 
     pub(crate) fn update_game_over(&mut self, mut server: ServerMut) {
@@ -208,7 +486,10 @@ impl Match {
         let red_score = values.red_score;
         let blue_score = values.blue_score;
         let old_game_over = values.game_over;
-        values.game_over = if values.period > self.config.periods && red_score != blue_score {
+        values.game_over = if self.forfeited.is_some()
+            || (values.period > self.config.periods
+                && (red_score != blue_score || self.config.overtime == OvertimeConfiguration::None))
+        {
             true
         } else if self.config.mercy > 0
             && (red_score.saturating_sub(blue_score) >= self.config.mercy
@@ -243,6 +524,7 @@ impl Match {
         };
 
         self.next_faceoff_spot = RinkFaceoffSpot::Center;
+        self.release_penalty_on_goal(server.rb_mut(), team);
 
         let (
             goal_scorer_index,
@@ -302,17 +584,10 @@ impl Match {
         server
             .players_mut()
             .add_goal_message(team, goal_scorer_index, assist_index);
-
-        fn convert(puck_speed: f32, use_mph: bool) -> (f32, &'static str) {
-            if use_mph {
-                (puck_speed * 100f32 * 2.23693, "mph")
-            } else {
-                (puck_speed * 100f32 * 3.6, "km/h")
-            }
-        }
+        server.add_annotation(AnnotationKind::Goal, Some(team), goal_scorer_index, "Goal");
 
         let (puck_speed_across_line_converted, puck_speed_unit) =
-            convert(puck_speed_across_line, self.config.use_mph);
+            convert_speed(puck_speed_across_line, self.config.use_mph);
 
         let str1 = format!(
             "Goal scored, {:.1} {} across line",
@@ -321,7 +596,7 @@ impl Match {
 
         let str2 = if let Some(puck_speed_from_stick) = puck_speed_from_stick {
             let (puck_speed_converted, puck_speed_unit) =
-                convert(puck_speed_from_stick, self.config.use_mph);
+                convert_speed(puck_speed_from_stick, self.config.use_mph);
             format!(
                 ", {:.1} {} from stick",
                 puck_speed_converted, puck_speed_unit
@@ -352,11 +627,14 @@ impl Match {
 
         if self.config.goal_replay {
             let force_view = goal_scorer_index.or(last_touch);
-            self.start_next_replay = Some((
-                self.faceoff_game_step.max(gamestep - 600),
-                gamestep + 200,
-                force_view,
-            ));
+            // Start at the faceoff or shortly before the scoring team left its own zone, whichever is later
+            let zone_exit = self.zone_exit_game_step[team.get_num() as usize]
+                .saturating_sub(GOAL_REPLAY_LEAD_IN);
+            let start = self
+                .faceoff_game_step
+                .max(zone_exit)
+                .max(gamestep.saturating_sub(self.config.goal_replay_max * 100));
+            self.start_next_replay = Some((start, gamestep + 200, force_view));
 
             self.pause_timer = self.pause_timer.saturating_sub(800).max(400);
         }
@@ -407,7 +685,7 @@ impl Match {
                     } else {
                         RinkSide::HigherHalfZ
                     };
-                    self.pass = Some(Pass {
+                    self.puck_state(puck_index).pass = Some(Pass {
                         team: touching_team,
                         side,
                         from: None,
@@ -416,32 +694,49 @@ impl Match {
 
                     let other_team = touching_team.get_other_team();
 
-                    if let OffsideStatus::Warning(team, side, position, i) = self.offside_status {
+                    if let OffsideStatus::Warning(team, side, position, i) =
+                        self.puck_state(puck_index).offside_status
+                    {
                         if team == touching_team {
                             let self_touch = player_id == i;
 
-                            self.call_offside(server, touching_team, side, position, self_touch);
+                            self.call_offside(
+                                server,
+                                puck_index,
+                                touching_team,
+                                side,
+                                position,
+                                self_touch,
+                            );
                             return;
                         }
                     }
                     if let TwoLinePassStatus::Warning(team, side, position, ref i) =
-                        self.twoline_pass_status
+                        self.puck_state(puck_index).twoline_pass_status
                     {
                         if team == touching_team && i.contains(&player_id) {
-                            self.call_twoline_pass(server, touching_team, side, position);
+                            self.call_twoline_pass(
+                                server,
+                                puck_index,
+                                touching_team,
+                                side,
+                                position,
+                            );
                             return;
                         } else {
-                            self.twoline_pass_status = TwoLinePassStatus::No;
+                            self.puck_state(puck_index).twoline_pass_status = TwoLinePassStatus::No;
                             server
                                 .players_mut()
                                 .add_server_chat_message("Two-line pass waved off");
                         }
                     }
-                    if let IcingStatus::Warning(team, side) = self.icing_status {
+                    if let IcingStatus::Warning(team, side) =
+                        self.puck_state(puck_index).icing_status
+                    {
                         if touching_team != team && !self.started_as_goalie.contains(&player_id) {
-                            self.call_icing(server, other_team, side);
+                            self.call_icing(server, puck_index, other_team, side);
                         } else {
-                            self.icing_status = IcingStatus::No;
+                            self.puck_state(puck_index).icing_status = IcingStatus::No;
                             server
                                 .players_mut()
                                 .add_server_chat_message("Icing waved off");
@@ -460,9 +755,9 @@ impl Match {
         puck: usize,
     ) {
         let team = net_team.get_other_team();
-        match self.offside_status {
+        match self.puck_state(puck).offside_status {
             OffsideStatus::Warning(offside_team, side, position, _) if offside_team == team => {
-                self.call_offside(server, team, side, position, false);
+                self.call_offside(server, puck, team, side, position, false);
             }
             OffsideStatus::Offside(_) => {}
             _ => {
@@ -471,25 +766,30 @@ impl Match {
         }
     }
 
-    fn handle_puck_passed_goal_line(&mut self, mut server: ServerMut, line_team: Team) {
+    fn handle_puck_passed_goal_line(
+        &mut self,
+        mut server: ServerMut,
+        line_team: Team,
+        puck: usize,
+    ) {
         if let Some(Pass {
             team: icing_team,
             side,
             from: Some(transition),
             ..
-        }) = self.pass
+        }) = self.puck_state(puck).pass
         {
             let team = line_team.get_other_team();
             if team == icing_team && transition <= PassLocation::ReachedCenter {
                 match self.config.icing {
                     IcingConfiguration::Touch => {
-                        self.icing_status = IcingStatus::Warning(team, side);
+                        self.puck_state(puck).icing_status = IcingStatus::Warning(team, side);
                         server
                             .players_mut()
                             .add_server_chat_message("Icing warning");
                     }
                     IcingConfiguration::NoTouch => {
-                        self.call_icing(server, team, side);
+                        self.call_icing(server, puck, team, side);
                     }
                     IcingConfiguration::Off => {}
                 }
@@ -497,8 +797,8 @@ impl Match {
         }
     }
 
-    fn puck_into_offside_zone(&mut self, mut server: ServerMut, team: Team) {
-        if self.offside_status == OffsideStatus::InOffensiveZone(team) {
+    fn puck_into_offside_zone(&mut self, mut server: ServerMut, team: Team, puck: usize) {
+        if self.puck_state(puck).offside_status == OffsideStatus::InOffensiveZone(team) {
             return;
         }
         if let Some(Pass {
@@ -506,77 +806,98 @@ impl Match {
             side,
             from: transition,
             player,
-        }) = self.pass
+        }) = self.puck_state(puck).pass
         {
             if team == pass_team && has_players_in_offensive_zone(server.rb(), team, Some(player)) {
                 match self.config.offside {
                     OffsideConfiguration::Delayed => {
-                        self.offside_status =
+                        self.puck_state(puck).offside_status =
                             OffsideStatus::Warning(team, side, transition, player);
                         server
                             .players_mut()
                             .add_server_chat_message("Offside warning");
                     }
                     OffsideConfiguration::Immediate => {
-                        self.call_offside(server, team, side, transition, false);
+                        self.call_offside(server, puck, team, side, transition, false);
                     }
                     OffsideConfiguration::Off => {
-                        self.offside_status = OffsideStatus::InOffensiveZone(team);
+                        self.puck_state(puck).offside_status = OffsideStatus::InOffensiveZone(team);
                     }
                 }
             } else {
-                self.offside_status = OffsideStatus::InOffensiveZone(team);
+                self.puck_state(puck).offside_status = OffsideStatus::InOffensiveZone(team);
             }
         } else {
-            self.offside_status = OffsideStatus::InOffensiveZone(team);
+            self.puck_state(puck).offside_status = OffsideStatus::InOffensiveZone(team);
         }
     }
 
-    fn handle_puck_entered_offensive_half(&mut self, mut server: ServerMut, team: Team) {
-        if !matches!(&self.offside_status, OffsideStatus::Offside(_))
-            && self.config.offside_line == OffsideLineConfiguration::Center
+    fn handle_puck_entered_offensive_half(
+        &mut self,
+        mut server: ServerMut,
+        team: Team,
+        puck: usize,
+    ) {
+        if !matches!(
+            &self.puck_state(puck).offside_status,
+            OffsideStatus::Offside(_)
+        ) && self.config.offside_line == OffsideLineConfiguration::Center
         {
-            self.puck_into_offside_zone(server.rb_mut(), team);
+            self.puck_into_offside_zone(server.rb_mut(), team, puck);
         }
-        if let OffsideStatus::Warning(warning_team, _, _, _) = self.offside_status {
+        if let OffsideStatus::Warning(warning_team, _, _, _) = self.puck_state(puck).offside_status
+        {
             if warning_team != team {
                 server
                     .players_mut()
                     .add_server_chat_message("Offside waved off");
             }
         }
-        if let Some(Pass {
-            team: pass_team,
-            side,
-            from: Some(from),
-            player: pass_player,
-        }) = self.pass
+        if let Some(
+            pass @ Pass {
+                team: pass_team,
+                from: Some(from),
+                ..
+            },
+        ) = self.puck_state(puck).pass
         {
-            if self.twoline_pass_status == TwoLinePassStatus::No && pass_team == team {
+            if self.puck_state(puck).twoline_pass_status == TwoLinePassStatus::No
+                && pass_team == team
+            {
                 let is_regular_twoline_pass_active = self.config.twoline_pass
                     == TwoLinePassConfiguration::Double
                     || self.config.twoline_pass == TwoLinePassConfiguration::On;
                 if from <= PassLocation::ReachedOwnBlue && is_regular_twoline_pass_active {
-                    self.check_twoline_pass(server, team, side, from, pass_player, false);
+                    self.check_twoline_pass(server, puck, pass, from, false);
                 }
             }
         }
     }
 
-    fn handle_puck_entered_offensive_zone(&mut self, mut server: ServerMut, team: Team) {
-        if !matches!(&self.offside_status, OffsideStatus::Offside(_))
-            && self.config.offside_line == OffsideLineConfiguration::OffensiveBlue
+    fn handle_puck_entered_offensive_zone(
+        &mut self,
+        mut server: ServerMut,
+        team: Team,
+        puck: usize,
+    ) {
+        if !matches!(
+            &self.puck_state(puck).offside_status,
+            OffsideStatus::Offside(_)
+        ) && self.config.offside_line == OffsideLineConfiguration::OffensiveBlue
         {
-            self.puck_into_offside_zone(server.rb_mut(), team);
+            self.puck_into_offside_zone(server.rb_mut(), team, puck);
         }
-        if let Some(Pass {
-            team: pass_team,
-            side,
-            from: Some(from),
-            player: pass_player,
-        }) = self.pass
+        if let Some(
+            pass @ Pass {
+                team: pass_team,
+                from: Some(from),
+                ..
+            },
+        ) = self.puck_state(puck).pass
         {
-            if self.twoline_pass_status == TwoLinePassStatus::No && pass_team == team {
+            if self.puck_state(puck).twoline_pass_status == TwoLinePassStatus::No
+                && pass_team == team
+            {
                 let is_forward_twoline_pass_active = self.config.twoline_pass
                     == TwoLinePassConfiguration::Double
                     || self.config.twoline_pass == TwoLinePassConfiguration::Forward;
@@ -585,7 +906,7 @@ impl Match {
                 if (from <= PassLocation::ReachedCenter && is_forward_twoline_pass_active)
                     || from <= PassLocation::ReachedOwnBlue && is_threeline_pass_active
                 {
-                    self.check_twoline_pass(server, team, side, from, pass_player, true);
+                    self.check_twoline_pass(server, puck, pass, from, true);
                 }
             }
         }
@@ -594,12 +915,17 @@ impl Match {
     fn check_twoline_pass(
         &mut self,
         mut server: ServerMut,
-        team: Team,
-        side: RinkSide,
+        puck: usize,
+        pass: Pass,
         from: PassLocation,
-        pass_player: PlayerId,
         is_offensive_line: bool,
     ) {
+        let Pass {
+            team,
+            side,
+            player: pass_player,
+            ..
+        } = pass;
         let line = if is_offensive_line {
             match team {
                 Team::Red => &server.rink().blue_zone_blue_line,
@@ -608,7 +934,7 @@ impl Match {
         } else {
             &server.rink().center_line
         };
-        let mut players_past_line = vec![];
+        let mut players_past_line = SmallVec::new();
         for player in server.players().iter() {
             if player.id == pass_player {
                 continue;
@@ -618,7 +944,7 @@ impl Match {
             }
         }
         if !players_past_line.is_empty() {
-            self.twoline_pass_status =
+            self.puck_state(puck).twoline_pass_status =
                 TwoLinePassStatus::Warning(team, side, from, players_past_line);
             server
                 .players_mut()
@@ -626,33 +952,40 @@ impl Match {
         }
     }
 
-    fn handle_puck_passed_defensive_line(&mut self, mut server: ServerMut, team: Team) {
-        if !matches!(&self.offside_status, OffsideStatus::Offside(_))
+    fn handle_puck_passed_defensive_line(
+        &mut self,
+        mut server: ServerMut,
+        team: Team,
+        puck: usize,
+    ) {
+        let state = self.puck_states.entry(puck).or_default();
+        if !matches!(&state.offside_status, OffsideStatus::Offside(_))
             && self.config.offside_line == OffsideLineConfiguration::OffensiveBlue
         {
-            if let OffsideStatus::Warning(t, _, _, _) = self.offside_status {
+            if let OffsideStatus::Warning(t, _, _, _) = state.offside_status {
                 if team.get_other_team() == t {
                     server
                         .players_mut()
                         .add_server_chat_message("Offside waved off");
                 }
             }
-            self.offside_status = OffsideStatus::Neutral;
+            state.offside_status = OffsideStatus::Neutral;
         }
     }
 
-    fn update_pass(&mut self, team: Team, p: PassLocation) {
-        if let Some(pass) = &mut self.pass {
+    fn update_pass(&mut self, team: Team, p: PassLocation, puck: usize) {
+        if let Some(pass) = &mut self.puck_state(puck).pass {
             if pass.team == team && pass.from.is_none() {
                 pass.from = Some(p);
             }
         }
     }
 
-    fn check_wave_off_twoline(&mut self, mut server: ServerMut, team: Team) {
-        if let TwoLinePassStatus::Warning(warning_team, _, _, _) = self.twoline_pass_status {
+    fn check_wave_off_twoline(&mut self, mut server: ServerMut, team: Team, puck: usize) {
+        let state = self.puck_state(puck);
+        if let TwoLinePassStatus::Warning(warning_team, _, _, _) = state.twoline_pass_status {
             if team != warning_team {
-                self.twoline_pass_status = TwoLinePassStatus::No;
+                state.twoline_pass_status = TwoLinePassStatus::No;
                 server
                     .players_mut()
                     .add_server_chat_message("Two-line pass waved off");
@@ -674,31 +1007,32 @@ impl Match {
                 PhysicsEvent::PuckTouch { player, puck, .. } => {
                     self.handle_puck_touch(server.rb_mut(), player, puck);
                 }
-                PhysicsEvent::PuckReachedDefensiveLine { team, puck: _ } => {
-                    self.check_wave_off_twoline(server.rb_mut(), team);
-                    self.update_pass(team, PassLocation::ReachedOwnBlue);
+                PhysicsEvent::PuckReachedDefensiveLine { team, puck } => {
+                    self.check_wave_off_twoline(server.rb_mut(), team, puck);
+                    self.update_pass(team, PassLocation::ReachedOwnBlue, puck);
                 }
-                PhysicsEvent::PuckPassedDefensiveLine { team, puck: _ } => {
-                    self.update_pass(team, PassLocation::PassedOwnBlue);
-                    self.handle_puck_passed_defensive_line(server.rb_mut(), team);
+                PhysicsEvent::PuckPassedDefensiveLine { team, puck } => {
+                    self.zone_exit_game_step[team.get_num() as usize] = server.replay().game_step();
+                    self.update_pass(team, PassLocation::PassedOwnBlue, puck);
+                    self.handle_puck_passed_defensive_line(server.rb_mut(), team, puck);
                 }
-                PhysicsEvent::PuckReachedCenterLine { team, puck: _ } => {
-                    self.check_wave_off_twoline(server.rb_mut(), team);
-                    self.update_pass(team, PassLocation::ReachedCenter);
+                PhysicsEvent::PuckReachedCenterLine { team, puck } => {
+                    self.check_wave_off_twoline(server.rb_mut(), team, puck);
+                    self.update_pass(team, PassLocation::ReachedCenter, puck);
                 }
-                PhysicsEvent::PuckPassedCenterLine { team, puck: _ } => {
-                    self.update_pass(team, PassLocation::PassedCenter);
-                    self.handle_puck_entered_offensive_half(server.rb_mut(), team);
+                PhysicsEvent::PuckPassedCenterLine { team, puck } => {
+                    self.update_pass(team, PassLocation::PassedCenter, puck);
+                    self.handle_puck_entered_offensive_half(server.rb_mut(), team, puck);
                 }
-                PhysicsEvent::PuckReachedOffensiveZone { team, puck: _ } => {
-                    self.update_pass(team, PassLocation::ReachedOffensive);
+                PhysicsEvent::PuckReachedOffensiveZone { team, puck } => {
+                    self.update_pass(team, PassLocation::ReachedOffensive, puck);
                 }
-                PhysicsEvent::PuckEnteredOffensiveZone { team, puck: _ } => {
-                    self.update_pass(team, PassLocation::PassedOffensive);
-                    self.handle_puck_entered_offensive_zone(server.rb_mut(), team);
+                PhysicsEvent::PuckEnteredOffensiveZone { team, puck } => {
+                    self.update_pass(team, PassLocation::PassedOffensive, puck);
+                    self.handle_puck_entered_offensive_zone(server.rb_mut(), team, puck);
                 }
-                PhysicsEvent::PuckPassedGoalLine { team, puck: _ } => {
-                    self.handle_puck_passed_goal_line(server.rb_mut(), team);
+                PhysicsEvent::PuckPassedGoalLine { team, puck } => {
+                    self.handle_puck_passed_goal_line(server.rb_mut(), team, puck);
                 }
                 _ => {}
             }
@@ -714,6 +1048,7 @@ impl Match {
     fn call_offside(
         &mut self,
         mut server: ServerMut,
+        puck: usize,
         team: Team,
         side: RinkSide,
         position: Option<PassLocation>,
@@ -740,13 +1075,15 @@ impl Match {
 
         self.next_faceoff_spot = faceoff_spot;
         self.pause_timer = time_break;
-        self.offside_status = OffsideStatus::Offside(team);
+        self.puck_state(puck).offside_status = OffsideStatus::Offside(team);
         server.players_mut().add_server_chat_message("Offside");
+        server.add_annotation(AnnotationKind::Stoppage, Some(team), None, "Offside");
     }
 
     fn call_twoline_pass(
         &mut self,
         mut server: ServerMut,
+        puck: usize,
         team: Team,
         side: RinkSide,
         position: PassLocation,
@@ -763,19 +1100,58 @@ impl Match {
 
         self.next_faceoff_spot = faceoff_spot;
         self.pause_timer = time_break;
-        self.twoline_pass_status = TwoLinePassStatus::Offside(team);
+        self.puck_state(puck).twoline_pass_status = TwoLinePassStatus::Offside(team);
         server
             .players_mut()
             .add_server_chat_message("Two-line pass");
+        server.add_annotation(AnnotationKind::Stoppage, Some(team), None, "Two-line pass");
     }
 
-    fn call_icing(&mut self, mut server: ServerMut, team: Team, side: RinkSide) {
+    /// Stops play if a puck has been pinned against the boards or the net for too long.
+    fn check_frozen_puck(&mut self, mut server: ServerMut) {
+        if self.config.frozen_puck_time == 0 || self.pause_timer > 0 {
+            self.frozen_puck_ticks = 0;
+            return;
+        }
+        let max_speed = self.config.frozen_puck_speed / 100.0;
+        let rink = server.rink();
+        let frozen = server.pucks().iter().flatten().find_map(|puck| {
+            let pos = puck.body.pos;
+            let pinned = puck.body.linear_velocity.norm() < max_speed
+                && (is_near_boards(rink, &pos) || is_near_net(rink, &pos))
+                && server.players().iter().any(|player| {
+                    player.skater().is_some_and(|(_, skater)| {
+                        (skater.body.pos - pos).norm() < PINNING_DISTANCE
+                            || (skater.stick_pos - pos).norm() < PINNING_DISTANCE
+                    })
+                });
+            pinned.then_some(pos)
+        });
+        let Some(pos) = frozen else {
+            self.frozen_puck_ticks = 0;
+            return;
+        };
+        self.frozen_puck_ticks += 1;
+        if self.frozen_puck_ticks < self.config.frozen_puck_time * 100 {
+            return;
+        }
+
+        self.frozen_puck_ticks = 0;
+        self.next_faceoff_spot = closest_faceoff_spot(rink, &pos);
+        self.pause_timer = self.config.time_break * 100;
+        server.players_mut().add_server_chat_message("Frozen puck");
+        server.add_annotation(AnnotationKind::Stoppage, None, None, "Frozen puck");
+    }
+
+    fn call_icing(&mut self, mut server: ServerMut, puck: usize, team: Team, side: RinkSide) {
         let time_break = self.config.time_break * 100;
 
         self.next_faceoff_spot = RinkFaceoffSpot::DefensiveZone(team, side);
         self.pause_timer = time_break;
-        self.icing_status = IcingStatus::Icing(team);
+        self.puck_state(puck).icing_status = IcingStatus::Icing(team);
         server.players_mut().add_server_chat_message("Icing");
+        server.players_mut().announce(AnnouncementEvent::Icing);
+        server.add_annotation(AnnotationKind::Stoppage, Some(team), None, "Icing");
     }
 
     pub fn after_tick(
@@ -783,10 +1159,15 @@ impl Match {
         mut server: ServerMut,
         events: &[PhysicsEvent],
     ) -> Vec<MatchEvent> {
-        let mut match_events = vec![];
+        let mut match_events = std::mem::take(&mut self.pending_events);
+        self.enforce_penalty_box(server.rb_mut());
         let values = server.scoreboard();
         if values.time == 0 && values.period > 1 {
             self.handle_events_end_of_period(server.rb_mut(), events);
+            if self.config.intermission_shootout && !server.scoreboard().game_over && !self.paused {
+                self.contest
+                    .update(server.rb_mut(), events, self.pause_timer);
+            }
         } else if self.pause_timer > 0
             || values.time == 0
             || values.game_over
@@ -795,31 +1176,45 @@ impl Match {
         {
             // Nothing
         } else {
+            self.update_ice_time(server.rb_mut());
+            self.update_penalties(server.rb_mut(), &mut match_events);
             self.handle_events(server.rb_mut(), events, &mut match_events);
+            self.check_frozen_puck(server.rb_mut());
 
-            if let OffsideStatus::Warning(team, _, _, _) = self.offside_status {
-                if !has_players_in_offensive_zone(server.rb(), team, None) {
-                    self.offside_status = OffsideStatus::InOffensiveZone(team);
-                    server
-                        .players_mut()
-                        .add_server_chat_message("Offside waved off");
+            for state in self.puck_states.values_mut() {
+                if let OffsideStatus::Warning(team, _, _, _) = state.offside_status {
+                    if !has_players_in_offensive_zone(server.rb(), team, None) {
+                        state.offside_status = OffsideStatus::InOffensiveZone(team);
+                        server
+                            .players_mut()
+                            .add_server_chat_message("Offside waved off");
+                    }
                 }
             }
 
-            let rules_state = if matches!(self.offside_status, OffsideStatus::Offside(_))
-                || matches!(self.twoline_pass_status, TwoLinePassStatus::Offside(_))
-            {
+            // The scoreboard shows the state of the puck that is closest to a stoppage
+            let states = self.puck_states.values();
+            let rules_state = if states.clone().any(|state| {
+                matches!(state.offside_status, OffsideStatus::Offside(_))
+                    || matches!(state.twoline_pass_status, TwoLinePassStatus::Offside(_))
+            }) {
                 RulesState::Offside
-            } else if matches!(self.icing_status, IcingStatus::Icing(_)) {
+            } else if states
+                .clone()
+                .any(|state| matches!(state.icing_status, IcingStatus::Icing(_)))
+            {
                 RulesState::Icing
             } else {
-                let icing_warning = matches!(self.icing_status, IcingStatus::Warning(_, _));
-                let offside_warning =
-                    matches!(self.offside_status, OffsideStatus::Warning(_, _, _, _))
+                let icing_warning = states
+                    .clone()
+                    .any(|state| matches!(state.icing_status, IcingStatus::Warning(_, _)));
+                let offside_warning = states.clone().any(|state| {
+                    matches!(state.offside_status, OffsideStatus::Warning(_, _, _, _))
                         || matches!(
-                            self.twoline_pass_status,
+                            state.twoline_pass_status,
                             TwoLinePassStatus::Warning(_, _, _, _)
-                        );
+                        )
+                });
                 RulesState::Regular {
                     offside_warning,
                     icing_warning,
@@ -829,7 +1224,7 @@ impl Match {
             server.scoreboard_mut().rules_state = rules_state;
         }
 
-        self.update_clock(server.rb_mut());
+        self.update_clock(server.rb_mut(), &mut match_events);
 
         if let Some((start_replay, end_replay, force_view)) = self.start_next_replay {
             if end_replay <= server.replay().game_step() {
@@ -840,13 +1235,44 @@ impl Match {
                 self.start_next_replay = None;
             }
         }
+        for event in match_events.iter() {
+            if let Some(sound_event) = event.sound_event() {
+                server.players_mut().play_sound_event(sound_event);
+            }
+            if let Some(announcement) = event.announcement() {
+                server.players_mut().announce(announcement);
+            }
+        }
         match_events
     }
 
-    fn update_clock(&mut self, mut server: ServerMut) {
-        let period_length = self.config.time_period * 100;
+    /// Returns true if the period is an overtime period, and not the shootout after it.
+    pub(crate) fn is_overtime(&self, period: u32) -> bool {
+        match self.config.overtime {
+            OvertimeConfiguration::Shootout => {
+                self.config.time_overtime > 0 && period == self.config.periods + 1
+            }
+            _ => period > self.config.periods,
+        }
+    }
+
+    /// Returns true if the regular periods and the overtime are over, and a shootout has to decide the game.
+    pub(crate) fn is_shootout_due(&self, values: &ScoreboardValues) -> bool {
+        self.config.overtime == OvertimeConfiguration::Shootout
+            && values.period > self.config.periods
+            && !self.is_overtime(values.period)
+            && values.time == 0
+            && !values.game_over
+    }
+
+    fn update_clock(&mut self, mut server: ServerMut, match_events: &mut Vec<MatchEvent>) {
         let intermission_time = self.config.time_intermission * 100;
         let values = server.scoreboard_mut();
+        let period_length = if values.period > self.config.periods {
+            self.config.time_overtime * 100
+        } else {
+            self.config.time_period * 100
+        };
 
         if !self.paused {
             if self.pause_timer > 0 {
@@ -856,16 +1282,26 @@ impl Match {
                     if values.game_over {
                         server.new_game(self.get_initial_game_values());
                     } else {
-                        if values.time == 0 {
+                        let period_start = values.time == 0;
+                        if period_start {
                             values.time = period_length;
                         }
 
-                        self.do_faceoff(server.rb_mut());
+                        self.do_faceoff(server.rb_mut(), match_events);
+                        if period_start {
+                            server
+                                .players_mut()
+                                .announce(AnnouncementEvent::PeriodStart);
+                        }
                     }
                 }
-            } else {
+            } else if !(values.period == 0 && (self.waiting_for_players || self.waiting_for_start))
+            {
                 values.time = values.time.saturating_sub(1);
                 if values.time == 0 {
+                    match_events.push(MatchEvent::PeriodEnded {
+                        period: values.period,
+                    });
                     values.period += 1;
                     self.pause_timer = intermission_time;
                     self.is_pause_goal = false;
@@ -873,6 +1309,13 @@ impl Match {
                     self.too_late_printed_this_period = false;
                     self.next_faceoff_spot = RinkFaceoffSpot::Center;
                     self.update_game_over(server.rb_mut());
+                    self.apply_period_rules(server.rb_mut());
+                    if self.config.intermission_shootout
+                        && !server.scoreboard().game_over
+                        && !self.is_shootout_due(server.scoreboard())
+                    {
+                        self.contest.begin(server.rb_mut(), intermission_time);
+                    }
                 }
             }
         }
@@ -883,6 +1326,308 @@ impl Match {
         };
     }
 
+    /// Checks the team sizes against the minimum number of players. Should be called once each tick with
+    /// the number of players on the ice in each team.
+    ///
+    /// The warmup clock is stopped while a team has too few players, and a team that has too few players
+    /// for too long during the game forfeits. The forfeit countdown only runs while the game clock runs.
+    pub fn update_team_sizes(
+        &mut self,
+        mut server: ServerMut,
+        red_player_count: usize,
+        blue_player_count: usize,
+    ) {
+        let min_players = self.config.min_players;
+        if min_players == 0 {
+            self.waiting_for_players = false;
+            return;
+        }
+        let values = server.scoreboard();
+        if values.period == 0 {
+            let waiting = red_player_count < min_players || blue_player_count < min_players;
+            if waiting && !self.waiting_for_players {
+                let msg = format!("Waiting for {} players in each team", min_players);
+                server.players_mut().add_server_chat_message(msg);
+            }
+            self.waiting_for_players = waiting;
+            return;
+        }
+        if values.game_over || self.config.forfeit_time == 0 {
+            return;
+        }
+        let forfeit_ticks = self.config.forfeit_time * 100;
+        let clock_running = !self.paused && self.pause_timer == 0 && values.time > 0;
+        for (team, player_count) in [
+            (Team::Red, red_player_count),
+            (Team::Blue, blue_player_count),
+        ] {
+            let ticks = &mut self.short_handed_ticks[team.get_num() as usize];
+            if player_count >= min_players {
+                if *ticks > 0 {
+                    *ticks = 0;
+                    let msg = format!(
+                        "{} has enough players again",
+                        server.config().team_names.name(team)
+                    );
+                    server.players_mut().add_server_chat_message(msg);
+                }
+                continue;
+            }
+            if !clock_running {
+                continue;
+            }
+            if *ticks == 0 {
+                let msg = format!(
+                    "{} has too few players and will forfeit in {} seconds",
+                    server.config().team_names.name(team),
+                    self.config.forfeit_time
+                );
+                server.players_mut().add_server_chat_message(msg);
+                server.players_mut().play_sound_event(SoundEvent::Warning);
+            }
+            *ticks += 1;
+            if *ticks >= forfeit_ticks {
+                self.forfeit(server.rb_mut(), team);
+                return;
+            }
+        }
+    }
+
+    fn forfeit(&mut self, mut server: ServerMut, team: Team) {
+        let team_name = server.config().team_names.name(team).to_owned();
+        info!("{} forfeited the game", team_name);
+        let msg = format!("{} forfeits the game", team_name);
+        server.players_mut().add_server_chat_message(msg);
+        server.add_annotation(AnnotationKind::Stoppage, Some(team), None, "Forfeit");
+        server.game_summary_mut().forfeit = Some(team);
+        self.forfeited = Some(team);
+        self.paused = false;
+        self.update_game_over(server);
+    }
+
+    /// Sends a player on the ice to the penalty box for `duration` seconds of game time.
+    ///
+    /// The player stays in the box and their team plays short-handed until the time runs out,
+    /// or until the other team scores. Returns false if the player is not on the ice, or if the game
+    /// is not in progress.
+    pub fn assess_penalty(
+        &mut self,
+        mut server: ServerMut,
+        player_id: PlayerId,
+        duration: u32,
+    ) -> bool {
+        let values = server.scoreboard();
+        if values.period == 0 || values.game_over {
+            return false;
+        }
+        let Some((team, name)) = server
+            .players()
+            .get(player_id)
+            .and_then(|player| Some((player.team()?, player.name())))
+        else {
+            return false;
+        };
+        let remaining = duration * 100;
+        match self.penalties.iter_mut().find(|x| x.player == player_id) {
+            // A player who is already in the box serves the penalties one after the other
+            Some(penalty) => penalty.remaining += remaining,
+            None => self.penalties.push(Penalty {
+                player: player_id,
+                team,
+                remaining,
+            }),
+        }
+        self.enforce_penalty_box(server.rb_mut());
+
+        let msg = format!(
+            "{} ({}) gets a {} second penalty",
+            name,
+            server.config().team_names.name(team),
+            duration
+        );
+        server.players_mut().add_server_chat_message(msg);
+        let reason = format!("{} seconds", duration);
+        server.add_annotation(AnnotationKind::Penalty, Some(team), Some(player_id), reason);
+        let values = server.scoreboard();
+        self.pending_events.push(MatchEvent::Penalty {
+            team,
+            player: player_id,
+            duration,
+            time: values.time,
+            period: values.period,
+        });
+        true
+    }
+
+    /// The players serving penalties, in the order the penalties were given.
+    pub fn penalties(&self) -> &[Penalty] {
+        &self.penalties
+    }
+
+    /// Returns the puck that the player was the last to touch, if any.
+    pub(crate) fn last_touched_puck(&self, player_id: PlayerId) -> Option<usize> {
+        self.puck_touches.iter().find_map(|(puck_index, touches)| {
+            let touch = touches.front()?;
+            (touch.player_id == player_id).then_some(*puck_index)
+        })
+    }
+
+    pub fn is_penalized(&self, player_id: PlayerId) -> bool {
+        self.penalties.iter().any(|x| x.player == player_id)
+    }
+
+    /// Game time left of the shortest penalty of a team, in hundredths of a second.
+    pub fn penalty_clock(&self, team: Team) -> Option<u32> {
+        self.penalties
+            .iter()
+            .filter(|x| x.team == team)
+            .map(|x| x.remaining)
+            .min()
+    }
+
+    /// Counts down the penalties while the game clock is running, and releases players whose time is up.
+    fn update_penalties(&mut self, mut server: ServerMut, match_events: &mut Vec<MatchEvent>) {
+        for penalty in self.penalties.iter_mut() {
+            penalty.remaining = penalty.remaining.saturating_sub(1);
+        }
+        while let Some(i) = self.penalties.iter().position(|x| x.remaining == 0) {
+            let penalty = self.penalties.remove(i);
+            release_penalty(server.rb_mut(), penalty, false, match_events);
+        }
+    }
+
+    /// Releases the penalty that ends first on the team that was scored on, if the scoring team had more
+    /// players on the ice.
+    fn release_penalty_on_goal(&mut self, server: ServerMut, scoring_team: Team) {
+        let penalty_count = |team| self.penalties.iter().filter(|x| x.team == team).count();
+        let team = scoring_team.get_other_team();
+        if penalty_count(team) <= penalty_count(scoring_team) {
+            return;
+        }
+        let first = self
+            .penalties
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.team == team)
+            .min_by_key(|(_, x)| x.remaining)
+            .map(|(i, _)| i);
+        if let Some(i) = first {
+            let penalty = self.penalties.remove(i);
+            release_penalty(server, penalty, true, &mut self.pending_events);
+        }
+    }
+
+    /// Puts penalized players that have left the penalty box back into it.
+    fn enforce_penalty_box(&mut self, mut server: ServerMut) {
+        for penalty in self.penalties.iter() {
+            // Players that went to the spectators are put back in the box if they return
+            let Some((team, skater_pos)) =
+                server.players().get(penalty.player).and_then(|player| {
+                    player
+                        .skater()
+                        .map(|(team, skater)| (team, skater.body.pos))
+                })
+            else {
+                continue;
+            };
+            let (pos, rot) =
+                get_penalty_box(server.rink(), team, self.config.spawn_player_altitude);
+            let diff = skater_pos - pos;
+            if diff.x.hypot(diff.z) > PENALTY_BOX_RADIUS {
+                server.players_mut().spawn_skater(
+                    penalty.player,
+                    team,
+                    pos,
+                    rot,
+                    self.config.spawn_keep_stick_position,
+                );
+            }
+        }
+    }
+
+    /// Adds one tick of ice time to every player on the ice, in the game summary.
+    fn update_ice_time(&mut self, mut server: ServerMut) {
+        let period = server.scoreboard().period as usize;
+        let on_ice: SmallVec<[_; 32]> = server
+            .players()
+            .iter()
+            .filter_map(|player| player.team().map(|team| (player.id, team)))
+            .collect();
+        for (player_id, team) in on_ice {
+            let index = match self.ice_time_index.entry((player_id, team)) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    let Some(name) = server.players().get(player_id).map(|player| player.name())
+                    else {
+                        continue;
+                    };
+                    let ice_time = &mut server.game_summary_mut().ice_time;
+                    // A player who reconnects continues where they left off
+                    let index = ice_time
+                        .iter()
+                        .position(|x| x.team == team && x.player == name)
+                        .unwrap_or_else(|| {
+                            ice_time.push(IceTime {
+                                player: name,
+                                team,
+                                periods: vec![],
+                            });
+                            ice_time.len() - 1
+                        });
+                    *entry.insert(index)
+                }
+            };
+            if let Some(entry) = server.game_summary_mut().ice_time.get_mut(index) {
+                if entry.periods.len() < period {
+                    entry.periods.resize(period, 0);
+                }
+                entry.periods[period - 1] += 1;
+            }
+        }
+    }
+
+    /// Changes rule settings on behalf of an admin. The settings are kept for the rest of the game,
+    /// instead of being restored or replaced by the period rules.
+    pub(crate) fn set_rules_by_admin(&mut self, rules: PeriodRules) {
+        self.replaced_rules.remove(&rules);
+        rules.apply_to(&mut self.config);
+        if rules.offside.is_some() {
+            self.admin_rules.offside = rules.offside;
+        }
+        if rules.icing.is_some() {
+            self.admin_rules.icing = rules.icing;
+        }
+        if rules.offside_line.is_some() {
+            self.admin_rules.offside_line = rules.offside_line;
+        }
+        if rules.twoline_pass.is_some() {
+            self.admin_rules.twoline_pass = rules.twoline_pass;
+        }
+    }
+
+    /// Restores the rules that were changed for the previous period, and applies the rule changes for the current period.
+    pub(crate) fn apply_period_rules(&mut self, mut server: ServerMut) {
+        let period = server.scoreboard().period;
+        std::mem::take(&mut self.replaced_rules).apply_to(&mut self.config);
+        let rules = if period > self.config.periods {
+            self.config.overtime_rules.clone()
+        } else {
+            self.config.period_rules.get(&period).cloned()
+        };
+        if let Some(mut rules) = rules {
+            rules.remove(&self.admin_rules);
+            self.replaced_rules = rules.apply_to(&mut self.config);
+            if !server.scoreboard().game_over {
+                let msg = format!("Rules for this period: {}", self.rules_str());
+                server.players_mut().add_server_chat_message(msg);
+                let twoline_str = self.twoline_str();
+                if !twoline_str.is_empty() {
+                    server.players_mut().add_server_chat_message(twoline_str);
+                }
+            }
+        }
+    }
+
     pub fn cleanup_player(&mut self, player_index: PlayerId) {
         if let Some(x) = self
             .started_as_goalie
@@ -891,7 +1636,7 @@ impl Match {
         {
             self.started_as_goalie.remove(x);
         }
-        self.preferred_positions.remove(&player_index);
+        self.contest.remove_player(player_index);
     }
 
     pub fn get_initial_game_values(&mut self) -> InitialGameValues {
@@ -900,17 +1645,30 @@ impl Match {
         values.time = self.config.time_warmup * 100;
         InitialGameValues {
             values,
-            puck_slots: self.config.warmup_pucks,
+            puck_slots: self.config.warmup_pucks.max(self.config.game_pucks),
         }
     }
     pub fn game_started(&mut self, mut server: ServerMut) {
         self.paused = false;
-        self.pause_timer = 0;
         self.next_faceoff_spot = RinkFaceoffSpot::Center;
-        self.icing_status = IcingStatus::No;
-        self.offside_status = OffsideStatus::Neutral;
-        self.twoline_pass_status = TwoLinePassStatus::No;
+        self.puck_states.clear();
         self.start_next_replay = None;
+        self.zone_exit_game_step = [0; 2];
+        self.contest.reset();
+        self.ice_time_index.clear();
+        self.waiting_for_players = false;
+        self.waiting_for_start = false;
+        self.short_handed_ticks = [0; 2];
+        self.forfeited = None;
+        self.penalties.clear();
+        self.pending_events.clear();
+        self.admin_rules = PeriodRules::default();
+        self.apply_period_rules(server.rb_mut());
+        if server.scoreboard().period > 0 {
+            // A resumed game goes on from where it was, without a warmup
+            return;
+        }
+        self.pause_timer = 0;
         let warmup_pucks = self.config.warmup_pucks;
         let rink = server.rink();
         let width = rink.width;
@@ -977,6 +1735,26 @@ struct Pass {
     pub player: PlayerId,
 }
 
+/// Rule state of a single puck.
+#[derive(Debug, Clone)]
+struct PuckState {
+    icing_status: IcingStatus,
+    offside_status: OffsideStatus,
+    twoline_pass_status: TwoLinePassStatus,
+    pass: Option<Pass>,
+}
+
+impl Default for PuckState {
+    fn default() -> Self {
+        Self {
+            icing_status: IcingStatus::No,
+            offside_status: OffsideStatus::Neutral,
+            twoline_pass_status: TwoLinePassStatus::No,
+            pass: None,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 enum IcingStatus {
     No,                      // No icing
@@ -994,9 +1772,9 @@ enum OffsideStatus {
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 enum TwoLinePassStatus {
-    No,                                                   // No offside
-    Warning(Team, RinkSide, PassLocation, Vec<PlayerId>), // Warning, puck entered offensive zone in an offside situation but not touched yet
-    Offside(Team),                                        // Offside has been called
+    No,                                                             // No offside
+    Warning(Team, RinkSide, PassLocation, SmallVec<[PlayerId; 8]>), // Warning, puck entered offensive zone in an offside situation but not touched yet
+    Offside(Team),                                                  // Offside has been called
 }
 
 #[derive(Debug, Clone)]
@@ -1009,6 +1787,15 @@ struct PuckTouch {
     pub last_time: u32,
 }
 
+/// Converts a speed in meters per hundredth of a second to miles or kilometers per hour.
+pub(crate) fn convert_speed(speed: f32, use_mph: bool) -> (f32, &'static str) {
+    if use_mph {
+        (speed * 100f32 * 2.23693, "mph")
+    } else {
+        (speed * 100f32 * 3.6, "km/h")
+    }
+}
+
 fn add_touch(
     puck: &Puck,
     entry: Entry<usize, ArrayDeque<PuckTouch, 16, Wrapping>>,
@@ -1043,20 +1830,55 @@ fn add_touch(
     }
 }
 
+/// How far from the middle of the penalty box a penalized player can move.
+const PENALTY_BOX_RADIUS: f32 = 1.5;
+
+/// The penalty box of a team is by the boards next to the center line, on the team's own half.
+fn get_penalty_box(rink: &Rink, team: Team, altitude: f32) -> (Point3<f32>, Rotation3<f32>) {
+    let z = match team {
+        Team::Red => rink.length / 2.0 + 3.0,
+        Team::Blue => rink.length / 2.0 - 3.0,
+    };
+    let pos = Point3::new(0.75, altitude, z);
+    // Facing the ice
+    let rot = Rotation3::from_axis_angle(&Vector3::y_axis(), -FRAC_PI_2);
+    (pos, rot)
+}
+
+fn release_penalty(
+    mut server: ServerMut,
+    penalty: Penalty,
+    goal_against: bool,
+    match_events: &mut Vec<MatchEvent>,
+) {
+    if let Some(player) = server.players().get(penalty.player) {
+        let msg = format!("{} is released from the penalty box", player.name());
+        server.players_mut().add_server_chat_message(msg);
+    }
+    match_events.push(MatchEvent::PenaltyEnded {
+        team: penalty.team,
+        player: penalty.player,
+        goal_against,
+    });
+}
+
 fn get_faceoff_positions(
     players: ServerPlayers,
-    preferred_positions: &HashMap<PlayerId, &'static str>,
+    preferred_positions: &PreferredPositions,
+    overtime: bool,
 ) -> HashMap<PlayerId, (Team, &'static str)> {
     let mut res = HashMap::new();
 
-    let mut red_players = smallvec::SmallVec::<[_; 32]>::new();
-    let mut blue_players = smallvec::SmallVec::<[_; 32]>::new();
+    let mut red_players = SmallVec::<[_; 32]>::new();
+    let mut blue_players = SmallVec::<[_; 32]>::new();
     for player in players.iter() {
         let player_id = player.id;
 
         let team = player.team();
 
-        let preferred_position = preferred_positions.get(&player_id).map(|x| *x);
+        let preferred_position = preferred_positions
+            .get(&player)
+            .filter(|x| !overtime || OVERTIME_POSITIONS[..4].contains(x));
 
         if team == Some(Team::Red) {
             red_players.push((player_id, preferred_position));
@@ -1065,8 +1887,13 @@ fn get_faceoff_positions(
         }
     }
 
-    setup_position(&mut res, &red_players, Team::Red);
-    setup_position(&mut res, &blue_players, Team::Blue);
+    let allowed_positions = if overtime {
+        &OVERTIME_POSITIONS
+    } else {
+        &ALLOWED_POSITIONS
+    };
+    setup_position(&mut res, &red_players, Team::Red, allowed_positions);
+    setup_position(&mut res, &blue_players, Team::Blue, allowed_positions);
 
     res
 }
@@ -1109,12 +1936,15 @@ fn has_players_in_offensive_zone(
     false
 }
 
+/// Gives each player a faceoff position out of `allowed_positions`, which are given out in order
+/// to the players without a preferred position.
 fn setup_position(
     positions: &mut HashMap<PlayerId, (Team, &'static str)>,
     players: &[(PlayerId, Option<&'static str>)],
     team: Team,
+    allowed_positions: &[&'static str],
 ) {
-    let mut available_positions = Vec::from(ALLOWED_POSITIONS);
+    let mut available_positions = Vec::from(allowed_positions);
 
     // First, we try to give each player its preferred position
     for (player_id, player_position) in players.iter() {
@@ -1175,7 +2005,50 @@ fn setup_position(
     }
 }
 
-fn get_faceoff_spot(
+fn is_near_boards(rink: &Rink, pos: &Point3<f32>) -> bool {
+    let near_wall = rink
+        .planes
+        .iter()
+        .filter(|(_, normal)| normal.y == 0.0)
+        .any(|(point, normal)| (pos - point).dot(normal) < BOARDS_DISTANCE);
+    let near_corner = rink.corners.iter().any(|(center, dir, radius)| {
+        let dx = pos.x - center.x;
+        let dz = pos.z - center.z;
+        dx * dir.x > 0.0 && dz * dir.z > 0.0 && radius - dx.hypot(dz) < BOARDS_DISTANCE
+    });
+    near_wall || near_corner
+}
+
+fn is_near_net(rink: &Rink, pos: &Point3<f32>) -> bool {
+    [Team::Red, Team::Blue].into_iter().any(|team| {
+        let net = rink.net(team);
+        // The middle of the net is half a meter behind the goal line
+        let middle = net.center() - net.normal() * 0.5;
+        (pos.x - middle.x).hypot(pos.z - middle.z) < NET_DISTANCE
+    })
+}
+
+/// Returns the faceoff spot that is closest to `pos`.
+fn closest_faceoff_spot(rink: &Rink, pos: &Point3<f32>) -> RinkFaceoffSpot {
+    let mut spots = vec![RinkFaceoffSpot::Center];
+    for team in [Team::Red, Team::Blue] {
+        for side in [RinkSide::LowerHalfZ, RinkSide::HigherHalfZ] {
+            spots.push(RinkFaceoffSpot::DefensiveZone(team, side));
+            spots.push(RinkFaceoffSpot::Offside(team, side));
+        }
+    }
+    let distance = |spot: &RinkFaceoffSpot| {
+        let center = get_faceoff_spot(rink, *spot, 0.0, 0.0).center_position;
+        (center.x - pos.x).hypot(center.z - pos.z)
+    };
+    spots
+        .into_iter()
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .unwrap_or(RinkFaceoffSpot::Center)
+}
+
+/// Returns the puck and player positions of a faceoff spot.
+pub fn get_faceoff_spot(
     rink: &Rink,
     spot: RinkFaceoffSpot,
     spawn_point_offset: f32,
@@ -1191,14 +2064,18 @@ fn get_faceoff_spot(
 
     let goal_line_distance = 4.0; // IIHF rule 17iv
 
+    // Small-area rinks get their faceoff spots moved closer to the middle
+    let length_scale = length / 61.0;
+    let width_scale = width / 30.0;
+
     let blue_line_distance_neutral_zone_edge = rink.blue_zone_blue_line.z;
     // IIHF specifies distance between end boards and edge closest to the neutral zone, but my code specifies middle of line
-    let distance_neutral_faceoff_spot = blue_line_distance_neutral_zone_edge + 1.5; // IIHF rule 18iv and 18vii
-    let distance_zone_faceoff_spot = goal_line_distance + 6.0; // IIHF rule 18vi and 18vii
+    let distance_neutral_faceoff_spot = blue_line_distance_neutral_zone_edge + 1.5 * length_scale; // IIHF rule 18iv and 18vii
+    let distance_zone_faceoff_spot = goal_line_distance + 6.0 * length_scale; // IIHF rule 18vi and 18vii
 
     let center_x = width / 2.0;
-    let left_faceoff_x = center_x - 7.0; // IIHF rule 18vi and 18iv
-    let right_faceoff_x = center_x + 7.0; // IIHF rule 18vi and 18iv
+    let left_faceoff_x = center_x - 7.0 * width_scale; // IIHF rule 18vi and 18iv
+    let right_faceoff_x = center_x + 7.0 * width_scale; // IIHF rule 18vi and 18iv
 
     let red_zone_faceoff_z = length - distance_zone_faceoff_spot;
     let red_neutral_faceoff_z = length - distance_neutral_faceoff_spot;
@@ -1207,11 +2084,11 @@ fn get_faceoff_spot(
     let blue_zone_faceoff_z = distance_zone_faceoff_spot;
 
     let create_faceoff_spot = |center_position: Point3<f32>| {
-        let red_defensive_zone = center_position.z > length - 11.0;
-        let blue_defensive_zone = center_position.z < 11.0;
-        let (red_left, red_right) = if center_position.x < 9.0 {
+        let red_defensive_zone = center_position.z > length - 11.0 * length_scale;
+        let blue_defensive_zone = center_position.z < 11.0 * length_scale;
+        let (red_left, red_right) = if center_position.x < 9.0 * width_scale {
             (true, false)
-        } else if center_position.x > width - 9.0 {
+        } else if center_position.x > width - 9.0 * width_scale {
             (false, true)
         } else {
             (false, false)
@@ -1390,9 +2267,14 @@ fn get_faceoff_spot(
 
 #[cfg(test)]
 mod tests {
+    use crate::game::Rink;
     use crate::game::Team;
     use crate::game::{PlayerId, PlayerIndex};
-    use crate::gamemode::match_util::setup_position;
+    use crate::gamemode::match_util::{
+        closest_faceoff_spot, is_near_boards, is_near_net, setup_position, RinkFaceoffSpot,
+        RinkSide, ALLOWED_POSITIONS, OVERTIME_POSITIONS,
+    };
+    use nalgebra::Point3;
     use std::collections::HashMap;
 
     #[test]
@@ -1412,52 +2294,78 @@ mod tests {
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, None)];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "C");
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, Some(c))];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "C");
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, Some(lw))];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "C");
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, Some(g))];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "C");
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, Some(c)), (i1, Some(lw))];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "C");
         assert_eq!(res1[&i1].1, "LW");
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, None), (i1, Some(lw))];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "C");
         assert_eq!(res1[&i1].1, "LW");
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, Some(rw)), (i1, Some(lw))];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "C");
         assert_eq!(res1[&i1].1, "LW");
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, Some(g)), (i1, Some(lw))];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "G");
         assert_eq!(res1[&i1].1, "C");
 
         let mut res1 = HashMap::new();
         let players = vec![(i0, Some(c)), (i1, Some(c))];
-        setup_position(&mut res1, players.as_ref(), Team::Red);
+        setup_position(&mut res1, players.as_ref(), Team::Red, &ALLOWED_POSITIONS);
         assert_eq!(res1[&i0].1, "C");
         assert_eq!(res1[&i1].1, "LW");
+
+        // Overtime lineups have defensemen instead of wingers
+        let mut res1 = HashMap::new();
+        let players = vec![(i0, None), (i1, None)];
+        setup_position(&mut res1, players.as_ref(), Team::Red, &OVERTIME_POSITIONS);
+        assert_eq!(res1[&i0].1, "C");
+        assert_eq!(res1[&i1].1, "LD");
+    }
+
+    #[test]
+    fn frozen_puck_places() {
+        let rink = Rink::new(30.0, 61.0, 8.5);
+        assert!(is_near_boards(&rink, &Point3::new(0.5, 0.0, 30.0)));
+        assert!(is_near_boards(&rink, &Point3::new(1.0, 0.0, 1.5)));
+        assert!(!is_near_boards(&rink, &Point3::new(15.0, 0.0, 30.0)));
+        assert!(is_near_net(&rink, &Point3::new(15.0, 0.0, 57.5)));
+        assert!(!is_near_net(&rink, &Point3::new(15.0, 0.0, 50.0)));
+
+        assert_eq!(
+            closest_faceoff_spot(&rink, &Point3::new(15.0, 0.0, 31.0)),
+            RinkFaceoffSpot::Center
+        );
+        assert_eq!(
+            closest_faceoff_spot(&rink, &Point3::new(1.0, 0.0, 58.0)),
+            RinkFaceoffSpot::DefensiveZone(Team::Red, RinkSide::LowerHalfZ)
+        );
     }
 }
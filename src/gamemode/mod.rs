@@ -1,25 +1,51 @@
+use crate::auth::PlayerIdentity;
+use crate::commands::ChatCommand;
+use crate::extension::{AnnouncementEvent, ExtensionMessage, SoundEvent};
 use crate::game::{
-    PhysicsEvent, PlayerId, PlayerIndex, PlayerInput, Puck, Rink, ScoreboardValues, SkaterObject,
-    Team,
+    PhysicsConfiguration, PhysicsEvent, PlayerId, PlayerIndex, PlayerInput, Puck, Rink,
+    ScoreboardDisplay, ScoreboardValues, SkaterObject, Team,
 };
+use crate::observer::GameObserver;
+use crate::protocol::ObjectPacket;
+use crate::record::{AnnotationKind, GameSummary};
+use crate::rng::GameRng;
 use crate::server::{
     HQMServer, HQMServerPlayer, HQMServerPlayersAndMessages, HQMTickHistory, PlayerListExt,
     ServerPlayerData,
 };
+use crate::votes::VoteHandler;
 use crate::ServerConfiguration;
+use chrono::{DateTime, Utc};
 use nalgebra::{Point3, Rotation3};
 use reborrow::{Reborrow, ReborrowCopyTraits, ReborrowTraits};
 use std::borrow::Cow;
 use std::cmp::PartialEq;
 use std::rc::Rc;
-
+use tokio::task::JoinHandle;
+
+pub mod bots;
+pub mod events;
+pub mod freeforall;
+pub mod layers;
+pub mod playback;
+pub mod practice;
+pub mod profiles;
+pub mod rotation;
 pub mod russian;
 pub mod shootout;
+pub mod skills;
+pub mod template;
 pub mod util;
 pub mod warmup;
 
+mod intermission;
+mod layouts;
 mod match_commands;
 mod match_util;
+mod mvp;
+mod overtime;
+mod positions;
+mod scheduled_start;
 pub mod standard_match;
 
 /// Specifies the server game behaviour.
@@ -40,13 +66,37 @@ pub trait GameMode {
     fn after_tick(&mut self, server: ServerMut, events: &[PhysicsEvent]);
 
     /// Called when a chat message starting with "/" is received from a user. This method is called between ticks and not during, so you can do anything here.
-    fn handle_command(
+    ///
+    /// Commands registered with [`ServerMut::register_command`] are only passed here if the player has the permission
+    /// and the arguments match, and `cmd` is the name of the command even if an alias was used.
+    fn handle_command(&mut self, _server: ServerMut, _cmd: &str, _arg: &str, _player_id: PlayerId) {
+    }
+
+    /// Called when a command is received from the web admin interface and the server doesn't handle it itself.
+    ///
+    /// The sender has already been authenticated as an administrator. `admin_name` is the name that should be
+    /// shown in chat messages. Returns [`None`] if the command wasn't recognized, and an error message,
+    /// such as the usage of the command, if its arguments were invalid.
+    fn handle_remote_command(
         &mut self,
         _server: ServerMut,
         _cmd: &str,
         _arg: &str,
-        _player_index: PlayerId,
-    ) {
+        _admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        None
+    }
+
+    /// Called when a player sends a chat message that has passed the chat filter, right before it is sent.
+    ///
+    /// The game mode can change the message, or return `false` to drop it.
+    fn before_chat_message(
+        &mut self,
+        _server: ServerMut,
+        _player_id: PlayerId,
+        _message: &mut String,
+    ) -> bool {
+        true
     }
 
     fn get_initial_game_values(&mut self) -> InitialGameValues;
@@ -64,7 +114,17 @@ pub trait GameMode {
     }
 
     /// Called right after a new player has joined the server.
-    fn after_player_join(&mut self, _server: ServerMut, _player_index: PlayerId) {}
+    fn after_player_join(&mut self, _server: ServerMut, _player_id: PlayerId) {}
+
+    /// Called right after a skater has been moved to the spectators for not touching their controls
+    /// for `afk_time` seconds of live play.
+    fn on_player_afk(&mut self, _server: ServerMut, _player_id: PlayerId) {}
+
+    /// Called right after a new game has been started in place of an abandoned game, if the abandon policy
+    /// is [`AbandonPolicy::Warmup`](crate::AbandonPolicy::Warmup).
+    ///
+    /// Game modes with a warmup should stay in it until an admin starts the game.
+    fn hold_warmup(&mut self, _server: ServerMut) {}
 
     /// Gets the server team size that will be shown in the server list.
     fn server_list_team_size(&self) -> u32;
@@ -72,6 +132,104 @@ pub trait GameMode {
     fn include_tick_in_recording(&self, _server: Server) -> bool {
         false
     }
+
+    /// Called once each tick to decide what the scoreboard will look like for the players.
+    ///
+    /// `display` starts out with the current [`ScoreboardValues`] and no custom values.
+    /// Changes only affect what is sent to the clients and written to recordings.
+    fn scoreboard_display(&self, _server: Server, _display: &mut ScoreboardDisplay) {}
+
+    /// Returns the handler for vote types added by the game mode, if any.
+    fn vote_handler(&mut self) -> Option<&mut dyn VoteHandler> {
+        None
+    }
+
+    /// Returns the saves of the game mode that are still being written, so that they can be waited for before
+    /// the server exits.
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        vec![]
+    }
+}
+
+impl<T: GameMode + ?Sized> GameMode for Box<T> {
+    fn init(&mut self, server: ServerMut) {
+        (**self).init(server)
+    }
+
+    fn before_tick(&mut self, server: ServerMut) {
+        (**self).before_tick(server)
+    }
+
+    fn after_tick(&mut self, server: ServerMut, events: &[PhysicsEvent]) {
+        (**self).after_tick(server, events)
+    }
+
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        (**self).handle_command(server, cmd, arg, player_id)
+    }
+
+    fn handle_remote_command(
+        &mut self,
+        server: ServerMut,
+        cmd: &str,
+        arg: &str,
+        admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        (**self).handle_remote_command(server, cmd, arg, admin_name)
+    }
+
+    fn before_chat_message(
+        &mut self,
+        server: ServerMut,
+        player_id: PlayerId,
+        message: &mut String,
+    ) -> bool {
+        (**self).before_chat_message(server, player_id, message)
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        (**self).get_initial_game_values()
+    }
+
+    fn game_started(&mut self, server: ServerMut) {
+        (**self).game_started(server)
+    }
+
+    fn before_player_exit(&mut self, server: ServerMut, player_id: PlayerId, reason: ExitReason) {
+        (**self).before_player_exit(server, player_id, reason)
+    }
+
+    fn after_player_join(&mut self, server: ServerMut, player_id: PlayerId) {
+        (**self).after_player_join(server, player_id)
+    }
+
+    fn on_player_afk(&mut self, server: ServerMut, player_id: PlayerId) {
+        (**self).on_player_afk(server, player_id)
+    }
+
+    fn hold_warmup(&mut self, server: ServerMut) {
+        (**self).hold_warmup(server)
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        (**self).server_list_team_size()
+    }
+
+    fn include_tick_in_recording(&self, server: Server) -> bool {
+        (**self).include_tick_in_recording(server)
+    }
+
+    fn scoreboard_display(&self, server: Server, display: &mut ScoreboardDisplay) {
+        (**self).scoreboard_display(server, display)
+    }
+
+    fn vote_handler(&mut self) -> Option<&mut dyn VoteHandler> {
+        (**self).vote_handler()
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        (**self).take_pending_saves()
+    }
 }
 
 /// A struct containing the individual parts of a [ServerMut].
@@ -156,6 +314,12 @@ impl<'a> ServerMut<'a> {
         self.server.new_game(v)
     }
 
+    /// Replaces the initial values of a game that has just been started, without starting another game.
+    /// Useful when a game mode switches to another game mode right after a new game has started.
+    pub fn replace_game_values(&mut self, v: InitialGameValues) {
+        self.server.replace_game_values(v)
+    }
+
     pub fn rink(&self) -> &Rink {
         &self.server.rink
     }
@@ -179,6 +343,87 @@ impl<'a> ServerMut<'a> {
     pub fn config_mut(&mut self) -> &mut ServerConfiguration {
         &mut self.server.config
     }
+
+    pub fn physics_config(&self) -> &PhysicsConfiguration {
+        &self.server.physics_config
+    }
+
+    /// Returns the current time. Use it instead of the system clock, since the time is simulated in
+    /// headless mode and in tests.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.server.now()
+    }
+
+    /// Changes take effect from the next tick.
+    pub fn physics_config_mut(&mut self) -> &mut PhysicsConfiguration {
+        &mut self.server.physics_config
+    }
+
+    /// Returns true if the current game has been tagged as a league game.
+    pub fn is_league_game(&self) -> bool {
+        self.server.state.summary.league
+    }
+
+    /// Tags or untags the current game as a league game.
+    ///
+    /// League games are always recorded and saved, regardless of the recording setting, and the saved recording is marked as a league game.
+    /// The tag is cleared when a new game starts.
+    pub fn set_league_game(&mut self, league: bool) {
+        self.server.state.summary.league = league;
+    }
+
+    /// Gets the summary that will be saved together with the recording of the current game.
+    pub fn game_summary(&self) -> &GameSummary {
+        &self.server.state.summary
+    }
+
+    pub fn game_summary_mut(&mut self) -> &mut GameSummary {
+        &mut self.server.state.summary
+    }
+
+    /// Returns the random number generator of the current game. See [`rng`](crate::rng).
+    pub fn rng_mut(&mut self) -> &mut GameRng {
+        &mut self.server.rng
+    }
+
+    /// Returns the seed of the random numbers of the current game.
+    pub fn random_seed(&self) -> u64 {
+        self.server.rng.seed()
+    }
+
+    /// Marks a game event in the recording, if recording annotations are enabled.
+    /// Observers receive annotations either way.
+    pub fn add_annotation(
+        &mut self,
+        kind: AnnotationKind,
+        team: Option<Team>,
+        player_id: Option<PlayerId>,
+        reason: impl Into<Cow<'static, str>>,
+    ) {
+        let recording = self.server.config.recording_annotations;
+        self.server
+            .state
+            .players
+            .add_annotation(kind, team, player_id, reason.into(), recording);
+    }
+
+    /// Registers an observer that will receive the same object and message stream as the clients.
+    pub fn add_observer(&mut self, observer: Box<dyn GameObserver>) {
+        self.server.observers.push(observer);
+    }
+
+    /// Registers a chat command of the game mode, so that its arguments are checked and it is listed by /help.
+    /// Replaces any earlier command with the same name. See [`commands`](crate::commands).
+    pub fn register_command(&mut self, command: ChatCommand) {
+        self.server.commands.register(command);
+    }
+
+    /// Removes all commands registered by game modes.
+    ///
+    /// Game modes that switch between other game modes should call this before the next game mode is initialized.
+    pub fn clear_commands(&mut self) {
+        self.server.commands.clear();
+    }
 }
 
 /// Immutable handle to server.
@@ -211,6 +456,30 @@ impl<'a> Server<'a> {
         &self.server.config
     }
 
+    pub fn physics_config(&self) -> &PhysicsConfiguration {
+        &self.server.physics_config
+    }
+
+    /// Returns true if the current game has been tagged as a league game.
+    pub fn is_league_game(&self) -> bool {
+        self.server.state.summary.league
+    }
+
+    /// Gets the summary that will be saved together with the recording of the current game.
+    pub fn game_summary(&self) -> &GameSummary {
+        &self.server.state.summary
+    }
+
+    /// Returns the seed of the random numbers of the current game.
+    pub fn random_seed(&self) -> u64 {
+        self.server.rng.seed()
+    }
+
+    /// Returns the current time, which is simulated in headless mode and in tests.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.server.now()
+    }
+
     pub fn pucks(&self) -> &[Option<Puck>] {
         self.server.state.pucks.as_slice()
     }
@@ -222,6 +491,19 @@ impl<'a> Server<'a> {
     }
 }
 
+/// How a replay added with [`ServerReplayMut::add_replay_with_options`] is shown.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+    /// Each tick is shown this many times, so 2 is half speed. 0 and 1 are both normal speed.
+    pub slow_motion: u32,
+    /// The replay is shown once following each of these players in turn.
+    /// If empty, it is shown once and everyone keeps their own view.
+    pub cameras: Vec<PlayerId>,
+    /// Players that don't see the replay. As the game is paused during the replay, they see the last tick
+    /// before the replay, with their own view.
+    pub skip_players: Vec<PlayerId>,
+}
+
 #[derive(ReborrowTraits)]
 #[Const(ServerReplay)]
 pub struct ServerReplayMut<'a> {
@@ -241,6 +523,17 @@ impl<'a> ServerReplayMut<'a> {
             .add_replay_to_queue(start_step, end_step, force_view)
     }
 
+    /// Adds a replay to the replay queue, with options for how it is shown.
+    pub fn add_replay_with_options(
+        &mut self,
+        start_step: u32,
+        end_step: u32,
+        options: &ReplayOptions,
+    ) {
+        self.replay
+            .add_replay_with_options(start_step, end_step, options)
+    }
+
     pub fn is_in_replay(&self) -> bool {
         self.replay.is_in_replay()
     }
@@ -249,6 +542,10 @@ impl<'a> ServerReplayMut<'a> {
         self.replay.history_length = history_length;
     }
 
+    pub(crate) fn add_tick_to_queue(&mut self, packets: [ObjectPacket; 32]) {
+        self.replay.add_tick_to_queue(packets)
+    }
+
     pub fn game_step(&self) -> u32 {
         self.replay.game_step
     }
@@ -285,43 +582,67 @@ impl<'a> ServerPlayersMut<'a> {
     pub fn add_directed_server_chat_message(
         &mut self,
         message: impl Into<Cow<'static, str>>,
-        receiver_index: PlayerId,
+        receiver_id: PlayerId,
     ) {
         self.state
-            .add_directed_server_chat_message(message, receiver_index);
+            .add_directed_server_chat_message(message, receiver_id);
     }
 
     pub fn add_user_chat_message(
         &mut self,
         message: impl Into<Cow<'static, str>>,
-        sender_index: PlayerId,
+        sender_id: PlayerId,
     ) {
-        if self.state.players.get_player(sender_index).is_some() {
-            self.state
-                .add_user_chat_message(message, sender_index.index);
+        if self.state.players.get_player(sender_id).is_some() {
+            self.state.add_user_chat_message(message, sender_id);
         }
     }
 
     pub fn add_goal_message(
         &mut self,
         team: Team,
-        goal_player_index: Option<PlayerId>,
-        assist_player_index: Option<PlayerId>,
+        goal_player: Option<PlayerId>,
+        assist_player: Option<PlayerId>,
+    ) {
+        self.state
+            .add_goal_message(team, goal_player, assist_player);
+    }
+
+    /// Sends an extension message to all players whose clients support extensions.
+    pub fn add_extension_message(&mut self, message: &impl ExtensionMessage) {
+        self.state.add_extension_message(message);
+    }
+
+    /// Sends an extension message to a single player, if the player's client supports extensions.
+    pub fn add_directed_extension_message(
+        &mut self,
+        message: &impl ExtensionMessage,
+        receiver_id: PlayerId,
     ) {
         self.state
-            .add_goal_message(team, goal_player_index, assist_player_index);
+            .add_directed_extension_message(message, receiver_id);
+    }
+
+    /// Signals an event that clients can play a sound for, see [`SoundEvent`].
+    pub fn play_sound_event(&mut self, event: SoundEvent) {
+        self.state.play_sound_event(event);
+    }
+
+    /// Announces the event as set in the Announcements section of the configuration, see [`AnnouncementEvent`].
+    pub fn announce(&mut self, event: AnnouncementEvent) {
+        self.state.announce(event);
     }
 
     pub fn spawn_skater(
         &mut self,
-        player_index: PlayerId,
+        player_id: PlayerId,
         team: Team,
         pos: Point3<f32>,
         rot: Rotation3<f32>,
         keep_stick_position: bool,
     ) -> bool {
         self.state
-            .spawn_skater(player_index, team, pos, rot, keep_stick_position)
+            .spawn_skater(player_id, team, pos, rot, keep_stick_position)
     }
 
     pub fn move_to_spectator(&mut self, player_id: PlayerId) -> bool {
@@ -410,6 +731,11 @@ impl<'a> ServerPlayersMut<'a> {
             })
     }
 
+    /// Returns the ID of the player currently in the given slot, if any.
+    pub fn id_by_index(&self, index: PlayerIndex) -> Option<PlayerId> {
+        self.rb().id_by_index(index)
+    }
+
     /// Convenience method to count the number of players currently in the red or blue team.
     pub fn count_team_members(&self) -> (usize, usize) {
         let a = self.rb();
@@ -448,6 +774,16 @@ impl<'a> ServerPlayers<'a> {
             .map(|player| ServerPlayer { id, player })
     }
 
+    /// Returns the ID of the player currently in the given slot, if any.
+    ///
+    /// Useful for chat commands that take a player number, as all other APIs identify players by [`PlayerId`].
+    pub fn id_by_index(&self, index: PlayerIndex) -> Option<PlayerId> {
+        self.state
+            .players
+            .get_player_by_index(index)
+            .map(|(id, _)| id)
+    }
+
     /// Convenience method to count the number of players currently in the red or blue team.
     pub fn count_team_members(&self) -> (usize, usize) {
         let mut red_player_count = 0usize;
@@ -555,6 +891,17 @@ impl<'a> ServerPlayer<'a> {
         self.player.is_admin
     }
 
+    /// Returns true if the player only wants to watch, chosen with /spec, and should never be put in a team.
+    pub fn is_spectator(&self) -> bool {
+        self.player.is_spectator
+    }
+
+    /// Returns true if the server wants the player off the ice, for example during maintenance.
+    /// Game modes should move the player to the spectators, like when the player asks to spectate.
+    pub fn should_leave_ice(&self) -> bool {
+        self.player.leave_ice
+    }
+
     pub fn name(&self) -> Rc<str> {
         self.player.player_name.clone()
     }
@@ -566,12 +913,25 @@ impl<'a> ServerPlayer<'a> {
             .map(|(_, skater, team)| (*team, skater))
     }
 
+    /// Returns true if the player's client can receive extension messages.
+    pub fn supports_extensions(&self) -> bool {
+        self.player.supports_extensions()
+    }
+
     pub fn player_type(&self) -> ServerPlayerType {
         match self.player.data {
             ServerPlayerData::NetworkPlayer { .. } => ServerPlayerType::Player,
             ServerPlayerData::Bot { .. } => ServerPlayerType::Bot,
         }
     }
+
+    /// Returns the identity verified by the authentication provider, if any.
+    pub fn identity(&self) -> Option<&PlayerIdentity> {
+        match &self.player.data {
+            ServerPlayerData::NetworkPlayer { data } => data.identity.as_ref(),
+            ServerPlayerData::Bot { .. } => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -580,11 +940,18 @@ pub struct InitialGameValues {
     pub puck_slots: usize,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum ExitReason {
     Disconnected,
     Timeout,
     AdminKicked,
+    /// A bot was removed by the server, for example to make room for a human player.
+    BotRemoved,
+    /// The player was kicked after a vote.
+    VoteKicked,
+    /// The player was removed because the server closed, see the schedule setting.
+    ServerClosed,
 }
 
 pub trait PuckExt {
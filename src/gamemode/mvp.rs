@@ -0,0 +1,166 @@
+//! Vote for the most valuable player of a game.
+//!
+//! When a game is over, the players have a while to vote for the MVP with `/mvp NAME`. The game isn't
+//! reset until the voting has closed, so that the result can be added to the game summary.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use tracing::info;
+
+use crate::game::PlayerId;
+use crate::gamemode::ServerMut;
+use crate::record::MvpVote;
+
+/// Vote for the most valuable player, held by the players when a game is over.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MvpVoting {
+    /// Ticks until the voting closes, or [`None`] if there is no vote.
+    timer: Option<u32>,
+    /// True once the vote of the current game has been held, so that it is only held once.
+    held: bool,
+    votes: HashMap<PlayerId, Rc<str>>,
+}
+
+impl MvpVoting {
+    /// Clears everything. Called when a new game starts.
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.timer.is_some()
+    }
+
+    /// Opens the voting for `ticks`, unless it has already been held this game.
+    pub(crate) fn open(&mut self, mut server: ServerMut, ticks: u32) -> bool {
+        if self.held {
+            return false;
+        }
+        self.held = true;
+        self.timer = Some(ticks);
+        server
+            .players_mut()
+            .add_server_chat_message("Vote for the MVP of the game with /mvp NAME");
+        true
+    }
+
+    pub(crate) fn vote(&mut self, mut server: ServerMut, player_id: PlayerId, name: &str) {
+        if !self.is_open() {
+            server
+                .players_mut()
+                .add_directed_server_chat_message("No MVP vote in progress", player_id);
+            return;
+        }
+        let name = name.trim().to_lowercase();
+        let matches = server
+            .players()
+            .iter()
+            .filter(|player| player.name().to_lowercase().contains(&name))
+            .map(|player| (player.id, player.name()))
+            .collect::<Vec<_>>();
+        // An exact match wins over players whose names only contain the name
+        let candidate = match matches
+            .iter()
+            .find(|(_, candidate)| candidate.to_lowercase() == name)
+        {
+            Some(x) => Some(x.clone()),
+            None if matches.len() == 1 => matches.into_iter().next(),
+            None => None,
+        };
+        let msg = match candidate {
+            None => "No matches found".into(),
+            Some((candidate_id, _)) if candidate_id == player_id => {
+                "You can't vote for yourself".into()
+            }
+            Some((_, candidate)) => {
+                let msg = format!("You voted for {}", candidate);
+                if let Some(voter) = server.players().get(player_id) {
+                    info!(
+                        "{} ({}) voted {} for MVP",
+                        voter.name(),
+                        player_id,
+                        candidate
+                    );
+                }
+                self.votes.insert(player_id, candidate);
+                msg
+            }
+        };
+        server
+            .players_mut()
+            .add_directed_server_chat_message(msg, player_id);
+    }
+
+    /// Counts down the voting, and announces the result when it closes.
+    pub(crate) fn update(&mut self, mut server: ServerMut) {
+        let Some(timer) = &mut self.timer else {
+            return;
+        };
+        *timer = timer.saturating_sub(1);
+        if *timer > 0 {
+            return;
+        }
+        self.timer = None;
+
+        let Some(result) = count_votes(&self.votes) else {
+            server
+                .players_mut()
+                .add_server_chat_message("Nobody voted for the MVP");
+            return;
+        };
+        let votes = if result.votes == 1 { "vote" } else { "votes" };
+        let msg = format!("MVP: {} with {} {}", result.player, result.votes, votes);
+        info!("{}", msg);
+        server.players_mut().add_server_chat_message(msg);
+        server.game_summary_mut().mvp = Some(result);
+    }
+}
+
+/// Returns the player with the most votes. Ties go to the player whose name comes first.
+fn count_votes(votes: &HashMap<PlayerId, Rc<str>>) -> Option<MvpVote> {
+    let mut counts: HashMap<&Rc<str>, u32> = HashMap::new();
+    for candidate in votes.values() {
+        *counts.entry(candidate).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|(a_name, a_votes), (b_name, b_votes)| {
+            a_votes.cmp(b_votes).then_with(|| b_name.cmp(a_name))
+        })
+        .map(|(player, count)| MvpVote {
+            player: player.clone(),
+            votes: count,
+            total_votes: votes.len() as u32,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::count_votes;
+    use crate::game::{PlayerId, PlayerIndex};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn most_votes_wins() {
+        let player = |i| PlayerId {
+            index: PlayerIndex(i),
+            gen: 0,
+        };
+        let (alice, bob): (Rc<str>, Rc<str>) = ("Alice".into(), "Bob".into());
+        let mut votes = HashMap::new();
+        assert!(count_votes(&votes).is_none());
+
+        votes.insert(player(0), bob.clone());
+        votes.insert(player(1), alice.clone());
+        let result = count_votes(&votes).unwrap();
+        assert_eq!(&*result.player, "Alice");
+
+        votes.insert(player(2), bob.clone());
+        let result = count_votes(&votes).unwrap();
+        assert_eq!(&*result.player, "Bob");
+        assert_eq!(result.votes, 2);
+        assert_eq!(result.total_votes, 3);
+    }
+}
@@ -0,0 +1,197 @@
+//! Smaller teams during overtime.
+//!
+//! When the overtime team size is smaller than the teams, the extra skaters are moved off the ice when an
+//! overtime period starts. The players of each team take turns sitting out, so that nobody sits out two
+//! periods in a row while others play both. A shootout that decides a tied game is played one against one
+//! in the same way. When the overtime and the shootout are over, for a new game or because the period was
+//! changed back, the benched players are put back on their teams.
+
+use reborrow::ReborrowMut;
+use tracing::info;
+
+use crate::game::{PlayerId, Team};
+use crate::gamemode::util::{get_spawnpoint, SpawnPoint};
+use crate::gamemode::ServerMut;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OvertimeRoster {
+    /// The players of both teams, in the order they get to play. Players are moved to the back
+    /// when they have played a period.
+    rotation: Vec<(PlayerId, Team)>,
+    /// Players that were moved off the ice, with their team.
+    benched: Vec<(PlayerId, Team)>,
+    /// The overtime period or shootout the current lineups were picked for.
+    lineup_for: Option<Lineup>,
+}
+
+/// Which players are on the ice during an overtime period or a shootout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Lineup {
+    pub(crate) period: u32,
+    pub(crate) team_size: usize,
+    /// True if the lineup is for the shootout that decides a tied game.
+    pub(crate) shootout: bool,
+}
+
+impl OvertimeRoster {
+    /// Picks the lineups when an overtime period or a shootout starts, and restores the full rosters when the
+    /// overtime is over. Should be called once each tick, with `lineup_for` set during overtime periods and
+    /// shootouts. New lineups are picked when it changes.
+    ///
+    /// `join_order` is the order the players on the ice joined their teams. Players that are put back on
+    /// the ice are added to it.
+    pub(crate) fn update(
+        &mut self,
+        server: ServerMut,
+        lineup_for: Option<Lineup>,
+        join_order: &mut Vec<PlayerId>,
+        spawn_point: SpawnPoint,
+    ) {
+        match lineup_for {
+            Some(lineup) => {
+                if self.lineup_for != lineup_for {
+                    self.lineup_for = lineup_for;
+                    self.pick_lineups(server, lineup, join_order, spawn_point);
+                }
+            }
+            None => {
+                if self.lineup_for.is_some() {
+                    self.restore(server, join_order, spawn_point);
+                }
+            }
+        }
+    }
+
+    fn pick_lineups(
+        &mut self,
+        mut server: ServerMut,
+        lineup_for: Lineup,
+        join_order: &mut Vec<PlayerId>,
+        spawn_point: SpawnPoint,
+    ) {
+        let team_size = lineup_for.team_size;
+        // Players that left, or changed teams on their own, lose their turn
+        let players = server.players();
+        let benched = &self.benched;
+        self.rotation.retain(|(player_id, team)| {
+            players.get(*player_id).is_some_and(|player| {
+                player.team() == Some(*team)
+                    || (player.team().is_none() && benched.contains(&(*player_id, *team)))
+            })
+        });
+        // Players that joined since the last period go last, in the order they joined
+        for player_id in join_order.iter() {
+            if let Some(team) = players.get(*player_id).and_then(|player| player.team()) {
+                if !self.rotation.iter().any(|(x, _)| x == player_id) {
+                    self.rotation.push((*player_id, team));
+                }
+            }
+        }
+        for player in players.iter() {
+            if let Some(team) = player.team() {
+                if !self.rotation.iter().any(|(x, _)| *x == player.id) {
+                    self.rotation.push((player.id, team));
+                }
+            }
+        }
+
+        let mut lineup = vec![];
+        let mut sitting_out = vec![];
+        for team in [Team::Red, Team::Blue] {
+            let mut count = 0;
+            for (player_id, _) in self.rotation.iter().filter(|(_, x)| *x == team) {
+                if count < team_size {
+                    lineup.push((*player_id, team));
+                    count += 1;
+                } else {
+                    sitting_out.push((*player_id, team));
+                }
+            }
+        }
+
+        for &(player_id, team) in sitting_out.iter() {
+            let Some(name) = server.players().get(player_id).map(|player| player.name()) else {
+                continue;
+            };
+            if server
+                .players()
+                .get(player_id)
+                .and_then(|x| x.team())
+                .is_some()
+            {
+                server.players_mut().move_to_spectator(player_id);
+                join_order.retain(|x| *x != player_id);
+            } else if self.benched.contains(&(player_id, team)) {
+                // Still sitting out from the last period
+                continue;
+            }
+            let msg = if lineup_for.shootout {
+                info!("{} ({}) sits out shootout", name, player_id);
+                format!("{} sits out the shootout", name)
+            } else {
+                info!("{} ({}) sits out overtime period", name, player_id);
+                format!("{} sits out this overtime period", name)
+            };
+            server.players_mut().add_server_chat_message(msg);
+        }
+        for &(player_id, team) in lineup.iter() {
+            if self.benched.contains(&(player_id, team)) {
+                put_back(server.rb_mut(), player_id, team, join_order, spawn_point);
+            }
+        }
+
+        // The players that play this period go to the back of the line
+        self.rotation.retain(|x| !lineup.contains(x));
+        self.rotation.extend(lineup);
+        self.benched = sitting_out;
+    }
+
+    fn restore(
+        &mut self,
+        mut server: ServerMut,
+        join_order: &mut Vec<PlayerId>,
+        spawn_point: SpawnPoint,
+    ) {
+        let benched = std::mem::take(&mut self.benched);
+        *self = Self::default();
+        if benched.is_empty() {
+            return;
+        }
+        for (player_id, team) in benched {
+            put_back(server.rb_mut(), player_id, team, join_order, spawn_point);
+        }
+        server
+            .players_mut()
+            .add_server_chat_message("Overtime is over, full rosters are back");
+    }
+
+    pub(crate) fn player_left(&mut self, player_id: PlayerId) {
+        self.rotation.retain(|(x, _)| *x != player_id);
+        self.benched.retain(|(x, _)| *x != player_id);
+    }
+}
+
+/// Puts a benched player back on the ice, unless they have joined a team on their own.
+fn put_back(
+    mut server: ServerMut,
+    player_id: PlayerId,
+    team: Team,
+    join_order: &mut Vec<PlayerId>,
+    spawn_point: SpawnPoint,
+) {
+    let is_spectator = server
+        .players()
+        .get(player_id)
+        .is_some_and(|player| player.team().is_none());
+    if !is_spectator {
+        return;
+    }
+    let (pos, rot) = get_spawnpoint(server.rink(), team, spawn_point);
+    if server
+        .players_mut()
+        .spawn_skater(player_id, team, pos, rot, false)
+    {
+        join_order.retain(|x| *x != player_id);
+        join_order.push(player_id);
+    }
+}
@@ -0,0 +1,633 @@
+//! Playback of recorded games.
+//!
+//! [`ReplayServerGameMode`] loads a `.hrp` recording and sends it to the connected clients as if it was
+//! a live game, which is useful for casting and for reviewing league games. The recorded players are
+//! added to the server as bots, so that the clients show their names, teams and chat messages.
+//! Connected players can only spectate.
+
+use std::io;
+use std::rc::Rc;
+
+use nalgebra::{Point3, Rotation3};
+use reborrow::{Reborrow, ReborrowMut};
+use tracing::{info, warn};
+
+use crate::commands::{ArgKind, ChatCommand, CommandArg};
+use crate::game::{PhysicsEvent, PlayerId, ScoreboardValues, Team};
+use crate::gamemode::{GameMode, InitialGameValues, Server, ServerMut};
+use crate::live_state::GameSnapshot;
+use crate::protocol::{HQMMessageReader, ObjectPacket, PuckPacket, SkaterPacket};
+
+/// Ticks between the saved positions that are used for seeking.
+const KEYFRAME_INTERVAL: u32 = 1000;
+
+#[derive(Debug, Clone)]
+struct RecordedPlayer {
+    name: Rc<str>,
+    object: Option<(usize, Team)>,
+}
+
+enum RecordedMessage {
+    Chat {
+        player_index: Option<usize>,
+        message: String,
+    },
+    Goal {
+        team: Team,
+        goal_player_index: Option<usize>,
+        assist_player_index: Option<usize>,
+    },
+}
+
+struct RecordedTick {
+    game_over: bool,
+    red_score: u32,
+    blue_score: u32,
+    time: u32,
+    goal_message_timer: u32,
+    period: u32,
+    messages: Vec<RecordedMessage>,
+}
+
+/// Reads the ticks of a recording in order.
+#[derive(Clone)]
+struct RecordingCursor {
+    /// Position of the next tick in the recording data.
+    offset: usize,
+    /// Number of the next tick.
+    tick: u32,
+    /// Packet number and objects of the last read tick. Objects are delta compressed against the previous tick.
+    packets: Option<(u32, [ObjectPacket; 32])>,
+    players: Vec<Option<RecordedPlayer>>,
+}
+
+impl RecordingCursor {
+    fn new() -> Self {
+        Self {
+            offset: 0,
+            tick: 0,
+            packets: None,
+            players: vec![None; 64],
+        }
+    }
+
+    fn objects(&self) -> &[ObjectPacket] {
+        match &self.packets {
+            Some((_, packets)) => packets,
+            None => &[],
+        }
+    }
+
+    /// Reads the next tick, or returns [`None`] at the end of the recording.
+    fn read_tick(&mut self, data: &[u8]) -> io::Result<Option<RecordedTick>> {
+        if self.offset >= data.len() {
+            return Ok(None);
+        }
+        let mut reader = HQMMessageReader::new(&data[self.offset..]);
+        if reader.read_byte_aligned() != 5 {
+            return Err(invalid_data("Invalid tick header"));
+        }
+        let game_over = reader.read_bits(1) == 1;
+        let red_score = reader.read_bits(8);
+        let blue_score = reader.read_bits(8);
+        let time = reader.read_bits(16);
+        let goal_message_timer = reader.read_bits(16);
+        let period = reader.read_bits(8);
+
+        let current_packet = reader.read_u32_aligned();
+        let known_packet = reader.read_u32_aligned();
+        let old_packets = match &self.packets {
+            _ if known_packet == u32::MAX => None,
+            Some((packet, packets)) if *packet == known_packet => {
+                // The recording server only had the last 192 packets
+                match current_packet.checked_sub(known_packet) {
+                    Some(1..192) => Some(packets),
+                    _ => None,
+                }
+            }
+            _ => return Err(invalid_data("Objects refer to an unknown tick")),
+        };
+        let mut packets = [const { ObjectPacket::None }; 32];
+        for (i, packet) in packets.iter_mut().enumerate() {
+            if reader.read_bits(1) == 0 {
+                continue;
+            }
+            let old_packet = old_packets.map(|x| &x[i]);
+            *packet = match reader.read_bits(2) {
+                0 => {
+                    let old = match old_packet {
+                        Some(ObjectPacket::Skater(old)) => Some(old),
+                        _ => None,
+                    };
+                    ObjectPacket::Skater(SkaterPacket {
+                        pos: (
+                            read_pos(&mut reader, 17, old.map(|x| x.pos.0))?,
+                            read_pos(&mut reader, 17, old.map(|x| x.pos.1))?,
+                            read_pos(&mut reader, 17, old.map(|x| x.pos.2))?,
+                        ),
+                        rot: (
+                            read_pos(&mut reader, 31, old.map(|x| x.rot.0))?,
+                            read_pos(&mut reader, 31, old.map(|x| x.rot.1))?,
+                        ),
+                        stick_pos: (
+                            read_pos(&mut reader, 13, old.map(|x| x.stick_pos.0))?,
+                            read_pos(&mut reader, 13, old.map(|x| x.stick_pos.1))?,
+                            read_pos(&mut reader, 13, old.map(|x| x.stick_pos.2))?,
+                        ),
+                        stick_rot: (
+                            read_pos(&mut reader, 25, old.map(|x| x.stick_rot.0))?,
+                            read_pos(&mut reader, 25, old.map(|x| x.stick_rot.1))?,
+                        ),
+                        head_rot: read_pos(&mut reader, 16, old.map(|x| x.head_rot))?,
+                        body_rot: read_pos(&mut reader, 16, old.map(|x| x.body_rot))?,
+                    })
+                }
+                1 => {
+                    let old = match old_packet {
+                        Some(ObjectPacket::Puck(old)) => Some(old),
+                        _ => None,
+                    };
+                    ObjectPacket::Puck(PuckPacket {
+                        pos: (
+                            read_pos(&mut reader, 17, old.map(|x| x.pos.0))?,
+                            read_pos(&mut reader, 17, old.map(|x| x.pos.1))?,
+                            read_pos(&mut reader, 17, old.map(|x| x.pos.2))?,
+                        ),
+                        rot: (
+                            read_pos(&mut reader, 31, old.map(|x| x.rot.0))?,
+                            read_pos(&mut reader, 31, old.map(|x| x.rot.1))?,
+                        ),
+                    })
+                }
+                _ => return Err(invalid_data("Unknown object type")),
+            };
+        }
+        self.packets = Some((current_packet, packets));
+
+        let message_count = reader.read_bits(16);
+        let _message_pos = reader.read_bits(16);
+        let mut messages = vec![];
+        for _ in 0..message_count {
+            match reader.read_bits(6) {
+                0 => {
+                    let player_index = reader.read_bits(6) as usize;
+                    let in_server = reader.read_bits(1) == 1;
+                    let team = team_from_num(reader.read_bits(2));
+                    let object_index = reader.read_bits(6) as usize;
+                    let name: Vec<u8> = (0..31).map(|_| reader.read_bits(7) as u8).collect();
+                    let name = name.split(|x| *x == 0).next().unwrap_or_default();
+                    self.players[player_index] = in_server.then(|| RecordedPlayer {
+                        name: String::from_utf8_lossy(name).into(),
+                        object: team
+                            .filter(|_| object_index < 32)
+                            .map(|team| (object_index, team)),
+                    });
+                }
+                1 => {
+                    let team = team_from_num(reader.read_bits(2));
+                    let goal_player_index = player_index_from_num(reader.read_bits(6));
+                    let assist_player_index = player_index_from_num(reader.read_bits(6));
+                    if let Some(team) = team {
+                        messages.push(RecordedMessage::Goal {
+                            team,
+                            goal_player_index,
+                            assist_player_index,
+                        });
+                    }
+                }
+                2 => {
+                    let player_index = player_index_from_num(reader.read_bits(6));
+                    let size = reader.read_bits(6);
+                    let message: Vec<u8> = (0..size).map(|_| reader.read_bits(7) as u8).collect();
+                    messages.push(RecordedMessage::Chat {
+                        player_index,
+                        message: String::from_utf8_lossy(&message).into_owned(),
+                    });
+                }
+                3 => {
+                    // Extension messages are not shown
+                    let _message_type = reader.read_bits(16);
+                    let size = reader.read_bits(8);
+                    for _ in 0..size {
+                        reader.read_bits(8);
+                    }
+                }
+                4 => {
+                    // Annotations are only meant for the recording
+                    let _kind = reader.read_bits(4);
+                    let _team = reader.read_bits(2);
+                    let _player_index = reader.read_bits(6);
+                    let size = reader.read_bits(8);
+                    for _ in 0..size {
+                        reader.read_bits(8);
+                    }
+                }
+                _ => return Err(invalid_data("Unknown message type")),
+            }
+        }
+        // Each tick ends with padding, see HQMMessageWriter::recording_fix
+        reader.next();
+
+        self.offset += reader.get_pos();
+        self.tick += 1;
+        Ok(Some(RecordedTick {
+            game_over,
+            red_score,
+            blue_score,
+            time,
+            goal_message_timer,
+            period,
+            messages,
+        }))
+    }
+}
+
+fn read_pos(reader: &mut HQMMessageReader, n: u8, old_value: Option<u32>) -> io::Result<u32> {
+    let diff = match reader.read_bits(2) {
+        0 => reader.read_bits_signed(3),
+        1 => reader.read_bits_signed(6),
+        2 => reader.read_bits_signed(12),
+        _ => return Ok(reader.read_bits(n)),
+    };
+    let old_value = old_value.ok_or_else(|| invalid_data("Position refers to unknown object"))?;
+    Ok((old_value as i32 + diff).max(0) as u32)
+}
+
+fn team_from_num(v: u32) -> Option<Team> {
+    match v {
+        0 => Some(Team::Red),
+        1 => Some(Team::Blue),
+        _ => None,
+    }
+}
+
+fn player_index_from_num(v: u32) -> Option<usize> {
+    (v < 63).then_some(v as usize)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Returns the tick data of a `.hrp` file, without the header.
+fn recording_data(recording: &[u8]) -> io::Result<&[u8]> {
+    let header = recording
+        .get(0..8)
+        .ok_or_else(|| invalid_data("Recording is too short"))?;
+    let version = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    if version > 1 {
+        return Err(invalid_data("Unknown recording version"));
+    }
+    Ok(recording[8..].get(..size).unwrap_or(&recording[8..]))
+}
+
+impl GameSnapshot {
+    /// Gets the score, period and clock at the end of a `.hrp` recording.
+    ///
+    /// Recordings that end abruptly are read up to the last complete tick.
+    pub fn from_recording(recording: &[u8]) -> io::Result<Self> {
+        let data = recording_data(recording)?;
+        let mut cursor = RecordingCursor::new();
+        let mut last = None;
+        while let Ok(Some(tick)) = cursor.read_tick(data) {
+            last = Some(GameSnapshot {
+                red_score: tick.red_score,
+                blue_score: tick.blue_score,
+                period: tick.period,
+                time: tick.time,
+            });
+        }
+        last.ok_or_else(|| invalid_data("Recording has no complete ticks"))
+    }
+}
+
+/// Plays a recorded game.
+pub struct ReplayServerGameMode {
+    data: Vec<u8>,
+    /// Saved positions in the recording, one every [`KEYFRAME_INTERVAL`] ticks.
+    keyframes: Vec<RecordingCursor>,
+    ticks: u32,
+    cursor: RecordingCursor,
+    paused: bool,
+    /// Bots that represent the recorded players, by recorded player index.
+    bots: Vec<Option<(PlayerId, Rc<str>)>>,
+    /// Objects of the last shown tick, in the object slots of the bots.
+    objects: [ObjectPacket; 32],
+}
+
+impl ReplayServerGameMode {
+    /// Creates the game mode from the contents of a `.hrp` file.
+    pub fn new(recording: &[u8]) -> io::Result<Self> {
+        let data = recording_data(recording)?.to_vec();
+
+        let mut keyframes = vec![];
+        let mut cursor = RecordingCursor::new();
+        loop {
+            if cursor.tick.is_multiple_of(KEYFRAME_INTERVAL) {
+                keyframes.push(cursor.clone());
+            }
+            if cursor.read_tick(&data)?.is_none() {
+                break;
+            }
+        }
+        let ticks = cursor.tick;
+        info!("Loaded recording with {} ticks", ticks);
+
+        Ok(Self {
+            data,
+            keyframes,
+            ticks,
+            cursor: RecordingCursor::new(),
+            paused: false,
+            bots: vec![None; 64],
+            objects: [const { ObjectPacket::None }; 32],
+        })
+    }
+
+    /// Shows the next tick of the recording.
+    fn show_next_tick(&mut self, mut server: ServerMut, show_messages: bool) {
+        let tick = match self.cursor.read_tick(&self.data) {
+            Ok(Some(tick)) => tick,
+            Ok(None) => {
+                self.paused = true;
+                server
+                    .players_mut()
+                    .add_server_chat_message("End of recording");
+                return;
+            }
+            Err(e) => {
+                warn!("Could not read recording: {}", e);
+                self.paused = true;
+                return;
+            }
+        };
+        self.update_bots(server.rb_mut());
+        self.objects = self.map_objects(server.rb());
+
+        let values = server.scoreboard_mut();
+        values.game_over = tick.game_over;
+        values.red_score = tick.red_score;
+        values.blue_score = tick.blue_score;
+        values.time = tick.time;
+        values.goal_message_timer = tick.goal_message_timer;
+        values.period = tick.period;
+
+        if !show_messages {
+            return;
+        }
+        for message in tick.messages {
+            match message {
+                RecordedMessage::Chat {
+                    player_index,
+                    message,
+                } => match player_index.and_then(|x| self.bot_id(x)) {
+                    Some(bot_id) => server.players_mut().add_user_chat_message(message, bot_id),
+                    None => server.players_mut().add_server_chat_message(message),
+                },
+                RecordedMessage::Goal {
+                    team,
+                    goal_player_index,
+                    assist_player_index,
+                } => {
+                    let goal_player = goal_player_index.and_then(|x| self.bot_id(x));
+                    let assist_player = assist_player_index.and_then(|x| self.bot_id(x));
+                    server
+                        .players_mut()
+                        .add_goal_message(team, goal_player, assist_player);
+                }
+            }
+        }
+    }
+
+    fn bot_id(&self, recorded_index: usize) -> Option<PlayerId> {
+        self.bots
+            .get(recorded_index)
+            .and_then(|x| x.as_ref())
+            .map(|(bot_id, _)| *bot_id)
+    }
+
+    /// Adds, removes and moves bots so that they match the recorded players.
+    fn update_bots(&mut self, mut server: ServerMut) {
+        for (recorded, bot) in self.cursor.players.iter().zip(self.bots.iter_mut()) {
+            if let Some((bot_id, name)) = bot {
+                if recorded.as_ref().is_none_or(|x| x.name != *name) {
+                    server.players_mut().remove_player(*bot_id);
+                    *bot = None;
+                }
+            }
+            let Some(recorded) = recorded else {
+                continue;
+            };
+            let bot_id = match bot {
+                Some((bot_id, _)) => *bot_id,
+                None => match server.players_mut().add_bot(&recorded.name) {
+                    Some(bot_id) => {
+                        *bot = Some((bot_id, recorded.name.clone()));
+                        bot_id
+                    }
+                    None => continue,
+                },
+            };
+            let team = server.players().get(bot_id).and_then(|x| x.team());
+            match recorded.object {
+                Some((_, recorded_team)) if team != Some(recorded_team) => {
+                    // The position doesn't matter, the skater is never simulated
+                    server.players_mut().spawn_skater(
+                        bot_id,
+                        recorded_team,
+                        Point3::origin(),
+                        Rotation3::identity(),
+                        false,
+                    );
+                }
+                None if team.is_some() => {
+                    server.players_mut().move_to_spectator(bot_id);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Moves the recorded skaters to the object slots of their bots, and the pucks to the remaining slots.
+    fn map_objects(&self, server: Server) -> [ObjectPacket; 32] {
+        let recorded_objects = self.cursor.objects();
+        let players = server.players();
+        let mut objects = [const { ObjectPacket::None }; 32];
+        for (recorded, bot) in self.cursor.players.iter().zip(self.bots.iter()) {
+            let (Some(recorded), Some((bot_id, _))) = (recorded, bot) else {
+                continue;
+            };
+            let Some((recorded_object_index, _)) = recorded.object else {
+                continue;
+            };
+            let Some(player) = players.get(*bot_id) else {
+                continue;
+            };
+            if let (Some((object_index, _, _)), Some(packet)) = (
+                &player.player.object,
+                recorded_objects.get(recorded_object_index),
+            ) {
+                objects[*object_index] = packet.clone();
+            }
+        }
+        for (i, packet) in recorded_objects.iter().enumerate() {
+            if let ObjectPacket::Puck(_) = packet {
+                let slot = if matches!(objects[i], ObjectPacket::None) {
+                    Some(i)
+                } else {
+                    objects.iter().position(|x| matches!(x, ObjectPacket::None))
+                };
+                if let Some(slot) = slot {
+                    objects[slot] = packet.clone();
+                }
+            }
+        }
+        objects
+    }
+
+    /// Moves to a tick in the recording.
+    fn seek(&mut self, mut server: ServerMut, tick: u32) {
+        let tick = tick.min(self.ticks.saturating_sub(1));
+        let keyframe = (tick / KEYFRAME_INTERVAL) as usize;
+        self.cursor = self.keyframes[keyframe.min(self.keyframes.len() - 1)].clone();
+        while self.cursor.tick < tick {
+            if !matches!(self.cursor.read_tick(&self.data), Ok(Some(_))) {
+                break;
+            }
+        }
+        self.show_next_tick(server.rb_mut(), false);
+    }
+
+    fn pause_replay(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let name = player.name();
+            info!("{} ({}) paused or resumed replay", name, player_id);
+            self.pause_replay_by(server, &name);
+        }
+    }
+
+    /// Pauses or resumes the replay on behalf of an administrator that has already been authenticated.
+    fn pause_replay_by(&mut self, mut server: ServerMut, admin_name: &str) {
+        self.paused = !self.paused;
+        let msg = if self.paused {
+            format!("Replay paused by {}", admin_name)
+        } else {
+            format!("Replay resumed by {}", admin_name)
+        };
+        server.players_mut().add_server_chat_message(msg);
+    }
+
+    fn seek_replay(&mut self, mut server: ServerMut, player_id: PlayerId, arg: &str) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let name = player.name();
+            match self.seek_replay_by(server.rb_mut(), &name, arg) {
+                Ok(()) => info!("{} ({}) moved replay to {}", name, player_id, arg),
+                Err(usage) => server
+                    .players_mut()
+                    .add_directed_server_chat_message(usage, player_id),
+            }
+        }
+    }
+
+    /// Moves the replay to a time given in seconds or as minutes:seconds, on behalf of an
+    /// administrator that has already been authenticated. Returns the usage if the time is invalid.
+    fn seek_replay_by(
+        &mut self,
+        mut server: ServerMut,
+        admin_name: &str,
+        arg: &str,
+    ) -> Result<(), &'static str> {
+        let seconds = match arg.split_once(':') {
+            Some((minutes, seconds)) => minutes
+                .parse::<u32>()
+                .ok()
+                .zip(seconds.parse::<u32>().ok())
+                .map(|(minutes, seconds)| minutes * 60 + seconds),
+            None => arg.parse::<u32>().ok(),
+        };
+        let Some(seconds) = seconds else {
+            return Err("Usage: /seek <seconds>");
+        };
+        self.seek(server.rb_mut(), seconds.saturating_mul(100));
+
+        let seconds = self.cursor.tick / 100;
+        let msg = format!(
+            "Replay moved to {}:{:02} by {}",
+            seconds / 60,
+            seconds % 60,
+            admin_name
+        );
+        server.players_mut().add_server_chat_message(msg);
+        Ok(())
+    }
+}
+
+const REPLAY_COMMANDS: &[ChatCommand] = &[
+    ChatCommand::new("pausereplay", "Pauses or resumes the playback").admin(),
+    ChatCommand::new("seek", "Jumps to a time in the recording, like 5:30")
+        .args(&[CommandArg::required("TIME", ArgKind::Word)])
+        .admin(),
+];
+
+impl GameMode for ReplayServerGameMode {
+    fn init(&mut self, mut server: ServerMut) {
+        for command in REPLAY_COMMANDS {
+            server.register_command(*command);
+        }
+    }
+
+    fn before_tick(&mut self, mut server: ServerMut) {
+        if !self.paused {
+            self.show_next_tick(server.rb_mut(), true);
+        }
+        // The tick is shown instead of a simulated one
+        server.replay_mut().add_tick_to_queue(self.objects.clone());
+    }
+
+    fn after_tick(&mut self, _server: ServerMut, _events: &[PhysicsEvent]) {}
+
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        match cmd {
+            "pausereplay" => self.pause_replay(server, player_id),
+            "seek" => self.seek_replay(server, player_id, arg),
+            _ => {}
+        }
+    }
+
+    fn handle_remote_command(
+        &mut self,
+        server: ServerMut,
+        cmd: &str,
+        arg: &str,
+        admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        match cmd {
+            "pausereplay" => {
+                self.pause_replay_by(server, admin_name);
+                Some(Ok(()))
+            }
+            "seek" => Some(self.seek_replay_by(server, admin_name, arg)),
+            _ => None,
+        }
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        InitialGameValues {
+            values: ScoreboardValues::default(),
+            puck_slots: 0,
+        }
+    }
+
+    fn game_started(&mut self, mut server: ServerMut) {
+        // Start from the beginning when the first viewer joins
+        server.players_mut().remove_bots();
+        self.bots = vec![None; 64];
+        self.cursor = RecordingCursor::new();
+        self.paused = false;
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        0
+    }
+}
@@ -0,0 +1,166 @@
+//! Preferred faceoff positions, chosen with /sp.
+//!
+//! Positions are kept by account for players with a verified identity, and by name for everyone else, so that
+//! players keep their position when they reconnect. If a position file is configured, the positions are saved
+//! to it whenever they change, one player per line as `<position> <key>`, where the key is `@account` or the
+//! player name. Names that start with `@` or `\` are written with a `\` in front, so that they can't be
+//! mistaken for accounts. Only the [`MAX_POSITIONS`] most recently chosen positions are kept.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use tokio::task::JoinHandle;
+
+use crate::file_writer::FileWriter;
+use crate::gamemode::match_util::ALLOWED_POSITIONS;
+use crate::gamemode::ServerPlayer;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum PositionKey {
+    Account(String),
+    Name(String),
+}
+
+impl PositionKey {
+    fn new(player: &ServerPlayer) -> Self {
+        match player.identity() {
+            Some(identity) => PositionKey::Account(identity.account.clone()),
+            None => PositionKey::Name(player.name().to_string()),
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        if let Some(name) = s.strip_prefix('\\') {
+            PositionKey::Name(name.to_owned())
+        } else if let Some(account) = s.strip_prefix('@') {
+            PositionKey::Account(account.to_owned())
+        } else {
+            PositionKey::Name(s.to_owned())
+        }
+    }
+
+    fn write(&self, s: &mut String) {
+        let _ = match self {
+            PositionKey::Account(account) => write!(s, "@{}", account),
+            PositionKey::Name(name) if name.starts_with(['@', '\\']) => write!(s, "\\{}", name),
+            PositionKey::Name(name) => write!(s, "{}", name),
+        };
+    }
+}
+
+/// The number of positions that are kept. When a new player chooses a position, the position that was
+/// chosen longest ago is forgotten.
+pub(crate) const MAX_POSITIONS: usize = 10000;
+
+#[derive(Default)]
+pub(crate) struct PreferredPositions {
+    file: Option<FileWriter>,
+    /// The position of each player, and when it was chosen, counted in positions set since the server started.
+    positions: HashMap<PositionKey, (&'static str, u64)>,
+    counter: u64,
+}
+
+impl PreferredPositions {
+    /// Creates the list of positions, and loads the positions in `file` if it exists.
+    pub(crate) fn new(file: Option<PathBuf>) -> Self {
+        let mut res = Self::default();
+        if let Some(path) = &file {
+            if let Ok(s) = std::fs::read_to_string(path) {
+                // The file lists the positions from the oldest to the newest
+                for (key, position) in s.lines().filter_map(parse_line) {
+                    res.insert(key, position);
+                }
+            }
+        }
+        res.file = file.map(|path| FileWriter::new(path, "position file"));
+        res
+    }
+
+    pub(crate) fn get(&self, player: &ServerPlayer) -> Option<&'static str> {
+        self.positions
+            .get(&PositionKey::new(player))
+            .map(|(position, _)| *position)
+    }
+
+    pub(crate) fn set(&mut self, player: &ServerPlayer, position: &'static str) {
+        if self.insert(PositionKey::new(player), position) != Some(position) {
+            self.save();
+        }
+    }
+
+    fn insert(&mut self, key: PositionKey, position: &'static str) -> Option<&'static str> {
+        self.counter += 1;
+        let old = self.positions.insert(key, (position, self.counter));
+        if self.positions.len() > MAX_POSITIONS {
+            let oldest = self
+                .positions
+                .iter()
+                .min_by_key(|(_, (_, set))| *set)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.positions.remove(&oldest);
+            }
+        }
+        old.map(|(position, _)| position)
+    }
+
+    fn save(&mut self) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let mut positions: Vec<_> = self.positions.iter().collect();
+        positions.sort_by_key(|(_, (_, set))| *set);
+        let mut s = String::new();
+        for (key, (position, _)) in positions {
+            let _ = write!(s, "{} ", position);
+            key.write(&mut s);
+            s.push('\n');
+        }
+        file.write(s);
+    }
+
+    /// Returns the save that is still being written, if any.
+    pub(crate) fn take_pending_save(&mut self) -> Option<JoinHandle<()>> {
+        self.file.as_mut()?.take_pending_save()
+    }
+}
+
+fn parse_line(line: &str) -> Option<(PositionKey, &'static str)> {
+    // Names can contain spaces, so the name is the rest of the line
+    let (position, key) = line.split_once(' ')?;
+    let position = ALLOWED_POSITIONS.into_iter().find(|x| *x == position)?;
+    Some((PositionKey::parse(key), position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_line, PositionKey};
+
+    #[test]
+    fn position_lines() {
+        assert_eq!(
+            parse_line("LD @alice"),
+            Some((PositionKey::Account("alice".to_owned()), "LD"))
+        );
+        assert_eq!(
+            parse_line("G Bob the goalie"),
+            Some((PositionKey::Name("Bob the goalie".to_owned()), "G"))
+        );
+        assert_eq!(
+            parse_line("RW \\@alice"),
+            Some((PositionKey::Name("@alice".to_owned()), "RW"))
+        );
+        for key in [
+            PositionKey::Account("alice".to_owned()),
+            PositionKey::Name("@alice".to_owned()),
+            PositionKey::Name("\\bob".to_owned()),
+        ] {
+            let mut s = String::new();
+            key.write(&mut s);
+            assert_eq!(PositionKey::parse(&s), key);
+        }
+        assert_eq!(parse_line("XYZ Carol"), None);
+        assert_eq!(parse_line("C"), None);
+    }
+}
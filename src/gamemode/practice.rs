@@ -0,0 +1,365 @@
+//! Practice, with no clock and no rules.
+//!
+//! Players can drop pucks wherever they like with /puck and /puckhere, and teleport to the faceoff dots and
+//! the nets with /tp. There is room for a fixed number of pucks, and when it's full, the oldest puck of the
+//! player is removed to make room for a new one.
+
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+
+use nalgebra::{Point3, Rotation3, Vector3};
+use reborrow::ReborrowMut;
+use tracing::info;
+
+use crate::commands::{ArgKind, ChatCommand, CommandArg};
+use crate::game::{PhysicsEvent, PlayerId, Puck, Rink, Team, OBJECT_SLOTS};
+use crate::gamemode::match_util::{get_faceoff_spot, RinkFaceoffSpot, RinkSide};
+use crate::gamemode::util::{
+    add_players, get_spawnpoint, msg_puck_speed, msg_speed, SpawnPoint, SPEED_COMMANDS,
+};
+use crate::gamemode::{GameMode, InitialGameValues, PuckExt, ServerMut, ServerMutParts};
+
+/// Places players can teleport to with /tp.
+const SPOTS: &[&str] = &[
+    "center",
+    "red",
+    "blue",
+    "redleft",
+    "redright",
+    "blueleft",
+    "blueright",
+];
+
+pub struct PracticeGameMode {
+    max_pucks: usize,
+    spawn_point: SpawnPoint,
+    team_switch_timer: HashMap<PlayerId, u32>,
+    /// Slots of the pucks on the ice and the players who dropped them, oldest first.
+    pucks: VecDeque<(usize, Option<PlayerId>)>,
+    use_mph: bool,
+}
+
+impl PracticeGameMode {
+    /// `max_pucks` is the number of pucks that can be on the ice at the same time. At least one of the
+    /// [`OBJECT_SLOTS`] is always left for the skaters.
+    pub fn new(max_pucks: usize, spawn_point: SpawnPoint) -> Self {
+        PracticeGameMode {
+            max_pucks: max_pucks.clamp(1, OBJECT_SLOTS - 1),
+            spawn_point,
+            team_switch_timer: Default::default(),
+            pucks: Default::default(),
+            use_mph: false,
+        }
+    }
+
+    /// Makes /speed and /puckspeed show speeds in miles per hour instead of km/h.
+    pub fn with_mph(mut self, use_mph: bool) -> Self {
+        self.use_mph = use_mph;
+        self
+    }
+
+    /// Creates a practice mode where pucks can use all object slots that are not needed for the skaters
+    /// of `player_max` players.
+    pub fn with_room_for_players(player_max: usize, spawn_point: SpawnPoint) -> Self {
+        Self::new(OBJECT_SLOTS.saturating_sub(player_max), spawn_point)
+    }
+
+    /// Spawns a puck dropped by `owner`. If there is no room for it, the oldest puck of the owner is removed
+    /// first, and if the owner has no pucks, it's not spawned. Returns false if it wasn't spawned.
+    fn spawn_puck(
+        &mut self,
+        mut server: ServerMut,
+        pos: Point3<f32>,
+        owner: Option<PlayerId>,
+    ) -> bool {
+        let pucks = server.pucks_mut();
+        if pucks.iter().all(|puck| puck.is_some()) {
+            let Some(oldest) = self.pucks.iter().position(|(_, x)| *x == owner) else {
+                return false;
+            };
+            if let Some((slot, _)) = self.pucks.remove(oldest) {
+                pucks[slot] = None;
+            }
+        }
+        match pucks.spawn_puck(Puck::new(pos, Rotation3::identity())) {
+            Some(i) => {
+                self.pucks.push_back((i, owner));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn spawn_player_puck(&mut self, mut server: ServerMut, pos: Point3<f32>, player_id: PlayerId) {
+        if !self.spawn_puck(server.rb_mut(), pos, Some(player_id)) {
+            server
+                .players_mut()
+                .add_directed_server_chat_message("There is no room for more pucks", player_id);
+        }
+    }
+
+    /// Handles /puck, which drops a puck in front of the player.
+    fn puck_command(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        let players = server.players();
+        let pos = players
+            .get(player_id)
+            .as_ref()
+            .and_then(|player| player.skater())
+            .map(|(_, skater)| {
+                let mut forward = skater.body.rot * -Vector3::z();
+                forward.y = 0.0;
+                let pos = skater.body.pos + 1.0 * forward.normalize();
+                Point3::new(pos.x, 0.5, pos.z)
+            });
+        match pos {
+            Some(pos) => self.spawn_player_puck(server, pos, player_id),
+            None => server.players_mut().add_directed_server_chat_message(
+                "You must be on the ice to drop a puck",
+                player_id,
+            ),
+        }
+    }
+
+    /// Handles /puckhere, which drops a puck at the given coordinates.
+    fn puck_here_command(&mut self, mut server: ServerMut, arg: &str, player_id: PlayerId) {
+        let coords = arg
+            .split_whitespace()
+            .map(|x| x.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>();
+        let rink = server.rink();
+        let pos = match coords.as_deref() {
+            Ok(&[x, y, z])
+                if (0.0..=rink.width).contains(&x)
+                    && (0.0..=10.0).contains(&y)
+                    && (0.0..=rink.length).contains(&z) =>
+            {
+                Point3::new(x, y, z)
+            }
+            _ => {
+                let msg = format!(
+                    "Usage: /puckhere X Y Z, with X from 0 to {}, Y from 0 to 10 and Z from 0 to {}",
+                    rink.width, rink.length
+                );
+                server
+                    .players_mut()
+                    .add_directed_server_chat_message(msg, player_id);
+                return;
+            }
+        };
+        self.spawn_player_puck(server, pos, player_id);
+    }
+
+    /// Handles /clearpucks, which removes the pucks the player has dropped, or all pucks if the player is an admin.
+    fn clear_pucks(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        let Some((name, is_admin)) = server
+            .players()
+            .get(player_id)
+            .map(|player| (player.name(), player.is_admin()))
+        else {
+            return;
+        };
+        if is_admin {
+            self.pucks.clear();
+            server.pucks_mut().remove_all_pucks();
+            info!("{} ({}) cleared pucks", name, player_id);
+            let msg = format!("Pucks cleared by {}", name);
+            server.players_mut().add_server_chat_message(msg);
+        } else {
+            let pucks = server.pucks_mut();
+            self.pucks.retain(|&(slot, owner)| {
+                let own = owner == Some(player_id);
+                if own {
+                    pucks[slot] = None;
+                }
+                !own
+            });
+            server
+                .players_mut()
+                .add_directed_server_chat_message("Your pucks were removed", player_id);
+        }
+    }
+
+    /// Handles /tp, which moves the player to one of the [`SPOTS`].
+    fn teleport(&mut self, mut server: ServerMut, arg: &str, player_id: PlayerId) {
+        let team = server
+            .players()
+            .get(player_id)
+            .and_then(|player| player.skater().map(|(team, _)| team));
+        let Some(team) = team else {
+            server
+                .players_mut()
+                .add_directed_server_chat_message("You must be on the ice to teleport", player_id);
+            return;
+        };
+        let Some((pos, rot)) = get_spot(server.rink(), arg.trim(), team) else {
+            let msg = format!("Usage: /tp {}", SPOTS.join("|"));
+            server
+                .players_mut()
+                .add_directed_server_chat_message(msg, player_id);
+            return;
+        };
+        server
+            .players_mut()
+            .spawn_skater(player_id, team, pos, rot, true);
+    }
+
+    fn update_players(&mut self, mut server: ServerMut) {
+        let spawn_point = self.spawn_point;
+        let ServerMutParts {
+            players,
+            rink,
+            config,
+            ..
+        } = server.as_mut_parts();
+        let rink = &*rink;
+        add_players(
+            players,
+            usize::MAX,
+            &mut self.team_switch_timer,
+            None,
+            &config.team_names,
+            |team, _| get_spawnpoint(rink, team, spawn_point),
+            |_, _| Ok(()),
+            |_| {},
+            |_, _| {},
+        );
+    }
+}
+
+/// Returns the position and rotation of a spot, or [`None`] if there is no spot with that name.
+///
+/// At the nets, players face away from the net, and at the zone faceoff dots they face the net.
+/// Left and right are seen from center ice.
+fn get_spot(rink: &Rink, spot: &str, team: Team) -> Option<(Point3<f32>, Rotation3<f32>)> {
+    let altitude = 1.5;
+    // Red defends the end with the larger Z
+    let towards_blue_net = Rotation3::identity();
+    let towards_red_net = Rotation3::from_euler_angles(0.0, PI, 0.0);
+    let zone_dot = |team, side| {
+        let pos = get_faceoff_spot(
+            rink,
+            RinkFaceoffSpot::DefensiveZone(team, side),
+            0.0,
+            altitude,
+        )
+        .center_position;
+        Point3::new(pos.x, altitude, pos.z)
+    };
+    let (pos, rot) = match spot {
+        "center" => {
+            let rot = match team {
+                Team::Red => towards_blue_net,
+                Team::Blue => towards_red_net,
+            };
+            (
+                Point3::new(rink.width / 2.0, altitude, rink.length / 2.0),
+                rot,
+            )
+        }
+        "red" => (
+            Point3::new(rink.width / 2.0, altitude, rink.length - 5.0),
+            towards_blue_net,
+        ),
+        "blue" => (
+            Point3::new(rink.width / 2.0, altitude, 5.0),
+            towards_red_net,
+        ),
+        "redleft" => (zone_dot(Team::Red, RinkSide::HigherHalfZ), towards_red_net),
+        "redright" => (zone_dot(Team::Red, RinkSide::LowerHalfZ), towards_red_net),
+        "blueleft" => (zone_dot(Team::Blue, RinkSide::LowerHalfZ), towards_blue_net),
+        "blueright" => (
+            zone_dot(Team::Blue, RinkSide::HigherHalfZ),
+            towards_blue_net,
+        ),
+        _ => return None,
+    };
+    Some((pos, rot))
+}
+
+const PRACTICE_COMMANDS: &[ChatCommand] = &[
+    ChatCommand::new("puck", "Drops a puck in front of you"),
+    ChatCommand::new("puckhere", "Drops a puck at the given position").args(&[
+        CommandArg::required("X", ArgKind::Word),
+        CommandArg::required("Y", ArgKind::Word),
+        CommandArg::required("Z", ArgKind::Word),
+    ]),
+    ChatCommand::new(
+        "tp",
+        "Teleports you to center, a net (red/blue) or a zone faceoff dot, like redleft",
+    )
+    .args(&[CommandArg::required("SPOT", ArgKind::Word)]),
+    ChatCommand::new("clearpucks", "Removes your pucks, or all pucks for admins"),
+];
+
+impl GameMode for PracticeGameMode {
+    fn init(&mut self, mut server: ServerMut) {
+        for command in PRACTICE_COMMANDS.iter().chain(SPEED_COMMANDS) {
+            server.register_command(*command);
+        }
+    }
+
+    fn before_tick(&mut self, server: ServerMut) {
+        self.update_players(server);
+    }
+
+    fn after_tick(&mut self, _server: ServerMut, _events: &[PhysicsEvent]) {}
+
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        match cmd {
+            "puck" => {
+                self.puck_command(server, player_id);
+            }
+            "puckhere" => {
+                self.puck_here_command(server, arg, player_id);
+            }
+            "tp" => {
+                self.teleport(server, arg, player_id);
+            }
+            "clearpucks" => {
+                self.clear_pucks(server, player_id);
+            }
+            "speed" => {
+                msg_speed(server, player_id, self.use_mph);
+            }
+            "puckspeed" => {
+                msg_puck_speed(server, player_id, None, self.use_mph);
+            }
+            _ => {}
+        }
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        InitialGameValues {
+            values: Default::default(),
+            puck_slots: self.max_pucks,
+        }
+    }
+
+    fn game_started(&mut self, server: ServerMut) {
+        self.pucks.clear();
+        let rink = server.rink();
+        let pos = Point3::new(rink.width / 2.0, 1.5, rink.length / 2.0);
+        self.spawn_puck(server, pos, None);
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_spot, SPOTS};
+    use crate::game::{Rink, Team};
+
+    #[test]
+    fn spots_on_the_ice() {
+        let rink = Rink::new(30.0, 61.0, 8.5);
+        for spot in SPOTS {
+            let (pos, _) = get_spot(&rink, spot, Team::Red).unwrap();
+            assert!(pos.x > 0.0 && pos.x < rink.width, "{}", spot);
+            assert!(pos.z > 0.0 && pos.z < rink.length, "{}", spot);
+        }
+        assert!(get_spot(&rink, "nowhere", Team::Red).is_none());
+    }
+}
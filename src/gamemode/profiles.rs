@@ -0,0 +1,249 @@
+use reborrow::ReborrowMut;
+use tracing::info;
+
+use crate::commands::{ArgKind, ChatCommand, CommandArg};
+use crate::game::{PhysicsConfiguration, PhysicsEvent, PlayerId, ScoreboardDisplay};
+use crate::gamemode::{ExitReason, GameMode, InitialGameValues, Server, ServerMut};
+use crate::votes::VoteHandler;
+use tokio::task::JoinHandle;
+
+const PROFILE_COMMAND: ChatCommand = ChatCommand::new(
+    "profile",
+    "Lists the profiles, or switches to a profile from the next game",
+)
+.args(&[CommandArg::optional("NAME", ArgKind::Word)]);
+
+/// A named set of rules and physics settings.
+pub struct Profile {
+    pub name: String,
+    /// The game mode with the rules of the profile.
+    pub game_mode: Box<dyn GameMode>,
+    pub physics: PhysicsConfiguration,
+}
+
+/// Game mode that switches between named configuration profiles.
+///
+/// Admins select a profile with `/profile NAME`, and it is applied when the next game starts.
+/// `/profile` lists the profiles, with the current profile marked with brackets.
+pub struct ProfileGameMode {
+    profiles: Vec<Profile>,
+    current: usize,
+    next: Option<usize>,
+}
+
+impl ProfileGameMode {
+    /// Creates a new profile game mode, starting with the first profile.
+    /// Returns [`None`] if `profiles` is empty.
+    pub fn new(profiles: Vec<Profile>) -> Option<Self> {
+        if profiles.is_empty() {
+            return None;
+        }
+        Some(ProfileGameMode {
+            profiles,
+            current: 0,
+            next: None,
+        })
+    }
+
+    fn current_mode(&self) -> &dyn GameMode {
+        self.profiles[self.current].game_mode.as_ref()
+    }
+
+    fn current_mode_mut(&mut self) -> &mut dyn GameMode {
+        self.profiles[self.current].game_mode.as_mut()
+    }
+
+    fn show_profiles(&self, mut server: ServerMut, player_id: PlayerId) {
+        let list = self
+            .profiles
+            .iter()
+            .enumerate()
+            .map(|(i, profile)| {
+                if i == self.current {
+                    format!("[{}]", profile.name)
+                } else {
+                    profile.name.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let msg = format!("Profiles: {}", list);
+        server
+            .players_mut()
+            .add_directed_server_chat_message(msg, player_id);
+    }
+
+    fn select_profile(&mut self, mut server: ServerMut, name: &str, player_id: PlayerId) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let player_name = player.name();
+            let Some(index) = self
+                .profiles
+                .iter()
+                .position(|profile| profile.name.eq_ignore_ascii_case(name))
+            else {
+                server
+                    .players_mut()
+                    .add_directed_server_chat_message("Unknown profile", player_id);
+                return;
+            };
+            let profile_name = &self.profiles[index].name;
+            info!(
+                "{} ({}) selected profile {}",
+                player_name, player_id, profile_name
+            );
+            let msg = format!(
+                "Profile {} will be used from the next game, selected by {}",
+                profile_name, player_name
+            );
+            server.players_mut().add_server_chat_message(msg);
+            self.next = Some(index);
+        }
+    }
+
+    /// Switches to the profile `index` in a game that has just started, and starts the game in its game mode.
+    fn switch_profile(&mut self, mut server: ServerMut, index: usize) {
+        let on_ice = server
+            .players()
+            .iter()
+            .filter(|player| player.team().is_some())
+            .map(|player| player.id)
+            .collect::<Vec<_>>();
+        for player_id in on_ice {
+            server.players_mut().move_to_spectator(player_id);
+        }
+
+        self.current = index;
+        let profile = &mut self.profiles[index];
+        info!("Switching to profile {}", profile.name);
+
+        *server.physics_config_mut() = profile.physics.clone();
+        server.replace_game_values(profile.game_mode.get_initial_game_values());
+        server.clear_commands();
+        server.register_command(PROFILE_COMMAND);
+        profile.game_mode.init(server.rb_mut());
+
+        let msg = format!("Profile changed to {}", profile.name);
+        server.players_mut().add_server_chat_message(msg);
+        profile.game_mode.game_started(server);
+    }
+}
+
+impl GameMode for ProfileGameMode {
+    fn init(&mut self, mut server: ServerMut) {
+        *server.physics_config_mut() = self.profiles[self.current].physics.clone();
+        server.register_command(PROFILE_COMMAND);
+        self.current_mode_mut().init(server);
+    }
+
+    fn before_tick(&mut self, server: ServerMut) {
+        self.current_mode_mut().before_tick(server);
+    }
+
+    fn after_tick(&mut self, server: ServerMut, events: &[PhysicsEvent]) {
+        self.current_mode_mut().after_tick(server, events);
+    }
+
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        match cmd {
+            "profile" => {
+                if arg.is_empty() {
+                    self.show_profiles(server, player_id);
+                } else {
+                    self.select_profile(server, arg.trim(), player_id);
+                }
+            }
+            _ => self
+                .current_mode_mut()
+                .handle_command(server, cmd, arg, player_id),
+        }
+    }
+
+    fn handle_remote_command(
+        &mut self,
+        server: ServerMut,
+        cmd: &str,
+        arg: &str,
+        admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        self.current_mode_mut()
+            .handle_remote_command(server, cmd, arg, admin_name)
+    }
+
+    fn before_chat_message(
+        &mut self,
+        server: ServerMut,
+        player_id: PlayerId,
+        message: &mut String,
+    ) -> bool {
+        self.current_mode_mut()
+            .before_chat_message(server, player_id, message)
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        self.current_mode_mut().get_initial_game_values()
+    }
+
+    fn game_started(&mut self, server: ServerMut) {
+        // The new profile takes over the game that was just started
+        if let Some(index) = self.next.take() {
+            self.switch_profile(server, index);
+        } else {
+            self.current_mode_mut().game_started(server);
+        }
+    }
+
+    fn before_player_exit(
+        &mut self,
+        mut server: ServerMut,
+        player_id: PlayerId,
+        reason: ExitReason,
+    ) {
+        // Inactive profiles may also have data about the player
+        for (i, profile) in self.profiles.iter_mut().enumerate() {
+            if i != self.current {
+                profile.game_mode.before_player_exit(
+                    server.rb_mut(),
+                    player_id,
+                    ExitReason::Disconnected,
+                );
+            }
+        }
+        self.current_mode_mut()
+            .before_player_exit(server, player_id, reason);
+    }
+
+    fn after_player_join(&mut self, server: ServerMut, player_id: PlayerId) {
+        self.current_mode_mut().after_player_join(server, player_id);
+    }
+
+    fn on_player_afk(&mut self, server: ServerMut, player_id: PlayerId) {
+        self.current_mode_mut().on_player_afk(server, player_id);
+    }
+
+    fn hold_warmup(&mut self, server: ServerMut) {
+        self.current_mode_mut().hold_warmup(server);
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        self.current_mode().server_list_team_size()
+    }
+
+    fn include_tick_in_recording(&self, server: Server) -> bool {
+        self.current_mode().include_tick_in_recording(server)
+    }
+
+    fn scoreboard_display(&self, server: Server, display: &mut ScoreboardDisplay) {
+        self.current_mode().scoreboard_display(server, display)
+    }
+
+    fn vote_handler(&mut self) -> Option<&mut dyn VoteHandler> {
+        self.current_mode_mut().vote_handler()
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.profiles
+            .iter_mut()
+            .flat_map(|profile| profile.game_mode.take_pending_saves())
+            .collect()
+    }
+}
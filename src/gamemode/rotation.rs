@@ -0,0 +1,369 @@
+use reborrow::ReborrowMut;
+use tracing::info;
+
+use crate::commands::{wrap_chat_lines, ArgKind, ChatCommand, CommandArg};
+use crate::game::{PhysicsEvent, PlayerId, ScoreboardDisplay};
+use crate::gamemode::{ExitReason, GameMode, InitialGameValues, Server, ServerMut};
+use crate::votes::VoteHandler;
+use tokio::task::JoinHandle;
+
+const ROTATION_COMMAND: ChatCommand = ChatCommand::new(
+    "rotation",
+    "Shows the rotation. Admins can use add MODE, remove N and next",
+)
+.args(&[CommandArg::optional("ACTION", ArgKind::Text)]);
+
+const MODE_COMMAND: ChatCommand = ChatCommand::new(
+    "mode",
+    "Ends the current game and starts a new game in another game mode",
+)
+.args(&[CommandArg::required("MODE", ArgKind::Word)])
+.admin();
+
+/// Game mode that switches between other game modes.
+///
+/// When a game ends, the next game mode in the rotation list is started.
+/// The rotation can be viewed and changed in-game with the `/rotation` command, and admins can switch to
+/// any game mode right away with `/mode`.
+pub struct RotationGameMode {
+    modes: Vec<(String, Box<dyn GameMode>)>,
+    rotation: Vec<usize>,
+    position: usize,
+    current: usize,
+}
+
+impl RotationGameMode {
+    /// Creates a new rotation.
+    ///
+    /// `modes` contains all game modes that can be part of the rotation, by name, and
+    /// `rotation` contains the names of the game modes in the order they will be played.
+    /// Returns [`None`] if the rotation is empty or contains a name that is not in `modes`.
+    pub fn new(modes: Vec<(String, Box<dyn GameMode>)>, rotation: &[&str]) -> Option<Self> {
+        let rotation = rotation
+            .iter()
+            .map(|name| modes.iter().position(|(mode_name, _)| mode_name == name))
+            .collect::<Option<Vec<_>>>()?;
+        let current = *rotation.first()?;
+        Some(RotationGameMode {
+            modes,
+            rotation,
+            position: 0,
+            current,
+        })
+    }
+
+    fn current_mode(&self) -> &dyn GameMode {
+        self.modes[self.current].1.as_ref()
+    }
+
+    fn current_mode_mut(&mut self) -> &mut dyn GameMode {
+        self.modes[self.current].1.as_mut()
+    }
+
+    fn find_mode(&self, name: &str) -> Option<usize> {
+        self.modes
+            .iter()
+            .position(|(mode_name, _)| mode_name.eq_ignore_ascii_case(name))
+    }
+
+    /// Switches to the next game mode in the rotation.
+    ///
+    /// `game_started` is true if the current game mode has just started a new game, which is then
+    /// reused by the next game mode.
+    fn advance(&mut self, server: ServerMut, game_started: bool) {
+        self.position = (self.position + 1) % self.rotation.len();
+        let next = self.rotation[self.position];
+        // With the same game mode, there is nothing to switch
+        if next != self.current {
+            self.switch_mode(server, next, game_started);
+        }
+    }
+
+    fn switch_mode(&mut self, mut server: ServerMut, index: usize, game_started: bool) {
+        let on_ice = server
+            .players()
+            .iter()
+            .filter(|player| player.team().is_some())
+            .map(|player| player.id)
+            .collect::<Vec<_>>();
+        for player_id in on_ice {
+            server.players_mut().move_to_spectator(player_id);
+        }
+
+        self.current = index;
+        let name = self.modes[index].0.clone();
+        info!("Switching game mode to {}", name);
+
+        server.replay_mut().set_history_length(0);
+        let mode = self.current_mode_mut();
+        if game_started {
+            server.replace_game_values(mode.get_initial_game_values());
+        } else {
+            server.new_game(mode.get_initial_game_values());
+        }
+        server.clear_commands();
+        server.register_command(ROTATION_COMMAND);
+        server.register_command(MODE_COMMAND);
+        mode.init(server.rb_mut());
+
+        let msg = format!("Game mode changed to {}", name);
+        server.players_mut().add_server_chat_message(msg);
+    }
+
+    /// Runs a hook of the current game mode, and advances the rotation if the game mode started a new game
+    /// because the previous one was over.
+    fn run_current<F: FnOnce(&mut dyn GameMode, ServerMut)>(
+        &mut self,
+        mut server: ServerMut,
+        f: F,
+    ) {
+        let was_game_over = server.scoreboard().game_over;
+        f(self.current_mode_mut(), server.rb_mut());
+        if was_game_over && !server.scoreboard().game_over {
+            self.advance(server, true);
+        }
+    }
+
+    fn show_rotation(&self, mut server: ServerMut, player_id: PlayerId) {
+        let list = self
+            .rotation
+            .iter()
+            .enumerate()
+            .map(|(i, index)| {
+                let name = &self.modes[*index].0;
+                if i == self.position {
+                    format!("{}: [{}]", i + 1, name)
+                } else {
+                    format!("{}: {}", i + 1, name)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let msg = format!("Rotation: {}", list);
+        server
+            .players_mut()
+            .add_directed_server_chat_message(msg, player_id);
+    }
+
+    fn add_to_rotation(&mut self, mut server: ServerMut, name: &str, player_id: PlayerId) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let player_name = player.name();
+            if let Some(index) = self.find_mode(name) {
+                self.rotation.push(index);
+                let mode_name = &self.modes[index].0;
+                info!(
+                    "{} ({}) added {} to the rotation",
+                    player_name, player_id, mode_name
+                );
+                let msg = format!("{} added to the rotation by {}", mode_name, player_name);
+                server.players_mut().add_server_chat_message(msg);
+            } else {
+                self.show_unknown_mode(server, player_id);
+            }
+        }
+    }
+
+    fn show_unknown_mode(&self, mut server: ServerMut, player_id: PlayerId) {
+        let names = self
+            .modes
+            .iter()
+            .map(|(mode_name, _)| mode_name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let msg = format!("Unknown game mode, available modes are {}", names);
+        for line in wrap_chat_lines(&msg) {
+            server
+                .players_mut()
+                .add_directed_server_chat_message(line, player_id);
+        }
+    }
+
+    /// Ends the current game and starts a new one in the game mode `name`.
+    ///
+    /// With a rotation of several game modes, the rotation goes on from where it was when the game ends.
+    /// Otherwise, the new game mode replaces the old one.
+    fn set_mode(&mut self, mut server: ServerMut, name: &str, player_id: PlayerId) {
+        let Some(player_name) = server
+            .players_mut()
+            .check_admin_or_deny(player_id)
+            .map(|player| player.name())
+        else {
+            return;
+        };
+        let Some(index) = self.find_mode(name) else {
+            self.show_unknown_mode(server, player_id);
+            return;
+        };
+        info!(
+            "{} ({}) changed the game mode to {}",
+            player_name, player_id, self.modes[index].0
+        );
+        if self.rotation.len() == 1 {
+            self.rotation[0] = index;
+        }
+        self.switch_mode(server, index, false);
+    }
+
+    fn remove_from_rotation(
+        &mut self,
+        mut server: ServerMut,
+        position: usize,
+        player_id: PlayerId,
+    ) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let player_name = player.name();
+            if self.rotation.len() <= 1 {
+                server.players_mut().add_directed_server_chat_message(
+                    "The rotation must contain at least one game mode",
+                    player_id,
+                );
+            } else if position >= 1 && position <= self.rotation.len() {
+                let i = position - 1;
+                let index = self.rotation.remove(i);
+                if i < self.position {
+                    self.position -= 1;
+                } else if i == self.position {
+                    // Make the entry after the removed one the next one
+                    self.position = (i + self.rotation.len() - 1) % self.rotation.len();
+                }
+                let mode_name = &self.modes[index].0;
+                info!(
+                    "{} ({}) removed {} from the rotation",
+                    player_name, player_id, mode_name
+                );
+                let msg = format!("{} removed from the rotation by {}", mode_name, player_name);
+                server.players_mut().add_server_chat_message(msg);
+            }
+        }
+    }
+
+    fn skip(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let player_name = player.name();
+            info!(
+                "{} ({}) skipped to the next game mode",
+                player_name, player_id
+            );
+            self.advance(server, false);
+        }
+    }
+}
+
+impl GameMode for RotationGameMode {
+    fn init(&mut self, mut server: ServerMut) {
+        server.register_command(ROTATION_COMMAND);
+        server.register_command(MODE_COMMAND);
+        self.current_mode_mut().init(server);
+    }
+
+    fn before_tick(&mut self, server: ServerMut) {
+        self.run_current(server, |mode, server| mode.before_tick(server));
+    }
+
+    fn after_tick(&mut self, server: ServerMut, events: &[PhysicsEvent]) {
+        self.run_current(server, |mode, server| mode.after_tick(server, events));
+    }
+
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        match cmd {
+            "rotation" => {
+                let args = arg.split_whitespace().collect::<Vec<&str>>();
+                match args.as_slice() {
+                    [] => self.show_rotation(server, player_id),
+                    ["add", name] => self.add_to_rotation(server, name, player_id),
+                    ["remove", position] => {
+                        if let Ok(position) = position.parse::<usize>() {
+                            self.remove_from_rotation(server, position, player_id);
+                        }
+                    }
+                    ["next"] => self.skip(server, player_id),
+                    _ => {}
+                }
+            }
+            "mode" => self.set_mode(server, arg.trim(), player_id),
+            _ => self
+                .current_mode_mut()
+                .handle_command(server, cmd, arg, player_id),
+        }
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        self.current_mode_mut().get_initial_game_values()
+    }
+
+    fn game_started(&mut self, server: ServerMut) {
+        self.current_mode_mut().game_started(server);
+    }
+
+    fn before_player_exit(
+        &mut self,
+        mut server: ServerMut,
+        player_id: PlayerId,
+        reason: ExitReason,
+    ) {
+        // Inactive game modes may also have data about the player
+        for (i, (_, mode)) in self.modes.iter_mut().enumerate() {
+            if i != self.current {
+                mode.before_player_exit(server.rb_mut(), player_id, ExitReason::Disconnected);
+            }
+        }
+        self.current_mode_mut()
+            .before_player_exit(server, player_id, reason);
+    }
+
+    fn after_player_join(&mut self, server: ServerMut, player_id: PlayerId) {
+        self.current_mode_mut().after_player_join(server, player_id);
+    }
+
+    fn on_player_afk(&mut self, server: ServerMut, player_id: PlayerId) {
+        self.current_mode_mut().on_player_afk(server, player_id);
+    }
+
+    fn handle_remote_command(
+        &mut self,
+        server: ServerMut,
+        cmd: &str,
+        arg: &str,
+        admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        self.current_mode_mut()
+            .handle_remote_command(server, cmd, arg, admin_name)
+    }
+
+    fn before_chat_message(
+        &mut self,
+        server: ServerMut,
+        player_id: PlayerId,
+        message: &mut String,
+    ) -> bool {
+        self.current_mode_mut()
+            .before_chat_message(server, player_id, message)
+    }
+
+    fn hold_warmup(&mut self, server: ServerMut) {
+        self.current_mode_mut().hold_warmup(server);
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        self.current_mode().server_list_team_size()
+    }
+
+    fn include_tick_in_recording(&self, server: Server) -> bool {
+        self.current_mode().include_tick_in_recording(server)
+    }
+
+    fn scoreboard_display(&self, server: Server, display: &mut ScoreboardDisplay) {
+        self.current_mode().scoreboard_display(server, display)
+    }
+
+    fn vote_handler(&mut self) -> Option<&mut dyn VoteHandler> {
+        self.current_mode_mut().vote_handler()
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.modes
+            .iter_mut()
+            .flat_map(|(_, mode)| mode.take_pending_saves())
+            .collect()
+    }
+}
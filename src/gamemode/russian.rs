@@ -2,9 +2,12 @@ use nalgebra::{Point3, Rotation3, Vector3};
 use std::collections::HashMap;
 use tracing::info;
 
+use crate::commands::{ArgKind, ChatCommand, CommandArg};
+use crate::game::{
+    CustomScoreboardValue, PlayerIndex, Puck, ScoreboardDisplay, ScoreboardValues, Team,
+};
 use crate::game::{PhysicsEvent, PlayerId};
-use crate::game::{PlayerIndex, Puck, ScoreboardValues, Team};
-use crate::gamemode::util::add_players;
+use crate::gamemode::util::{add_players, announce_early_win, decided_winner};
 use crate::gamemode::{
     ExitReason, GameMode, InitialGameValues, PuckExt, Server, ServerMut, ServerMutParts,
 };
@@ -43,13 +46,19 @@ impl RussianGameMode {
     }
 
     fn update_players(&mut self, mut server: ServerMut) {
-        let ServerMutParts { players, rink, .. } = server.as_mut_parts();
+        let ServerMutParts {
+            players,
+            rink,
+            config,
+            ..
+        } = server.as_mut_parts();
         let rink = &*rink;
         add_players(
             players,
             self.team_max,
             &mut self.team_switch_timer,
             None,
+            &config.team_names,
             move |team, _| {
                 let mid_z = rink.length / 2.0;
                 let z = match team {
@@ -60,6 +69,7 @@ impl RussianGameMode {
                 let rot = Rotation3::from_euler_angles(0.0, 3.0 * FRAC_PI_2, 0.0);
                 (pos, rot)
             },
+            |_, _| Ok(()),
             |_| {},
             |_, _| {},
         );
@@ -82,6 +92,7 @@ impl RussianGameMode {
     }
 
     fn fix_status(&mut self, mut server: ServerMut, team: Team) {
+        let team_name = server.config().team_names.name(team).to_owned();
         match &mut self.status {
             RussianStatus::WaitingForGame => {
                 self.status = RussianStatus::Game {
@@ -92,11 +103,11 @@ impl RussianGameMode {
 
                 let remaining_attempts = self.attempts;
                 let msg = if remaining_attempts >= 2 {
-                    format!("{} attempts left for {}", remaining_attempts, team)
+                    format!("{} attempts left for {}", remaining_attempts, team_name)
                 } else if remaining_attempts == 1 {
-                    format!("Last attempt for {}", team)
+                    format!("Last attempt for {}", team_name)
                 } else {
-                    format!("Tie-breaker round for {}", team)
+                    format!("Tie-breaker round for {}", team_name)
                 };
                 server.players_mut().add_server_chat_message(msg);
             }
@@ -108,12 +119,19 @@ impl RussianGameMode {
                         *round += 1;
                     }
                     let remaining_attempts = self.attempts.saturating_sub(*round);
+
+                    // The team that lost the puck may have been on its last attempt
+                    self.check_ending(server.rb_mut());
+                    if matches!(self.status, RussianStatus::GameOver { .. }) {
+                        return;
+                    }
+
                     let msg = if remaining_attempts >= 2 {
-                        format!("{} attempts left for {}", remaining_attempts, team)
+                        format!("{} attempts left for {}", remaining_attempts, team_name)
                     } else if remaining_attempts == 1 {
-                        format!("Last attempt for {}", team)
+                        format!("Last attempt for {}", team_name)
                     } else {
-                        format!("Tie-breaker round for {}", team)
+                        format!("Tie-breaker round for {}", team_name)
                     };
                     server.players_mut().add_server_chat_message(msg);
                 }
@@ -165,7 +183,7 @@ impl RussianGameMode {
         }
     }
 
-    fn check_ending(&mut self, game: &mut ScoreboardValues) {
+    fn check_ending(&mut self, mut server: ServerMut) {
         if let RussianStatus::Game { in_zone, round, .. } = self.status {
             let red_attempts_taken = round + if in_zone == Team::Blue { 1 } else { 0 };
             let blue_attempts_taken = round;
@@ -173,16 +191,21 @@ impl RussianGameMode {
             let remaining_red_attempts = attempts - red_attempts_taken;
             let remaining_blue_attempts = attempts - blue_attempts_taken;
 
-            let game_over = if let Some(difference) = game.red_score.checked_sub(game.blue_score) {
-                remaining_blue_attempts < difference
-            } else if let Some(difference) = game.blue_score.checked_sub(game.red_score) {
-                remaining_red_attempts < difference
-            } else {
-                false
-            };
-            if game_over {
+            // A team keeps the puck after scoring, so any number of goals can be scored in one attempt
+            if let Some(winner) = decided_winner(
+                server.scoreboard(),
+                remaining_red_attempts,
+                remaining_blue_attempts,
+                None,
+            ) {
                 self.status = RussianStatus::GameOver { timer: 500 };
-                game.game_over = true;
+                server.scoreboard_mut().game_over = true;
+                if remaining_red_attempts + remaining_blue_attempts > 0 {
+                    let ServerMutParts {
+                        players, config, ..
+                    } = server.as_mut_parts();
+                    announce_early_win(players, &config.team_names, winner);
+                }
             }
         }
     }
@@ -228,7 +251,22 @@ impl RussianGameMode {
     }
 }
 
+const RUSSIAN_COMMANDS: &[ChatCommand] = &[
+    ChatCommand::new("reset", "Resets the game")
+        .aliases(&["resetgame"])
+        .admin(),
+    ChatCommand::new("fs", "Forces the player off the ice")
+        .args(&[CommandArg::required("ID", ArgKind::Player)])
+        .admin(),
+];
+
 impl GameMode for RussianGameMode {
+    fn init(&mut self, mut server: ServerMut) {
+        for command in RUSSIAN_COMMANDS {
+            server.register_command(*command);
+        }
+    }
+
     fn before_tick(&mut self, server: ServerMut) {
         self.update_players(server);
     }
@@ -334,7 +372,7 @@ impl GameMode for RussianGameMode {
                             };
                             values.goal_message_timer = 300;
                             server.players_mut().add_goal_message(team, None, None);
-                            self.check_ending(server.scoreboard_mut());
+                            self.check_ending(server.rb_mut());
                         }
                         PhysicsEvent::PuckTouch { player, .. } => {
                             if let Some(player) = server.players().get(*player) {
@@ -348,10 +386,10 @@ impl GameMode for RussianGameMode {
                             self.fix_status(server.rb_mut(), other_team);
                         }
                         PhysicsEvent::PuckPassedDefensiveLine { .. } => {
-                            self.check_ending(server.scoreboard_mut());
+                            self.check_ending(server.rb_mut());
                         }
                         PhysicsEvent::PuckPassedGoalLine { .. } => {
-                            self.check_ending(server.scoreboard_mut());
+                            self.check_ending(server.rb_mut());
                         }
                         _ => {}
                     }
@@ -359,7 +397,7 @@ impl GameMode for RussianGameMode {
                 let values = server.scoreboard_mut();
                 values.time = values.time.saturating_sub(1);
                 if values.time == 0 {
-                    self.check_ending(values);
+                    self.check_ending(server.rb_mut());
                     match self.status {
                         RussianStatus::Game { in_zone, .. } => {
                             let other_team = in_zone.get_other_team();
@@ -411,4 +449,23 @@ impl GameMode for RussianGameMode {
     fn include_tick_in_recording(&self, _server: Server) -> bool {
         !matches!(self.status, RussianStatus::WaitingForGame)
     }
+
+    fn scoreboard_display(&self, _server: Server, display: &mut ScoreboardDisplay) {
+        if let RussianStatus::Game { in_zone, round, .. } = self.status {
+            display.custom.extend([
+                CustomScoreboardValue {
+                    name: "round",
+                    value: round as i32 + 1,
+                },
+                CustomScoreboardValue {
+                    name: "attempts",
+                    value: self.attempts as i32,
+                },
+                CustomScoreboardValue {
+                    name: "team",
+                    value: in_zone.get_num() as i32,
+                },
+            ]);
+        }
+    }
 }
@@ -0,0 +1,220 @@
+//! Games that start at a set time of day.
+//!
+//! With `/startat 20:00 UTC` or the `scheduled_start` setting, the warmup lasts until that time. The warmup
+//! clock counts down to the start, and players are told how long is left now and then. When the time has come
+//! and both teams have enough players, the game starts.
+
+use std::fmt;
+
+use chrono::{DateTime, Local, NaiveTime, TimeDelta, TimeZone, Utc};
+use tracing::info;
+
+use crate::game::PlayerId;
+use crate::gamemode::ServerMut;
+
+/// Seconds before the start when players are told how long is left.
+const COUNTDOWN: &[u32] = &[3600, 1800, 900, 600, 300, 120, 60, 30, 10];
+
+/// Time of day when a game starts, in UTC or in the local time of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StartTime {
+    pub time: NaiveTime,
+    pub utc: bool,
+}
+
+impl StartTime {
+    /// Parses times like "20:00" in local time, or "20:00 UTC".
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (time, utc) = match s.split_once(char::is_whitespace) {
+            Some((time, zone)) if zone.trim().eq_ignore_ascii_case("utc") => (time, true),
+            Some(_) => return None,
+            None => (s, false),
+        };
+        let time = NaiveTime::parse_from_str(time, "%H:%M").ok()?;
+        Some(StartTime { time, utc })
+    }
+
+    /// Returns the first time after `now` with this time of day.
+    fn next_after(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let next = |date: chrono::NaiveDate| {
+            let naive = date.and_time(self.time);
+            if self.utc {
+                naive.and_utc()
+            } else {
+                Local
+                    .from_local_datetime(&naive)
+                    .earliest()
+                    .map_or_else(|| naive.and_utc(), |x| x.with_timezone(&Utc))
+            }
+        };
+        let today = if self.utc {
+            now.date_naive()
+        } else {
+            now.with_timezone(&Local).date_naive()
+        };
+        let start = next(today);
+        if start > now {
+            start
+        } else {
+            next(today + TimeDelta::days(1))
+        }
+    }
+}
+
+impl fmt::Display for StartTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.time.format("%H:%M"))?;
+        if self.utc {
+            write!(f, " UTC")?;
+        }
+        Ok(())
+    }
+}
+
+/// The next scheduled start of a game.
+#[derive(Debug, Clone)]
+pub(crate) struct ScheduledStart {
+    start: DateTime<Utc>,
+    /// Index of the next countdown message in [`COUNTDOWN`].
+    countdown: usize,
+    /// True once the start time has passed, while waiting for players.
+    waiting: bool,
+}
+
+impl ScheduledStart {
+    /// Schedules the next start at `start_time` after `now`, which should be the time of the server.
+    pub(crate) fn new(start_time: StartTime, now: DateTime<Utc>) -> Self {
+        let start = start_time.next_after(now);
+        let remaining = seconds_between(now, start);
+        ScheduledStart {
+            start,
+            countdown: COUNTDOWN.partition_point(|x| *x >= remaining),
+            waiting: false,
+        }
+    }
+
+    /// Counts down to the start during the warmup. Should be called once each tick during the warmup, with
+    /// the number of players on the ice in each team. Returns true when the game should start.
+    pub(crate) fn update(
+        &mut self,
+        mut server: ServerMut,
+        min_players: usize,
+        red_player_count: usize,
+        blue_player_count: usize,
+    ) -> bool {
+        let now = server.now();
+        let remaining = (self.start - now).num_milliseconds().max(0) as u64 / 10;
+        // The warmup clock shows the time left
+        server.scoreboard_mut().time = remaining.clamp(1, u32::MAX as u64) as u32;
+        if remaining > 0 {
+            let seconds = seconds_between(now, self.start);
+            if COUNTDOWN.get(self.countdown).is_some_and(|x| seconds <= *x) {
+                self.countdown = COUNTDOWN.partition_point(|x| *x >= seconds);
+                let msg = format!("The game starts in {}", format_duration(seconds));
+                server.players_mut().add_server_chat_message(msg);
+            }
+            return false;
+        }
+        if red_player_count >= min_players && blue_player_count >= min_players {
+            info!("Scheduled game started");
+            server
+                .players_mut()
+                .add_server_chat_message("The scheduled game is starting");
+            return true;
+        }
+        if !self.waiting {
+            self.waiting = true;
+            let msg = if min_players == 1 {
+                "Waiting for a player in each team to start the game".to_owned()
+            } else {
+                format!(
+                    "Waiting for {} players in each team to start the game",
+                    min_players
+                )
+            };
+            server.players_mut().add_server_chat_message(msg);
+        }
+        false
+    }
+}
+
+fn seconds_between(now: DateTime<Utc>, time: DateTime<Utc>) -> u32 {
+    (time - now).num_seconds().clamp(0, u32::MAX as i64) as u32
+}
+
+fn format_duration(seconds: u32) -> String {
+    match seconds {
+        0..60 => format!("{} seconds", seconds),
+        60..120 => "1 minute".to_owned(),
+        _ => format!("{} minutes", seconds / 60),
+    }
+}
+
+/// Sets or cancels the scheduled start with `/startat TIME` or `/startat off`.
+pub(crate) fn set_scheduled_start(
+    scheduled_start: &mut Option<ScheduledStart>,
+    mut server: ServerMut,
+    player_id: PlayerId,
+    arg: &str,
+) {
+    let Some(name) = server
+        .players_mut()
+        .check_admin_or_deny(player_id)
+        .map(|player| player.name())
+    else {
+        return;
+    };
+    if arg.trim().eq_ignore_ascii_case("off") {
+        if scheduled_start.take().is_some() {
+            info!("{} ({}) cancelled the scheduled start", name, player_id);
+            let msg = format!("Scheduled start cancelled by {}", name);
+            server.players_mut().add_server_chat_message(msg);
+        }
+        return;
+    }
+    let Some(start_time) = StartTime::parse(arg) else {
+        server
+            .players_mut()
+            .add_directed_server_chat_message("Usage: /startat HH:MM [UTC]", player_id);
+        return;
+    };
+    info!(
+        "{} ({}) scheduled the game to start at {}",
+        name, player_id, start_time
+    );
+    let msg = format!("Game scheduled to start at {} by {}", start_time, name);
+    *scheduled_start = Some(ScheduledStart::new(start_time, server.now()));
+    server.players_mut().add_server_chat_message(msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, TimeZone, Utc};
+
+    use super::StartTime;
+
+    #[test]
+    fn start_times() {
+        let start_time = StartTime::parse("20:00 UTC").unwrap();
+        assert_eq!(
+            start_time,
+            StartTime {
+                time: NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+                utc: true
+            }
+        );
+        assert_eq!(start_time.to_string(), "20:00 UTC");
+        assert!(!StartTime::parse("8:30").unwrap().utc);
+        assert!(StartTime::parse("20:00 CET").is_none());
+        assert!(StartTime::parse("25:00").is_none());
+
+        let morning = Utc.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap();
+        let evening = Utc.with_ymd_and_hms(2024, 3, 1, 20, 0, 0).unwrap();
+        assert_eq!(start_time.next_after(morning), evening);
+        assert_eq!(
+            start_time.next_after(evening),
+            Utc.with_ymd_and_hms(2024, 3, 2, 20, 0, 0).unwrap()
+        );
+    }
+}
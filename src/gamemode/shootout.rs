@@ -5,9 +5,15 @@ use std::f32::consts::PI;
 
 use tracing::info;
 
+use crate::commands::{ArgKind, ChatCommand, CommandArg};
+use crate::game::{
+    CustomScoreboardValue, PlayerIndex, Puck, ScoreboardDisplay, ScoreboardValues, Team,
+};
 use crate::game::{PhysicsEvent, PlayerId};
-use crate::game::{PlayerIndex, Puck, ScoreboardValues, Team};
-use crate::gamemode::util::{add_players, get_spawnpoint, SpawnPoint};
+use crate::gamemode::bots::{GoalieBot, GoalieBotConfiguration};
+use crate::gamemode::util::{
+    add_players, announce_early_win, decided_winner, get_spawnpoint, SpawnPoint,
+};
 use crate::gamemode::{
     ExitReason, GameMode, InitialGameValues, PuckExt, Server, ServerMut, ServerMutParts,
 };
@@ -29,22 +35,59 @@ enum ShootoutStatus {
     },
 }
 
+const TEAM_ROUND_ARGS: &[CommandArg] = &[
+    CommandArg::required("r/b", ArgKind::Word),
+    CommandArg::required("ROUND", ArgKind::Number),
+];
+
+const SHOOTOUT_COMMANDS: &[ChatCommand] = &[
+    ChatCommand::new("reset", "Resets the game")
+        .aliases(&["resetgame"])
+        .admin(),
+    ChatCommand::new("fs", "Forces the player off the ice")
+        .args(&[CommandArg::required("ID", ArgKind::Player)])
+        .admin(),
+    ChatCommand::new(
+        "set",
+        "Changes a setting: /set redscore N, /set bluescore N or /set round r/b N",
+    )
+    .args(&[
+        CommandArg::required("SETTING", ArgKind::Word),
+        CommandArg::required("VALUE", ArgKind::Text),
+    ])
+    .admin(),
+    ChatCommand::new("redo", "Replays a round of the team")
+        .args(TEAM_ROUND_ARGS)
+        .admin(),
+    ChatCommand::new("pause", "Pauses the game")
+        .aliases(&["pausegame"])
+        .admin(),
+    ChatCommand::new("unpause", "Unpauses the game")
+        .aliases(&["unpausegame"])
+        .admin(),
+];
+
 pub struct ShootoutGameMode {
     attempts: u32,
     status: ShootoutStatus,
     paused: bool,
     team_switch_timer: HashMap<PlayerId, u32>,
     team_max: usize,
+    bot_goalie: Option<GoalieBotConfiguration>,
+    goalie: Option<GoalieBot>,
 }
 
 impl ShootoutGameMode {
-    pub fn new(attempts: u32) -> Self {
+    /// If `bot_goalie` is set, a bot will play goalie when the defending team has no players.
+    pub fn new(attempts: u32, bot_goalie: Option<GoalieBotConfiguration>) -> Self {
         ShootoutGameMode {
             attempts,
             status: ShootoutStatus::WaitingForGame,
             paused: false,
             team_switch_timer: Default::default(),
             team_max: 1,
+            bot_goalie,
+            goalie: None,
         }
     }
 
@@ -61,13 +104,19 @@ impl ShootoutGameMode {
 
         let defending_team = team.get_other_team();
 
+        if let Some(goalie) = &mut self.goalie {
+            server.players_mut().move_to_spectator(goalie.player_id);
+            goalie.reset();
+        }
+
+        let team_name = server.config().team_names.name(team);
         let remaining_attempts = self.attempts.saturating_sub(round);
         let msg = if remaining_attempts >= 2 {
-            format!("{} attempts left for {}", remaining_attempts, team)
+            format!("{} attempts left for {}", remaining_attempts, team_name)
         } else if remaining_attempts == 1 {
-            format!("Last attempt for {}", team)
+            format!("Last attempt for {}", team_name)
         } else {
-            format!("Tie-breaker round for {}", team)
+            format!("Tie-breaker round for {}", team_name)
         };
         server.players_mut().add_server_chat_message(msg);
 
@@ -136,6 +185,17 @@ impl ShootoutGameMode {
                 false,
             );
         }
+        if defending_players.is_empty() {
+            if let Some(goalie) = self.get_or_add_goalie(server.rb_mut()) {
+                server.players_mut().spawn_skater(
+                    goalie,
+                    defending_team,
+                    goalie_pos,
+                    defending_rot,
+                    false,
+                );
+            }
+        }
         for (index, player_index) in defending_players.into_iter().enumerate() {
             let mut pos = goalie_pos.clone();
             if index > 0 {
@@ -158,6 +218,18 @@ impl ShootoutGameMode {
         }
     }
 
+    fn get_or_add_goalie(&mut self, mut server: ServerMut) -> Option<PlayerId> {
+        if let Some(goalie) = &self.goalie {
+            if server.players().get(goalie.player_id).is_some() {
+                return Some(goalie.player_id);
+            }
+        }
+        let config = self.bot_goalie.clone()?;
+        let player_id = server.players_mut().add_bot("Goalie bot")?;
+        self.goalie = Some(GoalieBot::new(player_id, config));
+        Some(player_id)
+    }
+
     fn start_next_attempt(&mut self, server: ServerMut) {
         let (next_team, next_round) = match &self.status {
             ShootoutStatus::WaitingForGame => (Team::Red, 0),
@@ -175,14 +247,21 @@ impl ShootoutGameMode {
     }
 
     fn update_players(&mut self, mut server: ServerMut) {
-        let ServerMutParts { players, rink, .. } = server.as_mut_parts();
+        let ServerMutParts {
+            players,
+            rink,
+            config,
+            ..
+        } = server.as_mut_parts();
         let rink = &*rink;
         add_players(
             players,
             self.team_max,
             &mut self.team_switch_timer,
             None,
+            &config.team_names,
             move |team, _| get_spawnpoint(rink, team, SpawnPoint::Bench),
+            |_, _| Ok(()),
             |_| {},
             |_, _| {},
         );
@@ -204,16 +283,23 @@ impl ShootoutGameMode {
             let attempts = self.attempts.max(red_attempts_taken);
             let remaining_red_attempts = attempts - red_attempts_taken;
             let remaining_blue_attempts = attempts - blue_attempts_taken;
-            let values = server.scoreboard_mut();
 
-            values.game_over =
-                if let Some(difference) = values.red_score.checked_sub(values.blue_score) {
-                    remaining_blue_attempts < difference
-                } else if let Some(difference) = values.blue_score.checked_sub(values.red_score) {
-                    remaining_red_attempts < difference
-                } else {
-                    false
-                };
+            // Each attempt can score at most one goal
+            let winner = decided_winner(
+                server.scoreboard(),
+                remaining_red_attempts,
+                remaining_blue_attempts,
+                Some(1),
+            );
+            server.scoreboard_mut().game_over = winner.is_some();
+            if let Some(winner) = winner {
+                if remaining_red_attempts + remaining_blue_attempts > 0 {
+                    let ServerMutParts {
+                        players, config, ..
+                    } = server.as_mut_parts();
+                    announce_early_win(players, &config.team_names, winner);
+                }
+            }
         }
     }
 
@@ -237,6 +323,21 @@ impl ShootoutGameMode {
                 timer: 500,
                 goal_scored,
             };
+            if let Some(goalie) = &mut self.goalie {
+                let defending_team = team.get_other_team();
+                let defended = server
+                    .players()
+                    .get(goalie.player_id)
+                    .and_then(|player| player.team())
+                    == Some(defending_team);
+                if defended {
+                    if goal_scored {
+                        goalie.goal_against();
+                    } else {
+                        goalie.save();
+                    }
+                }
+            }
             self.update_gameover(server);
         }
     }
@@ -297,7 +398,11 @@ impl ShootoutGameMode {
                         "{} ({}) changed red score to {}",
                         name, player_id, input_score
                     );
-                    let msg = format!("Red score changed by {}", name);
+                    let msg = format!(
+                        "{} score changed by {}",
+                        server.config().team_names.name(Team::Red),
+                        name
+                    );
                     server.players_mut().add_server_chat_message(msg);
                 }
                 Team::Blue => {
@@ -307,7 +412,11 @@ impl ShootoutGameMode {
                         "{} ({}) changed blue score to {}",
                         name, player_id, input_score
                     );
-                    let msg = format!("Blue score changed by {}", name);
+                    let msg = format!(
+                        "{} score changed by {}",
+                        server.config().team_names.name(Team::Blue),
+                        name
+                    );
                     server.players_mut().add_server_chat_message(msg);
                 }
             }
@@ -342,7 +451,9 @@ impl ShootoutGameMode {
                 );
                 let msg = format!(
                     "Round changed to {} for {} by {}",
-                    input_round, input_team, name
+                    input_round,
+                    server.config().team_names.name(input_team),
+                    name
                 );
                 server.players_mut().add_server_chat_message(msg);
             }
@@ -377,7 +488,9 @@ impl ShootoutGameMode {
             );
             let msg = format!(
                 "Round changed to {} for {} by {}",
-                input_round, input_team, name
+                input_round,
+                server.config().team_names.name(input_team),
+                name
             );
             server.players_mut().add_server_chat_message(msg);
             self.update_gameover(server.rb_mut());
@@ -390,37 +503,53 @@ impl ShootoutGameMode {
 
     fn pause(&mut self, mut server: ServerMut, player_id: PlayerId) {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
-            self.paused = true;
             let name = player.name();
 
             info!("{} ({}) paused game", name, player_id);
-            let msg = format!("Game paused by {}", name);
-            server.players_mut().add_server_chat_message(msg);
+            self.pause_by(server, &name);
         }
     }
 
+    fn pause_by(&mut self, mut server: ServerMut, admin_name: &str) {
+        self.paused = true;
+        let msg = format!("Game paused by {}", admin_name);
+        server.players_mut().add_server_chat_message(msg);
+    }
+
     fn unpause(&mut self, mut server: ServerMut, player_id: PlayerId) {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
-            self.paused = false;
-            if let ShootoutStatus::Game {
-                state: ShootoutAttemptState::Over { timer, .. },
-                ..
-            } = &mut self.status
-            {
-                *timer = (*timer).max(200);
-            }
             let name = player.name();
             info!("{} ({}) resumed game", name, player_id);
-            let msg = format!("Game resumed by {}", name);
+            self.unpause_by(server, &name);
+        }
+    }
 
-            server.players_mut().add_server_chat_message(msg);
+    fn unpause_by(&mut self, mut server: ServerMut, admin_name: &str) {
+        self.paused = false;
+        if let ShootoutStatus::Game {
+            state: ShootoutAttemptState::Over { timer, .. },
+            ..
+        } = &mut self.status
+        {
+            *timer = (*timer).max(200);
         }
+        let msg = format!("Game resumed by {}", admin_name);
+        server.players_mut().add_server_chat_message(msg);
     }
 }
 
 impl GameMode for ShootoutGameMode {
-    fn before_tick(&mut self, server: ServerMut) {
-        self.update_players(server);
+    fn init(&mut self, mut server: ServerMut) {
+        for command in SHOOTOUT_COMMANDS {
+            server.register_command(*command);
+        }
+    }
+
+    fn before_tick(&mut self, mut server: ServerMut) {
+        self.update_players(server.rb_mut());
+        if let Some(goalie) = &mut self.goalie {
+            goalie.update(server);
+        }
     }
 
     fn after_tick(&mut self, mut server: ServerMut, events: &[PhysicsEvent]) {
@@ -503,8 +632,14 @@ impl GameMode for ShootoutGameMode {
         match &mut self.status {
             ShootoutStatus::WaitingForGame => {
                 let (red_player_count, blue_player_count) = server.players().count_team_members();
+                // With a bot goalie, a single player is enough
+                let enough_players = if self.bot_goalie.is_some() {
+                    red_player_count + blue_player_count > 0
+                } else {
+                    red_player_count > 0 && blue_player_count > 0
+                };
                 let values = server.scoreboard_mut();
-                if red_player_count > 0 && blue_player_count > 0 && !self.paused {
+                if enough_players && !self.paused {
                     values.time = values.time.saturating_sub(1);
                     if values.time == 0 {
                         self.init(server);
@@ -530,7 +665,6 @@ impl GameMode for ShootoutGameMode {
                         let values = server.scoreboard_mut();
                         values.time = values.time.saturating_sub(1);
                         if values.time == 0 {
-                            values.time = 1; // A hack to avoid "Intermission" or "Game starting"
                             self.end_attempt(server, false);
                         } else {
                             if let Some(puck) = server.pucks().get_puck(0) {
@@ -655,6 +789,21 @@ impl GameMode for ShootoutGameMode {
         self.team_switch_timer.remove(&player_id);
     }
 
+    fn handle_remote_command(
+        &mut self,
+        server: ServerMut,
+        cmd: &str,
+        _arg: &str,
+        admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        match cmd {
+            "pause" => self.pause_by(server, admin_name),
+            "unpause" => self.unpause_by(server, admin_name),
+            _ => return None,
+        }
+        Some(Ok(()))
+    }
+
     fn server_list_team_size(&self) -> u32 {
         self.team_max as u32
     }
@@ -662,4 +811,25 @@ impl GameMode for ShootoutGameMode {
     fn include_tick_in_recording(&self, _server: Server) -> bool {
         !matches!(self.status, ShootoutStatus::WaitingForGame)
     }
+
+    fn scoreboard_display(&self, _server: Server, display: &mut ScoreboardDisplay) {
+        if let ShootoutStatus::Game { round, team, .. } = self.status {
+            // The client shows "Intermission" or "Game starting" when the time is 0
+            display.values.time = display.values.time.max(1);
+            display.custom.extend([
+                CustomScoreboardValue {
+                    name: "round",
+                    value: round as i32 + 1,
+                },
+                CustomScoreboardValue {
+                    name: "attempts",
+                    value: self.attempts as i32,
+                },
+                CustomScoreboardValue {
+                    name: "team",
+                    value: team.get_num() as i32,
+                },
+            ]);
+        }
+    }
 }
@@ -0,0 +1,476 @@
+//! Shooting drills, where the players take turns to score from the same spots.
+//!
+//! Each drill puts the puck at a spot in front of the blue net. In each round, every player on the ice gets one
+//! attempt at the same drill, and the next round moves on to the next drill. Waiting players are moved to the
+//! bench, and a bot can play goalie, like in shootouts. Goals and attempts are counted for each player.
+
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use nalgebra::{Point3, Rotation3, Vector3};
+use reborrow::ReborrowMut;
+use tracing::info;
+
+use crate::commands::ChatCommand;
+use crate::game::{PhysicsEvent, PlayerId, Puck, ScoreboardValues, Team};
+use crate::gamemode::bots::{GoalieBot, GoalieBotConfiguration};
+use crate::gamemode::util::{add_players, get_spawnpoint, SpawnPoint};
+use crate::gamemode::{
+    ExitReason, GameMode, InitialGameValues, PuckExt, Server, ServerMut, ServerMutParts,
+};
+
+/// Ticks between two attempts.
+const ATTEMPT_PAUSE: u32 = 300;
+/// Distance between the puck and the player at the start of an attempt, in meters.
+const SHOOTER_DISTANCE: f32 = 2.0;
+/// The net the drills are taken at.
+const TARGET_NET: Team = Team::Blue;
+
+/// Where the puck is put for a drill, relative to the middle of the goal line of the blue net.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkillsDrill {
+    /// Distance from the goal line in meters.
+    pub distance: f32,
+    /// Distance to the right of the middle of the net in meters, as seen by the shooter. Negative values are to the left.
+    pub offset: f32,
+}
+
+/// Parses drills like "10,0; 8,-4; 8,4", where each drill is a distance and an offset.
+pub fn parse_drills(s: &str) -> Option<Vec<SkillsDrill>> {
+    s.split(';')
+        .filter(|x| !x.trim().is_empty())
+        .map(|drill| {
+            let (distance, offset) = drill.split_once(',')?;
+            Some(SkillsDrill {
+                distance: distance.trim().parse().ok()?,
+                offset: offset.trim().parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct SkillsScore {
+    attempts: u32,
+    goals: u32,
+}
+
+#[derive(Debug, Clone)]
+enum SkillsStatus {
+    /// Nobody is on the ice.
+    Waiting,
+    Attempt {
+        player_id: PlayerId,
+        name: Rc<str>,
+    },
+    Over {
+        timer: u32,
+    },
+}
+
+pub struct SkillsGameMode {
+    drills: Vec<SkillsDrill>,
+    /// Time for each attempt in seconds.
+    attempt_time: u32,
+    status: SkillsStatus,
+    /// Index of the current drill, or [`None`] before the first round.
+    drill: Option<usize>,
+    /// Players still waiting for their attempt in this round.
+    round: VecDeque<PlayerId>,
+    /// Goals and attempts of each player by name, so that players keep them if they reconnect.
+    scores: HashMap<Rc<str>, SkillsScore>,
+    team_switch_timer: HashMap<PlayerId, u32>,
+    bot_goalie: Option<GoalieBotConfiguration>,
+    goalie: Option<GoalieBot>,
+}
+
+impl SkillsGameMode {
+    /// If `bot_goalie` is set, a bot plays goalie in every attempt.
+    pub fn new(
+        drills: Vec<SkillsDrill>,
+        attempt_time: u32,
+        bot_goalie: Option<GoalieBotConfiguration>,
+    ) -> Self {
+        let drills = if drills.is_empty() {
+            vec![SkillsDrill {
+                distance: 10.0,
+                offset: 0.0,
+            }]
+        } else {
+            drills
+        };
+        SkillsGameMode {
+            drills,
+            attempt_time,
+            status: SkillsStatus::Waiting,
+            drill: None,
+            round: VecDeque::new(),
+            scores: HashMap::new(),
+            team_switch_timer: Default::default(),
+            bot_goalie,
+            goalie: None,
+        }
+    }
+
+    fn is_goalie(&self, player_id: PlayerId) -> bool {
+        self.goalie
+            .as_ref()
+            .is_some_and(|goalie| goalie.player_id == player_id)
+    }
+
+    /// Returns the next player in the round, starting a new round with the next drill if everyone has had their turn.
+    fn next_player(&mut self, server: ServerMut) -> Option<(PlayerId, Rc<str>)> {
+        let players = server.players();
+        let on_ice = |player_id: PlayerId| {
+            players
+                .get(player_id)
+                .filter(|player| player.skater().is_some())
+                .map(|player| player.name())
+        };
+        while let Some(player_id) = self.round.pop_front() {
+            if let Some(name) = on_ice(player_id) {
+                return Some((player_id, name));
+            }
+        }
+        self.round = players
+            .iter()
+            .filter(|player| player.skater().is_some() && !self.is_goalie(player.id))
+            .map(|player| player.id)
+            .collect();
+        let player_id = self.round.pop_front()?;
+        self.drill = Some(
+            self.drill
+                .map_or(0, |drill| (drill + 1) % self.drills.len()),
+        );
+        on_ice(player_id).map(|name| (player_id, name))
+    }
+
+    fn start_next_attempt(&mut self, mut server: ServerMut) {
+        let Some((player_id, name)) = self.next_player(server.rb_mut()) else {
+            self.status = SkillsStatus::Waiting;
+            return;
+        };
+        let drill_index = self.drill.unwrap_or(0);
+        let drill = self.drills[drill_index];
+
+        let rink = server.rink();
+        let net = rink.net(TARGET_NET);
+        let normal = net.normal();
+        // Facing the net, the right is the normal rotated a quarter turn
+        let right = Vector3::new(normal.z, 0.0, -normal.x);
+        let puck_pos = net.center() + drill.distance * normal + drill.offset * right;
+        let puck_pos = Point3::new(puck_pos.x, 1.0, puck_pos.z);
+        let shooter_pos = puck_pos + SHOOTER_DISTANCE * normal;
+        let shooter_pos = Point3::new(shooter_pos.x, 1.5, shooter_pos.z);
+        let shooter_rot = Rotation3::from_euler_angles(0.0, normal.x.atan2(normal.z), 0.0);
+        let goalie_pos = net.center() + 1.0 * normal;
+        let goalie_pos = Point3::new(goalie_pos.x, 1.5, goalie_pos.z);
+        let goalie_rot = Rotation3::from_euler_angles(0.0, (-normal.x).atan2(-normal.z), 0.0);
+
+        server.pucks_mut().remove_all_pucks();
+        server
+            .pucks_mut()
+            .spawn_puck(Puck::new(puck_pos, Rotation3::identity()));
+
+        // Everyone else waits at the bench
+        let waiting = server
+            .players()
+            .iter()
+            .filter(|player| player.id != player_id && !self.is_goalie(player.id))
+            .filter_map(|player| player.skater().map(|(team, _)| (player.id, team)))
+            .collect::<Vec<_>>();
+        for (waiting_id, team) in waiting {
+            let (pos, rot) = get_spawnpoint(server.rink(), team, SpawnPoint::Bench);
+            server
+                .players_mut()
+                .spawn_skater(waiting_id, team, pos, rot, false);
+        }
+        let team = server
+            .players()
+            .get(player_id)
+            .and_then(|player| player.team())
+            .unwrap_or(Team::Red);
+        server
+            .players_mut()
+            .spawn_skater(player_id, team, shooter_pos, shooter_rot, false);
+
+        if let Some(goalie) = self.get_or_add_goalie(server.rb_mut()) {
+            server
+                .players_mut()
+                .spawn_skater(goalie, TARGET_NET, goalie_pos, goalie_rot, false);
+        }
+        if let Some(goalie) = &mut self.goalie {
+            goalie.reset();
+        }
+
+        let values = server.scoreboard_mut();
+        values.time = self.attempt_time * 100;
+        values.goal_message_timer = 0;
+
+        let msg = format!(
+            "{}'s turn, drill {} of {}",
+            name,
+            drill_index + 1,
+            self.drills.len()
+        );
+        server.players_mut().add_server_chat_message(msg);
+        self.status = SkillsStatus::Attempt { player_id, name };
+    }
+
+    fn get_or_add_goalie(&mut self, mut server: ServerMut) -> Option<PlayerId> {
+        if let Some(goalie) = &self.goalie {
+            if server.players().get(goalie.player_id).is_some() {
+                return Some(goalie.player_id);
+            }
+        }
+        let config = self.bot_goalie.clone()?;
+        let player_id = server.players_mut().add_bot("Goalie bot")?;
+        self.goalie = Some(GoalieBot::new(player_id, config));
+        Some(player_id)
+    }
+
+    fn end_attempt(&mut self, mut server: ServerMut, goal_scored: bool) {
+        let SkillsStatus::Attempt { name, .. } = &self.status else {
+            return;
+        };
+        let score = self.scores.entry(name.clone()).or_default();
+        score.attempts += 1;
+        if goal_scored {
+            score.goals += 1;
+        }
+        let msg = if goal_scored {
+            format!("Goal by {}! {}/{}", name, score.goals, score.attempts)
+        } else {
+            format!("Miss by {}, {}/{}", name, score.goals, score.attempts)
+        };
+        server.players_mut().add_server_chat_message(msg);
+        if goal_scored {
+            server.scoreboard_mut().goal_message_timer = ATTEMPT_PAUSE;
+        }
+        if let Some(goalie) = &mut self.goalie {
+            if goal_scored {
+                goalie.goal_against();
+            } else {
+                goalie.save();
+            }
+        }
+        self.status = SkillsStatus::Over {
+            timer: ATTEMPT_PAUSE,
+        };
+    }
+
+    /// Handles /scores, which shows the goals and attempts of every player to the player who asked.
+    fn msg_scores(&self, mut server: ServerMut, receiver_id: PlayerId) {
+        let standings = standings(&self.scores);
+        if standings.is_empty() {
+            server
+                .players_mut()
+                .add_directed_server_chat_message("No attempts yet", receiver_id);
+            return;
+        }
+        for (i, (name, score)) in standings.iter().enumerate() {
+            let msg = format!("{}. {} {}/{}", i + 1, name, score.goals, score.attempts);
+            server
+                .players_mut()
+                .add_directed_server_chat_message(msg, receiver_id);
+        }
+    }
+
+    fn reset_game(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let name = player.name();
+            info!("{} ({}) reset game", name, player_id);
+            let msg = format!("Game reset by {}", name);
+
+            server.new_game(self.get_initial_game_values());
+
+            server.players_mut().add_server_chat_message(msg);
+        }
+    }
+
+    fn update_players(&mut self, mut server: ServerMut) {
+        let ServerMutParts {
+            players,
+            rink,
+            config,
+            ..
+        } = server.as_mut_parts();
+        let rink = &*rink;
+        add_players(
+            players,
+            usize::MAX,
+            &mut self.team_switch_timer,
+            None,
+            &config.team_names,
+            |team, _| get_spawnpoint(rink, team, SpawnPoint::Bench),
+            |_, _| Ok(()),
+            |_| {},
+            |_, _| {},
+        );
+    }
+}
+
+/// Returns the players sorted by goals, most goals first. Ties go to the player with fewer attempts, and then by name.
+fn standings(scores: &HashMap<Rc<str>, SkillsScore>) -> Vec<(Rc<str>, SkillsScore)> {
+    let mut standings = scores
+        .iter()
+        .map(|(name, score)| (name.clone(), *score))
+        .collect::<Vec<_>>();
+    standings.sort_by(|(a_name, a), (b_name, b)| {
+        b.goals
+            .cmp(&a.goals)
+            .then_with(|| a.attempts.cmp(&b.attempts))
+            .then_with(|| a_name.cmp(b_name))
+    });
+    standings
+}
+
+const SKILLS_COMMANDS: &[ChatCommand] = &[
+    ChatCommand::new("scores", "Shows the goals and attempts of every player"),
+    ChatCommand::new("reset", "Resets the game")
+        .aliases(&["resetgame"])
+        .admin(),
+];
+
+impl GameMode for SkillsGameMode {
+    fn init(&mut self, mut server: ServerMut) {
+        for command in SKILLS_COMMANDS {
+            server.register_command(*command);
+        }
+    }
+
+    fn before_tick(&mut self, mut server: ServerMut) {
+        self.update_players(server.rb_mut());
+        if let Some(goalie) = &mut self.goalie {
+            goalie.update(server);
+        }
+    }
+
+    fn after_tick(&mut self, mut server: ServerMut, events: &[PhysicsEvent]) {
+        for event in events {
+            match *event {
+                PhysicsEvent::PuckEnteredNet { team, .. } => {
+                    self.end_attempt(server.rb_mut(), team == TARGET_NET);
+                }
+                PhysicsEvent::PuckPassedGoalLine { .. } => {
+                    self.end_attempt(server.rb_mut(), false);
+                }
+                _ => {}
+            }
+        }
+
+        match &mut self.status {
+            SkillsStatus::Waiting => {
+                let playing = server
+                    .players()
+                    .iter()
+                    .any(|player| player.skater().is_some() && !self.is_goalie(player.id));
+                if playing {
+                    self.status = SkillsStatus::Over {
+                        timer: ATTEMPT_PAUSE,
+                    };
+                }
+            }
+            SkillsStatus::Attempt { player_id, .. } => {
+                let on_ice = server
+                    .players()
+                    .get(*player_id)
+                    .is_some_and(|player| player.skater().is_some());
+                let values = server.scoreboard_mut();
+                values.time = values.time.saturating_sub(1);
+                if !on_ice {
+                    // The player left, the attempt doesn't count
+                    self.status = SkillsStatus::Over {
+                        timer: ATTEMPT_PAUSE,
+                    };
+                } else if values.time == 0 {
+                    self.end_attempt(server, false);
+                }
+            }
+            SkillsStatus::Over { timer } => {
+                *timer = timer.saturating_sub(1);
+                if *timer == 0 {
+                    self.start_next_attempt(server);
+                }
+            }
+        }
+    }
+
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, _arg: &str, player_id: PlayerId) {
+        match cmd {
+            "scores" => {
+                self.msg_scores(server, player_id);
+            }
+            "reset" | "resetgame" => {
+                self.reset_game(server, player_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        InitialGameValues {
+            values: ScoreboardValues {
+                period: 1,
+                ..Default::default()
+            },
+            puck_slots: 1,
+        }
+    }
+
+    fn game_started(&mut self, _server: ServerMut) {
+        self.status = SkillsStatus::Waiting;
+        self.drill = None;
+        self.round.clear();
+        self.scores.clear();
+    }
+
+    fn before_player_exit(&mut self, _server: ServerMut, player_id: PlayerId, _reason: ExitReason) {
+        self.team_switch_timer.remove(&player_id);
+        self.round.retain(|x| *x != player_id);
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        0
+    }
+
+    fn include_tick_in_recording(&self, _server: Server) -> bool {
+        !matches!(self.status, SkillsStatus::Waiting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_drills, standings, SkillsDrill, SkillsScore};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[test]
+    fn drills_and_standings() {
+        assert_eq!(
+            parse_drills("10,0; 8,-4.5").unwrap(),
+            [
+                SkillsDrill {
+                    distance: 10.0,
+                    offset: 0.0
+                },
+                SkillsDrill {
+                    distance: 8.0,
+                    offset: -4.5
+                }
+            ]
+        );
+        assert!(parse_drills("10").is_none());
+
+        let mut scores: HashMap<Rc<str>, SkillsScore> = HashMap::new();
+        let score = |goals, attempts| SkillsScore { attempts, goals };
+        scores.insert("Alice".into(), score(2, 5));
+        scores.insert("Bob".into(), score(2, 3));
+        scores.insert("Carol".into(), score(3, 5));
+        let names = standings(&scores)
+            .into_iter()
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Carol", "Bob", "Alice"]);
+    }
+}
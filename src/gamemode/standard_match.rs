@@ -2,14 +2,125 @@ use tracing::info;
 
 use std::collections::{HashMap, HashSet};
 
+use crate::commands::{ArgKind, ChatCommand, CommandArg};
+use crate::game::{CustomScoreboardValue, PlayerIndex, ScoreboardDisplay, Team};
 use crate::game::{PhysicsEvent, PlayerId};
-use crate::game::{PlayerIndex, Team};
 pub use crate::gamemode::match_util::{
-    IcingConfiguration, Match, MatchConfiguration, OffsideConfiguration, OffsideLineConfiguration,
-    TwoLinePassConfiguration, ALLOWED_POSITIONS,
+    get_faceoff_spot, FaceoffHook, FaceoffSpot, IcingConfiguration, Match, MatchConfiguration,
+    MatchEvent, OffsideConfiguration, OffsideLineConfiguration, OvertimeConfiguration, Penalty,
+    PeriodRules, RinkFaceoffSpot, RinkSide, TwoLinePassConfiguration, ALLOWED_POSITIONS,
+};
+use crate::gamemode::mvp::MvpVoting;
+use crate::gamemode::overtime::{Lineup, OvertimeRoster};
+pub use crate::gamemode::scheduled_start::StartTime;
+use crate::gamemode::scheduled_start::{set_scheduled_start, ScheduledStart};
+use crate::gamemode::shootout::ShootoutGameMode;
+use crate::gamemode::util::{
+    add_players_with_team_sizes, get_spawnpoint, msg_puck_speed, msg_speed, AutoTeams, Captains,
+    SpawnPoint, SPEED_COMMANDS,
 };
-use crate::gamemode::util::{add_players, get_spawnpoint, SpawnPoint};
 use crate::gamemode::{ExitReason, GameMode, InitialGameValues, Server, ServerMut, ServerMutParts};
+use reborrow::ReborrowMut;
+use tokio::task::JoinHandle;
+
+/// Number of attempts each team gets in the shootout that decides a tied game.
+const TIE_BREAKER_ATTEMPTS: u32 = 3;
+
+const PLAYER_ARG: CommandArg = CommandArg::required("ID", ArgKind::Player);
+const RULE_ARG: CommandArg = CommandArg::required("RULE", ArgKind::Word);
+
+const MATCH_COMMANDS: &[ChatCommand] = &[
+    ChatCommand::new(
+        "set",
+        "Changes a setting, like /set clock 5:00 or /set redscore 2",
+    )
+    .args(&[
+        CommandArg::required("SETTING", ArgKind::Word),
+        CommandArg::required("VALUE", ArgKind::Text),
+    ])
+    .admin(),
+    ChatCommand::new("faceoff", "Calls a center-ice faceoff").admin(),
+    ChatCommand::new(
+        "penalty",
+        "Sends the player to the penalty box for SECONDS, 60 by default",
+    )
+    .args(&[PLAYER_ARG, CommandArg::optional("SECONDS", ArgKind::Number)])
+    .admin(),
+    ChatCommand::new("start", "Starts the game")
+        .aliases(&["startgame"])
+        .admin(),
+    ChatCommand::new(
+        "startat",
+        "Keeps the warmup going until TIME, like 20:00 UTC, or off",
+    )
+    .args(&[CommandArg::required("TIME", ArgKind::Text)])
+    .admin(),
+    ChatCommand::new("reset", "Resets the game")
+        .aliases(&["resetgame"])
+        .admin(),
+    ChatCommand::new(
+        "resume",
+        "Resumes a game from a live state file or a recording",
+    )
+    .args(&[CommandArg::required("FILE", ArgKind::Text)])
+    .admin(),
+    ChatCommand::new("pause", "Pauses the game")
+        .aliases(&["pausegame"])
+        .admin(),
+    ChatCommand::new("unpause", "Unpauses the game")
+        .aliases(&["unpausegame"])
+        .admin(),
+    ChatCommand::new("sp", "Sets your preferred faceoff position, like C or LD")
+        .aliases(&["setposition"])
+        .args(&[CommandArg::required("POSITION", ArgKind::Word)]),
+    ChatCommand::new(
+        "league",
+        "Tags the game as a league game, with locked teams and strict rules",
+    )
+    .args(&[CommandArg::required("on/off", ArgKind::Word)])
+    .admin(),
+    ChatCommand::new("fs", "Forces the player off the ice")
+        .args(&[PLAYER_ARG])
+        .admin(),
+    ChatCommand::new("icing", "Sets the icing rule: off, on or notouch")
+        .args(&[RULE_ARG])
+        .admin(),
+    ChatCommand::new("offside", "Sets the offside rule: off, on or immediate")
+        .args(&[RULE_ARG])
+        .admin(),
+    ChatCommand::new(
+        "goalreplay",
+        "Turns goal replays on or off, or sets their length, like /goalreplay length 8",
+    )
+    .args(&[CommandArg::required("on/off/length N", ArgKind::Text)])
+    .admin(),
+    ChatCommand::new("rules", "Shows the offside and icing rules"),
+    ChatCommand::new("icetime", "Shows the ice time of each player"),
+    ChatCommand::new("enter", "Enters the intermission shootout contest"),
+    ChatCommand::new("mvp", "Votes for the MVP when the game is over")
+        .args(&[CommandArg::required("NAME", ArgKind::Text)]),
+    ChatCommand::new(
+        "chatextend",
+        "Shows messages when players join or leave teams",
+    )
+    .args(&[CommandArg::required("on/off", ArgKind::Word)]),
+    ChatCommand::new("captain", "Makes you the captain of your team"),
+    ChatCommand::new(
+        "lockteams",
+        "Captains only: locks your team to its roster, or unlocks it",
+    )
+    .args(&[CommandArg::optional("on/off", ArgKind::Word)]),
+    ChatCommand::new(
+        "invite",
+        "Captains only: lets the player join your team when it's locked",
+    )
+    .args(&[PLAYER_ARG]),
+    ChatCommand::new(
+        "bench",
+        "Captains only: moves the player off the ice and off the roster",
+    )
+    .args(&[PLAYER_ARG]),
+];
 
 pub struct StandardMatchGameMode {
     pub m: Match,
@@ -17,6 +128,29 @@ pub struct StandardMatchGameMode {
     pub(crate) team_switch_timer: HashMap<PlayerId, u32>,
     pub(crate) show_extra_messages: HashSet<PlayerId>,
     pub team_max: usize,
+    league_roster: HashMap<PlayerId, Team>,
+    /// The rules that were replaced by the league preset, restored by /league off.
+    league_replaced_rules: Option<PeriodRules>,
+    auto_teams: AutoTeams,
+    captains: Captains,
+    /// Players on the ice, in the order they joined their team.
+    join_order: Vec<PlayerId>,
+    mvp: MvpVoting,
+    tie_breaker: Option<TieBreaker>,
+    overtime_roster: OvertimeRoster,
+    scheduled_start: Option<ScheduledStart>,
+    /// True if the next game should stay in the warmup until an admin starts it.
+    hold_warmup: bool,
+}
+
+/// Shootout that decides a game that is still tied after overtime.
+struct TieBreaker {
+    shootout: ShootoutGameMode,
+    /// The score and period before the shootout. Goals in the shootout are added to the score while it runs,
+    /// and the winner only gets one goal in the end.
+    red_score: u32,
+    blue_score: u32,
+    period: u32,
 }
 
 impl StandardMatchGameMode {
@@ -27,34 +161,251 @@ impl StandardMatchGameMode {
             team_switch_timer: Default::default(),
             show_extra_messages: Default::default(),
             team_max,
+            league_roster: Default::default(),
+            league_replaced_rules: None,
+            auto_teams: Default::default(),
+            captains: Default::default(),
+            join_order: vec![],
+            mvp: Default::default(),
+            tie_breaker: None,
+            overtime_roster: Default::default(),
+            scheduled_start: None,
+            hold_warmup: false,
+        }
+    }
+
+    fn start_tie_breaker(&mut self, mut server: ServerMut) {
+        let values = server.scoreboard_mut();
+        self.tie_breaker = Some(TieBreaker {
+            shootout: ShootoutGameMode::new(TIE_BREAKER_ATTEMPTS, None),
+            red_score: values.red_score,
+            blue_score: values.blue_score,
+            period: values.period,
+        });
+        // The shootout starts when its clock runs out
+        values.time = (self.m.config.time_intermission * 100).max(1);
+        info!("Shootout started");
+        server
+            .players_mut()
+            .add_server_chat_message("Still tied, the game is decided by a shootout");
+    }
+
+    fn end_tie_breaker(&mut self, mut server: ServerMut) {
+        let Some(tie_breaker) = self.tie_breaker.take() else {
+            return;
+        };
+        let values = server.scoreboard_mut();
+        let winner = if values.red_score > values.blue_score {
+            Team::Red
+        } else {
+            Team::Blue
+        };
+        values.red_score = tie_breaker.red_score;
+        values.blue_score = tie_breaker.blue_score;
+        match winner {
+            Team::Red => values.red_score += 1,
+            Team::Blue => values.blue_score += 1,
+        }
+        values.period = tie_breaker.period;
+        values.time = 0;
+        values.game_over = true;
+        self.m.pause_timer = self
+            .m
+            .pause_timer
+            .max(self.m.config.time_intermission * 100);
+
+        let msg = format!(
+            "{} wins the shootout",
+            server.config().team_names.name(winner)
+        );
+        info!("{}", msg);
+        server.players_mut().add_server_chat_message(msg);
+    }
+
+    fn update_mvp_voting(&mut self, mut server: ServerMut) {
+        let vote_time = self.m.config.mvp_vote_time * 100;
+        if vote_time > 0
+            && server.scoreboard().game_over
+            && self.mvp.open(server.rb_mut(), vote_time)
+        {
+            // Keep the game from being reset until the voting has closed
+            self.m.pause_timer = self.m.pause_timer.max(vote_time + 1);
         }
+        self.mvp.update(server);
     }
 
     fn update_players(&mut self, mut server: ServerMut) {
         let spawn_point = self.spawn_point;
-        let ServerMutParts { players, rink, .. } = server.as_mut_parts();
+        // The shootout is one shooter against one goalie
+        let period = server.scoreboard().period;
+        let overtime_lineup = if let Some(tie_breaker) = &self.tie_breaker {
+            Some(Lineup {
+                period: tie_breaker.period,
+                team_size: 1,
+                shootout: true,
+            })
+        } else {
+            (self.m.config.overtime_team_size > 0 && self.m.is_overtime(period)).then_some(Lineup {
+                period,
+                team_size: self.m.config.overtime_team_size.min(self.team_max),
+                shootout: false,
+            })
+        };
+        self.overtime_roster.update(
+            server.rb_mut(),
+            overtime_lineup,
+            &mut self.join_order,
+            spawn_point,
+        );
+        let team_max = overtime_lineup.map_or(self.team_max, |lineup| lineup.team_size);
+        let league = server.is_league_game();
+        if !league && !self.league_roster.is_empty() {
+            self.league_roster.clear();
+        }
+        let league_roster = &self.league_roster;
+        let captains = &self.captains;
+        let force_team_size_parity = self.m.config.force_team_size_parity;
+        let join_order = &mut self.join_order;
+        let ServerMutParts {
+            players,
+            rink,
+            config,
+            ..
+        } = server.as_mut_parts();
         let rink = &*rink;
 
-        let (red_player_count, blue_player_count) = add_players(
+        let (mut red_player_count, mut blue_player_count) = add_players_with_team_sizes(
             players,
-            self.team_max,
+            team_max,
             &mut self.team_switch_timer,
             Some(&self.show_extra_messages),
+            &config.team_names,
             |team, _| get_spawnpoint(rink, team, spawn_point),
+            |player_id, team, (red_player_count, blue_player_count)| {
+                let (player_count, other_player_count) = match team {
+                    Team::Red => (red_player_count, blue_player_count),
+                    Team::Blue => (blue_player_count, red_player_count),
+                };
+                // Teams are locked in league games, if they were locked with /league on
+                if league
+                    && !league_roster.is_empty()
+                    && league_roster.get(&player_id) != Some(&team)
+                {
+                    Err("Teams are locked for this league game")
+                } else if force_team_size_parity && player_count > other_player_count {
+                    Err("This team has more players, join the other team")
+                } else {
+                    captains.can_join(player_id, team)
+                }
+            },
             |_| {},
             |player_index, _| {
                 self.m.clear_started_goalie(player_index);
+                join_order.retain(|x| *x != player_index);
+                join_order.push(player_index);
             },
         );
+        self.captains.update(server.rb_mut());
+        self.join_order.retain(|player_id| {
+            server
+                .players()
+                .get(*player_id)
+                .is_some_and(|x| x.team().is_some())
+        });
+
+        // The clock runs out and the faceoff is done in this tick
+        if self.m.config.force_team_balance && !league && !self.m.paused && self.m.pause_timer == 1
+        {
+            self.balance_teams(server.rb_mut());
+        }
+
+        if self.m.config.warmup_auto_teams {
+            if server.scoreboard().period == 0 && !league {
+                let ServerMutParts { players, rink, .. } = server.as_mut_parts();
+                let rink = &*rink;
+                self.auto_teams.add_queued_players(
+                    players,
+                    self.team_max,
+                    &mut red_player_count,
+                    &mut blue_player_count,
+                    |team, _| get_spawnpoint(rink, team, spawn_point),
+                );
+            } else {
+                self.auto_teams.clear();
+            }
+        }
 
+        self.m
+            .update_team_sizes(server.rb_mut(), red_player_count, blue_player_count);
+
+        let min_players = self.m.config.min_players.max(1);
+        if let Some(scheduled_start) = &mut self.scheduled_start {
+            if server.scoreboard().period == 0 {
+                let start = scheduled_start.update(
+                    server.rb_mut(),
+                    min_players,
+                    red_player_count,
+                    blue_player_count,
+                );
+                self.m.waiting_for_start = !start;
+                if start {
+                    self.scheduled_start = None;
+                }
+                return;
+            }
+        }
         let values = server.scoreboard_mut();
 
-        if values.period == 0 && values.time > 2000 && red_player_count > 0 && blue_player_count > 0
+        if values.period == 0
+            && values.time > 2000
+            && red_player_count >= min_players
+            && blue_player_count >= min_players
         {
             values.time = 2000;
         }
     }
 
+    /// Moves the player who joined the larger team last to the other team, if the teams are uneven by two or more.
+    fn balance_teams(&mut self, mut server: ServerMut) {
+        let (red_player_count, blue_player_count) = server.players().count_team_members();
+        let larger_team = if red_player_count >= blue_player_count + 2 {
+            Team::Red
+        } else if blue_player_count >= red_player_count + 2 {
+            Team::Blue
+        } else {
+            return;
+        };
+        let players = server.players();
+        let Some((player_id, player_name)) = self.join_order.iter().rev().find_map(|player_id| {
+            players
+                .get(*player_id)
+                .filter(|player| player.team() == Some(larger_team))
+                .map(|player| (player.id, player.name()))
+        }) else {
+            return;
+        };
+        let team = larger_team.get_other_team();
+
+        let (pos, rot) = get_spawnpoint(server.rink(), team, self.spawn_point);
+        if server
+            .players_mut()
+            .spawn_skater(player_id, team, pos, rot, false)
+        {
+            self.join_order.retain(|x| *x != player_id);
+            self.join_order.push(player_id);
+            info!(
+                "{} ({}) was moved to team {:?} to balance the teams",
+                player_name, player_id, team
+            );
+            let msg = format!(
+                "{} moved to {} to balance the teams",
+                player_name,
+                server.config().team_names.name(team)
+            );
+            server.players_mut().add_server_chat_message(msg);
+        }
+    }
+
     pub(crate) fn force_player_off_ice(
         &mut self,
         mut server: ServerMut,
@@ -83,6 +434,58 @@ impl StandardMatchGameMode {
         }
     }
 
+    pub(crate) fn set_league(&mut self, mut server: ServerMut, player_id: PlayerId, arg: &str) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let name = player.name();
+            match arg {
+                "on" => {
+                    // Lock the teams as they are right now
+                    self.league_roster = server
+                        .players()
+                        .iter()
+                        .filter_map(|player| player.team().map(|team| (player.id, team)))
+                        .collect();
+                    server.set_league_game(true);
+
+                    // Strict rules preset
+                    if self.league_replaced_rules.is_none() {
+                        self.league_replaced_rules = Some(PeriodRules {
+                            offside: Some(self.m.config.offside),
+                            offside_line: Some(self.m.config.offside_line),
+                            icing: Some(self.m.config.icing),
+                            ..Default::default()
+                        });
+                    }
+                    self.m.set_rules_by_admin(PeriodRules {
+                        offside: Some(OffsideConfiguration::Delayed),
+                        offside_line: Some(OffsideLineConfiguration::OffensiveBlue),
+                        icing: Some(IcingConfiguration::Touch),
+                        ..Default::default()
+                    });
+
+                    info!("{} ({}) tagged game as league game", name, player_id);
+                    let msg = format!("League game enabled by {}", name);
+                    server.players_mut().add_server_chat_message(msg);
+                    server.players_mut().add_server_chat_message(
+                        "Recording on, teams locked, offside and icing enabled",
+                    );
+                }
+                "off" => {
+                    self.league_roster.clear();
+                    server.set_league_game(false);
+                    if let Some(rules) = self.league_replaced_rules.take() {
+                        self.m.set_rules_by_admin(rules);
+                    }
+
+                    info!("{} ({}) removed league game tag", name, player_id);
+                    let msg = format!("League game disabled by {}", name);
+                    server.players_mut().add_server_chat_message(msg);
+                }
+                _ => {}
+            }
+        }
+    }
+
     pub(crate) fn set_team_size(&mut self, mut server: ServerMut, player_id: PlayerId, size: &str) {
         if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
             if let Ok(new_num) = size.parse::<usize>() {
@@ -102,15 +505,35 @@ impl StandardMatchGameMode {
 
 impl GameMode for StandardMatchGameMode {
     fn init(&mut self, mut server: ServerMut) {
-        server.replay_mut().set_history_length(1000)
+        for command in MATCH_COMMANDS.iter().chain(SPEED_COMMANDS) {
+            server.register_command(*command);
+        }
+        server
+            .replay_mut()
+            .set_history_length(self.m.replay_history_length())
     }
 
-    fn before_tick(&mut self, server: ServerMut) {
+    fn before_tick(&mut self, mut server: ServerMut) {
+        if self.m.update_resume(server.rb_mut()) {
+            // The players of the resumed game join again, so the teams aren't locked
+            self.league_roster.clear();
+        }
         self.update_players(server);
     }
 
-    fn after_tick(&mut self, server: ServerMut, events: &[PhysicsEvent]) {
-        self.m.after_tick(server, events);
+    fn after_tick(&mut self, mut server: ServerMut, events: &[PhysicsEvent]) {
+        if let Some(tie_breaker) = &mut self.tie_breaker {
+            tie_breaker.shootout.after_tick(server.rb_mut(), events);
+            if server.scoreboard().game_over {
+                self.end_tie_breaker(server.rb_mut());
+            }
+        } else {
+            self.m.after_tick(server.rb_mut(), events);
+            if self.m.is_shootout_due(server.scoreboard()) {
+                self.start_tie_breaker(server.rb_mut());
+            }
+        }
+        self.update_mvp_voting(server);
     }
 
     fn handle_command(
@@ -120,6 +543,14 @@ impl GameMode for StandardMatchGameMode {
         arg: &str,
         player_id: PlayerId,
     ) {
+        if let Some(tie_breaker) = &mut self.tie_breaker {
+            if matches!(command, "pause" | "pausegame" | "unpause" | "unpausegame") {
+                tie_breaker
+                    .shootout
+                    .handle_command(server, command, arg, player_id);
+                return;
+            }
+        }
         match command {
             "set" => {
                 let args = arg.split(" ").collect::<Vec<&str>>();
@@ -223,9 +654,8 @@ impl GameMode for StandardMatchGameMode {
                             }
                         }
                         "goalreplay" => {
-                            if let Some(arg) = args.get(1) {
-                                self.m.set_goal_replay(server, player_id, arg);
-                            }
+                            self.m
+                                .set_goal_replay(server, player_id, &args[1..].join(" "));
                         }
                         "spawnoffset" => {
                             if let Ok(rule) = args[1].parse::<f32>() {
@@ -254,12 +684,29 @@ impl GameMode for StandardMatchGameMode {
             "faceoff" => {
                 self.m.faceoff(server, player_id);
             }
+            "penalty" => {
+                self.m.penalty(server, player_id, arg);
+            }
             "start" | "startgame" => {
-                self.m.start_game(server, player_id);
+                let started = self.m.start_game(server, player_id);
+                if started {
+                    // Started early, so the schedule is no longer needed
+                    self.scheduled_start = None;
+                    self.m.waiting_for_start = false;
+                }
+            }
+            "startat" => {
+                set_scheduled_start(&mut self.scheduled_start, server.rb_mut(), player_id, arg);
+                if self.scheduled_start.is_none() {
+                    self.m.waiting_for_start = false;
+                }
             }
             "reset" | "resetgame" => {
                 self.m.reset_game(server, player_id);
             }
+            "resume" => {
+                self.m.resume_game(server, player_id, arg.trim());
+            }
             "pause" | "pausegame" => {
                 self.m.pause(server, player_id);
             }
@@ -270,6 +717,9 @@ impl GameMode for StandardMatchGameMode {
                 self.m
                     .set_preferred_faceoff_position(server, player_id, arg);
             }
+            "league" => {
+                self.set_league(server, player_id, arg);
+            }
             "fs" => {
                 if let Ok(force_player_index) = arg.parse::<PlayerIndex>() {
                     self.force_player_off_ice(server, player_id, force_player_index);
@@ -281,9 +731,28 @@ impl GameMode for StandardMatchGameMode {
             "offside" => {
                 self.m.set_offside_rule(server, player_id, arg);
             }
+            "goalreplay" => {
+                self.m.set_goal_replay(server, player_id, arg.trim());
+            }
             "rules" => {
                 self.m.msg_rules(server, player_id);
             }
+            "icetime" => {
+                self.m.msg_ice_time(server, player_id);
+            }
+            "speed" => {
+                msg_speed(server, player_id, self.m.config.use_mph);
+            }
+            "puckspeed" => {
+                let puck = self.m.last_touched_puck(player_id);
+                msg_puck_speed(server, player_id, puck, self.m.config.use_mph);
+            }
+            "enter" => {
+                self.m.enter_intermission_contest(server, player_id);
+            }
+            "mvp" => {
+                self.mvp.vote(server, player_id, arg);
+            }
             "chatextend" => {
                 if arg.eq_ignore_ascii_case("true") || arg.eq_ignore_ascii_case("on") {
                     if self.show_extra_messages.insert(player_id) {
@@ -301,6 +770,26 @@ impl GameMode for StandardMatchGameMode {
                     }
                 }
             }
+            "captain" => {
+                self.captains.claim(server, player_id);
+            }
+            "lockteams" => {
+                let locked = !arg.trim().eq_ignore_ascii_case("off");
+                self.captains.set_locked(server, player_id, locked);
+            }
+            "invite" => {
+                if let Ok(invited_index) = arg.trim().parse::<PlayerIndex>() {
+                    self.captains.invite(server, player_id, invited_index);
+                }
+            }
+            "bench" => {
+                if let Ok(benched_index) = arg.trim().parse::<PlayerIndex>() {
+                    if let Some(benched_id) = self.captains.bench(server, player_id, benched_index)
+                    {
+                        self.team_switch_timer.insert(benched_id, 500);
+                    }
+                }
+            }
             _ => {}
         };
     }
@@ -309,14 +798,69 @@ impl GameMode for StandardMatchGameMode {
         self.m.get_initial_game_values()
     }
 
-    fn game_started(&mut self, server: ServerMut) {
-        self.m.game_started(server);
+    fn game_started(&mut self, mut server: ServerMut) {
+        self.mvp.reset();
+        self.tie_breaker = None;
+        self.m.game_started(server.rb_mut());
+        if self.scheduled_start.is_none() {
+            let now = server.now();
+            self.scheduled_start = self
+                .m
+                .config
+                .scheduled_start
+                .map(|start_time| ScheduledStart::new(start_time, now));
+        }
+        if std::mem::take(&mut self.hold_warmup) && self.scheduled_start.is_none() {
+            self.m.waiting_for_start = true;
+            server
+                .players_mut()
+                .add_server_chat_message("Warmup until an admin starts the game");
+        }
+    }
+
+    fn hold_warmup(&mut self, _server: ServerMut) {
+        self.hold_warmup = true;
     }
 
     fn before_player_exit(&mut self, _server: ServerMut, player_id: PlayerId, _reason: ExitReason) {
         self.m.cleanup_player(player_id);
+        self.league_roster.remove(&player_id);
         self.team_switch_timer.remove(&player_id);
         self.show_extra_messages.remove(&player_id);
+        self.auto_teams.player_left(player_id);
+        self.captains.player_left(player_id);
+        self.join_order.retain(|x| *x != player_id);
+        self.overtime_roster.player_left(player_id);
+    }
+
+    fn after_player_join(&mut self, _server: ServerMut, player_id: PlayerId) {
+        if self.m.config.warmup_auto_teams {
+            self.auto_teams.player_joined(player_id);
+        }
+    }
+
+    fn handle_remote_command(
+        &mut self,
+        server: ServerMut,
+        cmd: &str,
+        arg: &str,
+        admin_name: &str,
+    ) -> Option<Result<(), &'static str>> {
+        if let Some(tie_breaker) = &mut self.tie_breaker {
+            if matches!(cmd, "pause" | "unpause") {
+                return tie_breaker
+                    .shootout
+                    .handle_remote_command(server, cmd, arg, admin_name);
+            }
+        }
+        match cmd {
+            "pause" => self.m.pause_by(server, admin_name),
+            "unpause" => self.m.unpause_by(server, admin_name),
+            "faceoff" => self.m.faceoff_by(server, admin_name),
+            "penalty" => return Some(self.m.penalty_by(server, admin_name, arg)),
+            _ => return None,
+        }
+        Some(Ok(()))
     }
 
     fn server_list_team_size(&self) -> u32 {
@@ -326,4 +870,29 @@ impl GameMode for StandardMatchGameMode {
     fn include_tick_in_recording(&self, server: Server) -> bool {
         server.scoreboard().period > 0
     }
+
+    fn scoreboard_display(&self, server: Server, display: &mut ScoreboardDisplay) {
+        if let Some(tie_breaker) = &self.tie_breaker {
+            tie_breaker.shootout.scoreboard_display(server, display);
+            display.values.period = tie_breaker.period;
+            return;
+        }
+        for (name, team) in [("red_penalty", Team::Red), ("blue_penalty", Team::Blue)] {
+            if let Some(remaining) = self.m.penalty_clock(team) {
+                // Seconds left, rounded up
+                display.custom.push(CustomScoreboardValue {
+                    name,
+                    value: remaining.div_ceil(100) as i32,
+                });
+            }
+        }
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.m
+            .preferred_positions
+            .take_pending_save()
+            .into_iter()
+            .collect()
+    }
 }
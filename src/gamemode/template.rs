@@ -0,0 +1,188 @@
+//! A minimal game mode to start from when writing your own.
+//!
+//! It contains everything a game mode needs to be playable: players join and leave teams, a puck is
+//! dropped at center ice, goals are counted and announced, and the game ends when a team reaches
+//! the goal limit. There is also an admin command, `/droppuck`, that returns the puck to center ice.
+//!
+//! Everything used here is public, so the file can be copied into another crate that depends on this one.
+//! Replace `crate::` with `migo_hqm_server::` in the imports, rename the struct, and start the server with
+//! [`run_server`](crate::run_server) and your game mode.
+
+use std::collections::HashMap;
+
+use nalgebra::{Point3, Rotation3};
+use reborrow::ReborrowMut;
+use tracing::info;
+
+use crate::commands::ChatCommand;
+use crate::game::{PhysicsEvent, PlayerId, Puck, Team};
+use crate::gamemode::util::{add_players, get_spawnpoint, SpawnPoint};
+use crate::gamemode::{
+    ExitReason, GameMode, InitialGameValues, PuckExt, Server, ServerMut, ServerMutParts,
+};
+
+/// Ticks between a goal and the next puck drop.
+const GOAL_PAUSE: u32 = 300;
+/// Ticks between the end of a game and the start of the next one.
+const GAME_OVER_PAUSE: u32 = 1000;
+
+pub struct TemplateGameMode {
+    goal_limit: u32,
+    team_max: usize,
+    team_switch_timer: HashMap<PlayerId, u32>,
+    last_touch: HashMap<usize, PlayerId>,
+    pause_timer: u32,
+}
+
+impl TemplateGameMode {
+    pub fn new(goal_limit: u32, team_max: usize) -> Self {
+        TemplateGameMode {
+            goal_limit,
+            team_max,
+            team_switch_timer: Default::default(),
+            last_touch: Default::default(),
+            pause_timer: 0,
+        }
+    }
+
+    fn drop_puck(&mut self, mut server: ServerMut) {
+        self.last_touch.clear();
+        server.pucks_mut().remove_all_pucks();
+        let rink = server.rink();
+        let pos = Point3::new(rink.width / 2.0, 1.5, rink.length / 2.0);
+        server
+            .pucks_mut()
+            .spawn_puck(Puck::new(pos, Rotation3::identity()));
+    }
+
+    fn handle_goal(&mut self, mut server: ServerMut, net_team: Team, puck: usize) {
+        if self.pause_timer > 0 || server.scoreboard().game_over {
+            return;
+        }
+        let team = net_team.get_other_team();
+        let scorer = self.last_touch.get(&puck).copied();
+        server.players_mut().add_goal_message(team, scorer, None);
+
+        let goal_limit = self.goal_limit;
+        let values = server.scoreboard_mut();
+        let score = match team {
+            Team::Red => {
+                values.red_score += 1;
+                values.red_score
+            }
+            Team::Blue => {
+                values.blue_score += 1;
+                values.blue_score
+            }
+        };
+        if score >= goal_limit {
+            values.game_over = true;
+            self.pause_timer = GAME_OVER_PAUSE;
+        } else {
+            self.pause_timer = GOAL_PAUSE;
+        }
+    }
+
+    fn update_players(&mut self, mut server: ServerMut) {
+        let ServerMutParts {
+            players,
+            rink,
+            config,
+            ..
+        } = server.as_mut_parts();
+        let rink = &*rink;
+        add_players(
+            players,
+            self.team_max,
+            &mut self.team_switch_timer,
+            None,
+            &config.team_names,
+            |team, _| get_spawnpoint(rink, team, SpawnPoint::Bench),
+            |_, _| Ok(()),
+            |_| {},
+            |_, _| {},
+        );
+    }
+}
+
+const DROP_PUCK_COMMAND: ChatCommand =
+    ChatCommand::new("droppuck", "Returns the puck to center ice").admin();
+
+impl GameMode for TemplateGameMode {
+    fn init(&mut self, mut server: ServerMut) {
+        // Registered commands are checked by the server, and listed by /help
+        server.register_command(DROP_PUCK_COMMAND);
+    }
+
+    fn before_tick(&mut self, mut server: ServerMut) {
+        self.update_players(server.rb_mut());
+
+        if self.pause_timer > 0 {
+            self.pause_timer -= 1;
+            if self.pause_timer == 0 {
+                if server.scoreboard().game_over {
+                    server.new_game(self.get_initial_game_values());
+                } else {
+                    self.drop_puck(server);
+                }
+            }
+        }
+    }
+
+    fn after_tick(&mut self, mut server: ServerMut, events: &[PhysicsEvent]) {
+        for event in events {
+            match *event {
+                PhysicsEvent::PuckTouch { player, puck } => {
+                    self.last_touch.insert(puck, player);
+                }
+                PhysicsEvent::PuckEnteredNet { team, puck } => {
+                    self.handle_goal(server.rb_mut(), team, puck);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_command(
+        &mut self,
+        mut server: ServerMut,
+        cmd: &str,
+        _arg: &str,
+        player_id: PlayerId,
+    ) {
+        if cmd == "droppuck" {
+            if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+                let name = player.name();
+                info!("{} ({}) dropped the puck", name, player_id);
+                self.drop_puck(server.rb_mut());
+                let msg = format!("Puck dropped by {}", name);
+                server.players_mut().add_server_chat_message(msg);
+            }
+        }
+    }
+
+    fn get_initial_game_values(&mut self) -> InitialGameValues {
+        InitialGameValues {
+            values: Default::default(),
+            puck_slots: 1,
+        }
+    }
+
+    fn game_started(&mut self, server: ServerMut) {
+        self.pause_timer = 0;
+        self.drop_puck(server);
+    }
+
+    fn before_player_exit(&mut self, _server: ServerMut, player_id: PlayerId, _reason: ExitReason) {
+        self.team_switch_timer.remove(&player_id);
+        self.last_touch.retain(|_, touch| *touch != player_id);
+    }
+
+    fn server_list_team_size(&self) -> u32 {
+        self.team_max as u32
+    }
+
+    fn include_tick_in_recording(&self, _server: Server) -> bool {
+        true
+    }
+}
@@ -1,14 +1,56 @@
-use crate::game::{PlayerId, Rink, Team};
-use crate::gamemode::ServerPlayersMut;
+use crate::commands::ChatCommand;
+use crate::game::{PlayerId, PlayerIndex, Rink, ScoreboardValues, Team};
+use crate::gamemode::match_util::convert_speed;
+use crate::gamemode::{PuckExt, ServerMut, ServerMutParts, ServerPlayersMut};
+use crate::TeamNames;
 use nalgebra::{Point3, Rotation3};
+use reborrow::ReborrowMut;
 use smallvec::SmallVec;
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::f32::consts::{FRAC_PI_2, PI};
 use std::rc::Rc;
 use tracing::info;
 
+/// Moves players to and from teams based on their input.
+///
+/// `can_join` is called before a player is added to a team. If it returns an error, the player stays a spectator,
+/// receives the error as a chat message, and has to wait a few seconds before the next attempt.
+#[allow(clippy::too_many_arguments)]
 pub fn add_players<
     F1: Fn(Team, usize) -> (Point3<f32>, Rotation3<f32>),
+    FCanJoin: FnMut(PlayerId, Team) -> Result<(), &'static str>,
+    FSpectate: FnMut(PlayerId) -> (),
+    FJoin: FnMut(PlayerId, Team) -> (),
+>(
+    server: ServerPlayersMut,
+    team_max: usize,
+    team_switch_timer: &mut HashMap<PlayerId, u32>,
+    show_extra_messages: Option<&HashSet<PlayerId>>,
+    team_names: &TeamNames,
+    coords: F1,
+    mut can_join: FCanJoin,
+    on_spectate: FSpectate,
+    on_join: FJoin,
+) -> (usize, usize) {
+    add_players_with_team_sizes(
+        server,
+        team_max,
+        team_switch_timer,
+        show_extra_messages,
+        team_names,
+        coords,
+        |player_id, team, _| can_join(player_id, team),
+        on_spectate,
+        on_join,
+    )
+}
+
+/// Like [`add_players`], but `can_join` is also given the current red and blue team sizes.
+#[allow(clippy::too_many_arguments)]
+pub fn add_players_with_team_sizes<
+    F1: Fn(Team, usize) -> (Point3<f32>, Rotation3<f32>),
+    FCanJoin: FnMut(PlayerId, Team, (usize, usize)) -> Result<(), &'static str>,
     FSpectate: FnMut(PlayerId) -> (),
     FJoin: FnMut(PlayerId, Team) -> (),
 >(
@@ -16,7 +58,9 @@ pub fn add_players<
     team_max: usize,
     team_switch_timer: &mut HashMap<PlayerId, u32>,
     show_extra_messages: Option<&HashSet<PlayerId>>,
+    team_names: &TeamNames,
     coords: F1,
+    mut can_join: FCanJoin,
     mut on_spectate: FSpectate,
     mut on_join: FJoin,
 ) -> (usize, usize) {
@@ -33,7 +77,7 @@ pub fn add_players<
             .get_mut(&player_id)
             .map(|x| *x = x.saturating_sub(1));
         if let Some(team) = team {
-            if input.spectate() {
+            if input.spectate() || player.should_leave_ice() || player.is_spectator() {
                 team_switch_timer.insert(player_id, 500);
                 spectating_players.push((player_id, player.name()))
             } else if team == Team::Red {
@@ -41,15 +85,14 @@ pub fn add_players<
             } else {
                 blue_player_count += 1;
             }
-        } else {
-            if (input.join_red() || input.join_blue())
-                && team_switch_timer.get(&player_id).map_or(true, |x| *x == 0)
-            {
-                if input.join_red() {
-                    joining_red.push((player_id, player.name()));
-                } else if input.join_blue() {
-                    joining_blue.push((player_id, player.name()));
-                }
+        } else if (input.join_red() || input.join_blue())
+            && !player.is_spectator()
+            && team_switch_timer.get(&player_id).map_or(true, |x| *x == 0)
+        {
+            if input.join_red() {
+                joining_red.push((player_id, player.name()));
+            } else if input.join_blue() {
+                joining_blue.push((player_id, player.name()));
             }
         }
     }
@@ -65,39 +108,356 @@ pub fn add_players<
         }
     }
 
-    let mut add_players =
-        |players: SmallVec<[(PlayerId, Rc<str>); 32]>, team: Team, player_count: &mut usize| {
-            for (i, (player_id, player_name)) in players.into_iter().enumerate() {
-                if *player_count >= team_max {
-                    break;
-                }
+    let mut add_players = |players: SmallVec<[(PlayerId, Rc<str>); 32]>, team: Team| {
+        let mut i = 0;
+        for (player_id, player_name) in players {
+            let team_sizes = (red_player_count, blue_player_count);
+            let player_count = match team {
+                Team::Red => &mut red_player_count,
+                Team::Blue => &mut blue_player_count,
+            };
+            if *player_count >= team_max {
+                break;
+            }
+
+            if let Err(reason) = can_join(player_id, team, team_sizes) {
+                team_switch_timer.insert(player_id, 500);
+                server.add_directed_server_chat_message(reason, player_id);
+                continue;
+            }
 
-                let (pos, rot) = coords(team, i);
+            let (pos, rot) = coords(team, i);
+            i += 1;
 
-                let res = server.spawn_skater(player_id, team, pos, rot, false);
+            let res = server.spawn_skater(player_id, team, pos, rot, false);
 
-                if res {
-                    info!("{} ({}) has joined team {:?}", player_name, player_id, team);
-                    *player_count += 1;
-                    on_join(player_id, team);
-                    if let Some(show_extra_messages) = show_extra_messages {
-                        let s = format!("{} is playing for Red", player_name);
-                        for msg_player_id in show_extra_messages.iter() {
-                            server.add_directed_server_chat_message(s.clone(), *msg_player_id);
-                        }
+            if res {
+                info!("{} ({}) has joined team {:?}", player_name, player_id, team);
+                *player_count += 1;
+                on_join(player_id, team);
+                if let Some(show_extra_messages) = show_extra_messages {
+                    let s = format!("{} is playing for {}", player_name, team_names.name(team));
+                    for msg_player_id in show_extra_messages.iter() {
+                        server.add_directed_server_chat_message(s.clone(), *msg_player_id);
                     }
-                } else {
-                    break;
                 }
+            } else {
+                break;
             }
-        };
+        }
+    };
 
-    add_players(joining_red, Team::Red, &mut red_player_count);
-    add_players(joining_blue, Team::Blue, &mut blue_player_count);
+    add_players(joining_red, Team::Red);
+    add_players(joining_blue, Team::Blue);
 
     (red_player_count, blue_player_count)
 }
 
+/// Puts players into teams when they join the server, so that they don't have to pick a team themselves.
+///
+/// Players are added in the order they joined, to the team with the fewest players,
+/// alternating between red and blue when the teams are even. Players that are waiting for
+/// a free spot stay in the queue, and players that go to spectator afterwards are not moved back.
+#[derive(Debug, Default)]
+pub struct AutoTeams {
+    queue: VecDeque<PlayerId>,
+    next_team: Option<Team>,
+}
+
+impl AutoTeams {
+    pub fn player_joined(&mut self, player_id: PlayerId) {
+        self.queue.push_back(player_id);
+    }
+
+    pub fn player_left(&mut self, player_id: PlayerId) {
+        self.queue.retain(|x| *x != player_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Adds queued players to teams. `red_player_count` and `blue_player_count` are the current team sizes,
+    /// as returned by [`add_players`], and are updated for the added players.
+    pub fn add_queued_players<F: Fn(Team, usize) -> (Point3<f32>, Rotation3<f32>)>(
+        &mut self,
+        mut server: ServerPlayersMut,
+        team_max: usize,
+        red_player_count: &mut usize,
+        blue_player_count: &mut usize,
+        coords: F,
+    ) {
+        let mut i = 0;
+        while let Some(&player_id) = self.queue.front() {
+            let Some(player) = server.get(player_id) else {
+                self.queue.pop_front();
+                continue;
+            };
+            if player.team().is_some() || player.is_spectator() {
+                // Already picked a team on their own, or only wants to watch
+                self.queue.pop_front();
+                continue;
+            }
+            let player_name = player.name();
+            let next_team = self.next_team.unwrap_or(Team::Red);
+            let team = if *red_player_count < *blue_player_count {
+                Team::Red
+            } else if *blue_player_count < *red_player_count {
+                Team::Blue
+            } else {
+                next_team
+            };
+            let player_count = match team {
+                Team::Red => &mut *red_player_count,
+                Team::Blue => &mut *blue_player_count,
+            };
+            if *player_count >= team_max {
+                break;
+            }
+            let (pos, rot) = coords(team, i);
+            i += 1;
+            if !server.spawn_skater(player_id, team, pos, rot, false) {
+                break;
+            }
+            info!("{} ({}) was put in team {:?}", player_name, player_id, team);
+            *player_count += 1;
+            self.next_team = Some(team.get_other_team());
+            self.queue.pop_front();
+        }
+    }
+}
+
+/// Team captains and their rosters.
+///
+/// A player on a team can claim the captaincy of the team with `/captain`, if nobody has claimed it yet.
+/// Captains can lock their team, so that only players on the roster can join it. The roster is the team
+/// as it was when it was locked, and the players the captain has invited. Captains can also bench players
+/// of their team, which moves them to spectator and takes them off the roster.
+/// When the captain leaves the team, the team loses its captain and is unlocked.
+#[derive(Debug, Default)]
+pub struct Captains {
+    teams: HashMap<Team, CaptainedTeam>,
+}
+
+#[derive(Debug)]
+struct CaptainedTeam {
+    captain: PlayerId,
+    locked: bool,
+    roster: HashSet<PlayerId>,
+}
+
+impl Captains {
+    pub fn captain(&self, team: Team) -> Option<PlayerId> {
+        self.teams.get(&team).map(|x| x.captain)
+    }
+
+    /// Checks that `player_id` may join `team`, for the `can_join` callback of [`add_players`].
+    pub fn can_join(&self, player_id: PlayerId, team: Team) -> Result<(), &'static str> {
+        match self.teams.get(&team) {
+            Some(captained) if captained.locked && !captained.roster.contains(&player_id) => {
+                Err("This team has been locked by its captain")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn claim(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        let ServerMutParts {
+            mut players,
+            config,
+            ..
+        } = server.as_mut_parts();
+        let Some(player) = players.get(player_id) else {
+            return;
+        };
+        let Some(team) = player.team() else {
+            players.add_directed_server_chat_message(
+                "You must be on a team to become captain",
+                player_id,
+            );
+            return;
+        };
+        let team_name = config.team_names.name(team);
+        if let Some(captained) = self.teams.get(&team) {
+            let msg = if captained.captain == player_id {
+                format!("You are already the captain of {}", team_name)
+            } else {
+                format!("{} already has a captain", team_name)
+            };
+            players.add_directed_server_chat_message(msg, player_id);
+            return;
+        }
+        let name = player.name();
+        self.teams.insert(
+            team,
+            CaptainedTeam {
+                captain: player_id,
+                locked: false,
+                roster: HashSet::new(),
+            },
+        );
+        info!("{} ({}) is the captain of team {:?}", name, player_id, team);
+        let msg = format!("{} is the captain of {}", name, team_name);
+        players.add_server_chat_message(msg);
+    }
+
+    /// Locks or unlocks the team of the captain `player_id`. Locking puts the current team on the roster.
+    pub fn set_locked(&mut self, mut server: ServerMut, player_id: PlayerId, locked: bool) {
+        let Some(team) = self.captained_team(server.rb_mut(), player_id) else {
+            return;
+        };
+        let team_players = server
+            .players()
+            .iter()
+            .filter(|player| player.team() == Some(team))
+            .map(|player| player.id)
+            .collect::<Vec<_>>();
+        let captained = self.teams.get_mut(&team).unwrap();
+        if captained.locked == locked {
+            return;
+        }
+        captained.locked = locked;
+        if locked {
+            captained.roster.extend(team_players);
+        }
+
+        let ServerMutParts {
+            mut players,
+            config,
+            ..
+        } = server.as_mut_parts();
+        let name = players.get(player_id).map(|x| x.name()).unwrap_or_default();
+        let team_name = config.team_names.name(team);
+        let msg = if locked {
+            info!("{} ({}) locked team {:?}", name, player_id, team);
+            format!("{} locked by {}", team_name, name)
+        } else {
+            info!("{} ({}) unlocked team {:?}", name, player_id, team);
+            format!("{} unlocked by {}", team_name, name)
+        };
+        players.add_server_chat_message(msg);
+    }
+
+    /// Puts a player on the roster of the team of the captain `player_id`, so that they can join it when it is locked.
+    pub fn invite(
+        &mut self,
+        mut server: ServerMut,
+        player_id: PlayerId,
+        invited_index: PlayerIndex,
+    ) {
+        let Some(team) = self.captained_team(server.rb_mut(), player_id) else {
+            return;
+        };
+        let ServerMutParts {
+            mut players,
+            config,
+            ..
+        } = server.as_mut_parts();
+        let Some(invited) = players.get_by_index(invited_index) else {
+            players.add_directed_server_chat_message("No player with this ID exists", player_id);
+            return;
+        };
+        let invited_id = invited.id;
+        let invited_name = invited.name();
+        let name = players.get(player_id).map(|x| x.name()).unwrap_or_default();
+        self.teams.get_mut(&team).unwrap().roster.insert(invited_id);
+
+        info!(
+            "{} ({}) invited {} ({}) to team {:?}",
+            name, player_id, invited_name, invited_id, team
+        );
+        let msg = format!(
+            "{} invited {} to {}",
+            name,
+            invited_name,
+            config.team_names.name(team)
+        );
+        players.add_server_chat_message(msg);
+    }
+
+    /// Moves a player of the team of the captain `player_id` to spectator, and takes them off the roster.
+    /// Returns the benched player.
+    pub fn bench(
+        &mut self,
+        mut server: ServerMut,
+        player_id: PlayerId,
+        benched_index: PlayerIndex,
+    ) -> Option<PlayerId> {
+        let team = self.captained_team(server.rb_mut(), player_id)?;
+        let mut players = server.players_mut();
+        let Some(benched) = players.get_by_index(benched_index) else {
+            players.add_directed_server_chat_message("No player with this ID exists", player_id);
+            return None;
+        };
+        let benched_id = benched.id;
+        let benched_name = benched.name();
+        if benched_id == player_id {
+            players.add_directed_server_chat_message("You can't bench yourself", player_id);
+            return None;
+        }
+        if benched.team() != Some(team) {
+            players.add_directed_server_chat_message("That player is not on your team", player_id);
+            return None;
+        }
+        self.teams
+            .get_mut(&team)
+            .unwrap()
+            .roster
+            .remove(&benched_id);
+        players.move_to_spectator(benched_id);
+
+        let name = players.get(player_id).map(|x| x.name()).unwrap_or_default();
+        info!(
+            "{} ({}) benched {} ({})",
+            name, player_id, benched_name, benched_id
+        );
+        let msg = format!("{} was benched by {}", benched_name, name);
+        players.add_server_chat_message(msg);
+        Some(benched_id)
+    }
+
+    /// Removes captains that are no longer on their team. Call this after players have changed teams.
+    pub fn update(&mut self, mut server: ServerMut) {
+        let ServerMutParts {
+            mut players,
+            config,
+            ..
+        } = server.as_mut_parts();
+        self.teams.retain(|team, captained| {
+            let on_team = players
+                .get(captained.captain)
+                .is_some_and(|player| player.team() == Some(*team));
+            if !on_team {
+                info!("Team {:?} lost its captain", team);
+                let msg = format!("{} no longer has a captain", config.team_names.name(*team));
+                players.add_server_chat_message(msg);
+            }
+            on_team
+        });
+    }
+
+    pub fn player_left(&mut self, player_id: PlayerId) {
+        for captained in self.teams.values_mut() {
+            captained.roster.remove(&player_id);
+        }
+    }
+
+    /// Returns the team captained by `player_id`, or tells the player that the command is for captains only.
+    fn captained_team(&self, mut server: ServerMut, player_id: PlayerId) -> Option<Team> {
+        let team = self
+            .teams
+            .iter()
+            .find(|(_, captained)| captained.captain == player_id)
+            .map(|(team, _)| *team);
+        if team.is_none() {
+            server
+                .players_mut()
+                .add_directed_server_chat_message("Only team captains can do that", player_id);
+        }
+        team
+    }
+}
+
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
 pub enum SpawnPoint {
     Center,
@@ -140,3 +500,112 @@ pub fn get_spawnpoint(
         },
     }
 }
+
+/// Returns the leading team if the other team can no longer catch up with its remaining attempts,
+/// or [`None`] if the game is still open.
+///
+/// `max_goals_per_attempt` is [`None`] if a team can score any number of goals in one attempt.
+/// A team that can only draw level can still win in the tie-breaker rounds, so the game isn't decided then.
+pub fn decided_winner(
+    values: &ScoreboardValues,
+    remaining_red_attempts: u32,
+    remaining_blue_attempts: u32,
+    max_goals_per_attempt: Option<u32>,
+) -> Option<Team> {
+    let (leader, difference, remaining_attempts) = if values.red_score > values.blue_score {
+        (
+            Team::Red,
+            values.red_score - values.blue_score,
+            remaining_blue_attempts,
+        )
+    } else if values.blue_score > values.red_score {
+        (
+            Team::Blue,
+            values.blue_score - values.red_score,
+            remaining_red_attempts,
+        )
+    } else {
+        return None;
+    };
+    let can_catch_up = match max_goals_per_attempt {
+        Some(max_goals) => remaining_attempts.saturating_mul(max_goals) >= difference,
+        None => remaining_attempts > 0,
+    };
+    (!can_catch_up).then_some(leader)
+}
+
+/// Announces that the game is over before all attempts have been taken, because `winner` can no longer be caught.
+pub fn announce_early_win(mut players: ServerPlayersMut, team_names: &TeamNames, winner: Team) {
+    let msg = format!(
+        "{} wins, {} can no longer catch up",
+        team_names.name(winner),
+        team_names.name(winner.get_other_team())
+    );
+    players.add_server_chat_message(msg);
+}
+
+/// The /speed and /puckspeed commands, handled with [`msg_speed`] and [`msg_puck_speed`].
+pub(crate) const SPEED_COMMANDS: &[ChatCommand] = &[
+    ChatCommand::new("speed", "Shows your current skating speed"),
+    ChatCommand::new(
+        "puckspeed",
+        "Shows the speed of the puck you last touched, or the closest puck",
+    ),
+];
+
+/// Tells a player how fast they are skating, in miles per hour if `use_mph` is set and in km/h otherwise.
+pub(crate) fn msg_speed(mut server: ServerMut, receiver_id: PlayerId, use_mph: bool) {
+    let speed = server.players().get(receiver_id).and_then(|player| {
+        player
+            .skater()
+            .map(|(_, skater)| skater.body.linear_velocity.norm())
+    });
+    let msg = match speed {
+        Some(speed) => {
+            let (speed, unit) = convert_speed(speed, use_mph);
+            format!("Your speed: {:.1} {}", speed, unit).into()
+        }
+        None => Cow::Borrowed("You are not on the ice"),
+    };
+    server
+        .players_mut()
+        .add_directed_server_chat_message(msg, receiver_id);
+}
+
+/// Tells a player how fast a puck is moving. The puck is `touched_puck`, the puck the player touched last, if it is
+/// still on the ice. Otherwise it is the puck closest to the player, or the first puck if the player is spectating.
+pub(crate) fn msg_puck_speed(
+    mut server: ServerMut,
+    receiver_id: PlayerId,
+    touched_puck: Option<usize>,
+    use_mph: bool,
+) {
+    let skater_pos = server
+        .players()
+        .get(receiver_id)
+        .and_then(|player| player.skater().map(|(_, skater)| skater.body.pos));
+    let pucks = server.pucks();
+    let puck = touched_puck
+        .and_then(|index| pucks.get_puck(index))
+        .or_else(|| {
+            let mut pucks = pucks.iter().flatten();
+            match skater_pos {
+                Some(pos) => pucks.min_by(|a, b| {
+                    let a = (a.body.pos - pos).norm();
+                    let b = (b.body.pos - pos).norm();
+                    a.total_cmp(&b)
+                }),
+                None => pucks.next(),
+            }
+        });
+    let msg = match puck {
+        Some(puck) => {
+            let (speed, unit) = convert_speed(puck.body.linear_velocity.norm(), use_mph);
+            format!("Puck speed: {:.1} {}", speed, unit).into()
+        }
+        None => Cow::Borrowed("There is no puck on the ice"),
+    };
+    server
+        .players_mut()
+        .add_directed_server_chat_message(msg, receiver_id);
+}
@@ -1,56 +1,415 @@
-use crate::game::Puck;
+use crate::commands::{wrap_chat_lines, ArgKind, ChatCommand, CommandArg};
+use crate::game::{Obstacle, Puck, Team};
 use crate::game::{PhysicsEvent, PlayerId};
-use crate::gamemode::util::{add_players, get_spawnpoint, SpawnPoint};
+use crate::gamemode::layouts::{Layout, LayoutResult, LayoutStore};
+use crate::gamemode::util::{
+    add_players, get_spawnpoint, msg_puck_speed, msg_speed, AutoTeams, SpawnPoint, SPEED_COMMANDS,
+};
 use crate::gamemode::{GameMode, InitialGameValues, PuckExt, ServerMut, ServerMutParts};
-use nalgebra::{Point3, Rotation3};
-use std::collections::HashMap;
+use nalgebra::{Point3, Rotation3, Vector3};
+use reborrow::ReborrowMut;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
 
 pub struct PermanentWarmup {
     pucks: usize,
     spawn_point: SpawnPoint,
     team_switch_timer: HashMap<PlayerId, u32>,
+    track_score: bool,
+    last_touch: HashMap<usize, PlayerId>,
+    scored_pucks: HashSet<usize>,
+    auto_teams: Option<AutoTeams>,
+    layouts: Option<LayoutStore>,
+    use_mph: bool,
 }
 
 impl PermanentWarmup {
-    pub fn new(pucks: usize, spawn_point: SpawnPoint) -> Self {
+    /// If `track_score` is true, goals are counted and announced in chat, and the score can be reset with /resetscore.
+    /// If `auto_teams` is true, joining players are put into teams automatically.
+    pub fn new(pucks: usize, spawn_point: SpawnPoint, track_score: bool, auto_teams: bool) -> Self {
         PermanentWarmup {
             pucks,
             spawn_point,
             team_switch_timer: Default::default(),
+            track_score,
+            last_touch: Default::default(),
+            scored_pucks: Default::default(),
+            auto_teams: auto_teams.then(AutoTeams::default),
+            layouts: None,
+            use_mph: false,
         }
     }
+
+    /// Like [`PermanentWarmup::new`], but admins can also save and load layouts of pucks and obstacles in
+    /// `layout_dir` with /layout.
+    pub fn with_layouts(
+        pucks: usize,
+        spawn_point: SpawnPoint,
+        track_score: bool,
+        auto_teams: bool,
+        layout_dir: PathBuf,
+    ) -> Self {
+        PermanentWarmup {
+            layouts: Some(LayoutStore::new(layout_dir)),
+            ..Self::new(pucks, spawn_point, track_score, auto_teams)
+        }
+    }
+
+    /// Makes /speed and /puckspeed show speeds in miles per hour instead of km/h.
+    pub fn with_mph(mut self, use_mph: bool) -> Self {
+        self.use_mph = use_mph;
+        self
+    }
+
+    fn handle_goal(&mut self, mut server: ServerMut, net_team: Team, puck: usize) {
+        // A puck that stays in the net or bounces back in doesn't count again until someone touches it
+        if !self.scored_pucks.insert(puck) {
+            return;
+        }
+        let team = net_team.get_other_team();
+        let values = server.scoreboard_mut();
+        match team {
+            Team::Red => values.red_score += 1,
+            Team::Blue => values.blue_score += 1,
+        }
+        let (red_score, blue_score) = (values.red_score, values.blue_score);
+
+        let scorer = self
+            .last_touch
+            .get(&puck)
+            .and_then(|player_id| server.players().get(*player_id).map(|player| player.name()));
+        let team_names = &server.config().team_names;
+        let score = format!(
+            "{} {} - {} {}",
+            team_names.red, red_score, blue_score, team_names.blue
+        );
+        let msg = if let Some(scorer) = scorer {
+            format!("Goal by {}! {}", scorer, score)
+        } else {
+            format!("{} scored! {}", team_names.name(team), score)
+        };
+        server.players_mut().add_server_chat_message(msg);
+    }
+
+    fn reset_score(&mut self, mut server: ServerMut, player_id: PlayerId) {
+        if let Some(player) = server.players_mut().check_admin_or_deny(player_id) {
+            let name = player.name();
+            let values = server.scoreboard_mut();
+            values.red_score = 0;
+            values.blue_score = 0;
+            info!("{} ({}) reset warmup score", name, player_id);
+            let msg = format!("Score reset by {}", name);
+            server.players_mut().add_server_chat_message(msg);
+        }
+    }
+    /// Handles /obstacle, which places and removes obstacles for drills.
+    fn obstacle_command(&mut self, mut server: ServerMut, arg: &str, player_id: PlayerId) {
+        let Some(name) = server
+            .players_mut()
+            .check_admin_or_deny(player_id)
+            .map(|player| player.name())
+        else {
+            return;
+        };
+        // Obstacles are placed in front of the admin, or removed from around them
+        let players = server.players();
+        let player = players.get(player_id);
+        let pos = player
+            .as_ref()
+            .and_then(|player| player.skater())
+            .map(|(_, skater)| {
+                let mut forward = skater.body.rot * -Vector3::z();
+                forward.y = 0.0;
+                (skater.body.pos, skater.body.pos + 1.5 * forward.normalize())
+            });
+        let obstacles = &mut server.rink_mut().obstacles;
+        let msg = match (arg, pos) {
+            ("clear", _) => {
+                obstacles.clear();
+                info!("{} ({}) cleared obstacles", name, player_id);
+                format!("Obstacles cleared by {}", name)
+            }
+            ("cone" | "barrier", Some((_, front))) => {
+                let obstacle = if arg == "cone" {
+                    Obstacle::cone(front)
+                } else {
+                    Obstacle::barrier(front)
+                };
+                obstacles.push(obstacle);
+                info!("{} ({}) placed {}", name, player_id, arg);
+                format!("{} placed a {}", name, arg)
+            }
+            ("remove", Some((skater_pos, _))) => {
+                let distance = |obstacle: &Obstacle| {
+                    let diff = obstacle.pos - skater_pos;
+                    diff.x.hypot(diff.z)
+                };
+                let closest = obstacles
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+                    .map(|(i, _)| i);
+                let Some(i) = closest else {
+                    server
+                        .players_mut()
+                        .add_directed_server_chat_message("There are no obstacles", player_id);
+                    return;
+                };
+                obstacles.remove(i);
+                info!("{} ({}) removed obstacle", name, player_id);
+                format!("{} removed an obstacle", name)
+            }
+            ("cone" | "barrier" | "remove", None) => {
+                server.players_mut().add_directed_server_chat_message(
+                    "You must be on the ice to place or remove obstacles",
+                    player_id,
+                );
+                return;
+            }
+            _ => {
+                server.players_mut().add_directed_server_chat_message(
+                    "Usage: /obstacle cone|barrier|remove|clear",
+                    player_id,
+                );
+                return;
+            }
+        };
+        server.players_mut().add_server_chat_message(msg);
+    }
+
+    /// Handles /layout, which saves and loads the pucks and obstacles.
+    fn layout_command(&mut self, mut server: ServerMut, arg: &str, player_id: PlayerId) {
+        let Some(layouts) = &mut self.layouts else {
+            return;
+        };
+        let Some(name) = server
+            .players_mut()
+            .check_admin_or_deny(player_id)
+            .map(|player| player.name())
+        else {
+            return;
+        };
+        let (action, layout_name) = arg.trim().split_once(' ').unwrap_or((arg.trim(), ""));
+        let layout_name = layout_name.trim();
+        match action {
+            "save" | "load" if !layouts.is_valid_name(layout_name) => {
+                server.players_mut().add_directed_server_chat_message(
+                    "Layout names can only have letters, digits, - and _",
+                    player_id,
+                );
+            }
+            "save" => {
+                let layout = Layout::new(server.pucks().iter().flatten(), &server.rink().obstacles);
+                layouts.save(layout_name, layout);
+                info!("{} ({}) saved layout {}", name, player_id, layout_name);
+                let msg = format!("Layout {} saved", layout_name);
+                server
+                    .players_mut()
+                    .add_directed_server_chat_message(msg, player_id);
+            }
+            "load" => {
+                layouts.load(layout_name, player_id, &name);
+            }
+            "list" => {
+                layouts.list(player_id);
+            }
+            _ => {
+                server.players_mut().add_directed_server_chat_message(
+                    "Usage: /layout save|load NAME, or /layout list",
+                    player_id,
+                );
+            }
+        }
+    }
+
+    /// Applies the layouts and sends the lists that have been read since the previous tick.
+    fn handle_layout_results(&mut self, mut server: ServerMut) {
+        let Some(layouts) = &mut self.layouts else {
+            return;
+        };
+        for result in layouts.results() {
+            match result {
+                LayoutResult::Loaded {
+                    player_id,
+                    player_name,
+                    layout_name,
+                    layout: Ok(layout),
+                } => {
+                    let pucks = server.pucks_mut();
+                    pucks.remove_all_pucks();
+                    let left_out = layout
+                        .create_pucks()
+                        .map(|puck| pucks.spawn_puck(puck))
+                        .filter(Option::is_none)
+                        .count();
+                    server.rink_mut().obstacles = layout.obstacles;
+                    self.last_touch.clear();
+                    self.scored_pucks.clear();
+                    info!(
+                        "{} ({}) loaded layout {}",
+                        player_name, player_id, layout_name
+                    );
+                    let msg = format!("Layout {} loaded by {}", layout_name, player_name);
+                    server.players_mut().add_server_chat_message(msg);
+                    if left_out > 0 {
+                        let msg = format!(
+                            "{} of the {} pucks of the layout did not fit",
+                            left_out,
+                            layout.pucks.len()
+                        );
+                        server
+                            .players_mut()
+                            .add_directed_server_chat_message(msg, player_id);
+                    }
+                }
+                LayoutResult::Loaded {
+                    player_id,
+                    layout_name,
+                    layout: Err(e),
+                    ..
+                } => {
+                    let msg = if e.kind() == io::ErrorKind::NotFound {
+                        format!("There is no layout named {}", layout_name)
+                    } else {
+                        warn!("Could not load layout {}: {}", layout_name, e);
+                        format!("Could not load layout {}", layout_name)
+                    };
+                    server
+                        .players_mut()
+                        .add_directed_server_chat_message(msg, player_id);
+                }
+                LayoutResult::Listed { player_id, names } => {
+                    let text = if names.is_empty() {
+                        "There are no saved layouts".to_owned()
+                    } else {
+                        format!("Layouts: {}", names.join(", "))
+                    };
+                    for line in wrap_chat_lines(&text) {
+                        server
+                            .players_mut()
+                            .add_directed_server_chat_message(line, player_id);
+                    }
+                }
+            }
+        }
+    }
+
     fn update_players(&mut self, mut server: ServerMut) {
         let spawn_point = self.spawn_point;
-        let ServerMutParts { players, rink, .. } = server.as_mut_parts();
+        let ServerMutParts {
+            mut players,
+            rink,
+            config,
+            ..
+        } = server.as_mut_parts();
         let rink = &*rink;
-        add_players(
-            players,
+        let (mut red_player_count, mut blue_player_count) = add_players(
+            players.rb_mut(),
             usize::MAX,
             &mut self.team_switch_timer,
             None,
+            &config.team_names,
             |team, _| get_spawnpoint(rink, team, spawn_point),
+            |_, _| Ok(()),
             |_| {},
             |_, _| {},
         );
+        if let Some(auto_teams) = &mut self.auto_teams {
+            auto_teams.add_queued_players(
+                players,
+                usize::MAX,
+                &mut red_player_count,
+                &mut blue_player_count,
+                |team, _| get_spawnpoint(rink, team, spawn_point),
+            );
+        }
     }
 }
 
+const RESET_SCORE_COMMAND: ChatCommand =
+    ChatCommand::new("resetscore", "Resets the warmup score").admin();
+const OBSTACLE_COMMAND: ChatCommand = ChatCommand::new(
+    "obstacle",
+    "Places a cone or a barrier in front of you, or removes obstacles with remove or clear",
+)
+.args(&[CommandArg::required(
+    "cone/barrier/remove/clear",
+    ArgKind::Word,
+)])
+.admin();
+const LAYOUT_COMMAND: ChatCommand = ChatCommand::new(
+    "layout",
+    "Saves the pucks and obstacles as NAME, loads them, or lists the saved layouts",
+)
+.args(&[
+    CommandArg::required("save/load/list", ArgKind::Word),
+    CommandArg::optional("NAME", ArgKind::Word),
+])
+.admin();
+
 impl GameMode for PermanentWarmup {
-    fn before_tick(&mut self, server: ServerMut) {
+    fn init(&mut self, mut server: ServerMut) {
+        if self.track_score {
+            server.register_command(RESET_SCORE_COMMAND);
+        }
+        server.register_command(OBSTACLE_COMMAND);
+        for command in SPEED_COMMANDS {
+            server.register_command(*command);
+        }
+        if self.layouts.is_some() {
+            server.register_command(LAYOUT_COMMAND);
+        }
+    }
+
+    fn before_tick(&mut self, mut server: ServerMut) {
+        self.handle_layout_results(server.rb_mut());
         self.update_players(server);
     }
 
-    fn after_tick(&mut self, _server: ServerMut, _events: &[PhysicsEvent]) {
-        // Nothing
+    fn after_tick(&mut self, mut server: ServerMut, events: &[PhysicsEvent]) {
+        if !self.track_score {
+            return;
+        }
+        for event in events {
+            match *event {
+                PhysicsEvent::PuckTouch { player, puck } => {
+                    self.last_touch.insert(puck, player);
+                    self.scored_pucks.remove(&puck);
+                }
+                PhysicsEvent::PuckEnteredNet { team, puck } => {
+                    self.handle_goal(server.rb_mut(), team, puck);
+                }
+                _ => {}
+            }
+        }
     }
 
-    fn handle_command(
-        &mut self,
-        _server: ServerMut,
-        _cmd: &str,
-        _arg: &str,
-        _player_index: PlayerId,
-    ) {
+    fn handle_command(&mut self, server: ServerMut, cmd: &str, arg: &str, player_id: PlayerId) {
+        match cmd {
+            "resetscore" if self.track_score => {
+                self.reset_score(server, player_id);
+            }
+            "obstacle" => {
+                self.obstacle_command(server, arg, player_id);
+            }
+            "layout" => {
+                self.layout_command(server, arg, player_id);
+            }
+            "speed" => {
+                msg_speed(server, player_id, self.use_mph);
+            }
+            "puckspeed" => {
+                let puck = self
+                    .last_touch
+                    .iter()
+                    .find_map(|(puck, toucher)| (*toucher == player_id).then_some(*puck));
+                msg_puck_speed(server, player_id, puck, self.use_mph);
+            }
+            _ => {}
+        }
     }
 
     fn get_initial_game_values(&mut self) -> InitialGameValues {
@@ -63,6 +422,8 @@ impl GameMode for PermanentWarmup {
     }
 
     fn game_started(&mut self, mut server: ServerMut) {
+        self.last_touch.clear();
+        self.scored_pucks.clear();
         let warmup_pucks = self.pucks;
         let rink = server.rink();
         let width = rink.width;
@@ -76,7 +437,19 @@ impl GameMode for PermanentWarmup {
         }
     }
 
+    fn after_player_join(&mut self, _server: ServerMut, player_id: PlayerId) {
+        if let Some(auto_teams) = &mut self.auto_teams {
+            auto_teams.player_joined(player_id);
+        }
+    }
+
     fn server_list_team_size(&self) -> u32 {
         0
     }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.layouts
+            .as_mut()
+            .map_or_else(Vec::new, LayoutStore::take_pending_saves)
+    }
 }
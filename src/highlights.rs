@@ -0,0 +1,432 @@
+//! Detection of highlight-worthy moments in recorded games, and of the goals, shots and hits
+//! counted in the [`GameStats`].
+
+use std::collections::HashMap;
+
+use nalgebra::{Point3, Vector3};
+
+use crate::game::{PhysicsEvent, PlayerId, Puck, Rink, RinkNet, ScoreboardValues, Team};
+use crate::record::{Highlight, HighlightKind};
+use crate::server::{PlayerListExt, ServerStatePlayerItem};
+use crate::stats::GameStats;
+
+/// Recording ticks included before the moment itself.
+const HIGHLIGHT_BEFORE: u32 = 500;
+/// Recording ticks included after the moment itself.
+const HIGHLIGHT_AFTER: u32 = 200;
+/// Minimum number of ticks between two highlights of the same kind.
+const HIGHLIGHT_COOLDOWN: u32 = 300;
+/// Change of skater velocity in one tick that counts as a big hit, in meters per hundred of a second.
+const BIG_HIT_VELOCITY_CHANGE: f32 = 0.025;
+/// Minimum skater speed for a breakaway, in meters per hundred of a second.
+const BREAKAWAY_SPEED: f32 = 0.03;
+/// Maximum distance between two skaters for one of them to be credited with hitting the other, in meters.
+const HIT_DISTANCE: f32 = 2.5;
+/// Minimum number of ticks between two hits credited to the same skater.
+const HIT_COOLDOWN: u32 = 100;
+/// Shots from further away than this are not counted as shots on goal, in meters.
+const SHOT_DISTANCE: f32 = 20.0;
+
+#[derive(Default)]
+pub(crate) struct HighlightDetector {
+    last_touch: HashMap<usize, PlayerId>,
+    /// The player who touched the puck before the last one, for assists.
+    previous_touch: HashMap<usize, PlayerId>,
+    /// Positions and velocities of the pucks in the previous tick, to see where a touched puck was going.
+    puck_paths: HashMap<usize, (Point3<f32>, Vector3<f32>)>,
+    velocities: HashMap<PlayerId, Vector3<f32>>,
+    last_highlight: HashMap<HighlightKind, u32>,
+    last_hit: HashMap<PlayerId, u32>,
+    last_scores: (u32, u32),
+    last_time: u32,
+    step: u32,
+}
+
+impl HighlightDetector {
+    pub(crate) fn clear(&mut self) {
+        self.last_touch.clear();
+        self.previous_touch.clear();
+        self.puck_paths.clear();
+        self.velocities.clear();
+        self.last_highlight.clear();
+        self.last_hit.clear();
+        self.last_scores = (0, 0);
+        self.last_time = 0;
+        self.step = 0;
+    }
+
+    /// Looks for highlights and stats in a tick. Highlights are added to the list if the tick is
+    /// recorded, together with its recording tick.
+    ///
+    /// Goals are counted when the scoreboard shows them, so that goals the game mode disallows are not.
+    /// Saved shots and hits are only counted while the game clock runs, and skaters that were
+    /// spawned since the last tick are not hit.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn update(
+        &mut self,
+        players: &[ServerStatePlayerItem],
+        pucks: &[Option<Puck>],
+        rink: &Rink,
+        scoreboard: &ScoreboardValues,
+        events: &[PhysicsEvent],
+        spawned: &[PlayerId],
+        recording: Option<(u32, &mut Vec<Highlight>)>,
+        stats: &mut GameStats,
+    ) {
+        self.step = self.step.wrapping_add(1);
+        let clock_running = scoreboard.period > 0 && scoreboard.time != self.last_time;
+        self.last_time = scoreboard.time;
+        let scores = (scoreboard.red_score, scoreboard.blue_score);
+        let scored = [
+            (Team::Red, scores.0 > self.last_scores.0),
+            (Team::Blue, scores.1 > self.last_scores.1),
+        ];
+        self.last_scores = scores;
+
+        for event in events {
+            match *event {
+                PhysicsEvent::PuckTouch { player, puck } => {
+                    if clock_running {
+                        self.check_saved_shot(players, rink, stats, player, puck);
+                    }
+                    if let Some(last) = self.last_touch.insert(puck, player) {
+                        if last != player {
+                            self.previous_touch.insert(puck, last);
+                        }
+                    }
+                }
+                PhysicsEvent::PuckEnteredNet { team, puck } => {
+                    let scoring_team = team.get_other_team();
+                    if scored.contains(&(scoring_team, true)) {
+                        self.add_goal(players, stats, scoring_team, puck);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut hit = None;
+        // Updated in place, this runs every tick and shouldn't allocate
+        self.velocities.retain(|player_id, _| {
+            !spawned.contains(player_id)
+                && players
+                    .get_player(*player_id)
+                    .is_some_and(|player| player.object.is_some())
+        });
+        for (player_id, player) in players.iter_players() {
+            if let Some((_, skater, _)) = &player.object {
+                let velocity = skater.body.linear_velocity;
+                if let Some(old_velocity) = self.velocities.insert(player_id, velocity) {
+                    if (velocity - old_velocity).norm() > BIG_HIT_VELOCITY_CHANGE {
+                        hit = Some(player_id);
+                        if clock_running {
+                            self.add_hit(players, stats, player_id);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some((tick, highlights)) = recording {
+            self.find_highlights(
+                players, pucks, rink, scoreboard, events, hit, tick, highlights,
+            );
+        }
+
+        self.puck_paths.clear();
+        for (i, puck) in pucks.iter().enumerate() {
+            if let Some(puck) = puck {
+                self.puck_paths
+                    .insert(i, (puck.body.pos, puck.body.linear_velocity));
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn find_highlights(
+        &mut self,
+        players: &[ServerStatePlayerItem],
+        pucks: &[Option<Puck>],
+        rink: &Rink,
+        scoreboard: &ScoreboardValues,
+        events: &[PhysicsEvent],
+        hit: Option<PlayerId>,
+        tick: u32,
+        highlights: &mut Vec<Highlight>,
+    ) {
+        for event in events {
+            match *event {
+                PhysicsEvent::PuckEnteredNet { puck, .. } => {
+                    // A goal right after a post is the same highlight
+                    if let Some(last) = highlights.last() {
+                        if last.kind == HighlightKind::Post && tick - last.tick < 100 {
+                            highlights.pop();
+                        }
+                    }
+                    let player = self.last_touch.get(&puck).copied();
+                    self.add(
+                        highlights,
+                        HighlightKind::Goal,
+                        player,
+                        players,
+                        scoreboard,
+                        tick,
+                    );
+                }
+                PhysicsEvent::PuckTouchedNet { team, puck } => {
+                    let net = match team {
+                        Team::Red => &rink.red_net,
+                        Team::Blue => &rink.blue_net,
+                    };
+                    if let Some(Some(p)) = pucks.get(puck) {
+                        // Only count touches at the mouth of the net, not the back or the sides
+                        let distance = (p.body.pos - net.left_post).dot(&net.normal);
+                        let recent_goal = self
+                            .last_highlight
+                            .get(&HighlightKind::Goal)
+                            .is_some_and(|goal_tick| tick - goal_tick < 100);
+                        if distance.abs() < 0.4 && !recent_goal {
+                            let player = self.last_touch.get(&puck).copied();
+                            self.add(
+                                highlights,
+                                HighlightKind::Post,
+                                player,
+                                players,
+                                scoreboard,
+                                tick,
+                            );
+                        }
+                    }
+                }
+                PhysicsEvent::PuckEnteredOffensiveZone { team, puck } => {
+                    if let Some(player_id) = self.last_touch.get(&puck).copied() {
+                        if is_breakaway(players, rink, player_id, team) {
+                            self.add(
+                                highlights,
+                                HighlightKind::Breakaway,
+                                Some(player_id),
+                                players,
+                                scoreboard,
+                                tick,
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if hit.is_some() {
+            self.add(
+                highlights,
+                HighlightKind::BigHit,
+                hit,
+                players,
+                scoreboard,
+                tick,
+            );
+        }
+    }
+
+    /// Credits a goal on the scoreboard to the last player of the scoring team who touched the puck,
+    /// and the assist to the one before, if they were on the same team.
+    fn add_goal(
+        &self,
+        players: &[ServerStatePlayerItem],
+        stats: &mut GameStats,
+        team: Team,
+        puck: usize,
+    ) {
+        let player_on_team = |player_id: Option<&PlayerId>| {
+            player_id
+                .and_then(|player_id| players.get_player(*player_id))
+                .filter(|player| player.team() == Some(team))
+        };
+        if let Some(scorer) = player_on_team(self.last_touch.get(&puck)) {
+            let assist = player_on_team(self.previous_touch.get(&puck));
+            stats.add_goal(
+                &scorer.player_name,
+                assist.map(|player| &player.player_name),
+            );
+        }
+    }
+
+    /// A shot on goal is saved when a player touches a puck that was last touched by an opponent,
+    /// and was on its way into the net of the player's team.
+    fn check_saved_shot(
+        &self,
+        players: &[ServerStatePlayerItem],
+        rink: &Rink,
+        stats: &mut GameStats,
+        player_id: PlayerId,
+        puck: usize,
+    ) {
+        let (Some(&shooter_id), Some((pos, velocity))) =
+            (self.last_touch.get(&puck), self.puck_paths.get(&puck))
+        else {
+            return;
+        };
+        let (Some(team), Some(shooter)) = (
+            players
+                .get_player(player_id)
+                .and_then(|player| player.team()),
+            players.get_player(shooter_id),
+        ) else {
+            return;
+        };
+        if shooter.team() == Some(team.get_other_team())
+            && is_on_target(rink.net(team), pos, velocity)
+        {
+            stats.add_shot(&shooter.player_name);
+        }
+    }
+
+    /// Credits a big hit to the closest opponent of the skater who was hit.
+    fn add_hit(
+        &mut self,
+        players: &[ServerStatePlayerItem],
+        stats: &mut GameStats,
+        player_id: PlayerId,
+    ) {
+        let Some((_, skater, team)) = players
+            .get_player(player_id)
+            .and_then(|player| player.object.as_ref())
+        else {
+            return;
+        };
+        let hitter = players
+            .iter_players()
+            .filter_map(|(hitter_id, player)| {
+                let (_, hitter, hitter_team) = player.object.as_ref()?;
+                let distance = (hitter.body.pos - skater.body.pos).norm();
+                (*hitter_team != *team && distance < HIT_DISTANCE)
+                    .then_some((hitter_id, player, distance))
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2));
+        if let Some((hitter_id, hitter, _)) = hitter {
+            let recent = self
+                .last_hit
+                .get(&hitter_id)
+                .is_some_and(|last| self.step.wrapping_sub(*last) < HIT_COOLDOWN);
+            if !recent {
+                self.last_hit.insert(hitter_id, self.step);
+                stats.add_hit(&hitter.player_name);
+            }
+        }
+    }
+
+    fn add(
+        &mut self,
+        highlights: &mut Vec<Highlight>,
+        kind: HighlightKind,
+        player: Option<PlayerId>,
+        players: &[ServerStatePlayerItem],
+        scoreboard: &ScoreboardValues,
+        tick: u32,
+    ) {
+        if let Some(last_tick) = self.last_highlight.get(&kind) {
+            if tick - last_tick < HIGHLIGHT_COOLDOWN {
+                return;
+            }
+        }
+        self.last_highlight.insert(kind, tick);
+        highlights.push(Highlight {
+            kind,
+            period: scoreboard.period,
+            time: scoreboard.time,
+            tick,
+            start: tick.saturating_sub(HIGHLIGHT_BEFORE),
+            end: tick + HIGHLIGHT_AFTER,
+            player: player
+                .and_then(|player_id| players.get_player(player_id))
+                .map(|player| player.player_name.clone()),
+        });
+    }
+}
+
+/// A breakaway is when a fast skater brings the puck into the offensive zone with at most one
+/// opponent, usually the goalie, between the skater and the goal line.
+fn is_breakaway(
+    players: &[ServerStatePlayerItem],
+    rink: &Rink,
+    player_id: PlayerId,
+    team: Team,
+) -> bool {
+    let goal_line_z = match team {
+        Team::Red => rink.blue_net.left_post.z,
+        Team::Blue => rink.red_net.left_post.z,
+    };
+    let Some((_, carrier, carrier_team)) = players
+        .get_player(player_id)
+        .and_then(|player| player.object.as_ref())
+    else {
+        return false;
+    };
+    if *carrier_team != team || carrier.body.linear_velocity.norm() < BREAKAWAY_SPEED {
+        return false;
+    }
+    let carrier_distance = (carrier.body.pos.z - goal_line_z).abs();
+    let defenders_ahead = players
+        .iter_players()
+        .filter_map(|(_, player)| player.object.as_ref())
+        .filter(|(_, skater, skater_team)| {
+            *skater_team != team && (skater.body.pos.z - goal_line_z).abs() < carrier_distance
+        })
+        .count();
+    defenders_ahead <= 1
+}
+
+/// Checks if a puck moving in a straight line would cross the goal line between the posts and
+/// below the crossbar, from not too far away.
+fn is_on_target(net: &RinkNet, pos: &Point3<f32>, velocity: &Vector3<f32>) -> bool {
+    let distance = (pos - net.left_post).dot(&net.normal);
+    let speed = -velocity.dot(&net.normal);
+    if distance <= 0.0 || distance > SHOT_DISTANCE || speed <= 0.0 {
+        return false;
+    }
+    let crossing = pos + velocity * (distance / speed);
+    let across = net.right_post - net.left_post;
+    let along = (crossing - net.left_post).dot(&across) / across.norm_squared();
+    (0.0..=1.0).contains(&along) && crossing.y < 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_on_target;
+    use crate::game::{Rink, Team};
+    use nalgebra::{Point3, Vector3};
+
+    #[test]
+    fn shots_on_target() {
+        let rink = Rink::new(30.0, 61.0, 8.5);
+        let net = rink.net(Team::Blue);
+        let out = net.normal() * 5.0;
+        let shot_from = |offset: Vector3<f32>| net.center() + out + offset;
+
+        // Straight at the net, and at the post from an angle
+        assert!(is_on_target(
+            net,
+            &shot_from(Vector3::y() * 0.1),
+            &(-out * 0.01)
+        ));
+        let post = net.left_post() + Vector3::y() * 0.1;
+        let from = shot_from(Vector3::x() * 2.0);
+        assert!(is_on_target(net, &from, &((post - from) * 0.01)));
+
+        // Wide, over the crossbar, away from the net and from too far away
+        assert!(!is_on_target(
+            net,
+            &shot_from(Vector3::x() * 2.0),
+            &(-out * 0.01)
+        ));
+        let high = Vector3::new(0.0, 0.3, 0.0) - out * 0.01;
+        assert!(!is_on_target(net, &shot_from(Vector3::zeros()), &high));
+        assert!(!is_on_target(
+            net,
+            &shot_from(Vector3::zeros()),
+            &(out * 0.01)
+        ));
+        let far = Point3::from(net.center().coords + net.normal() * 30.0);
+        assert!(!is_on_target(net, &far, &(-net.normal() * 0.1)));
+    }
+}
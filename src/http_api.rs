@@ -0,0 +1,377 @@
+//! HTTP API for server status and control.
+//!
+//! When the `[Http]` section of the configuration file has a port and an API key, the server accepts
+//! HTTP requests on that TCP port. All endpoints require the API key in an `Authorization: Bearer KEY`
+//! header. `GET /status` returns the scoreboard, the connected players and the master server announcement
+//! status as JSON. With `public_status`, it can also be used without the key, but then leaves out the
+//! master servers. The POST endpoints are `/kick`, `/message` and `/newgame`. Their parameters are given in
+//! the query string or as a form-encoded body, for example `POST /kick?player=3` or `POST /message` with
+//! the body `text=Hello`.
+//!
+//! Each connection handles a single request, and has to send it within a few seconds.
+
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::admin_commands::KickOptions;
+use crate::game::{PlayerIndex, Team};
+use crate::gamemode::GameMode;
+use crate::json::{JsonObjectWriter, ToJson};
+use crate::server::{HQMServer, HQMServerPlayer, PlayerListExt};
+
+/// Largest accepted request body. Parameters are short, so anything larger is an error.
+const MAX_BODY_LENGTH: usize = 4096;
+
+/// Largest accepted request line and headers.
+const MAX_HEADER_LENGTH: usize = 8192;
+
+/// Time a client has to send the request headers.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time a connection can take in total, from the request to the end of the response.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Name shown in chat messages for actions done through the HTTP API.
+const HTTP_API_NAME: &str = "HTTP API";
+
+enum HttpApiCall {
+    /// Status, with the master servers if the request had the API key.
+    Status {
+        full: bool,
+    },
+    /// Kick with an optional reason.
+    Kick(PlayerIndex, Option<String>),
+    Message(String),
+    NewGame,
+}
+
+/// A request to the HTTP API, handled by the server between ticks.
+pub(crate) struct HttpApiRequest {
+    peer: SocketAddr,
+    call: HttpApiCall,
+    /// JSON response body, or an error message.
+    reply: oneshot::Sender<Result<String, &'static str>>,
+}
+
+struct HttpApi {
+    api_key: String,
+    /// True if `/status` can be used without the API key.
+    public_status: bool,
+    requests: mpsc::Sender<HttpApiRequest>,
+}
+
+impl HttpApi {
+    /// Checks the key of a request, in constant time so that the key can't be guessed from response times.
+    fn is_authorized(&self, api_key: Option<&str>) -> bool {
+        api_key.is_some_and(|key| {
+            ring::constant_time::verify_slices_are_equal(key.as_bytes(), self.api_key.as_bytes())
+                .is_ok()
+        })
+    }
+}
+
+/// Starts listening for HTTP requests on `port`. Requests are sent to `requests`.
+pub(crate) async fn start(
+    port: u16,
+    api_key: String,
+    public_status: bool,
+    requests: mpsc::Sender<HttpApiRequest>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
+    info!("HTTP API listening at address {:?}", listener.local_addr()?);
+    let api = Arc::new(HttpApi {
+        api_key,
+        public_status,
+        requests,
+    });
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Could not accept HTTP connection: {}", e);
+                    continue;
+                }
+            };
+            let api = api.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let api = api.clone();
+                    async move { Ok::<_, Infallible>(respond(request, peer, &api).await) }
+                });
+                let connection = http1::Builder::new()
+                    .timer(TokioTimer::new())
+                    .header_read_timeout(HEADER_TIMEOUT)
+                    .max_buf_size(MAX_HEADER_LENGTH)
+                    .keep_alive(false)
+                    .serve_connection(TokioIo::new(stream), service);
+                match tokio::time::timeout(CONNECTION_TIMEOUT, connection).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => info!("HTTP request from {} failed: {}", peer, e),
+                    Err(_) => info!("HTTP request from {} timed out", peer),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn respond(
+    request: Request<Incoming>,
+    peer: SocketAddr,
+    api: &HttpApi,
+) -> Response<Full<Bytes>> {
+    let (status, body) = match handle_request(request, peer, api).await {
+        Ok(body) => (StatusCode::OK, body),
+        Err((status, error)) => (status, error_json(error)),
+    };
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+async fn handle_request(
+    request: Request<Incoming>,
+    peer: SocketAddr,
+    api: &HttpApi,
+) -> Result<String, (StatusCode, &'static str)> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let mut params = parse_params(request.uri().query().unwrap_or(""));
+    let authorized = api.is_authorized(
+        request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .map(str::trim),
+    );
+    let body = Limited::new(request.into_body(), MAX_BODY_LENGTH)
+        .collect()
+        .await
+        .map_err(|_| (StatusCode::PAYLOAD_TOO_LARGE, "Request body too long"))?
+        .to_bytes();
+    let body = std::str::from_utf8(&body)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid request body"))?;
+    params.extend(parse_params(body));
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let expected_method = match path.as_str() {
+        "/status" => Method::GET,
+        "/kick" | "/message" | "/newgame" => Method::POST,
+        _ => return Err((StatusCode::NOT_FOUND, "Unknown endpoint")),
+    };
+    if method != expected_method {
+        return Err(if expected_method == Method::GET {
+            (StatusCode::METHOD_NOT_ALLOWED, "Use GET")
+        } else {
+            (StatusCode::METHOD_NOT_ALLOWED, "Use POST")
+        });
+    }
+    let allowed = authorized || (path == "/status" && api.public_status);
+    if !allowed {
+        info!("HTTP request from {} to {} with wrong API key", peer, path);
+        return Err((StatusCode::UNAUTHORIZED, "Wrong API key"));
+    }
+    let call = match path.as_str() {
+        "/status" => HttpApiCall::Status { full: authorized },
+        "/kick" => {
+            let player_index = param("player")
+                .and_then(|x| x.parse::<PlayerIndex>().ok())
+                .ok_or((StatusCode::BAD_REQUEST, "Invalid player index"))?;
+            let reason = param("reason")
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(str::to_owned);
+            HttpApiCall::Kick(player_index, reason)
+        }
+        "/message" => {
+            let text = param("text")
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .ok_or((StatusCode::BAD_REQUEST, "Missing text"))?;
+            HttpApiCall::Message(text.to_owned())
+        }
+        _ => HttpApiCall::NewGame,
+    };
+    let (reply, reply_receiver) = oneshot::channel();
+    let request = HttpApiRequest { peer, call, reply };
+    if api.requests.send(request).await.is_err() {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down"));
+    }
+    match reply_receiver.await {
+        Ok(Ok(body)) => Ok(body),
+        Ok(Err(e)) => Err((StatusCode::BAD_REQUEST, e)),
+        Err(_) => Err((StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down")),
+    }
+}
+
+/// Parses form-encoded parameters, like `player=3&text=Hello+world`.
+fn parse_params(s: &str) -> Vec<(String, String)> {
+    s.split('&')
+        .filter(|x| !x.is_empty())
+        .filter_map(|x| {
+            let (key, value) = x.split_once('=').unwrap_or((x, ""));
+            Some((percent_decode(key)?, percent_decode(value)?))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn error_json(error: &str) -> String {
+    let mut out = String::new();
+    JsonObjectWriter::new(&mut out)
+        .field("ok", &false)
+        .field("error", error)
+        .finish();
+    out
+}
+
+fn ok_json() -> String {
+    let mut out = String::new();
+    JsonObjectWriter::new(&mut out).field("ok", &true).finish();
+    out
+}
+
+struct PlayerJson<'a>(PlayerIndex, &'a HQMServerPlayer);
+
+impl ToJson for PlayerJson<'_> {
+    fn write_json(&self, out: &mut String) {
+        let team = self.1.team().map(|team| match team {
+            Team::Red => "red",
+            Team::Blue => "blue",
+        });
+        JsonObjectWriter::new(out)
+            .field("index", &self.0.get())
+            .field("name", &self.1.player_name)
+            .field("team", &team)
+            .field("admin", &self.1.is_admin)
+            .finish();
+    }
+}
+
+impl HQMServer {
+    pub(crate) fn handle_http_api_request<B: GameMode>(
+        &mut self,
+        request: HttpApiRequest,
+        behaviour: &mut B,
+    ) {
+        let HttpApiRequest { peer, call, reply } = request;
+        let result = match call {
+            HttpApiCall::Status { full } => Ok(self.http_api_status(full)),
+            HttpApiCall::Kick(player_index, reason) => {
+                let options = KickOptions {
+                    reason: reason.as_deref(),
+                    ..Default::default()
+                };
+                self.remote_kick(HTTP_API_NAME, peer, player_index, options)
+                    .map(|_| ok_json())
+            }
+            HttpApiCall::Message(text) => {
+                info!("{} ({}) sent message: {}", HTTP_API_NAME, peer, text);
+                self.state.players.add_server_chat_message(text);
+                Ok(ok_json())
+            }
+            HttpApiCall::NewGame => {
+                info!("{} ({}) reset game", HTTP_API_NAME, peer);
+                self.new_game(behaviour.get_initial_game_values());
+                let msg = format!("Game reset by {}", HTTP_API_NAME);
+                self.state.players.add_server_chat_message(msg);
+                Ok(ok_json())
+            }
+        };
+        let _ = reply.send(result);
+    }
+
+    fn http_api_status(&self, full: bool) -> String {
+        let players: Vec<_> = self
+            .state
+            .players
+            .players
+            .iter_players()
+            .map(|(player_id, player)| PlayerJson(player_id.index(), player))
+            .collect();
+        let scoreboard = &self.state.scoreboard;
+        let mut out = String::new();
+        let mut json = JsonObjectWriter::new(&mut out);
+        json.field("name", &self.config.server_name)
+            .field("game_id", &self.game_id)
+            .field("period", &scoreboard.period)
+            .field("time", &scoreboard.time)
+            .field("red_score", &scoreboard.red_score)
+            .field("blue_score", &scoreboard.blue_score)
+            .field("game_over", &scoreboard.game_over)
+            .field("players", &players);
+        if full {
+            json.field("master_servers", &self.master_servers);
+        }
+        json.finish();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_params, HttpApi};
+
+    #[test]
+    fn api_keys_and_params() {
+        let (requests, _) = tokio::sync::mpsc::channel(1);
+        let api = HttpApi {
+            api_key: "secret".to_owned(),
+            public_status: false,
+            requests,
+        };
+        assert!(api.is_authorized(Some("secret")));
+        assert!(!api.is_authorized(Some("secre")));
+        assert!(!api.is_authorized(None));
+
+        assert_eq!(
+            parse_params("player=3&text=Hello+w%C3%B6rld&empty"),
+            vec![
+                ("player".to_owned(), "3".to_owned()),
+                ("text".to_owned(), "Hello wörld".to_owned()),
+                ("empty".to_owned(), String::new()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,477 @@
+//! Posting game events to Discord, and relaying Discord messages into the server chat.
+//!
+//! With a webhook URL in the `[Discord]` section, the server posts when a game starts and ends, goals with
+//! the scorer and the assist, and players joining and leaving. The events are read from a
+//! [`ServerEvent`] channel, like any other subscriber, and events that come close together are posted as one
+//! message to stay under the rate limit of the webhook.
+//!
+//! With a bot token and a channel ID, the server also checks the channel for new messages now and then, and
+//! shows them in chat as "[Discord] name: text". Messages from bots and webhooks are skipped, so that the
+//! posts of the server itself are not relayed back. Messages sent before the server started are not relayed.
+
+use std::error::Error;
+use std::time::Duration;
+
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::StatusCode;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+
+use crate::commands::wrap_chat_lines;
+use crate::json::{parse_json, JsonObjectWriter, JsonValue, ToJson};
+use crate::server::HQMServer;
+use crate::subscription::ServerEvent;
+use crate::TeamNames;
+
+const API_URL: &str = "https://discord.com/api/v10";
+
+/// Longest message Discord accepts.
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+/// Most chat lines a relayed message can take up. The rest is cut off.
+const MAX_RELAYED_LINES: usize = 3;
+
+/// Shortest time between two checks for new messages, to stay within the rate limits of Discord.
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct DiscordConfiguration {
+    /// Webhook that game events are posted to.
+    pub webhook_url: Option<String>,
+    /// Token of the bot that reads the messages of `channel_id`, to relay them into the server chat.
+    pub bot_token: Option<String>,
+    pub channel_id: Option<String>,
+    /// Time between two checks for new messages in the channel. At least one second is used.
+    pub poll_interval: Duration,
+}
+
+/// A message from Discord to show in the server chat.
+#[derive(Debug, Clone)]
+pub(crate) struct DiscordMessage {
+    author: String,
+    content: String,
+}
+
+/// Starts posting the events of `events` to the webhook, and sending the messages of the channel to `messages`.
+pub(crate) fn start(
+    config: DiscordConfiguration,
+    events: broadcast::Receiver<ServerEvent>,
+    team_names: TeamNames,
+    messages: mpsc::Sender<DiscordMessage>,
+) {
+    let client = reqwest::Client::new();
+    if let Some(webhook_url) = config.webhook_url {
+        info!("Posting game events to Discord");
+        tokio::spawn(post_events(
+            client.clone(),
+            webhook_url,
+            events,
+            EventFormatter::new(team_names),
+        ));
+    }
+    match (config.bot_token, config.channel_id) {
+        (Some(bot_token), Some(channel_id)) => {
+            info!("Relaying messages from Discord channel {}", channel_id);
+            tokio::spawn(relay_messages(
+                client,
+                bot_token,
+                channel_id,
+                config.poll_interval.max(MIN_POLL_INTERVAL),
+                messages,
+            ));
+        }
+        (Some(_), None) => {
+            warn!("Relaying Discord messages requires a channel ID, not starting it");
+        }
+        _ => {}
+    }
+}
+
+/// Turns server events into the lines posted to Discord.
+struct EventFormatter {
+    team_names: TeamNames,
+    /// Period and game over flag of the previous summary, to tell when a game starts or ends.
+    state: Option<(u32, bool)>,
+}
+
+impl EventFormatter {
+    fn new(team_names: TeamNames) -> Self {
+        EventFormatter {
+            team_names,
+            state: None,
+        }
+    }
+
+    fn score(&self, red_score: u32, blue_score: u32) -> String {
+        format!(
+            "{} {} - {} {}",
+            self.team_names.red, red_score, blue_score, self.team_names.blue
+        )
+    }
+
+    fn format(&mut self, event: &ServerEvent) -> Option<String> {
+        match event {
+            ServerEvent::PlayerJoined { name, .. } => {
+                Some(format!("{} joined the server", escape_markdown(name)))
+            }
+            ServerEvent::PlayerLeft { name, .. } => {
+                Some(format!("{} left the server", escape_markdown(name)))
+            }
+            ServerEvent::Goal {
+                team,
+                goal_player,
+                assist_player,
+                red_score,
+                blue_score,
+            } => {
+                let mut msg = format!("**Goal** for {}", self.team_names.name(*team));
+                if let Some(goal_player) = goal_player {
+                    msg.push_str(&format!(" by {}", escape_markdown(goal_player)));
+                }
+                if let Some(assist_player) = assist_player {
+                    msg.push_str(&format!(", assisted by {}", escape_markdown(assist_player)));
+                }
+                msg.push_str(&format!(" ({})", self.score(*red_score, *blue_score)));
+                Some(msg)
+            }
+            ServerEvent::Summary {
+                period,
+                red_score,
+                blue_score,
+                game_over,
+                ..
+            } => {
+                let previous = self.state.replace((*period, *game_over));
+                match previous {
+                    Some((0, _)) if *period > 0 && !*game_over => {
+                        Some("**Game started**".to_owned())
+                    }
+                    Some((_, false)) if *game_over => Some(format!(
+                        "**Game over**: {}",
+                        self.score(*red_score, *blue_score)
+                    )),
+                    _ => None,
+                }
+            }
+            ServerEvent::NewGame { .. }
+            | ServerEvent::GameStarted { .. }
+            | ServerEvent::GameOver { .. }
+            | ServerEvent::TeamChanged { .. }
+            | ServerEvent::Chat { .. }
+            | ServerEvent::Penalty { .. } => None,
+        }
+    }
+}
+
+/// Escapes the characters Discord uses for formatting, so that player names are shown as they are.
+fn escape_markdown(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(
+            c,
+            '\\' | '*' | '_' | '~' | '`' | '|' | '>' | '#' | '[' | ']'
+        ) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+async fn post_events(
+    client: reqwest::Client,
+    webhook_url: String,
+    mut events: broadcast::Receiver<ServerEvent>,
+    mut formatter: EventFormatter,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                warn!("{} events were not posted to Discord", n);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let mut lines: Vec<String> = formatter.format(&event).into_iter().collect();
+        loop {
+            match events.try_recv() {
+                Ok(event) => lines.extend(formatter.format(&event)),
+                Err(TryRecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+
+        let mut text = String::new();
+        for line in lines {
+            if !text.is_empty() && text.len() + 1 + line.len() > MAX_MESSAGE_LENGTH {
+                post(&client, &webhook_url, &std::mem::take(&mut text)).await;
+            }
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&line);
+        }
+        if !text.is_empty() {
+            post(&client, &webhook_url, &text).await;
+        }
+    }
+}
+
+struct WebhookMessage<'a>(&'a str);
+
+impl ToJson for WebhookMessage<'_> {
+    fn write_json(&self, out: &mut String) {
+        struct NoMentions;
+        impl ToJson for NoMentions {
+            fn write_json(&self, out: &mut String) {
+                let parse: &[&str] = &[];
+                JsonObjectWriter::new(out).field("parse", parse).finish();
+            }
+        }
+        JsonObjectWriter::new(out)
+            .field("content", self.0)
+            .field("allowed_mentions", &NoMentions)
+            .finish();
+    }
+}
+
+/// Posts `text` to the webhook, waiting and trying again once if the webhook is rate limited.
+async fn post(client: &reqwest::Client, webhook_url: &str, text: &str) {
+    let body = WebhookMessage(text).to_json();
+    for _ in 0..2 {
+        let result = client
+            .post(webhook_url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.clone())
+            .send()
+            .await;
+        match result {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                let seconds = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|x| x.to_str().ok())
+                    .and_then(|x| x.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                tokio::time::sleep(Duration::from_secs_f32(seconds.clamp(0.0, 60.0))).await;
+            }
+            Ok(response) => {
+                if let Err(e) = response.error_for_status() {
+                    warn!("Could not post to Discord: {}", e);
+                }
+                return;
+            }
+            Err(e) => {
+                warn!("Could not post to Discord: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn relay_messages(
+    client: reqwest::Client,
+    bot_token: String,
+    channel_id: String,
+    interval: Duration,
+    sender: mpsc::Sender<DiscordMessage>,
+) {
+    let url = format!("{}/channels/{}/messages", API_URL, channel_id);
+    let authorization = format!("Bot {}", bot_token);
+    // ID of the latest message seen, or None before the first check
+    let mut last_id: Option<u64> = None;
+    let mut timer = tokio::time::interval(interval);
+    loop {
+        timer.tick().await;
+        let mut request = client
+            .get(&url)
+            .header(AUTHORIZATION, &authorization)
+            .query(&[("limit", "50")]);
+        if let Some(last_id) = last_id.filter(|x| *x > 0) {
+            request = request.query(&[("after", last_id.to_string())]);
+        }
+        let mut messages = match fetch_messages(request).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                warn!("Could not read messages from Discord: {}", e);
+                continue;
+            }
+        };
+        messages.sort_by_key(|(id, _)| *id);
+        let first_check = last_id.is_none();
+        let last = last_id.unwrap_or(0);
+        last_id = Some(messages.last().map_or(last, |(id, _)| (*id).max(last)));
+        if first_check {
+            continue;
+        }
+        for (id, message) in messages {
+            if id <= last {
+                continue;
+            }
+            if let Some(message) = message {
+                if sender.send(message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Returns the ID of each message, with its author and text unless it should not be relayed.
+async fn fetch_messages(
+    request: reqwest::RequestBuilder,
+) -> Result<Vec<(u64, Option<DiscordMessage>)>, Box<dyn Error + Send + Sync>> {
+    let response = request.send().await?.error_for_status()?.text().await?;
+    let json = parse_json(&response).ok_or("Invalid JSON")?;
+    let messages = json.as_array().ok_or("Expected a list of messages")?;
+    Ok(messages.iter().filter_map(parse_message).collect())
+}
+
+fn parse_message(message: &JsonValue) -> Option<(u64, Option<DiscordMessage>)> {
+    let id = message.get("id")?.as_str()?.parse::<u64>().ok()?;
+    let author = message.get("author")?;
+    let relayed = message.get("webhook_id").is_none()
+        && author.get("bot").and_then(JsonValue::as_bool) != Some(true);
+    let content = to_chat_text(message.get("content")?.as_str()?);
+    // Usernames are always ASCII, display names may be in any script
+    let name = author
+        .get("global_name")
+        .and_then(JsonValue::as_str)
+        .map(to_chat_text)
+        .filter(|name| !name.is_empty())
+        .or_else(|| {
+            author
+                .get("username")
+                .and_then(JsonValue::as_str)
+                .map(to_chat_text)
+        })?;
+    let message = (relayed && !content.is_empty()).then_some(DiscordMessage {
+        author: name,
+        content,
+    });
+    Some((id, message))
+}
+
+/// Makes a text from Discord fit the chat, which only supports ASCII. Accented Latin letters lose their
+/// accents, other characters outside ASCII are left out, and whitespace is collapsed to single spaces.
+fn to_chat_text(s: &str) -> String {
+    let ascii = s
+        .chars()
+        .filter_map(|c| {
+            let c = match c {
+                ' '..='~' => c,
+                c if c.is_whitespace() => ' ',
+                'à'..='å' => 'a',
+                'À'..='Å' => 'A',
+                'ç' => 'c',
+                'Ç' => 'C',
+                'è'..='ë' => 'e',
+                'È'..='Ë' => 'E',
+                'ì'..='ï' => 'i',
+                'Ì'..='Ï' => 'I',
+                'ñ' => 'n',
+                'Ñ' => 'N',
+                'ò'..='ö' | 'ø' => 'o',
+                'Ò'..='Ö' | 'Ø' => 'O',
+                'ù'..='ü' => 'u',
+                'Ù'..='Ü' => 'U',
+                'ý' | 'ÿ' => 'y',
+                'Ý' => 'Y',
+                _ => return None,
+            };
+            Some(c)
+        })
+        .collect::<String>();
+    ascii.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl HQMServer {
+    pub(crate) fn handle_discord_message(&mut self, message: DiscordMessage) {
+        info!("[Discord] {}: {}", message.author, message.content);
+        let text = format!("[Discord] {}: {}", message.author, message.content);
+        let text = self.chat_filter.censor_words(&text);
+        for line in wrap_chat_lines(&text).into_iter().take(MAX_RELAYED_LINES) {
+            self.state.players.add_server_chat_message(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_message, EventFormatter};
+    use crate::game::{PlayerId, Team};
+    use crate::json::parse_json;
+    use crate::subscription::ServerEvent;
+    use crate::TeamNames;
+
+    fn summary(period: u32, red_score: u32, blue_score: u32, game_over: bool) -> ServerEvent {
+        ServerEvent::Summary {
+            game_id: 1,
+            period,
+            time: 0,
+            red_score,
+            blue_score,
+            game_over,
+            players: 2,
+        }
+    }
+
+    #[test]
+    fn discord_event_messages() {
+        let mut formatter = EventFormatter::new(TeamNames::default());
+        let mut format = |event| formatter.format(&event);
+        assert_eq!(
+            format(ServerEvent::PlayerJoined {
+                player_id: PlayerId::new(0, 0),
+                name: "Mr_Hockey".to_owned(),
+            })
+            .as_deref(),
+            Some("Mr\\_Hockey joined the server")
+        );
+        assert_eq!(format(summary(0, 0, 0, false)), None);
+        assert_eq!(
+            format(summary(1, 0, 0, false)).as_deref(),
+            Some("**Game started**")
+        );
+        assert_eq!(
+            format(ServerEvent::Goal {
+                team: Team::Blue,
+                goal_player: Some("A".to_owned()),
+                assist_player: Some("B".to_owned()),
+                red_score: 0,
+                blue_score: 1,
+            })
+            .as_deref(),
+            Some("**Goal** for Blue by A, assisted by B (Red 0 - 1 Blue)")
+        );
+        assert_eq!(format(summary(3, 0, 1, false)), None);
+        assert_eq!(
+            format(summary(4, 0, 1, true)).as_deref(),
+            Some("**Game over**: Red 0 - 1 Blue")
+        );
+        assert_eq!(format(summary(4, 0, 1, true)), None);
+    }
+
+    #[test]
+    fn discord_channel_messages() {
+        let json = parse_json(
+            r#"[{"id": "2", "author": {"username": "a", "global_name": "Alice"}, "content": "hi\nall"},
+                {"id": "1", "author": {"username": "bot", "bot": true}, "content": "Goal"},
+                {"id": "3", "author": {"username": "olli", "global_name": "\u30aa\u30ea"}, "content": "Hyv\u00e4\u00e4 p\u00e4iv\u00e4\u00e4 \ud83c\udfd2"}]"#,
+        )
+        .unwrap();
+        let messages: Vec<_> = json.as_array().unwrap().iter().map(parse_message).collect();
+        let (id, message) = messages[0].as_ref().unwrap();
+        let message = message.as_ref().unwrap();
+        assert_eq!(*id, 2);
+        assert_eq!(message.author, "Alice");
+        assert_eq!(message.content, "hi all");
+        assert!(matches!(messages[1], Some((1, None))));
+        let (_, message) = messages[2].as_ref().unwrap();
+        let message = message.as_ref().unwrap();
+        assert_eq!(message.author, "olli");
+        assert_eq!(message.content, "Hyvaa paivaa");
+    }
+}
@@ -0,0 +1,317 @@
+//! A stream of server events as JSON lines, for statistics sites and other tools that follow the server live.
+//!
+//! With `event_log` in the `[Server]` section, every [`ServerEvent`] except the once-a-second summaries is
+//! written as one line of JSON, to a file, as UDP datagrams or over a TCP connection. Each line has the time
+//! and the kind of event, and the fields of the event:
+//!
+//! ```text
+//! {"time":"2024-03-01T20:15:02.120Z","event":"goal","team":"red","goal_player":"Alice","assist_player":null,"red_score":1,"blue_score":0}
+//! ```
+//!
+//! If the sink can't be opened or written to, events are dropped and the sink is opened again a few seconds
+//! later, so that a stats collector can be restarted without restarting the server.
+
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::game::{PlayerId, Team};
+use crate::json::{JsonObjectWriter, ToJson};
+use crate::subscription::ServerEvent;
+
+/// Time to wait before opening the sink again after an error.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where the event log is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventLogSink {
+    /// Lines are appended to a file.
+    File(PathBuf),
+    /// Each line is sent as a UDP datagram to `host:port`.
+    Udp(String),
+    /// Lines are sent over a TCP connection to `host:port`.
+    Tcp(String),
+}
+
+impl EventLogSink {
+    /// Parses `udp://host:port` or `tcp://host:port`. Anything else is a file path.
+    pub fn parse(s: &str) -> Self {
+        if let Some(addr) = s.strip_prefix("udp://") {
+            EventLogSink::Udp(addr.to_owned())
+        } else if let Some(addr) = s.strip_prefix("tcp://") {
+            EventLogSink::Tcp(addr.to_owned())
+        } else {
+            EventLogSink::File(PathBuf::from(s))
+        }
+    }
+
+    async fn open(&self) -> io::Result<Output> {
+        match self {
+            EventLogSink::File(path) => {
+                let file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                Ok(Output::File(file))
+            }
+            EventLogSink::Udp(addr) => {
+                let addr: SocketAddr = tokio::net::lookup_host(addr)
+                    .await?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown host"))?;
+                let local: SocketAddr = if addr.is_ipv4() {
+                    (Ipv4Addr::UNSPECIFIED, 0).into()
+                } else {
+                    (Ipv6Addr::UNSPECIFIED, 0).into()
+                };
+                let socket = UdpSocket::bind(local).await?;
+                socket.connect(addr).await?;
+                Ok(Output::Udp(socket))
+            }
+            EventLogSink::Tcp(addr) => Ok(Output::Tcp(TcpStream::connect(addr).await?)),
+        }
+    }
+}
+
+impl fmt::Display for EventLogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventLogSink::File(path) => write!(f, "{}", path.display()),
+            EventLogSink::Udp(addr) => write!(f, "udp://{}", addr),
+            EventLogSink::Tcp(addr) => write!(f, "tcp://{}", addr),
+        }
+    }
+}
+
+enum Output {
+    File(tokio::fs::File),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Output {
+    async fn write(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Output::File(file) => {
+                file.write_all(line.as_bytes()).await?;
+                file.flush().await
+            }
+            Output::Udp(socket) => socket.send(line.as_bytes()).await.map(|_| ()),
+            Output::Tcp(stream) => stream.write_all(line.as_bytes()).await,
+        }
+    }
+}
+
+/// Starts writing the events of `events` to `sink`.
+pub(crate) fn start(sink: EventLogSink, events: broadcast::Receiver<ServerEvent>) {
+    info!("Writing the event log to {}", sink);
+    tokio::spawn(write_events(sink, events));
+}
+
+async fn write_events(sink: EventLogSink, mut events: broadcast::Receiver<ServerEvent>) {
+    let mut output = None;
+    let mut retry_at = None;
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                warn!("{} events were not written to the event log", n);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Some(line) = event_line(&event, Utc::now()) else {
+            continue;
+        };
+        if output.is_none() {
+            if retry_at.is_some_and(|x| Instant::now() < x) {
+                continue;
+            }
+            match sink.open().await {
+                Ok(x) => output = Some(x),
+                Err(e) => {
+                    warn!("Could not open the event log {}: {}", sink, e);
+                    retry_at = Some(Instant::now() + RETRY_INTERVAL);
+                    continue;
+                }
+            }
+        }
+        if let Some(x) = &mut output {
+            if let Err(e) = x.write(&line).await {
+                warn!("Could not write to the event log {}: {}", sink, e);
+                output = None;
+                retry_at = Some(Instant::now() + RETRY_INTERVAL);
+            }
+        }
+    }
+}
+
+struct PlayerIdJson(PlayerId);
+
+impl ToJson for PlayerIdJson {
+    fn write_json(&self, out: &mut String) {
+        self.0.to_string().write_json(out);
+    }
+}
+
+struct TeamJson(Team);
+
+impl ToJson for TeamJson {
+    fn write_json(&self, out: &mut String) {
+        match self.0 {
+            Team::Red => "red",
+            Team::Blue => "blue",
+        }
+        .write_json(out);
+    }
+}
+
+/// Returns the line written for `event`, ending with a newline, or [`None`] if the event isn't logged.
+fn event_line(event: &ServerEvent, time: DateTime<Utc>) -> Option<String> {
+    let mut out = String::new();
+    let mut json = JsonObjectWriter::new(&mut out);
+    json.field("time", &time.to_rfc3339_opts(SecondsFormat::Millis, true));
+    match event {
+        ServerEvent::NewGame { game_id } => {
+            json.field("event", "new_game").field("game_id", game_id);
+        }
+        ServerEvent::GameStarted { game_id } => {
+            json.field("event", "game_started")
+                .field("game_id", game_id);
+        }
+        ServerEvent::GameOver {
+            game_id,
+            red_score,
+            blue_score,
+        } => {
+            json.field("event", "game_over")
+                .field("game_id", game_id)
+                .field("red_score", red_score)
+                .field("blue_score", blue_score);
+        }
+        ServerEvent::PlayerJoined { player_id, name } => {
+            json.field("event", "player_joined")
+                .field("player_id", &PlayerIdJson(*player_id))
+                .field("name", name);
+        }
+        ServerEvent::PlayerLeft { player_id, name } => {
+            json.field("event", "player_left")
+                .field("player_id", &PlayerIdJson(*player_id))
+                .field("name", name);
+        }
+        ServerEvent::TeamChanged {
+            player_id,
+            name,
+            team,
+        } => {
+            json.field("event", "team_changed")
+                .field("player_id", &PlayerIdJson(*player_id))
+                .field("name", name)
+                .field("team", &team.map(TeamJson));
+        }
+        ServerEvent::Chat {
+            player_id,
+            name,
+            message,
+        } => {
+            json.field("event", "chat")
+                .field("player_id", &player_id.map(PlayerIdJson))
+                .field("name", name)
+                .field("message", message);
+        }
+        ServerEvent::Goal {
+            team,
+            goal_player,
+            assist_player,
+            red_score,
+            blue_score,
+        } => {
+            json.field("event", "goal")
+                .field("team", &TeamJson(*team))
+                .field("goal_player", goal_player)
+                .field("assist_player", assist_player)
+                .field("red_score", red_score)
+                .field("blue_score", blue_score);
+        }
+        ServerEvent::Penalty {
+            team,
+            player_id,
+            name,
+            reason,
+        } => {
+            json.field("event", "penalty")
+                .field("team", &team.map(TeamJson))
+                .field("player_id", &player_id.map(PlayerIdJson))
+                .field("name", name)
+                .field("reason", reason);
+        }
+        ServerEvent::Summary { .. } => return None,
+    }
+    json.finish();
+    out.push('\n');
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{event_line, EventLogSink};
+    use crate::game::{PlayerId, Team};
+    use crate::subscription::ServerEvent;
+
+    #[test]
+    fn event_log_lines() {
+        assert_eq!(
+            EventLogSink::parse("udp://127.0.0.1:9000"),
+            EventLogSink::Udp("127.0.0.1:9000".to_owned())
+        );
+        assert_eq!(
+            EventLogSink::parse("tcp://stats.example.com:9000"),
+            EventLogSink::Tcp("stats.example.com:9000".to_owned())
+        );
+        assert_eq!(
+            EventLogSink::parse("events.jsonl"),
+            EventLogSink::File(PathBuf::from("events.jsonl"))
+        );
+
+        let time = Utc.with_ymd_and_hms(2024, 3, 1, 20, 15, 2).unwrap();
+        let event = ServerEvent::Chat {
+            player_id: Some(PlayerId::new(3, 1)),
+            name: Some("Alice".to_owned()),
+            message: "gg \"all\"".to_owned(),
+        };
+        assert_eq!(
+            event_line(&event, time).as_deref(),
+            Some(
+                "{\"time\":\"2024-03-01T20:15:02.000Z\",\"event\":\"chat\",\"player_id\":\"3.1\",\
+                 \"name\":\"Alice\",\"message\":\"gg \\\"all\\\"\"}\n"
+            )
+        );
+        let event = ServerEvent::Penalty {
+            team: Some(Team::Blue),
+            player_id: None,
+            name: None,
+            reason: "Delay of game".to_owned(),
+        };
+        assert_eq!(
+            event_line(&event, time).as_deref(),
+            Some(
+                "{\"time\":\"2024-03-01T20:15:02.000Z\",\"event\":\"penalty\",\"team\":\"blue\",\
+                 \"player_id\":null,\"name\":null,\"reason\":\"Delay of game\"}\n"
+            )
+        );
+    }
+}
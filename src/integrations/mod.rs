@@ -0,0 +1,4 @@
+//! Integrations with other services, started by the server when they are configured.
+
+pub mod discord;
+pub mod event_log;
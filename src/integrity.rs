@@ -0,0 +1,139 @@
+//! Packet signing for clients that support it.
+//!
+//! A client can ask for packet signing when it joins. The server then creates a random key for the
+//! player and sends it in an extension message of type [`KEY_MESSAGE_TYPE`], together with a random
+//! key ID. From then on, the client appends the key ID, a counter and an HMAC-SHA256 tag of the
+//! rest of the packet to every packet it sends. Once the server has received the first correctly
+//! signed packet, unsigned or wrongly signed packets from the player's address are ignored, so that
+//! nobody else can send inputs, chat messages or exit requests in the player's name. The counter
+//! has to grow with every packet, so that captured packets can't be sent again later.
+//!
+//! The key ID identifies the player. If a signed packet arrives from an unknown address, for
+//! example because the player's NAT mapping has changed, the player with that key ID is moved to
+//! the new address.
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Extension message type used to send the key to the client.
+pub(crate) const KEY_MESSAGE_TYPE: u16 = 0xFFFF;
+
+const KEY_LENGTH: usize = 32;
+const KEY_ID_LENGTH: usize = 4;
+const COUNTER_LENGTH: usize = 8;
+const TAG_LENGTH: usize = 32;
+
+pub(crate) struct PacketIntegrity {
+    key: hmac::Key,
+    key_bytes: [u8; KEY_LENGTH],
+    key_id: u32,
+    /// Counter of the latest accepted packet, or [`None`] before the first signed packet.
+    last_counter: Option<u64>,
+}
+
+impl PacketIntegrity {
+    /// Creates a new random key, or returns [`None`] if no random data could be generated.
+    pub(crate) fn new() -> Option<Self> {
+        let random = SystemRandom::new();
+        let mut key_bytes = [0u8; KEY_LENGTH];
+        random.fill(&mut key_bytes).ok()?;
+        let mut key_id = [0u8; KEY_ID_LENGTH];
+        random.fill(&mut key_id).ok()?;
+        Some(PacketIntegrity {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key_bytes),
+            key_bytes,
+            key_id: u32::from_le_bytes(key_id),
+            last_counter: None,
+        })
+    }
+
+    /// Payload of the key message: the key followed by the key ID.
+    pub(crate) fn key_message(&self) -> Vec<u8> {
+        let mut payload = self.key_bytes.to_vec();
+        payload.extend_from_slice(&self.key_id.to_le_bytes());
+        payload
+    }
+
+    pub(crate) fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    /// Checks the key ID, counter and tag at the end of a packet, and returns the packet without them.
+    ///
+    /// Unsigned packets are accepted until the first signed packet has been received, as the client
+    /// doesn't have the key right after joining.
+    pub(crate) fn verify<'a>(&mut self, packet: &'a [u8]) -> Option<&'a [u8]> {
+        if let Some(body) = self.verify_signed(packet) {
+            return Some(body);
+        }
+        if self.last_counter.is_some() {
+            None
+        } else {
+            Some(packet)
+        }
+    }
+
+    /// Like [`verify`](Self::verify), but only accepts packets with a valid tag and a counter
+    /// larger than in any earlier packet.
+    pub(crate) fn verify_signed<'a>(&mut self, packet: &'a [u8]) -> Option<&'a [u8]> {
+        let split = packet.len().checked_sub(TAG_LENGTH)?;
+        let (signed, tag) = packet.split_at(split);
+        let split = signed.len().checked_sub(KEY_ID_LENGTH + COUNTER_LENGTH)?;
+        let (body, trailer) = signed.split_at(split);
+        let (key_id, counter) = trailer.split_at(KEY_ID_LENGTH);
+        if u32::from_le_bytes(key_id.try_into().ok()?) != self.key_id {
+            return None;
+        }
+        let counter = u64::from_le_bytes(counter.try_into().ok()?);
+        if self.last_counter.is_some_and(|last| counter <= last) {
+            return None;
+        }
+        hmac::verify(&self.key, signed, tag).ok()?;
+        self.last_counter = Some(counter);
+        Some(body)
+    }
+}
+
+/// Returns the key ID of a signed packet, without checking the tag.
+pub(crate) fn packet_key_id(packet: &[u8]) -> Option<u32> {
+    let end = packet.len().checked_sub(COUNTER_LENGTH + TAG_LENGTH)?;
+    let start = end.checked_sub(KEY_ID_LENGTH)?;
+    Some(u32::from_le_bytes(packet[start..end].try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(integrity: &PacketIntegrity, body: &[u8], counter: u64) -> Vec<u8> {
+        let mut packet = body.to_vec();
+        packet.extend_from_slice(&integrity.key_id.to_le_bytes());
+        packet.extend_from_slice(&counter.to_le_bytes());
+        let tag = hmac::sign(&integrity.key, &packet);
+        packet.extend_from_slice(tag.as_ref());
+        packet
+    }
+
+    #[test]
+    fn replayed_packets_are_rejected() {
+        let mut integrity = PacketIntegrity::new().unwrap();
+        assert_eq!(integrity.verify(b"hello"), Some(&b"hello"[..]));
+
+        let first = sign(&integrity, b"hello", 1);
+        assert_eq!(packet_key_id(&first), Some(integrity.key_id()));
+        let second = sign(&integrity, b"world", 2);
+        assert_eq!(integrity.verify(&first), Some(&b"hello"[..]));
+        assert_eq!(integrity.verify(&second), Some(&b"world"[..]));
+        assert_eq!(integrity.verify(&first), None);
+        assert_eq!(integrity.verify(&second), None);
+        assert_eq!(integrity.verify(b"hello"), None);
+
+        let mut forged = sign(&integrity, b"hello", 3);
+        forged[0] ^= 1;
+        assert_eq!(integrity.verify(&forged), None);
+        assert_eq!(
+            integrity.verify(&sign(&integrity, b"hello", 3)),
+            Some(&b"hello"[..])
+        );
+    }
+}
@@ -0,0 +1,354 @@
+//! Minimal JSON writing, used for the data the server exports as JSON, and reading, used for the responses of
+//! the services the server talks to.
+
+use std::fmt::Write;
+use std::rc::Rc;
+
+pub(crate) trait ToJson {
+    fn write_json(&self, out: &mut String);
+
+    fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out);
+        out
+    }
+}
+
+pub(crate) fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Writes a JSON object field by field.
+pub(crate) struct JsonObjectWriter<'a> {
+    out: &'a mut String,
+    first: bool,
+}
+
+impl<'a> JsonObjectWriter<'a> {
+    pub(crate) fn new(out: &'a mut String) -> Self {
+        out.push('{');
+        Self { out, first: true }
+    }
+
+    pub(crate) fn field<T: ToJson + ?Sized>(&mut self, key: &str, value: &T) -> &mut Self {
+        if !self.first {
+            self.out.push(',');
+        }
+        self.first = false;
+        write_json_string(self.out, key);
+        self.out.push(':');
+        value.write_json(self.out);
+        self
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.out.push('}');
+    }
+}
+
+impl ToJson for str {
+    fn write_json(&self, out: &mut String) {
+        write_json_string(out, self);
+    }
+}
+
+impl ToJson for String {
+    fn write_json(&self, out: &mut String) {
+        write_json_string(out, self);
+    }
+}
+
+impl ToJson for Rc<str> {
+    fn write_json(&self, out: &mut String) {
+        write_json_string(out, self);
+    }
+}
+
+impl ToJson for bool {
+    fn write_json(&self, out: &mut String) {
+        out.push_str(if *self { "true" } else { "false" });
+    }
+}
+
+macro_rules! impl_to_json_int {
+    ($($t:ty),*) => {
+        $(impl ToJson for $t {
+            fn write_json(&self, out: &mut String) {
+                let _ = write!(out, "{}", self);
+            }
+        })*
+    };
+}
+
+impl_to_json_int!(u8, u16, u32, u64, usize, i32, i64);
+
+impl ToJson for f32 {
+    fn write_json(&self, out: &mut String) {
+        if self.is_finite() {
+            let _ = write!(out, "{}", self);
+        } else {
+            out.push_str("null");
+        }
+    }
+}
+
+impl<T: ToJson + ?Sized> ToJson for &T {
+    fn write_json(&self, out: &mut String) {
+        (**self).write_json(out);
+    }
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Some(value) => value.write_json(out),
+            None => out.push_str("null"),
+        }
+    }
+}
+
+impl<T: ToJson> ToJson for [T] {
+    fn write_json(&self, out: &mut String) {
+        out.push('[');
+        for (i, value) in self.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            value.write_json(out);
+        }
+        out.push(']');
+    }
+}
+
+impl<T: ToJson> ToJson for Vec<T> {
+    fn write_json(&self, out: &mut String) {
+        self.as_slice().write_json(out);
+    }
+}
+
+/// A parsed JSON value. Numbers are kept as text, so that large IDs don't lose precision.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Returns the value of `key`, if this is an object that has it.
+    pub(crate) fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u32(&self) -> Option<u32> {
+        match self {
+            JsonValue::Number(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            JsonValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a JSON document, or returns [`None`] if it is not valid JSON.
+pub(crate) fn parse_json(s: &str) -> Option<JsonValue> {
+    let mut parser = JsonParser {
+        chars: s.chars().peekable(),
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    parser.chars.peek().is_none().then_some(value)
+}
+
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, s: &str) -> Option<()> {
+        for c in s.chars() {
+            self.chars.next_if_eq(&c)?;
+        }
+        Some(())
+    }
+
+    fn value(&mut self) -> Option<JsonValue> {
+        self.skip_whitespace();
+        match *self.chars.peek()? {
+            'n' => self.expect("null").map(|_| JsonValue::Null),
+            't' => self.expect("true").map(|_| JsonValue::Bool(true)),
+            'f' => self.expect("false").map(|_| JsonValue::Bool(false)),
+            '"' => self.string().map(JsonValue::String),
+            '[' => {
+                self.chars.next();
+                let mut values = vec![];
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&']').is_none() {
+                    loop {
+                        values.push(self.value()?);
+                        self.skip_whitespace();
+                        match self.chars.next()? {
+                            ',' => {}
+                            ']' => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                Some(JsonValue::Array(values))
+            }
+            '{' => {
+                self.chars.next();
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&'}').is_none() {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.skip_whitespace();
+                        self.expect(":")?;
+                        fields.push((key, self.value()?));
+                        self.skip_whitespace();
+                        match self.chars.next()? {
+                            ',' => {}
+                            '}' => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                Some(JsonValue::Object(fields))
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+                {
+                    number.push(c);
+                }
+                Some(JsonValue::Number(number))
+            }
+            _ => None,
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(s),
+                '\\' => match self.chars.next()? {
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // Characters outside the basic plane are written as surrogate pairs
+                        if (0xD800..0xDC00).contains(&code) {
+                            self.expect("\\u")?;
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low.checked_sub(0xDC00)?);
+                        }
+                        s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            code = code * 16 + self.chars.next()?.to_digit(16)?;
+        }
+        Some(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_json, JsonValue};
+
+    #[test]
+    fn read_json() {
+        let value = parse_json(
+            r#" [{"id": "1234567890123456789", "author": {"username": "Migö", "bot": false},
+                 "content": "Hi \"all\" 😀", "n": -1.5e3, "x": null}, []] "#,
+        )
+        .unwrap();
+        let message = &value.as_array().unwrap()[0];
+        assert_eq!(
+            message.get("id").and_then(JsonValue::as_str),
+            Some("1234567890123456789")
+        );
+        let author = message.get("author").unwrap();
+        assert_eq!(
+            author.get("username").and_then(JsonValue::as_str),
+            Some("Migö")
+        );
+        assert_eq!(author.get("bot").and_then(JsonValue::as_bool), Some(false));
+        assert_eq!(
+            message.get("content").and_then(JsonValue::as_str),
+            Some("Hi \"all\" 😀")
+        );
+        assert_eq!(
+            message.get("n"),
+            Some(&JsonValue::Number("-1.5e3".to_owned()))
+        );
+        assert_eq!(message.get("x"), Some(&JsonValue::Null));
+
+        assert_eq!(
+            parse_json(r#""\u00e9\ud83d\ude00""#),
+            Some(JsonValue::String("é😀".to_owned()))
+        );
+        assert!(parse_json("[1, 2").is_none());
+        assert!(parse_json("{} extra").is_none());
+    }
+}
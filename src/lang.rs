@@ -0,0 +1,318 @@
+//! Translations of server messages.
+//!
+//! Messages are translated by looking up their English text in a catalog, so messages that are not in
+//! the catalog, like messages with player names or numbers in them, are sent in English.
+//! Directed messages are translated into the language of the receiving player, chosen with `/lang`,
+//! and messages to everyone into the default language of the server.
+
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    English,
+    Finnish,
+    Russian,
+}
+
+impl Language {
+    /// Parses a two-letter language code, like "fi".
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.to_ascii_lowercase().as_str() {
+            "en" => Some(Language::English),
+            "fi" => Some(Language::Finnish),
+            "ru" => Some(Language::Russian),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Finnish => "fi",
+            Language::Russian => "ru",
+        }
+    }
+}
+
+/// English, Finnish and Russian text of each translated message.
+///
+/// Chat messages are sent with 7 bits for each byte, so the translations are written in ASCII, with ä and ö
+/// as a and o, and Russian transliterated into Latin letters.
+const CATALOG: &[(&str, &str, &str)] = &[
+    (
+        "Please log in before using that command",
+        "Kirjaudu sisaan ennen komennon kayttoa",
+        "Snachala voydite kak admin",
+    ),
+    ("No matches found", "Ei osumia", "Nichego ne naydeno"),
+    (
+        "No player with this ID exists",
+        "Pelaajaa ei loydy talla ID:lla",
+        "Net igroka s etim ID",
+    ),
+    (
+        "View has been restored",
+        "Nakyma palautettu",
+        "Vid vosstanovlen",
+    ),
+    (
+        "You must be a spectator to change view",
+        "Vain katsojat voivat vaihtaa nakymaa",
+        "Smena vida tolko dlya zriteley",
+    ),
+    (
+        "Stick hand will change after next intermission",
+        "Mailakasi vaihtuu seuraavalla tauolla",
+        "Khvat smenitsya v pereryve",
+    ),
+    (
+        "Too many spectators",
+        "Liikaa katsojia",
+        "Slishkom mnogo zriteley",
+    ),
+    (
+        "You are now a spectator, use /spec again to play",
+        "Olet nyt katsoja, /spec uudestaan pelataksesi",
+        "Vy zritel, /spec snova chtoby igrat",
+    ),
+    (
+        "You can join a team again",
+        "Voit taas liittya joukkueeseen",
+        "Vy snova mozhete voyti v komandu",
+    ),
+    (
+        "Team change messages activated",
+        "Joukkueen vaihtoviestit paalla",
+        "Soobshcheniya o smene komand vkl.",
+    ),
+    (
+        "Team change messages de-activated",
+        "Joukkueen vaihtoviestit pois",
+        "Soobshcheniya o smene komand vykl.",
+    ),
+    (
+        "Your report has been sent to the admins",
+        "Ilmoituksesi on lahetetty yllapidolle",
+        "Zhaloba otpravlena adminam",
+    ),
+    (
+        "Please wait before sending another report",
+        "Odota ennen uutta ilmoitusta",
+        "Podozhdite pered novoy zhaloboy",
+    ),
+    (
+        "Rewind is disabled",
+        "Kelaus ei ole kaytossa",
+        "Peremotka otklyuchena",
+    ),
+    ("Back to live", "Takaisin suoraan", "Snova v pryamom efire"),
+    (
+        "Votes are disabled",
+        "Aanestykset eivat ole kaytossa",
+        "Golosovaniya otklyucheny",
+    ),
+    (
+        "A vote is already in progress",
+        "Aanestys on jo kaynnissa",
+        "Golosovanie uzhe idet",
+    ),
+    (
+        "No vote in progress",
+        "Ei kaynnissa olevaa aanestysta",
+        "Golosovaniya net",
+    ),
+    (
+        "You can't vote on this",
+        "Et voi aanestaa tasta",
+        "Vy ne mozhete golosovat",
+    ),
+    (
+        "Please wait before starting another vote",
+        "Odota ennen uutta aanestysta",
+        "Podozhdite do novogo golosovaniya",
+    ),
+    (
+        "Type /vote yes or /vote no",
+        "Kirjoita /vote yes tai /vote no",
+        "Pishite /vote yes ili /vote no",
+    ),
+    (
+        "Intermission shootout contest is disabled",
+        "Valiaikakilpailu ei ole kaytossa",
+        "Konkurs bullitov otklyuchen",
+    ),
+    (
+        "You have already entered the shootout contest",
+        "Olet jo mukana kilpailussa",
+        "Vy uzhe uchastvuete v konkurse",
+    ),
+    (
+        "Shootout contest: type /enter to take part",
+        "Rankkarikilpailu: kirjoita /enter",
+        "Konkurs bullitov: pishite /enter",
+    ),
+    ("Offside", "Paitsio", "Ofsayd"),
+    ("Offside warning", "Paitsiovaroitus", "Ugroza ofsayda"),
+    ("Offside waved off", "Paitsio peruttu", "Ofsayd otmenen"),
+    ("Icing", "Pitka kiekko", "Probros"),
+    ("Icing warning", "Pitkan kiekon varoitus", "Ugroza probrosa"),
+    ("Icing waved off", "Pitka kiekko peruttu", "Probros otmenen"),
+    ("Frozen puck", "Kiekko jaatynyt", "Shayba zamorozhena"),
+    (
+        "Two-line pass",
+        "Kahden viivan syotto",
+        "Pas cherez dve linii",
+    ),
+    ("Goal replay", "Maalin uusinta", "Povtor gola"),
+    (
+        "The server is now open",
+        "Palvelin on nyt auki",
+        "Server otkryt",
+    ),
+    (
+        "No goals scored yet",
+        "Maaleja ei ole viela tehty",
+        "Golov poka net",
+    ),
+    (
+        "Unknown command",
+        "Tuntematon komento",
+        "Neizvestnaya komanda",
+    ),
+    (
+        "You must be on a team to become captain",
+        "Vain pelaajat voivat olla kapteeneja",
+        "Kapitan dolzhen byt v komande",
+    ),
+    (
+        "Only team captains can do that",
+        "Vain kapteenit voivat tehda taman",
+        "Tolko dlya kapitanov",
+    ),
+    (
+        "This team has been locked by its captain",
+        "Kapteeni on lukinnut joukkueen",
+        "Kapitan zakryl komandu",
+    ),
+    (
+        "This team has more players, join the other team",
+        "Joukkueessa on enemman pelaajia, valitse toinen",
+        "V etoy komande bolshe igrokov",
+    ),
+    (
+        "That player is not on your team",
+        "Pelaaja ei ole joukkueessasi",
+        "Igrok ne v vashey komande",
+    ),
+    (
+        "You can't bench yourself",
+        "Et voi siirtaa itseasi vaihtoon",
+        "Nelzya ubrat sebya",
+    ),
+    (
+        "You are sending messages too fast",
+        "Lahetat viesteja liian nopeasti",
+        "Vy pishete slishkom bystro",
+    ),
+    (
+        "Please don't repeat your messages",
+        "Ala toista viestejasi",
+        "Ne povtoryayte soobshcheniya",
+    ),
+    (
+        "Please watch your language",
+        "Pida kielenkayttosi asiallisena",
+        "Sledite za yazykom",
+    ),
+    (
+        "Vote for the MVP of the game with /mvp NAME",
+        "Aanesta ottelun parasta pelaajaa: /mvp NIMI",
+        "Golosuyte za MVP: /mvp IMYa",
+    ),
+    (
+        "No MVP vote in progress",
+        "MVP-aanestys ei ole kaynnissa",
+        "Golosovaniya za MVP net",
+    ),
+    (
+        "You can't vote for yourself",
+        "Et voi aanestaa itseasi",
+        "Nelzya golosovat za sebya",
+    ),
+    (
+        "Nobody voted for the MVP",
+        "Kukaan ei aanestanyt parasta pelaajaa",
+        "Nikto ne golosoval za MVP",
+    ),
+    (
+        "Type /help COMMAND for details",
+        "Kirjoita /help KOMENTO saadaksesi lisatietoja",
+        "Podrobnee: /help KOMANDA",
+    ),
+    (
+        "Server maintenance: you can watch, but not join a team",
+        "Huoltotila: voit katsoa, mutta et liittya peliin",
+        "Obsluzhivanie: tolko prosmotr",
+    ),
+    (
+        "All players have been moved to the spectators",
+        "Kaikki pelaajat on siirretty katsojiksi",
+        "Vse igroki perevedeny v zriteli",
+    ),
+    (
+        "Waiting for a player in each team to start the game",
+        "Odotetaan pelaajaa kumpaankin joukkueeseen",
+        "Zhdem po igroku v kazhdoy komande",
+    ),
+    (
+        "Warmup until an admin starts the game",
+        "Lammittely jatkuu, kunnes yllapitaja aloittaa pelin",
+        "Razminka do starta adminom",
+    ),
+    (
+        "The scheduled game is starting",
+        "Ajastettu ottelu alkaa",
+        "Zaplanirovannaya igra nachinaetsya",
+    ),
+    (
+        "Server is shutting down",
+        "Palvelin sammuu",
+        "Server vyklyuchaetsya",
+    ),
+    ("You are not on the ice", "Et ole jaalla", "Vy ne na ldu"),
+    (
+        "There is no puck on the ice",
+        "Jaalla ei ole kiekkoa",
+        "Na ldu net shayby",
+    ),
+];
+
+/// Translates a message, or returns it as it is if it is not in the catalog.
+pub(crate) fn translate(message: Cow<'static, str>, language: Language) -> Cow<'static, str> {
+    if language == Language::English {
+        return message;
+    }
+    match CATALOG.iter().find(|(english, _, _)| *english == message) {
+        Some((_, finnish, russian)) => Cow::Borrowed(match language {
+            Language::Finnish => finnish,
+            _ => russian,
+        }),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CATALOG;
+
+    #[test]
+    fn translations_fit_in_chat_messages() {
+        for (english, finnish, russian) in CATALOG {
+            for text in [english, finnish, russian] {
+                assert!(text.len() <= 63, "{} is too long", text);
+                assert!(text.is_ascii(), "{} can't be sent in chat", text);
+            }
+        }
+    }
+}
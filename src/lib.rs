@@ -1,15 +1,64 @@
 mod admin_commands;
 
+pub mod allocations;
+pub mod archive;
+pub mod auth;
+
 pub mod gamemode;
 
 pub mod ban;
+pub mod chat;
+pub mod commands;
+mod consistency;
+pub mod extension;
+mod file_writer;
 pub mod game;
+mod highlights;
+mod http_api;
+pub mod integrations;
+mod integrity;
+mod json;
+pub mod lang;
+pub mod live_state;
+pub mod loadtest;
+mod maintenance;
+pub mod master_server;
+mod message_log;
+mod mutes;
+pub mod observer;
 pub mod physics;
 mod protocol;
+mod query;
 pub mod record;
+mod reports;
+pub mod rng;
+pub mod schedule;
 mod server;
+mod stats;
+mod stick_check;
+pub mod subscription;
+pub mod testing;
+pub mod votes;
+mod webadmin;
+
+pub use server::{
+    run_headless, run_server, run_server_with_auth, run_server_with_master_servers, shut_down_all,
+};
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
-pub use server::run_server;
+use crate::chat::ChatConfiguration;
+use crate::extension::{Announcement, AnnouncementEvent};
+use crate::game::{BoardZone, RinkArea, Team};
+use crate::integrations::discord::DiscordConfiguration;
+use crate::integrations::event_log::EventLogSink;
+use crate::lang::Language;
+use crate::schedule::ScheduleConfiguration;
+use crate::subscription::ServerEvent;
+use crate::votes::VoteConfiguration;
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 pub enum ReplayRecording {
@@ -18,13 +67,184 @@ pub enum ReplayRecording {
     Standby,
 }
 
+/// What happens to a game when the last player leaves it.
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum AbandonPolicy {
+    /// The game is saved and a new game is started right away.
+    Reset,
+    /// The game is kept for this many ticks, so that it goes on if players come back in time.
+    /// After that, the game is saved and a new game is started.
+    Keep(u32),
+    /// The game is saved and a new game is started, which stays in the warmup until an admin starts it.
+    /// See [`GameMode::hold_warmup`](crate::gamemode::GameMode::hold_warmup).
+    Warmup,
+    /// The game is saved and the server shuts down, so that a supervisor like systemd can restart it.
+    Shutdown,
+}
+
+impl AbandonPolicy {
+    /// Parses "reset", "keep MINUTES", "warmup" or "shutdown".
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split_whitespace();
+        let policy = match parts.next()?.to_ascii_lowercase().as_str() {
+            "reset" => AbandonPolicy::Reset,
+            "keep" => {
+                let minutes = parts.next()?.parse::<f32>().ok().filter(|x| *x >= 0.0)?;
+                AbandonPolicy::Keep((minutes * 6000.0) as u32)
+            }
+            "warmup" => AbandonPolicy::Warmup,
+            "shutdown" => AbandonPolicy::Shutdown,
+            _ => return None,
+        };
+        parts.next().is_none().then_some(policy)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ServerConfiguration {
     pub welcome: Vec<String>,
     pub password: Option<String>,
     pub player_max: usize,
+    /// Maximum number of players connected from the same IP address, or [`None`] for no limit.
+    pub ip_player_max: Option<usize>,
+    /// Maximum number of players that can be pure spectators with /spec, or [`None`] for no limit.
+    pub spectator_max: Option<usize>,
+    /// IP addresses that are not affected by `ip_player_max`, for example for LAN parties.
+    pub ip_whitelist: Vec<IpAddr>,
+    /// If set, mutes are saved in this file, so that they are kept across reconnects and restarts.
+    pub mute_file: Option<PathBuf>,
 
     pub recording_enabled: ReplayRecording,
     pub server_name: String,
     pub server_service: Option<String>,
+    pub team_names: TeamNames,
+    /// Number of players in each team that bots fill up to, or 0 for no bots.
+    /// Bots leave again when human players join their team.
+    pub bots: usize,
+    /// The part of the rink used for play, for small-area games.
+    pub rink_area: RinkArea,
+    /// Parts of the boards where pucks bounce and slide differently.
+    pub board_zones: Vec<BoardZone>,
+    /// Cooldowns and permissions of chat commands, by command name.
+    pub commands: HashMap<String, CommandConfiguration>,
+    /// Seconds of live play a skater can go without changing their input before being moved
+    /// to the spectators, or 0 to never move AFK players.
+    pub afk_time: u32,
+    /// How many seconds spectators can go back with /rewind, or 0 if /rewind is disabled.
+    pub rewind_max: u32,
+    /// How many seconds of the game admins can save with /clip. The tick history is kept at least this long.
+    pub clip_max: u32,
+    /// Number of seconds of live play, with a countdown in chat, before a replay starts.
+    pub replay_countdown: u32,
+    /// Number of ticks at the start and end of each replay where the view isn't forced, to avoid abrupt view changes.
+    pub replay_view_buffer: u32,
+    /// Join and exit messages that come less than this many seconds apart are sent together, or 0 to send them right away.
+    pub join_message_window: u32,
+    /// Number of goal messages sent to players that join during a game, counting from the latest one,
+    /// or [`None`] for all of them. If set, players are also only sent the current state of every player
+    /// instead of every player update of the game.
+    pub join_history: Option<usize>,
+    /// If true, recordings include annotations for goals, stoppages and penalties.
+    /// Such recordings use a newer format version, and can't be played by the vanilla client.
+    pub recording_annotations: bool,
+    /// If true, recordings end with a chunk with the input of every skater in each recorded tick,
+    /// so that games can be simulated again for physics debugging and anti-cheat analysis.
+    pub recording_inputs: bool,
+    /// Number of ticks between consistency checks of the server state, or 0 to disable the checks.
+    /// Violations are logged as errors.
+    pub consistency_check_interval: u32,
+    /// If set, admins are told about players whose stick input often turns faster than this many radians
+    /// per tick, which hints at a macro.
+    pub stick_report_speed: Option<f32>,
+    /// Seed that the random numbers of each game are derived from, to make games reproducible.
+    /// If [`None`], a new seed is picked each time the server starts. See [`rng`].
+    pub random_seed: Option<u64>,
+    /// What happens to a game when the last player leaves it.
+    pub abandon_policy: AbandonPolicy,
+    /// If set, the score, clock and lineups are continuously written to this file as JSON.
+    pub live_state_file: Option<PathBuf>,
+    /// If set, the TCP port of the WebSocket interface for remote monitoring and administration.
+    pub web_admin_port: Option<u16>,
+    /// Address that the web admin interface listens on.
+    pub web_admin_address: IpAddr,
+    /// If set, server info queries are also answered on this UDP port, by a separate task.
+    pub query_port: Option<u16>,
+    /// If set, the TCP port of the HTTP API for server status and control.
+    pub http_port: Option<u16>,
+    /// Key required by the HTTP API endpoints.
+    pub http_api_key: Option<String>,
+    /// If true, the status endpoint of the HTTP API can be used without the key.
+    pub http_public_status: bool,
+    /// Settings for /vote.
+    pub votes: VoteConfiguration,
+    /// Settings of the chat filter.
+    pub chat: ChatConfiguration,
+    /// Language of server messages, unless players choose another language with /lang.
+    pub language: Language,
+    /// What is sent to the players when game events are announced.
+    pub announcements: HashMap<AnnouncementEvent, Announcement>,
+    /// Open hours of the server. If [`None`], the server is always open.
+    pub schedule: Option<ScheduleConfiguration>,
+    /// If set, joins, chat messages, goals, penalties, the start and end of games and a summary of the game
+    /// each second are sent on this channel.
+    /// See [`subscription`].
+    pub events: Option<tokio::sync::broadcast::Sender<ServerEvent>>,
+    /// If set, game events are posted to Discord, and Discord messages can be relayed into the chat.
+    pub discord: Option<DiscordConfiguration>,
+    /// If set, server events are written to this file or network endpoint as JSON lines.
+    pub event_log: Option<EventLogSink>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommandPermission {
+    #[default]
+    Everyone,
+    Admin,
+}
+
+/// Restrictions on a single chat command, checked before the command is run.
+#[derive(Debug, Clone, Default)]
+pub struct CommandConfiguration {
+    /// Minimum time between two uses of the command by the same player. Admins are not affected.
+    pub cooldown: Option<Duration>,
+    pub permission: CommandPermission,
+}
+
+/// Display names of the two teams, used in chat messages and exported data instead of "Red" and "Blue".
+///
+/// The client always shows the teams as red and blue, so the color hint can be used to tell which color
+/// the team has in real life, for example in recording summaries.
+#[derive(Debug, Clone)]
+pub struct TeamNames {
+    pub red: String,
+    pub blue: String,
+    pub red_color: Option<String>,
+    pub blue_color: Option<String>,
+}
+
+impl TeamNames {
+    pub fn name(&self, team: Team) -> &str {
+        match team {
+            Team::Red => &self.red,
+            Team::Blue => &self.blue,
+        }
+    }
+
+    pub fn color(&self, team: Team) -> Option<&str> {
+        match team {
+            Team::Red => self.red_color.as_deref(),
+            Team::Blue => self.blue_color.as_deref(),
+        }
+    }
+}
+
+impl Default for TeamNames {
+    fn default() -> Self {
+        Self {
+            red: "Red".to_owned(),
+            blue: "Blue".to_owned(),
+            red_color: None,
+            blue_color: None,
+        }
+    }
 }
@@ -0,0 +1,240 @@
+//! Live game state for stream overlays.
+//!
+//! [`LiveStateExport`] is an observer that keeps a JSON file with the score, clock, period, penalties and
+//! the players on the ice up to date, so that it can be shown by a browser source in OBS or similar.
+//! The file is written by a separate task started with [`start`], so a slow disk never delays the tick loop.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use tokio::sync::watch;
+use tracing::warn;
+
+use crate::game::{CustomScoreboardValue, PlayerId, RulesState, Team};
+use crate::json::{parse_json, JsonObjectWriter, ToJson};
+use crate::observer::{GameObserver, ObservedMessage, ObservedTick};
+use crate::TeamNames;
+
+/// Ticks between updates of the file.
+const UPDATE_INTERVAL: u32 = 10;
+
+/// The score, period and clock of a game, enough to resume it after it was abandoned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameSnapshot {
+    pub red_score: u32,
+    pub blue_score: u32,
+    pub period: u32,
+    /// Time left of the period, in hundredths of a second.
+    pub time: u32,
+}
+
+impl GameSnapshot {
+    /// Reads a snapshot from the contents of a live state file written by [`LiveStateExport`].
+    pub fn from_live_state(json: &str) -> Option<Self> {
+        let json = parse_json(json)?;
+        let score = |team: &str| json.get(team)?.get("score")?.as_u32();
+        Some(GameSnapshot {
+            red_score: score("red")?,
+            blue_score: score("blue")?,
+            period: json.get("period")?.as_u32()?,
+            time: json.get("time")?.as_u32()?,
+        })
+    }
+}
+
+struct LivePlayer {
+    name: String,
+    team: Option<Team>,
+}
+
+pub struct LiveStateExport {
+    team_names: TeamNames,
+    players: HashMap<PlayerId, LivePlayer>,
+    ticks: u32,
+    sender: watch::Sender<String>,
+}
+
+impl LiveStateExport {
+    /// Creates an export that sends the live state to `sender`, which is written to the file by [`start`].
+    pub fn new(team_names: TeamNames, sender: watch::Sender<String>) -> Self {
+        LiveStateExport {
+            team_names,
+            players: HashMap::new(),
+            ticks: 0,
+            sender,
+        }
+    }
+}
+
+/// Starts the task that writes each live state received from `receiver` to `path`.
+pub(crate) fn start(path: PathBuf, mut receiver: watch::Receiver<String>) {
+    tokio::spawn(async move {
+        let tmp_path = path.with_extension("tmp");
+        while receiver.changed().await.is_ok() {
+            let json = receiver.borrow_and_update().clone();
+            // Write to a temporary file first, so that readers never see a half-written file
+            let res = async {
+                tokio::fs::write(&tmp_path, json).await?;
+                tokio::fs::rename(&tmp_path, &path).await
+            }
+            .await;
+            if let Err(e) = res {
+                warn!("Could not write live state to {}: {}", path.display(), e);
+            }
+        }
+    });
+}
+
+impl GameObserver for LiveStateExport {
+    fn tick(&mut self, tick: &ObservedTick) {
+        for message in tick.messages() {
+            if let ObservedMessage::PlayerUpdate {
+                player_id,
+                player_name,
+                object,
+            } = message
+            {
+                match player_name {
+                    Some(name) => {
+                        self.players.insert(
+                            player_id,
+                            LivePlayer {
+                                name: name.to_owned(),
+                                team: object.map(|(_, team)| team),
+                            },
+                        );
+                    }
+                    None => {
+                        self.players.remove(&player_id);
+                    }
+                }
+            }
+        }
+
+        self.ticks += 1;
+        if self.ticks < UPDATE_INTERVAL {
+            return;
+        }
+        self.ticks = 0;
+
+        let scoreboard = tick.scoreboard();
+        let seconds = scoreboard.time / 100;
+        let clock = format!("{}:{:02}", seconds / 60, seconds % 60);
+        let (offside_warning, icing_warning, stoppage) = match scoreboard.rules_state {
+            RulesState::Regular {
+                offside_warning,
+                icing_warning,
+            } => (offside_warning, icing_warning, None),
+            RulesState::Offside => (false, false, Some("offside")),
+            RulesState::Icing => (false, false, Some("icing")),
+        };
+
+        let custom = tick.custom_scoreboard();
+        let mut out = String::new();
+        JsonObjectWriter::new(&mut out)
+            .field("game_id", &tick.game_id())
+            .field("period", &scoreboard.period)
+            .field("time", &scoreboard.time)
+            .field("clock", &clock)
+            .field("game_over", &scoreboard.game_over)
+            .field("offside_warning", &offside_warning)
+            .field("icing_warning", &icing_warning)
+            .field("stoppage", &stoppage)
+            .field(
+                "red",
+                &TeamJson(self, Team::Red, scoreboard.red_score, custom),
+            )
+            .field(
+                "blue",
+                &TeamJson(self, Team::Blue, scoreboard.blue_score, custom),
+            )
+            .finish();
+        self.sender.send_if_modified(|current| {
+            if *current != out {
+                *current = out;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+struct TeamJson<'a>(&'a LiveStateExport, Team, u32, &'a [CustomScoreboardValue]);
+
+impl ToJson for TeamJson<'_> {
+    fn write_json(&self, out: &mut String) {
+        let TeamJson(export, team, score, custom) = *self;
+        // Seconds left of the team's shortest penalty, as sent to clients by the match mode
+        let penalty_name = match team {
+            Team::Red => "red_penalty",
+            Team::Blue => "blue_penalty",
+        };
+        let penalty = custom
+            .iter()
+            .find(|x| x.name == penalty_name)
+            .map(|x| x.value);
+        let mut players: Vec<&str> = export
+            .players
+            .values()
+            .filter(|player| player.team == Some(team))
+            .map(|player| player.name.as_str())
+            .collect();
+        players.sort_unstable();
+        JsonObjectWriter::new(out)
+            .field("name", export.team_names.name(team))
+            .field("score", &score)
+            .field("penalty", &penalty)
+            .field("players", &players)
+            .finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::watch;
+
+    use super::{GameSnapshot, LiveStateExport, TeamJson};
+    use crate::game::{CustomScoreboardValue, Team};
+    use crate::json::ToJson;
+
+    #[test]
+    fn snapshot_from_live_state() {
+        let json = r#"{"game_id":3,"period":2,"time":12345,"clock":"2:03","game_over":false,
+            "red":{"name":"Red","score":1,"players":["Alice"]},
+            "blue":{"name":"Blue \"time\":9","score":4,"players":[]}}"#;
+        assert_eq!(
+            GameSnapshot::from_live_state(json),
+            Some(GameSnapshot {
+                red_score: 1,
+                blue_score: 4,
+                period: 2,
+                time: 12345,
+            })
+        );
+        assert_eq!(GameSnapshot::from_live_state(r#"{"period":2}"#), None);
+    }
+
+    #[test]
+    fn team_penalty() {
+        let (sender, _receiver) = watch::channel(String::new());
+        let export = LiveStateExport::new(Default::default(), sender);
+        let custom = [CustomScoreboardValue {
+            name: "blue_penalty",
+            value: 42,
+        }];
+        let json = |team| {
+            let mut out = String::new();
+            TeamJson(&export, team, 1, &custom).write_json(&mut out);
+            out
+        };
+        assert_eq!(
+            json(Team::Red),
+            r#"{"name":"Red","score":1,"penalty":null,"players":[]}"#
+        );
+        assert_eq!(
+            json(Team::Blue),
+            r#"{"name":"Blue","score":1,"penalty":42,"players":[]}"#
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Synthetic load testing.
+//!
+//! Spawns a number of simulated clients that join a server, send plausible inputs and
+//! acknowledge packets and messages the same way a real client does. The clients measure how much
+//! data the server sends and how evenly the updates arrive, which makes it possible to see how
+//! tick time and bandwidth behave when the server is full.
+
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use tokio::net::UdpSocket;
+use tokio::time::MissedTickBehavior;
+
+use crate::protocol::{HQMMessageReader, HQMMessageWriter};
+use crate::server::GAME_HEADER;
+
+/// Bit sizes of the position fields of a skater object, in the order they are sent.
+const SKATER_FIELDS: [u8; 12] = [17, 17, 17, 31, 31, 13, 13, 13, 25, 25, 16, 16];
+/// Bit sizes of the position fields of a puck object, in the order they are sent.
+const PUCK_FIELDS: [u8; 5] = [17, 17, 17, 31, 31];
+
+/// Updates arriving later than this after the previous update are counted as late.
+const LATE_UPDATE_THRESHOLD: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone)]
+pub struct LoadTestConfiguration {
+    /// Address of the server that should be tested.
+    pub server: SocketAddr,
+    /// Number of simulated clients.
+    pub clients: usize,
+    /// How long the clients should stay connected.
+    pub duration: Duration,
+    /// Prefix of the player names, the client number will be appended.
+    pub name_prefix: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoadTestReport {
+    pub clients: usize,
+    pub joined_clients: usize,
+    pub duration: Duration,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub mean_update_interval: Duration,
+    pub max_update_interval: Duration,
+    pub late_updates: u64,
+}
+
+impl Display for LoadTestReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.duration.as_secs_f64().max(0.001);
+        let per_client = self.joined_clients.max(1) as f64;
+        writeln!(
+            f,
+            "Clients: {} ({} joined), duration {:.1} s",
+            self.clients, self.joined_clients, seconds
+        )?;
+        writeln!(
+            f,
+            "Received: {} packets, {:.1} kB ({:.2} kB/s per client)",
+            self.packets_received,
+            self.bytes_received as f64 / 1000.0,
+            self.bytes_received as f64 / 1000.0 / seconds / per_client
+        )?;
+        writeln!(
+            f,
+            "Sent: {} packets, {:.1} kB ({:.2} kB/s per client)",
+            self.packets_sent,
+            self.bytes_sent as f64 / 1000.0,
+            self.bytes_sent as f64 / 1000.0 / seconds / per_client
+        )?;
+        write!(
+            f,
+            "Update interval: mean {:.2} ms, max {:.2} ms, {} late (> {} ms)",
+            self.mean_update_interval.as_secs_f64() * 1000.0,
+            self.max_update_interval.as_secs_f64() * 1000.0,
+            self.late_updates,
+            LATE_UPDATE_THRESHOLD.as_millis()
+        )
+    }
+}
+
+#[derive(Default)]
+struct ClientStats {
+    joined: bool,
+    packets_received: u64,
+    bytes_received: u64,
+    packets_sent: u64,
+    bytes_sent: u64,
+    interval_sum: Duration,
+    interval_count: u32,
+    max_interval: Duration,
+    late_updates: u64,
+}
+
+struct SimulatedClient {
+    name: String,
+    join_key: u32,
+    phase: f32,
+    game_id: u32,
+    known_packet: u32,
+    known_msgpos: u32,
+    last_update: Option<Instant>,
+    stats: ClientStats,
+}
+
+impl SimulatedClient {
+    fn new(number: usize, name_prefix: &str) -> Self {
+        SimulatedClient {
+            name: format!("{}{}", name_prefix, number),
+            // Every other client tries to join red, the rest blue
+            join_key: if number.is_multiple_of(2) { 0x4 } else { 0x8 },
+            phase: number as f32 * 0.37,
+            game_id: u32::MAX,
+            known_packet: u32::MAX,
+            known_msgpos: 0,
+            last_update: None,
+            stats: ClientStats::default(),
+        }
+    }
+
+    fn write_join(&self, buf: &mut BytesMut) {
+        buf.clear();
+        let mut writer = HQMMessageWriter::new(buf);
+        writer.write_bytes_aligned(GAME_HEADER);
+        writer.write_byte_aligned(2);
+        writer.write_bits(8, 55);
+        writer.write_bytes_aligned_padded(32, self.name.as_bytes());
+    }
+
+    fn write_update(&self, buf: &mut BytesMut, elapsed: Duration) {
+        let t = elapsed.as_secs_f32() + self.phase;
+        // Hold the join key for a while, then skate around in circles while moving the stick
+        let keys = if elapsed < Duration::from_secs(3) {
+            self.join_key
+        } else {
+            0
+        };
+
+        buf.clear();
+        let mut writer = HQMMessageWriter::new(buf);
+        writer.write_bytes_aligned(GAME_HEADER);
+        writer.write_byte_aligned(0x10);
+        writer.write_u32_aligned(self.game_id);
+        writer.write_f32_aligned((t * 1.3).sin()); // Stick angle
+        writer.write_f32_aligned((t * 0.7).sin()); // Turn
+        writer.write_f32_aligned(0.0);
+        writer.write_f32_aligned(if (t * 0.2).sin() > -0.3 { 1.0 } else { -1.0 }); // Forwards/backwards
+        writer.write_f32_aligned((t * 3.0).sin() * 0.5);
+        writer.write_f32_aligned((t * 2.0).cos() * 0.3);
+        writer.write_f32_aligned((t * 0.5).sin() * 0.5); // Head rotation
+        writer.write_f32_aligned(0.0); // Body rotation
+        writer.write_u32_aligned(keys);
+        writer.write_u32_aligned(elapsed.as_millis() as u32);
+        writer.write_u32_aligned(self.known_packet);
+        writer.write_byte_aligned((self.known_msgpos & 0xFF) as u8);
+        writer.write_byte_aligned(((self.known_msgpos >> 8) & 0xFF) as u8);
+        writer.write_bits(1, 0); // No chat message
+    }
+
+    fn handle_packet(&mut self, data: &[u8], received: Instant) {
+        self.stats.packets_received += 1;
+        self.stats.bytes_received += data.len() as u64;
+
+        let mut reader = HQMMessageReader::new(data);
+        let mut header = [0u8; 4];
+        reader.read_bytes_aligned(&mut header);
+        if header != GAME_HEADER {
+            return;
+        }
+        match reader.read_byte_aligned() {
+            6 => {
+                // New game, start over
+                self.game_id = reader.read_u32_aligned();
+                self.known_packet = u32::MAX;
+                self.known_msgpos = 0;
+                self.stats.joined = true;
+            }
+            5 => {
+                let game_id = reader.read_u32_aligned();
+                if game_id != self.game_id {
+                    return;
+                }
+                if let Some(last_update) = self.last_update {
+                    let interval = received.saturating_duration_since(last_update);
+                    self.stats.interval_sum += interval;
+                    self.stats.interval_count += 1;
+                    self.stats.max_interval = self.stats.max_interval.max(interval);
+                    if interval > LATE_UPDATE_THRESHOLD {
+                        self.stats.late_updates += 1;
+                    }
+                }
+                self.last_update = Some(received);
+
+                let _game_step = reader.read_u32_aligned();
+                // Game over, scores, time, goal message timer, period and view
+                for bits in [1, 8, 8, 16, 16, 8, 8] {
+                    reader.read_bits(bits);
+                }
+                let _deltatime = reader.read_u32_aligned();
+                let _rules = reader.read_u32_aligned();
+                let current_packet = reader.read_u32_aligned();
+                let _known_packet = reader.read_u32_aligned();
+                for _ in 0..32 {
+                    skip_object(&mut reader);
+                }
+                let message_count = reader.read_bits(4);
+                let message_start = reader.read_bits(16);
+
+                if self.known_packet == u32::MAX
+                    || current_packet.wrapping_sub(self.known_packet) < u32::MAX / 2
+                {
+                    self.known_packet = current_packet;
+                }
+                if message_start <= self.known_msgpos {
+                    self.known_msgpos = self.known_msgpos.max(message_start + message_count);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn run(mut self, server: SocketAddr, duration: Duration) -> std::io::Result<ClientStats> {
+        let local_addr = if server.is_ipv4() {
+            SocketAddr::from(([0, 0, 0, 0], 0))
+        } else {
+            SocketAddr::from(([0u16; 8], 0))
+        };
+        let socket = UdpSocket::bind(local_addr).await?;
+        socket.connect(server).await?;
+
+        let mut tick_timer = tokio::time::interval(Duration::from_millis(10));
+        tick_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let start = Instant::now();
+        let mut write_buf = BytesMut::with_capacity(512);
+        let mut read_buf = [0u8; 4096];
+
+        while start.elapsed() < duration {
+            tokio::select! {
+                _ = tick_timer.tick() => {
+                    if self.stats.joined {
+                        self.write_update(&mut write_buf, start.elapsed());
+                    } else {
+                        self.write_join(&mut write_buf);
+                    }
+                    if socket.send(&write_buf).await.is_ok() {
+                        self.stats.packets_sent += 1;
+                        self.stats.bytes_sent += write_buf.len() as u64;
+                    }
+                }
+                res = socket.recv(&mut read_buf) => {
+                    if let Ok(size) = res {
+                        self.handle_packet(&read_buf[..size], Instant::now());
+                    }
+                }
+            }
+        }
+
+        // Leave politely so that the server doesn't have to time the client out
+        write_buf.clear();
+        let mut writer = HQMMessageWriter::new(&mut write_buf);
+        writer.write_bytes_aligned(GAME_HEADER);
+        writer.write_byte_aligned(7);
+        let _ = socket.send(&write_buf).await;
+
+        Ok(self.stats)
+    }
+}
+
+fn skip_pos(reader: &mut HQMMessageReader, n: u8) {
+    let bits = match reader.read_bits(2) {
+        0 => 3,
+        1 => 6,
+        2 => 12,
+        _ => n,
+    };
+    reader.read_bits(bits);
+}
+
+pub(crate) fn skip_object(reader: &mut HQMMessageReader) {
+    if reader.read_bits(1) == 1 {
+        let fields: &[u8] = match reader.read_bits(2) {
+            0 => &SKATER_FIELDS,
+            1 => &PUCK_FIELDS,
+            _ => &[],
+        };
+        for n in fields {
+            skip_pos(reader, *n);
+        }
+    }
+}
+
+/// Runs a load test against a server, and returns a summary once all simulated clients have
+/// disconnected.
+pub async fn run_load_test(config: LoadTestConfiguration) -> std::io::Result<LoadTestReport> {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..config.clients)
+        .map(|number| {
+            let client = SimulatedClient::new(number, &config.name_prefix);
+            tokio::spawn(client.run(config.server, config.duration))
+        })
+        .collect();
+
+    let mut report = LoadTestReport {
+        clients: config.clients,
+        ..Default::default()
+    };
+    let mut interval_sum = Duration::ZERO;
+    let mut interval_count = 0u32;
+    for handle in handles {
+        let stats = handle.await.map_err(std::io::Error::other)??;
+        if stats.joined {
+            report.joined_clients += 1;
+        }
+        report.packets_received += stats.packets_received;
+        report.bytes_received += stats.bytes_received;
+        report.packets_sent += stats.packets_sent;
+        report.bytes_sent += stats.bytes_sent;
+        report.max_update_interval = report.max_update_interval.max(stats.max_interval);
+        report.late_updates += stats.late_updates;
+        interval_sum += stats.interval_sum;
+        interval_count += stats.interval_count;
+    }
+    if interval_count > 0 {
+        report.mean_update_interval = interval_sum / interval_count;
+    }
+    report.duration = start.elapsed();
+    Ok(report)
+}
@@ -1,32 +1,120 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
 
 // INI Crate For configuration
 use ini::Ini;
 use std::env;
 
+use chrono::{NaiveDate, NaiveTime};
+use futures::future::join_all;
 use ini::Properties;
+#[cfg(feature = "count-allocations")]
+use migo_hqm_server::allocations::CountingAllocator;
+use migo_hqm_server::archive::{create_archive, ArchiveFileKind, ArchiveSource};
+use migo_hqm_server::auth::{AuthProvider, FileAuthProvider};
+#[cfg(feature = "sql-bans")]
+use migo_hqm_server::ban::SqlBanCheck;
 use migo_hqm_server::ban::{BanCheck, FileBanCheck, InMemoryBanCheck};
-use migo_hqm_server::game::PhysicsConfiguration;
+use migo_hqm_server::chat::ChatConfiguration;
+use migo_hqm_server::extension::{Announcement, AnnouncementEvent};
+use migo_hqm_server::game::{BoardZone, PhysicsConfiguration, RinkArea, Team, OBJECT_SLOTS};
+use migo_hqm_server::gamemode::bots::{GoalieBotConfiguration, GoalieBotLayer};
+use migo_hqm_server::gamemode::events::{EventGameMode, EventRule};
+use migo_hqm_server::gamemode::freeforall::FreeForAllGameMode;
+use migo_hqm_server::gamemode::layers::LayeredGameMode;
+use migo_hqm_server::gamemode::playback::ReplayServerGameMode;
+use migo_hqm_server::gamemode::practice::PracticeGameMode;
+use migo_hqm_server::gamemode::profiles::{Profile, ProfileGameMode};
+use migo_hqm_server::gamemode::rotation::RotationGameMode;
 use migo_hqm_server::gamemode::russian::RussianGameMode;
 use migo_hqm_server::gamemode::shootout::ShootoutGameMode;
+use migo_hqm_server::gamemode::skills::{parse_drills, SkillsGameMode};
 use migo_hqm_server::gamemode::standard_match::{
     IcingConfiguration, MatchConfiguration, OffsideConfiguration, OffsideLineConfiguration,
-    StandardMatchGameMode, TwoLinePassConfiguration,
+    OvertimeConfiguration, PeriodRules, StandardMatchGameMode, StartTime, TwoLinePassConfiguration,
 };
 use migo_hqm_server::gamemode::util::SpawnPoint;
 use migo_hqm_server::gamemode::warmup::PermanentWarmup;
+use migo_hqm_server::gamemode::GameMode;
+use migo_hqm_server::integrations::discord::DiscordConfiguration;
+use migo_hqm_server::integrations::event_log::EventLogSink;
+use migo_hqm_server::lang::Language;
+use migo_hqm_server::master_server::{
+    parse_master_servers, MasterServerAnnouncer, DEFAULT_MASTER_SERVER,
+};
 use migo_hqm_server::record::{
     RecordingSaveMethod, RecordingSaveToFile, RecordingSendToHttpEndpoint,
 };
-use migo_hqm_server::{ReplayRecording, ServerConfiguration};
+use migo_hqm_server::schedule::{parse_open_hours, ScheduleConfiguration};
+use migo_hqm_server::votes::{VoteConfiguration, VoteEligibility};
+use migo_hqm_server::{
+    AbandonPolicy, CommandConfiguration, CommandPermission, ReplayRecording, ServerConfiguration,
+    TeamNames,
+};
+use nalgebra::Point3;
+use tracing::{error, info_span, warn, Instrument, Span};
 use tracing_appender;
 use tracing_subscriber;
 
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
 enum HQMServerMode {
     Match,
     PermanentWarmup,
     Russian,
     Shootout,
+    FreeForAll,
+    Practice,
+    Skills,
+    Replay,
+}
+
+impl HQMServerMode {
+    /// Returns the name of the game mode in the mode and rotation settings.
+    fn name(&self) -> &'static str {
+        match self {
+            HQMServerMode::Match => "match",
+            HQMServerMode::PermanentWarmup => "warmup",
+            HQMServerMode::Russian => "russian",
+            HQMServerMode::Shootout => "shootout",
+            HQMServerMode::FreeForAll => "ffa",
+            HQMServerMode::Practice => "practice",
+            HQMServerMode::Skills => "skills",
+            HQMServerMode::Replay => "replay",
+        }
+    }
+}
+
+/// Options for running games without networking to produce recordings, set with command line flags.
+struct HeadlessOptions {
+    bots: usize,
+    games: u32,
+    ticks: u32,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            bots: 4,
+            games: 1,
+            ticks: 1_000_000,
+        }
+    }
+}
+
+fn get_optional<U, F: FnOnce(&str) -> U>(
+    section: Option<&Properties>,
+    property: &str,
+    default: U,
+    f: F,
+) -> U {
+    section.and_then(|x| x.get(property)).map_or(default, f)
 }
 
 fn is_true(s: &str) -> bool {
@@ -35,371 +123,1309 @@ fn is_true(s: &str) -> bool {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    if env::args().nth(1).as_deref() == Some("archive") {
+        return run_archive(env::args().skip(2));
+    }
 
-    let config_path = if args.len() > 1 {
-        &args[1]
-    } else {
-        "config.ini"
-    };
+    let mut config_path = "config.ini".to_owned();
+    let mut headless = None;
+    for arg in env::args().skip(1) {
+        if arg == "--headless" {
+            headless.get_or_insert_with(HeadlessOptions::default);
+        } else if let Some(bots) = arg.strip_prefix("--bots=") {
+            headless.get_or_insert_with(HeadlessOptions::default).bots = bots.parse().unwrap();
+        } else if let Some(games) = arg.strip_prefix("--games=") {
+            headless.get_or_insert_with(HeadlessOptions::default).games = games.parse().unwrap();
+        } else if let Some(ticks) = arg.strip_prefix("--ticks=") {
+            headless.get_or_insert_with(HeadlessOptions::default).ticks = ticks.parse().unwrap();
+        } else {
+            config_path = arg;
+        }
+    }
+    let config_path = config_path.as_str();
 
     // Load configuration (if exists)
     if Path::new(config_path).exists() {
         // Load configuration file
         let conf = Ini::load_from_file(config_path).unwrap();
 
-        // Server information
+        // The log, the ban list and the recording saver are shared by all the servers in the process
         let server_section = conf.section(Some("Server")).unwrap();
-        let server_name = server_section
-            .get("name")
-            .unwrap()
-            .parse::<String>()
-            .unwrap();
-        let server_port = server_section.get("port").unwrap().parse::<u16>().unwrap();
-        let server_public = is_true(server_section.get("public").unwrap());
-        let public_address = if server_public {
-            Some(
-                server_section
-                    .get("public_address")
-                    .unwrap_or("https://sam2.github.io/HQMMasterServerEndpoint/"),
-            )
-        } else {
-            None
-        };
-        let server_player_max = server_section
-            .get("player_max")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap();
-        let server_team_max = server_section
-            .get("team_max")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap();
-
-        let server_password = server_section.get("password").map(|x| x.to_string());
-        let mode = server_section
-            .get("mode")
-            .map_or(HQMServerMode::Match, |x| match x {
-                "warmup" => HQMServerMode::PermanentWarmup,
-                "match" => HQMServerMode::Match,
-                "russian" => HQMServerMode::Russian,
-                "shootout" => HQMServerMode::Shootout,
-                _ => HQMServerMode::Match,
-            });
-
-        let replays_enabled = match server_section.get("replays") {
-            Some(s) if is_true(s) => ReplayRecording::On,
-            Some(s) if s.eq_ignore_ascii_case("standby") => ReplayRecording::Standby,
-            _ => ReplayRecording::Off,
-        };
-
+        let server_name = server_section.get("name").unwrap();
         let log_name = server_section
             .get("log_name")
             .map_or(format!("{}.log", server_name), |x| String::from(x));
 
-        let welcome = server_section.get("welcome").unwrap_or("");
+        let file_appender = tracing_appender::rolling::daily("log", log_name);
+        let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+        tracing_subscriber::fmt()
+            .with_line_number(false)
+            .with_file(false)
+            .with_target(false)
+            .with_writer(non_blocking)
+            .init();
 
-        let welcome_str = welcome
-            .lines()
-            .map(String::from)
-            .filter(|x| !x.is_empty())
-            .collect();
+        let ban: Rc<RefCell<dyn BanCheck>> = if let Some(ban_database) =
+            server_section.get("ban_database")
+        {
+            #[cfg(feature = "sql-bans")]
+            {
+                Rc::new(RefCell::new(SqlBanCheck::new(ban_database).await?))
+            }
+            #[cfg(not(feature = "sql-bans"))]
+            {
+                anyhow::bail!(
+                        "ban_database is set to {}, but the server was built without the sql-bans feature",
+                        ban_database
+                    );
+            }
+        } else if let Some(ban_file) = server_section.get("ban_file") {
+            Rc::new(RefCell::new(FileBanCheck::new(ban_file.into()).await?))
+        } else {
+            Rc::new(RefCell::new(InMemoryBanCheck::new()))
+        };
 
-        let replay_saving: Box<dyn RecordingSaveMethod> =
+        let replay_saving: Rc<RefCell<dyn RecordingSaveMethod>> =
             if let Some(url) = server_section.get("replay_endpoint") {
-                Box::new(RecordingSendToHttpEndpoint::new(url.to_string()))
+                Rc::new(RefCell::new(RecordingSendToHttpEndpoint::new(
+                    url.to_string(),
+                )))
             } else {
                 let dir = if let Some(path) = server_section.get("replay_directory") {
                     PathBuf::from(path)
                 } else {
                     PathBuf::from("replays")
                 };
-                Box::new(RecordingSaveToFile::new(dir))
+                Rc::new(RefCell::new(RecordingSaveToFile::new(dir)))
             };
 
-        fn get_optional<U, F: FnOnce(&str) -> U>(
-            section: Option<&Properties>,
-            property: &str,
-            default: U,
-            f: F,
-        ) -> U {
-            section.and_then(|x| x.get(property)).map_or(default, f)
+        // Each section like [Instance.casual] is a server of its own, with its own port and game mode
+        let instance_names = conf
+            .sections()
+            .flatten()
+            .filter_map(|x| x.strip_prefix("Instance."))
+            .collect::<Vec<_>>();
+        let instances = if instance_names.is_empty() {
+            vec![(None, conf.clone())]
+        } else {
+            instance_names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    let instance_conf = instance_configuration(&conf, name, i == 0);
+                    (Some(name.to_string()), instance_conf)
+                })
+                .collect()
+        };
+        check_instance_conflicts(&instances)?;
+
+        if let Some(headless) = headless {
+            // Headless games are played with the configuration of the first server
+            let (_, instance_conf) = instances.into_iter().next().unwrap();
+            run_instance(
+                instance_conf,
+                Some(headless),
+                Box::new(ban),
+                Box::new(replay_saving),
+            )
+            .await?;
+        } else {
+            // The servers run together on the same task, which is why they can share the ban list and the
+            // recording saver without locks. When one of them stops or fails, the others are shut down too,
+            // so that they get to save their games.
+            let results = join_all(instances.into_iter().map(|(name, instance_conf)| {
+                let span = match name {
+                    Some(name) => info_span!("instance", name),
+                    None => Span::none(),
+                };
+                let ban = ban.clone();
+                let replay_saving = replay_saving.clone();
+                async move {
+                    let result =
+                        run_instance(instance_conf, None, Box::new(ban), Box::new(replay_saving))
+                            .await;
+                    if let Err(e) = &result {
+                        error!("Server stopped: {:#}", e);
+                    }
+                    migo_hqm_server::shut_down_all();
+                    result
+                }
+                .instrument(span)
+            }))
+            .await;
+            results.into_iter().collect::<anyhow::Result<Vec<()>>>()?;
         }
+    } else {
+        println!("Could not open configuration file {}!", config_path);
+    };
+    Ok(())
+}
+
+/// Runs the server configured by `conf`.
+async fn run_instance(
+    conf: Ini,
+    headless: Option<HeadlessOptions>,
+    ban: Box<dyn BanCheck>,
+    replay_saving: Box<dyn RecordingSaveMethod>,
+) -> anyhow::Result<()> {
+    // Server information
+    let server_section = conf.section(Some("Server")).unwrap();
+    let server_name = server_section
+        .get("name")
+        .unwrap()
+        .parse::<String>()
+        .unwrap();
+    let server_port = server_section.get("port").unwrap().parse::<u16>().unwrap();
+    let server_public = is_true(server_section.get("public").unwrap());
+    let master_servers = if server_public {
+        let public_address = server_section
+            .get("public_address")
+            .unwrap_or(DEFAULT_MASTER_SERVER);
+        parse_master_servers(public_address, Duration::from_secs(10))
+            .unwrap_or_else(|e| panic!("Invalid public_address: {}", e))
+            .into_iter()
+            .map(|x| Box::new(x) as Box<dyn MasterServerAnnouncer>)
+            .collect()
+    } else {
+        vec![]
+    };
+    let server_player_max = server_section
+        .get("player_max")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+    let server_team_max = server_section
+        .get("team_max")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+
+    let ip_player_max = server_section
+        .get("ip_player_max")
+        .map(|x| x.parse::<usize>().unwrap());
+    let spectator_max = server_section
+        .get("spectator_max")
+        .map(|x| x.parse::<usize>().unwrap());
+    let ip_whitelist = server_section.get("ip_whitelist").map_or(vec![], |x| {
+        x.split(',')
+            .map(|ip| ip.trim().parse::<IpAddr>().unwrap())
+            .collect()
+    });
 
-        let server_service = server_section.get("service").map(|x| x.to_owned());
+    let server_password = server_section.get("password").map(|x| x.to_string());
+    let mode = server_section
+        .get("mode")
+        .map_or(HQMServerMode::Match, |x| match x {
+            "warmup" => HQMServerMode::PermanentWarmup,
+            "match" => HQMServerMode::Match,
+            "russian" => HQMServerMode::Russian,
+            "shootout" => HQMServerMode::Shootout,
+            "ffa" => HQMServerMode::FreeForAll,
+            "practice" => HQMServerMode::Practice,
+            "skills" => HQMServerMode::Skills,
+            "replay" => HQMServerMode::Replay,
+            _ => HQMServerMode::Match,
+        });
 
-        let ban_file = server_section.get("ban_file").map(|x| x.to_owned());
+    let replays_enabled = match server_section.get("replays") {
+        Some(s) if is_true(s) => ReplayRecording::On,
+        Some(s) if s.eq_ignore_ascii_case("standby") => ReplayRecording::Standby,
+        _ => ReplayRecording::Off,
+    };
 
-        // Game
-        let game_section = conf.section(Some("Game"));
+    let welcome = server_section.get("welcome").unwrap_or("");
 
-        let limit_jump_speed = get_optional(game_section, "limit_jump_speed", false, is_true);
+    let welcome_str = welcome
+        .lines()
+        .map(String::from)
+        .filter(|x| !x.is_empty())
+        .collect();
 
-        let config = ServerConfiguration {
-            welcome: welcome_str,
-            password: server_password,
-            player_max: server_player_max,
-            recording_enabled: replays_enabled,
-            server_name,
-            server_service,
-        };
+    let server_service = server_section.get("service").map(|x| x.to_owned());
 
-        // Physics
-        let physics_section = conf.section(Some("Physics"));
-        let gravity = get_optional(physics_section, "gravity", 0.000680555, |x| {
-            x.parse::<f32>().unwrap() / 10000.0
-        });
-        let player_acceleration =
-            get_optional(physics_section, "player_acceleration", 0.000208333, |x| {
-                x.parse::<f32>().unwrap() / 10000.0
-            });
-        let player_deceleration =
-            get_optional(physics_section, "player_deceleration", 0.000555555, |x| {
-                x.parse::<f32>().unwrap() / 10000.0
-            });
-        let max_player_speed = get_optional(physics_section, "max_player_speed", 0.05, |x| {
-            x.parse::<f32>().unwrap() / 100.0
+    let mute_file = server_section.get("mute_file").map(PathBuf::from);
+
+    let auth_file = server_section.get("auth_file").map(PathBuf::from);
+    let auth_guests = server_section.get("auth_guests").map_or(true, is_true);
+
+    let afk_time = server_section
+        .get("afk_time")
+        .map_or(0, |x| x.parse::<u32>().unwrap());
+    let rewind_max = server_section
+        .get("rewind_max")
+        .map_or(0, |x| x.parse::<u32>().unwrap());
+    let clip_max = server_section
+        .get("clip_max")
+        .map_or(0, |x| x.parse::<u32>().unwrap());
+
+    let bots = server_section
+        .get("bots")
+        .map_or(0, |x| x.parse::<usize>().unwrap());
+
+    let join_message_window = server_section
+        .get("join_message_window")
+        .map_or(0, |x| x.parse::<u32>().unwrap());
+
+    let consistency_check_interval = server_section
+        .get("consistency_check")
+        .map_or(0, |x| x.parse::<u32>().unwrap());
+    let abandon_policy = server_section
+        .get("abandon_policy")
+        .map_or(AbandonPolicy::Reset, |x| {
+            AbandonPolicy::parse(x).expect("Invalid abandon_policy")
         });
-        let max_player_shift_speed =
-            get_optional(physics_section, "max_player_shift_speed", 0.0333333, |x| {
-                x.parse::<f32>().unwrap() / 100.0
-            });
+    let random_seed = server_section
+        .get("random_seed")
+        .map(|x| x.parse::<u64>().unwrap());
+    let stick_report_speed = server_section
+        .get("stick_report_speed")
+        .map(|x| x.parse::<f32>().unwrap() / 100.0);
+    let replay_countdown = server_section
+        .get("replay_countdown")
+        .map_or(0, |x| x.parse::<u32>().unwrap());
+    let replay_view_buffer = server_section
+        .get("replay_view_buffer")
+        .map_or(0, |x| x.parse::<u32>().unwrap());
 
-        let puck_rink_friction = get_optional(physics_section, "puck_rink_friction", 0.05, |x| {
-            x.parse::<f32>().unwrap()
+    let join_history = server_section
+        .get("join_history")
+        .map(|x| x.parse::<usize>().unwrap());
+
+    let language = server_section
+        .get("language")
+        .map_or(Language::English, |x| {
+            Language::from_code(x).expect("Unknown language")
         });
-        let player_turning = get_optional(physics_section, "player_turning", 0.00041666666, |x| {
-            x.parse::<f32>().unwrap() / 10000.0
+    let query_port = server_section
+        .get("query_port")
+        .map(|x| x.parse::<u16>().unwrap());
+    let live_state_file = server_section.get("live_state_file").map(PathBuf::from);
+    let event_log = server_section.get("event_log").map(EventLogSink::parse);
+
+    let web_admin_port = server_section
+        .get("webadmin_port")
+        .map(|x| x.parse::<u16>().unwrap());
+    let web_admin_address = server_section
+        .get("webadmin_address")
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |x| {
+            x.parse::<IpAddr>().expect("Invalid webadmin_address")
         });
-        let player_shift_turning = get_optional(
-            physics_section,
-            "player_shift_turning",
-            0.00038888888,
-            |x| x.parse::<f32>().unwrap() / 10000.0,
-        );
 
-        let player_shift_acceleration = get_optional(
-            physics_section,
-            "player_shift_acceleration",
-            0.00027777,
-            |x| x.parse::<f32>().unwrap() / 10000.0,
-        );
+    let recording_annotations = server_section
+        .get("replay_annotations")
+        .map_or(false, is_true);
+    let recording_inputs = server_section.get("replay_inputs").map_or(false, is_true);
 
-        let physics_config = PhysicsConfiguration {
-            gravity,
-            limit_jump_speed,
-            player_acceleration,
-            player_deceleration,
-            player_shift_acceleration,
-            max_player_speed,
-            max_player_shift_speed,
-            puck_rink_friction,
-            player_turning,
-            player_shift_turning,
-        };
+    // Game
+    let game_section = conf.section(Some("Game"));
 
-        let file_appender = tracing_appender::rolling::daily("log", log_name);
-        let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
-        tracing_subscriber::fmt()
-            .with_line_number(false)
-            .with_file(false)
-            .with_target(false)
-            .with_writer(non_blocking)
-            .init();
+    let limit_jump_speed = get_optional(game_section, "limit_jump_speed", false, is_true);
 
-        let ban: Box<dyn BanCheck> = if let Some(ban_file) = ban_file.as_deref() {
-            Box::new(FileBanCheck::new(ban_file.to_string().into()).await?)
-        } else {
-            Box::new(InMemoryBanCheck::new())
-        };
+    let rink_area = get_optional(game_section, "rink", RinkArea::Full, |x| match x {
+        "half" => RinkArea::HalfIce,
+        "cross" => RinkArea::CrossIce,
+        _ => RinkArea::Full,
+    });
 
-        match mode {
-            HQMServerMode::Match => {
-                let periods =
-                    get_optional(game_section, "periods", 3, |x| x.parse::<u32>().unwrap());
-
-                let rules_time_period = get_optional(game_section, "time_period", 300, |x| {
-                    x.parse::<u32>().unwrap()
-                });
-                let rules_time_warmup = get_optional(game_section, "time_warmup", 300, |x| {
-                    x.parse::<u32>().unwrap()
-                });
-                let rule_time_break = get_optional(game_section, "time_break", 10, |x| {
-                    x.parse::<u32>().unwrap()
-                });
-                let rule_time_intermission =
-                    get_optional(game_section, "time_intermission", 20, |x| {
-                        x.parse::<u32>().unwrap()
-                    });
-                let warmup_pucks = get_optional(game_section, "warmup_pucks", 1, |x| {
-                    x.parse::<usize>().unwrap()
-                });
-
-                let mercy = get_optional(game_section, "mercy", 0, |x| x.parse::<u32>().unwrap());
-                let first_to =
-                    get_optional(game_section, "first", 0, |x| x.parse::<u32>().unwrap());
-
-                let icing = get_optional(
-                    game_section,
-                    "icing",
-                    IcingConfiguration::Off,
-                    |x| match x {
-                        "on" | "touch" => IcingConfiguration::Touch,
-                        "notouch" => IcingConfiguration::NoTouch,
-                        _ => IcingConfiguration::Off,
-                    },
-                );
+    let default_team_names = TeamNames::default();
+    let team_names = TeamNames {
+        red: server_section
+            .get("team_red_name")
+            .map_or(default_team_names.red, String::from),
+        blue: server_section
+            .get("team_blue_name")
+            .map_or(default_team_names.blue, String::from),
+        red_color: server_section.get("team_red_color").map(String::from),
+        blue_color: server_section.get("team_blue_color").map(String::from),
+    };
 
-                let offside = get_optional(
-                    game_section,
-                    "offside",
-                    OffsideConfiguration::Off,
-                    |x| match x {
-                        "on" | "delayed" => OffsideConfiguration::Delayed,
-                        "immediate" | "imm" => OffsideConfiguration::Immediate,
-                        _ => OffsideConfiguration::Off,
-                    },
-                );
+    // HTTP API
+    let http_section = conf.section(Some("Http"));
+    let http_port = http_section
+        .and_then(|x| x.get("port"))
+        .map(|x| x.parse::<u16>().unwrap());
+    let http_api_key = http_section
+        .and_then(|x| x.get("api_key"))
+        .map(String::from);
+    let http_public_status = get_optional(http_section, "public_status", false, is_true);
 
-                let offside_line = get_optional(
-                    game_section,
-                    "offsideline",
-                    OffsideLineConfiguration::OffensiveBlue,
-                    |x| match x {
-                        "blue" => OffsideLineConfiguration::OffensiveBlue,
-                        "center" => OffsideLineConfiguration::Center,
-                        _ => OffsideLineConfiguration::OffensiveBlue,
-                    },
-                );
+    // Commands
+    let mut commands: HashMap<String, CommandConfiguration> = HashMap::new();
+    if let Some(commands_section) = conf.section(Some("Commands")) {
+        for (key, value) in commands_section.iter() {
+            if let Some(command) = key.strip_suffix("_cooldown") {
+                let seconds = value.parse::<u64>().unwrap();
+                commands.entry(command.to_owned()).or_default().cooldown =
+                    Some(Duration::from_secs(seconds));
+            } else if let Some(command) = key.strip_suffix("_permission") {
+                let permission = match value {
+                    "admin" => CommandPermission::Admin,
+                    _ => CommandPermission::Everyone,
+                };
+                commands.entry(command.to_owned()).or_default().permission = permission;
+            }
+        }
+    }
 
-                let twoline_pass = get_optional(
-                    game_section,
-                    "twolinepass",
-                    TwoLinePassConfiguration::Off,
-                    |x| match x {
-                        "on" => TwoLinePassConfiguration::On,
-                        "forward" => TwoLinePassConfiguration::Forward,
-                        "double" | "both" => TwoLinePassConfiguration::Double,
-                        "blue" | "three" | "threeline" => TwoLinePassConfiguration::ThreeLine,
-                        _ => TwoLinePassConfiguration::Off,
-                    },
-                );
+    // Votes
+    let mut votes = VoteConfiguration::default();
+    if let Some(votes_section) = conf.section(Some("Votes")) {
+        votes.enabled = true;
+        for (key, value) in votes_section.iter() {
+            match key {
+                "threshold" => votes.threshold = value.parse::<f32>().unwrap() / 100.0,
+                "duration" => votes.duration = Duration::from_secs(value.parse().unwrap()),
+                "cooldown" => votes.cooldown = Duration::from_secs(value.parse().unwrap()),
+                "eligibility" => {
+                    votes.eligibility = match value {
+                        "team" => VoteEligibility::Team,
+                        _ => VoteEligibility::All,
+                    }
+                }
+                _ => {
+                    if let Some(vote_type) = key.strip_suffix("_threshold") {
+                        let threshold = value.parse::<f32>().unwrap() / 100.0;
+                        votes.thresholds.insert(vote_type.to_owned(), threshold);
+                    }
+                }
+            }
+        }
+    }
 
-                let spawn_point =
-                    get_optional(game_section, "spawn", SpawnPoint::Center, |x| match x {
-                        "bench" => SpawnPoint::Bench,
-                        _ => SpawnPoint::Center,
-                    });
+    // Chat
+    let mut chat = ChatConfiguration::default();
+    if let Some(chat_section) = conf.section(Some("Chat")) {
+        for (key, value) in chat_section.iter() {
+            match key {
+                "rate_limit" => chat.rate_limit = value.parse().unwrap(),
+                "rate_window" => chat.rate_window = Duration::from_secs(value.parse().unwrap()),
+                "repeat_window" => chat.repeat_window = Duration::from_secs(value.parse().unwrap()),
+                "word_file" => chat.word_file = Some(PathBuf::from(value)),
+                "mute_after" => chat.mute_after = value.parse().unwrap(),
+                "mute_minutes" => chat.mute_minutes = value.parse().unwrap(),
+                _ => {}
+            }
+        }
+    }
 
-                let spawn_point_offset = get_optional(game_section, "spawn_offset", 2.75f32, |x| {
-                    x.parse::<f32>().unwrap()
-                });
-
-                let spawn_player_altitude =
-                    get_optional(game_section, "spawn_player_altitude", 1.5f32, |x| {
-                        x.parse::<f32>().unwrap()
-                    });
-
-                let spawn_puck_altitude =
-                    get_optional(game_section, "spawn_puck_altitude", 1.5f32, |x| {
-                        x.parse::<f32>().unwrap()
-                    });
-
-                let spawn_keep_stick_position =
-                    get_optional(game_section, "spawn_player_keep_stick", false, is_true);
-
-                let use_mph = get_optional(game_section, "use_mph", false, is_true);
-
-                let goal_replay = get_optional(game_section, "goal_replay", false, is_true);
-
-                let match_config = MatchConfiguration {
-                    time_period: rules_time_period,
-                    time_warmup: rules_time_warmup,
-                    time_break: rule_time_break,
-                    time_intermission: rule_time_intermission,
-                    mercy,
-                    first_to,
-                    icing,
-                    offside,
-                    offside_line,
-                    twoline_pass,
-                    warmup_pucks,
-                    use_mph,
-                    goal_replay,
-                    periods,
-                    spawn_point_offset,
-                    spawn_player_altitude,
-                    spawn_puck_altitude,
-                    spawn_keep_stick_position,
+    // Announcements
+    let mut announcements = HashMap::new();
+    if let Some(announcements_section) = conf.section(Some("Announcements")) {
+        for (key, value) in announcements_section.iter() {
+            if let Some(event) = AnnouncementEvent::from_name(key) {
+                let (code, text) = match value.split_once(',') {
+                    Some((code, text)) => (code, Some(text.trim().to_owned())),
+                    None => (value, None),
                 };
+                let code = code.trim().parse::<u16>().unwrap();
+                announcements.insert(event, Announcement { code, text });
+            }
+        }
+    }
+
+    // Open hours
+    let schedule = conf.section(Some("Schedule")).map(|schedule_section| {
+        // Without open hours the server would never open
+        if schedule_section.get("open").is_none() {
+            panic!("The Schedule section needs open hours, like open=mon-fri 18:00-22:00");
+        }
+        let mut schedule = ScheduleConfiguration::default();
+        for (key, value) in schedule_section.iter() {
+            match key {
+                "open" => schedule.periods = parse_open_hours(value).expect("Invalid open hours"),
+                "warmup" => schedule.warmup = value.parse().unwrap(),
+                "close_notice" => schedule.close_notice = value.parse().unwrap(),
+                _ => {}
+            }
+        }
+        schedule
+    });
+
+    // Discord
+    let discord = match conf.section(Some("Discord")) {
+        Some(discord_section) => {
+            let poll_interval = match discord_section.get("poll_interval") {
+                Some(x) => x.parse::<u64>().ok().filter(|x| *x > 0).ok_or_else(|| {
+                    anyhow::anyhow!("Invalid poll_interval in the Discord section: {}", x)
+                })?,
+                None => 5,
+            };
+            Some(DiscordConfiguration {
+                webhook_url: discord_section.get("webhook_url").map(String::from),
+                bot_token: discord_section.get("bot_token").map(String::from),
+                channel_id: discord_section.get("channel_id").map(String::from),
+                poll_interval: Duration::from_secs(poll_interval),
+            })
+        }
+        None => None,
+    };
+
+    let config = ServerConfiguration {
+        welcome: welcome_str,
+        password: server_password,
+        player_max: server_player_max,
+        ip_player_max,
+        spectator_max,
+        ip_whitelist,
+        mute_file,
+        recording_enabled: replays_enabled,
+        server_name,
+        server_service,
+        team_names,
+        bots,
+        rink_area,
+        board_zones: board_zones(&conf),
+        commands,
+        afk_time,
+        rewind_max,
+        clip_max,
+        replay_countdown,
+        consistency_check_interval,
+        stick_report_speed,
+        random_seed,
+        abandon_policy,
+        replay_view_buffer,
+        join_message_window,
+        join_history,
+        recording_annotations,
+        recording_inputs,
+        live_state_file,
+        web_admin_port,
+        web_admin_address,
+        query_port,
+        http_port,
+        http_api_key,
+        http_public_status,
+        votes,
+        chat,
+        language,
+        announcements,
+        schedule,
+        events: None,
+        discord,
+        event_log,
+    };
+
+    // Physics
+    let physics_section = conf.section(Some("Physics"));
+    let physics_config = physics_configuration(physics_section, limit_jump_speed);
+
+    let auth: Option<Box<dyn AuthProvider>> = if let Some(auth_file) = auth_file.as_deref() {
+        Some(Box::new(
+            FileAuthProvider::new(auth_file, auth_guests).await?,
+        ))
+    } else {
+        None
+    };
+
+    let rotation = server_section.get("rotation");
+    let profile_names = conf
+        .sections()
+        .flatten()
+        .filter_map(|x| x.strip_prefix("Profile."))
+        .collect::<Vec<_>>();
+    let game_mode: Box<dyn GameMode> = if rotation.is_none()
+        && matches!(mode, HQMServerMode::Replay)
+    {
+        let path = game_section
+            .and_then(|x| x.get("replay_file"))
+            .expect("Replay mode requires replay_file");
+        let recording = tokio::fs::read(path).await?;
+        Box::new(ReplayServerGameMode::new(&recording)?)
+    } else if profile_names.is_empty() {
+        create_game_mode(
+            &conf,
+            game_section,
+            rotation,
+            &mode,
+            server_team_max,
+            server_player_max,
+        )
+    } else {
+        // Each profile can override the Game and Physics sections with its own section, like [Profile.casual]
+        let mut profiles = vec![Profile {
+            name: "default".to_owned(),
+            game_mode: create_game_mode(
+                &conf,
+                game_section,
+                rotation,
+                &mode,
+                server_team_max,
+                server_player_max,
+            ),
+            physics: physics_config.clone(),
+        }];
+        for name in profile_names {
+            let profile_section = conf.section(Some(format!("Profile.{}", name)));
+            let profile_game_section = merge_sections(game_section, profile_section);
+            let profile_physics_section = merge_sections(physics_section, profile_section);
+            let limit_jump_speed = get_optional(
+                Some(&profile_game_section),
+                "limit_jump_speed",
+                false,
+                is_true,
+            );
+            profiles.push(Profile {
+                name: name.to_owned(),
+                game_mode: create_game_mode(
+                    &conf,
+                    Some(&profile_game_section),
+                    rotation,
+                    &mode,
+                    server_team_max,
+                    server_player_max,
+                ),
+                physics: physics_configuration(Some(&profile_physics_section), limit_jump_speed),
+            });
+        }
+        Box::new(ProfileGameMode::new(profiles).unwrap())
+    };
+    let event_rules = event_rules(&conf);
+    let game_mode: Box<dyn GameMode> = if event_rules.is_empty()
+        || (rotation.is_none() && matches!(mode, HQMServerMode::Replay))
+    {
+        game_mode
+    } else {
+        Box::new(EventGameMode::new(game_mode, event_rules))
+    };
 
-                migo_hqm_server::run_server(
-                    server_port,
-                    public_address,
-                    config,
-                    physics_config,
-                    ban,
-                    replay_saving,
-                    StandardMatchGameMode::new(match_config, server_team_max, spawn_point),
-                )
-                .await?
+    if let Some(headless) = headless {
+        migo_hqm_server::run_headless(
+            config,
+            physics_config,
+            replay_saving,
+            game_mode,
+            headless.bots,
+            headless.games,
+            headless.ticks,
+        )
+        .await;
+    } else if let Some(auth) = auth {
+        migo_hqm_server::run_server_with_auth(
+            server_port,
+            master_servers,
+            config,
+            physics_config,
+            ban,
+            auth,
+            replay_saving,
+            game_mode,
+        )
+        .await?;
+    } else {
+        migo_hqm_server::run_server_with_master_servers(
+            server_port,
+            master_servers,
+            config,
+            physics_config,
+            ban,
+            replay_saving,
+            game_mode,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Returns the configuration of the instance `name`, where the properties of [Instance.NAME] replace the
+/// properties with the same names in [Server] and [Game]. The HTTP API can only be started once, so it is kept
+/// for the first instance only.
+fn instance_configuration(conf: &Ini, name: &str, first: bool) -> Ini {
+    let mut instance_conf = conf.clone();
+    if let Some(instance_section) = conf.section(Some(format!("Instance.{}", name))) {
+        for section_name in ["Server", "Game"] {
+            for (key, value) in instance_section.iter() {
+                instance_conf
+                    .with_section(Some(section_name))
+                    .set(key, value);
             }
-            HQMServerMode::PermanentWarmup => {
-                let warmup_pucks = get_optional(game_section, "warmup_pucks", 1, |x| {
-                    x.parse::<usize>().unwrap()
-                });
-
-                let spawn_point =
-                    get_optional(game_section, "spawn", SpawnPoint::Center, |x| match x {
-                        "bench" => SpawnPoint::Bench,
-                        _ => SpawnPoint::Center,
-                    });
-
-                migo_hqm_server::run_server(
-                    server_port,
-                    public_address,
-                    config,
-                    physics_config,
-                    ban,
-                    replay_saving,
-                    PermanentWarmup::new(warmup_pucks, spawn_point),
-                )
-                .await?
+        }
+    }
+    if !first {
+        instance_conf.delete(Some("Http"));
+    }
+    instance_conf
+}
+
+/// Properties that two instances can't share, as they would listen on the same port or write to the same file.
+const INSTANCE_UNIQUE_PROPERTIES: [(&str, &str); 7] = [
+    ("Server", "port"),
+    ("Server", "query_port"),
+    ("Server", "webadmin_port"),
+    ("Server", "mute_file"),
+    ("Server", "live_state_file"),
+    ("Server", "event_log"),
+    ("Game", "positions_file"),
+];
+
+/// Fails if two instances have the same value for one of [`INSTANCE_UNIQUE_PROPERTIES`].
+fn check_instance_conflicts(instances: &[(Option<String>, Ini)]) -> anyhow::Result<()> {
+    for (section, key) in INSTANCE_UNIQUE_PROPERTIES {
+        let mut used = HashMap::new();
+        for (name, conf) in instances {
+            let Some(value) = conf.section(Some(section)).and_then(|x| x.get(key)) else {
+                continue;
+            };
+            let name = name.as_deref().unwrap_or_default();
+            if let Some(other) = used.insert(value, name) {
+                anyhow::bail!(
+                    "Instances {} and {} have the same {}: {}",
+                    other,
+                    name,
+                    key,
+                    value
+                );
             }
-            HQMServerMode::Russian => {
-                let attempts =
-                    get_optional(game_section, "attempts", 10, |x| x.parse::<u32>().unwrap());
-
-                migo_hqm_server::run_server(
-                    server_port,
-                    public_address,
-                    config,
-                    physics_config,
-                    ban,
-                    replay_saving,
-                    RussianGameMode::new(attempts, server_team_max),
-                )
-                .await?
+        }
+    }
+    Ok(())
+}
+
+/// Bundles recordings, summaries and logs from the directories in the configuration file.
+///
+/// Usage: `migo-hqm-server archive --since YYYY-MM-DD [--output=PATH] [config.ini]`
+fn run_archive(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let mut config_path = "config.ini".to_owned();
+    let mut since = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        if arg == "--since" {
+            since = args.next();
+        } else if let Some(date) = arg.strip_prefix("--since=") {
+            since = Some(date.to_owned());
+        } else if let Some(path) = arg.strip_prefix("--output=") {
+            output = Some(PathBuf::from(path));
+        } else {
+            config_path = arg;
+        }
+    }
+    let Some(since) = since else {
+        println!("Usage: migo-hqm-server archive --since YYYY-MM-DD [--output=PATH] [config.ini]");
+        return Ok(());
+    };
+    let since_date = NaiveDate::parse_from_str(&since, "%Y-%m-%d")?;
+    let since_time = since_date.and_time(NaiveTime::MIN).and_utc();
+
+    let conf = Ini::load_from_file(&config_path)?;
+    let server_section = conf
+        .section(Some("Server"))
+        .ok_or_else(|| anyhow::anyhow!("No Server section in {}", config_path))?;
+    let server_name = server_section.get("name").unwrap_or("server");
+    let log_name = server_section
+        .get("log_name")
+        .map_or(format!("{}.log", server_name), String::from);
+    let replay_directory =
+        PathBuf::from(server_section.get("replay_directory").unwrap_or("replays"));
+
+    let sources = [
+        ArchiveSource {
+            directory: replay_directory.clone(),
+            prefix: String::new(),
+            extension: Some("hrp"),
+            kind: ArchiveFileKind::Recording,
+        },
+        ArchiveSource {
+            directory: replay_directory,
+            prefix: String::new(),
+            extension: Some("json"),
+            kind: ArchiveFileKind::Stats,
+        },
+        ArchiveSource {
+            directory: PathBuf::from("log"),
+            prefix: log_name,
+            extension: None,
+            kind: ArchiveFileKind::Log,
+        },
+    ];
+    let output = output
+        .unwrap_or_else(|| PathBuf::from(format!("{}.season.{}.tar.gz", server_name, since_date)));
+    let count = create_archive(&sources, since_time, &output)?;
+    println!("Archived {} files to {}", count, output.display());
+    Ok(())
+}
+
+fn physics_configuration(
+    section: Option<&Properties>,
+    limit_jump_speed: bool,
+) -> PhysicsConfiguration {
+    let gravity = get_optional(section, "gravity", 0.000680555, |x| {
+        x.parse::<f32>().unwrap() / 10000.0
+    });
+    let player_acceleration = get_optional(section, "player_acceleration", 0.000208333, |x| {
+        x.parse::<f32>().unwrap() / 10000.0
+    });
+    let player_deceleration = get_optional(section, "player_deceleration", 0.000555555, |x| {
+        x.parse::<f32>().unwrap() / 10000.0
+    });
+    let max_player_speed = get_optional(section, "max_player_speed", 0.05, |x| {
+        x.parse::<f32>().unwrap() / 100.0
+    });
+    let max_player_shift_speed = get_optional(section, "max_player_shift_speed", 0.0333333, |x| {
+        x.parse::<f32>().unwrap() / 100.0
+    });
+
+    let puck_rink_friction = get_optional(section, "puck_rink_friction", 0.05, |x| {
+        x.parse::<f32>().unwrap()
+    });
+    let player_turning = get_optional(section, "player_turning", 0.00041666666, |x| {
+        x.parse::<f32>().unwrap() / 10000.0
+    });
+    let player_shift_turning = get_optional(section, "player_shift_turning", 0.00038888888, |x| {
+        x.parse::<f32>().unwrap() / 10000.0
+    });
+
+    let player_shift_acceleration =
+        get_optional(section, "player_shift_acceleration", 0.00027777, |x| {
+            x.parse::<f32>().unwrap() / 10000.0
+        });
+
+    let knockdown_impulse = section
+        .and_then(|x| x.get("knockdown_impulse"))
+        .map(|x| x.parse::<f32>().unwrap() / 100.0);
+    let knockdown_recovery_time = get_optional(section, "knockdown_recovery_time", 100, |x| {
+        (x.parse::<f32>().unwrap() * 100.0) as u32
+    });
+
+    let lag_compensation = get_optional(section, "lag_compensation", false, is_true);
+    let max_stick_rotation = section
+        .and_then(|x| x.get("max_stick_rotation"))
+        .map(|x| x.parse::<f32>().unwrap() / 100.0);
+
+    PhysicsConfiguration {
+        gravity,
+        limit_jump_speed,
+        player_acceleration,
+        player_deceleration,
+        player_shift_acceleration,
+        max_player_speed,
+        max_player_shift_speed,
+        puck_rink_friction,
+        player_turning,
+        player_shift_turning,
+        knockdown_impulse,
+        knockdown_recovery_time,
+        lag_compensation,
+        max_stick_rotation,
+    }
+}
+
+fn create_game_mode(
+    conf: &Ini,
+    game_section: Option<&Properties>,
+    rotation: Option<&str>,
+    mode: &HQMServerMode,
+    team_max: usize,
+    player_max: usize,
+) -> Box<dyn GameMode> {
+    // Each game mode can override the Game section with its own section, like [Game.shootout]
+    let mode_section =
+        |name: &str| merge_sections(game_section, conf.section(Some(format!("Game.{}", name))));
+    let modes: Vec<(String, Box<dyn GameMode>)> = vec![
+        (
+            "match".to_owned(),
+            Box::new(match_game_mode(Some(&mode_section("match")), team_max)),
+        ),
+        (
+            "warmup".to_owned(),
+            with_bot_goalies(
+                warmup_game_mode(Some(&mode_section("warmup"))),
+                Some(&mode_section("warmup")),
+            ),
+        ),
+        (
+            "russian".to_owned(),
+            Box::new(russian_game_mode(Some(&mode_section("russian")), team_max)),
+        ),
+        (
+            "shootout".to_owned(),
+            Box::new(shootout_game_mode(Some(&mode_section("shootout")))),
+        ),
+        (
+            "ffa".to_owned(),
+            Box::new(free_for_all_game_mode(Some(&mode_section("ffa")), team_max)),
+        ),
+        (
+            "practice".to_owned(),
+            with_bot_goalies(
+                practice_game_mode(Some(&mode_section("practice")), player_max),
+                Some(&mode_section("practice")),
+            ),
+        ),
+        (
+            "skills".to_owned(),
+            Box::new(skills_game_mode(Some(&mode_section("skills")))),
+        ),
+    ];
+    // Without a rotation, the configured game mode is played until an admin switches to another one with /mode
+    let rotation = match rotation {
+        Some(rotation) => rotation
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>(),
+        None => vec![mode.name()],
+    };
+    let rotation_game_mode =
+        RotationGameMode::new(modes, &rotation).expect("Invalid game mode rotation");
+    Box::new(rotation_game_mode)
+}
+
+/// Reads the event rules from sections named like [Event.mercy].
+fn event_rules(conf: &Ini) -> Vec<EventRule> {
+    conf.sections()
+        .flatten()
+        .filter_map(|x| x.strip_prefix("Event."))
+        .map(|name| {
+            let section = conf.section(Some(format!("Event.{}", name))).unwrap();
+            EventRule {
+                name: name.to_owned(),
+                condition: section
+                    .get("when")
+                    .expect("Events require a when property")
+                    .parse()
+                    .unwrap_or_else(|e: String| panic!("Invalid event {}: {}", name, e)),
+                action: section
+                    .get("action")
+                    .unwrap_or("announce")
+                    .parse()
+                    .unwrap_or_else(|e: String| panic!("Invalid event {}: {}", name, e)),
+                message: section.get("message").map(String::from),
+                interval: section.get("every").map(|x| x.parse::<u32>().unwrap()),
             }
-            HQMServerMode::Shootout => {
-                let attempts =
-                    get_optional(game_section, "attempts", 5, |x| x.parse::<u32>().unwrap());
-
-                migo_hqm_server::run_server(
-                    server_port,
-                    public_address,
-                    config,
-                    physics_config,
-                    ban,
-                    replay_saving,
-                    ShootoutGameMode::new(attempts),
-                )
-                .await?;
+        })
+        .collect()
+}
+
+/// Reads the zones of the boards from sections named like [Boards.glass].
+fn board_zones(conf: &Ini) -> Vec<BoardZone> {
+    conf.sections()
+        .flatten()
+        .filter_map(|x| x.strip_prefix("Boards."))
+        .map(|name| {
+            let section = conf.section(Some(format!("Boards.{}", name))).unwrap();
+            // Each coordinate is a range like "0-8.5", and is unlimited if not given
+            let range = |key| {
+                section
+                    .get(key)
+                    .map_or((f32::NEG_INFINITY, f32::INFINITY), |x| {
+                        parse_range(x)
+                            .unwrap_or_else(|| panic!("Invalid {} in boards {}", key, name))
+                    })
+            };
+            let (x, y, z) = (range("x"), range("height"), range("z"));
+            BoardZone {
+                min: Point3::new(x.0, y.0, z.0),
+                max: Point3::new(x.1, y.1, z.1),
+                stiffness: get_optional(Some(section), "stiffness", 1.0, |x| {
+                    x.parse::<f32>().unwrap()
+                }),
+                friction: section.get("friction").map(|x| x.parse::<f32>().unwrap()),
             }
+        })
+        .collect()
+}
+
+/// Parses a range like "0-8.5" or "-30--10". The bounds can be negative, and the first can't be larger than
+/// the second.
+fn parse_range(s: &str) -> Option<(f32, f32)> {
+    // The separator is the first dash that comes right after a digit or a dot
+    let s = s.trim();
+    let separator = s
+        .char_indices()
+        .skip(1)
+        .find(|(i, c)| {
+            *c == '-'
+                && s[..*i]
+                    .trim_end()
+                    .ends_with(|x: char| x.is_ascii_digit() || x == '.')
+        })
+        .map(|(i, _)| i)?;
+    let min: f32 = s[..separator].trim().parse().ok()?;
+    let max: f32 = s[separator + 1..].trim().parse().ok()?;
+    (min <= max).then_some((min, max))
+}
+
+/// Returns `base` with the properties of `overrides` added, replacing properties with the same name.
+fn merge_sections(base: Option<&Properties>, overrides: Option<&Properties>) -> Properties {
+    let mut section = base.cloned().unwrap_or_default();
+    for (key, value) in overrides.iter().flat_map(|x| x.iter()) {
+        section.insert(key, value);
+    }
+    section
+}
+
+fn match_game_mode(section: Option<&Properties>, team_max: usize) -> StandardMatchGameMode {
+    let periods = get_optional(section, "periods", 3, |x| x.parse::<u32>().unwrap());
+
+    let rules_time_period =
+        get_optional(section, "time_period", 300, |x| x.parse::<u32>().unwrap());
+    let rules_time_warmup =
+        get_optional(section, "time_warmup", 300, |x| x.parse::<u32>().unwrap());
+    let rule_time_break = get_optional(section, "time_break", 10, |x| x.parse::<u32>().unwrap());
+    let rule_time_intermission = get_optional(section, "time_intermission", 20, |x| {
+        x.parse::<u32>().unwrap()
+    });
+    let warmup_pucks = get_optional(section, "warmup_pucks", 1, |x| x.parse::<usize>().unwrap());
+    let game_pucks = get_optional(section, "game_pucks", 1, |x| x.parse::<usize>().unwrap());
+
+    let mercy = get_optional(section, "mercy", 0, |x| x.parse::<u32>().unwrap());
+    let first_to = get_optional(section, "first", 0, |x| x.parse::<u32>().unwrap());
+
+    let icing = get_optional(section, "icing", IcingConfiguration::Off, parse_icing);
+
+    let offside = get_optional(section, "offside", OffsideConfiguration::Off, parse_offside);
+
+    let offside_line = get_optional(
+        section,
+        "offsideline",
+        OffsideLineConfiguration::OffensiveBlue,
+        parse_offside_line,
+    );
+
+    let twoline_pass = get_optional(
+        section,
+        "twolinepass",
+        TwoLinePassConfiguration::Off,
+        parse_twoline_pass,
+    );
+
+    // Rule changes for single periods, like icing_period2=off or icing_overtime=off
+    let mut period_rules: HashMap<u32, PeriodRules> = HashMap::new();
+    let mut overtime_rules: Option<PeriodRules> = None;
+    for (key, value) in section.iter().flat_map(|x| x.iter()) {
+        let Some((rule, period)) = key.rsplit_once('_') else {
+            continue;
         };
+        let rules = if period == "overtime" {
+            overtime_rules.get_or_insert_with(PeriodRules::default)
+        } else if let Some(Ok(period)) = period.strip_prefix("period").map(|x| x.parse::<u32>()) {
+            period_rules.entry(period).or_default()
+        } else {
+            continue;
+        };
+        match rule {
+            "icing" => rules.icing = Some(parse_icing(value)),
+            "offside" => rules.offside = Some(parse_offside(value)),
+            "offsideline" => rules.offside_line = Some(parse_offside_line(value)),
+            "twolinepass" => rules.twoline_pass = Some(parse_twoline_pass(value)),
+            _ => {}
+        }
+    }
+
+    let spawn_point = get_optional(section, "spawn", SpawnPoint::Center, |x| match x {
+        "bench" => SpawnPoint::Bench,
+        _ => SpawnPoint::Center,
+    });
+
+    let spawn_point_offset = get_optional(section, "spawn_offset", 2.75f32, |x| {
+        x.parse::<f32>().unwrap()
+    });
+
+    let spawn_player_altitude = get_optional(section, "spawn_player_altitude", 1.5f32, |x| {
+        x.parse::<f32>().unwrap()
+    });
+
+    let spawn_puck_altitude = get_optional(section, "spawn_puck_altitude", 1.5f32, |x| {
+        x.parse::<f32>().unwrap()
+    });
+
+    let spawn_keep_stick_position =
+        get_optional(section, "spawn_player_keep_stick", false, is_true);
+
+    let use_mph = get_optional(section, "use_mph", false, is_true);
+
+    let goal_replay = get_optional(section, "goal_replay", false, is_true);
+    let goal_replay_max =
+        get_optional(section, "goal_replay_max", 6, |x| x.parse::<u32>().unwrap());
+
+    let intermission_shootout = get_optional(section, "intermission_shootout", false, is_true);
+    let frozen_puck_time = get_optional(section, "frozen_puck_time", 0, |x| {
+        x.parse::<u32>().unwrap()
+    });
+    let frozen_puck_speed = get_optional(section, "frozen_puck_speed", 1.0f32, |x| {
+        x.parse::<f32>().unwrap()
+    });
+    let warmup_auto_teams = get_optional(section, "auto_teams", false, is_true);
+    let mvp_vote_time = get_optional(section, "mvp_vote", 0, |x| x.parse::<u32>().unwrap());
+
+    let min_players = get_optional(section, "min_players", 0, |x| x.parse::<usize>().unwrap());
+    let forfeit_time = get_optional(section, "forfeit_time", 0, |x| x.parse::<u32>().unwrap());
+    let force_team_size_parity = get_optional(section, "force_team_size_parity", false, is_true);
+    let force_team_balance = get_optional(section, "force_team_balance", false, is_true);
+
+    let overtime = get_optional(
+        section,
+        "overtime",
+        OvertimeConfiguration::SuddenDeath,
+        |x| match x {
+            "shootout" => OvertimeConfiguration::Shootout,
+            "none" => OvertimeConfiguration::None,
+            _ => OvertimeConfiguration::SuddenDeath,
+        },
+    );
+    let time_overtime = get_optional(section, "overtime_period_length", rules_time_period, |x| {
+        x.parse::<u32>().unwrap()
+    });
+    // Only a shootout can follow no overtime at all
+    let time_overtime = if time_overtime == 0 && overtime != OvertimeConfiguration::Shootout {
+        rules_time_period
     } else {
-        println!("Could not open configuration file {}!", config_path);
+        time_overtime
     };
-    Ok(())
+    let overtime_team_size = get_optional(section, "overtime_team_size", 0, |x| {
+        x.parse::<usize>().unwrap()
+    });
+    let positions_file = section
+        .and_then(|x| x.get("positions_file"))
+        .map(PathBuf::from);
+    let scheduled_start = section
+        .and_then(|x| x.get("scheduled_start"))
+        .map(|x| StartTime::parse(x).expect("Invalid scheduled start"));
+
+    let match_config = MatchConfiguration {
+        time_period: rules_time_period,
+        time_warmup: rules_time_warmup,
+        time_break: rule_time_break,
+        time_intermission: rule_time_intermission,
+        mercy,
+        first_to,
+        icing,
+        offside,
+        offside_line,
+        twoline_pass,
+        warmup_pucks,
+        game_pucks,
+        use_mph,
+        goal_replay,
+        goal_replay_max,
+        periods,
+        spawn_point_offset,
+        spawn_player_altitude,
+        spawn_puck_altitude,
+        spawn_keep_stick_position,
+        intermission_shootout,
+        frozen_puck_time,
+        frozen_puck_speed,
+        mvp_vote_time,
+        warmup_auto_teams,
+        min_players,
+        forfeit_time,
+        force_team_size_parity,
+        force_team_balance,
+        period_rules,
+        overtime_rules,
+        overtime,
+        time_overtime,
+        overtime_team_size,
+        positions_file,
+        scheduled_start,
+    };
+
+    StandardMatchGameMode::new(match_config, team_max, spawn_point)
+}
+
+fn parse_icing(x: &str) -> IcingConfiguration {
+    match x {
+        "on" | "touch" => IcingConfiguration::Touch,
+        "notouch" => IcingConfiguration::NoTouch,
+        _ => IcingConfiguration::Off,
+    }
+}
+
+fn parse_offside(x: &str) -> OffsideConfiguration {
+    match x {
+        "on" | "delayed" => OffsideConfiguration::Delayed,
+        "immediate" | "imm" => OffsideConfiguration::Immediate,
+        _ => OffsideConfiguration::Off,
+    }
+}
+
+fn parse_offside_line(x: &str) -> OffsideLineConfiguration {
+    match x {
+        "blue" => OffsideLineConfiguration::OffensiveBlue,
+        "center" => OffsideLineConfiguration::Center,
+        _ => OffsideLineConfiguration::OffensiveBlue,
+    }
+}
+
+fn parse_twoline_pass(x: &str) -> TwoLinePassConfiguration {
+    match x {
+        "on" => TwoLinePassConfiguration::On,
+        "forward" => TwoLinePassConfiguration::Forward,
+        "double" | "both" => TwoLinePassConfiguration::Double,
+        "blue" | "three" | "threeline" => TwoLinePassConfiguration::ThreeLine,
+        _ => TwoLinePassConfiguration::Off,
+    }
+}
+
+fn warmup_game_mode(section: Option<&Properties>) -> PermanentWarmup {
+    let warmup_pucks = get_optional(section, "warmup_pucks", 1, |x| x.parse::<usize>().unwrap());
+
+    let spawn_point = get_optional(section, "spawn", SpawnPoint::Center, |x| match x {
+        "bench" => SpawnPoint::Bench,
+        _ => SpawnPoint::Center,
+    });
+
+    let warmup_score = get_optional(section, "warmup_score", false, is_true);
+    let auto_teams = get_optional(section, "auto_teams", false, is_true);
+    let layout_dir = section
+        .and_then(|x| x.get("layout_dir"))
+        .map_or_else(|| PathBuf::from("layouts"), PathBuf::from);
+    let use_mph = get_optional(section, "use_mph", false, is_true);
+
+    PermanentWarmup::with_layouts(
+        warmup_pucks,
+        spawn_point,
+        warmup_score,
+        auto_teams,
+        layout_dir,
+    )
+    .with_mph(use_mph)
+}
+
+fn russian_game_mode(section: Option<&Properties>, team_max: usize) -> RussianGameMode {
+    let attempts = get_optional(section, "attempts", 10, |x| x.parse::<u32>().unwrap());
+
+    RussianGameMode::new(attempts, team_max)
+}
+
+fn shootout_game_mode(section: Option<&Properties>) -> ShootoutGameMode {
+    let attempts = get_optional(section, "attempts", 5, |x| x.parse::<u32>().unwrap());
+
+    ShootoutGameMode::new(attempts, bot_goalie(section))
+}
+
+fn bot_goalie(section: Option<&Properties>) -> Option<GoalieBotConfiguration> {
+    get_optional(section, "bot_goalie", false, is_true).then(|| {
+        let default = GoalieBotConfiguration::default();
+        GoalieBotConfiguration {
+            reaction_delay: get_optional(
+                section,
+                "bot_goalie_reaction",
+                default.reaction_delay,
+                |x| x.parse::<u32>().unwrap(),
+            ),
+            ..default
+        }
+    })
+}
+
+/// Adds a bot goalie to both nets of a game mode, if bot_goalie is set in its section.
+fn with_bot_goalies(
+    game_mode: impl GameMode + 'static,
+    section: Option<&Properties>,
+) -> Box<dyn GameMode> {
+    match bot_goalie(section) {
+        Some(config) => {
+            let goalies = GoalieBotLayer::new(config, &[Team::Red, Team::Blue]);
+            Box::new(LayeredGameMode::new(game_mode).with(goalies))
+        }
+        None => Box::new(game_mode),
+    }
+}
+
+fn skills_game_mode(section: Option<&Properties>) -> SkillsGameMode {
+    let drills = get_optional(section, "drills", vec![], |x| {
+        parse_drills(x).expect("Invalid drills")
+    });
+    let attempt_time = get_optional(section, "attempt_time", 10, |x| x.parse::<u32>().unwrap());
+
+    SkillsGameMode::new(drills, attempt_time, bot_goalie(section))
+}
+
+fn free_for_all_game_mode(section: Option<&Properties>, team_max: usize) -> FreeForAllGameMode {
+    let goal_limit = get_optional(section, "first", 10, |x| x.parse::<u32>().unwrap());
+    let time_limit = get_optional(section, "time_period", 600, |x| x.parse::<u32>().unwrap());
+    let standings_interval = get_optional(section, "standings_interval", 60, |x| {
+        x.parse::<u32>().unwrap()
+    });
+
+    FreeForAllGameMode::new(goal_limit, time_limit, standings_interval, team_max)
+}
+
+fn practice_game_mode(section: Option<&Properties>, player_max: usize) -> PracticeGameMode {
+    let max_pucks = section
+        .and_then(|x| x.get("max_pucks"))
+        .map(|x| x.parse::<usize>().unwrap());
+
+    let spawn_point = get_optional(section, "spawn", SpawnPoint::Center, |x| match x {
+        "bench" => SpawnPoint::Bench,
+        _ => SpawnPoint::Center,
+    });
+
+    let use_mph = get_optional(section, "use_mph", false, is_true);
+
+    // Pucks can't take the object slots the skaters need, including the bot goalies
+    let player_max = player_max + if bot_goalie(section).is_some() { 2 } else { 0 };
+    let available = OBJECT_SLOTS.saturating_sub(player_max);
+    let practice = match max_pucks {
+        Some(max_pucks) if max_pucks <= available => PracticeGameMode::new(max_pucks, spawn_point),
+        Some(max_pucks) => {
+            warn!(
+                "max_pucks is {}, but only {} object slots are left for pucks with player_max {}",
+                max_pucks, available, player_max
+            );
+            PracticeGameMode::with_room_for_players(player_max, spawn_point)
+        }
+        None => PracticeGameMode::with_room_for_players(player_max, spawn_point),
+    };
+    practice.with_mph(use_mph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instances(conf: &str) -> Vec<(Option<String>, Ini)> {
+        let conf = Ini::load_from_str(conf).unwrap();
+        ["casual", "league"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let instance_conf = instance_configuration(&conf, name, i == 0);
+                (Some(name.to_owned()), instance_conf)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn instance_configurations() {
+        let conf = "[Server]\nname=Main\nport=27585\nmute_file=mutes.txt\n\
+            [Game]\nperiods=3\n[Http]\nport=8080\n\
+            [Instance.casual]\nname=Casual\nport=27586\n\
+            [Instance.league]\nname=League\nport=27587\nperiods=1\n";
+        let configurations = instances(conf);
+        let (_, casual) = &configurations[0];
+        let (_, league) = &configurations[1];
+        assert_eq!(casual.get_from(Some("Server"), "name"), Some("Casual"));
+        assert_eq!(casual.get_from(Some("Game"), "periods"), Some("3"));
+        assert_eq!(casual.get_from(Some("Http"), "port"), Some("8080"));
+        assert_eq!(league.get_from(Some("Server"), "port"), Some("27587"));
+        assert_eq!(league.get_from(Some("Game"), "periods"), Some("1"));
+        assert_eq!(league.get_from(Some("Http"), "port"), None);
+
+        // Both instances inherit the mute file from the Server section
+        let error = check_instance_conflicts(&configurations).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Instances casual and league have the same mute_file: mutes.txt"
+        );
+
+        let conf = conf.replace("name=League\n", "name=League\nmute_file=league.txt\n");
+        assert!(check_instance_conflicts(&instances(&conf)).is_ok());
+
+        let conf = conf.replace("port=27587", "port=27586");
+        assert!(check_instance_conflicts(&instances(&conf)).is_err());
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("0-8.5"), Some((0.0, 8.5)));
+        assert_eq!(parse_range(" 1. - 2 "), Some((1.0, 2.0)));
+        assert_eq!(parse_range("-30--10"), Some((-30.0, -10.0)));
+        assert_eq!(parse_range("-5-5"), Some((-5.0, 5.0)));
+        assert_eq!(parse_range("10-0"), None);
+        assert_eq!(parse_range("-10"), None);
+        assert_eq!(parse_range("a-b"), None);
+    }
 }
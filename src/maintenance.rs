@@ -0,0 +1,99 @@
+//! Maintenance mode, turned on by admins with `/maintenance on`.
+//!
+//! During maintenance, nobody can join a team, and the game mode moves the players on the ice to the spectators
+//! at the next stoppage, so that the operators can reconfigure the server without a game going on. Players can still
+//! connect and watch, and are reminded of the maintenance in chat every minute.
+
+use tracing::info;
+
+use crate::game::PlayerId;
+use crate::server::{HQMServer, PlayerListExt};
+
+/// Ticks between two maintenance reminders.
+const BANNER_INTERVAL: u32 = 60 * 100;
+
+const BANNER: &str = "Server maintenance: you can watch, but not join a team";
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Maintenance {
+    banner_timer: u32,
+    /// Game clock of the previous tick, to tell when play has stopped.
+    clock: u32,
+    /// True when the players have been moved off the ice.
+    cleared: bool,
+}
+
+impl HQMServer {
+    pub(crate) fn set_maintenance(&mut self, admin_player_id: PlayerId, arg: &str) {
+        let Some(player) = self
+            .state
+            .players
+            .players
+            .check_admin_or_deny(admin_player_id)
+        else {
+            return;
+        };
+        let name = player.player_name.clone();
+        let enabled = match arg.trim().to_ascii_lowercase().as_str() {
+            "on" => true,
+            "off" => false,
+            _ => {
+                self.state.players.add_directed_server_chat_message(
+                    "Usage: /maintenance on|off",
+                    admin_player_id,
+                );
+                return;
+            }
+        };
+        if enabled == self.maintenance.is_some() {
+            return;
+        }
+        self.state.players.teams_locked = enabled;
+        if enabled {
+            info!("{} ({}) started maintenance", name, admin_player_id);
+            self.maintenance = Some(Maintenance {
+                banner_timer: BANNER_INTERVAL,
+                clock: self.state.scoreboard.time,
+                cleared: false,
+            });
+            let msg = format!("Maintenance started by {}", name);
+            self.state.players.add_server_chat_message(msg);
+            self.state.players.add_server_chat_message(BANNER);
+        } else {
+            info!("{} ({}) ended maintenance", name, admin_player_id);
+            self.maintenance = None;
+            for (_, player) in self.state.players.players.iter_players_mut() {
+                player.leave_ice = false;
+            }
+            let msg = format!("Maintenance ended by {}, teams are open again", name);
+            self.state.players.add_server_chat_message(msg);
+        }
+    }
+
+    /// Moves the players off the ice at the first stoppage, and shows the maintenance banner now and then.
+    pub(crate) fn update_maintenance(&mut self) {
+        let Some(maintenance) = &mut self.maintenance else {
+            return;
+        };
+        let scoreboard = &self.state.scoreboard;
+        let stopped =
+            scoreboard.period == 0 || scoreboard.game_over || scoreboard.time == maintenance.clock;
+        maintenance.clock = scoreboard.time;
+        if stopped && !maintenance.cleared {
+            maintenance.cleared = true;
+            // The game mode moves them, so that it knows they have left
+            for (_, player) in self.state.players.players.iter_players_mut() {
+                player.leave_ice = player.has_skater();
+            }
+            self.state
+                .players
+                .add_server_chat_message("All players have been moved to the spectators");
+        }
+
+        maintenance.banner_timer = maintenance.banner_timer.saturating_sub(1);
+        if maintenance.banner_timer == 0 {
+            maintenance.banner_timer = BANNER_INTERVAL;
+            self.state.players.add_server_chat_message(BANNER);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Announcing the server to master servers, so that clients can find it in the server list.
+//!
+//! Each [`MasterServerAnnouncer`] is run in its own task, which calls it again after its interval when an
+//! announcement succeeds, and after an increasing delay when it fails. The result of the latest attempt of
+//! each announcer is kept in a [`MasterServerStatus`], which is included in the status of the HTTP API.
+
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use crate::json::{JsonObjectWriter, ToJson};
+
+/// The master server that clients use by default.
+pub const DEFAULT_MASTER_SERVER: &str = "https://sam2.github.io/HQMMasterServerEndpoint/";
+
+/// Delay before trying again after the first failed announcement. Doubled for each failure in a row.
+const RETRY_DELAY: Duration = Duration::from_secs(15);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+pub type AnnounceError = Box<dyn Error + Send + Sync>;
+
+pub trait MasterServerAnnouncer: Send {
+    /// Name of the master server in logs and in the status.
+    fn name(&self) -> &str;
+
+    /// Time between two announcements.
+    fn interval(&self) -> Duration;
+
+    /// Tells the master server that the server listening on `socket` is up.
+    fn announce<'a>(
+        &'a mut self,
+        socket: &'a UdpSocket,
+    ) -> BoxFuture<'a, Result<(), AnnounceError>>;
+}
+
+/// Master server whose address is looked up from an HTTP endpoint, like the default one.
+///
+/// The endpoint returns a line with the IP address and the port of the master server as the second and third
+/// words. The address is looked up again every 60 announcements, or after a failure.
+pub struct HttpMasterServer {
+    url: String,
+    interval: Duration,
+    client: reqwest::Client,
+    address: Option<(SocketAddr, u32)>,
+}
+
+impl HttpMasterServer {
+    pub fn new(url: impl Into<String>, interval: Duration) -> Self {
+        Self {
+            url: url.into(),
+            interval,
+            client: reqwest::Client::new(),
+            address: None,
+        }
+    }
+
+    async fn lookup(&self) -> Result<SocketAddr, AnnounceError> {
+        let response = self.client.get(&self.url).send().await?.text().await?;
+
+        let split = response.split_ascii_whitespace().collect::<Vec<&str>>();
+
+        let addr = split.get(1).unwrap_or(&"").parse::<IpAddr>()?;
+        let port = split.get(2).unwrap_or(&"").parse::<u16>()?;
+        Ok(SocketAddr::new(addr, port))
+    }
+}
+
+impl MasterServerAnnouncer for HttpMasterServer {
+    fn name(&self) -> &str {
+        &self.url
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn announce<'a>(
+        &'a mut self,
+        socket: &'a UdpSocket,
+    ) -> BoxFuture<'a, Result<(), AnnounceError>> {
+        async move {
+            let (addr, count) = match self.address.take() {
+                Some((addr, count)) if count < 60 => (addr, count),
+                _ => (self.lookup().await?, 0),
+            };
+            socket.send_to(b"Hock\x20", addr).await?;
+            self.address = Some((addr, count + 1));
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct AnnouncerStatus {
+    name: String,
+    last_success: Option<Instant>,
+    /// Failed attempts since the last successful one.
+    failures: u32,
+    last_error: Option<String>,
+}
+
+impl ToJson for AnnouncerStatus {
+    fn write_json(&self, out: &mut String) {
+        let seconds_since_success = self.last_success.map(|x| x.elapsed().as_secs());
+        JsonObjectWriter::new(out)
+            .field("name", &self.name)
+            .field("ok", &(self.last_success.is_some() && self.failures == 0))
+            .field("seconds_since_success", &seconds_since_success)
+            .field("failures", &self.failures)
+            .field("last_error", &self.last_error)
+            .finish();
+    }
+}
+
+/// Whether the announcements to each master server are succeeding.
+#[derive(Debug, Clone, Default)]
+pub struct MasterServerStatus {
+    announcers: Arc<Mutex<Vec<AnnouncerStatus>>>,
+}
+
+impl MasterServerStatus {
+    fn update(&self, index: usize, f: impl FnOnce(&mut AnnouncerStatus)) {
+        if let Some(status) = self.announcers.lock().unwrap().get_mut(index) {
+            f(status);
+        }
+    }
+}
+
+impl ToJson for MasterServerStatus {
+    fn write_json(&self, out: &mut String) {
+        self.announcers.lock().unwrap().write_json(out);
+    }
+}
+
+/// Starts announcing the server on `socket` to each master server.
+pub(crate) fn start(
+    announcers: Vec<Box<dyn MasterServerAnnouncer>>,
+    socket: Arc<UdpSocket>,
+) -> MasterServerStatus {
+    let status = MasterServerStatus::default();
+    for (index, mut announcer) in announcers.into_iter().enumerate() {
+        status.announcers.lock().unwrap().push(AnnouncerStatus {
+            name: announcer.name().to_owned(),
+            ..Default::default()
+        });
+        let status = status.clone();
+        let socket = socket.clone();
+        tokio::spawn(async move {
+            loop {
+                let delay = match announcer.announce(&socket).await {
+                    Ok(()) => {
+                        status.update(index, |x| {
+                            if x.failures > 0 {
+                                info!("Announcing to {} succeeded again", x.name);
+                            }
+                            x.last_success = Some(Instant::now());
+                            x.failures = 0;
+                        });
+                        announcer.interval()
+                    }
+                    Err(e) => {
+                        let mut failures = 0;
+                        status.update(index, |x| {
+                            x.failures += 1;
+                            x.last_error = Some(e.to_string());
+                            failures = x.failures;
+                        });
+                        let delay = retry_delay(failures);
+                        warn!(
+                            "Could not announce to {}, trying again in {} seconds: {}",
+                            announcer.name(),
+                            delay.as_secs(),
+                            e
+                        );
+                        delay
+                    }
+                };
+                tokio::time::sleep(delay).await;
+            }
+        });
+    }
+    status
+}
+
+fn retry_delay(failures: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(1 << failures.saturating_sub(1).min(8))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Parses the master servers of the `public_address` setting: URLs separated by commas, each optionally
+/// followed by the announcement interval in seconds, like `https://example.com/endpoint 30`.
+pub fn parse_master_servers(
+    s: &str,
+    default_interval: Duration,
+) -> Result<Vec<HttpMasterServer>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| match x.split_once(char::is_whitespace) {
+            Some((url, interval)) => {
+                let seconds = interval
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .ok_or_else(|| format!("Invalid announcement interval for {}", url))?;
+                Ok(HttpMasterServer::new(url, Duration::from_secs(seconds)))
+            }
+            None => Ok(HttpMasterServer::new(x, default_interval)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_master_servers, retry_delay};
+
+    #[test]
+    fn master_server_list() {
+        let default_interval = Duration::from_secs(10);
+        let servers = parse_master_servers(
+            "https://a.example/, https://b.example/ 30",
+            default_interval,
+        )
+        .unwrap();
+        let parsed: Vec<_> = servers
+            .iter()
+            .map(|x| (x.url.as_str(), x.interval))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("https://a.example/", default_interval),
+                ("https://b.example/", Duration::from_secs(30))
+            ]
+        );
+        assert!(parse_master_servers("https://a.example/ soon", default_interval).is_err());
+
+        assert_eq!(retry_delay(1), Duration::from_secs(15));
+        assert_eq!(retry_delay(3), Duration::from_secs(60));
+        assert_eq!(retry_delay(100), Duration::from_secs(300));
+    }
+}
@@ -0,0 +1,170 @@
+//! Messages sent to the players during a game.
+//!
+//! Each player has its own list of messages, where the position of each message is what the client acknowledges.
+//! Most messages are sent to many players, so they are stored once in the [`MessageLog`] of the game, and the list
+//! of each player only refers to them, with consecutive messages of the log kept as a single range. Messages
+//! that are only sent to one player, like directed chat and the messages sent to a player that joins during the
+//! game, are stored in the list of that player.
+
+use std::rc::Rc;
+
+use crate::server::HQMMessage;
+
+/// Append-only log of the messages sent to more than one player during the current game.
+#[derive(Debug, Default)]
+pub(crate) struct MessageLog {
+    messages: Vec<Rc<HQMMessage>>,
+}
+
+impl MessageLog {
+    /// Adds a message to the log, and returns its index.
+    pub(crate) fn push(&mut self, message: Rc<HQMMessage>) -> usize {
+        self.messages.push(message);
+        self.messages.len() - 1
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.messages.clear();
+    }
+}
+
+#[derive(Debug)]
+enum MessageEntry {
+    /// Messages `start..end` of the log.
+    Log {
+        start: usize,
+        end: usize,
+    },
+    Own(Rc<HQMMessage>),
+}
+
+/// The messages sent to one player during the current game.
+#[derive(Debug, Default)]
+pub(crate) struct PlayerMessages {
+    /// Each entry with the position of its first message.
+    entries: Vec<(usize, MessageEntry)>,
+    len: usize,
+}
+
+impl PlayerMessages {
+    /// Creates a message list that starts with `messages`.
+    pub(crate) fn new(messages: impl IntoIterator<Item = Rc<HQMMessage>>) -> Self {
+        let mut res = Self::default();
+        for message in messages {
+            res.push(message);
+        }
+        res
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.len = 0;
+    }
+
+    /// Adds a message that only this player receives.
+    pub(crate) fn push(&mut self, message: Rc<HQMMessage>) {
+        self.entries.push((self.len, MessageEntry::Own(message)));
+        self.len += 1;
+    }
+
+    /// Adds the message at `index` of the log.
+    pub(crate) fn push_log(&mut self, index: usize) {
+        match self.entries.last_mut() {
+            Some((_, MessageEntry::Log { end, .. })) if *end == index => {
+                *end += 1;
+            }
+            _ => {
+                let entry = MessageEntry::Log {
+                    start: index,
+                    end: index + 1,
+                };
+                self.entries.push((self.len, entry));
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Returns up to `count` messages, starting at position `start`.
+    pub(crate) fn get<'a>(
+        &'a self,
+        log: &'a MessageLog,
+        start: usize,
+        count: usize,
+    ) -> impl Iterator<Item = &'a HQMMessage> + 'a {
+        let first = self
+            .entries
+            .partition_point(|(pos, _)| *pos <= start)
+            .saturating_sub(1);
+        let skip = self.entries.get(first).map_or(0, |(pos, _)| start - pos);
+        self.entries[first..]
+            .iter()
+            .flat_map(move |(_, entry)| {
+                let messages: &[Rc<HQMMessage>] = match entry {
+                    MessageEntry::Log { start, end } => &log.messages[*start..*end],
+                    MessageEntry::Own(message) => std::slice::from_ref(message),
+                };
+                messages.iter().map(Rc::as_ref)
+            })
+            .skip(skip)
+            .take(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::rc::Rc;
+
+    use super::{MessageEntry, MessageLog, PlayerMessages};
+    use crate::server::HQMMessage;
+
+    fn chat(s: &'static str) -> Rc<HQMMessage> {
+        Rc::new(HQMMessage::Chat {
+            player_id: None,
+            message: Cow::Borrowed(s),
+        })
+    }
+
+    fn text(message: &HQMMessage) -> &str {
+        match message {
+            HQMMessage::Chat { message, .. } => message,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn player_message_positions() {
+        let mut log = MessageLog::default();
+        let mut messages = PlayerMessages::new([chat("join")]);
+        for s in ["a", "b"] {
+            let index = log.push(chat(s));
+            messages.push_log(index);
+        }
+        messages.push(chat("directed"));
+        // Sent to other players only
+        log.push(chat("c"));
+        let index = log.push(chat("d"));
+        messages.push_log(index);
+        let index = log.push(chat("e"));
+        messages.push_log(index);
+
+        assert_eq!(messages.len(), 6);
+        // Consecutive log messages share one entry
+        assert_eq!(messages.entries.len(), 4);
+        assert!(matches!(
+            messages.entries[3],
+            (4, MessageEntry::Log { start: 3, end: 5 })
+        ));
+
+        let get =
+            |start, count| -> Vec<&str> { messages.get(&log, start, count).map(text).collect() };
+        assert_eq!(get(0, 15), ["join", "a", "b", "directed", "d", "e"]);
+        assert_eq!(get(2, 3), ["b", "directed", "d"]);
+        assert_eq!(get(5, 15), ["e"]);
+        assert!(get(6, 15).is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! Mutes that are kept across reconnects and restarts.
+//!
+//! Mutes are stored by account name for players with a verified identity, and by IP address for everyone else.
+//! If a mute file is configured, the list is saved to it whenever it changes, one mute per line as
+//! `<key> <muted|shadow> <expiry>`, where the key is an IP address or `@account`, and the expiry is a Unix
+//! timestamp or `-` for mutes that don't expire.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+
+use crate::file_writer::FileWriter;
+use crate::server::MuteStatus;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MuteKey {
+    Account(String),
+    Ip(IpAddr),
+}
+
+impl MuteKey {
+    fn new(ip: IpAddr, account: Option<&str>) -> Self {
+        match account {
+            Some(account) => MuteKey::Account(account.to_owned()),
+            None => MuteKey::Ip(ip),
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s.strip_prefix('@') {
+            Some(account) => Some(MuteKey::Account(account.to_owned())),
+            None => s.parse().ok().map(MuteKey::Ip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Mute {
+    status: MuteStatus,
+    until: Option<DateTime<Utc>>,
+}
+
+pub(crate) struct MuteList {
+    file: Option<FileWriter>,
+    mutes: HashMap<MuteKey, Mute>,
+}
+
+impl MuteList {
+    /// Creates a mute list, and loads the mutes in `file` if it exists.
+    pub(crate) fn new(file: Option<PathBuf>) -> Self {
+        let mut mutes = HashMap::new();
+        if let Some(path) = &file {
+            if let Ok(s) = std::fs::read_to_string(path) {
+                for line in s.lines() {
+                    if let Some((key, mute)) = parse_line(line) {
+                        mutes.insert(key, mute);
+                    }
+                }
+            }
+        }
+        Self {
+            file: file.map(|path| FileWriter::new(path, "mute file")),
+            mutes,
+        }
+    }
+
+    /// Returns the mute status of a player, forgetting the mute if it has expired.
+    pub(crate) fn check(
+        &mut self,
+        ip: IpAddr,
+        account: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> MuteStatus {
+        let key = MuteKey::new(ip, account);
+        match self.mutes.get(&key) {
+            Some(mute) if mute.until.is_some_and(|until| until <= now) => {
+                self.mutes.remove(&key);
+                self.save();
+                MuteStatus::NotMuted
+            }
+            Some(mute) => mute.status,
+            None => MuteStatus::NotMuted,
+        }
+    }
+
+    /// Mutes or shadow mutes a player until `until`, or forever if `until` is `None`.
+    /// Setting the status to [`MuteStatus::NotMuted`] removes the mute.
+    pub(crate) fn set(
+        &mut self,
+        ip: IpAddr,
+        account: Option<&str>,
+        status: MuteStatus,
+        until: Option<DateTime<Utc>>,
+    ) {
+        let key = MuteKey::new(ip, account);
+        if status == MuteStatus::NotMuted {
+            self.mutes.remove(&key);
+        } else {
+            self.mutes.insert(key, Mute { status, until });
+        }
+        self.save();
+    }
+
+    fn save(&mut self) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let mut s = String::new();
+        for (key, mute) in self.mutes.iter() {
+            let _ = match key {
+                MuteKey::Account(account) => write!(s, "@{}", account),
+                MuteKey::Ip(ip) => write!(s, "{}", ip),
+            };
+            let status = match mute.status {
+                MuteStatus::ShadowMuted => "shadow",
+                _ => "muted",
+            };
+            let _ = match mute.until {
+                Some(until) => writeln!(s, " {} {}", status, until.timestamp()),
+                None => writeln!(s, " {} -", status),
+            };
+        }
+        file.write(s);
+    }
+
+    /// Returns the saves that are still in progress, so that they can be waited for before the program exits.
+    pub(crate) fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.file
+            .as_mut()
+            .and_then(FileWriter::take_pending_save)
+            .into_iter()
+            .collect()
+    }
+}
+
+fn parse_line(line: &str) -> Option<(MuteKey, Mute)> {
+    let mut parts = line.split_whitespace();
+    let key = MuteKey::parse(parts.next()?)?;
+    let status = match parts.next()? {
+        "shadow" => MuteStatus::ShadowMuted,
+        _ => MuteStatus::Muted,
+    };
+    let until = match parts.next() {
+        Some("-") | None => None,
+        Some(t) => Some(DateTime::from_timestamp(t.parse().ok()?, 0)?),
+    };
+    Some((key, Mute { status, until }))
+}
@@ -0,0 +1,198 @@
+//! Server-internal observers.
+//!
+//! An observer receives the same object and message stream as a connected client, but without
+//! going through a UDP socket. This is useful for things like statistics processing, streaming
+//! the game somewhere else, or collecting training data.
+//!
+//! Observers are registered with [`ServerMut::add_observer`](crate::gamemode::ServerMut::add_observer),
+//! usually from [`GameMode::init`](crate::gamemode::GameMode::init).
+
+use nalgebra::{Point3, Rotation3, Vector3};
+
+use crate::game::{CustomScoreboardValue, PlayerId, ScoreboardValues, Team};
+use crate::protocol::{convert_matrix_from_network, ObjectPacket};
+use crate::record::AnnotationKind;
+use crate::server::{HQMMessage, TickPayload};
+
+/// Receives the game state each tick.
+pub trait GameObserver {
+    /// Called when a new game has been started.
+    fn new_game(&mut self, _game_id: u32) {}
+
+    /// Called once each tick, with the same data that is sent to clients.
+    fn tick(&mut self, tick: &ObservedTick);
+}
+
+/// The data that was sent to clients during one tick.
+pub struct ObservedTick<'a> {
+    pub(crate) payload: &'a TickPayload<'a>,
+}
+
+impl<'a> ObservedTick<'a> {
+    pub fn game_id(&self) -> u32 {
+        self.payload.game_id
+    }
+
+    pub fn game_step(&self) -> u32 {
+        self.payload.game_step
+    }
+
+    pub fn scoreboard(&self) -> &ScoreboardValues {
+        self.payload.scoreboard
+    }
+
+    /// The custom scoreboard values of the game mode, like the penalty clocks of the match mode.
+    pub fn custom_scoreboard(&self) -> &'a [CustomScoreboardValue] {
+        self.payload.custom_scoreboard
+    }
+
+    /// If a replay is shown, the player the clients are forced to follow.
+    pub fn forced_view(&self) -> Option<PlayerId> {
+        self.payload.forced_view
+    }
+
+    /// Returns the objects on the ice with their object index.
+    ///
+    /// Positions and rotations have the same precision as the ones the clients receive.
+    pub fn objects(&self) -> impl Iterator<Item = (usize, ObservedObject)> + 'a {
+        self.payload
+            .packets
+            .front()
+            .into_iter()
+            .flat_map(|packets| packets.iter().enumerate())
+            .filter_map(|(i, packet)| ObservedObject::from_packet(packet).map(|x| (i, x)))
+    }
+
+    /// Returns the messages that were sent to all clients since the previous tick.
+    ///
+    /// Messages directed to a single player or a single team are not included.
+    pub fn messages(&self) -> impl Iterator<Item = ObservedMessage<'a>> + 'a {
+        self.payload
+            .messages
+            .iter()
+            .map(|message| ObservedMessage::from_message(message))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ObservedObject {
+    Puck {
+        pos: Point3<f32>,
+        rot: Rotation3<f32>,
+    },
+    Skater {
+        pos: Point3<f32>,
+        rot: Rotation3<f32>,
+        stick_pos: Point3<f32>,
+        stick_rot: Rotation3<f32>,
+        head_rot: f32,
+        body_rot: f32,
+    },
+}
+
+impl ObservedObject {
+    fn from_packet(packet: &ObjectPacket) -> Option<Self> {
+        fn pos(v: (u32, u32, u32)) -> Point3<f32> {
+            Point3::new(v.0 as f32, v.1 as f32, v.2 as f32) / 1024.0
+        }
+        fn rot(b: u8, v: (u32, u32)) -> Rotation3<f32> {
+            Rotation3::from_matrix_unchecked(convert_matrix_from_network(b, v.0, v.1))
+        }
+        match packet {
+            ObjectPacket::None => None,
+            ObjectPacket::Puck(puck) => Some(ObservedObject::Puck {
+                pos: pos(puck.pos),
+                rot: rot(31, puck.rot),
+            }),
+            ObjectPacket::Skater(skater) => {
+                let body_pos = pos(skater.pos);
+                let stick_offset = pos(skater.stick_pos).coords - Vector3::new(4.0, 4.0, 4.0);
+                Some(ObservedObject::Skater {
+                    pos: body_pos,
+                    rot: rot(31, skater.rot),
+                    stick_pos: body_pos + stick_offset,
+                    stick_rot: rot(25, skater.stick_rot),
+                    head_rot: skater.head_rot as f32 / 8192.0 - 2.0,
+                    body_rot: skater.body_rot as f32 / 8192.0 - 2.0,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ObservedMessage<'a> {
+    /// A player has joined, left, or has changed team.
+    PlayerUpdate {
+        player_id: PlayerId,
+        /// Name of the player, or [`None`] if the player left.
+        player_name: Option<&'a str>,
+        /// Object index and team of the player, if the player is on the ice.
+        object: Option<(usize, Team)>,
+    },
+    Goal {
+        team: Team,
+        goal_player: Option<PlayerId>,
+        assist_player: Option<PlayerId>,
+    },
+    Chat {
+        /// Sender of the message, or [`None`] if it was sent by the server.
+        player_id: Option<PlayerId>,
+        message: &'a str,
+    },
+    /// A custom message that is only sent to clients that support extensions.
+    Extension {
+        message_type: u16,
+        payload: &'a [u8],
+    },
+    /// A game event that is marked in recordings, never sent to clients.
+    Annotation {
+        kind: AnnotationKind,
+        team: Option<Team>,
+        player_id: Option<PlayerId>,
+        reason: &'a str,
+    },
+}
+
+impl<'a> ObservedMessage<'a> {
+    fn from_message(message: &'a HQMMessage) -> Self {
+        match message {
+            HQMMessage::PlayerUpdate { player_id, data } => ObservedMessage::PlayerUpdate {
+                player_id: *player_id,
+                player_name: data.as_ref().map(|data| &*data.player_name),
+                object: data.as_ref().and_then(|data| data.object),
+            },
+            HQMMessage::Goal {
+                team,
+                goal_player,
+                assist_player,
+            } => ObservedMessage::Goal {
+                team: *team,
+                goal_player: *goal_player,
+                assist_player: *assist_player,
+            },
+            HQMMessage::Chat { player_id, message } => ObservedMessage::Chat {
+                player_id: *player_id,
+                message,
+            },
+            HQMMessage::Extension {
+                message_type,
+                payload,
+            } => ObservedMessage::Extension {
+                message_type: *message_type,
+                payload,
+            },
+            HQMMessage::Annotation {
+                kind,
+                team,
+                player_id,
+                reason,
+            } => ObservedMessage::Annotation {
+                kind: *kind,
+                team: *team,
+                player_id: *player_id,
+                reason,
+            },
+        }
+    }
+}
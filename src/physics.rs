@@ -1,3 +1,9 @@
+//! The physics simulation.
+//!
+//! [`simulate_step`] is the same function the server runs each tick, so tools like bots, analysis tools
+//! or client-side prediction can reproduce the server's physics exactly. [`PhysicsWorld`] holds the objects
+//! for running the simulation without a server.
+
 use crate::game::RinkSideOfLine::{BlueSide, RedSide};
 use crate::game::{
     PhysicsBody, PhysicsConfiguration, PlayerInput, Puck, Rink, RinkNet, SkaterCollisionBall,
@@ -24,15 +30,47 @@ fn replace_nan(v: f32, d: f32) -> f32 {
     }
 }
 
-type PhysicsEventList = SmallVec<[PhysicsEvent; 16]>;
+pub type PhysicsEventList = SmallVec<[PhysicsEvent; 16]>;
+
+/// Version of the physics simulation.
+///
+/// Increased whenever a change makes [`simulate_step`] give different results for the same input,
+/// so that tools reusing the simulation can tell whether their results are still comparable.
+pub const PHYSICS_VERSION: u32 = 1;
+
+/// A skater has fallen when the body Y axis points less upwards than this.
+const FALLEN_UPRIGHT: f32 = 0.5;
+/// A fallen skater is back on their feet when the body Y axis points more upwards than this.
+const RECOVERED_UPRIGHT: f32 = 0.85;
 type CollisionList = SmallVec<[Collision; 32]>;
 
+/// Maximum number of ticks stick collisions are rewound for players with a high ping.
+pub const MAX_LAG_COMPENSATION_TICKS: usize = 20;
+/// Maximum distance between the current puck position and the one a player is compensated for, in meters.
+const MAX_LAG_OFFSET: f32 = 0.5;
+
+/// How far a puck has moved since the player last saw it, used for lag compensation.
+///
+/// When the stick of the player is checked against the puck, the stick is moved by `offset`, so that the
+/// player hits the puck where it was on their screen when they moved the stick.
+#[derive(Debug, Clone, Copy)]
+pub struct LagOffset {
+    pub player: PlayerId,
+    pub puck: usize,
+    pub offset: Vector3<f32>,
+}
+
 impl HQMServer {
     pub(crate) fn simulate_step(&mut self) -> PhysicsEventList {
-        let mut events: PhysicsEventList = SmallVec::new();
+        let lag_offsets = if self.physics_config.lag_compensation {
+            self.lag_offsets()
+        } else {
+            self.puck_history.clear();
+            vec![]
+        };
         let mut players: ArrayVec<(PlayerId, &mut SkaterObject, &mut PlayerInput), 32> =
             ArrayVec::new();
-        let mut pucks: ArrayVec<(usize, &mut Puck, Point3<f32>), 32> = ArrayVec::new();
+        let mut pucks: ArrayVec<(usize, &mut Puck), 32> = ArrayVec::new();
         for (i, p) in self.state.players.players.iter_players_mut() {
             if let Some((_, skater, _)) = &mut p.object {
                 players.push((i, skater, &mut p.input));
@@ -40,93 +78,208 @@ impl HQMServer {
         }
         for (i, p) in self.state.pucks.iter_mut().enumerate() {
             if let Some(p) = p {
-                let old_pos = p.body.pos.clone();
-                pucks.push((i, p, old_pos));
+                pucks.push((i, p));
             }
         }
+        simulate_step_compensated(
+            &mut players,
+            &mut pucks,
+            &self.rink,
+            &self.physics_config,
+            &lag_offsets,
+        )
+    }
 
-        let mut collisions: CollisionList = SmallVec::new();
-        for (i, (_, player, input)) in players.iter_mut().enumerate() {
-            update_player(
-                i,
-                player,
-                input,
-                &self.physics_config,
-                &self.rink,
-                &mut collisions,
-            );
+    /// Saves the current puck positions, and finds how far each puck has moved since each skater's
+    /// player last saw it, based on the player's ping.
+    fn lag_offsets(&mut self) -> Vec<LagOffset> {
+        let positions = self
+            .state
+            .pucks
+            .iter()
+            .map(|puck| puck.as_ref().map(|puck| puck.body.pos))
+            .collect::<Vec<_>>();
+        self.puck_history.push_front(positions);
+        self.puck_history.truncate(MAX_LAG_COMPENSATION_TICKS + 1);
+
+        let mut res = vec![];
+        for (player_id, player) in self.state.players.players.iter_players() {
+            let Some(ping) = player.ping_data().filter(|_| player.has_skater()) else {
+                continue;
+            };
+            let ticks = ((ping.avg * 100.0).round() as usize).min(MAX_LAG_COMPENSATION_TICKS);
+            let (Some(current), Some(past)) =
+                (self.puck_history.front(), self.puck_history.get(ticks))
+            else {
+                continue;
+            };
+            for (puck, (current, past)) in current.iter().zip(past.iter()).enumerate() {
+                if let (Some(current), Some(past)) = (current, past) {
+                    let offset = current - past;
+                    if offset.norm() > 0.0 && offset.norm() <= MAX_LAG_OFFSET {
+                        res.push(LagOffset {
+                            player: player_id,
+                            puck,
+                            offset,
+                        });
+                    }
+                }
+            }
         }
+        res
+    }
+}
 
-        for i in 0..players.len() {
-            let (a, b) = players.split_at_mut(i + 1);
-            let (_, ref mut p1, _) = &mut a[i];
-
-            for (j, (_, p2, _)) in ((i + 1)..).zip(b.iter_mut()) {
-                for (ib, p1_collision_ball) in p1.collision_balls.iter().enumerate() {
-                    for (jb, p2_collision_ball) in p2.collision_balls.iter().enumerate() {
-                        let pos_diff = &p1_collision_ball.pos - &p2_collision_ball.pos;
-                        let radius_sum = &p1_collision_ball.radius + &p2_collision_ball.radius;
-                        if pos_diff.norm() < radius_sum {
-                            let overlap = radius_sum - pos_diff.norm();
-
-                            collisions.push(Collision::PlayerPlayer(
-                                (i, ib),
-                                (j, jb),
-                                overlap,
-                                Unit::new_normalize(pos_diff),
-                            ));
-                        }
+/// The moving objects of a physics simulation, for running the simulation outside of a server.
+#[derive(Debug, Clone, Default)]
+pub struct PhysicsWorld {
+    /// Skaters, with the player controlling each skater and the input for the next step. At most 32 are simulated.
+    pub skaters: Vec<(PlayerId, SkaterObject, PlayerInput)>,
+    /// Puck slots. The slot index is the puck number used in events. At most 32 pucks are simulated.
+    pub pucks: Vec<Option<Puck>>,
+}
+
+impl PhysicsWorld {
+    /// Advances the world by one tick, exactly like the server does.
+    pub fn step(&mut self, rink: &Rink, physics_config: &PhysicsConfiguration) -> PhysicsEventList {
+        let mut players: ArrayVec<(PlayerId, &mut SkaterObject, &mut PlayerInput), 32> = self
+            .skaters
+            .iter_mut()
+            .take(32)
+            .map(|(player_id, skater, input)| (*player_id, skater, input))
+            .collect();
+        let mut pucks: ArrayVec<(usize, &mut Puck), 32> = self
+            .pucks
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, puck)| puck.as_mut().map(|puck| (i, puck)))
+            .take(32)
+            .collect();
+        simulate_step(&mut players, &mut pucks, rink, physics_config)
+    }
+}
+
+/// Runs one tick of the physics simulation on the given skaters and pucks.
+///
+/// The result only depends on the arguments, see [`PHYSICS_VERSION`]. Returns the events, like puck touches
+/// and line crossings, that happened during the tick. At most 32 pucks can be simulated.
+pub fn simulate_step(
+    players: &mut [(PlayerId, &mut SkaterObject, &mut PlayerInput)],
+    pucks: &mut [(usize, &mut Puck)],
+    rink: &Rink,
+    physics_config: &PhysicsConfiguration,
+) -> PhysicsEventList {
+    simulate_step_compensated(players, pucks, rink, physics_config, &[])
+}
+
+/// Runs one tick of the physics simulation like [`simulate_step`], with stick collisions rewound
+/// for the players in `lag_offsets`.
+pub fn simulate_step_compensated(
+    players: &mut [(PlayerId, &mut SkaterObject, &mut PlayerInput)],
+    pucks: &mut [(usize, &mut Puck)],
+    rink: &Rink,
+    physics_config: &PhysicsConfiguration,
+    lag_offsets: &[LagOffset],
+) -> PhysicsEventList {
+    let mut events: PhysicsEventList = SmallVec::new();
+    let mut pucks: ArrayVec<(usize, &mut Puck, Point3<f32>), 32> = pucks
+        .iter_mut()
+        .map(|(i, puck)| {
+            let old_pos = puck.body.pos;
+            (*i, &mut **puck, old_pos)
+        })
+        .collect();
+
+    let mut collisions: CollisionList = SmallVec::new();
+    for (i, (_, player, input)) in players.iter_mut().enumerate() {
+        update_player(i, player, input, physics_config, rink, &mut collisions);
+    }
+
+    for i in 0..players.len() {
+        let (a, b) = players.split_at_mut(i + 1);
+        let (_, ref mut p1, _) = &mut a[i];
+
+        for (j, (_, p2, _)) in ((i + 1)..).zip(b.iter_mut()) {
+            for (ib, p1_collision_ball) in p1.collision_balls.iter().enumerate() {
+                for (jb, p2_collision_ball) in p2.collision_balls.iter().enumerate() {
+                    let pos_diff = &p1_collision_ball.pos - &p2_collision_ball.pos;
+                    let radius_sum = &p1_collision_ball.radius + &p2_collision_ball.radius;
+                    if pos_diff.norm() < radius_sum {
+                        let overlap = radius_sum - pos_diff.norm();
+
+                        collisions.push(Collision::PlayerPlayer(
+                            (i, ib),
+                            (j, jb),
+                            overlap,
+                            Unit::new_normalize(pos_diff),
+                        ));
                     }
                 }
-                let stick_v = &p1.stick_pos - &p2.stick_pos;
-                let stick_distance = stick_v.norm();
-                if stick_distance < 0.25 {
-                    let stick_overlap = 0.25 - stick_distance;
-                    let normal = Unit::new_normalize(stick_v);
-                    let mut force = 0.125 * stick_overlap * *normal
-                        + 0.25 * (&p2.stick_velocity - &p1.stick_velocity);
-                    if force.dot(&normal) > 0.0 {
-                        limit_friction(&mut force, &normal, 0.01);
-                        let force = force.scale(0.5);
-                        p1.stick_velocity += 0.5 * force;
-                        p2.stick_velocity -= 0.5 * force;
-                    }
+            }
+            let stick_v = &p1.stick_pos - &p2.stick_pos;
+            let stick_distance = stick_v.norm();
+            if stick_distance < 0.25 {
+                let stick_overlap = 0.25 - stick_distance;
+                let normal = Unit::new_normalize(stick_v);
+                let mut force = 0.125 * stick_overlap * *normal
+                    + 0.25 * (&p2.stick_velocity - &p1.stick_velocity);
+                if force.dot(&normal) > 0.0 {
+                    limit_friction(&mut force, &normal, 0.01);
+                    let force = force.scale(0.5);
+                    p1.stick_velocity += 0.5 * force;
+                    p2.stick_velocity -= 0.5 * force;
                 }
             }
         }
+    }
+
+    for (_, puck, _) in pucks.iter_mut() {
+        puck.body.linear_velocity[1] -= physics_config.gravity;
+    }
 
-        for (_, puck, _) in pucks.iter_mut() {
-            puck.body.linear_velocity[1] -= self.physics_config.gravity;
+    update_sticks_and_pucks(
+        players,
+        &mut pucks,
+        rink,
+        &mut events,
+        physics_config,
+        lag_offsets,
+    );
+
+    for (puck_index, puck, old_puck_pos) in pucks.iter_mut() {
+        if puck.body.linear_velocity.norm() > 1.0 / 65536.0 {
+            let scale = puck.body.linear_velocity.norm().powi(2) * 0.125 * 0.125;
+            let scaled = scale * puck.body.linear_velocity.normalize();
+            puck.body.linear_velocity -= scaled;
+        }
+        if puck.body.angular_velocity.norm() > 1.0 / 65536.0 {
+            rotate_matrix_around_axis(
+                &mut puck.body.rot,
+                &Unit::new_normalize(puck.body.angular_velocity),
+                puck.body.angular_velocity.norm(),
+            )
         }
 
-        update_sticks_and_pucks(
-            &mut players,
-            &mut pucks,
-            &self.rink,
-            &mut events,
-            &self.physics_config,
-        );
+        puck_detection(puck, *puck_index, &old_puck_pos, rink, &mut events);
+    }
 
-        for (puck_index, puck, old_puck_pos) in pucks.iter_mut() {
-            if puck.body.linear_velocity.norm() > 1.0 / 65536.0 {
-                let scale = puck.body.linear_velocity.norm().powi(2) * 0.125 * 0.125;
-                let scaled = scale * puck.body.linear_velocity.normalize();
-                puck.body.linear_velocity -= scaled;
-            }
-            if puck.body.angular_velocity.norm() > 1.0 / 65536.0 {
-                rotate_matrix_around_axis(
-                    &mut puck.body.rot,
-                    &Unit::new_normalize(puck.body.angular_velocity),
-                    puck.body.angular_velocity.norm(),
-                )
-            }
+    let impulses = apply_collisions(players, &collisions);
 
-            puck_detection(puck, *puck_index, &old_puck_pos, &self.rink, &mut events);
+    for ((player_id, skater, _), impulse) in players.iter_mut().zip(impulses) {
+        if let Some(knockdown_impulse) = physics_config.knockdown_impulse {
+            if impulse > knockdown_impulse {
+                skater.balance_timer = physics_config.knockdown_recovery_time;
+            }
+        }
+        let upright = (skater.body.rot * Vector3::y()).y;
+        if !skater.fallen && upright < FALLEN_UPRIGHT {
+            skater.fallen = true;
+            events.push(PhysicsEvent::SkaterFell { player: *player_id });
+        } else if skater.fallen && upright > RECOVERED_UPRIGHT {
+            skater.fallen = false;
         }
-
-        apply_collisions(&mut players, &collisions);
-        events
     }
+    events
 }
 
 fn update_sticks_and_pucks(
@@ -135,6 +288,7 @@ fn update_sticks_and_pucks(
     rink: &Rink,
     events: &mut PhysicsEventList,
     physics_config: &PhysicsConfiguration,
+    lag_offsets: &[LagOffset],
 ) {
     for i in 0..10 {
         for (_, player, _) in players.iter_mut() {
@@ -157,6 +311,13 @@ fn update_sticks_and_pucks(
                 );
             }
             for (player_index, player, _) in players.iter_mut() {
+                let lag_offset = lag_offsets
+                    .iter()
+                    .find(|x| x.player == *player_index && x.puck == *puck_index)
+                    .map(|x| x.offset);
+                if let Some(offset) = lag_offset {
+                    player.stick_pos += offset;
+                }
                 let old_stick_velocity = player.stick_velocity.clone_owned();
                 if (&puck.body.pos - &player.stick_pos).norm() < 1.0 {
                     let has_touched = do_puck_stick_forces(
@@ -174,6 +335,9 @@ fn update_sticks_and_pucks(
                         })
                     }
                 }
+                if let Some(offset) = lag_offset {
+                    player.stick_pos -= offset;
+                }
             }
             let red_net_collision = do_puck_post_forces(
                 puck,
@@ -203,6 +367,15 @@ fn update_sticks_and_pucks(
                     &puck_angular_velocity_before,
                 );
 
+            for obstacle in rink.obstacles.iter() {
+                do_puck_post_force(
+                    puck,
+                    &obstacle.post(),
+                    &puck_linear_velocity_before,
+                    &puck_angular_velocity_before,
+                );
+            }
+
             if red_net_collision {
                 events.push(PhysicsEvent::PuckTouchedNet {
                     team: Team::Red,
@@ -219,17 +392,35 @@ fn update_sticks_and_pucks(
     }
 }
 
+/// Returns the stick position and stick angle input, limited to their normal ranges.
+pub(crate) fn clamped_stick_input(input: &PlayerInput) -> (Vector2<f32>, f32) {
+    let stick = Vector2::new(
+        replace_nan(input.stick[0], 0.0).clamp(-FRAC_PI_2, FRAC_PI_2),
+        replace_nan(input.stick[1], 0.0).clamp(-5.0 * PI / 16.0, FRAC_PI_8),
+    );
+    let stick_angle = replace_nan(input.stick_angle, 0.0).clamp(-1.0, 1.0);
+    (stick, stick_angle)
+}
+
 fn update_stick(
     player: &mut SkaterObject,
     input: &mut PlayerInput,
     linear_velocity_before: &Vector3<f32>,
     angular_velocity_before: &Vector3<f32>,
     rink: &Rink,
+    max_stick_rotation: Option<f32>,
 ) {
-    let stick_input = Vector2::new(
-        replace_nan(input.stick[0], 0.0).clamp(-FRAC_PI_2, FRAC_PI_2),
-        replace_nan(input.stick[1], 0.0).clamp(-5.0 * PI / 16.0, FRAC_PI_8),
-    );
+    let (mut stick_input, mut stick_angle) = clamped_stick_input(input);
+    if let Some(max_rotation) = max_stick_rotation {
+        stick_input = player.stick_input
+            + limit_vector_length2(&(stick_input - player.stick_input), max_rotation);
+        // The stick angle input is a quarter turn for each unit
+        let max_angle_change = max_rotation / FRAC_PI_4;
+        stick_angle = player.stick_angle
+            + (stick_angle - player.stick_angle).clamp(-max_angle_change, max_angle_change);
+    }
+    player.stick_input = stick_input;
+    player.stick_angle = stick_angle;
 
     let placement_diff = stick_input - &player.stick_placement;
     let placement_change = 0.0625 * placement_diff - 0.5 * player.stick_placement_delta;
@@ -275,7 +466,7 @@ fn update_stick(
         rotate_matrix_around_axis(
             &mut new_stick_rotation,
             &handle_axis,
-            (-replace_nan(input.stick_angle, 0.0)).clamp(-1.0, 1.0) * FRAC_PI_4,
+            -stick_angle * FRAC_PI_4,
         );
 
         new_stick_rotation
@@ -463,6 +654,17 @@ fn update_player(
         if let Some((overlap, normal)) = collision {
             collisions.push(Collision::PlayerRink((i, ib), overlap, normal));
         }
+        // Obstacles don't move, so they push back like the rink does
+        for obstacle in rink.obstacles.iter() {
+            let collision = collision_between_sphere_and_post(
+                &collision_ball.pos,
+                collision_ball.radius,
+                &obstacle.post(),
+            );
+            if let Some((overlap, normal)) = collision {
+                collisions.push(Collision::PlayerRink((i, ib), overlap, normal));
+            }
+        }
     }
     let linear_velocity_before = player.body.linear_velocity.clone_owned();
     let angular_velocity_before = player.body.angular_velocity.clone_owned();
@@ -502,6 +704,7 @@ fn update_player(
         player.body.linear_velocity[1] += 0.00055555555; // Extra speed boost upwards if body is low (fallen?) and the speed is slow
         touches_ice = true;
     }
+    player.balance_timer = player.balance_timer.saturating_sub(1);
     if touches_ice {
         // This is where the leaning happens
         player.body.angular_velocity *= 0.975;
@@ -520,7 +723,11 @@ fn update_player(
         }
 
         let rotation1 = intended_up.cross(&(player.body.rot * Vector3::y())); // Vector that is perpendicular to the main Y and the current player Y
-        if let Some(rotation1_direction) = rotation1.try_normalize(0.0) {
+                                                                              // A knocked down skater can't keep balance until the timer runs out
+        let rotation1_direction = rotation1
+            .try_normalize(0.0)
+            .filter(|_| player.balance_timer == 0);
+        if let Some(rotation1_direction) = rotation1_direction {
             let rotation1_direction = Unit::new_unchecked(rotation1_direction);
             let angular_change = 0.008333333 * rotation1
                 - 0.25 * get_projection(&player.body.angular_velocity, &rotation1_direction);
@@ -534,6 +741,7 @@ fn update_player(
         &linear_velocity_before,
         &angular_velocity_before,
         rink,
+        physics_config.max_stick_rotation,
     );
 }
 
@@ -542,10 +750,13 @@ fn get_projection(a: &Vector3<f32>, normal: &Unit<Vector3<f32>>) -> Vector3<f32>
     normal.scale(normal.dot(a))
 }
 
+/// Applies the collisions, and returns the total velocity change of each player caused by
+/// collisions with other players.
 fn apply_collisions(
     players: &mut [(PlayerId, &mut SkaterObject, &mut PlayerInput)],
     collisions: &[Collision],
-) {
+) -> ArrayVec<f32, 32> {
+    let mut impulses = ArrayVec::<f32, 32>::from_iter(players.iter().map(|_| 0.0));
     for _ in 0..16 {
         let original_ball_velocities =
             ArrayVec::<_, 32>::from_iter(players.iter().map(|(_, skater, _)| {
@@ -589,14 +800,17 @@ fn apply_collisions(
 
                         let (_, skater1, _) = &mut players[i];
                         skater1.collision_balls[ib].velocity += (mass2 / mass_sum) * new;
+                        impulses[i] += (mass2 / mass_sum) * new.norm();
 
                         let (_, skater2, _) = &mut players[j];
                         skater2.collision_balls[jb].velocity -= (mass1 / mass_sum) * new;
+                        impulses[j] += (mass1 / mass_sum) * new.norm();
                     }
                 }
             }
         }
     }
+    impulses
 }
 
 fn puck_detection(
@@ -788,25 +1002,37 @@ fn do_puck_post_forces(
 ) -> bool {
     let mut res = false;
     for post in net.posts.iter() {
-        let collision = collision_between_sphere_and_post(&puck.body.pos, puck.radius, post);
-        if let Some((overlap, normal)) = collision {
-            res = true;
-            let p = puck.body.pos - normal.scale(puck.radius);
-            let vertex_velocity = speed_of_point_including_rotation(
-                &p,
-                &puck.body.pos,
-                puck_linear_velocity,
-                puck_angular_velocity,
-            );
-            let mut puck_force = normal.scale(overlap * 0.125) - 0.25 * vertex_velocity;
+        res |= do_puck_post_force(puck, post, puck_linear_velocity, puck_angular_velocity);
+    }
+    res
+}
 
-            if normal.dot(&puck_force) > 0.0 {
-                limit_friction(&mut puck_force, &normal, 0.2);
-                apply_acceleration_to_object(&mut puck.body, &puck_force, &p);
-            }
+/// Pushes the puck away from a post or an obstacle. Returns true if they collided.
+fn do_puck_post_force(
+    puck: &mut Puck,
+    post: &(Point3<f32>, Point3<f32>, f32),
+    puck_linear_velocity: &Vector3<f32>,
+    puck_angular_velocity: &Vector3<f32>,
+) -> bool {
+    let collision = collision_between_sphere_and_post(&puck.body.pos, puck.radius, post);
+    if let Some((overlap, normal)) = collision {
+        let p = puck.body.pos - normal.scale(puck.radius);
+        let vertex_velocity = speed_of_point_including_rotation(
+            &p,
+            &puck.body.pos,
+            puck_linear_velocity,
+            puck_angular_velocity,
+        );
+        let mut puck_force = normal.scale(overlap * 0.125) - 0.25 * vertex_velocity;
+
+        if normal.dot(&puck_force) > 0.0 {
+            limit_friction(&mut puck_force, &normal, 0.2);
+            apply_acceleration_to_object(&mut puck.body, &puck_force, &p);
         }
+        true
+    } else {
+        false
     }
-    res
 }
 
 fn do_puck_stick_forces(
@@ -863,6 +1089,17 @@ fn do_puck_rink_forces(
             );
             let mut puck_force = 0.125 * 0.125 * (overlap * 0.5 * *normal - vertex_velocity);
 
+            // The ice is the only plane that points up, everything else is boards
+            let zone = (normal.y < 0.5).then(|| rink.board_zone(vertex)).flatten();
+            let friction = match zone {
+                Some(zone) => {
+                    // A stiffer or softer push back, with the same damping
+                    puck_force += (zone.stiffness - 1.0) * 0.125 * 0.125 * overlap * 0.5 * *normal;
+                    zone.friction.unwrap_or(friction)
+                }
+                None => friction,
+            };
+
             if normal.dot(&puck_force) > 0.0 {
                 limit_friction(&mut puck_force, &normal, friction);
                 apply_acceleration_to_object(&mut puck.body, &puck_force, &vertex);
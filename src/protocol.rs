@@ -32,6 +32,8 @@ pub enum HQMClientToServerMessage {
     Join {
         version: u32,
         player_name: String,
+        /// True if the client wants to sign its packets.
+        signed: bool,
     },
     Update {
         current_game_id: u32,
@@ -70,6 +72,7 @@ impl HQMMessageCodec {
             4 => self.parse_player_update(&mut parser, HQMClientVersion::Vanilla),
             8 => self.parse_player_update(&mut parser, HQMClientVersion::Ping),
             0x10 => self.parse_player_update(&mut parser, HQMClientVersion::PingRules),
+            0x20 => self.parse_player_update(&mut parser, HQMClientVersion::Extensions),
             7 => Ok(HQMClientToServerMessage::Exit),
             _ => Err(HQMClientToServerMessageDecoderError::UnknownType),
         }
@@ -92,9 +95,12 @@ impl HQMMessageCodec {
         let mut player_name = [0; 32];
         parser.read_bytes_aligned(&mut player_name);
         let player_name = get_player_name(&player_name)?;
+        // Vanilla clients don't send anything more
+        let signed = parser.read_byte_aligned() == 1;
         Ok(HQMClientToServerMessage::Join {
             version,
             player_name,
+            signed,
         })
     }
 
@@ -200,7 +206,6 @@ pub fn convert_matrix_to_network(b: u8, v: &Matrix3<f32>) -> (u32, u32) {
     (r1, r2)
 }
 
-#[allow(dead_code)]
 pub fn convert_matrix_from_network(b: u8, v1: u32, v2: u32) -> Matrix3<f32> {
     let r1 = convert_rot_column_from_network(b, v1);
     let r2 = convert_rot_column_from_network(b, v2);
@@ -208,7 +213,6 @@ pub fn convert_matrix_from_network(b: u8, v1: u32, v2: u32) -> Matrix3<f32> {
     Matrix3::from_columns(&[r0, r1, r2])
 }
 
-#[allow(dead_code)]
 fn convert_rot_column_from_network(b: u8, v: u32) -> Vector3<f32> {
     let start = v & 7;
 
@@ -562,15 +566,12 @@ pub(crate) struct PuckPacket {
 
 pub(crate) fn write_message(writer: &mut HQMMessageWriter, message: &HQMMessage) {
     match message {
-        HQMMessage::Chat {
-            player_index,
-            message,
-        } => {
+        HQMMessage::Chat { player_id, message } => {
             writer.write_bits(6, 2);
             writer.write_bits(
                 6,
-                match *player_index {
-                    Some(x) => x.0 as u32,
+                match *player_id {
+                    Some(x) => x.index.0 as u32,
                     None => u32::MAX,
                 },
             );
@@ -584,29 +585,63 @@ pub(crate) fn write_message(writer: &mut HQMMessageWriter, message: &HQMMessage)
         }
         HQMMessage::Goal {
             team,
-            goal_player_index,
-            assist_player_index,
+            goal_player,
+            assist_player,
         } => {
             writer.write_bits(6, 1);
             writer.write_bits(2, team.get_num());
             writer.write_bits(
                 6,
-                match *goal_player_index {
-                    Some(x) => x.0 as u32,
+                match *goal_player {
+                    Some(x) => x.index.0 as u32,
                     None => u32::MAX,
                 },
             );
             writer.write_bits(
                 6,
-                match *assist_player_index {
-                    Some(x) => x.0 as u32,
+                match *assist_player {
+                    Some(x) => x.index.0 as u32,
                     None => u32::MAX,
                 },
             );
         }
-        HQMMessage::PlayerUpdate { player_index, data } => {
+        HQMMessage::Extension {
+            message_type,
+            payload,
+        } => {
+            writer.write_bits(6, 3);
+            writer.write_bits(16, *message_type as u32);
+            writer.write_bits(8, payload.len() as u32);
+            for byte in payload {
+                writer.write_bits(8, *byte as u32);
+            }
+        }
+        HQMMessage::Annotation {
+            kind,
+            team,
+            player_id,
+            reason,
+        } => {
+            writer.write_bits(6, 4);
+            writer.write_bits(4, kind.get_num());
+            writer.write_bits(2, team.map_or(u32::MAX, |team| team.get_num()));
+            writer.write_bits(
+                6,
+                match *player_id {
+                    Some(x) => x.index.0 as u32,
+                    None => u32::MAX,
+                },
+            );
+            let reason_bytes = reason.as_bytes();
+            let size = min(255, reason_bytes.len());
+            writer.write_bits(8, size as u32);
+            for byte in &reason_bytes[..size] {
+                writer.write_bits(8, *byte as u32);
+            }
+        }
+        HQMMessage::PlayerUpdate { player_id, data } => {
             writer.write_bits(6, 0);
-            writer.write_bits(6, player_index.0 as u32);
+            writer.write_bits(6, player_id.index.0 as u32);
 
             let (in_server, name_bytes) = match data {
                 None => (false, &[] as &[u8]),
@@ -658,6 +693,23 @@ pub(crate) fn write_objects(
         }
     };
 
+    write_object_packets(
+        writer,
+        current_packets,
+        old_packets,
+        current_packet,
+        known_packet,
+    );
+}
+
+/// Writes a list of objects, delta compressed against `old_packets` if there are any.
+pub(crate) fn write_object_packets(
+    writer: &mut HQMMessageWriter,
+    current_packets: &[ObjectPacket],
+    old_packets: Option<&[ObjectPacket; 32]>,
+    current_packet: u32,
+    known_packet: u32,
+) {
     writer.write_u32_aligned(current_packet);
     writer.write_u32_aligned(known_packet);
 
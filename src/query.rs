@@ -0,0 +1,64 @@
+//! Server list queries on a separate port.
+//!
+//! Server browsers ask every server in the list for its name and player count, and a popular server can
+//! get a lot of these queries. When a query port is configured, the queries are also answered on that UDP
+//! port by a separate task, from a copy of the server info that the server updates each tick. This keeps
+//! the query traffic away from the socket and the task that handle the game.
+
+use std::io;
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tracing::info;
+
+use crate::protocol::{HQMClientToServerMessage, HQMMessageCodec, HQMMessageWriter};
+use crate::server::GAME_HEADER;
+
+/// The data sent in response to a server info query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ServerInfo {
+    pub player_count: usize,
+    pub team_size: u32,
+    pub server_name: String,
+}
+
+/// Writes the response to a server info query. `ping` is the value sent in the query.
+pub(crate) fn write_server_info(write_buf: &mut BytesMut, ping: u32, server_info: &ServerInfo) {
+    write_buf.clear();
+    let mut writer = HQMMessageWriter::new(write_buf);
+    writer.write_bytes_aligned(GAME_HEADER);
+    writer.write_byte_aligned(1);
+    writer.write_bits(8, 55);
+    writer.write_u32_aligned(ping);
+
+    writer.write_bits(8, server_info.player_count as u32);
+    writer.write_bits(4, 4);
+    writer.write_bits(4, server_info.team_size);
+
+    writer.write_bytes_aligned_padded(32, server_info.server_name.as_ref());
+}
+
+/// Starts answering server info queries on `port`, with the latest info from `server_info`.
+pub(crate) async fn start(port: u16, server_info: watch::Receiver<ServerInfo>) -> io::Result<()> {
+    let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
+    info!("Answering queries at address {:?}", socket.local_addr()?);
+    tokio::spawn(async move {
+        let mut buf = BytesMut::with_capacity(512);
+        let mut write_buf = BytesMut::with_capacity(64);
+        loop {
+            buf.clear();
+            let Ok((_, addr)) = socket.recv_buf_from(&mut buf).await else {
+                continue;
+            };
+            if let Ok(HQMClientToServerMessage::ServerInfo { ping, .. }) =
+                HQMMessageCodec.parse_message(&buf)
+            {
+                write_server_info(&mut write_buf, ping, &server_info.borrow());
+                let _ = socket.send_to(&write_buf, addr).await;
+            }
+        }
+    });
+    Ok(())
+}
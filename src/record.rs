@@ -1,9 +1,285 @@
-use crate::ServerConfiguration;
+use crate::game::Team;
+use crate::json::{JsonObjectWriter, ToJson};
+use crate::{ServerConfiguration, TeamNames};
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
+use std::cell::RefCell;
 use std::path::PathBuf;
+use std::rc::Rc;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+
+/// Information about a recorded game, saved together with the recording.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct GameSummary {
+    /// True if the game has been tagged as a league game.
+    pub league: bool,
+    /// Team names at the end of the game.
+    pub teams: TeamNames,
+    /// Automatically detected highlights, in the order they happened.
+    pub highlights: Vec<Highlight>,
+    /// Time each player has spent on the ice for each team. Only filled in by game modes that keep track of it.
+    pub ice_time: Vec<IceTime>,
+    /// Issues reported by players with /report during the game.
+    pub reports: Vec<PlayerReport>,
+    /// Moments marked by admins with /bookmark during the game.
+    pub bookmarks: Vec<Bookmark>,
+    /// The team that forfeited the game, if any.
+    pub forfeit: Option<Team>,
+    /// Result of the MVP vote at the end of the game, if anyone voted.
+    pub mvp: Option<MvpVote>,
+    /// If the game was resumed after being abandoned, the snapshot or recording it was resumed from.
+    pub resumed_from: Option<String>,
+    /// True if the recording is a clip saved with /clip, instead of a whole game.
+    pub clip: bool,
+    /// Seed of the random numbers of the game, see [`rng`](crate::rng).
+    pub random_seed: u64,
+}
+
+impl ToJson for GameSummary {
+    fn write_json(&self, out: &mut String) {
+        JsonObjectWriter::new(out)
+            .field("league", &self.league)
+            .field("teams", &self.teams)
+            .field("highlights", &self.highlights)
+            .field("ice_time", &self.ice_time)
+            .field("reports", &self.reports)
+            .field("bookmarks", &self.bookmarks)
+            .field("forfeit", &self.forfeit.map(team_str))
+            .field("mvp", &self.mvp)
+            .field("resumed_from", &self.resumed_from)
+            .field("clip", &self.clip)
+            // As a string, since JSON numbers often lose precision above 2^53
+            .field("random_seed", &self.random_seed.to_string())
+            .finish();
+    }
+}
+
+impl ToJson for TeamNames {
+    fn write_json(&self, out: &mut String) {
+        struct TeamJson<'a>(&'a TeamNames, Team);
+
+        impl ToJson for TeamJson<'_> {
+            fn write_json(&self, out: &mut String) {
+                JsonObjectWriter::new(out)
+                    .field("name", self.0.name(self.1))
+                    .field("color", &self.0.color(self.1))
+                    .finish();
+            }
+        }
+
+        JsonObjectWriter::new(out)
+            .field("red", &TeamJson(self, Team::Red))
+            .field("blue", &TeamJson(self, Team::Blue))
+            .finish();
+    }
+}
+
+/// Kind of an annotation in a recording.
+///
+/// Annotations mark game events in the recording stream, so that replay viewers can build a timeline.
+/// The period and game clock of the annotation are the ones in the recorded tick it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnnotationKind {
+    Goal,
+    /// Play was stopped, for example for offside or icing.
+    Stoppage,
+    Penalty,
+    /// A player has reported an issue with /report.
+    Report,
+    /// An admin has marked a moment with /bookmark.
+    Bookmark,
+}
+
+impl AnnotationKind {
+    pub(crate) fn get_num(self) -> u32 {
+        match self {
+            AnnotationKind::Goal => 0,
+            AnnotationKind::Stoppage => 1,
+            AnnotationKind::Penalty => 2,
+            AnnotationKind::Report => 3,
+            AnnotationKind::Bookmark => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HighlightKind {
+    Goal,
+    Post,
+    BigHit,
+    Breakaway,
+}
+
+impl HighlightKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            HighlightKind::Goal => "goal",
+            HighlightKind::Post => "post",
+            HighlightKind::BigHit => "hit",
+            HighlightKind::Breakaway => "breakaway",
+        }
+    }
+}
+
+/// A moment of a recorded game that might be worth including in a highlight reel.
+///
+/// Ticks are counted from the start of the recording, with 100 ticks per second.
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    pub kind: HighlightKind,
+    pub period: u32,
+    /// Game clock when it happened, in hundreds of a second.
+    pub time: u32,
+    /// Recording tick when it happened.
+    pub tick: u32,
+    /// Suggested first recording tick of the clip.
+    pub start: u32,
+    /// Suggested last recording tick of the clip.
+    pub end: u32,
+    /// The player involved, if any. For goals and posts, it's the last player to touch the puck.
+    pub player: Option<Rc<str>>,
+}
+
+impl ToJson for Highlight {
+    fn write_json(&self, out: &mut String) {
+        JsonObjectWriter::new(out)
+            .field("kind", self.kind.as_str())
+            .field("period", &self.period)
+            .field("time", &self.time)
+            .field("tick", &self.tick)
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("player", &self.player)
+            .finish();
+    }
+}
+
+/// An issue reported by a player with /report.
+#[derive(Debug, Clone)]
+pub struct PlayerReport {
+    pub reporter: Rc<str>,
+    /// The player the report is about, if any.
+    pub target: Option<Rc<str>>,
+    pub reason: String,
+    pub game_id: u32,
+    pub game_step: u32,
+    pub period: u32,
+    /// Game clock when the report was made, in hundreds of a second.
+    pub time: u32,
+    /// Recording tick when the report was made, if the game is recorded.
+    pub tick: Option<u32>,
+    pub created: DateTime<Utc>,
+}
+
+impl ToJson for PlayerReport {
+    fn write_json(&self, out: &mut String) {
+        JsonObjectWriter::new(out)
+            .field("reporter", &self.reporter)
+            .field("target", &self.target)
+            .field("reason", &self.reason)
+            .field("game_id", &self.game_id)
+            .field("game_step", &self.game_step)
+            .field("period", &self.period)
+            .field("time", &self.time)
+            .field("tick", &self.tick)
+            .field("created", &self.created.to_rfc3339())
+            .finish();
+    }
+}
+
+/// A moment marked by an admin with /bookmark, for example to review it later.
+#[derive(Debug, Clone)]
+pub struct Bookmark {
+    pub admin: Rc<str>,
+    pub label: String,
+    pub game_step: u32,
+    pub period: u32,
+    /// Game clock when the bookmark was made, in hundreds of a second.
+    pub time: u32,
+    /// Recording tick when the bookmark was made, if the game is recorded.
+    pub tick: Option<u32>,
+    pub created: DateTime<Utc>,
+}
+
+impl ToJson for Bookmark {
+    fn write_json(&self, out: &mut String) {
+        JsonObjectWriter::new(out)
+            .field("admin", &self.admin)
+            .field("label", &self.label)
+            .field("game_step", &self.game_step)
+            .field("period", &self.period)
+            .field("time", &self.time)
+            .field("tick", &self.tick)
+            .field("created", &self.created.to_rfc3339())
+            .finish();
+    }
+}
+
+/// The player voted as the MVP of a game.
+#[derive(Debug, Clone)]
+pub struct MvpVote {
+    pub player: Rc<str>,
+    pub votes: u32,
+    /// Number of players who voted.
+    pub total_votes: u32,
+}
+
+impl ToJson for MvpVote {
+    fn write_json(&self, out: &mut String) {
+        JsonObjectWriter::new(out)
+            .field("player", &self.player)
+            .field("votes", &self.votes)
+            .field("total_votes", &self.total_votes)
+            .finish();
+    }
+}
+
+/// Game time a player has spent on the ice for one team.
+#[derive(Debug, Clone)]
+pub struct IceTime {
+    pub player: Rc<str>,
+    pub team: Team,
+    /// Time on the ice in each period, in hundreds of a second. The first element is period 1,
+    /// and overtime periods follow the regular ones.
+    pub periods: Vec<u32>,
+}
+
+impl IceTime {
+    /// Total time on the ice, in hundreds of a second.
+    pub fn total(&self) -> u32 {
+        self.periods.iter().sum()
+    }
+
+    /// Time on the ice in a period, in hundreds of a second.
+    pub fn period(&self, period: u32) -> u32 {
+        period
+            .checked_sub(1)
+            .and_then(|i| self.periods.get(i as usize))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl ToJson for IceTime {
+    fn write_json(&self, out: &mut String) {
+        JsonObjectWriter::new(out)
+            .field("player", &self.player)
+            .field("team", team_str(self.team))
+            .field("periods", &self.periods)
+            .field("total", &self.total())
+            .finish();
+    }
+}
+
+fn team_str(team: Team) -> &'static str {
+    match team {
+        Team::Red => "red",
+        Team::Blue => "blue",
+    }
+}
 
 pub trait RecordingSaveMethod {
     fn save_recording_data(
@@ -11,16 +287,62 @@ pub trait RecordingSaveMethod {
         config: &ServerConfiguration,
         replay_data: Bytes,
         start_time: DateTime<Utc>,
+        summary: &GameSummary,
     );
+
+    /// Returns the saves that are still in progress, so that they can be waited for before the program exits.
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        vec![]
+    }
+}
+
+/// A recording saver shared by several servers in the same process.
+impl<T> RecordingSaveMethod for Rc<RefCell<T>>
+where
+    T: RecordingSaveMethod + ?Sized,
+{
+    fn save_recording_data(
+        &mut self,
+        config: &ServerConfiguration,
+        replay_data: Bytes,
+        start_time: DateTime<Utc>,
+        summary: &GameSummary,
+    ) {
+        self.borrow_mut()
+            .save_recording_data(config, replay_data, start_time, summary);
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        self.borrow_mut().take_pending_saves()
+    }
+}
+
+fn recording_file_name(
+    config: &ServerConfiguration,
+    start_time: DateTime<Utc>,
+    summary: &GameSummary,
+) -> String {
+    let time = start_time.format("%Y-%m-%dT%H%M%S").to_string();
+    if summary.clip {
+        format!("{}.{}.clip", config.server_name, time)
+    } else if summary.league {
+        format!("{}.{}.league", config.server_name, time)
+    } else {
+        format!("{}.{}", config.server_name, time)
+    }
 }
 
 pub struct RecordingSaveToFile {
     directory: PathBuf,
+    pending: Vec<JoinHandle<()>>,
 }
 
 impl RecordingSaveToFile {
     pub fn new(directory: PathBuf) -> Self {
-        Self { directory }
+        Self {
+            directory,
+            pending: vec![],
+        }
     }
 }
 
@@ -30,13 +352,16 @@ impl RecordingSaveMethod for RecordingSaveToFile {
         config: &ServerConfiguration,
         replay_data: Bytes,
         start_time: DateTime<Utc>,
+        summary: &GameSummary,
     ) {
-        let time = start_time.format("%Y-%m-%dT%H%M%S").to_string();
-        let file_name = format!("{}.{}.hrp", config.server_name, time);
+        let file_name = recording_file_name(config, start_time, summary);
         let directory = self.directory.clone();
-        let path = self.directory.join(&file_name);
+        let path = self.directory.join(format!("{}.hrp", file_name));
+        let summary_path = self.directory.join(format!("{}.json", file_name));
+        let summary = summary.to_json();
 
-        tokio::spawn(async move {
+        self.pending.retain(|handle| !handle.is_finished());
+        let handle = tokio::spawn(async move {
             if tokio::fs::create_dir_all(directory).await.is_err() {
                 return;
             };
@@ -50,13 +375,21 @@ impl RecordingSaveMethod for RecordingSaveToFile {
 
             let _x = file_handle.write(&replay_data).await;
             let _x = file_handle.sync_all().await;
+
+            let _x = tokio::fs::write(summary_path, summary).await;
         });
+        self.pending.push(handle);
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        std::mem::take(&mut self.pending)
     }
 }
 
 pub struct RecordingSendToHttpEndpoint {
     url: String,
     client: reqwest::Client,
+    pending: Vec<JoinHandle<()>>,
 }
 
 impl RecordingSendToHttpEndpoint {
@@ -64,6 +397,7 @@ impl RecordingSendToHttpEndpoint {
         Self {
             url,
             client: reqwest::Client::new(),
+            pending: vec![],
         }
     }
 }
@@ -74,22 +408,33 @@ impl RecordingSaveMethod for RecordingSendToHttpEndpoint {
         config: &ServerConfiguration,
         replay_data: Bytes,
         start_time: DateTime<Utc>,
+        summary: &GameSummary,
     ) {
         let client = self.client.clone();
         let server_name = config.server_name.clone();
         let time = start_time.format("%Y-%m-%dT%H%M%S").to_string();
-        let file_name = format!("{}.{}.hrp", config.server_name, time);
+        let file_name = format!("{}.hrp", recording_file_name(config, start_time, summary));
         let form = reqwest::multipart::Form::new()
             .text("time", time)
             .text("server", server_name)
+            .text("league", summary.league.to_string())
+            .text("red_team", summary.teams.red.clone())
+            .text("blue_team", summary.teams.blue.clone())
+            .text("summary", summary.to_json())
             .part(
                 "replay",
                 reqwest::multipart::Part::stream(replay_data).file_name(file_name),
             );
 
         let request = client.post(&self.url).multipart(form);
-        tokio::spawn(async move {
+        self.pending.retain(|handle| !handle.is_finished());
+        let handle = tokio::spawn(async move {
             let _x = request.send().await;
         });
+        self.pending.push(handle);
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        std::mem::take(&mut self.pending)
     }
 }
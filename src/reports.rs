@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use tracing::info;
+
+use crate::game::{PlayerId, PlayerIndex};
+use crate::record::{AnnotationKind, PlayerReport};
+use crate::server::{HQMServer, PlayerListExt};
+use crate::ReplayRecording;
+
+/// Number of reports kept for /reports.
+const REPORT_QUEUE_LENGTH: usize = 50;
+
+/// Minimum time between two reports from the same player.
+const REPORT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Number of reports shown by /reports.
+const REPORTS_SHOWN: usize = 5;
+
+struct QueuedReport {
+    id: u32,
+    reporter_id: PlayerId,
+    received: Instant,
+    report: PlayerReport,
+}
+
+/// Reports that admins haven't cleared yet, newest last.
+#[derive(Default)]
+pub(crate) struct ReportQueue {
+    reports: VecDeque<QueuedReport>,
+    next_id: u32,
+}
+
+impl HQMServer {
+    /// Handles /report [player] reason.
+    pub(crate) fn report(&mut self, player_id: PlayerId, arg: &str) {
+        let Some(reporter) = self.state.players.players.get_player(player_id) else {
+            return;
+        };
+        let reporter_name = reporter.player_name.clone();
+
+        let (target, reason) = match arg.split_once(' ') {
+            Some((first, rest)) => match first
+                .parse::<PlayerIndex>()
+                .ok()
+                .and_then(|index| self.state.players.players.get_player_by_index(index))
+            {
+                Some((target_id, target)) => {
+                    (Some((target_id, target.player_name.clone())), rest.trim())
+                }
+                None => (None, arg.trim()),
+            },
+            None => (None, arg.trim()),
+        };
+        if reason.is_empty() {
+            self.state
+                .players
+                .add_directed_server_chat_message("Usage: /report [player ID] reason", player_id);
+            return;
+        }
+
+        let is_admin = reporter.is_admin;
+        let recent = self
+            .reports
+            .reports
+            .iter()
+            .rev()
+            .find(|x| x.reporter_id == player_id);
+        if !is_admin && recent.is_some_and(|x| x.received.elapsed() < REPORT_COOLDOWN) {
+            self.state.players.add_directed_server_chat_message(
+                "Please wait before sending another report",
+                player_id,
+            );
+            return;
+        }
+
+        let recording =
+            self.config.recording_enabled != ReplayRecording::Off || self.state.summary.league;
+        let report = PlayerReport {
+            reporter: reporter_name,
+            target: target.as_ref().map(|(_, name)| name.clone()),
+            reason: reason.to_owned(),
+            game_id: self.game_id,
+            game_step: self.state.replay.game_step,
+            period: self.state.scoreboard.period,
+            time: self.state.scoreboard.time,
+            tick: recording.then_some(self.state.recording_ticks),
+            created: Utc::now(),
+        };
+
+        self.reports.next_id += 1;
+        let id = self.reports.next_id;
+        match &target {
+            Some((target_id, target_name)) => info!(
+                "Report #{} from {} ({}) about {} ({}): {} (game {}, step {})",
+                id,
+                report.reporter,
+                player_id,
+                target_name,
+                target_id,
+                report.reason,
+                report.game_id,
+                report.game_step
+            ),
+            None => info!(
+                "Report #{} from {} ({}): {} (game {}, step {})",
+                id, report.reporter, player_id, report.reason, report.game_id, report.game_step
+            ),
+        }
+
+        let recording_annotations = self.config.recording_annotations;
+        self.state.players.add_annotation(
+            AnnotationKind::Report,
+            None,
+            target.map(|(target_id, _)| target_id),
+            report.reason.clone().into(),
+            recording_annotations,
+        );
+        self.state.summary.reports.push(report.clone());
+
+        if self.reports.reports.len() >= REPORT_QUEUE_LENGTH {
+            self.reports.reports.pop_front();
+        }
+        self.reports.reports.push_back(QueuedReport {
+            id,
+            reporter_id: player_id,
+            received: Instant::now(),
+            report,
+        });
+
+        self.state
+            .players
+            .add_directed_server_chat_message("Your report has been sent to the admins", player_id);
+        let admins: Vec<PlayerId> = self
+            .state
+            .players
+            .players
+            .iter_players()
+            .filter(|(admin_id, admin)| admin.is_admin && *admin_id != player_id)
+            .map(|(admin_id, _)| admin_id)
+            .collect();
+        for admin_id in admins {
+            let msg = format!("New report #{}, see /reports", id);
+            self.state
+                .players
+                .add_directed_server_chat_message(msg, admin_id);
+        }
+    }
+
+    /// Handles /reports and /reports clear.
+    pub(crate) fn show_reports(&mut self, admin_player_id: PlayerId, arg: &str) {
+        if let Some(player) = self
+            .state
+            .players
+            .players
+            .check_admin_or_deny(admin_player_id)
+        {
+            if arg == "clear" {
+                self.reports.reports.clear();
+                info!(
+                    "{} ({}) cleared reports",
+                    player.player_name, admin_player_id
+                );
+                self.state
+                    .players
+                    .add_directed_server_chat_message("Reports cleared", admin_player_id);
+                return;
+            }
+            if self.reports.reports.is_empty() {
+                self.state
+                    .players
+                    .add_directed_server_chat_message("No reports", admin_player_id);
+                return;
+            }
+            let messages: Vec<String> = self
+                .reports
+                .reports
+                .iter()
+                .rev()
+                .take(REPORTS_SHOWN)
+                .map(|x| {
+                    let report = &x.report;
+                    let seconds = report.time / 100;
+                    let about = report
+                        .target
+                        .as_ref()
+                        .map(|target| format!(" about {}", target))
+                        .unwrap_or_default();
+                    format!(
+                        "#{} game {} P{} {}:{:02} {}{}: {}",
+                        x.id,
+                        report.game_id,
+                        report.period,
+                        seconds / 60,
+                        seconds % 60,
+                        report.reporter,
+                        about,
+                        report.reason
+                    )
+                })
+                .collect();
+            for msg in messages {
+                self.state
+                    .players
+                    .add_directed_server_chat_message(msg, admin_player_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Random numbers for game modes, reproducible from a seed.
+//!
+//! Each game has its own [`GameRng`], seeded from the server seed and the game ID. The seed of each game is
+//! logged and saved in the recording summary. With `random_seed` set in the config, the same games get the
+//! same seeds again, so that games can be reproduced exactly in the headless mode or with
+//! [`TestServer`](crate::testing::TestServer), and random outcomes can be checked afterwards.
+//!
+//! Everything random in a game should come from [`ServerMut::rng_mut`](crate::gamemode::ServerMut::rng_mut),
+//! in the same order each time, for the game to be reproducible.
+
+/// Pseudo-random number generator, SplitMix64. Fast and good enough for games, but not for anything secret.
+#[derive(Debug, Clone)]
+pub struct GameRng {
+    seed: u64,
+    state: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng { seed, state: seed }
+    }
+
+    /// Returns the seed the generator was created with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        mix(self.state)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// Returns a number in `0.0..1.0`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns a number in `0..n`, or 0 if `n` is 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    /// Returns true with the probability `p`.
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.next_f32() < p
+    }
+
+    /// Returns a random element of `items`, or [`None`] if it is empty.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            None
+        } else {
+            items.get(self.below(items.len()))
+        }
+    }
+
+    /// Puts `items` in random order.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
+
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94D049BB133111EB);
+    x ^ (x >> 31)
+}
+
+/// Returns the seed of a game, from the seed of the server and the game ID.
+pub(crate) fn game_seed(server_seed: u64, game_id: u32) -> u64 {
+    mix(server_seed ^ mix(game_id as u64))
+}
+
+/// Returns a server seed from the system random number generator.
+pub(crate) fn random_server_seed() -> u64 {
+    use ring::rand::{SecureRandom, SystemRandom};
+    let mut bytes = [0u8; 8];
+    // Falls back to a fixed seed in the unlikely case that the system has no random numbers to give
+    let _ = SystemRandom::new().fill(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{game_seed, GameRng};
+
+    #[test]
+    fn seeded_random_numbers() {
+        let mut a = GameRng::new(game_seed(42, 1));
+        let mut b = GameRng::new(game_seed(42, 1));
+        let mut c = GameRng::new(game_seed(42, 2));
+        let a_values: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+        let b_values: Vec<u64> = (0..10).map(|_| b.next_u64()).collect();
+        let c_values: Vec<u64> = (0..10).map(|_| c.next_u64()).collect();
+        assert_eq!(a_values, b_values);
+        assert_ne!(a_values, c_values);
+
+        let mut rng = GameRng::new(1);
+        for _ in 0..1000 {
+            assert!(rng.below(6) < 6);
+            assert!((0.0..1.0).contains(&rng.next_f32()));
+        }
+        assert_eq!(rng.below(0), 0);
+        let mut items = [1, 2, 3, 4, 5, 6, 7, 8];
+        rng.shuffle(&mut items);
+        items.sort();
+        assert_eq!(items, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(rng.choose::<u32>(&[]), None);
+    }
+}
@@ -0,0 +1,334 @@
+//! Open hours of the server.
+//!
+//! With a schedule, the server is only open at the configured hours of the day. A while before it opens,
+//! players can join to warm up, and a new game is started when it opens. Players are warned before the
+//! server closes, and are removed when it closes. While the server is closed, joins are disabled and the
+//! server list shows when it opens.
+//!
+//! The schedule is followed by a task that checks the local time and tells the server when the state
+//! changes.
+
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Weekday};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::gamemode::{ExitReason, GameMode};
+use crate::server::{HQMServer, PlayerListExt, ServerPlayerData};
+
+/// Open hours on some days of the week.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenPeriod {
+    /// Days the server opens, Monday first.
+    pub days: [bool; 7],
+    pub open: NaiveTime,
+    /// If this is not after `open`, the server closes the next day.
+    pub close: NaiveTime,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScheduleConfiguration {
+    pub periods: Vec<OpenPeriod>,
+    /// Minutes before opening when players can join to warm up.
+    pub warmup: u32,
+    /// Minutes before closing when players are told that the server closes.
+    pub close_notice: u32,
+}
+
+/// Whether the server is open according to the schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScheduleState {
+    /// Nobody can join. `opens` is [`None`] if the server never opens.
+    Closed {
+        opens: Option<NaiveDateTime>,
+    },
+    /// Players can join to warm up before the server opens.
+    Warmup {
+        opens: NaiveDateTime,
+    },
+    Open {
+        closes: NaiveDateTime,
+    },
+    /// The server is open, and players have been told that it closes soon.
+    Closing {
+        closes: NaiveDateTime,
+    },
+}
+
+/// Parses open hours like "mon-fri 18:00-22:00; sat,sun 12:00-23:00".
+///
+/// Days can be single days, ranges of days, or "daily". A period that closes before it opens ends the next day.
+pub fn parse_open_hours(s: &str) -> Option<Vec<OpenPeriod>> {
+    fn parse_day(s: &str) -> Option<usize> {
+        s.trim()
+            .parse::<Weekday>()
+            .ok()
+            .map(|x| x.num_days_from_monday() as usize)
+    }
+    fn parse_time(s: &str) -> Option<NaiveTime> {
+        NaiveTime::parse_from_str(s.trim(), "%H:%M").ok()
+    }
+
+    s.split(';')
+        .filter(|x| !x.trim().is_empty())
+        .map(|period| {
+            let (days_str, hours) = period.trim().rsplit_once(' ')?;
+            let mut days = [false; 7];
+            for part in days_str.split(',') {
+                if part.trim() == "daily" {
+                    days = [true; 7];
+                } else if let Some((first, last)) = part.split_once('-') {
+                    let (first, last) = (parse_day(first)?, parse_day(last)?);
+                    // Ranges can wrap around the weekend, like sat-mon
+                    let mut day = first;
+                    loop {
+                        days[day] = true;
+                        if day == last {
+                            break;
+                        }
+                        day = (day + 1) % 7;
+                    }
+                } else {
+                    days[parse_day(part)?] = true;
+                }
+            }
+            let (open, close) = hours.split_once('-')?;
+            Some(OpenPeriod {
+                days,
+                open: parse_time(open)?,
+                close: parse_time(close)?,
+            })
+        })
+        .collect()
+}
+
+impl ScheduleConfiguration {
+    /// Returns the times the server is open, from the day before `date` to a week after it.
+    /// Periods that overlap or follow each other are merged.
+    fn open_intervals(&self, date: NaiveDate) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let mut intervals = vec![];
+        for offset in -1..=7 {
+            let date = date + TimeDelta::days(offset);
+            let day = date.weekday().num_days_from_monday() as usize;
+            for period in self.periods.iter().filter(|period| period.days[day]) {
+                let close_date = if period.close > period.open {
+                    date
+                } else {
+                    date + TimeDelta::days(1)
+                };
+                intervals.push((
+                    date.and_time(period.open),
+                    close_date.and_time(period.close),
+                ));
+            }
+        }
+        intervals.sort();
+
+        let mut merged: Vec<(NaiveDateTime, NaiveDateTime)> = vec![];
+        for (open, close) in intervals {
+            match merged.last_mut() {
+                Some((_, last_close)) if open <= *last_close => {
+                    *last_close = (*last_close).max(close);
+                }
+                _ => merged.push((open, close)),
+            }
+        }
+        merged
+    }
+
+    pub(crate) fn state_at(&self, now: NaiveDateTime) -> ScheduleState {
+        let intervals = self.open_intervals(now.date());
+        if let Some((_, closes)) = intervals
+            .iter()
+            .find(|(open, close)| *open <= now && now < *close)
+        {
+            let closes = *closes;
+            return if closes - now <= TimeDelta::minutes(self.close_notice as i64) {
+                ScheduleState::Closing { closes }
+            } else {
+                ScheduleState::Open { closes }
+            };
+        }
+        let opens = intervals
+            .iter()
+            .map(|(open, _)| *open)
+            .find(|open| *open > now);
+        match opens {
+            Some(opens) if opens - now <= TimeDelta::minutes(self.warmup as i64) => {
+                ScheduleState::Warmup { opens }
+            }
+            _ => ScheduleState::Closed { opens },
+        }
+    }
+}
+
+/// Starts the task that follows the schedule, and sends each new state of the server to `sender`.
+pub(crate) fn start(schedule: ScheduleConfiguration, sender: mpsc::Sender<ScheduleState>) {
+    tokio::spawn(async move {
+        let mut current = None;
+        loop {
+            let state = schedule.state_at(Local::now().naive_local());
+            if current != Some(state) {
+                current = Some(state);
+                if sender.send(state).await.is_err() {
+                    break;
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+fn format_opening(opens: Option<NaiveDateTime>) -> String {
+    match opens {
+        Some(opens) => format!("opens {}", opens.format("%a %H:%M")),
+        None => "closed".to_owned(),
+    }
+}
+
+/// Returns the name the server has in the server list while it's closed, like "Practice (opens Mon 18:00)".
+///
+/// The name is shortened to leave room for the opening time, as the server list only shows 32 bytes.
+pub(crate) fn closed_server_name(server_name: &str, opens: Option<NaiveDateTime>) -> String {
+    let suffix = format!(" ({})", format_opening(opens));
+    let mut len = server_name.len().min(32usize.saturating_sub(suffix.len()));
+    while !server_name.is_char_boundary(len) {
+        len -= 1;
+    }
+    format!("{}{}", &server_name[..len], suffix)
+}
+
+impl HQMServer {
+    pub(crate) fn is_closed(&self) -> bool {
+        matches!(self.schedule_state, Some(ScheduleState::Closed { .. }))
+    }
+
+    pub(crate) fn handle_schedule_change<B: GameMode>(
+        &mut self,
+        state: ScheduleState,
+        behaviour: &mut B,
+    ) {
+        let old_state = self.schedule_state.replace(state);
+        match state {
+            ScheduleState::Closed { opens } => {
+                info!("Server closed, {}", format_opening(opens));
+                self.allow_join = false;
+                let notice = match opens {
+                    Some(opens) => format!(
+                        "The server is closed, it opens again {}",
+                        opens.format("%a %H:%M")
+                    ),
+                    None => "The server is closed".to_owned(),
+                };
+                // Admins can stay to look after the server
+                let players = self
+                    .state
+                    .players
+                    .players
+                    .iter_players()
+                    .filter(|(_, player)| {
+                        !player.is_admin
+                            && matches!(player.data, ServerPlayerData::NetworkPlayer { .. })
+                    })
+                    .map(|(player_id, _)| player_id)
+                    .collect::<Vec<_>>();
+                for player_id in players {
+                    self.remove_after_notice(player_id, &notice, ExitReason::ServerClosed);
+                }
+            }
+            ScheduleState::Warmup { opens } => {
+                info!("Server warmup until {}", opens);
+                self.allow_join = true;
+                let msg = format!(
+                    "The server opens at {}, warm up until then",
+                    opens.format("%H:%M")
+                );
+                self.state.players.add_server_chat_message(msg);
+            }
+            ScheduleState::Open { closes } => {
+                info!("Server open until {}", closes);
+                self.allow_join = true;
+                // The warmup doesn't count, the first game starts when the server opens
+                if matches!(
+                    old_state,
+                    Some(ScheduleState::Warmup { .. } | ScheduleState::Closed { .. })
+                ) {
+                    self.new_game(behaviour.get_initial_game_values());
+                    self.state
+                        .players
+                        .add_server_chat_message("The server is now open");
+                }
+            }
+            ScheduleState::Closing { closes } => {
+                info!("Server closing at {}", closes);
+                let msg = format!(
+                    "The server closes at {}, and all players will be removed",
+                    closes.format("%H:%M")
+                );
+                self.state.players.add_server_chat_message(msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_open_hours, ScheduleConfiguration, ScheduleState};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn open_hours() {
+        let schedule = ScheduleConfiguration {
+            periods: parse_open_hours("mon-fri 18:00-22:00; sat,sun 20:00-02:00").unwrap(),
+            warmup: 15,
+            close_notice: 5,
+        };
+        assert!(parse_open_hours("mon-fri 18:00").is_none());
+        // 2024-01-05 is a Friday
+        let at = |day, hour, minute| {
+            NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap()
+        };
+
+        assert_eq!(
+            schedule.state_at(at(5, 12, 0)),
+            ScheduleState::Closed {
+                opens: Some(at(5, 18, 0))
+            }
+        );
+        assert_eq!(
+            schedule.state_at(at(5, 17, 50)),
+            ScheduleState::Warmup {
+                opens: at(5, 18, 0)
+            }
+        );
+        assert_eq!(
+            schedule.state_at(at(5, 18, 0)),
+            ScheduleState::Open {
+                closes: at(5, 22, 0)
+            }
+        );
+        assert_eq!(
+            schedule.state_at(at(5, 21, 56)),
+            ScheduleState::Closing {
+                closes: at(5, 22, 0)
+            }
+        );
+        // Saturday night, open past midnight
+        assert_eq!(
+            schedule.state_at(at(7, 1, 0)),
+            ScheduleState::Open {
+                closes: at(7, 2, 0)
+            }
+        );
+        assert_eq!(
+            schedule.state_at(at(7, 2, 0)),
+            ScheduleState::Closed {
+                opens: Some(at(7, 20, 0))
+            }
+        );
+    }
+}
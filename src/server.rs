@@ -1,46 +1,495 @@
 use std::borrow::Cow;
 use std::cmp::min;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::net::{IpAddr, SocketAddr};
 
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
 
 use arraydeque::{ArrayDeque, Wrapping};
+use arrayvec::ArrayVec;
 use async_stream::stream;
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use chrono::{DateTime, Utc};
 use futures::StreamExt;
 use nalgebra::{Point3, Rotation3};
-use std::error::Error;
+use smallvec::SmallVec;
 
 use tokio::net::UdpSocket;
+use tokio::sync::watch;
 use tokio::time::MissedTickBehavior;
 use tracing::{info, warn};
 
-use crate::gamemode::{ExitReason, GameMode, InitialGameValues};
+use crate::gamemode::bots::SkaterBot;
+use crate::gamemode::{ExitReason, GameMode, InitialGameValues, ReplayOptions};
 
-use crate::ban::{BanCheck, BanCheckResponse};
+use crate::admin_commands::KickOptions;
+use crate::allocations::allocation_count;
+use crate::auth::{split_name_token, AuthProvider, AuthResponse, PlayerIdentity};
+use crate::ban::{BanCheck, BanCheckResponse, BanTarget, InMemoryBanCheck, IpRange};
+use crate::chat::{ChatFilter, Chatter};
+use crate::commands::{
+    wrap_chat_lines, ArgKind, ChatCommand, CommandArg, CommandArgs, CommandRegistry,
+};
+use crate::consistency::{ConsistencyChecker, Counters};
+use crate::extension::{
+    Announcement, AnnouncementEvent, AnnouncementMessage, CustomScoreboardMessage,
+    ExtensionMessage, RinkAreaMessage, SoundEvent, SoundEventMessage, MAX_PAYLOAD_LENGTH,
+};
 use crate::game::{
-    PhysicsConfiguration, PlayerId, PlayerIndex, PlayerInput, Puck, Rink, RulesState,
-    ScoreboardValues, SkaterHand, SkaterObject, Team,
+    CustomScoreboardValue, PhysicsConfiguration, PlayerId, PlayerIndex, PlayerInput, Puck, Rink,
+    RinkArea, RulesState, ScoreboardDisplay, ScoreboardValues, SkaterHand, SkaterObject, Team,
 };
+use crate::highlights::HighlightDetector;
+use crate::http_api::{self, HttpApiRequest};
+use crate::integrations::discord::{self, DiscordMessage};
+use crate::integrations::event_log;
+use crate::integrity::{self, PacketIntegrity, KEY_MESSAGE_TYPE};
+use crate::lang::{self, Language};
+use crate::live_state::{self, LiveStateExport};
+use crate::maintenance::Maintenance;
+use crate::master_server::{self, MasterServerAnnouncer, MasterServerStatus};
+use crate::message_log::{MessageLog, PlayerMessages};
+use crate::mutes::MuteList;
+use crate::observer::{GameObserver, ObservedTick};
 use crate::protocol::{
-    write_message, write_objects, HQMClientToServerMessage, HQMMessageCodec, HQMMessageWriter,
-    ObjectPacket,
+    write_message, write_object_packets, write_objects, HQMClientToServerMessage, HQMMessageCodec,
+    HQMMessageWriter, ObjectPacket,
 };
-use crate::record::RecordingSaveMethod;
-use crate::{ReplayRecording, ServerConfiguration};
+use crate::query::{self, write_server_info, ServerInfo};
+use crate::record::{AnnotationKind, GameSummary, RecordingSaveMethod};
+use crate::reports::ReportQueue;
+use crate::rng::{self, GameRng};
+use crate::schedule::{self, ScheduleState};
+use crate::stats::{format_stat_line, GameStats, StatLine};
+use crate::stick_check::StickCheck;
+use crate::subscription::EventBroadcaster;
+use crate::votes::VoteState;
+use crate::webadmin::{self, WebAdminCommand};
+use crate::{AbandonPolicy, CommandPermission, ReplayRecording, ServerConfiguration, TeamNames};
 
 pub(crate) const GAME_HEADER: &[u8] = b"Hock";
 
+/// Tag of the input chunk at the end of recordings, see [`ServerConfiguration::recording_inputs`].
+pub(crate) const INPUT_CHUNK_TAG: &[u8] = b"INPT";
+pub(crate) const INPUT_CHUNK_VERSION: u32 = 1;
+
+/// Runs a server command, with arguments that have been checked against its [`ChatCommand`].
+type CommandHandler = fn(&mut HQMServer, &mut dyn GameMode, &CommandArgs, PlayerId);
+
+const PLAYER_ARG: CommandArg = CommandArg::required("ID", ArgKind::Player);
+const MINUTES_ARG: CommandArg = CommandArg::optional("MINUTES", ArgKind::Number);
+const NAME_ARG: CommandArg = CommandArg::required("NAME", ArgKind::Text);
+const REASON_ARG: CommandArg = CommandArg::optional("REASON", ArgKind::Text);
+
+/// Number of players listed on each page of /leaders.
+const LEADERS_PER_PAGE: usize = 5;
+
+/// Seconds the players are given before the server shuts down when it's stopped with Ctrl-C or SIGTERM.
+/// A second signal shuts it down right away.
+const SIGNAL_SHUTDOWN_SECONDS: u32 = 3;
+
+/// Set when all the servers in the process should shut down, see [`shut_down_all`].
+static SHUT_DOWN_ALL: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+/// How many packets more than its ping explains a client can be behind before it is considered to be drifting.
+const DRIFT_MARGIN: u32 = 50;
+/// Ticks a client has to be drifting before it is resynced.
+const DRIFT_TICKS: u8 = 100;
+
+/// Ticks a join can wait for a ban check or authentication before it is given up.
+const PENDING_JOIN_TICKS: u32 = 1000;
+
+/// Commands handled by the server itself, in the order they are listed by `/help`.
+const SERVER_COMMANDS: &[(ChatCommand, CommandHandler)] = &[
+    (
+        ChatCommand::new("help", "Lists the commands you can use, or explains one of them")
+            .args(&[CommandArg::optional("COMMAND", ArgKind::Word)]),
+        |server, _, args, player_id| server.help(player_id, args.get(0)),
+    ),
+    (
+        ChatCommand::new("t", "Sends a message to your team mates only")
+            .args(&[CommandArg::required("MESSAGE", ArgKind::Text)]),
+        |server, _, args, player_id| {
+            let msg = args.get(0).unwrap_or_default();
+            server
+                .state
+                .players
+                .add_user_team_message(msg, player_id, &server.config.team_names);
+        },
+    ),
+    (
+        ChatCommand::new("list", "Lists 5 player IDs, starting from ID if given")
+            .args(&[CommandArg::optional("ID", ArgKind::Number)]),
+        |server, _, args, player_id| {
+            server.list_players(player_id, args.number(0).unwrap_or(0) as usize)
+        },
+    ),
+    (
+        ChatCommand::new("search", "Lists the IDs of players whose name contains NAME")
+            .args(&[NAME_ARG]),
+        |server, _, args, player_id| {
+            server.search_players(player_id, args.get(0).unwrap_or_default())
+        },
+    ),
+    (
+        ChatCommand::new(
+            "stats",
+            "Shows your points, shots and hits this game, or those of the player named NAME",
+        )
+        .args(&[CommandArg::optional("NAME", ArgKind::Text)]),
+        |server, _, args, player_id| server.msg_stats(player_id, args.get(0)),
+    ),
+    (
+        ChatCommand::new("leaders", "Lists the players with the most points this game")
+            .args(&[CommandArg::optional("PAGE", ArgKind::Number)]),
+        |server, _, args, player_id| {
+            server.msg_leaders(player_id, args.number(0).unwrap_or(1) as usize)
+        },
+    ),
+    (
+        ChatCommand::new("view", "Views the game from the player's view").args(&[PLAYER_ARG]),
+        |server, _, args, player_id| {
+            if let Some(view_player_index) = args.player(0) {
+                server.view(view_player_index, player_id);
+            }
+        },
+    ),
+    (
+        ChatCommand::new("views", "Views the game from the view of the player named NAME")
+            .args(&[NAME_ARG]),
+        |server, _, args, player_id| {
+            let name = args.get(0).unwrap_or_default();
+            server.with_name_match(player_id, name, "/view X", |server, index, player_id| {
+                server.view(index, player_id)
+            });
+        },
+    ),
+    (
+        ChatCommand::new("restoreview", "Returns to your own view"),
+        |server, _, _, player_id| server.restore_view(player_id),
+    ),
+    (
+        ChatCommand::new(
+            "rewind",
+            "Shows you the last seconds again, at half speed with slow. /rewind stop returns to live",
+        )
+        .args(&[
+            CommandArg::optional("SECONDS", ArgKind::Word),
+            CommandArg::optional("slow", ArgKind::Word),
+        ]),
+        |server, _, args, player_id| {
+            let arg = match (args.get(0), args.get(1)) {
+                (Some(seconds), Some(slow)) => format!("{} {}", seconds, slow),
+                (Some(seconds), None) => seconds.to_owned(),
+                _ => String::new(),
+            };
+            server.rewind(player_id, &arg);
+        },
+    ),
+    (
+        ChatCommand::new(
+            "report",
+            "Reports an issue to the admins, optionally about the player with ID",
+        )
+        .args(&[CommandArg::required("[ID] REASON", ArgKind::Text)]),
+        |server, _, args, player_id| server.report(player_id, args.get(0).unwrap_or_default()),
+    ),
+    (
+        ChatCommand::new(
+            "vote",
+            "Starts a vote with /vote kick ID, /vote reset or /vote pause, or votes with /vote yes or /vote no",
+        )
+        .args(&[CommandArg::optional("VOTE", ArgKind::Text)]),
+        |server, behaviour, args, player_id| {
+            server.vote(player_id, args.get(0).unwrap_or_default(), behaviour)
+        },
+    ),
+    (
+        ChatCommand::new("ping", "Shows the server-side ping of the player").args(&[PLAYER_ARG]),
+        |server, _, args, player_id| {
+            if let Some(ping_player_index) = args.player(0) {
+                server.ping(ping_player_index, player_id);
+            }
+        },
+    ),
+    (
+        ChatCommand::new("pings", "Shows the server-side ping of the player named NAME")
+            .args(&[NAME_ARG]),
+        |server, _, args, player_id| {
+            let name = args.get(0).unwrap_or_default();
+            server.with_name_match(player_id, name, "/ping X", |server, index, player_id| {
+                server.ping(index, player_id)
+            });
+        },
+    ),
+    (
+        ChatCommand::new("lang", "Sets the language of messages to you: en, fi or ru")
+            .args(&[CommandArg::optional("LANGUAGE", ArgKind::Word)]),
+        |server, _, args, player_id| {
+            server.set_language(player_id, args.get(0).unwrap_or_default())
+        },
+    ),
+    (
+        ChatCommand::new("spec", "Only watch the game, or join the players again"),
+        |server, _, _, player_id| server.toggle_spectator(player_id),
+    ),
+    (
+        ChatCommand::new("lefty", "Makes your player left-handed"),
+        |server, _, _, player_id| server.set_hand(SkaterHand::Left, player_id),
+    ),
+    (
+        ChatCommand::new("righty", "Makes your player right-handed"),
+        |server, _, _, player_id| server.set_hand(SkaterHand::Right, player_id),
+    ),
+    (
+        ChatCommand::new("admin", "Logs in as administrator")
+            .args(&[CommandArg::required("PASSWORD", ArgKind::Text)]),
+        |server, _, args, player_id| server.admin_login(player_id, args.get(0).unwrap_or_default()),
+    ),
+    (
+        ChatCommand::new("version", "Shows the server version"),
+        |server, _, _, player_id| server.show_version(player_id),
+    ),
+    (
+        ChatCommand::new("git", "Shows the git commit of the server"),
+        |server, _, _, player_id| server.show_git_commit(player_id),
+    ),
+    (
+        ChatCommand::new("enablejoin", "Allows new players to join").admin(),
+        |server, _, _, player_id| server.set_allow_join(player_id, true),
+    ),
+    (
+        ChatCommand::new("disablejoin", "Prevents new players from joining").admin(),
+        |server, _, _, player_id| server.set_allow_join(player_id, false),
+    ),
+    (
+        ChatCommand::new("mute", "Mutes the player, for MINUTES if given")
+            .args(&[PLAYER_ARG, MINUTES_ARG])
+            .admin(),
+        |server, _, args, player_id| {
+            if let Some(mute_player_index) = args.player(0) {
+                server.mute_player(player_id, mute_player_index, args.number(1));
+            }
+        },
+    ),
+    (
+        ChatCommand::new(
+            "shadowmute",
+            "Mutes the player for everyone else, for MINUTES if given",
+        )
+        .args(&[PLAYER_ARG, MINUTES_ARG])
+        .admin(),
+        |server, _, args, player_id| {
+            if let Some(mute_player_index) = args.player(0) {
+                server.shadowmute_player(player_id, mute_player_index, args.number(1));
+            }
+        },
+    ),
+    (
+        ChatCommand::new("unmute", "Unmutes the player")
+            .args(&[PLAYER_ARG])
+            .admin(),
+        |server, _, args, player_id| {
+            if let Some(mute_player_index) = args.player(0) {
+                server.unmute_player(player_id, mute_player_index);
+            }
+        },
+    ),
+    (
+        ChatCommand::new("mutechat", "Mutes all chat").admin(),
+        |server, _, _, player_id| server.mute_chat(player_id),
+    ),
+    (
+        ChatCommand::new("unmutechat", "Unmutes all chat").admin(),
+        |server, _, _, player_id| server.unmute_chat(player_id),
+    ),
+    (
+        ChatCommand::new("reloadwords", "Reads the word list of the chat filter again").admin(),
+        |server, _, _, player_id| server.reload_chat_words(player_id),
+    ),
+    (
+        ChatCommand::new("kick", "Kicks the player, with the reason shown in the chat")
+            .args(&[PLAYER_ARG, REASON_ARG])
+            .admin(),
+        |server, _, args, player_id| {
+            if let Some(kick_player_index) = args.player(0) {
+                let options = KickOptions {
+                    reason: args.get(1),
+                    ..Default::default()
+                };
+                server.kick_player(player_id, kick_player_index, options);
+            }
+        },
+    ),
+    (
+        ChatCommand::new(
+            "kickall",
+            "Kicks all players named NAME, with % as a wildcard at the start and end",
+        )
+        .args(&[NAME_ARG])
+        .admin(),
+        |server, behaviour, args, player_id| {
+            server.kick_all_matching(player_id, args.get(0).unwrap_or_default(), false, behaviour)
+        },
+    ),
+    (
+        ChatCommand::new(
+            "ban",
+            "Kicks and bans the player, for DURATION like 30m, 12h or 2d if given",
+        )
+        .args(&[
+            PLAYER_ARG,
+            CommandArg::optional("DURATION", ArgKind::Duration),
+            REASON_ARG,
+        ])
+        .admin(),
+        |server, _, args, player_id| {
+            if let Some(kick_player_index) = args.player(0) {
+                let options = KickOptions {
+                    ban: true,
+                    minutes: args.duration(1),
+                    reason: args.get(2),
+                };
+                server.kick_player(player_id, kick_player_index, options);
+            }
+        },
+    ),
+    (
+        ChatCommand::new("banall", "Kicks and bans all players named NAME, like /kickall")
+            .args(&[NAME_ARG])
+            .admin(),
+        |server, behaviour, args, player_id| {
+            server.kick_all_matching(player_id, args.get(0).unwrap_or_default(), true, behaviour)
+        },
+    ),
+    (
+        ChatCommand::new(
+            "tempban",
+            "Kicks and bans the player for DURATION, like 30m, 12h or 2d",
+        )
+        .args(&[
+            PLAYER_ARG,
+            CommandArg::required("DURATION", ArgKind::Duration),
+            REASON_ARG,
+        ])
+        .admin(),
+        |server, _, args, player_id| {
+            if let (Some(ban_player_index), Some(minutes)) = (args.player(0), args.duration(1)) {
+                let options = KickOptions {
+                    ban: true,
+                    minutes: Some(minutes),
+                    reason: args.get(2),
+                };
+                server.kick_player(player_id, ban_player_index, options);
+            }
+        },
+    ),
+    (
+        ChatCommand::new(
+            "banrange",
+            "Bans a range of IP addresses, like 10.0.0.0/8, and kicks its players",
+        )
+        .args(&[CommandArg::required("CIDR", ArgKind::Word), REASON_ARG])
+        .admin(),
+        |server, _, args, player_id| {
+            match args.get(0).unwrap_or_default().parse::<IpRange>() {
+                Ok(range) => {
+                    server.ban_target(player_id, BanTarget::Range(range), args.get(1))
+                }
+                Err(_) => server.state.players.add_directed_server_chat_message(
+                    "Invalid range, use a range like 10.0.0.0/8",
+                    player_id,
+                ),
+            }
+        },
+    ),
+    (
+        ChatCommand::new(
+            "banname",
+            "Bans player names matching PATTERN, with * as a wildcard",
+        )
+        .args(&[CommandArg::required("PATTERN", ArgKind::Word), REASON_ARG])
+        .admin(),
+        |server, _, args, player_id| {
+            let target = BanTarget::Name(args.get(0).unwrap_or_default().to_owned());
+            server.ban_target(player_id, target, args.get(1))
+        },
+    ),
+    (
+        ChatCommand::new("clearbans", "Removes all bans").admin(),
+        |server, _, _, player_id| server.clear_bans(player_id),
+    ),
+    (
+        ChatCommand::new("replay", "Turns recording of games on or off")
+            .aliases(&["record"])
+            .args(&[CommandArg::required("on/off", ArgKind::Word)])
+            .admin(),
+        |server, _, args, player_id| server.set_recording(player_id, args.get(0).unwrap_or_default()),
+    ),
+    (
+        ChatCommand::new("reports", "Shows the latest player reports, or clears them")
+            .args(&[CommandArg::optional("clear", ArgKind::Word)])
+            .admin(),
+        |server, _, args, player_id| server.show_reports(player_id, args.get(0).unwrap_or_default()),
+    ),
+    (
+        ChatCommand::new(
+            "bookmark",
+            "Marks the current moment in the recording and the log, for later review",
+        )
+        .args(&[CommandArg::required("LABEL", ArgKind::Text)])
+        .admin(),
+        |server, _, args, player_id| server.bookmark(player_id, args.get(0).unwrap_or_default()),
+    ),
+    (
+        ChatCommand::new(
+            "clip",
+            "Saves the last SECONDS of the game as a recording right away",
+        )
+        .args(&[CommandArg::optional("SECONDS", ArgKind::Number)])
+        .admin(),
+        |server, _, args, player_id| server.clip(player_id, args.number(0)),
+    ),
+    (
+        ChatCommand::new("seed", "Shows the random seed of the current game").admin(),
+        |server, _, _, player_id| server.show_random_seed(player_id),
+    ),
+    (
+        ChatCommand::new("serverrestart", "Restarts the server service").admin(),
+        |server, _, _, player_id| server.restart_server(player_id),
+    ),
+    (
+        ChatCommand::new(
+            "maintenance",
+            "Turns maintenance on or off. During maintenance, nobody can join a team",
+        )
+        .args(&[CommandArg::required("on/off", ArgKind::Word)])
+        .admin(),
+        |server, _, args, player_id| {
+            server.set_maintenance(player_id, args.get(0).unwrap_or_default())
+        },
+    ),
+    (
+        ChatCommand::new(
+            "shutdown",
+            "Shuts the server down after SECONDS, saving the recording of the game",
+        )
+        .args(&[CommandArg::optional("SECONDS", ArgKind::Number)])
+        .admin(),
+        |server, _, args, player_id| server.shutdown_server(player_id, args.number(0)),
+    ),
+];
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub(crate) enum HQMClientVersion {
     Vanilla,
     Ping,
     PingRules,
+    /// Same as [`HQMClientVersion::PingRules`], but can also receive extension messages.
+    Extensions,
 }
 
 impl HQMClientVersion {
@@ -49,6 +498,7 @@ impl HQMClientVersion {
             HQMClientVersion::Vanilla => false,
             HQMClientVersion::Ping => true,
             HQMClientVersion::PingRules => true,
+            HQMClientVersion::Extensions => true,
         }
     }
 
@@ -57,8 +507,13 @@ impl HQMClientVersion {
             HQMClientVersion::Vanilla => false,
             HQMClientVersion::Ping => false,
             HQMClientVersion::PingRules => true,
+            HQMClientVersion::Extensions => true,
         }
     }
+
+    pub(crate) fn has_extensions(self) -> bool {
+        self == HQMClientVersion::Extensions
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -70,18 +525,38 @@ pub(crate) struct PlayerUpdateData {
 #[derive(Debug, Clone)]
 pub(crate) enum HQMMessage {
     PlayerUpdate {
-        player_index: PlayerIndex,
+        player_id: PlayerId,
         data: Option<PlayerUpdateData>,
     },
     Goal {
         team: Team,
-        goal_player_index: Option<PlayerIndex>,
-        assist_player_index: Option<PlayerIndex>,
+        goal_player: Option<PlayerId>,
+        assist_player: Option<PlayerId>,
     },
     Chat {
-        player_index: Option<PlayerIndex>,
+        player_id: Option<PlayerId>,
         message: Cow<'static, str>,
     },
+    Extension {
+        message_type: u16,
+        payload: Vec<u8>,
+    },
+    Annotation {
+        kind: AnnotationKind,
+        team: Option<Team>,
+        player_id: Option<PlayerId>,
+        reason: Cow<'static, str>,
+    },
+}
+
+fn extension_message(message: &impl ExtensionMessage) -> HQMMessage {
+    let mut payload = Vec::new();
+    message.write_payload(&mut payload);
+    payload.truncate(MAX_PAYLOAD_LENGTH);
+    HQMMessage::Extension {
+        message_type: message.message_type(),
+        payload,
+    }
 }
 
 pub(crate) trait PlayerListExt {
@@ -223,10 +698,30 @@ impl PlayerListExt for [ServerStatePlayerItem] {
 pub(crate) struct HQMServerPlayersAndMessages {
     pub(crate) players: Vec<ServerStatePlayerItem>,
 
+    /// Messages sent to players that join during the game. If [`Self::join_history`] is set,
+    /// only the latest update of each player is kept.
     persistent_messages: Vec<Rc<HQMMessage>>,
+    /// Messages of the current game that are sent to more than one player.
+    message_log: MessageLog,
     recording_messages: Vec<Rc<HQMMessage>>,
-
-    puck_slots: usize,
+    // Global messages since the last tick, for observers
+    pub(crate) tick_messages: Vec<Rc<HQMMessage>>,
+    join_exit_messages: JoinExitMessages,
+    /// Language of messages to everyone, and of directed messages to players that haven't chosen a language.
+    pub(crate) default_language: Language,
+    /// Number of goal messages sent to players that join during the game, counting from the latest one,
+    /// or [`None`] for all of them. If set, joining players are also only sent the current state of each player.
+    pub(crate) join_history: Option<usize>,
+    /// What is sent for each announced event.
+    pub(crate) announcements: HashMap<AnnouncementEvent, Announcement>,
+    /// Goals, assists, shots and hits of the current game, filled in by the highlight detector.
+    pub(crate) stats: GameStats,
+    /// Skaters spawned since the highlight detector last ran, whose change in velocity is not a hit.
+    pub(crate) spawned_skaters: Vec<PlayerId>,
+
+    pub(crate) puck_slots: usize,
+    /// If true, players that are not on the ice can't be spawned, set during maintenance.
+    pub(crate) teams_locked: bool,
 }
 
 impl HQMServerPlayersAndMessages {
@@ -239,22 +734,36 @@ impl HQMServerPlayersAndMessages {
         Self {
             players,
             persistent_messages: vec![],
+            message_log: MessageLog::default(),
             recording_messages: vec![],
+            tick_messages: vec![],
+            join_exit_messages: JoinExitMessages::default(),
+            default_language: Language::default(),
+            join_history: None,
+            announcements: HashMap::new(),
+            stats: GameStats::default(),
+            spawned_skaters: vec![],
             puck_slots,
+            teams_locked: false,
         }
     }
 
     fn new_game(&mut self, puck_slots: usize) {
         self.recording_messages.clear();
         self.persistent_messages.clear();
+        self.message_log.clear();
+        self.stats.clear();
         self.puck_slots = puck_slots;
 
         let mut messages = Vec::new();
-        for (player_index, (_, p)) in self.players.iter_mut().enumerate() {
-            let player_index = PlayerIndex(player_index);
+        for (player_index, (gen, p)) in self.players.iter_mut().enumerate() {
+            let player_id = PlayerId {
+                index: PlayerIndex(player_index),
+                gen: *gen,
+            };
             if let Some(player) = p {
-                player.reset(player_index);
-                let update = player.get_update_message(player_index);
+                player.reset(player_id.index);
+                let update = player.get_update_message(player_id);
                 messages.push((update, true, true));
             }
         }
@@ -267,10 +776,10 @@ impl HQMServerPlayersAndMessages {
     pub fn add_user_chat_message(
         &mut self,
         message: impl Into<Cow<'static, str>>,
-        sender_index: PlayerIndex,
+        sender_id: PlayerId,
     ) {
         let chat = HQMMessage::Chat {
-            player_index: Some(sender_index),
+            player_id: Some(sender_id),
             message: message.into(),
         };
         self.add_global_message(chat, false, true);
@@ -278,20 +787,60 @@ impl HQMServerPlayersAndMessages {
 
     pub fn add_server_chat_message(&mut self, message: impl Into<Cow<'static, str>>) {
         let chat = HQMMessage::Chat {
-            player_index: None,
-            message: message.into(),
+            player_id: None,
+            message: lang::translate(message.into(), self.default_language),
         };
         self.add_global_message(chat, false, true);
     }
 
+    /// Announces that a player has joined or exited the server.
+    ///
+    /// If `window` is non-zero, announcements that come less than `window` ticks after the previous one
+    /// are held back and sent together, to avoid flooding the chat when many players join or exit at once.
+    pub(crate) fn add_join_exit_message(&mut self, player_name: &str, joined: bool, window: u32) {
+        let batch = &mut self.join_exit_messages;
+        if window == 0 || (batch.ticks_left == 0 && batch.is_empty()) {
+            let verb = if joined { "joined" } else { "exited" };
+            self.add_server_chat_message(format!("{} {}", player_name, verb));
+            self.join_exit_messages.ticks_left = window;
+        } else if joined {
+            batch.joined.push(player_name.to_owned());
+        } else if let Some(i) = batch.joined.iter().position(|x| x == player_name) {
+            // Joined and exited within the same window, nobody needs to know
+            batch.joined.remove(i);
+        } else {
+            batch.exited.push(player_name.to_owned());
+        }
+    }
+
+    /// Sends the held back join and exit announcements when the window has passed.
+    fn update_join_exit_messages(&mut self, window: u32) {
+        let batch = &mut self.join_exit_messages;
+        if batch.ticks_left == 0 {
+            return;
+        }
+        batch.ticks_left -= 1;
+        if batch.ticks_left == 0 && !batch.is_empty() {
+            let joined = std::mem::take(&mut batch.joined);
+            let exited = std::mem::take(&mut batch.exited);
+            batch.ticks_left = window;
+            if !joined.is_empty() {
+                self.add_server_chat_message(JoinExitMessages::summary(&joined, "joined"));
+            }
+            if !exited.is_empty() {
+                self.add_server_chat_message(JoinExitMessages::summary(&exited, "exited"));
+            }
+        }
+    }
+
     pub fn add_directed_chat_message(
         &mut self,
         message: impl Into<Cow<'static, str>>,
         receiver_id: PlayerId,
-        sender_index: Option<PlayerIndex>,
+        sender_id: Option<PlayerId>,
     ) {
         if let Some(player) = self.players.get_player_mut(receiver_id) {
-            player.add_directed_chat_message(message, sender_index)
+            player.add_directed_chat_message(message, sender_id)
         }
     }
 
@@ -299,9 +848,9 @@ impl HQMServerPlayersAndMessages {
         &mut self,
         message: impl Into<Cow<'static, str>>,
         receiver_id: PlayerId,
-        sender_index: PlayerIndex,
+        sender_id: PlayerId,
     ) {
-        self.add_directed_chat_message(message, receiver_id, Some(sender_index));
+        self.add_directed_chat_message(message, receiver_id, Some(sender_id));
     }
 
     pub fn add_directed_server_chat_message(
@@ -315,44 +864,147 @@ impl HQMServerPlayersAndMessages {
     pub fn add_goal_message(
         &mut self,
         team: Team,
-        goal_player_index: Option<PlayerId>,
-        assist_player_index: Option<PlayerId>,
+        goal_player: Option<PlayerId>,
+        assist_player: Option<PlayerId>,
     ) {
-        let goal_player_index = goal_player_index.and_then(|x| {
-            if self.players.get_player(x).is_some() {
-                Some(x.index)
-            } else {
-                None
-            }
-        });
-        let assist_player_index = assist_player_index.and_then(|x| {
-            if self.players.get_player(x).is_some() {
-                Some(x.index)
-            } else {
-                None
-            }
-        });
+        let goal_player = goal_player.filter(|x| self.players.get_player(*x).is_some());
+        let assist_player = assist_player.filter(|x| self.players.get_player(*x).is_some());
         let message = HQMMessage::Goal {
             team,
-            goal_player_index,
-            assist_player_index,
+            goal_player,
+            assist_player,
         };
         self.add_global_message(message, true, true);
     }
+    /// Annotations are never sent to clients, and only included in recordings if `recording` is true.
+    pub fn add_annotation(
+        &mut self,
+        kind: AnnotationKind,
+        team: Option<Team>,
+        player_id: Option<PlayerId>,
+        reason: Cow<'static, str>,
+        recording: bool,
+    ) {
+        let player_id = player_id.filter(|x| self.players.get_player(*x).is_some());
+        let rc = Rc::new(HQMMessage::Annotation {
+            kind,
+            team,
+            player_id,
+            reason,
+        });
+        self.tick_messages.push(rc.clone());
+        if recording {
+            self.recording_messages.push(rc);
+        }
+    }
+
+    pub fn add_extension_message(&mut self, message: &impl ExtensionMessage) {
+        let rc = Rc::new(extension_message(message));
+        self.tick_messages.push(rc.clone());
+        let index = self.message_log.push(rc);
+        for (_, player) in self.players.iter_players_mut() {
+            if player.supports_extensions() {
+                player.add_log_message(index);
+            }
+        }
+    }
+
+    pub fn add_directed_extension_message(
+        &mut self,
+        message: &impl ExtensionMessage,
+        receiver_id: PlayerId,
+    ) {
+        if let Some(player) = self.players.get_player_mut(receiver_id) {
+            if player.supports_extensions() {
+                player.add_message(Rc::new(extension_message(message)));
+            }
+        }
+    }
+
+    pub fn play_sound_event(&mut self, event: SoundEvent) {
+        self.add_extension_message(&SoundEventMessage(event));
+    }
+
+    /// Sends the configured announcement of the event, if any: the code to clients that support
+    /// extensions, and the text in chat to the other players.
+    pub fn announce(&mut self, event: AnnouncementEvent) {
+        let Some(Announcement { code, text }) = self.announcements.get(&event).cloned() else {
+            return;
+        };
+        self.add_extension_message(&AnnouncementMessage(code));
+        if let Some(text) = text {
+            for (_, player) in self.players.iter_players_mut() {
+                if !player.supports_extensions() {
+                    player.add_directed_server_chat_message(text.clone());
+                }
+            }
+        }
+    }
+
     fn add_global_message(&mut self, message: HQMMessage, persistent: bool, recording: bool) {
         let rc = Rc::new(message);
+        self.tick_messages.push(rc.clone());
         if recording {
             self.recording_messages.push(rc.clone());
         }
         if persistent {
-            self.persistent_messages.push(rc.clone());
+            self.add_persistent_message(rc.clone());
         }
+        let index = self.message_log.push(rc);
         for (_, player) in self.players.iter_players_mut() {
-            player.add_message(rc.clone());
+            player.add_log_message(index);
+        }
+    }
+
+    fn add_persistent_message(&mut self, message: Rc<HQMMessage>) {
+        if self.join_history.is_none() {
+            self.persistent_messages.push(message);
+            return;
         }
+        if let HQMMessage::PlayerUpdate { player_id, data } = message.as_ref() {
+            // Joining players only need to know the current state of each player
+            let index = player_id.index;
+            self.persistent_messages.retain(|x| {
+                !matches!(x.as_ref(), HQMMessage::PlayerUpdate { player_id, .. } if player_id.index == index)
+            });
+            if data.is_none() {
+                return;
+            }
+        }
+        self.persistent_messages.push(message);
+    }
+
+    /// Returns the messages to send to a player that joins during the game.
+    fn join_messages(&self) -> Vec<Rc<HQMMessage>> {
+        let Some(join_history) = self.join_history else {
+            return self.persistent_messages.clone();
+        };
+        let other_messages = self
+            .persistent_messages
+            .iter()
+            .filter(|x| !matches!(x.as_ref(), HQMMessage::PlayerUpdate { .. }))
+            .count();
+        let mut skip = other_messages.saturating_sub(join_history);
+        self.persistent_messages
+            .iter()
+            .filter(|x| {
+                if skip > 0 && !matches!(x.as_ref(), HQMMessage::PlayerUpdate { .. }) {
+                    skip -= 1;
+                    false
+                } else {
+                    true
+                }
+            })
+            .cloned()
+            .collect()
     }
 
-    fn add_user_team_message(&mut self, message: &str, sender_id: PlayerId) {
+    fn add_user_team_message(
+        &mut self,
+        message: &str,
+        sender_id: PlayerId,
+        team_names: &TeamNames,
+    ) {
         if let Some(player) = self.players.get_player(sender_id) {
             let team = if let Some((_, _, team)) = player.object {
                 Some(team)
@@ -369,35 +1021,33 @@ impl HQMServerPlayersAndMessages {
                     .as_ref()
                     .map(|(object_index, _, team)| (*object_index, *team));
 
-                let team_tag_name = match team {
-                    Team::Red => player.player_name_red.clone(),
-                    Team::Blue => player.player_name_blue.clone(),
-                };
+                let team_tag_name: Rc<str> =
+                    format!("[{}] {}", team_names.name(team), player.player_name).into();
 
-                let change1 = Rc::new(HQMMessage::PlayerUpdate {
-                    player_index: sender_id.index,
+                let change1 = self.message_log.push(Rc::new(HQMMessage::PlayerUpdate {
+                    player_id: sender_id,
                     data: Some(PlayerUpdateData {
                         player_name: team_tag_name,
                         object,
                     }),
-                });
-                let change2 = Rc::new(HQMMessage::PlayerUpdate {
-                    player_index: sender_id.index,
+                }));
+                let chat = self.message_log.push(Rc::new(HQMMessage::Chat {
+                    player_id: Some(sender_id),
+                    message: Cow::Owned(message.to_owned()),
+                }));
+                let change2 = self.message_log.push(Rc::new(HQMMessage::PlayerUpdate {
+                    player_id: sender_id,
                     data: Some(PlayerUpdateData {
                         player_name: player.player_name.clone(),
                         object,
                     }),
-                });
-                let chat = Rc::new(HQMMessage::Chat {
-                    player_index: Some(sender_id.index),
-                    message: Cow::Owned(message.to_owned()),
-                });
+                }));
 
                 for (_, player) in self.players.iter_players_mut() {
                     if player.team().is_some_and(|t| t == team) {
-                        player.add_message(change1.clone());
-                        player.add_message(chat.clone());
-                        player.add_message(change2.clone());
+                        player.add_log_message(change1);
+                        player.add_log_message(chat);
+                        player.add_log_message(change2);
                     }
                 }
             }
@@ -406,9 +1056,10 @@ impl HQMServerPlayersAndMessages {
 
     pub(crate) fn move_to_spectator(&mut self, player_id: PlayerId) -> bool {
         if let Some(player) = self.players.get_player_mut(player_id) {
+            player.leave_ice = false;
             if player.object.is_some() {
                 player.object = None;
-                let update = player.get_update_message(player_id.index);
+                let update = player.get_update_message(player_id);
                 self.add_global_message(update, true, true);
                 return true;
             }
@@ -426,6 +1077,9 @@ impl HQMServerPlayersAndMessages {
     ) -> bool {
         let empty_slot = self.find_empty_player_object_slot();
         if let Some(player) = self.players.get_player_mut(player_index) {
+            if self.teams_locked && player.object.is_none() {
+                return false;
+            }
             if let Some((_, skater, team2)) = &mut player.object {
                 let mut new_skater = SkaterObject::new(pos, rot, player.preferred_hand);
                 if keep_stick_position {
@@ -439,7 +1093,8 @@ impl HQMServerPlayersAndMessages {
                 }
                 *skater = new_skater;
                 *team2 = team;
-                let update = player.get_update_message(player_index.index);
+                self.spawned_skaters.push(player_index);
+                let update = player.get_update_message(player_index);
                 self.add_global_message(update, true, true);
                 return true;
             } else {
@@ -452,7 +1107,7 @@ impl HQMServerPlayersAndMessages {
                     if let ServerPlayerData::NetworkPlayer { data } = &mut player.data {
                         data.view_player_index = player_index.index;
                     }
-                    let update = player.get_update_message(player_index.index);
+                    let update = player.get_update_message(player_index);
                     self.add_global_message(update, true, true);
                     return true;
                 }
@@ -491,15 +1146,19 @@ impl HQMServerPlayersAndMessages {
                     player_index,
                     player_name,
                     addr,
-                    &self.persistent_messages,
+                    self.default_language,
+                    PlayerMessages::new(self.join_messages()),
                 );
-                let update = new_player.get_update_message(player_index);
-
                 self.players[player_index.0].1 = Some(new_player);
                 let player_id = PlayerId {
                     index: player_index,
                     gen: self.players[player_index.0].0,
                 };
+                let update = self.players[player_index.0]
+                    .1
+                    .as_ref()
+                    .unwrap()
+                    .get_update_message(player_id);
 
                 self.add_global_message(update, true, true);
 
@@ -514,13 +1173,16 @@ impl HQMServerPlayersAndMessages {
         match player_index {
             Some(player_index) => {
                 let new_player = HQMServerPlayer::new_bot(player_name);
-                let update = new_player.get_update_message(player_index);
-
                 self.players[player_index.0].1 = Some(new_player);
                 let player_id = PlayerId {
                     index: player_index,
                     gen: self.players[player_index.0].0,
                 };
+                let update = self.players[player_index.0]
+                    .1
+                    .as_ref()
+                    .unwrap()
+                    .get_update_message(player_id);
 
                 self.add_global_message(update, true, true);
 
@@ -533,7 +1195,7 @@ impl HQMServerPlayersAndMessages {
     pub fn remove_player(&mut self, player_id: PlayerId, on_recording: bool) -> bool {
         if let Some(_) = self.players.get_player(player_id) {
             let update = HQMMessage::PlayerUpdate {
-                player_index: player_id.index,
+                player_id,
                 data: None,
             };
 
@@ -547,12 +1209,88 @@ impl HQMServerPlayersAndMessages {
             false
         }
     }
+
+    /// Tells every client that all players have left, which makes the clients leave the server.
+    /// The players are kept, so that the message can still be sent to them.
+    fn announce_exit_of_all(&mut self) {
+        let player_ids: Vec<_> = self
+            .players
+            .iter_players()
+            .map(|(player_id, _)| player_id)
+            .collect();
+        for player_id in player_ids {
+            let update = HQMMessage::PlayerUpdate {
+                player_id,
+                data: None,
+            };
+            self.add_global_message(update, true, true);
+        }
+    }
+}
+
+/// Join and exit announcements that are held back to be sent together.
+#[derive(Default)]
+struct JoinExitMessages {
+    joined: Vec<String>,
+    exited: Vec<String>,
+    /// Ticks until the held back announcements are sent, or 0 if the next announcement can be sent right away.
+    ticks_left: u32,
+}
+
+impl JoinExitMessages {
+    fn is_empty(&self) -> bool {
+        self.joined.is_empty() && self.exited.is_empty()
+    }
+
+    /// Lists as many names as will fit in a chat message, like "A, B and 3 others joined".
+    fn summary(names: &[String], verb: &str) -> String {
+        if let [name] = names {
+            return format!("{} {}", name, verb);
+        }
+        let mut listed = 0;
+        let mut text = String::new();
+        for (i, name) in names.iter().enumerate() {
+            let rest = names.len() - i - 1;
+            let separator = if i == 0 { "" } else { ", " };
+            let tail = match rest {
+                0 => format!(" {}", verb),
+                1 => format!(" and 1 other {}", verb),
+                _ => format!(" and {} others {}", rest, verb),
+            };
+            if text.len() + separator.len() + name.len() + tail.len() > 63 {
+                break;
+            }
+            text.push_str(separator);
+            text.push_str(name);
+            listed += 1;
+        }
+        match names.len() - listed {
+            0 => {
+                // Replace the last comma with "and"
+                if let Some(pos) = text.rfind(", ") {
+                    text.replace_range(pos..pos + 2, " and ");
+                }
+                format!("{} {}", text, verb)
+            }
+            rest if listed == 0 => format!("{} players {}", rest, verb),
+            1 => format!("{} and 1 other {}", text, verb),
+            rest => format!("{} and {} others {}", text, rest, verb),
+        }
+    }
 }
 
 pub struct HQMTickHistory {
     pub(crate) game_step: u32,
-    replay_queue: VecDeque<(Option<PlayerId>, ReplayTick)>,
-    saved_history: VecDeque<ReplayTick>,
+    replay_queue: VecDeque<QueuedReplayTick>,
+    saved_history: VecDeque<SavedTick>,
+    /// Replays waiting for the countdown before they are moved to the replay queue.
+    pending_replays: VecDeque<QueuedReplayTick>,
+    /// Ticks left until the pending replays start, or `None` if the countdown hasn't started.
+    countdown: Option<u32>,
+    /// Number of ticks at the start and end of each replay that are shown without forcing the view.
+    view_buffer: usize,
+    /// Players that skip the replay tick that is currently shown.
+    skipping: Rc<[PlayerId]>,
 
     pub(crate) history_length: usize,
 }
@@ -563,6 +1301,10 @@ impl HQMTickHistory {
             game_step: u32::MAX,
             replay_queue: Default::default(),
             saved_history: Default::default(),
+            pending_replays: Default::default(),
+            countdown: None,
+            view_buffer: 0,
+            skipping: Rc::from([]),
             history_length: 0,
         }
     }
@@ -570,11 +1312,14 @@ impl HQMTickHistory {
     fn clear(&mut self) {
         self.replay_queue.clear();
         self.saved_history.clear();
+        self.pending_replays.clear();
+        self.countdown = None;
         self.game_step = u32::MAX;
     }
 
+    /// Returns true if a replay is being shown, or about to be shown after the countdown.
     pub fn is_in_replay(&self) -> bool {
-        !self.replay_queue.is_empty()
+        !self.replay_queue.is_empty() || !self.pending_replays.is_empty()
     }
 
     pub fn add_replay_to_queue(
@@ -582,6 +1327,19 @@ impl HQMTickHistory {
         start_step: u32,
         end_step: u32,
         force_view: Option<PlayerId>,
+    ) {
+        let options = ReplayOptions {
+            cameras: force_view.into_iter().collect(),
+            ..Default::default()
+        };
+        self.add_replay_with_options(start_step, end_step, &options)
+    }
+
+    pub fn add_replay_with_options(
+        &mut self,
+        start_step: u32,
+        end_step: u32,
+        options: &ReplayOptions,
     ) {
         if start_step > end_step {
             warn!("start_step must be less than or equal to end_step");
@@ -592,19 +1350,98 @@ impl HQMTickHistory {
 
         let i_end = game_step.saturating_sub(end_step) as usize;
         let i_start = game_step.saturating_sub(start_step) as usize;
+        if i_end >= self.saved_history.len() {
+            return;
+        }
+        // The view is only forced in the middle of the replay, so that the view doesn't switch abruptly
+        let buffer = self.view_buffer;
+        let i_start_buffered = (i_start + buffer).min(self.saved_history.len() - 1);
+
+        let skip: Rc<[PlayerId]> = options.skip_players.as_slice().into();
+        let frame_ticks = options.slow_motion.max(1) as usize;
+        // Without cameras, the replay is shown once with the view of each player
+        let cameras = if options.cameras.is_empty() {
+            &[None][..]
+        } else {
+            &options
+                .cameras
+                .iter()
+                .copied()
+                .map(Some)
+                .collect::<Vec<_>>()[..]
+        };
+        for &camera in cameras {
+            let data = self
+                .saved_history
+                .range(i_end..=i_start_buffered)
+                .enumerate()
+                .rev()
+                .flat_map(|(i, x)| {
+                    let buffered = i < buffer || i + i_end > i_start;
+                    std::iter::repeat_n(
+                        QueuedReplayTick {
+                            force_view: camera.filter(|_| !buffered),
+                            skip: skip.clone(),
+                            tick: x.tick.clone(),
+                        },
+                        frame_ticks,
+                    )
+                });
+            self.pending_replays.extend(data);
+        }
+    }
+
+    /// Counts down the ticks before the pending replays are shown, and returns the number of seconds left
+    /// whenever it reaches a whole second.
+    fn update_countdown(&mut self, countdown_length: u32) -> Option<u32> {
+        if self.pending_replays.is_empty() {
+            return None;
+        }
+        let countdown = self.countdown.get_or_insert(countdown_length);
+        if *countdown == 0 {
+            self.replay_queue.append(&mut self.pending_replays);
+            self.countdown = None;
+            return None;
+        }
+        let res = countdown.is_multiple_of(100).then_some(*countdown / 100);
+        *countdown -= 1;
+        res
+    }
 
-        let data = self
-            .saved_history
-            .range(i_end..=i_start)
+    /// Returns up to `ticks` of the most recent saved ticks, oldest first.
+    fn recent_ticks(&self, ticks: usize) -> VecDeque<ReplayTick> {
+        self.saved_history
+            .iter()
+            .take(ticks)
             .rev()
-            .map(|x| (force_view, x.clone()));
-        self.replay_queue.extend(data);
+            .map(|x| x.tick.clone())
+            .collect()
+    }
+
+    /// Adds a tick with objects that don't come from the simulation, like those of a recorded game.
+    pub(crate) fn add_tick_to_queue(&mut self, packets: [ObjectPacket; 32]) {
+        self.game_step = self.game_step.wrapping_add(1);
+        let tick = ReplayTick {
+            game_step: self.game_step,
+            packets,
+        };
+        self.replay_queue.push_back(QueuedReplayTick {
+            force_view: None,
+            skip: Rc::from([]),
+            tick,
+        });
     }
 
-    fn check_replay(&mut self) -> Option<(Option<PlayerId>, ReplayTick)> {
+    fn check_replay(&mut self) -> Option<QueuedReplayTick> {
         let res = self.replay_queue.pop_front();
+        self.skipping = res.as_ref().map_or(Rc::from([]), |x| x.skip.clone());
         res
     }
+
+    /// The latest tick of the game, which players that skip a replay see while it is shown.
+    fn live_tick(&self) -> Option<&ReplayTick> {
+        self.saved_history.front().map(|x| &x.tick)
+    }
 }
 
 pub(crate) struct HQMServerState {
@@ -616,10 +1453,20 @@ pub(crate) struct HQMServerState {
 
     pub scoreboard: ScoreboardValues,
 
+    /// The scoreboard as the clients see it, updated each tick.
+    display: ScoreboardDisplay,
+    sent_custom_scoreboard: SmallVec<[CustomScoreboardValue; 4]>,
+
+    pub(crate) summary: GameSummary,
+
     packet: u32,
     recording_data: BytesMut,
+    /// Contents of the input chunk of the recording, see [`HQMServer::write_recording_inputs`].
+    recording_inputs: BytesMut,
     recording_msg_pos: usize,
     recording_last_packet: u32,
+    pub(crate) recording_ticks: u32,
+    highlights: HighlightDetector,
 
     saved_packets: Box<ArrayDeque<[ObjectPacket; 32], 192, Wrapping>>,
 
@@ -636,10 +1483,21 @@ impl HQMServerState {
 
             scoreboard,
 
+            display: ScoreboardDisplay {
+                values: scoreboard,
+                custom: SmallVec::new(),
+            },
+            sent_custom_scoreboard: SmallVec::new(),
+
+            summary: GameSummary::default(),
+
             recording_data: BytesMut::with_capacity(64 * 1024 * 1024),
+            recording_inputs: BytesMut::new(),
             recording_msg_pos: 0,
             packet: u32::MAX,
             recording_last_packet: u32::MAX,
+            recording_ticks: 0,
+            highlights: HighlightDetector::default(),
 
             saved_packets: Box::new(ArrayDeque::new()),
 
@@ -655,6 +1513,8 @@ impl HQMServerState {
         self.recording_msg_pos = 0;
         self.packet = u32::MAX;
         self.recording_last_packet = u32::MAX;
+        self.recording_ticks = 0;
+        self.highlights.clear();
 
         self.saved_packets.clear();
 
@@ -662,6 +1522,7 @@ impl HQMServerState {
 
         self.pucks = vec![None; puck_slots];
         self.scoreboard = scoreboard;
+        self.summary = GameSummary::default();
     }
 }
 
@@ -676,19 +1537,58 @@ pub(crate) struct HQMServer {
     pub(crate) state: HQMServerState,
 
     pub(crate) allow_join: bool,
+    /// Ticks until the server shuts down, if a shutdown has been started.
+    pub(crate) shutdown_timer: Option<u32>,
+    /// True if the server is shutting down because the game was abandoned, see [`AbandonPolicy::Shutdown`].
+    pub(crate) abandon_shutdown: bool,
+    /// State of the maintenance mode, or [`None`] if the server is not in maintenance.
+    pub(crate) maintenance: Option<Maintenance>,
+    /// State of the open hours, or [`None`] if the server has no schedule.
+    pub(crate) schedule_state: Option<ScheduleState>,
     pub config: ServerConfiguration,
 
     pub physics_config: PhysicsConfiguration,
     pub rink: Rink,
 
-    game_id: u32,
+    pub(crate) game_id: u32,
     pub is_muted: bool,
     pub start_time: DateTime<Utc>,
 
     has_current_game_been_active: bool,
+    /// Ticks since the last player left the current game, if it is kept by [`AbandonPolicy::Keep`].
+    abandoned_ticks: u32,
 
     pub(crate) ban: Box<dyn BanCheck>,
+    /// Verifies joining players, if authentication is enabled.
+    pub(crate) auth: Option<Box<dyn AuthProvider>>,
+    /// Joins that wait for a ban check or authentication, by address.
+    pending_joins: HashMap<SocketAddr, PendingJoin>,
+    /// Whether the server is being announced to the master servers.
+    pub(crate) master_servers: MasterServerStatus,
     pub(crate) save_recording: Box<dyn RecordingSaveMethod>,
+    pub(crate) observers: Vec<Box<dyn GameObserver>>,
+    /// In headless mode, the time is simulated instead of taken from the system clock.
+    pub(crate) simulated_clock: Option<DateTime<Utc>>,
+    /// When each player last used each command with a cooldown.
+    command_last_used: HashMap<(String, Chatter), Instant>,
+    /// Commands registered by the game mode.
+    pub(crate) commands: CommandRegistry,
+    pub(crate) reports: ReportQueue,
+    pub(crate) votes: VoteState,
+    /// Puck positions of the last ticks, newest first, for lag compensation.
+    pub(crate) puck_history: VecDeque<Vec<Option<Point3<f32>>>>,
+    /// Skater bots run by the server, either to fill up the teams or in headless mode.
+    bots: Vec<SkaterBot>,
+    pub(crate) mutes: MuteList,
+    pub(crate) chat_filter: ChatFilter,
+    consistency: Option<ConsistencyChecker>,
+    /// Game clock of the previous tick, to tell whether play is live for AFK detection.
+    afk_clock: u32,
+    pub(crate) stick_check: StickCheck,
+    /// Seed that the random seed of each game is derived from.
+    random_seed: u64,
+    /// Random numbers of the current game.
+    pub(crate) rng: GameRng,
 }
 
 impl HQMServer {
@@ -699,9 +1599,30 @@ impl HQMServer {
         ban: Box<dyn BanCheck>,
         save_recording: Box<dyn RecordingSaveMethod>,
     ) -> Self {
+        let mut state = HQMServerState::new(initial_values.puck_slots, initial_values.values);
+        let random_seed = config.random_seed.unwrap_or_else(rng::random_server_seed);
+        let game_rng = GameRng::new(rng::game_seed(random_seed, 1));
+        state.summary.random_seed = game_rng.seed();
+        state.players.default_language = config.language;
+        state.players.join_history = config.join_history;
+        state.players.announcements = config.announcements.clone();
+        let mut observers: Vec<Box<dyn GameObserver>> = vec![];
+        if let Some(sender) = &config.events {
+            observers.push(Box::new(EventBroadcaster::new(sender.clone())));
+        }
+        let mut rink = Rink::new_area(30.0, 61.0, 8.5, config.rink_area);
+        rink.board_zones.clone_from(&config.board_zones);
+        let mutes = MuteList::new(config.mute_file.clone());
+        let chat_filter = ChatFilter::new(config.chat.clone());
+        let consistency = (config.consistency_check_interval > 0)
+            .then(|| ConsistencyChecker::new(config.consistency_check_interval));
         let server = HQMServer {
-            state: HQMServerState::new(initial_values.puck_slots, initial_values.values),
+            state,
             allow_join: true,
+            shutdown_timer: None,
+            abandon_shutdown: false,
+            maintenance: None,
+            schedule_state: None,
 
             physics_config,
             is_muted: false,
@@ -709,15 +1630,102 @@ impl HQMServer {
             game_id: 1,
 
             has_current_game_been_active: false,
+            abandoned_ticks: 0,
             ban,
+            auth: None,
+            pending_joins: HashMap::new(),
+            master_servers: MasterServerStatus::default(),
             save_recording,
+            observers,
+            simulated_clock: None,
+            command_last_used: HashMap::new(),
+            commands: CommandRegistry::default(),
+            reports: ReportQueue::default(),
+            votes: VoteState::default(),
+            puck_history: VecDeque::new(),
+            bots: vec![],
+            mutes,
+            chat_filter,
+            consistency,
+            afk_clock: 0,
+            stick_check: StickCheck::default(),
+            random_seed,
+            rng: game_rng,
 
             start_time: Default::default(),
-            rink: Rink::new(30.0, 61.0, 8.5),
+            rink,
         };
         server
     }
 
+    /// Checks the signature of a packet if the sender uses packet signing, and handles the message in it.
+    pub(crate) async fn handle_packet<B: GameMode>(
+        &mut self,
+        addr: SocketAddr,
+        socket: &Arc<UdpSocket>,
+        packet: &[u8],
+        behaviour: &mut B,
+        write_buf: &mut BytesMut,
+    ) {
+        if let Some(message) = self.parse_packet(addr, packet) {
+            self.handle_message(addr, socket, message, behaviour, write_buf)
+                .await;
+        }
+    }
+
+    /// Verifies the packet, if the player uses packet signing, and parses it.
+    pub(crate) fn parse_packet(
+        &mut self,
+        addr: SocketAddr,
+        packet: &[u8],
+    ) -> Option<HQMClientToServerMessage> {
+        let integrity = self
+            .state
+            .players
+            .players
+            .find_player_by_addr_mut(addr)
+            .and_then(|(_, player)| match &mut player.data {
+                ServerPlayerData::NetworkPlayer { data } => data.integrity.as_mut(),
+                ServerPlayerData::Bot { .. } => None,
+            });
+        let packet = match integrity {
+            Some(integrity) => integrity.verify(packet)?,
+            None => self.rebind_signed_player(addr, packet).unwrap_or(packet),
+        };
+        HQMMessageCodec.parse_message(packet).ok()
+    }
+
+    /// Moves a player using packet signing to a new address, if the packet is signed with their key.
+    ///
+    /// The player is found by the key ID in the packet. Replayed packets are rejected, as their
+    /// counter isn't larger than in the player's latest packet.
+    fn rebind_signed_player<'a>(&mut self, addr: SocketAddr, packet: &'a [u8]) -> Option<&'a [u8]> {
+        let key_id = integrity::packet_key_id(packet)?;
+        if self.ban.check_ip_banned(addr.ip()) != BanCheckResponse::Allowed {
+            return None;
+        }
+        self.state
+            .players
+            .players
+            .iter_players_mut()
+            .find_map(|(player_id, player)| {
+                let ServerPlayerData::NetworkPlayer { data } = &mut player.data else {
+                    return None;
+                };
+                let integrity = data.integrity.as_mut()?;
+                if integrity.key_id() != key_id {
+                    return None;
+                }
+                let body = integrity.verify_signed(packet)?;
+                info!(
+                    "{} ({}) changed address from {:?} to {:?}",
+                    player.player_name, player_id, data.addr, addr
+                );
+                data.addr = addr;
+                Some(body)
+            })
+    }
+
     pub(crate) async fn handle_message<B: GameMode>(
         &mut self,
         addr: SocketAddr,
@@ -725,13 +1733,30 @@ impl HQMServer {
         command: HQMClientToServerMessage,
         behaviour: &mut B,
         write_buf: &mut BytesMut,
+    ) {
+        match command {
+            HQMClientToServerMessage::ServerInfo { version, ping } => {
+                self.request_info(socket, addr, version, ping, behaviour, write_buf)
+                    .await;
+            }
+            command => self.handle_player_message(addr, command, behaviour),
+        }
+    }
+
+    /// Handles a message from a player. Server info requests are ignored, as they need a socket to be answered.
+    pub(crate) fn handle_player_message<B: GameMode>(
+        &mut self,
+        addr: SocketAddr,
+        command: HQMClientToServerMessage,
+        behaviour: &mut B,
     ) {
         match command {
             HQMClientToServerMessage::Join {
                 version,
                 player_name,
+                signed,
             } => {
-                self.player_join(addr, version, player_name, behaviour);
+                self.player_join(addr, version, player_name, signed, behaviour);
             }
             HQMClientToServerMessage::Update {
                 current_game_id,
@@ -753,10 +1778,7 @@ impl HQMServer {
                 behaviour,
             ),
             HQMClientToServerMessage::Exit => self.player_exit(addr, behaviour),
-            HQMClientToServerMessage::ServerInfo { version, ping } => {
-                self.request_info(socket, addr, version, ping, behaviour, write_buf)
-                    .await;
-            }
+            HQMClientToServerMessage::ServerInfo { .. } => {}
         }
     }
 
@@ -769,19 +1791,7 @@ impl HQMServer {
         behaviour: &B,
         write_buf: &mut BytesMut,
     ) {
-        write_buf.clear();
-        let mut writer = HQMMessageWriter::new(write_buf);
-        writer.write_bytes_aligned(GAME_HEADER);
-        writer.write_byte_aligned(1);
-        writer.write_bits(8, 55);
-        writer.write_u32_aligned(ping);
-
-        let player_count = self.real_player_count();
-        writer.write_bits(8, player_count as u32);
-        writer.write_bits(4, 4);
-        writer.write_bits(4, behaviour.server_list_team_size() as u32);
-
-        writer.write_bytes_aligned_padded(32, self.config.server_name.as_ref());
+        write_server_info(write_buf, ping, &self.server_info(behaviour));
 
         let socket = socket.clone();
         let addr = addr.clone();
@@ -790,6 +1800,19 @@ impl HQMServer {
         let _ = socket.send_to(slice, addr).await;
     }
 
+    pub(crate) fn server_info<B: GameMode>(&self, behaviour: &B) -> ServerInfo {
+        ServerInfo {
+            player_count: self.real_player_count(),
+            team_size: behaviour.server_list_team_size(),
+            server_name: match self.schedule_state {
+                Some(ScheduleState::Closed { opens }) => {
+                    schedule::closed_server_name(&self.config.server_name, opens)
+                }
+                _ => self.config.server_name.clone(),
+            },
+        }
+    }
+
     fn real_player_count(&self) -> usize {
         let mut player_count = 0;
         for (_, player) in self.state.players.players.iter_players() {
@@ -804,6 +1827,18 @@ impl HQMServer {
         player_count
     }
 
+    fn player_count_from_ip(&self, ip: IpAddr) -> usize {
+        self.state
+            .players
+            .players
+            .iter_players()
+            .filter(|(_, player)| match &player.data {
+                ServerPlayerData::NetworkPlayer { data } => data.addr.ip() == ip,
+                ServerPlayerData::Bot { .. } => false,
+            })
+            .count()
+    }
+
     fn player_update<B: GameMode>(
         &mut self,
         addr: SocketAddr,
@@ -847,13 +1882,20 @@ impl HQMServer {
             data.inactivity = 0;
             data.client_version = client_version;
             data.known_packet = new_known_packet;
-            player.input = input;
             data.game_id = current_game_id;
             data.known_msgpos = known_msgpos;
 
             if let Some(deltatime) = deltatime {
                 data.deltatime = deltatime;
             }
+            if data.kick_timer.is_some() {
+                return;
+            }
+            if player.input != input {
+                data.afk_ticks = 0;
+            }
+            player.input_packets = player.input_packets.wrapping_add(1);
+            player.input = input;
 
             if let Some((rep, message)) = chat {
                 if data.chat_rep != Some(rep) {
@@ -864,11 +1906,49 @@ impl HQMServer {
         }
     }
 
+    /// Remembers a join that waits for a ban check or authentication, so that it is tried again every tick
+    /// until the answer arrives, even if the client doesn't send the join again.
+    fn defer_join(&mut self, addr: SocketAddr, version: u32, name: String, signed: bool) {
+        let ticks_left = self
+            .pending_joins
+            .get(&addr)
+            .map_or(PENDING_JOIN_TICKS, |join| join.ticks_left);
+        self.pending_joins.insert(
+            addr,
+            PendingJoin {
+                version,
+                name,
+                signed,
+                ticks_left,
+            },
+        );
+    }
+
+    /// Tries the joins that were waiting for a ban check or authentication again.
+    fn retry_pending_joins<B: GameMode>(&mut self, behaviour: &mut B) {
+        if self.pending_joins.is_empty() {
+            return;
+        }
+        let pending_joins = std::mem::take(&mut self.pending_joins);
+        for (addr, join) in pending_joins {
+            if join.ticks_left == 0 {
+                info!("{} ({}) could not be checked in time", join.name, addr);
+                continue;
+            }
+            let ticks_left = join.ticks_left - 1;
+            self.player_join(addr, join.version, join.name, join.signed, behaviour);
+            if let Some(join) = self.pending_joins.get_mut(&addr) {
+                join.ticks_left = ticks_left;
+            }
+        }
+    }
+
     fn player_join<B: GameMode>(
         &mut self,
         addr: SocketAddr,
         player_version: u32,
         name: String,
+        signed: bool,
         behaviour: &mut B,
     ) {
         let player_count = self.real_player_count();
@@ -885,8 +1965,26 @@ impl HQMServer {
         }
 
         // Check ban list
-        if self.ban.check_ip_banned(addr.ip()) != BanCheckResponse::Allowed {
-            return;
+        match self.ban.check_ip_banned(addr.ip()) {
+            BanCheckResponse::Allowed => {}
+            BanCheckResponse::Banned => return,
+            BanCheckResponse::Pending => {
+                self.defer_join(addr, player_version, name, signed);
+                return;
+            }
+        }
+
+        // Check per-IP limit
+        if let Some(ip_player_max) = self.config.ip_player_max {
+            if !self.config.ip_whitelist.contains(&addr.ip())
+                && self.player_count_from_ip(addr.ip()) >= ip_player_max
+            {
+                info!(
+                    "{} tried to join from address {:?}, but the IP address already has {} players",
+                    name, addr, ip_player_max
+                );
+                return;
+            }
         }
 
         // Disabled join
@@ -894,17 +1992,90 @@ impl HQMServer {
             return;
         }
 
-        if let Some(player_index) = self.add_player(&name, addr) {
+        let (name, identity) = match &mut self.auth {
+            Some(auth) => {
+                let (name, token) = split_name_token(&name);
+                match auth.authenticate(name, token, addr.ip()) {
+                    AuthResponse::Allowed(identity) => (name, identity),
+                    AuthResponse::Denied => return,
+                    AuthResponse::Pending => {
+                        let name = name.to_owned();
+                        self.defer_join(addr, player_version, name, signed);
+                        return;
+                    }
+                }
+            }
+            None => (name.as_str(), None),
+        };
+
+        // Check name bans
+        match self.ban.check_player_banned(addr.ip(), name) {
+            BanCheckResponse::Allowed => {}
+            BanCheckResponse::Banned => {
+                info!(
+                    "{} tried to join from address {:?}, but is banned",
+                    name, addr
+                );
+                return;
+            }
+            BanCheckResponse::Pending => {
+                let name = name.to_owned();
+                self.defer_join(addr, player_version, name, signed);
+                return;
+            }
+        }
+
+        if let Some(player_index) = self.add_player(name, addr) {
+            if signed {
+                self.start_packet_signing(player_index);
+            }
+            match &identity {
+                Some(identity) => info!(
+                    "{} ({}) joined server from address {:?} as {}",
+                    name, player_index, addr, identity.account
+                ),
+                None => info!(
+                    "{} ({}) joined server from address {:?}",
+                    name, player_index, addr
+                ),
+            }
+            let now = self.now();
+            let account = identity.as_ref().map(|x| x.account.as_str());
+            let mute_status = self.mutes.check(addr.ip(), account, now);
+            if let Some(player) = self.state.players.players.get_player_mut(player_index) {
+                player.is_muted = mute_status;
+                if let ServerPlayerData::NetworkPlayer { data } = &mut player.data {
+                    data.identity = identity;
+                }
+            }
             behaviour.after_player_join(self.into(), player_index);
-            info!(
-                "{} ({}) joined server from address {:?}",
-                name, player_index, addr
-            );
-            let msg = format!("{} joined", name);
-            self.state.players.add_server_chat_message(msg);
+            let window = self.config.join_message_window * 100;
+            self.state.players.add_join_exit_message(name, true, window);
         }
     }
 
+    /// Creates a key for signing packets and sends it to the player.
+    fn start_packet_signing(&mut self, player_id: PlayerId) {
+        let Some(player) = self.state.players.players.get_player_mut(player_id) else {
+            return;
+        };
+        let ServerPlayerData::NetworkPlayer { data } = &mut player.data else {
+            return;
+        };
+        let Some(integrity) = PacketIntegrity::new() else {
+            warn!("Could not create packet signing key for {}", player_id);
+            return;
+        };
+        // Sent even if the client hasn't announced extension support yet, a client that
+        // asks for packet signing must be able to read this message
+        let message = HQMMessage::Extension {
+            message_type: KEY_MESSAGE_TYPE,
+            payload: integrity.key_message(),
+        };
+        data.integrity = Some(Box::new(integrity));
+        player.add_message(Rc::new(message));
+    }
+
     pub fn set_hand(&mut self, hand: SkaterHand, player_id: PlayerId) {
         if let Some(player) = self.state.players.players.get_player_mut(player_id) {
             player.preferred_hand = hand;
@@ -922,6 +2093,56 @@ impl HQMServer {
         }
     }
 
+    fn toggle_spectator(&mut self, player_id: PlayerId) {
+        let players = &self.state.players.players;
+        let Some(player) = players.get_player(player_id) else {
+            return;
+        };
+        if !player.is_spectator {
+            if let Some(spectator_max) = self.config.spectator_max {
+                let spectator_count = players
+                    .iter_players()
+                    .filter(|(_, player)| player.is_spectator)
+                    .count();
+                if spectator_count >= spectator_max {
+                    self.state
+                        .players
+                        .add_directed_server_chat_message("Too many spectators", player_id);
+                    return;
+                }
+            }
+        }
+        // The game mode moves the player off the ice, so that it learns about it like any other spectator
+        let Some(player) = self.state.players.players.get_player_mut(player_id) else {
+            return;
+        };
+        player.is_spectator = !player.is_spectator;
+        let name = player.player_name.clone();
+        let msg = if player.is_spectator {
+            info!("{} ({}) is only spectating", name, player_id);
+            "You are now a spectator, use /spec again to play"
+        } else {
+            info!("{} ({}) wants to play again", name, player_id);
+            "You can join a team again"
+        };
+        player.add_directed_server_chat_message(msg);
+    }
+
+    fn set_language(&mut self, player_id: PlayerId, arg: &str) {
+        if let Some(player) = self.state.players.players.get_player_mut(player_id) {
+            match Language::from_code(arg.trim()) {
+                Some(language) => {
+                    player.language = language;
+                    let msg = format!("Language set to {}", language.code());
+                    player.add_directed_server_chat_message(msg);
+                }
+                None => {
+                    player.add_directed_server_chat_message("Usage: /lang en|fi|ru");
+                }
+            }
+        }
+    }
+
     fn process_command<B: GameMode>(
         &mut self,
         command: &str,
@@ -929,180 +2150,176 @@ impl HQMServer {
         player_id: PlayerId,
         behaviour: &mut B,
     ) {
-        match command {
-            "enablejoin" => {
-                self.set_allow_join(player_id, true);
-            }
-            "disablejoin" => {
-                self.set_allow_join(player_id, false);
-            }
-            "mute" => {
-                if let Ok(mute_player_index) = arg.parse::<PlayerIndex>() {
-                    self.mute_player(player_id, mute_player_index);
-                }
-            }
-            "unmute" => {
-                if let Ok(mute_player_index) = arg.parse::<PlayerIndex>() {
-                    self.unmute_player(player_id, mute_player_index);
-                }
-            }
-            /*"shadowmute" => {
-                if let Ok(mute_player_index) = arg.parse::<usize>() {
-                    if mute_player_index < self.players.len() {
-                        self.shadowmute_player(player_index, mute_player_index);
-                    }
-                }
-            },*/
-            "mutechat" => {
-                self.mute_chat(player_id);
-            }
-            "unmutechat" => {
-                self.unmute_chat(player_id);
-            }
-            "kick" => {
-                if let Ok(kick_player_index) = arg.parse::<PlayerIndex>() {
-                    self.kick_player(player_id, kick_player_index, false, behaviour);
-                }
-            }
-            "kickall" => {
-                self.kick_all_matching(player_id, arg, false, behaviour);
-            }
-            "ban" => {
-                if let Ok(kick_player_index) = arg.parse::<PlayerIndex>() {
-                    self.kick_player(player_id, kick_player_index, true, behaviour);
-                }
-            }
-            "banall" => {
-                self.kick_all_matching(player_id, arg, true, behaviour);
-            }
-            "clearbans" => {
-                self.clear_bans(player_id);
-            }
-            "replay" | "record" => self.set_recording(player_id, arg),
-            "lefty" => {
-                self.set_hand(SkaterHand::Left, player_id);
-            }
-            "righty" => {
-                self.set_hand(SkaterHand::Right, player_id);
-            }
-            "admin" => {
-                self.admin_login(player_id, arg);
-            }
-            "serverrestart" => {
-                self.restart_server(player_id);
-            }
-            "list" => {
-                if arg.is_empty() {
-                    self.list_players(player_id, 0);
-                } else if let Ok(first_index) = arg.parse::<usize>() {
-                    self.list_players(player_id, first_index);
-                }
-            }
-            "search" => {
-                self.search_players(player_id, arg);
-            }
-            "ping" => {
-                if let Ok(ping_player_index) = arg.parse::<PlayerIndex>() {
-                    self.ping(ping_player_index, player_id);
-                }
-            }
-            "pings" => {
-                if let Some((ping_player_id, _name)) = self.player_exact_unique_match(arg) {
-                    self.ping(ping_player_id.index, player_id);
-                } else {
-                    let matches = self.player_search(arg);
-                    if matches.is_empty() {
-                        self.state
-                            .players
-                            .add_directed_server_chat_message("No matches found", player_id);
-                    } else if matches.len() > 1 {
-                        self.state.players.add_directed_server_chat_message(
-                            "Multiple matches found, use /ping X",
-                            player_id,
-                        );
-                        for (found_player_id, found_player_name) in matches.into_iter().take(5) {
-                            let msg = format!("{}: {}", found_player_id.index, found_player_name);
-                            self.state
-                                .players
-                                .add_directed_server_chat_message(msg, player_id);
-                        }
-                    } else {
-                        self.ping(matches[0].0.index, player_id);
-                    }
-                }
-            }
-            "view" => {
-                if let Ok(view_player_index) = arg.parse::<PlayerIndex>() {
-                    self.view(view_player_index, player_id);
-                }
-            }
-            "views" => {
-                if let Some((view_player_id, _name)) = self.player_exact_unique_match(arg) {
-                    self.view(view_player_id.index, player_id);
-                } else {
-                    let matches = self.player_search(arg);
-                    if matches.is_empty() {
-                        self.state
-                            .players
-                            .add_directed_server_chat_message("No matches found", player_id);
-                    } else if matches.len() > 1 {
-                        self.state.players.add_directed_server_chat_message(
-                            "Multiple matches found, use /view X",
-                            player_id,
-                        );
-                        for (found_player_id, found_player_name) in matches.into_iter().take(5) {
-                            let str = format!("{}: {}", found_player_id.index, found_player_name);
-                            self.state
-                                .players
-                                .add_directed_server_chat_message(str, player_id);
-                        }
-                    } else {
-                        self.view(matches[0].0.index, player_id);
-                    }
-                }
-            }
-            "restoreview" => {
-                if let Some(player) = self.state.players.players.get_player_mut(player_id) {
-                    if let ServerPlayerData::NetworkPlayer { data } = &mut player.data {
-                        if data.view_player_index != player_id.index {
-                            data.view_player_index = player_id.index;
-                            self.state.players.add_directed_server_chat_message(
-                                "View has been restored",
-                                player_id,
-                            );
-                        }
-                    }
-                }
-            }
-            "t" => {
-                self.state.players.add_user_team_message(arg, player_id);
+        let (definition, handler) = if let Some((definition, handler)) = SERVER_COMMANDS
+            .iter()
+            .find(|(definition, _)| definition.is_called(command))
+        {
+            (*definition, Some(*handler))
+        } else if let Some(definition) = self.commands.find(command) {
+            (*definition, None)
+        } else {
+            if self.check_command_allowed(command, player_id) {
+                behaviour.handle_command(self.into(), command, arg, player_id);
             }
-            "version" => {
-                let version = env!("CARGO_PKG_VERSION");
-                let s = format!("Migo HQM Server, version {}", version);
+            return;
+        };
 
+        if definition.permission == CommandPermission::Admin
+            && self
+                .state
+                .players
+                .players
+                .check_admin_or_deny(player_id)
+                .is_none()
+        {
+            return;
+        }
+        let Some(args) = definition.parse_args(arg) else {
+            let msg = format!("Usage: {}", definition.usage());
+            self.state
+                .players
+                .add_directed_server_chat_message(msg, player_id);
+            return;
+        };
+        if !self.check_command_allowed(definition.name, player_id) {
+            return;
+        }
+        match handler {
+            Some(handler) => handler(self, behaviour, &args, player_id),
+            None => behaviour.handle_command(self.into(), definition.name, arg, player_id),
+        }
+    }
+
+    /// Returns the commands that the player can use, server commands first.
+    fn available_commands(&self, player_id: PlayerId) -> Vec<ChatCommand> {
+        let is_admin = self
+            .state
+            .players
+            .players
+            .get_player(player_id)
+            .is_some_and(|player| player.is_admin);
+        SERVER_COMMANDS
+            .iter()
+            .map(|(definition, _)| definition)
+            .chain(self.commands.iter())
+            .filter(|definition| {
+                let admin_only = definition.permission == CommandPermission::Admin
+                    || self
+                        .config
+                        .commands
+                        .get(definition.name)
+                        .is_some_and(|x| x.permission == CommandPermission::Admin);
+                is_admin || !admin_only
+            })
+            .copied()
+            .collect()
+    }
+
+    fn help(&mut self, player_id: PlayerId, command: Option<&str>) {
+        let commands = self.available_commands(player_id);
+        let lines = if let Some(command) = command {
+            let command = command.trim_start_matches('/');
+            let Some(definition) = commands.iter().find(|x| x.is_called(command)) else {
                 self.state
                     .players
-                    .add_directed_server_chat_message(s, player_id);
+                    .add_directed_server_chat_message("Unknown command", player_id);
+                return;
+            };
+            let mut usage = definition.usage();
+            for alias in definition.aliases {
+                usage.push_str(&format!(", /{}", alias));
             }
-            "git" => {
-                let git_sha = option_env!("VERGEN_GIT_SHA");
-                let s: Cow<'static, str> = if let Some(git_sha) = git_sha {
-                    format!("Git commit: {}", git_sha).into()
-                } else {
-                    "No git commit ID found".into()
-                };
+            let mut lines = wrap_chat_lines(&usage);
+            lines.extend(wrap_chat_lines(definition.help));
+            lines
+        } else {
+            let mut names = commands
+                .iter()
+                .map(|definition| format!("/{}", definition.name))
+                .collect::<Vec<_>>();
+            names.sort();
+            names.dedup();
+            let mut lines = wrap_chat_lines(&format!("Commands: {}", names.join(" ")));
+            lines.push("Type /help COMMAND for details".to_owned());
+            lines
+        };
+        for line in lines {
+            self.state
+                .players
+                .add_directed_server_chat_message(line, player_id);
+        }
+    }
+
+    /// Finds a player by name, and runs `f` with the player's ID if there is a unique match.
+    /// Otherwise, tells the player about the matches, and suggests `alternative` for picking one.
+    fn with_name_match(
+        &mut self,
+        player_id: PlayerId,
+        name: &str,
+        alternative: &str,
+        f: fn(&mut Self, PlayerIndex, PlayerId),
+    ) {
+        if let Some((found_player_id, _name)) = self.player_exact_unique_match(name) {
+            f(self, found_player_id.index, player_id);
+            return;
+        }
+        let matches = self.player_search(name);
+        if matches.is_empty() {
+            self.state
+                .players
+                .add_directed_server_chat_message("No matches found", player_id);
+        } else if matches.len() > 1 {
+            let msg = format!("Multiple matches found, use {}", alternative);
+            self.state
+                .players
+                .add_directed_server_chat_message(msg, player_id);
+            for (found_player_id, found_player_name) in matches.into_iter().take(5) {
+                let msg = format!("{}: {}", found_player_id.index, found_player_name);
                 self.state
                     .players
-                    .add_directed_server_chat_message(s, player_id);
+                    .add_directed_server_chat_message(msg, player_id);
             }
+        } else {
+            f(self, matches[0].0.index, player_id);
+        }
+    }
 
-            _ => behaviour.handle_command(self.into(), command, arg, player_id),
+    fn restore_view(&mut self, player_id: PlayerId) {
+        if let Some(player) = self.state.players.players.get_player_mut(player_id) {
+            if let ServerPlayerData::NetworkPlayer { data } = &mut player.data {
+                if data.view_player_index != player_id.index {
+                    data.view_player_index = player_id.index;
+                    self.state
+                        .players
+                        .add_directed_server_chat_message("View has been restored", player_id);
+                }
+            }
         }
     }
 
+    fn show_version(&mut self, player_id: PlayerId) {
+        let version = env!("CARGO_PKG_VERSION");
+        let s = format!("Migo HQM Server, version {}", version);
+
+        self.state
+            .players
+            .add_directed_server_chat_message(s, player_id);
+    }
+
+    fn show_git_commit(&mut self, player_id: PlayerId) {
+        let git_sha = option_env!("VERGEN_GIT_SHA");
+        let s: Cow<'static, str> = if let Some(git_sha) = git_sha {
+            format!("Git commit: {}", git_sha).into()
+        } else {
+            "No git commit ID found".into()
+        };
+        self.state
+            .players
+            .add_directed_server_chat_message(s, player_id);
+    }
+
     fn list_players(&mut self, receiver_id: PlayerId, first_index: usize) {
-        let res: Vec<_> = self
+        let res: smallvec::SmallVec<[_; 5]> = self
             .state
             .players
             .players
@@ -1118,6 +2335,72 @@ impl HQMServer {
         }
     }
 
+    /// Shows the goals, assists and points of the player who asked in the current game, or of the players
+    /// whose name contains `name`.
+    fn msg_stats(&mut self, receiver_id: PlayerId, name: Option<&str>) {
+        let lines = match name {
+            Some(name) => self.state.players.stats.search(name),
+            None => match self.state.players.players.get_player(receiver_id) {
+                Some(player) => {
+                    let name = player.player_name.clone();
+                    vec![self
+                        .state
+                        .players
+                        .stats
+                        .get(&name)
+                        .cloned()
+                        .unwrap_or_else(|| StatLine::new(name))]
+                }
+                None => return,
+            },
+        };
+        if lines.is_empty() {
+            self.state
+                .players
+                .add_directed_server_chat_message("No matches found", receiver_id);
+            return;
+        }
+        for line in lines.into_iter().take(LEADERS_PER_PAGE) {
+            self.state
+                .players
+                .add_directed_server_chat_message(format_stat_line(&line), receiver_id);
+        }
+    }
+
+    /// Shows one page of the players with the most points in the current game.
+    fn msg_leaders(&mut self, receiver_id: PlayerId, page: usize) {
+        let leaders = self
+            .state
+            .players
+            .stats
+            .leaders()
+            .into_iter()
+            .enumerate()
+            .map(|(i, line)| format!("{}. {}", i + 1, format_stat_line(line)))
+            .collect::<Vec<_>>();
+        if leaders.is_empty() {
+            self.state
+                .players
+                .add_directed_server_chat_message("No stats yet this game", receiver_id);
+            return;
+        }
+        let pages = leaders.len().div_ceil(LEADERS_PER_PAGE);
+        let page = page.clamp(1, pages);
+        let header = format!("Leaders, page {}/{}", page, pages);
+        self.state
+            .players
+            .add_directed_server_chat_message(header, receiver_id);
+        for msg in leaders
+            .into_iter()
+            .skip((page - 1) * LEADERS_PER_PAGE)
+            .take(LEADERS_PER_PAGE)
+        {
+            self.state
+                .players
+                .add_directed_server_chat_message(msg, receiver_id);
+        }
+    }
+
     fn search_players(&mut self, player_id: PlayerId, name: &str) {
         let matches = self.player_search(name);
         if matches.is_empty() {
@@ -1173,6 +2456,139 @@ impl HQMServer {
         }
     }
 
+    fn rewind(&mut self, player_id: PlayerId, arg: &str) {
+        let rewind_max = self.config.rewind_max;
+        if rewind_max == 0 {
+            self.state
+                .players
+                .add_directed_server_chat_message("Rewind is disabled", player_id);
+            return;
+        }
+        let mut args = arg.split_whitespace();
+        let seconds = match args.next() {
+            None => rewind_max.min(10),
+            Some("stop") => 0,
+            Some(x) => match x.parse::<u32>() {
+                Ok(seconds) => seconds.min(rewind_max),
+                Err(_) => return,
+            },
+        };
+        let slow_motion = args.next().is_some_and(|x| x.eq_ignore_ascii_case("slow"));
+        let ticks = self.state.replay.recent_ticks(seconds as usize * 100);
+        let packet = self.state.packet;
+
+        let Some(player) = self.state.players.players.get_player_mut(player_id) else {
+            return;
+        };
+        let on_ice = player.object.is_some();
+        let ServerPlayerData::NetworkPlayer { data } = &mut player.data else {
+            return;
+        };
+        let msg: Cow<'static, str> = if on_ice {
+            "You must be a spectator to rewind".into()
+        } else if seconds == 0 {
+            if data.stop_personal_replay(packet) {
+                "Back to live".into()
+            } else {
+                return;
+            }
+        } else if ticks.is_empty() {
+            "Nothing to rewind".into()
+        } else {
+            let frame_ticks = if slow_motion { 2 } else { 1 };
+            data.personal_replay = Some(PersonalReplay::new(ticks, frame_ticks));
+            if slow_motion {
+                format!("Rewinding {} seconds in slow motion", seconds).into()
+            } else {
+                format!("Rewinding {} seconds", seconds).into()
+            }
+        };
+        self.state
+            .players
+            .add_directed_server_chat_message(msg, player_id);
+    }
+
+    /// Moves the personal replays of spectators forward, and ends the ones that are finished.
+    fn update_personal_replays(&mut self) {
+        let packet = self.state.packet;
+        for (_, player) in self.state.players.players.iter_players_mut() {
+            let on_ice = player.object.is_some();
+            let ServerPlayerData::NetworkPlayer { data } = &mut player.data else {
+                continue;
+            };
+            if let Some(replay) = &mut data.personal_replay {
+                if on_ice || !replay.advance() {
+                    data.stop_personal_replay(packet);
+                    if !on_ice {
+                        player.add_directed_server_chat_message("Back to live");
+                    }
+                }
+            } else if let Some(full_update_until) = data.full_update_until {
+                if data.known_packet != u32::MAX && data.known_packet >= full_update_until {
+                    data.full_update_until = None;
+                }
+            }
+        }
+    }
+
+    /// Objects are sent without delta compression to players that skip the replay, until they have
+    /// acknowledged a packet sent after it, as the older packets contain replay objects.
+    fn update_replay_skips(&mut self) {
+        let packet = self.state.packet.wrapping_add(1);
+        let skipping = self.state.replay.skipping.clone();
+        for player_id in skipping.iter() {
+            if let Some(ServerPlayerData::NetworkPlayer { data }) = self
+                .state
+                .players
+                .players
+                .get_player_mut(*player_id)
+                .map(|x| &mut x.data)
+            {
+                data.full_update_until = Some(packet);
+            }
+        }
+    }
+
+    /// Resyncs clients whose acknowledged packets keep falling further behind than their ping explains,
+    /// because of clock drift or heavy packet loss, by sending them objects without delta compression
+    /// until they have caught up.
+    fn update_drifting_clients(&mut self) {
+        let packet = self.state.packet;
+        for (player_id, player) in self.state.players.players.iter_players_mut() {
+            let ServerPlayerData::NetworkPlayer { data } = &mut player.data else {
+                continue;
+            };
+            if data.game_id != self.game_id
+                || data.known_packet == u32::MAX
+                || data.personal_replay.is_some()
+                || data.full_update_until.is_some()
+                || data.last_ping.is_empty()
+            {
+                data.drift_ticks = 0;
+                continue;
+            }
+            let ping = data.last_ping.iter().sum::<f32>() / data.last_ping.len() as f32;
+            let expected = (ping * 100.0) as u32 + DRIFT_MARGIN;
+            // Clients that acknowledge packets that haven't been sent yet get full updates anyway
+            let drifting = packet
+                .checked_sub(data.known_packet)
+                .is_some_and(|behind| behind > expected);
+            if !drifting || data.inactivity > DRIFT_MARGIN {
+                data.drift_ticks = 0;
+                continue;
+            }
+            data.drift_ticks += 1;
+            if data.drift_ticks >= DRIFT_TICKS {
+                info!(
+                    "{} ({}) acknowledged packet {} at packet {}, resyncing",
+                    player.player_name, player_id, data.known_packet, packet
+                );
+                data.drift_ticks = 0;
+                data.full_update_until = Some(packet.wrapping_add(1));
+            }
+        }
+    }
+
     fn ping(&mut self, ping_player_index: PlayerIndex, player_id: PlayerId) {
         if let Some((_, ping_player)) = self
             .state
@@ -1239,12 +2655,69 @@ impl HQMServer {
         found
     }
 
+    /// Checks the configured permission and cooldown of a command, and tells the player if it's denied.
+    fn check_command_allowed(&mut self, command: &str, player_id: PlayerId) -> bool {
+        let Some(command_config) = self.config.commands.get(command) else {
+            return true;
+        };
+        let Some(player) = self.state.players.players.get_player(player_id) else {
+            return false;
+        };
+        let is_admin = player.is_admin;
+        let chatter = player.chatter();
+        if command_config.permission == CommandPermission::Admin && !is_admin {
+            let msg = format!("/{} is only available to admins", command);
+            self.state
+                .players
+                .add_directed_server_chat_message(msg, player_id);
+            return false;
+        }
+        if let (Some(cooldown), Some(chatter)) = (command_config.cooldown, chatter) {
+            let now = Instant::now();
+            let key = (command.to_owned(), chatter);
+            if !is_admin {
+                if let Some(last_used) = self.command_last_used.get(&key) {
+                    let elapsed = now.duration_since(*last_used);
+                    if elapsed < cooldown {
+                        let remaining = (cooldown - elapsed).as_secs() + 1;
+                        let msg = format!(
+                            "/{} can be used again in {}:{:02}",
+                            command,
+                            remaining / 60,
+                            remaining % 60
+                        );
+                        self.state
+                            .players
+                            .add_directed_server_chat_message(msg, player_id);
+                        return false;
+                    }
+                }
+            }
+            // Forget the players whose cooldown has run out
+            self.command_last_used.retain(|(name, _), last_used| {
+                name != command || now.duration_since(*last_used) < cooldown
+            });
+            self.command_last_used.insert(key, now);
+        }
+        true
+    }
+
     fn process_message<B: GameMode>(
         &mut self,
         msg: String,
         player_id: PlayerId,
         behaviour: &mut B,
     ) {
+        if self
+            .state
+            .players
+            .players
+            .get_player(player_id)
+            .is_some_and(|player| player.is_muted != MuteStatus::NotMuted)
+        {
+            // The mute may have expired
+            self.update_mute_status(player_id);
+        }
         if let Some(player) = self.state.players.players.get_player(player_id) {
             if msg.starts_with("/") {
                 let split: Vec<&str> = msg.splitn(2, " ").collect();
@@ -1255,17 +2728,12 @@ impl HQMServer {
                 if !self.is_muted {
                     match player.is_muted {
                         MuteStatus::NotMuted => {
-                            info!("{} ({}): {}", &player.player_name, player_id, &msg);
-                            self.state
-                                .players
-                                .add_user_chat_message(msg, player_id.index);
+                            self.send_chat_message(msg, player_id, behaviour);
                         }
                         MuteStatus::ShadowMuted => {
-                            self.state.players.add_directed_user_chat_message(
-                                msg,
-                                player_id,
-                                player_id.index,
-                            );
+                            self.state
+                                .players
+                                .add_directed_user_chat_message(msg, player_id, player_id);
                         }
                         MuteStatus::Muted => {}
                     }
@@ -1274,6 +2742,58 @@ impl HQMServer {
         }
     }
 
+    /// Passes a chat message through the chat filter and the game mode, and sends it to everyone.
+    fn send_chat_message<B: GameMode>(
+        &mut self,
+        msg: String,
+        player_id: PlayerId,
+        behaviour: &mut B,
+    ) {
+        let Some(player) = self.state.players.players.get_player(player_id) else {
+            return;
+        };
+        let player_name = player.player_name.clone();
+        let mut msg = if player.is_admin {
+            msg
+        } else {
+            let Some(chatter) = player.chatter() else {
+                return;
+            };
+            let now = self.now();
+            let filtered = self.chat_filter.filter(player_id, chatter, msg, now);
+            if let Some(warning) = filtered.warning {
+                self.state
+                    .players
+                    .add_directed_server_chat_message(warning, player_id);
+            }
+            if let Some(minutes) = filtered.mute_minutes {
+                if let Some(player) = self.state.players.players.get_player_mut(player_id) {
+                    player.is_muted = MuteStatus::Muted;
+                }
+                info!(
+                    "{} ({}) muted by the chat filter for {} min",
+                    player_name, player_id, minutes
+                );
+                let msg = format!(
+                    "{} muted for {} min by the chat filter",
+                    player_name, minutes
+                );
+                self.state.players.add_server_chat_message(msg);
+                self.store_mute(player_id, MuteStatus::Muted, Some(minutes));
+                return;
+            }
+            let Some(msg) = filtered.message else {
+                return;
+            };
+            msg
+        };
+        if !behaviour.before_chat_message(self.into(), player_id, &mut msg) {
+            return;
+        }
+        info!("{} ({}): {}", player_name, player_id, &msg);
+        self.state.players.add_user_chat_message(msg, player_id);
+    }
+
     fn player_exit<B: GameMode>(&mut self, addr: SocketAddr, behaviour: &mut B) {
         let player = self.state.players.players.find_player_by_addr(addr);
 
@@ -1282,8 +2802,10 @@ impl HQMServer {
             behaviour.before_player_exit(self.into(), player_id, ExitReason::Disconnected);
             self.remove_player(player_id, true);
             info!("{} ({}) exited server", player_name, player_id);
-            let msg = format!("{} exited", player_name);
-            self.state.players.add_server_chat_message(msg);
+            let window = self.config.join_message_window * 100;
+            self.state
+                .players
+                .add_join_exit_message(&player_name, false, window);
         }
     }
 
@@ -1303,6 +2825,8 @@ impl HQMServer {
     pub fn remove_player(&mut self, player_id: PlayerId, on_recording: bool) -> bool {
         let res = self.state.players.remove_player(player_id, on_recording);
         if res {
+            let now = self.now();
+            self.chat_filter.player_left(player_id, now);
             let admin_found = self
                 .state
                 .players
@@ -1311,7 +2835,7 @@ impl HQMServer {
                 .any(|(_, x)| x.is_admin);
 
             if !admin_found {
-                self.allow_join = true;
+                self.allow_join = !self.is_closed();
             }
         }
         res
@@ -1320,21 +2844,32 @@ impl HQMServer {
     fn game_step<B: GameMode>(&mut self, behaviour: &mut B) {
         self.state.replay.game_step = self.state.replay.game_step.wrapping_add(1);
 
+        self.check_stick_speeds();
         let events = self.simulate_step();
 
         let packets = self.get_packets();
 
         behaviour.after_tick(self.into(), &events);
+        self.update_scoreboard_display(behaviour);
+        self.send_rink_area();
 
-        if self.state.replay.history_length > 0 {
-            let new_replay_tick = ReplayTick {
-                game_step: self.state.replay.game_step,
-                packets: packets.clone(),
+        // Spectators may rewind even if the game mode doesn't use replays
+        let history_length = self
+            .state
+            .replay
+            .history_length
+            .max(self.config.rewind_max as usize * 100)
+            .max(self.config.clip_max as usize * 100);
+        if history_length > 0 {
+            let new_replay_tick = SavedTick {
+                tick: ReplayTick {
+                    game_step: self.state.replay.game_step,
+                    packets: packets.clone(),
+                },
+                scoreboard: self.state.display.values,
+                message_pos: self.state.players.recording_messages.len(),
             };
-            self.state
-                .replay
-                .saved_history
-                .truncate(self.state.replay.history_length - 1);
+            self.state.replay.saved_history.truncate(history_length - 1);
             self.state.replay.saved_history.push_front(new_replay_tick);
         } else {
             self.state.replay.saved_history.clear();
@@ -1343,11 +2878,59 @@ impl HQMServer {
         self.state.saved_packets.push_front(packets);
         self.state.packet = self.state.packet.wrapping_add(1);
 
-        if self.config.recording_enabled != ReplayRecording::Off
-            && behaviour.include_tick_in_recording((&*self).into())
-        {
+        let recorded = (self.config.recording_enabled != ReplayRecording::Off
+            || self.state.summary.league)
+            && behaviour.include_tick_in_recording((&*self).into());
+        if recorded {
             self.write_recording_tick();
         }
+
+        let state = &mut self.state;
+        let recording = if recorded {
+            Some((state.recording_ticks - 1, &mut state.summary.highlights))
+        } else {
+            None
+        };
+        state.highlights.update(
+            &state.players.players,
+            &state.pucks,
+            &self.rink,
+            &state.scoreboard,
+            &events,
+            &state.players.spawned_skaters,
+            recording,
+            &mut state.players.stats,
+        );
+        state.players.spawned_skaters.clear();
+    }
+
+    /// Vanilla clients show the full rink, so clients with extensions are told where the nets of a
+    /// smaller area are. Sent every five seconds, for clients that have enabled extensions later.
+    fn send_rink_area(&mut self) {
+        let area = self.config.rink_area;
+        if area != RinkArea::Full && self.state.packet.is_multiple_of(500) {
+            self.state
+                .players
+                .add_extension_message(&RinkAreaMessage(area, &self.rink));
+        }
+    }
+
+    fn update_scoreboard_display<B: GameMode>(&mut self, behaviour: &mut B) {
+        let mut display = std::mem::take(&mut self.state.display);
+        display.values = self.state.scoreboard;
+        display.custom.clear();
+        behaviour.scoreboard_display((&*self).into(), &mut display);
+
+        // Unchanged values are sent again now and then, for clients that have enabled extensions later
+        let state = &mut self.state;
+        let resend = state.packet.is_multiple_of(500) && !display.custom.is_empty();
+        if resend || display.custom != state.sent_custom_scoreboard {
+            state
+                .players
+                .add_extension_message(&CustomScoreboardMessage(&display.custom));
+            state.sent_custom_scoreboard.clone_from(&display.custom);
+        }
+        state.display = display;
     }
 
     fn get_packets(&self) -> [ObjectPacket; 32] {
@@ -1375,8 +2958,14 @@ impl HQMServer {
             .filter_map(|(player_id, player)| {
                 if let ServerPlayerData::NetworkPlayer { data } = &mut player.data {
                     data.inactivity += 1;
+                    if let Some((kick_timer, reason)) = &mut data.kick_timer {
+                        *kick_timer = kick_timer.saturating_sub(1);
+                        if *kick_timer == 0 {
+                            return Some((player_id, player.player_name.clone(), Some(*reason)));
+                        }
+                    }
                     if data.inactivity > 500 {
-                        Some((player_id, player.player_name.clone()))
+                        Some((player_id, player.player_name.clone(), None))
                     } else {
                         None
                     }
@@ -1385,7 +2974,17 @@ impl HQMServer {
                 }
             })
             .collect();
-        for (player_id, player_name) in inactive_players {
+        for (player_id, player_name, reason) in inactive_players {
+            if let Some(reason) = reason {
+                behaviour.before_player_exit(self.into(), player_id, reason);
+                self.remove_player(player_id, true);
+                if reason == ExitReason::ServerClosed {
+                    info!("{} ({}) removed at closing time", player_name, player_id);
+                } else {
+                    info!("{} ({}) removed after kick", player_name, player_id);
+                }
+                continue;
+            }
             behaviour.before_player_exit(self.into(), player_id, ExitReason::Timeout);
             self.remove_player(player_id, true);
             info!("{} ({}) timed out", player_name, player_id);
@@ -1394,87 +2993,601 @@ impl HQMServer {
         }
     }
 
+    /// Moves skaters that haven't changed their input for `afk_time` seconds of live play to the spectators.
+    ///
+    /// Play counts as live while the game clock is running after the warmup.
+    fn move_afk_players<B: GameMode>(&mut self, behaviour: &mut B) {
+        let afk_ticks = self.config.afk_time * 100;
+        if afk_ticks == 0 {
+            return;
+        }
+        let scoreboard = &self.state.scoreboard;
+        let live = scoreboard.period != 0 && scoreboard.time != self.afk_clock;
+        self.afk_clock = scoreboard.time;
+
+        let afk_players: smallvec::SmallVec<[_; 8]> = self
+            .state
+            .players
+            .players
+            .iter_players_mut()
+            .filter_map(|(player_id, player)| {
+                let has_skater = player.has_skater();
+                let ServerPlayerData::NetworkPlayer { data } = &mut player.data else {
+                    return None;
+                };
+                if !has_skater {
+                    data.afk_ticks = 0;
+                } else if live {
+                    data.afk_ticks += 1;
+                    if data.afk_ticks >= afk_ticks {
+                        data.afk_ticks = 0;
+                        return Some((player_id, player.player_name.clone()));
+                    }
+                }
+                None
+            })
+            .collect();
+        for (player_id, player_name) in afk_players {
+            self.state.players.move_to_spectator(player_id);
+            info!(
+                "{} ({}) was moved to spectators for being AFK",
+                player_name, player_id
+            );
+            let msg = format!("{} was moved to spectators for being AFK", player_name);
+            self.state.players.add_server_chat_message(msg);
+            behaviour.on_player_afk(self.into(), player_id);
+        }
+    }
+
+    /// Starts queued replays when their countdown has ended, and announces the countdown in chat.
+    fn update_replay_countdown(&mut self) {
+        let replay = &mut self.state.replay;
+        replay.view_buffer = self.config.replay_view_buffer as usize;
+        if let Some(seconds) = replay.update_countdown(self.config.replay_countdown * 100) {
+            let msg = format!("Replay in {}...", seconds);
+            self.state.players.add_server_chat_message(msg);
+        }
+    }
+
+    /// The current time, which is simulated in headless mode.
+    pub(crate) fn now(&self) -> DateTime<Utc> {
+        self.simulated_clock.unwrap_or_else(Utc::now)
+    }
+
+    /// Adds and removes bots to keep each team filled up to the `bots` setting, and updates the input of all bots.
+    fn update_bots<B: GameMode>(&mut self, behaviour: &mut B) {
+        // Forget bots that have been removed by someone else, like the game mode
+        let players = &self.state.players.players;
+        self.bots
+            .retain(|bot| players.get_player(bot.player_id).is_some());
+
+        let fill = self.config.bots;
+        if fill > 0 {
+            for team in [Team::Red, Team::Blue] {
+                let mut humans = 0;
+                for (_, player) in self.state.players.players.iter_players() {
+                    if !matches!(player.data, ServerPlayerData::NetworkPlayer { .. })
+                        || player.is_spectator
+                    {
+                        continue;
+                    }
+                    // Players who want to join the team take the place of a bot right away
+                    let joining = match team {
+                        Team::Red => player.input.join_red(),
+                        Team::Blue => player.input.join_blue(),
+                    };
+                    if player.team() == Some(team) || (player.team().is_none() && joining) {
+                        humans += 1;
+                    }
+                }
+                let wanted = fill.saturating_sub(humans);
+                let mut team_bots = self.bots.iter().filter(|bot| bot.team == team).count();
+                while team_bots > wanted {
+                    let Some(i) = self.bots.iter().rposition(|bot| bot.team == team) else {
+                        break;
+                    };
+                    let bot = self.bots.remove(i);
+                    behaviour.before_player_exit(
+                        self.into(),
+                        bot.player_id,
+                        ExitReason::BotRemoved,
+                    );
+                    self.remove_player(bot.player_id, true);
+                    team_bots -= 1;
+                }
+                while team_bots < wanted {
+                    let name = (1..)
+                        .map(|i| format!("Bot {}", i))
+                        .find(|name| {
+                            !self
+                                .state
+                                .players
+                                .players
+                                .iter_players()
+                                .any(|(_, p)| &*p.player_name == name.as_str())
+                        })
+                        .unwrap();
+                    let Some(player_id) = self.state.players.add_bot(&name) else {
+                        break;
+                    };
+                    self.bots.push(SkaterBot::new(player_id, team));
+                    team_bots += 1;
+                }
+            }
+        }
+
+        let mut bots = std::mem::take(&mut self.bots);
+        for bot in bots.iter_mut() {
+            bot.update(self.into());
+        }
+        self.bots = bots;
+    }
+
     pub(crate) async fn tick<B: GameMode>(
         &mut self,
         socket: &UdpSocket,
         behaviour: &mut B,
         write_buf: &mut BytesMut,
     ) {
-        if self.real_player_count() != 0 {
+        let active = self.real_player_count() != 0;
+        let res = tokio::task::block_in_place(|| self.simulate_tick(behaviour, active));
+        if let Some((game_step, forced_view)) = res {
+            self.send_tick(game_step, forced_view, Some(socket), write_buf)
+                .await;
+        }
+        self.update_shutdown();
+    }
+
+    /// Starts shutting the server down in `seconds`, or right away if a shutdown has already been started.
+    pub(crate) fn start_shutdown(&mut self, seconds: u32) {
+        if self.shutdown_timer.is_some() {
+            self.shutdown_timer = Some(0);
+            return;
+        }
+        info!("Server shutting down in {} seconds", seconds);
+        self.shutdown_timer = Some(seconds * 100);
+        self.announce_shutdown(seconds);
+    }
+
+    fn announce_shutdown(&mut self, seconds: u32) {
+        let msg = if seconds == 0 {
+            Cow::Borrowed("Server is shutting down")
+        } else {
+            format!("Server shutting down in {} seconds", seconds).into()
+        };
+        self.state.players.add_server_chat_message(msg);
+    }
+
+    fn update_shutdown(&mut self) {
+        let Some(timer) = &mut self.shutdown_timer else {
+            return;
+        };
+        *timer = timer.saturating_sub(1);
+        if *timer % 100 == 0 {
+            let seconds = *timer / 100;
+            if matches!(seconds, 30 | 10 | 5) {
+                self.announce_shutdown(seconds);
+            }
+        }
+    }
+
+    /// Lets the game mode handle the exit of every connected player, and tells the clients to leave the server.
+    /// The players are only removed from the clients' view, the next update has to be sent for them to leave.
+    fn disconnect_all<B: GameMode>(&mut self, behaviour: &mut B) {
+        let player_ids: Vec<_> = self
+            .state
+            .players
+            .players
+            .iter_players()
+            .filter(|(_, player)| matches!(player.data, ServerPlayerData::NetworkPlayer { .. }))
+            .map(|(player_id, _)| player_id)
+            .collect();
+        for player_id in player_ids {
+            behaviour.before_player_exit(self.into(), player_id, ExitReason::Disconnected);
+        }
+        self.state.players.announce_exit_of_all();
+    }
+
+    /// Sends the clients a last update that makes them leave, saves the recording of the current game and waits
+    /// for everything that is being saved to be written.
+    async fn shut_down<B: GameMode>(
+        &mut self,
+        behaviour: &mut B,
+        socket: &UdpSocket,
+        write_buf: &mut BytesMut,
+    ) {
+        info!("Server shutting down");
+        self.disconnect_all(behaviour);
+        let game_step = self.state.replay.game_step;
+        self.send_tick(game_step, None, Some(socket), write_buf)
+            .await;
+        self.save_game_recording();
+        let pending = self
+            .save_recording
+            .take_pending_saves()
+            .into_iter()
+            .chain(self.ban.take_pending_saves())
+            .chain(self.mutes.take_pending_saves())
+            .chain(behaviour.take_pending_saves());
+        for handle in pending {
+            let _ = handle.await;
+        }
+        info!("Server shut down");
+    }
+
+    /// Runs the game mode and the physics for one tick, if the game is active.
+    ///
+    /// Returns the game step and the forced view that should be sent to the clients.
+    fn simulate_tick<B: GameMode>(
+        &mut self,
+        behaviour: &mut B,
+        active: bool,
+    ) -> Option<(u32, Option<PlayerId>)> {
+        if let Some(clock) = &mut self.simulated_clock {
+            *clock += chrono::Duration::milliseconds(10);
+        }
+        self.state
+            .players
+            .update_join_exit_messages(self.config.join_message_window * 100);
+        self.retry_pending_joins(behaviour);
+        if active {
+            if self.abandoned_ticks > 0 {
+                info!("Game {} resumed", self.game_id);
+                self.abandoned_ticks = 0;
+            }
             if !self.has_current_game_been_active {
-                self.start_time = Utc::now();
+                self.start_time = self.now();
                 self.has_current_game_been_active = true;
                 behaviour.game_started(self.into());
-                info!("New game {} started", self.game_id);
+                info!(
+                    "New game {} started, random seed {}",
+                    self.game_id,
+                    self.rng.seed()
+                );
             }
 
-            let (game_step, forced_view) = tokio::task::block_in_place(|| {
-                self.remove_inactive_players(behaviour);
+            self.remove_inactive_players(behaviour);
+            self.move_afk_players(behaviour);
+            self.update_bots(behaviour);
+            self.update_vote(behaviour);
+            self.update_maintenance();
 
-                behaviour.before_tick(self.into());
+            behaviour.before_tick(self.into());
 
-                let has_replay_data = self.state.replay.check_replay();
+            self.update_replay_countdown();
 
-                let res = if let Some((forced_view, tick)) = has_replay_data {
-                    let forced_view = forced_view.map(|x| x.index);
-                    let game_step = tick.game_step;
-                    let packets = tick.packets;
+            let has_replay_data = self.state.replay.check_replay();
 
-                    self.state.saved_packets.push_front(packets);
+            let res = if let Some(QueuedReplayTick {
+                force_view: forced_view,
+                tick,
+                ..
+            }) = has_replay_data
+            {
+                let game_step = tick.game_step;
+                let packets = tick.packets;
 
-                    self.state.packet = self.state.packet.wrapping_add(1);
-                    (game_step, forced_view)
-                } else {
-                    self.game_step(behaviour);
-                    (self.state.replay.game_step, None)
-                };
+                self.state.saved_packets.push_front(packets);
+
+                self.state.packet = self.state.packet.wrapping_add(1);
+                self.update_scoreboard_display(behaviour);
+                (game_step, forced_view)
+            } else {
+                self.game_step(behaviour);
+                (self.state.replay.game_step, None)
+            };
+
+            self.state.saved_pings.push_front(Instant::now());
+            self.check_consistency();
+
+            Some(res)
+        } else {
+            // No tick is passed to the observers, so the messages would pile up until someone joins
+            self.state.players.tick_messages.clear();
+            if self.has_current_game_been_active {
+                self.update_abandoned_game(behaviour);
+            }
+            None
+        }
+    }
+
+    /// Handles a game that nobody is playing, according to the abandon policy.
+    fn update_abandoned_game<B: GameMode>(&mut self, behaviour: &mut B) {
+        match self.config.abandon_policy {
+            AbandonPolicy::Reset => {
+                info!("Game {} abandoned", self.game_id);
+                self.new_game(behaviour.get_initial_game_values());
+            }
+            AbandonPolicy::Keep(ticks) => {
+                if self.abandoned_ticks == 0 {
+                    info!(
+                        "Game {} abandoned, keeping it for {} seconds",
+                        self.game_id,
+                        ticks / 100
+                    );
+                }
+                self.abandoned_ticks += 1;
+                if self.abandoned_ticks < ticks {
+                    return;
+                }
+                info!("Game {} was not resumed", self.game_id);
+                self.new_game(behaviour.get_initial_game_values());
+            }
+            AbandonPolicy::Warmup => {
+                info!(
+                    "Game {} abandoned, holding the next game in warmup",
+                    self.game_id
+                );
+                self.new_game(behaviour.get_initial_game_values());
+                behaviour.hold_warmup(self.into());
+            }
+            AbandonPolicy::Shutdown => {
+                if self.shutdown_timer != Some(0) {
+                    info!("Game {} abandoned, shutting down", self.game_id);
+                    // The recording is saved when the server shuts down
+                    self.shutdown_timer = Some(0);
+                    self.abandon_shutdown = true;
+                }
+                return;
+            }
+        }
+        self.allow_join = !self.is_closed();
+    }
+
+    fn check_consistency(&mut self) {
+        let Some(consistency) = &mut self.consistency else {
+            return;
+        };
+        let state = &self.state;
+        let counters = Counters {
+            game_id: self.game_id,
+            packet: state.packet,
+            game_step: state.replay.game_step,
+            recording_ticks: state.recording_ticks,
+            recording_msg_pos: state.recording_msg_pos,
+            recording_messages: state.players.recording_messages.len(),
+        };
+        consistency.tick(
+            &state.players.players,
+            state.players.puck_slots,
+            &state.pucks,
+            counters,
+        );
+    }
+
+    /// Passes the tick to the observers, and sends it to the clients if there is a socket.
+    async fn send_tick(
+        &mut self,
+        game_step: u32,
+        forced_view: Option<PlayerId>,
+        socket: Option<&UdpSocket>,
+        write_buf: &mut BytesMut,
+    ) {
+        let (payload, players) = self.prepare_tick(game_step, forced_view);
+        if let Some(socket) = socket {
+            send_updates(&payload, players, socket, write_buf).await;
+        }
+
+        self.state.players.tick_messages.clear();
+    }
+
+    /// Runs one tick like [`HQMServer::tick`], but returns the update packets of the clients instead of sending them.
+    pub(crate) fn tick_to_updates<B: GameMode>(
+        &mut self,
+        behaviour: &mut B,
+        write_buf: &mut BytesMut,
+    ) -> Vec<(SocketAddr, Bytes)> {
+        let active = self.real_player_count() != 0;
+        match self.simulate_tick(behaviour, active) {
+            Some((game_step, forced_view)) => self.updates(game_step, forced_view, write_buf),
+            None => vec![],
+        }
+    }
+
+    /// Tells the clients to leave like [`HQMServer::shut_down`], but returns the last update packets of the clients
+    /// instead of sending them.
+    pub(crate) fn shut_down_to_updates<B: GameMode>(
+        &mut self,
+        behaviour: &mut B,
+        write_buf: &mut BytesMut,
+    ) -> Vec<(SocketAddr, Bytes)> {
+        self.disconnect_all(behaviour);
+        let game_step = self.state.replay.game_step;
+        self.updates(game_step, None, write_buf)
+    }
+
+    fn updates(
+        &mut self,
+        game_step: u32,
+        forced_view: Option<PlayerId>,
+        write_buf: &mut BytesMut,
+    ) -> Vec<(SocketAddr, Bytes)> {
+        let mut updates = vec![];
+        let (payload, players) = self.prepare_tick(game_step, forced_view);
+        for (player_id, player) in players.iter_players() {
+            if let ServerPlayerData::NetworkPlayer { data } = &player.data {
+                write_buf.clear();
+                let mut writer = HQMMessageWriter::new(write_buf);
+                payload.write_update(&mut writer, player_id, data);
+                updates.push((data.addr, Bytes::copy_from_slice(write_buf)));
+            }
+        }
+        self.state.players.tick_messages.clear();
+        updates
+    }
+
+    /// Updates the replays for the tick and passes it to the observers.
+    /// Returns what should be sent to the clients, and the players.
+    fn prepare_tick(
+        &mut self,
+        game_step: u32,
+        forced_view: Option<PlayerId>,
+    ) -> (TickPayload<'_>, &[ServerStatePlayerItem]) {
+        self.update_personal_replays();
+        self.update_replay_skips();
+        self.update_drifting_clients();
+        let replay = &self.state.replay;
+        let payload = TickPayload {
+            game_id: self.game_id,
+            game_step,
+            scoreboard: &self.state.display.values,
+            custom_scoreboard: &self.state.display.custom,
+            packets: &self.state.saved_packets,
+            current_packet: self.state.packet,
+            forced_view,
+            messages: &self.state.players.tick_messages,
+            message_log: &self.state.players.message_log,
+            skipping: &replay.skipping,
+            live_tick: replay
+                .live_tick()
+                .filter(|_| !replay.skipping.is_empty())
+                .map(|x| &x.packets),
+        };
+
+        for observer in self.observers.iter_mut() {
+            observer.tick(&ObservedTick { payload: &payload });
+        }
+
+        (payload, &self.state.players.players)
+    }
+
+    fn recording_version(&self) -> u32 {
+        // Version 1 recordings may contain annotation messages
+        if self.config.recording_annotations {
+            1
+        } else {
+            0
+        }
+    }
 
-                self.state.saved_pings.push_front(Instant::now());
+    /// Writes up to `ticks` of the most recent ticks in the tick history as a standalone recording.
+    /// Returns the recording and the number of ticks in it, or [`None`] if the history is empty.
+    pub(crate) fn clip_recording_data(&self, ticks: usize) -> Option<(Bytes, usize)> {
+        let history = &self.state.replay.saved_history;
+        let ticks = ticks.min(history.len());
+        let first = history.get(ticks.checked_sub(1)?)?;
+        let messages = &self.state.players.recording_messages;
+
+        // The clip starts with the players that were in the game when it starts
+        let mut players = vec![None; 64];
+        for message in &messages[..first.message_pos] {
+            if let HQMMessage::PlayerUpdate { player_id, data } = message.as_ref() {
+                players[player_id.index.0] = data.is_some().then_some(message.as_ref());
+            }
+        }
+        let mut clip_messages = players.into_iter().flatten().collect::<Vec<_>>();
+
+        let mut data = BytesMut::new();
+        let mut written_messages = 0;
+        let mut message_pos = first.message_pos;
+        let mut previous = None;
+        for (i, saved) in history.iter().take(ticks).rev().enumerate() {
+            clip_messages.extend(
+                messages[message_pos..saved.message_pos]
+                    .iter()
+                    .map(Rc::as_ref),
+            );
+            message_pos = saved.message_pos;
 
-                res
-            });
+            let mut writer = HQMMessageWriter::new(&mut data);
+            write_recording_scoreboard(&mut writer, &saved.scoreboard);
+            let known_packet = if previous.is_some() {
+                i as u32 - 1
+            } else {
+                u32::MAX
+            };
+            write_object_packets(
+                &mut writer,
+                &saved.tick.packets,
+                previous,
+                i as u32,
+                known_packet,
+            );
+            previous = Some(&saved.tick.packets);
 
-            send_updates(
-                self.game_id,
-                &self.state.saved_packets,
-                game_step,
-                &self.state.scoreboard,
-                self.state.packet,
-                &self.state.players.players,
-                socket,
-                forced_view,
-                write_buf,
-            )
-            .await;
-        } else if self.has_current_game_been_active {
-            info!("Game {} abandoned", self.game_id);
-            self.new_game(behaviour.get_initial_game_values());
-            self.allow_join = true;
+            writer.write_bits(16, (clip_messages.len() - written_messages) as u32);
+            writer.write_bits(16, written_messages as u32);
+            for message in &clip_messages[written_messages..] {
+                write_message(&mut writer, message);
+            }
+            written_messages = clip_messages.len();
+            writer.recording_fix();
         }
+
+        let mut recording_data = BytesMut::with_capacity(data.len() + 8);
+        recording_data.put_u32_le(self.recording_version());
+        recording_data.put_u32_le(data.len() as u32);
+        recording_data.put_slice(&data);
+        Some((recording_data.freeze(), ticks))
     }
 
-    fn save_recording(&mut self, old_recording_data: &[u8]) {
+    fn save_recording(&mut self, old_recording_data: &[u8], old_recording_inputs: &[u8]) {
         let size = old_recording_data.len();
-        let mut recording_data = BytesMut::with_capacity(size + 8);
-        recording_data.put_u32_le(0u32);
+        let mut recording_data =
+            BytesMut::with_capacity(size + 8 + old_recording_inputs.len() + 12);
+        recording_data.put_u32_le(self.recording_version());
         recording_data.put_u32_le(size as u32);
         recording_data.put_slice(old_recording_data);
+        if self.config.recording_inputs {
+            recording_data.put_slice(INPUT_CHUNK_TAG);
+            recording_data.put_u32_le(INPUT_CHUNK_VERSION);
+            recording_data.put_u32_le(old_recording_inputs.len() as u32);
+            recording_data.put_slice(old_recording_inputs);
+        }
         let recording_data = recording_data.freeze();
-        self.save_recording
-            .save_recording_data(&self.config, recording_data, self.start_time);
+        self.state.summary.teams = self.config.team_names.clone();
+        // Highlights at the very end may reach past the end of the recording
+        let recording_ticks = self.state.recording_ticks;
+        for highlight in self.state.summary.highlights.iter_mut() {
+            highlight.end = highlight.end.min(recording_ticks.saturating_sub(1));
+        }
+        self.save_recording.save_recording_data(
+            &self.config,
+            recording_data,
+            self.start_time,
+            &self.state.summary,
+        );
+    }
+    /// Saves the recording of the current game, if recordings are enabled, and clears it.
+    fn save_game_recording(&mut self) {
+        let old_recording_data = std::mem::replace(&mut self.state.recording_data, BytesMut::new());
+        let old_recording_inputs =
+            std::mem::replace(&mut self.state.recording_inputs, BytesMut::new());
+
+        // League games are always saved
+        if (self.config.recording_enabled == ReplayRecording::On || self.state.summary.league)
+            && !old_recording_data.is_empty()
+        {
+            self.save_recording(&old_recording_data, &old_recording_inputs);
+        }
     }
+
     pub fn new_game(&mut self, v: InitialGameValues) {
         self.game_id += 1;
 
         self.has_current_game_been_active = false;
+        self.abandoned_ticks = 0;
 
-        let old_recording_data = std::mem::replace(&mut self.state.recording_data, BytesMut::new());
+        self.save_game_recording();
+
+        self.state.new_game(v.puck_slots, v.values);
+        self.puck_history.clear();
+        self.rink.obstacles.clear();
+        self.rng = GameRng::new(rng::game_seed(self.random_seed, self.game_id));
+        self.state.summary.random_seed = self.rng.seed();
 
-        if self.config.recording_enabled == ReplayRecording::On && !old_recording_data.is_empty() {
-            self.save_recording(&old_recording_data);
+        for observer in self.observers.iter_mut() {
+            observer.new_game(self.game_id);
         }
+    }
 
+    /// Sets up the current game again with `v`, without starting a new game.
+    ///
+    /// Meant for a game that was just started, before anything has happened in it.
+    pub(crate) fn replace_game_values(&mut self, v: InitialGameValues) {
         self.state.new_game(v.puck_slots, v.values);
+        self.puck_history.clear();
+        self.rink.obstacles.clear();
     }
 
     fn write_recording_tick(&mut self) {
@@ -1489,21 +3602,7 @@ impl HQMServer {
             + remaining_messages * 66, // Chat message can be up to 66 bytes each
         );
         let mut writer = HQMMessageWriter::new(&mut self.state.recording_data);
-
-        writer.write_byte_aligned(5);
-        writer.write_bits(
-            1,
-            match self.state.scoreboard.game_over {
-                true => 1,
-                false => 0,
-            },
-        );
-        writer.write_bits(8, self.state.scoreboard.red_score);
-        writer.write_bits(8, self.state.scoreboard.blue_score);
-        writer.write_bits(16, self.state.scoreboard.time);
-
-        writer.write_bits(16, self.state.scoreboard.goal_message_timer);
-        writer.write_bits(8, self.state.scoreboard.period); // 8.1
+        write_recording_scoreboard(&mut writer, &self.state.display.values);
 
         let packets = &self.state.saved_packets;
 
@@ -1523,6 +3622,47 @@ impl HQMServer {
         }
         self.state.recording_msg_pos = self.state.players.recording_messages.len();
         writer.recording_fix();
+        self.state.recording_ticks += 1;
+
+        if self.config.recording_inputs {
+            self.write_recording_inputs();
+        }
+    }
+
+    /// Writes the input that each skater had in the last step to the input chunk of the recording.
+    ///
+    /// For each recorded tick, the chunk has the number of skaters as one byte, and for each skater the
+    /// player index and object index as one byte each, the stick angle, turn, forward/backward movement,
+    /// stick X and Y, head and body rotation as 32-bit floats, and the keys as a 32-bit integer,
+    /// all little-endian.
+    fn write_recording_inputs(&mut self) {
+        let state = &mut self.state;
+        let skaters = state
+            .players
+            .players
+            .iter_players()
+            .filter_map(|(player_id, player)| {
+                player
+                    .object
+                    .as_ref()
+                    .map(|(object_index, _, _)| (player_id, *object_index, &player.input))
+            })
+            .collect::<ArrayVec<_, 32>>();
+        let out = &mut state.recording_inputs;
+        out.reserve(1 + skaters.len() * 34);
+        out.put_u8(skaters.len() as u8);
+        for (player_id, object_index, input) in skaters {
+            out.put_u8(player_id.index.0 as u8);
+            out.put_u8(object_index as u8);
+            out.put_f32_le(input.stick_angle);
+            out.put_f32_le(input.turn);
+            out.put_f32_le(input.fwbw);
+            out.put_f32_le(input.stick.x);
+            out.put_f32_le(input.stick.y);
+            out.put_f32_le(input.head_rot);
+            out.put_f32_le(input.body_rot);
+            out.put_u32_le(input.keys);
+        }
     }
 }
 
@@ -1532,92 +3672,178 @@ struct ReplayTick {
     packets: [ObjectPacket; 32],
 }
 
+/// A tick in the tick history, with what is needed to write it to a clip.
+#[derive(Clone, Debug)]
+struct SavedTick {
+    tick: ReplayTick,
+    scoreboard: ScoreboardValues,
+    /// Number of recording messages at the end of the tick.
+    message_pos: usize,
+}
+
+/// A join that waits for a ban check or authentication.
+struct PendingJoin {
+    version: u32,
+    name: String,
+    signed: bool,
+    ticks_left: u32,
+}
+
+#[derive(Clone, Debug)]
+struct QueuedReplayTick {
+    force_view: Option<PlayerId>,
+    /// Players that see the latest game tick instead of this tick.
+    skip: Rc<[PlayerId]>,
+    tick: ReplayTick,
+}
+
+/// Everything that is sent to the clients during one tick, apart from the per-client state.
+pub(crate) struct TickPayload<'a> {
+    pub(crate) game_id: u32,
+    pub(crate) game_step: u32,
+    pub(crate) scoreboard: &'a ScoreboardValues,
+    pub(crate) custom_scoreboard: &'a [CustomScoreboardValue],
+    pub(crate) packets: &'a ArrayDeque<[ObjectPacket; 32], 192, Wrapping>,
+    pub(crate) current_packet: u32,
+    pub(crate) forced_view: Option<PlayerId>,
+    pub(crate) messages: &'a [Rc<HQMMessage>],
+    pub(crate) message_log: &'a MessageLog,
+    /// Players that see `live_tick` instead of the replay.
+    pub(crate) skipping: &'a [PlayerId],
+    pub(crate) live_tick: Option<&'a [ObjectPacket; 32]>,
+}
+
+impl TickPayload<'_> {
+    fn write_update(
+        &self,
+        writer: &mut HQMMessageWriter,
+        player_id: PlayerId,
+        data: &NetworkPlayerData,
+    ) {
+        let value = self.scoreboard;
+        let live_tick = self
+            .live_tick
+            .filter(|_| self.skipping.contains(&player_id));
+        writer.write_bytes_aligned(GAME_HEADER);
+        if data.game_id != self.game_id {
+            writer.write_byte_aligned(6);
+            writer.write_u32_aligned(self.game_id);
+            return;
+        }
+        writer.write_byte_aligned(5);
+        writer.write_u32_aligned(self.game_id);
+        writer.write_u32_aligned(self.game_step);
+        writer.write_bits(
+            1,
+            match value.game_over {
+                true => 1,
+                false => 0,
+            },
+        );
+        writer.write_bits(8, value.red_score);
+        writer.write_bits(8, value.blue_score);
+        writer.write_bits(16, value.time);
+
+        writer.write_bits(16, value.goal_message_timer);
+        writer.write_bits(8, value.period);
+        let view = self
+            .forced_view
+            .filter(|_| live_tick.is_none())
+            .map_or(data.view_player_index, |x| x.index)
+            .0 as u32;
+        writer.write_bits(8, view);
+
+        // if using a non-cryptic version, send ping
+        if data.client_version.has_ping() {
+            writer.write_u32_aligned(data.deltatime);
+        }
+
+        // if baba's second version or above, send rules
+        if data.client_version.has_rules() {
+            let num = match value.rules_state {
+                RulesState::Regular {
+                    offside_warning,
+                    icing_warning,
+                } => {
+                    let mut res = 0;
+                    if offside_warning {
+                        res |= 1;
+                    }
+                    if icing_warning {
+                        res |= 2;
+                    }
+                    res
+                }
+                RulesState::Offside => 4,
+                RulesState::Icing => 8,
+            };
+            writer.write_u32_aligned(num);
+        }
+
+        if let Some(packets) = data
+            .personal_replay
+            .as_ref()
+            .and_then(|x| x.current())
+            .map(|x| &x.packets)
+            .or(live_tick)
+        {
+            // Never delta compressed, the client would otherwise mix up replay and live objects
+            write_object_packets(writer, packets, None, self.current_packet, u32::MAX);
+        } else if data.full_update_until.is_some() {
+            write_objects(writer, self.packets, self.current_packet, u32::MAX);
+        } else {
+            write_objects(writer, self.packets, self.current_packet, data.known_packet);
+        }
+
+        let (start, remaining_messages) = if data.known_msgpos > data.messages.len() {
+            (data.messages.len(), 0)
+        } else {
+            (
+                data.known_msgpos,
+                min(data.messages.len() - data.known_msgpos, 15),
+            )
+        };
+
+        writer.write_bits(4, remaining_messages as u32);
+        writer.write_bits(16, start as u32);
+
+        for message in data
+            .messages
+            .get(self.message_log, start, remaining_messages)
+        {
+            write_message(writer, message);
+        }
+    }
+}
+
+fn write_recording_scoreboard(writer: &mut HQMMessageWriter, values: &ScoreboardValues) {
+    writer.write_byte_aligned(5);
+    writer.write_bits(
+        1,
+        match values.game_over {
+            true => 1,
+            false => 0,
+        },
+    );
+    writer.write_bits(8, values.red_score);
+    writer.write_bits(8, values.blue_score);
+    writer.write_bits(16, values.time);
+
+    writer.write_bits(16, values.goal_message_timer);
+    writer.write_bits(8, values.period); // 8.1
+}
+
 async fn send_updates(
-    game_id: u32,
-    packets: &ArrayDeque<[ObjectPacket; 32], 192, Wrapping>,
-    game_step: u32,
-    value: &ScoreboardValues,
-    current_packet: u32,
+    payload: &TickPayload<'_>,
     players: &[ServerStatePlayerItem],
     socket: &UdpSocket,
-    force_view: Option<PlayerIndex>,
     write_buf: &mut BytesMut,
 ) {
-    for (_, player) in players.iter_players() {
+    for (player_id, player) in players.iter_players() {
         if let ServerPlayerData::NetworkPlayer { data } = &player.data {
             write_buf.clear();
             let mut writer = HQMMessageWriter::new(write_buf);
-
-            if data.game_id != game_id {
-                writer.write_bytes_aligned(GAME_HEADER);
-                writer.write_byte_aligned(6);
-                writer.write_u32_aligned(game_id);
-            } else {
-                writer.write_bytes_aligned(GAME_HEADER);
-                writer.write_byte_aligned(5);
-                writer.write_u32_aligned(game_id);
-                writer.write_u32_aligned(game_step);
-                writer.write_bits(
-                    1,
-                    match value.game_over {
-                        true => 1,
-                        false => 0,
-                    },
-                );
-                writer.write_bits(8, value.red_score);
-                writer.write_bits(8, value.blue_score);
-                writer.write_bits(16, value.time);
-
-                writer.write_bits(16, value.goal_message_timer);
-                writer.write_bits(8, value.period);
-                let view = force_view.unwrap_or(data.view_player_index).0 as u32;
-                writer.write_bits(8, view);
-
-                // if using a non-cryptic version, send ping
-                if data.client_version.has_ping() {
-                    writer.write_u32_aligned(data.deltatime);
-                }
-
-                // if baba's second version or above, send rules
-                if data.client_version.has_rules() {
-                    let num = match value.rules_state {
-                        RulesState::Regular {
-                            offside_warning,
-                            icing_warning,
-                        } => {
-                            let mut res = 0;
-                            if offside_warning {
-                                res |= 1;
-                            }
-                            if icing_warning {
-                                res |= 2;
-                            }
-                            res
-                        }
-                        RulesState::Offside => 4,
-                        RulesState::Icing => 8,
-                    };
-                    writer.write_u32_aligned(num);
-                }
-
-                write_objects(&mut writer, packets, current_packet, data.known_packet);
-
-                let (start, remaining_messages) = if data.known_msgpos > data.messages.len() {
-                    (data.messages.len(), 0)
-                } else {
-                    (
-                        data.known_msgpos,
-                        min(data.messages.len() - data.known_msgpos, 15),
-                    )
-                };
-
-                writer.write_bits(4, remaining_messages as u32);
-                writer.write_bits(16, start as u32);
-
-                for message in &data.messages[start..start + remaining_messages] {
-                    write_message(&mut writer, Rc::as_ref(message));
-                }
-            }
+            payload.write_update(&mut writer, player_id, data);
 
             let slice: &[u8] = &write_buf;
             let _ = socket.send_to(slice, data.addr).await;
@@ -1625,7 +3851,7 @@ async fn send_updates(
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum MuteStatus {
     NotMuted,
     ShadowMuted,
@@ -1643,7 +3869,65 @@ pub(crate) struct NetworkPlayerData {
     last_ping: Box<ArrayDeque<f32, 100, Wrapping>>,
     pub(crate) view_player_index: PlayerIndex,
     pub game_id: u32,
-    pub(crate) messages: Vec<Rc<HQMMessage>>,
+    pub(crate) messages: PlayerMessages,
+    /// Replay that only this player sees, started with /rewind.
+    personal_replay: Option<PersonalReplay>,
+    /// After a personal replay or a resync, objects are sent without delta compression until the client has
+    /// acknowledged this packet, as older packets known by the client may contain replay objects.
+    full_update_until: Option<u32>,
+    integrity: Option<Box<PacketIntegrity>>,
+    /// Identity verified by the auth provider, if any.
+    pub(crate) identity: Option<PlayerIdentity>,
+    /// Ticks until a kicked or banned player is removed, and why. Until then, the input and chat of the player
+    /// are ignored, so that the player can read why they are removed.
+    pub(crate) kick_timer: Option<(u32, ExitReason)>,
+    /// Ticks of live play the player has spent on the ice without changing their input.
+    afk_ticks: u32,
+    /// Ticks the acknowledged packets of the client have been further behind than its ping explains.
+    drift_ticks: u8,
+}
+
+impl NetworkPlayerData {
+    /// Stops the personal replay, if there is one. `packet` is the next packet that will be sent.
+    fn stop_personal_replay(&mut self, packet: u32) -> bool {
+        if self.personal_replay.take().is_some() {
+            self.full_update_until = Some(packet);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct PersonalReplay {
+    ticks: VecDeque<ReplayTick>,
+    /// How many ticks each replay tick is shown, more than 1 for slow motion.
+    frame_ticks: u32,
+    shown: u32,
+}
+
+impl PersonalReplay {
+    fn new(ticks: VecDeque<ReplayTick>, frame_ticks: u32) -> Self {
+        PersonalReplay {
+            ticks,
+            frame_ticks,
+            shown: 0,
+        }
+    }
+
+    /// Moves to the tick that should be shown next. Returns false if the replay is over.
+    fn advance(&mut self) -> bool {
+        if self.shown >= self.frame_ticks {
+            self.ticks.pop_front();
+            self.shown = 0;
+        }
+        self.shown += 1;
+        !self.ticks.is_empty()
+    }
+
+    fn current(&self) -> Option<&ReplayTick> {
+        self.ticks.front()
+    }
 }
 
 pub(crate) enum ServerPlayerData {
@@ -1653,27 +3937,44 @@ pub(crate) enum ServerPlayerData {
 
 pub(crate) struct HQMServerPlayer {
     pub player_name: Rc<str>,
-    player_name_red: Rc<str>,
-    player_name_blue: Rc<str>,
     pub(crate) object: Option<(usize, SkaterObject, Team)>,
     pub data: ServerPlayerData,
     pub is_admin: bool,
     pub is_muted: MuteStatus,
     pub preferred_hand: SkaterHand,
     pub input: PlayerInput,
+    /// Language of directed server messages, chosen with /lang.
+    pub language: Language,
+    /// True if the player only wants to watch, chosen with /spec. Such players are never put in a team.
+    pub is_spectator: bool,
+    /// True if the server wants the skater off the ice, for example during maintenance. The game mode
+    /// moves the player to the spectators, like when the player asks to spectate.
+    pub(crate) leave_ice: bool,
+    /// Number of input packets received from the client, wrapping around. Always 0 for bots.
+    pub(crate) input_packets: u32,
 }
 
 impl HQMServerPlayer {
+    /// Who the chat messages and commands of the player count against, or [`None`] if it's not a network player.
+    pub(crate) fn chatter(&self) -> Option<Chatter> {
+        match &self.data {
+            ServerPlayerData::NetworkPlayer { data } => Some(match &data.identity {
+                Some(identity) => Chatter::Account(identity.account.clone()),
+                None => Chatter::Ip(data.addr.ip()),
+            }),
+            _ => None,
+        }
+    }
+
     pub fn new_network_player(
         player_index: PlayerIndex,
         player_name: &str,
         addr: SocketAddr,
-        global_messages: &[Rc<HQMMessage>],
+        language: Language,
+        messages: PlayerMessages,
     ) -> Self {
         HQMServerPlayer {
             player_name: player_name.into(),
-            player_name_red: format!("[Red] {}", player_name).into(),
-            player_name_blue: format!("[Blue] {}", player_name).into(),
             object: None,
             data: ServerPlayerData::NetworkPlayer {
                 data: NetworkPlayerData {
@@ -1688,27 +3989,40 @@ impl HQMServerPlayer {
                     last_ping: Box::new(ArrayDeque::new()),
                     view_player_index: player_index,
                     game_id: u32::MAX,
-                    messages: global_messages.into_iter().cloned().collect(),
+                    messages,
+                    personal_replay: None,
+                    full_update_until: None,
+                    integrity: None,
+                    identity: None,
+                    kick_timer: None,
+                    afk_ticks: 0,
+                    drift_ticks: 0,
                 },
             },
             is_admin: false,
             input: Default::default(),
             is_muted: MuteStatus::NotMuted,
             preferred_hand: SkaterHand::Right,
+            language,
+            is_spectator: false,
+            leave_ice: false,
+            input_packets: 0,
         }
     }
 
     pub fn new_bot(player_name: &str) -> Self {
         HQMServerPlayer {
             player_name: player_name.into(),
-            player_name_red: format!("[Red] {}", player_name).into(),
-            player_name_blue: format!("[Blue] {}", player_name).into(),
             object: None,
             data: ServerPlayerData::Bot {},
             is_admin: false,
             input: Default::default(),
             is_muted: MuteStatus::NotMuted,
             preferred_hand: SkaterHand::Right,
+            language: Language::default(),
+            is_spectator: false,
+            leave_ice: false,
+            input_packets: 0,
         }
     }
 
@@ -1719,12 +4033,14 @@ impl HQMServerPlayer {
             data.known_packet = u32::MAX;
             data.messages.clear();
             data.view_player_index = player_index;
+            data.personal_replay = None;
+            data.full_update_until = None;
         }
     }
 
-    fn get_update_message(&self, player_index: PlayerIndex) -> HQMMessage {
+    fn get_update_message(&self, player_id: PlayerId) -> HQMMessage {
         HQMMessage::PlayerUpdate {
-            player_index,
+            player_id,
             data: Some(PlayerUpdateData {
                 player_name: self.player_name.clone(),
                 object: self
@@ -1746,7 +4062,20 @@ impl HQMServerPlayer {
         }
     }
 
-    fn ping_data(&self) -> Option<PingData> {
+    fn add_log_message(&mut self, index: usize) {
+        if let ServerPlayerData::NetworkPlayer { data } = &mut self.data {
+            data.messages.push_log(index);
+        }
+    }
+
+    pub(crate) fn supports_extensions(&self) -> bool {
+        match &self.data {
+            ServerPlayerData::NetworkPlayer { data } => data.client_version.has_extensions(),
+            ServerPlayerData::Bot { .. } => false,
+        }
+    }
+
+    pub(crate) fn ping_data(&self) -> Option<PingData> {
         match self.data {
             ServerPlayerData::NetworkPlayer {
                 data: NetworkPlayerData { ref last_ping, .. },
@@ -1782,9 +4111,9 @@ impl HQMServerPlayer {
     pub(crate) fn add_directed_user_chat_message(
         &mut self,
         message: impl Into<Cow<'static, str>>,
-        sender_index: PlayerIndex,
+        sender_id: PlayerId,
     ) {
-        self.add_directed_chat_message(message, Some(sender_index));
+        self.add_directed_chat_message(message, Some(sender_id));
     }
 
     pub(crate) fn add_directed_server_chat_message(
@@ -1797,11 +4126,15 @@ impl HQMServerPlayer {
     pub(crate) fn add_directed_chat_message(
         &mut self,
         message: impl Into<Cow<'static, str>>,
-        sender_index: Option<PlayerIndex>,
+        sender_id: Option<PlayerId>,
     ) {
+        let message = match sender_id {
+            Some(_) => message.into(),
+            None => lang::translate(message.into(), self.language),
+        };
         let chat = HQMMessage::Chat {
-            player_index: sender_index,
-            message: message.into(),
+            player_id: sender_id,
+            message,
         };
         self.add_message(Rc::new(chat));
     }
@@ -1816,7 +4149,7 @@ impl HQMServerPlayer {
 }
 
 #[derive(Copy, Clone)]
-struct PingData {
+pub(crate) struct PingData {
     pub min: f32,
     pub max: f32,
     pub avg: f32,
@@ -1824,6 +4157,10 @@ struct PingData {
 }
 
 /// Starts an HQM server. This method will not return until the server has terminated.
+///
+/// If `public` is set, the server is announced to the master servers in it, in the format of the
+/// public_address setting. Use [`run_server_with_master_servers`] for other ways of announcing the server.
+#[allow(clippy::too_many_arguments)]
 pub async fn run_server<B: GameMode>(
     port: u16,
     public: Option<&str>,
@@ -1831,13 +4168,93 @@ pub async fn run_server<B: GameMode>(
     physics_config: PhysicsConfiguration,
     ban: Box<dyn BanCheck>,
     recording: Box<dyn RecordingSaveMethod>,
+    behaviour: B,
+) -> std::io::Result<()> {
+    let master_servers = match public {
+        Some(public) => master_server::parse_master_servers(public, Duration::from_secs(10))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+            .into_iter()
+            .map(|x| Box::new(x) as Box<dyn MasterServerAnnouncer>)
+            .collect(),
+        None => vec![],
+    };
+    run_server_with_master_servers(
+        port,
+        master_servers,
+        config,
+        physics_config,
+        ban,
+        recording,
+        behaviour,
+    )
+    .await
+}
+
+/// Starts an HQM server that is announced with `master_servers`. This method will not return until the server
+/// has terminated.
+pub async fn run_server_with_master_servers<B: GameMode>(
+    port: u16,
+    master_servers: Vec<Box<dyn MasterServerAnnouncer>>,
+    config: ServerConfiguration,
+    physics_config: PhysicsConfiguration,
+    ban: Box<dyn BanCheck>,
+    recording: Box<dyn RecordingSaveMethod>,
+    behaviour: B,
+) -> std::io::Result<()> {
+    start_server(
+        port,
+        master_servers,
+        config,
+        physics_config,
+        ban,
+        None,
+        recording,
+        behaviour,
+    )
+    .await
+}
+
+/// Starts an HQM server like [`run_server_with_master_servers`], where players who join are checked by `auth`.
+/// This method will not return until the server has terminated.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_server_with_auth<B: GameMode>(
+    port: u16,
+    master_servers: Vec<Box<dyn MasterServerAnnouncer>>,
+    config: ServerConfiguration,
+    physics_config: PhysicsConfiguration,
+    ban: Box<dyn BanCheck>,
+    auth: Box<dyn AuthProvider>,
+    recording: Box<dyn RecordingSaveMethod>,
+    behaviour: B,
+) -> std::io::Result<()> {
+    start_server(
+        port,
+        master_servers,
+        config,
+        physics_config,
+        ban,
+        Some(auth),
+        recording,
+        behaviour,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn start_server<B: GameMode>(
+    port: u16,
+    master_servers: Vec<Box<dyn MasterServerAnnouncer>>,
+    config: ServerConfiguration,
+    physics_config: PhysicsConfiguration,
+    ban: Box<dyn BanCheck>,
+    auth: Option<Box<dyn AuthProvider>>,
+    recording: Box<dyn RecordingSaveMethod>,
     mut behaviour: B,
 ) -> std::io::Result<()> {
     let initial_values = behaviour.get_initial_game_values();
 
-    let reqwest_client = reqwest::Client::new();
-
     let mut server = HQMServer::new(initial_values, config, physics_config, ban, recording);
+    server.auth = auth;
     info!("Server started");
 
     behaviour.init((&mut server).into());
@@ -1854,48 +4271,80 @@ pub async fn run_server<B: GameMode>(
         socket.local_addr().unwrap()
     );
 
-    async fn get_http_response(
-        client: &reqwest::Client,
-        address: &str,
-    ) -> Result<SocketAddr, Box<dyn Error + Send + Sync>> {
-        let response = client.get(address).send().await?.text().await?;
+    server.master_servers = master_server::start(master_servers, socket.clone());
+
+    let (web_admin_sender, web_admin_receiver) = tokio::sync::mpsc::channel(16);
+    if let Some(web_admin_port) = server.config.web_admin_port {
+        if let Some(password) = server.config.password.clone() {
+            let address = SocketAddr::new(server.config.web_admin_address, web_admin_port);
+            let state = webadmin::start(address, password, web_admin_sender).await?;
+            server.observers.push(Box::new(state));
+        } else {
+            warn!("The web admin interface requires an administrator password, not starting it");
+        }
+    }
+
+    let (query_sender, query_receiver) = watch::channel(server.server_info(&behaviour));
+    if let Some(query_port) = server.config.query_port {
+        query::start(query_port, query_receiver).await?;
+    }
 
-        let split = response.split_ascii_whitespace().collect::<Vec<&str>>();
+    let (http_api_sender, http_api_receiver) = tokio::sync::mpsc::channel(16);
+    if let Some(http_port) = server.config.http_port {
+        if let Some(api_key) = server.config.http_api_key.clone() {
+            let public_status = server.config.http_public_status;
+            http_api::start(http_port, api_key, public_status, http_api_sender).await?;
+        } else {
+            warn!("The HTTP API requires an API key, not starting it");
+        }
+    }
 
-        let addr = split.get(1).unwrap_or(&"").parse::<IpAddr>()?;
-        let port = split.get(2).unwrap_or(&"").parse::<u16>()?;
-        Ok(SocketAddr::new(addr, port))
+    let (schedule_sender, schedule_receiver) = tokio::sync::mpsc::channel(16);
+    if let Some(schedule) = server.config.schedule.clone() {
+        schedule::start(schedule, schedule_sender);
     }
 
-    if let Some(public) = public {
-        let socket = socket.clone();
-        let reqwest_client = reqwest_client.clone();
-        let address = public.to_string();
-        tokio::spawn(async move {
-            loop {
-                let master_server = get_http_response(&reqwest_client, &address).await;
-                match master_server {
-                    Ok(addr) => {
-                        for _ in 0..60 {
-                            let msg = b"Hock\x20";
-                            let res = socket.send_to(msg, addr).await;
-                            if res.is_err() {
-                                break;
-                            }
-                            tokio::time::sleep(Duration::from_secs(10)).await;
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!(e);
-                        tokio::time::sleep(Duration::from_secs(15)).await;
-                    }
-                }
-            }
-        });
+    let (discord_sender, discord_receiver) = tokio::sync::mpsc::channel(16);
+    if let Some(discord) = server.config.discord.clone() {
+        let (events, receiver) = tokio::sync::broadcast::channel(1024);
+        server
+            .observers
+            .push(Box::new(EventBroadcaster::new(events)));
+        discord::start(
+            discord,
+            receiver,
+            server.config.team_names.clone(),
+            discord_sender,
+        );
     }
+
+    if let Some(path) = server.config.live_state_file.clone() {
+        let (sender, receiver) = watch::channel(String::new());
+        let export = LiveStateExport::new(server.config.team_names.clone(), sender);
+        server.observers.push(Box::new(export));
+        live_state::start(path, receiver);
+    }
+
+    if let Some(sink) = server.config.event_log.clone() {
+        let (events, receiver) = tokio::sync::broadcast::channel(1024);
+        server
+            .observers
+            .push(Box::new(EventBroadcaster::new(events)));
+        event_log::start(sink, receiver);
+    }
+
+    let (shutdown_sender, shutdown_receiver) = tokio::sync::mpsc::channel(1);
+    watch_shutdown_signals(shutdown_sender);
+    let mut shut_down_all = shut_down_all_sender().subscribe();
+
     enum Msg {
         Time,
-        Message(SocketAddr, HQMClientToServerMessage),
+        Packet(SocketAddr, Bytes),
+        WebAdmin(WebAdminCommand),
+        HttpApi(HttpApiRequest),
+        Schedule(ScheduleState),
+        Discord(DiscordMessage),
+        Shutdown,
     }
 
     let timeout_stream = tokio_stream::wrappers::IntervalStream::new(tick_timer).map(|_| Msg::Time);
@@ -1903,15 +4352,13 @@ pub async fn run_server<B: GameMode>(
         let socket = socket.clone();
         stream! {
             let mut buf = BytesMut::with_capacity(512);
-            let codec = HQMMessageCodec;
             loop {
-                buf.clear();
+                // The packet is handled before the next one is received, so this reuses the same memory
+                buf.reserve(512);
 
                 match socket.recv_buf_from(&mut buf).await {
                     Ok((_, addr)) => {
-                        if let Ok(data) = codec.parse_message(&buf) {
-                            yield Msg::Message(addr, data)
-                        }
+                        yield Msg::Packet(addr, buf.split().freeze())
                     }
                     Err(_) => {}
                 }
@@ -1920,17 +4367,210 @@ pub async fn run_server<B: GameMode>(
     };
     tokio::pin!(packet_stream);
 
-    let mut stream = futures::stream_select!(timeout_stream, packet_stream);
+    let web_admin_stream =
+        tokio_stream::wrappers::ReceiverStream::new(web_admin_receiver).map(Msg::WebAdmin);
+
+    let http_api_stream =
+        tokio_stream::wrappers::ReceiverStream::new(http_api_receiver).map(Msg::HttpApi);
+
+    let schedule_stream =
+        tokio_stream::wrappers::ReceiverStream::new(schedule_receiver).map(Msg::Schedule);
+
+    let discord_stream =
+        tokio_stream::wrappers::ReceiverStream::new(discord_receiver).map(Msg::Discord);
+
+    let shutdown_stream =
+        tokio_stream::wrappers::ReceiverStream::new(shutdown_receiver).map(|()| Msg::Shutdown);
+
+    let shut_down_all_stream = stream! {
+        if shut_down_all.wait_for(|x| *x).await.is_ok() {
+            yield Msg::Shutdown;
+        }
+    };
+    tokio::pin!(shut_down_all_stream);
+
+    let mut stream = futures::stream_select!(
+        timeout_stream,
+        packet_stream,
+        web_admin_stream,
+        http_api_stream,
+        schedule_stream,
+        discord_stream,
+        shutdown_stream,
+        shut_down_all_stream
+    );
     let mut write_buf = BytesMut::with_capacity(4096);
     while let Some(msg) = stream.next().await {
         match msg {
-            Msg::Time => server.tick(&socket, &mut behaviour, &mut write_buf).await,
-            Msg::Message(addr, data) => {
+            Msg::Time => {
+                server.tick(&socket, &mut behaviour, &mut write_buf).await;
+                if server.shutdown_timer == Some(0) {
+                    break;
+                }
+                let server_info = server.server_info(&behaviour);
+                query_sender.send_if_modified(|current| {
+                    if *current != server_info {
+                        *current = server_info;
+                        true
+                    } else {
+                        false
+                    }
+                });
+            }
+            Msg::Packet(addr, packet) => {
                 server
-                    .handle_message(addr, &socket, data, &mut behaviour, &mut write_buf)
+                    .handle_packet(addr, &socket, &packet, &mut behaviour, &mut write_buf)
                     .await
             }
+            Msg::WebAdmin(command) => server.handle_web_admin_command(command, &mut behaviour),
+            Msg::HttpApi(request) => server.handle_http_api_request(request, &mut behaviour),
+            Msg::Schedule(state) => server.handle_schedule_change(state, &mut behaviour),
+            Msg::Discord(message) => server.handle_discord_message(message),
+            Msg::Shutdown => server.start_shutdown(SIGNAL_SHUTDOWN_SECONDS),
         }
     }
+    server
+        .shut_down(&mut behaviour, &socket, &mut write_buf)
+        .await;
+    if server.abandon_shutdown {
+        // Fails, so that the process exits with an error code and supervisors restart it
+        return Err(std::io::Error::other(
+            "Shut down after the game was abandoned",
+        ));
+    }
     Ok(())
 }
+
+fn shut_down_all_sender() -> &'static watch::Sender<bool> {
+    SHUT_DOWN_ALL.get_or_init(|| watch::channel(false).0)
+}
+
+/// Asks all the servers running in this process to shut down, like Ctrl-C or SIGTERM does.
+///
+/// Servers that are started later shut down right after starting. Meant for processes that run several
+/// servers, which should stop together when one of them stops.
+pub fn shut_down_all() {
+    shut_down_all_sender().send_replace(true);
+}
+
+/// Sends a message on `sender` each time the process is asked to stop, with Ctrl-C or SIGTERM.
+fn watch_shutdown_signals(sender: tokio::sync::mpsc::Sender<()>) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut terminate =
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    warn!("Could not listen for SIGTERM: {}", e);
+                    None
+                }
+            };
+        loop {
+            #[cfg(unix)]
+            let terminated = async {
+                match &mut terminate {
+                    Some(terminate) => {
+                        terminate.recv().await;
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let terminated = std::future::pending::<()>();
+            tokio::select! {
+                res = tokio::signal::ctrl_c() => {
+                    if res.is_err() {
+                        return;
+                    }
+                }
+                _ = terminated => {}
+            }
+            if sender.send(()).await.is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Runs games without any networking, as fast as possible, to produce recordings.
+///
+/// The game mode is run the same way as on a normal server, with `bots` bots split between the two teams
+/// instead of human players. Stops after `games` games have ended or after `max_ticks` ticks, whichever
+/// comes first, and saves the recordings of all games, including the last unfinished one.
+pub async fn run_headless<B: GameMode>(
+    mut config: ServerConfiguration,
+    physics_config: PhysicsConfiguration,
+    recording: Box<dyn RecordingSaveMethod>,
+    mut behaviour: B,
+    bots: usize,
+    games: u32,
+    max_ticks: u32,
+) {
+    config.recording_enabled = ReplayRecording::On;
+    // The bots are added below instead
+    config.bots = 0;
+    let initial_values = behaviour.get_initial_game_values();
+    let mut server = HQMServer::new(
+        initial_values,
+        config,
+        physics_config,
+        Box::new(InMemoryBanCheck::new()),
+        recording,
+    );
+    server.simulated_clock = Some(Utc::now());
+    info!("Headless server started");
+
+    behaviour.init((&mut server).into());
+
+    for i in 0..bots {
+        let team = if i % 2 == 0 { Team::Red } else { Team::Blue };
+        let name = format!("Bot {}", i + 1);
+        if let Some(player_id) = server.state.players.add_bot(&name) {
+            server.bots.push(SkaterBot::new(player_id, team));
+        }
+    }
+
+    let first_game_id = server.game_id;
+    let mut write_buf = BytesMut::new();
+    let start = Instant::now();
+    let start_allocations = allocation_count();
+    let mut ticks = 0u64;
+    for _ in 0..max_ticks {
+        if server.game_id - first_game_id >= games {
+            break;
+        }
+        if let Some((game_step, forced_view)) = server.simulate_tick(&mut behaviour, true) {
+            server
+                .send_tick(game_step, forced_view, None, &mut write_buf)
+                .await;
+        }
+        ticks += 1;
+    }
+    let elapsed = start.elapsed();
+    let allocations = allocation_count() - start_allocations;
+    if cfg!(feature = "count-allocations") {
+        info!(
+            "Simulated {} ticks in {:.2} s, {:.1} µs and {:.2} allocations per tick",
+            ticks,
+            elapsed.as_secs_f64(),
+            elapsed.as_secs_f64() * 1e6 / ticks.max(1) as f64,
+            allocations as f64 / ticks.max(1) as f64
+        );
+    } else {
+        info!(
+            "Simulated {} ticks in {:.2} s, {:.1} µs per tick",
+            ticks,
+            elapsed.as_secs_f64(),
+            elapsed.as_secs_f64() * 1e6 / ticks.max(1) as f64
+        );
+    }
+    // Save the last game
+    if server.game_id - first_game_id < games {
+        server.new_game(behaviour.get_initial_game_values());
+    }
+    info!("Headless server finished");
+
+    for handle in server.save_recording.take_pending_saves() {
+        let _ = handle.await;
+    }
+}
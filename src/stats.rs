@@ -0,0 +1,154 @@
+//! Goals, assists, shots on goal and hits of the players in the current game, shown with /stats and /leaders.
+//!
+//! The lines are filled in from the events found by the [`HighlightDetector`](crate::highlights::HighlightDetector).
+//! Players are kept by name, so that they keep their points if they reconnect during the game.
+
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StatLine {
+    pub(crate) name: Rc<str>,
+    pub(crate) goals: u32,
+    pub(crate) assists: u32,
+    pub(crate) shots: u32,
+    pub(crate) hits: u32,
+}
+
+impl StatLine {
+    pub(crate) fn new(name: Rc<str>) -> Self {
+        StatLine {
+            name,
+            goals: 0,
+            assists: 0,
+            shots: 0,
+            hits: 0,
+        }
+    }
+
+    pub(crate) fn points(&self) -> u32 {
+        self.goals + self.assists
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GameStats {
+    lines: Vec<StatLine>,
+}
+
+impl GameStats {
+    /// Clears everything. Called when a new game starts.
+    pub(crate) fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    fn line_mut(&mut self, name: &Rc<str>) -> &mut StatLine {
+        let i = match self.lines.iter().position(|line| line.name == *name) {
+            Some(i) => i,
+            None => {
+                self.lines.push(StatLine::new(name.clone()));
+                self.lines.len() - 1
+            }
+        };
+        &mut self.lines[i]
+    }
+
+    /// Adds a goal, which is also a shot on goal for the scorer.
+    pub(crate) fn add_goal(&mut self, goal_player: &Rc<str>, assist_player: Option<&Rc<str>>) {
+        let line = self.line_mut(goal_player);
+        line.goals += 1;
+        line.shots += 1;
+        if let Some(name) = assist_player {
+            self.line_mut(name).assists += 1;
+        }
+    }
+
+    /// Adds a shot on goal that was saved.
+    pub(crate) fn add_shot(&mut self, player: &Rc<str>) {
+        self.line_mut(player).shots += 1;
+    }
+
+    pub(crate) fn add_hit(&mut self, player: &Rc<str>) {
+        self.line_mut(player).hits += 1;
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&StatLine> {
+        self.lines.iter().find(|line| &*line.name == name)
+    }
+
+    /// Returns the players whose name contains `name`, ignoring case. An exact match wins over the others.
+    pub(crate) fn search(&self, name: &str) -> Vec<StatLine> {
+        let name = name.trim().to_lowercase();
+        if let Some(line) = self
+            .lines
+            .iter()
+            .find(|line| line.name.to_lowercase() == name)
+        {
+            return vec![line.clone()];
+        }
+        self.leaders()
+            .into_iter()
+            .filter(|line| line.name.to_lowercase().contains(&name))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the players sorted by points, then goals. Ties are sorted by name.
+    pub(crate) fn leaders(&self) -> Vec<&StatLine> {
+        let mut leaders = self.lines.iter().collect::<Vec<_>>();
+        leaders.sort_by(|a, b| {
+            b.points()
+                .cmp(&a.points())
+                .then_with(|| b.goals.cmp(&a.goals))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        leaders
+    }
+}
+
+/// Formats a line like "Alice 2 G 1 A 3 P 5 SOG 1 H", which fits in a chat message with any player name.
+pub(crate) fn format_stat_line(line: &StatLine) -> String {
+    format!(
+        "{} {} G {} A {} P {} SOG {} H",
+        line.name,
+        line.goals,
+        line.assists,
+        line.points(),
+        line.shots,
+        line.hits
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GameStats;
+    use std::rc::Rc;
+
+    #[test]
+    fn most_points_first() {
+        let (alice, bob, carol): (Rc<str>, Rc<str>, Rc<str>) =
+            ("Alice".into(), "Bob".into(), "Carol".into());
+        let mut stats = GameStats::default();
+        assert!(stats.leaders().is_empty());
+
+        stats.add_goal(&bob, Some(&alice));
+        stats.add_goal(&carol, None);
+        stats.add_goal(&alice, Some(&bob));
+        stats.add_shot(&carol);
+        stats.add_hit(&bob);
+        let names = stats
+            .leaders()
+            .into_iter()
+            .map(|line| format!("{} {} {}", line.name, line.goals, line.assists))
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Alice 1 1", "Bob 1 1", "Carol 1 0"]);
+        assert_eq!(stats.get("Carol").map(|line| line.points()), Some(1));
+        assert_eq!(stats.get("Carol").map(|line| line.shots), Some(2));
+        assert_eq!(stats.get("Bob").map(|line| line.hits), Some(1));
+        assert!(stats.get("Dave").is_none());
+        assert_eq!(stats.search("b").len(), 1);
+        assert_eq!(stats.search("A").len(), 2);
+
+        stats.clear();
+        assert!(stats.leaders().is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+//! Detection of stick movements too fast for a human hand, like those of stick-lift macros.
+//!
+//! If [`ServerConfiguration::stick_report_speed`](crate::ServerConfiguration::stick_report_speed) is set, the
+//! stick input of each skater is compared to the input of the previous input packet of the player, and the
+//! difference is divided by the ticks between the two packets, so that lost or late packets don't look like fast
+//! movements. A player whose stick turns faster than that [`REPORT_COUNT`] times within [`REPORT_WINDOW`] ticks
+//! is reported to the admins on the server and in the log, at most once every [`REPORT_COOLDOWN`] ticks.
+
+use std::collections::{HashMap, VecDeque};
+
+use nalgebra::Vector2;
+use tracing::warn;
+
+use crate::game::{PlayerId, PlayerInput};
+use crate::physics::clamped_stick_input;
+use crate::server::{HQMServer, PlayerListExt, ServerPlayerData};
+
+const REPORT_COUNT: usize = 5;
+const REPORT_WINDOW: u32 = 10 * 100;
+const REPORT_COOLDOWN: u32 = 60 * 100;
+
+#[derive(Debug, Clone)]
+struct StickHistory {
+    stick: Vector2<f32>,
+    stick_angle: f32,
+    /// Input packets received when the stick was last compared, and the tick it was compared.
+    input_packets: u32,
+    input_tick: u32,
+    /// Ticks when the stick turned too fast, oldest first.
+    fast_ticks: VecDeque<u32>,
+    last_report: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct StickCheck {
+    ticks: u32,
+    players: HashMap<PlayerId, StickHistory>,
+}
+
+/// Returns how far the stick input turns from `previous` to `input`, in radians.
+fn stick_rotation(previous: (Vector2<f32>, f32), input: &PlayerInput) -> f32 {
+    let (stick, stick_angle) = clamped_stick_input(input);
+    let placement = (stick - previous.0).norm();
+    // The stick angle input is a quarter turn for each unit
+    let angle = (stick_angle - previous.1).abs() * std::f32::consts::FRAC_PI_4;
+    placement.max(angle)
+}
+
+impl HQMServer {
+    /// Checks the stick input of every skater. Should be called once each tick, before the input is applied.
+    pub(crate) fn check_stick_speeds(&mut self) {
+        let Some(max_speed) = self.config.stick_report_speed else {
+            return;
+        };
+        let check = &mut self.stick_check;
+        check.ticks = check.ticks.wrapping_add(1);
+        let tick = check.ticks;
+
+        let mut reports = vec![];
+        let players = &self.state.players.players;
+        check.players.retain(|player_id, _| {
+            players
+                .get_player(*player_id)
+                .is_some_and(|x| x.has_skater())
+        });
+        for (player_id, player) in players.iter_players().filter(|(_, x)| x.has_skater()) {
+            // Bots don't send input packets
+            if !matches!(player.data, ServerPlayerData::NetworkPlayer { .. }) {
+                continue;
+            }
+            let (stick, stick_angle) = clamped_stick_input(&player.input);
+            let history = check.players.entry(player_id).or_insert(StickHistory {
+                stick,
+                stick_angle,
+                input_packets: player.input_packets,
+                input_tick: tick,
+                fast_ticks: VecDeque::new(),
+                last_report: None,
+            });
+
+            while history
+                .fast_ticks
+                .front()
+                .is_some_and(|x| tick.wrapping_sub(*x) > REPORT_WINDOW)
+            {
+                history.fast_ticks.pop_front();
+            }
+            if history.input_packets == player.input_packets {
+                continue;
+            }
+            let elapsed = tick.wrapping_sub(history.input_tick).max(1);
+            let rotation = stick_rotation((history.stick, history.stick_angle), &player.input)
+                / elapsed as f32;
+            history.stick = stick;
+            history.stick_angle = stick_angle;
+            history.input_packets = player.input_packets;
+            history.input_tick = tick;
+
+            if rotation <= max_speed {
+                continue;
+            }
+            history.fast_ticks.push_back(tick);
+            let cooled_down = history
+                .last_report
+                .is_none_or(|x| tick.wrapping_sub(x) > REPORT_COOLDOWN);
+            if history.fast_ticks.len() >= REPORT_COUNT && cooled_down {
+                history.last_report = Some(tick);
+                history.fast_ticks.clear();
+                reports.push((player_id, player.player_name.clone(), rotation));
+            }
+        }
+
+        for (player_id, name, rotation) in reports {
+            warn!(
+                "{} ({}) turned the stick faster than allowed {} times in {} seconds, last at {:.1} rad/s",
+                name,
+                player_id,
+                REPORT_COUNT,
+                REPORT_WINDOW / 100,
+                rotation * 100.0
+            );
+            let msg = format!(
+                "Suspiciously fast stick from {}, ID {}",
+                name,
+                player_id.index()
+            );
+            let admins: Vec<PlayerId> = self
+                .state
+                .players
+                .players
+                .iter_players()
+                .filter(|(_, admin)| admin.is_admin)
+                .map(|(admin_id, _)| admin_id)
+                .collect();
+            for admin_id in admins {
+                self.state
+                    .players
+                    .add_directed_server_chat_message(msg.clone(), admin_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector2;
+
+    use super::stick_rotation;
+    use crate::game::PlayerInput;
+
+    #[test]
+    fn stick_rotation_per_tick() {
+        let input = PlayerInput {
+            stick: Vector2::new(0.3, -0.4),
+            stick_angle: -1.0,
+            ..Default::default()
+        };
+        let rotation = stick_rotation((Vector2::new(0.0, 0.0), -1.0), &input);
+        assert!((rotation - 0.5).abs() < 1e-6);
+        // A full flip of the blade is half a turn
+        let rotation = stick_rotation((Vector2::new(0.3, -0.4), 1.0), &input);
+        assert!((rotation - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        // Input outside of the normal range counts as the edge of the range
+        let input = PlayerInput {
+            stick_angle: f32::NAN,
+            ..Default::default()
+        };
+        assert_eq!(stick_rotation((Vector2::new(0.0, 0.0), 0.0), &input), 0.0);
+    }
+}
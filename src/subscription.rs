@@ -0,0 +1,239 @@
+//! Server events for integrations outside the tick loop.
+//!
+//! If [`ServerConfiguration::events`](crate::ServerConfiguration::events) is set, the server sends
+//! [`ServerEvent`]s on that broadcast channel, and any number of tasks can subscribe to it, for example a
+//! Discord bot or a statistics collector. The events are owned copies of what the clients see, so they can be
+//! handled on any thread without holding up the server. A subscriber that falls behind misses the oldest
+//! events, as with any [`tokio::sync::broadcast`] channel.
+//!
+//! ```
+//! use migo_hqm_server::subscription::ServerEvent;
+//!
+//! let (events, mut receiver) = tokio::sync::broadcast::channel::<ServerEvent>(1024);
+//! // Set `events` as ServerConfiguration::events, and hand out receivers with events.subscribe()
+//! assert!(receiver.try_recv().is_err());
+//! ```
+
+use std::collections::HashMap;
+
+use tokio::sync::broadcast;
+
+use crate::game::{PlayerId, Team};
+use crate::observer::{GameObserver, ObservedMessage, ObservedTick};
+use crate::record::AnnotationKind;
+
+/// Ticks between two [`ServerEvent::Summary`] events.
+const SUMMARY_INTERVAL: u32 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    /// A new game has been started.
+    NewGame {
+        game_id: u32,
+    },
+    /// The first period of the game has started, after the warmup.
+    GameStarted {
+        game_id: u32,
+    },
+    GameOver {
+        game_id: u32,
+        red_score: u32,
+        blue_score: u32,
+    },
+    PlayerJoined {
+        player_id: PlayerId,
+        name: String,
+    },
+    PlayerLeft {
+        player_id: PlayerId,
+        name: String,
+    },
+    /// A player has joined a team, or has gone to spectator if `team` is [`None`].
+    TeamChanged {
+        player_id: PlayerId,
+        name: String,
+        team: Option<Team>,
+    },
+    /// A chat message that was sent to everyone.
+    Chat {
+        /// Sender of the message, or [`None`] if it was sent by the server.
+        player_id: Option<PlayerId>,
+        name: Option<String>,
+        message: String,
+    },
+    Goal {
+        team: Team,
+        goal_player: Option<String>,
+        assist_player: Option<String>,
+        /// The score after the goal.
+        red_score: u32,
+        blue_score: u32,
+    },
+    Penalty {
+        team: Option<Team>,
+        player_id: Option<PlayerId>,
+        name: Option<String>,
+        reason: String,
+    },
+    /// The state of the game, sent once each second.
+    Summary {
+        game_id: u32,
+        period: u32,
+        /// Time left of the period, in hundredths of a second.
+        time: u32,
+        red_score: u32,
+        blue_score: u32,
+        game_over: bool,
+        /// Number of players on the server, including spectators.
+        players: usize,
+    },
+}
+
+/// Observer that sends the events of each tick on a broadcast channel.
+pub(crate) struct EventBroadcaster {
+    sender: broadcast::Sender<ServerEvent>,
+    /// Name and team of each player on the server.
+    players: HashMap<PlayerId, (String, Option<Team>)>,
+    /// Period and game over flag of the previous tick, to tell when a game starts or ends.
+    state: Option<(u32, bool)>,
+    ticks: u32,
+}
+
+impl EventBroadcaster {
+    pub(crate) fn new(sender: broadcast::Sender<ServerEvent>) -> Self {
+        EventBroadcaster {
+            sender,
+            players: HashMap::new(),
+            state: None,
+            ticks: 0,
+        }
+    }
+
+    fn send(&self, event: ServerEvent) {
+        // Nobody listening is fine
+        let _ = self.sender.send(event);
+    }
+
+    fn name(&self, player_id: PlayerId) -> Option<String> {
+        self.players.get(&player_id).map(|(name, _)| name.clone())
+    }
+}
+
+impl GameObserver for EventBroadcaster {
+    fn new_game(&mut self, game_id: u32) {
+        self.send(ServerEvent::NewGame { game_id });
+    }
+
+    fn tick(&mut self, tick: &ObservedTick) {
+        for message in tick.messages() {
+            match message {
+                ObservedMessage::PlayerUpdate {
+                    player_id,
+                    player_name: Some(name),
+                    object,
+                } => {
+                    let team = object.map(|(_, team)| team);
+                    match self.players.insert(player_id, (name.to_owned(), team)) {
+                        None => {
+                            self.send(ServerEvent::PlayerJoined {
+                                player_id,
+                                name: name.to_owned(),
+                            });
+                            if team.is_some() {
+                                self.send(ServerEvent::TeamChanged {
+                                    player_id,
+                                    name: name.to_owned(),
+                                    team,
+                                });
+                            }
+                        }
+                        Some((_, old_team)) if old_team != team => {
+                            self.send(ServerEvent::TeamChanged {
+                                player_id,
+                                name: name.to_owned(),
+                                team,
+                            });
+                        }
+                        Some(_) => {}
+                    }
+                }
+                ObservedMessage::PlayerUpdate {
+                    player_id,
+                    player_name: None,
+                    ..
+                } => {
+                    if let Some((name, _)) = self.players.remove(&player_id) {
+                        self.send(ServerEvent::PlayerLeft { player_id, name });
+                    }
+                }
+                ObservedMessage::Chat { player_id, message } => {
+                    self.send(ServerEvent::Chat {
+                        player_id,
+                        name: player_id.and_then(|x| self.name(x)),
+                        message: message.to_owned(),
+                    });
+                }
+                ObservedMessage::Goal {
+                    team,
+                    goal_player,
+                    assist_player,
+                } => {
+                    let scoreboard = tick.scoreboard();
+                    self.send(ServerEvent::Goal {
+                        team,
+                        goal_player: goal_player.and_then(|x| self.name(x)),
+                        assist_player: assist_player.and_then(|x| self.name(x)),
+                        red_score: scoreboard.red_score,
+                        blue_score: scoreboard.blue_score,
+                    });
+                }
+                ObservedMessage::Annotation {
+                    kind: AnnotationKind::Penalty,
+                    team,
+                    player_id,
+                    reason,
+                } => {
+                    self.send(ServerEvent::Penalty {
+                        team,
+                        player_id,
+                        name: player_id.and_then(|x| self.name(x)),
+                        reason: reason.to_owned(),
+                    });
+                }
+                ObservedMessage::Extension { .. } | ObservedMessage::Annotation { .. } => {}
+            }
+        }
+
+        let scoreboard = tick.scoreboard();
+        let state = (scoreboard.period, scoreboard.game_over);
+        match self.state.replace(state) {
+            Some((0, _)) if state.0 > 0 && !state.1 => {
+                self.send(ServerEvent::GameStarted {
+                    game_id: tick.game_id(),
+                });
+            }
+            Some((_, false)) if state.1 => {
+                self.send(ServerEvent::GameOver {
+                    game_id: tick.game_id(),
+                    red_score: scoreboard.red_score,
+                    blue_score: scoreboard.blue_score,
+                });
+            }
+            _ => {}
+        }
+
+        self.ticks += 1;
+        if self.ticks >= SUMMARY_INTERVAL {
+            self.ticks = 0;
+            self.send(ServerEvent::Summary {
+                game_id: tick.game_id(),
+                period: scoreboard.period,
+                time: scoreboard.time,
+                red_score: scoreboard.red_score,
+                blue_score: scoreboard.blue_score,
+                game_over: scoreboard.game_over,
+                players: self.players.len(),
+            });
+        }
+    }
+}
@@ -0,0 +1,2082 @@
+//! End-to-end testing of game modes, without a real client or network.
+//!
+//! [`TestServer`] runs a game mode on a server that isn't bound to a socket, with a simulated clock, so that
+//! tests run as fast as the physics allow and don't depend on timing. Its clients join through the same packet
+//! parsing as real clients, send inputs and chat messages, and keep track of what the server sends them:
+//! the scoreboard, the player list and the chat and goal messages.
+//!
+//! ```no_run
+//! use migo_hqm_server::game::Team;
+//! use migo_hqm_server::gamemode::standard_match::StandardMatchGameMode;
+//! use migo_hqm_server::gamemode::util::SpawnPoint;
+//! use migo_hqm_server::testing::TestServer;
+//!
+//! let game_mode = StandardMatchGameMode::new(Default::default(), 5, SpawnPoint::Center);
+//! let mut server = TestServer::new(TestServer::<StandardMatchGameMode>::configuration(), game_mode);
+//! let alice = server.connect("Alice");
+//! server.client_mut(alice).join(Team::Red);
+//! server.run(100);
+//! assert!(server.client(alice).player("Alice").is_some_and(|p| p.team == Some(Team::Red)));
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use bytes::{Bytes, BytesMut};
+use chrono::Utc;
+use tokio::task::JoinHandle;
+
+use crate::ban::InMemoryBanCheck;
+use crate::chat::ChatConfiguration;
+use crate::game::{
+    PhysicsConfiguration, PlayerInput, RinkArea, RulesState, ScoreboardValues, Team,
+};
+use crate::gamemode::{GameMode, Server, ServerMut};
+use crate::lang::Language;
+use crate::loadtest::skip_object;
+use crate::protocol::{HQMMessageReader, HQMMessageWriter};
+use crate::record::{GameSummary, RecordingSaveMethod};
+use crate::server::{HQMServer, GAME_HEADER};
+use crate::votes::VoteConfiguration;
+use crate::{AbandonPolicy, ReplayRecording, ServerConfiguration, TeamNames};
+
+/// Identifies a client of a [`TestServer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientId(usize);
+
+/// A server running a game mode, with scripted clients instead of real ones.
+///
+/// Each call to [`TestServer::tick`] lets every client send its input, runs one server tick and
+/// hands the update packets of the tick to the clients.
+pub struct TestServer<B: GameMode> {
+    server: HQMServer,
+    game_mode: B,
+    clients: Vec<TestClient>,
+    write_buf: BytesMut,
+}
+
+impl<B: GameMode> TestServer<B> {
+    pub fn new(config: ServerConfiguration, game_mode: B) -> Self {
+        Self::with_physics(config, PhysicsConfiguration::default(), game_mode)
+    }
+
+    pub fn with_physics(
+        config: ServerConfiguration,
+        physics_config: PhysicsConfiguration,
+        mut game_mode: B,
+    ) -> Self {
+        let initial_values = game_mode.get_initial_game_values();
+        let mut server = HQMServer::new(
+            initial_values,
+            config,
+            physics_config,
+            Box::new(InMemoryBanCheck::new()),
+            Box::new(DiscardRecording),
+        );
+        server.simulated_clock = Some(Utc::now());
+        game_mode.init((&mut server).into());
+        TestServer {
+            server,
+            game_mode,
+            clients: vec![],
+            write_buf: BytesMut::with_capacity(4096),
+        }
+    }
+
+    /// A server configuration for tests, with room for 20 players and nothing that uses files or ports.
+    pub fn configuration() -> ServerConfiguration {
+        ServerConfiguration {
+            welcome: vec![],
+            password: Some("test".to_owned()),
+            player_max: 20,
+            ip_player_max: None,
+            spectator_max: None,
+            ip_whitelist: vec![],
+            mute_file: None,
+            recording_enabled: ReplayRecording::Off,
+            server_name: "Test server".to_owned(),
+            server_service: None,
+            team_names: TeamNames::default(),
+            bots: 0,
+            rink_area: RinkArea::Full,
+            board_zones: vec![],
+            commands: HashMap::new(),
+            afk_time: 0,
+            rewind_max: 0,
+            clip_max: 0,
+            replay_countdown: 0,
+            replay_view_buffer: 0,
+            join_message_window: 0,
+            join_history: None,
+            recording_annotations: false,
+            recording_inputs: false,
+            consistency_check_interval: 0,
+            stick_report_speed: None,
+            random_seed: Some(0),
+            abandon_policy: AbandonPolicy::Reset,
+            live_state_file: None,
+            web_admin_port: None,
+            web_admin_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            query_port: None,
+            http_port: None,
+            http_api_key: None,
+            http_public_status: false,
+            votes: VoteConfiguration::default(),
+            chat: ChatConfiguration::default(),
+            language: Language::default(),
+            announcements: HashMap::new(),
+            schedule: None,
+            events: None,
+            discord: None,
+            event_log: None,
+        }
+    }
+
+    /// Adds a client, which asks to join the server in the next tick.
+    pub fn connect(&mut self, name: &str) -> ClientId {
+        let id = ClientId(self.clients.len());
+        let addr = SocketAddr::from(([127, 0, 0, 1], 10000 + id.0 as u16));
+        self.clients.push(TestClient::new(name, addr));
+        id
+    }
+
+    /// Tells the server that the client has left, like a client that is closed.
+    pub fn disconnect(&mut self, id: ClientId) {
+        let client = &mut self.clients[id.0];
+        if !client.connected {
+            return;
+        }
+        client.connected = false;
+        let addr = client.addr;
+        self.write_buf.clear();
+        let mut writer = HQMMessageWriter::new(&mut self.write_buf);
+        writer.write_bytes_aligned(GAME_HEADER);
+        writer.write_byte_aligned(7);
+        let packet = self.write_buf.split().freeze();
+        self.receive(addr, &packet);
+    }
+
+    pub fn client(&self, id: ClientId) -> &TestClient {
+        &self.clients[id.0]
+    }
+
+    pub fn client_mut(&mut self, id: ClientId) -> &mut TestClient {
+        &mut self.clients[id.0]
+    }
+
+    pub fn game_mode(&self) -> &B {
+        &self.game_mode
+    }
+
+    pub fn game_mode_mut(&mut self) -> &mut B {
+        &mut self.game_mode
+    }
+
+    pub fn server(&self) -> Server<'_> {
+        (&self.server).into()
+    }
+
+    /// Gives access to the server state, for setting up situations that are hard to reach with inputs,
+    /// like a puck that is about to enter the net.
+    pub fn server_mut(&mut self) -> ServerMut<'_> {
+        (&mut self.server).into()
+    }
+
+    /// Sends the input of every client, runs one tick and passes the updates to the clients.
+    pub fn tick(&mut self) {
+        for i in 0..self.clients.len() {
+            let client = &mut self.clients[i];
+            if !client.connected {
+                continue;
+            }
+            client.ticks = client.ticks.wrapping_add(1);
+            if !client.ticks.is_multiple_of(client.input_interval.max(1)) {
+                continue;
+            }
+            let addr = client.addr;
+            client.write_packet(&mut self.write_buf);
+            let packet = self.write_buf.split().freeze();
+            self.receive(addr, &packet);
+        }
+        let updates = self
+            .server
+            .tick_to_updates(&mut self.game_mode, &mut self.write_buf);
+        self.deliver(updates);
+    }
+
+    fn deliver(&mut self, updates: Vec<(SocketAddr, Bytes)>) {
+        for (addr, packet) in updates {
+            if let Some(client) = self
+                .clients
+                .iter_mut()
+                .find(|client| client.connected && client.addr == addr)
+            {
+                client.handle_packet(&packet);
+            }
+        }
+    }
+
+    /// Shuts the server down like a signal or /shutdown does, and passes the last update to the clients.
+    pub fn shut_down(&mut self) {
+        let updates = self
+            .server
+            .shut_down_to_updates(&mut self.game_mode, &mut self.write_buf);
+        self.deliver(updates);
+    }
+
+    /// Runs `ticks` ticks.
+    pub fn run(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    /// Runs ticks until `condition` is true, for at most `max_ticks` ticks. Returns true if the condition was met.
+    pub fn run_until(&mut self, max_ticks: u32, mut condition: impl FnMut(&Self) -> bool) -> bool {
+        for _ in 0..max_ticks {
+            if condition(self) {
+                return true;
+            }
+            self.tick();
+        }
+        condition(self)
+    }
+
+    fn receive(&mut self, addr: SocketAddr, packet: &[u8]) {
+        if let Some(message) = self.server.parse_packet(addr, packet) {
+            self.server
+                .handle_player_message(addr, message, &mut self.game_mode);
+        }
+    }
+}
+
+/// A player in the player list of a [`TestClient`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedPlayer {
+    pub name: String,
+    /// The team of the player's skater, or [`None`] for spectators.
+    pub team: Option<Team>,
+}
+
+/// A message received by a [`TestClient`]. Player list updates are applied to the player list instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReceivedMessage {
+    /// A chat message, from the server if `player_index` is [`None`].
+    Chat {
+        player_index: Option<usize>,
+        message: String,
+    },
+    Goal {
+        team: Team,
+        goal_player_index: Option<usize>,
+        assist_player_index: Option<usize>,
+    },
+    Extension {
+        message_type: u16,
+        payload: Vec<u8>,
+    },
+}
+
+/// A scripted client of a [`TestServer`].
+///
+/// The client sends [`TestClient::input`] every tick, acknowledges the packets and messages it receives
+/// and keeps the latest scoreboard, the player list and all received messages.
+pub struct TestClient {
+    name: String,
+    addr: SocketAddr,
+    connected: bool,
+    /// The input sent to the server every tick.
+    pub input: PlayerInput,
+    /// If true, the client announces that it supports extension messages.
+    pub extensions: bool,
+    /// If false, the client keeps acknowledging the last packet it acknowledged, as if every newer packet was lost.
+    pub acknowledge_packets: bool,
+    /// Ticks between the input packets of the client, 1 to send a packet every tick.
+    pub input_interval: u32,
+    ticks: u32,
+    game_id: u32,
+    known_packet: u32,
+    /// The packet the objects of the latest update were delta compressed against, [`u32::MAX`] if none.
+    delta_packet: u32,
+    known_msgpos: u32,
+    chat_rep: u8,
+    chat_queue: VecDeque<String>,
+    scoreboard: ScoreboardValues,
+    game_step: u32,
+    view_player_index: usize,
+    players: Vec<Option<ReceivedPlayer>>,
+    messages: Vec<ReceivedMessage>,
+}
+
+impl TestClient {
+    fn new(name: &str, addr: SocketAddr) -> Self {
+        TestClient {
+            name: name.to_owned(),
+            addr,
+            connected: true,
+            input: PlayerInput::default(),
+            extensions: false,
+            acknowledge_packets: true,
+            input_interval: 1,
+            ticks: 0,
+            game_id: u32::MAX,
+            known_packet: u32::MAX,
+            delta_packet: u32::MAX,
+            known_msgpos: 0,
+            chat_rep: 0,
+            chat_queue: VecDeque::new(),
+            scoreboard: ScoreboardValues::default(),
+            game_step: 0,
+            view_player_index: 0,
+            players: vec![None; 64],
+            messages: vec![],
+        }
+    }
+
+    /// Returns true once the client has been accepted by the server.
+    pub fn has_joined(&self) -> bool {
+        self.game_id != u32::MAX
+    }
+
+    /// The scoreboard of the latest update.
+    pub fn scoreboard(&self) -> &ScoreboardValues {
+        &self.scoreboard
+    }
+
+    /// Returns true if the objects of the latest update were delta compressed against an earlier packet.
+    pub fn is_delta_compressed(&self) -> bool {
+        self.delta_packet != u32::MAX
+    }
+
+    /// The game step of the latest update, which is the step of the replayed tick during replays.
+    pub fn game_step(&self) -> u32 {
+        self.game_step
+    }
+
+    /// Index of the player whose view the client is shown.
+    pub fn view_player_index(&self) -> usize {
+        self.view_player_index
+    }
+
+    /// The players in the server, with their player index.
+    pub fn players(&self) -> impl Iterator<Item = (usize, &ReceivedPlayer)> {
+        self.players
+            .iter()
+            .enumerate()
+            .filter_map(|(i, player)| player.as_ref().map(|player| (i, player)))
+    }
+
+    /// Finds a player in the player list by name.
+    pub fn player(&self, name: &str) -> Option<&ReceivedPlayer> {
+        self.players()
+            .map(|(_, player)| player)
+            .find(|player| player.name == name)
+    }
+
+    /// All messages received since the client joined or the messages were cleared.
+    pub fn messages(&self) -> &[ReceivedMessage] {
+        &self.messages
+    }
+
+    /// The text of all received chat messages.
+    pub fn chat_messages(&self) -> impl Iterator<Item = &str> {
+        self.messages.iter().filter_map(|message| match message {
+            ReceivedMessage::Chat { message, .. } => Some(message.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns true if the client has received a chat message containing `text`.
+    pub fn has_chat_message(&self, text: &str) -> bool {
+        self.chat_messages().any(|message| message.contains(text))
+    }
+
+    pub fn clear_messages(&mut self) {
+        self.messages.clear();
+    }
+
+    /// Sends a chat message, or a command if it starts with a slash, with the next input.
+    pub fn say(&mut self, message: &str) {
+        self.chat_queue.push_back(message.to_owned());
+    }
+
+    /// Holds the key for joining `team`. The key stays pressed until the input is changed.
+    pub fn join(&mut self, team: Team) {
+        self.input.keys = match team {
+            Team::Red => 0x4,
+            Team::Blue => 0x8,
+        };
+    }
+
+    /// Holds the key for going to the spectators. The key stays pressed until the input is changed.
+    pub fn spectate(&mut self) {
+        self.input.keys = 0x20;
+    }
+
+    fn write_packet(&mut self, buf: &mut BytesMut) {
+        buf.clear();
+        let mut writer = HQMMessageWriter::new(buf);
+        writer.write_bytes_aligned(GAME_HEADER);
+        if !self.has_joined() {
+            writer.write_byte_aligned(2);
+            writer.write_bits(8, 55);
+            writer.write_bytes_aligned_padded(32, self.name.as_bytes());
+            return;
+        }
+        writer.write_byte_aligned(if self.extensions { 0x20 } else { 0x10 });
+        writer.write_u32_aligned(self.game_id);
+        writer.write_f32_aligned(self.input.stick_angle);
+        writer.write_f32_aligned(self.input.turn);
+        writer.write_f32_aligned(0.0);
+        writer.write_f32_aligned(self.input.fwbw);
+        writer.write_f32_aligned(self.input.stick.x);
+        writer.write_f32_aligned(self.input.stick.y);
+        writer.write_f32_aligned(self.input.head_rot);
+        writer.write_f32_aligned(self.input.body_rot);
+        writer.write_u32_aligned(self.input.keys);
+        writer.write_u32_aligned(0); // Delta time
+        writer.write_u32_aligned(self.known_packet);
+        writer.write_byte_aligned((self.known_msgpos & 0xFF) as u8);
+        writer.write_byte_aligned(((self.known_msgpos >> 8) & 0xFF) as u8);
+        match self.chat_queue.pop_front() {
+            Some(message) => {
+                let bytes = &message.as_bytes()[..message.len().min(u8::MAX as usize)];
+                self.chat_rep = (self.chat_rep + 1) % 8;
+                writer.write_bits(1, 1);
+                writer.write_bits(3, self.chat_rep as u32);
+                writer.write_bits(8, bytes.len() as u32);
+                writer.write_bytes_aligned(bytes);
+            }
+            None => writer.write_bits(1, 0),
+        }
+    }
+
+    fn handle_packet(&mut self, data: &[u8]) {
+        let mut reader = HQMMessageReader::new(data);
+        let mut header = [0u8; 4];
+        reader.read_bytes_aligned(&mut header);
+        if header != GAME_HEADER {
+            return;
+        }
+        match reader.read_byte_aligned() {
+            6 => {
+                // New game, the server sends everything again
+                self.game_id = reader.read_u32_aligned();
+                self.known_packet = u32::MAX;
+                self.known_msgpos = 0;
+                self.players = vec![None; 64];
+            }
+            5 => {
+                if reader.read_u32_aligned() != self.game_id {
+                    return;
+                }
+                self.game_step = reader.read_u32_aligned();
+                let scoreboard = &mut self.scoreboard;
+                scoreboard.game_over = reader.read_bits(1) == 1;
+                scoreboard.red_score = reader.read_bits(8);
+                scoreboard.blue_score = reader.read_bits(8);
+                scoreboard.time = reader.read_bits(16);
+                scoreboard.goal_message_timer = reader.read_bits(16);
+                scoreboard.period = reader.read_bits(8);
+                self.view_player_index = reader.read_bits(8) as usize;
+                let _deltatime = reader.read_u32_aligned();
+                let rules = reader.read_u32_aligned();
+                scoreboard.rules_state = if rules & 4 != 0 {
+                    RulesState::Offside
+                } else if rules & 8 != 0 {
+                    RulesState::Icing
+                } else {
+                    RulesState::Regular {
+                        offside_warning: rules & 1 != 0,
+                        icing_warning: rules & 2 != 0,
+                    }
+                };
+                let current_packet = reader.read_u32_aligned();
+                self.delta_packet = reader.read_u32_aligned();
+                for _ in 0..32 {
+                    skip_object(&mut reader);
+                }
+                if self.acknowledge_packets || self.known_packet == u32::MAX {
+                    self.known_packet = current_packet;
+                }
+
+                let message_count = reader.read_bits(4);
+                let message_start = reader.read_bits(16);
+                for pos in message_start..message_start + message_count {
+                    let message = self.read_message(&mut reader);
+                    // Messages the client already has are sent again until they are acknowledged
+                    if pos == self.known_msgpos {
+                        self.known_msgpos += 1;
+                        if let Some(message) = message {
+                            self.messages.push(message);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads a message. Player list updates are applied right away, even if the message has been read before,
+    /// as they are always the latest state of the player.
+    fn read_message(&mut self, reader: &mut HQMMessageReader) -> Option<ReceivedMessage> {
+        match reader.read_bits(6) {
+            0 => {
+                let player_index = reader.read_bits(6) as usize;
+                let in_server = reader.read_bits(1) == 1;
+                let team = team_from_num(reader.read_bits(2));
+                let _object_index = reader.read_bits(6);
+                let name: Vec<u8> = (0..31).map(|_| reader.read_bits(7) as u8).collect();
+                let name = name.split(|x| *x == 0).next().unwrap_or_default();
+                self.players[player_index] = in_server.then(|| ReceivedPlayer {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    team,
+                });
+                None
+            }
+            1 => {
+                let team = team_from_num(reader.read_bits(2));
+                let goal_player_index = player_index_from_num(reader.read_bits(6));
+                let assist_player_index = player_index_from_num(reader.read_bits(6));
+                team.map(|team| ReceivedMessage::Goal {
+                    team,
+                    goal_player_index,
+                    assist_player_index,
+                })
+            }
+            2 => {
+                let player_index = player_index_from_num(reader.read_bits(6));
+                let size = reader.read_bits(6);
+                let message: Vec<u8> = (0..size).map(|_| reader.read_bits(7) as u8).collect();
+                Some(ReceivedMessage::Chat {
+                    player_index,
+                    message: String::from_utf8_lossy(&message).into_owned(),
+                })
+            }
+            3 => {
+                let message_type = reader.read_bits(16) as u16;
+                let size = reader.read_bits(8);
+                let payload = (0..size).map(|_| reader.read_bits(8) as u8).collect();
+                Some(ReceivedMessage::Extension {
+                    message_type,
+                    payload,
+                })
+            }
+            _ => {
+                // Annotations are only written to recordings
+                let _kind = reader.read_bits(4);
+                let _team = reader.read_bits(2);
+                let _player_index = reader.read_bits(6);
+                let size = reader.read_bits(8);
+                for _ in 0..size {
+                    reader.read_bits(8);
+                }
+                None
+            }
+        }
+    }
+}
+
+fn team_from_num(v: u32) -> Option<Team> {
+    match v {
+        0 => Some(Team::Red),
+        1 => Some(Team::Blue),
+        _ => None,
+    }
+}
+
+fn player_index_from_num(v: u32) -> Option<usize> {
+    (v < 63).then_some(v as usize)
+}
+
+/// Recordings of test games are not saved.
+struct DiscardRecording;
+
+impl RecordingSaveMethod for DiscardRecording {
+    fn save_recording_data(
+        &mut self,
+        _config: &ServerConfiguration,
+        _replay_data: Bytes,
+        _start_time: chrono::DateTime<Utc>,
+        _summary: &GameSummary,
+    ) {
+    }
+
+    fn take_pending_saves(&mut self) -> Vec<JoinHandle<()>> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TestServer;
+    use crate::game::Team;
+    use crate::game::{BoardZone, PhysicsConfiguration, PlayerId, Puck};
+    use crate::gamemode::bots::GoalieBotLayer;
+    use crate::gamemode::layers::{GameModeLayer, LayeredGameMode};
+    use crate::gamemode::practice::PracticeGameMode;
+    use crate::gamemode::profiles::{Profile, ProfileGameMode};
+    use crate::gamemode::rotation::RotationGameMode;
+    use crate::gamemode::skills::SkillsGameMode;
+    use crate::gamemode::standard_match::{
+        IcingConfiguration, MatchConfiguration, OffsideConfiguration, OvertimeConfiguration,
+        PeriodRules, StandardMatchGameMode, StartTime,
+    };
+    use crate::gamemode::util::SpawnPoint;
+    use crate::gamemode::warmup::PermanentWarmup;
+    use crate::gamemode::{ExitReason, GameMode, ReplayOptions, ServerMut};
+    use crate::schedule::ScheduleState;
+    use crate::subscription::ServerEvent;
+    use crate::testing::ReceivedMessage;
+    use crate::votes::VoteConfiguration;
+    use crate::{AbandonPolicy, CommandConfiguration, ServerConfiguration};
+    use chrono::{TimeZone, Utc};
+    use nalgebra::{Point3, Rotation3, Vector3};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn scoring_a_goal() {
+        let config = MatchConfiguration {
+            time_warmup: 1,
+            time_intermission: 1,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+
+        // Wait for the first faceoff
+        assert!(server.run_until(1000, |server| {
+            let scoreboard = server.client(alice).scoreboard();
+            scoreboard.period == 1 && scoreboard.time > 0 && scoreboard.time < 29900
+        }));
+        assert_eq!(
+            server.client(bob).player("Alice").and_then(|p| p.team),
+            Some(Team::Red)
+        );
+
+        // Shoot the puck into the blue net
+        let mut s = server.server_mut();
+        let net = s.rink().net(Team::Blue);
+        let goal = Point3::from((net.left_post().coords + net.right_post().coords) / 2.0);
+        let puck = s.pucks_mut().iter_mut().flatten().next().unwrap();
+        puck.body.pos = goal + Vector3::new(0.0, 0.3, 2.0);
+        puck.body.linear_velocity = Vector3::new(0.0, 0.0, -0.1);
+        server.run(100);
+
+        let scoreboard = server.client(bob).scoreboard();
+        assert_eq!((scoreboard.red_score, scoreboard.blue_score), (1, 0));
+        assert!(server.client(bob).messages().iter().any(|message| matches!(
+            message,
+            ReceivedMessage::Goal {
+                team: Team::Red,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn goal_replay_length() {
+        let game_mode = StandardMatchGameMode::new(Default::default(), 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/goalreplay length8");
+        server.run(10);
+        assert!(server
+            .client(alice)
+            .has_chat_message("Usage: /goalreplay on, off or length SECONDS"));
+
+        server.client_mut(alice).say("/goalreplay length 20");
+        server.run(10);
+        assert!(server
+            .client(alice)
+            .has_chat_message("Goal replay length set to 20 seconds by Alice"));
+        assert_eq!(server.server.state.replay.history_length, 2400);
+    }
+
+    #[test]
+    fn faceoff_with_several_pucks() {
+        let config = MatchConfiguration {
+            time_warmup: 1,
+            time_intermission: 1,
+            game_pucks: 3,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+
+        assert!(server.run_until(1000, |server| {
+            let scoreboard = server.client(alice).scoreboard();
+            scoreboard.period == 1 && scoreboard.time > 0
+        }));
+        assert_eq!(server.server().pucks().iter().flatten().count(), 3);
+    }
+
+    #[test]
+    fn shootout_after_tied_game() {
+        let config = MatchConfiguration {
+            time_warmup: 1,
+            time_period: 1,
+            time_intermission: 1,
+            periods: 1,
+            overtime: OvertimeConfiguration::Shootout,
+            time_overtime: 0,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+
+        // Red shoots first
+        assert!(server.run_until(2000, |server| server
+            .client(alice)
+            .has_chat_message("3 attempts left for Red")));
+        assert!(server
+            .client(alice)
+            .has_chat_message("Still tied, the game is decided by a shootout"));
+
+        let mut s = server.server_mut();
+        let net = s.rink().net(Team::Blue);
+        let goal = Point3::from((net.left_post().coords + net.right_post().coords) / 2.0);
+        let puck = s.pucks_mut().iter_mut().flatten().next().unwrap();
+        puck.body.pos = goal + Vector3::new(0.0, 0.3, 2.0);
+        puck.body.linear_velocity = Vector3::new(0.0, 0.0, -0.1);
+
+        // The other attempts run out
+        assert!(server.run_until(20000, |server| server.client(bob).scoreboard().game_over));
+        assert!(server.client(bob).has_chat_message("Red wins the shootout"));
+        let scoreboard = server.client(bob).scoreboard();
+        assert_eq!((scoreboard.red_score, scoreboard.blue_score), (1, 0));
+        assert_eq!(scoreboard.period, 2);
+    }
+
+    #[test]
+    fn smaller_teams_in_overtime() {
+        let config = MatchConfiguration {
+            time_warmup: 1,
+            time_period: 1,
+            time_intermission: 1,
+            periods: 1,
+            overtime: OvertimeConfiguration::Shootout,
+            time_overtime: 1,
+            overtime_team_size: 1,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        let carol = server.connect("Carol");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        server.run(10);
+        server.client_mut(carol).join(Team::Red);
+
+        // Carol joined last, and sits out the overtime
+        assert!(server.run_until(1000, |server| server.client(bob).scoreboard().period == 2));
+        server.run(10);
+        assert!(server
+            .client(bob)
+            .has_chat_message("Carol sits out this overtime period"));
+        assert_eq!(server.client(bob).player("Carol").unwrap().team, None);
+        assert_eq!(
+            server.client(bob).player("Alice").unwrap().team,
+            Some(Team::Red)
+        );
+
+        // The shootout is one against one as well, and it is Carol's turn to play
+        assert!(server.run_until(1000, |server| server
+            .client(bob)
+            .has_chat_message("3 attempts left for Red")));
+        server.run(10);
+        assert!(server
+            .client(bob)
+            .has_chat_message("Alice sits out the shootout"));
+        assert_eq!(server.client(bob).player("Alice").unwrap().team, None);
+        assert_eq!(
+            server.client(bob).player("Carol").unwrap().team,
+            Some(Team::Red)
+        );
+        assert!(!server
+            .client(bob)
+            .has_chat_message("Overtime is over, full rosters are back"));
+    }
+
+    #[test]
+    fn shootout_with_full_teams() {
+        let config = MatchConfiguration {
+            time_warmup: 1,
+            time_period: 1,
+            time_intermission: 1,
+            periods: 1,
+            overtime: OvertimeConfiguration::Shootout,
+            time_overtime: 0,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let mut clients = vec![];
+        for (name, team) in [
+            ("Alice", Team::Red),
+            ("Bob", Team::Blue),
+            ("Carol", Team::Red),
+            ("Dave", Team::Blue),
+            ("Erin", Team::Red),
+            ("Frank", Team::Blue),
+        ] {
+            let client = server.connect(name);
+            server.client_mut(client).join(team);
+            server.run(10);
+            clients.push(client);
+        }
+        let alice = clients[0];
+
+        // Only one shooter and one goalie are on the ice during the shootout
+        assert!(server.run_until(2000, |server| server
+            .client(alice)
+            .has_chat_message("3 attempts left for Red")));
+        server.run(10);
+        let on_ice = |server: &TestServer<StandardMatchGameMode>, team| {
+            server
+                .client(alice)
+                .players()
+                .filter(|(_, player)| player.team == Some(team))
+                .count()
+        };
+        assert_eq!(on_ice(&server, Team::Red), 1);
+        assert_eq!(on_ice(&server, Team::Blue), 1);
+        assert_eq!(
+            server
+                .server()
+                .players()
+                .iter()
+                .filter(|player| player.has_skater())
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn skills_attempt_runs_out() {
+        let game_mode = SkillsGameMode::new(vec![], 2, None);
+        let mut server = TestServer::new(TestServer::<SkillsGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        server.client_mut(alice).join(Team::Red);
+
+        assert!(server.run_until(1000, |server| server
+            .client(alice)
+            .has_chat_message("Alice's turn, drill 1 of 1")));
+        assert_eq!(server.server().pucks().iter().flatten().count(), 1);
+        assert!(server.run_until(1000, |server| server
+            .client(alice)
+            .has_chat_message("Miss by Alice, 0/1")));
+    }
+
+    #[test]
+    fn subscribed_events() {
+        let (events, mut receiver) = tokio::sync::broadcast::channel(1024);
+        let config = ServerConfiguration {
+            events: Some(events),
+            ..TestServer::<PracticeGameMode>::configuration()
+        };
+        let mut server = TestServer::new(config, PracticeGameMode::new(1, SpawnPoint::Center));
+        let alice = server.connect("Alice");
+        server.run(10);
+        server.client_mut(alice).say("hello");
+        server.client_mut(alice).join(Team::Red);
+        server.run(100);
+
+        let events = std::iter::from_fn(|| receiver.try_recv().ok()).collect::<Vec<_>>();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            ServerEvent::PlayerJoined { name, .. } if name == "Alice"
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            ServerEvent::TeamChanged {
+                team: Some(Team::Red),
+                ..
+            }
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            ServerEvent::Chat { name: Some(name), message, .. } if name == "Alice" && message == "hello"
+        )));
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, ServerEvent::Summary { players: 1, .. })));
+    }
+
+    #[test]
+    fn game_mode_layers() {
+        struct Hi;
+
+        impl GameModeLayer for Hi {
+            fn handle_command(
+                &mut self,
+                mut server: ServerMut,
+                cmd: &str,
+                _arg: &str,
+                player_id: PlayerId,
+            ) -> bool {
+                if cmd != "hi" {
+                    return false;
+                }
+                server
+                    .players_mut()
+                    .add_directed_server_chat_message("pong", player_id);
+                true
+            }
+
+            fn before_chat_message(
+                &mut self,
+                _server: ServerMut,
+                _player_id: PlayerId,
+                message: &mut String,
+            ) -> bool {
+                !message.contains("spam")
+            }
+        }
+
+        let game_mode = LayeredGameMode::new(PracticeGameMode::new(1, SpawnPoint::Center)).with(Hi);
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        server.run(10);
+        server.client_mut(alice).say("/hi");
+        server.client_mut(alice).say("buy spam");
+        server.client_mut(alice).say("hello");
+        server.client_mut(alice).join(Team::Red);
+        server.run(100);
+        let client = server.client(alice);
+        assert!(client.has_chat_message("pong"));
+        assert!(client.has_chat_message("hello"));
+        assert!(!client.chat_messages().any(|x| x.contains("spam")));
+        // The practice mode still runs underneath
+        assert_eq!(client.player("Alice").unwrap().team, Some(Team::Red));
+    }
+
+    #[test]
+    fn kick_votes() {
+        let config = ServerConfiguration {
+            votes: VoteConfiguration {
+                enabled: true,
+                cooldown: Duration::ZERO,
+                ..Default::default()
+            },
+            ..TestServer::<PracticeGameMode>::configuration()
+        };
+        let mut server = TestServer::new(config, PracticeGameMode::new(1, SpawnPoint::Center));
+        let alice = server.connect("Alice");
+        let carol = server.connect("Carol");
+        server.run(10);
+        let carol_index = server
+            .client(alice)
+            .players()
+            .find(|(_, player)| player.name == "Carol")
+            .map(|(index, _)| index)
+            .unwrap();
+
+        // Carol can't vote, and Alice alone can't kick her
+        server
+            .client_mut(alice)
+            .say(&format!("/vote kick {}", carol_index));
+        server.run(10);
+        assert!(server
+            .client(alice)
+            .has_chat_message("Vote to kick Carol failed"));
+
+        let bob = server.connect("Bob");
+        server.run(10);
+        server
+            .client_mut(alice)
+            .say(&format!("/vote kick {}", carol_index));
+        server.client_mut(carol).say("/vote no");
+        server.run(10);
+        assert!(server
+            .client(carol)
+            .has_chat_message("You can't vote on this"));
+        assert!(server.client(carol).has_joined());
+        server.client_mut(bob).say("/vote yes");
+        server.run(10);
+        assert!(server
+            .client(alice)
+            .has_chat_message("Carol kicked by vote"));
+    }
+
+    #[test]
+    fn obstacles_removed_in_new_game() {
+        let game_mode = PermanentWarmup::new(1, SpawnPoint::Center, false, false);
+        let mut server = TestServer::new(TestServer::<PermanentWarmup>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        server.client_mut(alice).join(Team::Red);
+        server.run(100);
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/obstacle cone");
+        server.run(10);
+        assert_eq!(server.server().rink().obstacles.len(), 1);
+
+        // Everyone leaves, so the game is reset
+        server.disconnect(alice);
+        server.run(10);
+        assert_eq!(server.server.game_id, 2);
+        assert!(server.server().rink().obstacles.is_empty());
+    }
+
+    #[test]
+    fn abandoned_games() {
+        // Returns the game ID after everyone has left for a second and someone has come back
+        let game_after_abandoning = |abandon_policy| {
+            let config = ServerConfiguration {
+                abandon_policy,
+                ..TestServer::<PracticeGameMode>::configuration()
+            };
+            let mut server = TestServer::new(config, PracticeGameMode::new(1, SpawnPoint::Center));
+            let alice = server.connect("Alice");
+            server.run(10);
+            server.disconnect(alice);
+            server.run(100);
+            server.connect("Bob");
+            server.run(10);
+            (server.server.game_id, server.server.shutdown_timer)
+        };
+        assert_eq!(game_after_abandoning(AbandonPolicy::Reset), (2, None));
+        assert_eq!(game_after_abandoning(AbandonPolicy::Keep(6000)), (1, None));
+        assert_eq!(game_after_abandoning(AbandonPolicy::Keep(50)), (2, None));
+        assert_eq!(game_after_abandoning(AbandonPolicy::Shutdown), (1, Some(0)));
+        assert_eq!(
+            AbandonPolicy::parse("keep 10"),
+            Some(AbandonPolicy::Keep(60000))
+        );
+        assert_eq!(AbandonPolicy::parse("keep"), None);
+        assert_eq!(AbandonPolicy::parse("Warmup"), Some(AbandonPolicy::Warmup));
+    }
+
+    #[test]
+    fn abandoned_match_held_in_warmup() {
+        let config = ServerConfiguration {
+            abandon_policy: AbandonPolicy::Warmup,
+            ..TestServer::<StandardMatchGameMode>::configuration()
+        };
+        let match_config = MatchConfiguration {
+            time_warmup: 1,
+            time_intermission: 1,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(match_config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(config, game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        assert!(server.run_until(1000, |server| server.client(alice).scoreboard().period == 1));
+
+        server.disconnect(alice);
+        server.disconnect(bob);
+        server.run(10);
+        assert_eq!(server.server.game_id, 2);
+
+        // The warmup doesn't end by itself, even with players in both teams
+        let carol = server.connect("Carol");
+        let dave = server.connect("Dave");
+        server.client_mut(carol).join(Team::Red);
+        server.client_mut(dave).join(Team::Blue);
+        server.run(1000);
+        assert!(server
+            .client(carol)
+            .has_chat_message("Warmup until an admin starts the game"));
+        assert_eq!(server.client(carol).scoreboard().period, 0);
+
+        server.client_mut(carol).say("/admin test");
+        server.client_mut(carol).say("/start");
+        assert!(server.run_until(1000, |server| server.client(carol).scoreboard().period == 1));
+        assert_eq!(server.server.game_id, 2);
+    }
+
+    #[test]
+    fn scheduled_start() {
+        let new_server = || {
+            let match_config = MatchConfiguration {
+                time_intermission: 1,
+                scheduled_start: StartTime::parse("20:00 UTC"),
+                ..Default::default()
+            };
+            let game_mode = StandardMatchGameMode::new(match_config, 5, SpawnPoint::Center);
+            let mut server = TestServer::new(
+                TestServer::<StandardMatchGameMode>::configuration(),
+                game_mode,
+            );
+            server.server.simulated_clock = Utc.with_ymd_and_hms(2024, 3, 1, 19, 58, 30).single();
+            let alice = server.connect("Alice");
+            let bob = server.connect("Bob");
+            server.client_mut(alice).join(Team::Red);
+            server.client_mut(bob).join(Team::Blue);
+            server.run(100);
+            (server, alice)
+        };
+
+        // The warmup clock counts down to the start on the server clock
+        let (mut server, alice) = new_server();
+        let time = server.client(alice).scoreboard().time;
+        assert!((8800..9000).contains(&time), "{}", time);
+        assert!(!server.run_until(8700, |server| server.client(alice).scoreboard().period == 1));
+        assert!(server
+            .client(alice)
+            .has_chat_message("The game starts in 1 minute"));
+        assert!(server.run_until(500, |server| server.client(alice).scoreboard().period == 1));
+        assert!(server
+            .client(alice)
+            .has_chat_message("The scheduled game is starting"));
+
+        // Starting the game early cancels the schedule
+        let (mut server, alice) = new_server();
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/start");
+        assert!(server.run_until(500, |server| server.client(alice).scoreboard().period == 1));
+    }
+
+    #[test]
+    fn forfeit_countdown_stops_with_the_clock() {
+        let match_config = MatchConfiguration {
+            time_warmup: 1,
+            time_intermission: 1,
+            min_players: 1,
+            forfeit_time: 2,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(match_config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        assert!(server.run_until(1000, |server| {
+            let scoreboard = server.client(alice).scoreboard();
+            scoreboard.period == 1 && scoreboard.time > 0
+        }));
+
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/pause");
+        server.run(10);
+        server.disconnect(bob);
+        server.run(500);
+        assert!(!server.client(alice).scoreboard().game_over);
+        assert!(!server
+            .client(alice)
+            .has_chat_message("Blue has too few players and will forfeit in 2 seconds"));
+
+        server.client_mut(alice).say("/unpause");
+        assert!(server.run_until(1000, |server| server.client(alice).scoreboard().game_over));
+        assert!(server
+            .client(alice)
+            .has_chat_message("Blue forfeits the game"));
+    }
+
+    #[test]
+    fn switching_game_modes() {
+        let modes: Vec<(String, Box<dyn GameMode>)> = vec![
+            (
+                "match".to_owned(),
+                Box::new(StandardMatchGameMode::new(
+                    MatchConfiguration::default(),
+                    5,
+                    SpawnPoint::Center,
+                )),
+            ),
+            (
+                "practice".to_owned(),
+                Box::new(PracticeGameMode::new(1, SpawnPoint::Center)),
+            ),
+        ];
+        let game_mode = RotationGameMode::new(modes, &["match"]).unwrap();
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        server.run(10);
+        server.client_mut(alice).say("/mode practice");
+        server.run(10);
+        assert!(!server
+            .client(alice)
+            .has_chat_message("Game mode changed to practice"));
+
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/mode practice");
+        server.run(10);
+        assert!(server
+            .client(alice)
+            .has_chat_message("Game mode changed to practice"));
+        assert_eq!(server.server.game_id, 2);
+        // Practice has no warmup, so the clock doesn't count down
+        let time = server.client(alice).scoreboard().time;
+        server.run(100);
+        assert_eq!(server.client(alice).scoreboard().time, time);
+
+        server.client_mut(alice).say("/mode curling");
+        server.run(10);
+        assert!(server
+            .client(alice)
+            .chat_messages()
+            .any(|x| x.starts_with("Unknown game mode")));
+    }
+
+    #[test]
+    fn rotating_after_a_game() {
+        let config = MatchConfiguration {
+            time_warmup: 1,
+            time_period: 1,
+            time_intermission: 1,
+            time_break: 1,
+            periods: 1,
+            overtime: OvertimeConfiguration::None,
+            ..Default::default()
+        };
+        let modes: Vec<(String, Box<dyn GameMode>)> = vec![
+            (
+                "match".to_owned(),
+                Box::new(StandardMatchGameMode::new(config, 5, SpawnPoint::Center)),
+            ),
+            (
+                "practice".to_owned(),
+                Box::new(PracticeGameMode::new(1, SpawnPoint::Center)),
+            ),
+        ];
+        let game_mode = RotationGameMode::new(modes, &["match", "practice"]).unwrap();
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        assert!(server.run_until(5000, |server| server
+            .client(alice)
+            .has_chat_message("Game mode changed to practice")));
+        // The game started by the match is played as practice, not replaced by another one
+        assert_eq!(server.server.game_id, 2);
+    }
+
+    #[test]
+    fn switching_profiles() {
+        let profile = |name: &str, periods| {
+            let config = MatchConfiguration {
+                time_warmup: 1,
+                time_period: 1,
+                time_intermission: 1,
+                time_break: 1,
+                periods,
+                overtime: OvertimeConfiguration::None,
+                ..Default::default()
+            };
+            Profile {
+                name: name.to_owned(),
+                game_mode: Box::new(StandardMatchGameMode::new(config, 5, SpawnPoint::Center)),
+                physics: Default::default(),
+            }
+        };
+        let game_mode =
+            ProfileGameMode::new(vec![profile("short", 1), profile("long", 3)]).unwrap();
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/profile long");
+        assert!(server.run_until(5000, |server| server
+            .client(alice)
+            .has_chat_message("Profile changed to long")));
+        // The game that the previous profile started is played with the new profile
+        assert_eq!(server.server.game_id, 2);
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        assert!(server.run_until(1000, |server| server.client(alice).scoreboard().period == 1));
+        assert_eq!(server.server.game_id, 2);
+    }
+
+    #[test]
+    fn practice_pucks_belong_to_players() {
+        let game_mode = PracticeGameMode::new(3, SpawnPoint::Center);
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        server.run(100);
+        let puck_count = |server: &mut TestServer<PracticeGameMode>| {
+            server.server_mut().pucks().iter().flatten().count()
+        };
+        assert_eq!(puck_count(&mut server), 1);
+
+        // When the ice is full, a new puck replaces the oldest puck of the same player
+        for _ in 0..3 {
+            server.client_mut(alice).say("/puck");
+            server.run(10);
+        }
+        assert_eq!(puck_count(&mut server), 3);
+        server.client_mut(bob).say("/puck");
+        server.run(10);
+        assert!(server
+            .client(bob)
+            .has_chat_message("There is no room for more pucks"));
+
+        server.client_mut(bob).say("/clearpucks");
+        server.run(10);
+        assert_eq!(puck_count(&mut server), 3);
+        server.client_mut(alice).say("/clearpucks");
+        server.run(10);
+        assert_eq!(puck_count(&mut server), 1);
+
+        server.client_mut(bob).say("/admin test");
+        server.client_mut(bob).say("/clearpucks");
+        server.run(10);
+        assert_eq!(puck_count(&mut server), 0);
+    }
+
+    #[test]
+    fn puck_speed_of_the_closest_puck() {
+        let game_mode = PracticeGameMode::new(2, SpawnPoint::Center);
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        server.client_mut(alice).join(Team::Red);
+        server.run(100);
+        let skater_pos = server
+            .server()
+            .players()
+            .iter()
+            .find_map(|player| player.skater().map(|(_, skater)| skater.body.pos))
+            .unwrap();
+
+        // A fast puck in the first slot far away, and a resting puck next to the skater
+        let mut far_puck = Puck::new(Point3::new(15.0, 5.0, 15.0), Rotation3::identity());
+        far_puck.body.linear_velocity = Vector3::new(0.0, 0.5, 0.0);
+        let near_puck = Puck::new(
+            Point3::new(skater_pos.x + 1.0, 0.2, skater_pos.z),
+            Rotation3::identity(),
+        );
+        let mut server_mut = server.server_mut();
+        let pucks = server_mut.pucks_mut();
+        pucks[0] = Some(far_puck);
+        pucks[1] = Some(near_puck);
+        server.client_mut(alice).say("/puckspeed");
+        server.run(1);
+        assert!(server
+            .client(alice)
+            .has_chat_message("Puck speed: 0.0 km/h"));
+
+        server.client_mut(alice).say("/speed");
+        server.run(1);
+        assert!(server.client(alice).has_chat_message("Your speed: "));
+    }
+
+    #[test]
+    fn drifting_client_is_resynced() {
+        let mut server = TestServer::new(
+            TestServer::<PracticeGameMode>::configuration(),
+            PracticeGameMode::new(1, SpawnPoint::Center),
+        );
+        let alice = server.connect("Alice");
+        server.run(100);
+        assert!(server.client(alice).is_delta_compressed());
+
+        // A client that stops acknowledging packets still gets delta updates while its ping explains the gap
+        server.client_mut(alice).acknowledge_packets = false;
+        server.run(40);
+        assert!(server.client(alice).is_delta_compressed());
+        assert!(server.run_until(300, |server| !server.client(alice).is_delta_compressed()));
+
+        // Full updates are sent until the client acknowledges a packet sent after the resync
+        server.run(10);
+        assert!(!server.client(alice).is_delta_compressed());
+        server.client_mut(alice).acknowledge_packets = true;
+        assert!(server.run_until(10, |server| server.client(alice).is_delta_compressed()));
+    }
+
+    #[test]
+    fn shutdown_makes_clients_leave() {
+        let mut server = TestServer::new(
+            TestServer::<PracticeGameMode>::configuration(),
+            PracticeGameMode::new(1, SpawnPoint::Center),
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.run(100);
+        assert!(server.client(alice).player("Alice").is_some());
+        assert!(server.client(bob).player("Alice").is_some());
+
+        server.shut_down();
+        for client in [alice, bob] {
+            assert_eq!(server.client(client).players().count(), 0);
+        }
+    }
+
+    #[test]
+    fn stick_rotation_cap() {
+        let physics_config = PhysicsConfiguration {
+            max_stick_rotation: Some(0.05),
+            ..Default::default()
+        };
+        let mut server = TestServer::with_physics(
+            TestServer::<PracticeGameMode>::configuration(),
+            physics_config,
+            PracticeGameMode::new(1, SpawnPoint::Center),
+        );
+        let alice = server.connect("Alice");
+        server.client_mut(alice).join(Team::Red);
+        server.run(100);
+        let stick_angle = |server: &TestServer<PracticeGameMode>| {
+            server
+                .server()
+                .players()
+                .iter()
+                .find_map(|player| player.skater().map(|(_, skater)| skater.stick_angle))
+                .unwrap()
+        };
+        assert_eq!(stick_angle(&server), 0.0);
+
+        // A flip of the blade is applied over several ticks
+        server.client_mut(alice).input.stick_angle = 1.0;
+        server.run(1);
+        let max_angle_change = 0.05 / std::f32::consts::FRAC_PI_4;
+        assert!((stick_angle(&server) - max_angle_change).abs() < 1e-6);
+        server.run(20);
+        assert_eq!(stick_angle(&server), 1.0);
+    }
+
+    #[test]
+    fn fast_stick_reports() {
+        let config = ServerConfiguration {
+            stick_report_speed: Some(0.3),
+            ..TestServer::<PracticeGameMode>::configuration()
+        };
+        let mut server = TestServer::new(config, PracticeGameMode::new(1, SpawnPoint::Center));
+        let admin = server.connect("Admin");
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(admin).say("/admin test");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        server.run(100);
+
+        // Bob sends a packet every fourth tick, so his stick moves four ticks' worth between packets
+        server.client_mut(bob).input_interval = 4;
+        for i in 0..100 {
+            let step = i % 40;
+            let step = if step < 20 { step } else { 40 - step };
+            server.client_mut(bob).input.stick_angle = -1.0 + 0.1 * step as f32;
+            server.client_mut(alice).input.stick_angle = if i % 2 == 0 { 1.0 } else { -1.0 };
+            server.run(1);
+        }
+        assert!(server
+            .client(admin)
+            .has_chat_message("Suspiciously fast stick from Alice"));
+        assert!(!server
+            .client(admin)
+            .has_chat_message("Suspiciously fast stick from Bob"));
+    }
+
+    #[tokio::test]
+    async fn warmup_layouts() {
+        let dir = std::env::temp_dir().join(format!("layouts_{}", std::process::id()));
+        let warmup = |pucks| {
+            let game_mode =
+                PermanentWarmup::with_layouts(pucks, SpawnPoint::Center, false, false, dir.clone());
+            let mut server =
+                TestServer::new(TestServer::<PermanentWarmup>::configuration(), game_mode);
+            let admin = server.connect("Admin");
+            server.client_mut(admin).say("/admin test");
+            server.run(10);
+            (server, admin)
+        };
+
+        // A layout can be loaded right after it is saved, before the file has been written
+        let (mut server, admin) = warmup(3);
+        server.client_mut(admin).say("/layout save drill");
+        server.client_mut(admin).say("/layout load drill");
+        server.run(3);
+        assert!(server
+            .client(admin)
+            .has_chat_message("Layout drill loaded by Admin"));
+        for handle in server.game_mode.take_pending_saves() {
+            handle.await.unwrap();
+        }
+
+        // The file is read in the background, and the pucks that don't fit are reported
+        let (mut server, admin) = warmup(1);
+        server.client_mut(admin).say("/layout load drill");
+        let mut loaded = false;
+        for _ in 0..100 {
+            server.run(1);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            if server
+                .client(admin)
+                .has_chat_message("2 of the 3 pucks of the layout did not fit")
+            {
+                loaded = true;
+                break;
+            }
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(loaded);
+    }
+
+    #[test]
+    fn maintenance_keeps_players_off_the_ice() {
+        let game_mode = PracticeGameMode::new(1, SpawnPoint::Center);
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.run(10);
+        server.client_mut(bob).say("/admin test");
+        server.run(10);
+        server.client_mut(bob).say("/maintenance on");
+        server.run(10);
+        assert!(server
+            .client(alice)
+            .has_chat_message("All players have been moved to the spectators"));
+        assert_eq!(server.client(alice).player("Alice").unwrap().team, None);
+
+        server.client_mut(alice).join(Team::Blue);
+        server.run(100);
+        assert_eq!(server.client(alice).player("Alice").unwrap().team, None);
+
+        server.client_mut(bob).say("/maintenance maybe");
+        server.run(10);
+        assert!(server
+            .client(bob)
+            .has_chat_message("Usage: /maintenance on|off"));
+
+        // Like other players who have left the ice, Alice has to wait a moment before joining again
+        server.client_mut(bob).say("/maintenance off");
+        server.client_mut(alice).join(Team::Blue);
+        server.run(600);
+        assert_eq!(
+            server.client(alice).player("Alice").unwrap().team,
+            Some(Team::Blue)
+        );
+    }
+
+    #[test]
+    fn spectating_with_spec() {
+        let game_mode = StandardMatchGameMode::new(Default::default(), 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(bob).say("/chatextend on");
+        server.client_mut(alice).join(Team::Red);
+        server.run(10);
+        assert_eq!(
+            server.client(alice).player("Alice").unwrap().team,
+            Some(Team::Red)
+        );
+
+        // The game mode sees Alice leave the ice like any other spectator
+        server.client_mut(alice).say("/spec");
+        server.run(10);
+        assert_eq!(server.client(alice).player("Alice").unwrap().team, None);
+        assert!(server.client(bob).has_chat_message("Alice is spectating"));
+
+        server.client_mut(alice).join(Team::Red);
+        server.run(600);
+        assert_eq!(server.client(alice).player("Alice").unwrap().team, None);
+
+        server.client_mut(alice).say("/spec");
+        server.run(10);
+        assert_eq!(
+            server.client(alice).player("Alice").unwrap().team,
+            Some(Team::Red)
+        );
+    }
+
+    #[test]
+    fn clips() {
+        let mut config = TestServer::<PracticeGameMode>::configuration();
+        let mut server =
+            TestServer::new(config.clone(), PracticeGameMode::new(1, SpawnPoint::Center));
+        let alice = server.connect("Alice");
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/clip");
+        server.run(10);
+        assert!(server.client(alice).has_chat_message("Clips are disabled"));
+
+        config.clip_max = 5;
+        let mut server = TestServer::new(config, PracticeGameMode::new(1, SpawnPoint::Center));
+        let alice = server.connect("Alice");
+        server.client_mut(alice).join(Team::Red);
+        server.run(200);
+        let bob = server.connect("Bob");
+        server.run(800);
+
+        // Only the last clip_max seconds are kept in memory
+        let (long_clip, ticks) = server.server.clip_recording_data(60 * 100).unwrap();
+        assert_eq!(ticks, 500);
+        let (short_clip, ticks) = server.server.clip_recording_data(2 * 100).unwrap();
+        assert_eq!(ticks, 200);
+        assert!(short_clip.len() < long_clip.len());
+
+        server.client_mut(bob).say("/admin test");
+        server.client_mut(bob).say("/clip 60");
+        server.run(10);
+        assert!(server
+            .client(bob)
+            .has_chat_message("Saved a clip of the last 5 seconds"));
+    }
+
+    #[test]
+    fn replay_options() {
+        let game_mode = PracticeGameMode::new(1, SpawnPoint::Center);
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        let carol = server.connect("Carol");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        server.server_mut().replay_mut().set_history_length(1000);
+        server.run(300);
+
+        let player_id = |server: &TestServer<PracticeGameMode>, name: &str| {
+            server
+                .server()
+                .players()
+                .iter()
+                .find(|player| &*player.name() == name)
+                .unwrap()
+                .id
+        };
+        let (alice_id, bob_id, carol_id) = (
+            player_id(&server, "Alice"),
+            player_id(&server, "Bob"),
+            player_id(&server, "Carol"),
+        );
+        let carol_view = server.client(carol).view_player_index();
+        let end_step = server.server().replay().game_step();
+        let options = ReplayOptions {
+            slow_motion: 2,
+            cameras: vec![alice_id, bob_id],
+            skip_players: vec![carol_id],
+        };
+        server
+            .server_mut()
+            .replay_mut()
+            .add_replay_with_options(end_step - 99, end_step, &options);
+
+        // Each of the 100 ticks is shown twice, first following Alice and then Bob
+        let mut steps = vec![];
+        for _ in 0..7 {
+            server.run(1);
+            steps.push(server.client(bob).game_step());
+        }
+        let first = steps.iter().position(|&step| step != steps[0]).unwrap();
+        assert_eq!(steps[first], steps[first + 1]);
+        assert_eq!(steps[first + 2], steps[first] + 1);
+        assert_eq!(server.client(bob).view_player_index(), alice_id.index.0);
+        server.run(200);
+        assert_eq!(server.client(bob).view_player_index(), bob_id.index.0);
+        assert_eq!(server.client(alice).view_player_index(), bob_id.index.0);
+
+        // Carol keeps her own view and sees the game as it was before the replay, without delta compression
+        assert_eq!(server.client(carol).view_player_index(), carol_view);
+        assert!(!server.client(carol).is_delta_compressed());
+        assert!(server.server().replay().is_in_replay());
+
+        server.run(200);
+        assert!(!server.server().replay().is_in_replay());
+        assert!(server.client(bob).game_step() > end_step);
+    }
+
+    struct Exits(Rc<RefCell<Vec<(PlayerId, ExitReason)>>>);
+
+    impl GameModeLayer for Exits {
+        fn before_player_exit(
+            &mut self,
+            _server: ServerMut,
+            player_id: PlayerId,
+            reason: ExitReason,
+        ) {
+            self.0.borrow_mut().push((player_id, reason));
+        }
+    }
+
+    #[test]
+    fn banned_player_exits_once() {
+        let exits = Rc::new(RefCell::new(vec![]));
+        let game_mode = LayeredGameMode::new(PracticeGameMode::new(1, SpawnPoint::Center))
+            .with(Exits(exits.clone()));
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).say("/admin test");
+        server.run(10);
+        let (bob_index, _) = server
+            .client(alice)
+            .players()
+            .find(|(_, player)| player.name == "Bob")
+            .unwrap();
+        server
+            .client_mut(alice)
+            .say(&format!("/tempban {} 10m griefing", bob_index));
+        server.run(10);
+        assert!(server
+            .client(bob)
+            .has_chat_message("You have been banned for 10 min: griefing"));
+        server.run(400);
+        assert!(server.client(alice).player("Bob").is_none());
+        assert_eq!(exits.borrow().len(), 1);
+        assert_eq!(exits.borrow()[0].1, ExitReason::AdminKicked);
+    }
+
+    #[test]
+    fn lively_boards() {
+        // Shoots the puck at the side boards, and returns how fast it comes back
+        let rebound_speed = |board_zones| {
+            let mut config = TestServer::<PracticeGameMode>::configuration();
+            config.board_zones = board_zones;
+            let game_mode = PracticeGameMode::new(1, SpawnPoint::Center);
+            let mut server = TestServer::new(config, game_mode);
+            server.connect("Alice");
+            let mut puck = Puck::new(Point3::new(2.0, 0.2, 30.0), Rotation3::identity());
+            puck.body.linear_velocity = Vector3::new(-0.1, 0.0, 0.0);
+            server.server_mut().pucks_mut()[0] = Some(puck);
+            server.run(50);
+            let puck = server
+                .server()
+                .pucks()
+                .iter()
+                .flatten()
+                .next()
+                .unwrap()
+                .clone();
+            puck.body.linear_velocity.x
+        };
+        let zone = |stiffness| BoardZone {
+            min: Point3::new(-1.0, -1.0, 20.0),
+            max: Point3::new(1.0, 5.0, 40.0),
+            stiffness,
+            friction: None,
+        };
+        let normal = rebound_speed(vec![]);
+        let lively = rebound_speed(vec![zone(2.0)]);
+        let dead = rebound_speed(vec![zone(0.5)]);
+        assert!(normal > 0.0);
+        assert!(lively > normal, "{} {}", lively, normal);
+        assert!(dead < normal, "{} {}", dead, normal);
+    }
+
+    #[test]
+    fn admin_rules_kept_across_periods() {
+        let rules = PeriodRules {
+            offside: Some(OffsideConfiguration::Delayed),
+            icing: Some(IcingConfiguration::Touch),
+            ..Default::default()
+        };
+        let config = MatchConfiguration {
+            time_warmup: 1,
+            time_period: 1,
+            time_intermission: 1,
+            period_rules: [(2, rules)].into(),
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        assert!(server.run_until(1000, |server| server.client(alice).scoreboard().period == 1));
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/icing notouch");
+
+        let config = |server: &TestServer<StandardMatchGameMode>| {
+            let config = &server.game_mode().m.config;
+            (config.offside, config.icing)
+        };
+        assert!(server.run_until(1000, |server| server.client(alice).scoreboard().period == 2));
+        assert_eq!(
+            config(&server),
+            (OffsideConfiguration::Delayed, IcingConfiguration::NoTouch)
+        );
+        assert!(server.run_until(1000, |server| server.client(alice).scoreboard().period == 3));
+        assert_eq!(
+            config(&server),
+            (OffsideConfiguration::Off, IcingConfiguration::NoTouch)
+        );
+    }
+
+    #[test]
+    fn reset_warmup_score() {
+        let game_mode = PermanentWarmup::new(1, SpawnPoint::Center, true, false);
+        let mut server = TestServer::new(TestServer::<PermanentWarmup>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        server.server_mut().scoreboard_mut().red_score = 2;
+        server.client_mut(alice).say("/resetscore");
+        server.run(10);
+        assert_eq!(server.server().scoreboard().red_score, 2);
+
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/resetscore");
+        server.run(10);
+        assert_eq!(server.server().scoreboard().red_score, 0);
+        assert!(server
+            .client(alice)
+            .has_chat_message("Score reset by Alice"));
+    }
+
+    #[test]
+    fn command_cooldown_per_player() {
+        let mut config = TestServer::<PermanentWarmup>::configuration();
+        config.commands.insert(
+            "rules".to_owned(),
+            CommandConfiguration {
+                cooldown: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        );
+        let game_mode = PermanentWarmup::new(1, SpawnPoint::Center, false, false);
+        let mut server = TestServer::new(config, game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.clients[bob.0].addr.set_ip([127, 0, 0, 2].into());
+        server.client_mut(alice).say("/rules");
+        server.client_mut(alice).say("/rules");
+        server.client_mut(bob).say("/rules");
+        server.run(10);
+        assert!(server
+            .client(alice)
+            .has_chat_message("/rules can be used again"));
+        assert!(!server
+            .client(bob)
+            .has_chat_message("/rules can be used again"));
+    }
+
+    #[test]
+    fn bot_goalies_in_warmup() {
+        let game_mode = PermanentWarmup::new(1, SpawnPoint::Center, false, false);
+        let goalies = GoalieBotLayer::new(Default::default(), &[Team::Red, Team::Blue]);
+        let game_mode = LayeredGameMode::new(game_mode).with(goalies);
+        let mut server = TestServer::new(TestServer::<PermanentWarmup>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        server.run(100);
+        let goalies: Vec<_> = server
+            .server()
+            .players()
+            .iter()
+            .filter(|player| &*player.name() == "Goalie bot")
+            .filter_map(|player| {
+                player
+                    .skater()
+                    .map(|(team, skater)| (team, skater.body.pos))
+            })
+            .collect();
+        assert_eq!(goalies.len(), 2);
+        for (team, pos) in goalies {
+            let net = server.server().rink().net(team).center();
+            assert!((pos - net).xz().norm() < 3.0, "{:?} {}", team, pos);
+        }
+        assert_eq!(server.client(alice).players().count(), 3);
+    }
+
+    #[test]
+    fn no_hit_when_spawned() {
+        let config = MatchConfiguration {
+            time_warmup: 1,
+            time_intermission: 1,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).join(Team::Red);
+        server.client_mut(bob).join(Team::Blue);
+        assert!(server.run_until(1000, |server| {
+            let scoreboard = server.client(alice).scoreboard();
+            scoreboard.period == 1 && scoreboard.time > 0 && scoreboard.time < 29900
+        }));
+
+        // Alice skates fast far from Bob, and is then spawned right next to him
+        let find = |server: &TestServer<StandardMatchGameMode>, name: &str| {
+            let s = server.server();
+            let players = s.players();
+            let player = players.iter().find(|p| &*p.name() == name).unwrap();
+            (player.id, player.skater().unwrap().1.body.pos)
+        };
+        let (alice_id, _) = find(&server, "Alice");
+        let mut s = server.server_mut();
+        let mut players = s.players_mut();
+        let mut player = players.get_mut(alice_id).unwrap();
+        let (_, skater) = player.skater_mut().unwrap();
+        skater.body.linear_velocity = Vector3::new(0.2, 0.0, 0.0);
+        server.run(1);
+        let (_, bob_pos) = find(&server, "Bob");
+        let pos = bob_pos + Vector3::new(1.0, 0.0, 0.0);
+        server.server_mut().players_mut().spawn_skater(
+            alice_id,
+            Team::Red,
+            pos,
+            Rotation3::identity(),
+            false,
+        );
+        server.run(1);
+        assert!(server
+            .server
+            .state
+            .players
+            .stats
+            .get("Bob")
+            .is_none_or(|line| line.hits == 0));
+    }
+
+    #[test]
+    fn tick_messages_cleared_when_empty() {
+        let game_mode = PermanentWarmup::new(1, SpawnPoint::Center, false, false);
+        let mut server = TestServer::new(TestServer::<PermanentWarmup>::configuration(), game_mode);
+        for _ in 0..10 {
+            let mut s = server.server_mut();
+            s.players_mut().add_server_chat_message("Nobody is here");
+            server.run(1);
+        }
+        assert!(server.server.state.players.tick_messages.is_empty());
+    }
+
+    #[test]
+    fn league_off_restores_rules() {
+        let config = MatchConfiguration {
+            offside: OffsideConfiguration::Off,
+            icing: IcingConfiguration::Off,
+            ..Default::default()
+        };
+        let game_mode = StandardMatchGameMode::new(config, 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        let alice = server.connect("Alice");
+        server.client_mut(alice).say("/admin test");
+        server.client_mut(alice).say("/league on");
+        server.run(10);
+        assert_eq!(server.game_mode.m.config.icing, IcingConfiguration::Touch);
+
+        server.client_mut(alice).say("/league off");
+        server.run(10);
+        let config = &server.game_mode.m.config;
+        assert_eq!(config.offside, OffsideConfiguration::Off);
+        assert_eq!(config.icing, IcingConfiguration::Off);
+    }
+
+    #[test]
+    fn kicked_player_sees_reason() {
+        let game_mode = PermanentWarmup::new(1, SpawnPoint::Center, false, false);
+        let mut server = TestServer::new(TestServer::<PermanentWarmup>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        let bob = server.connect("Bob");
+        server.client_mut(alice).say("/admin test");
+        server.run(10);
+        let (bob_index, _) = server
+            .client(alice)
+            .players()
+            .find(|(_, p)| p.name == "Bob")
+            .unwrap();
+        server
+            .client_mut(alice)
+            .say(&format!("/kick {} griefing", bob_index));
+        server.run(10);
+        assert!(server
+            .client(bob)
+            .has_chat_message("You have been kicked: griefing"));
+
+        server.run(400);
+        assert!(server.client(alice).players().all(|(_, p)| p.name != "Bob"));
+    }
+
+    #[test]
+    fn removed_at_closing_time() {
+        let exits = Rc::new(RefCell::new(vec![]));
+        let game_mode = LayeredGameMode::new(PracticeGameMode::new(1, SpawnPoint::Center))
+            .with(Exits(exits.clone()));
+        let mut server =
+            TestServer::new(TestServer::<PracticeGameMode>::configuration(), game_mode);
+        let alice = server.connect("Alice");
+        server.run(10);
+        server
+            .server
+            .handle_schedule_change(ScheduleState::Closed { opens: None }, &mut server.game_mode);
+        server.run(400);
+        assert!(server
+            .client(alice)
+            .has_chat_message("The server is closed"));
+        assert_eq!(exits.borrow().len(), 1);
+        assert_eq!(exits.borrow()[0].1, ExitReason::ServerClosed);
+    }
+
+    #[test]
+    fn remote_penalty_usage() {
+        let game_mode = StandardMatchGameMode::new(Default::default(), 5, SpawnPoint::Center);
+        let mut server = TestServer::new(
+            TestServer::<StandardMatchGameMode>::configuration(),
+            game_mode,
+        );
+        server.connect("Alice");
+        server.run(10);
+        let command = |server: &mut TestServer<StandardMatchGameMode>, cmd, arg| {
+            server
+                .game_mode
+                .handle_remote_command((&mut server.server).into(), cmd, arg, "Admin")
+        };
+        let usage = Some(Err(
+            "Usage: /penalty ID [seconds], for a player on the ice during the game",
+        ));
+        // Unknown player, and a player that isn't on the ice
+        assert_eq!(command(&mut server, "penalty", "9"), usage);
+        assert_eq!(command(&mut server, "penalty", "0"), usage);
+        assert_eq!(command(&mut server, "unknown", "0"), None);
+    }
+}
@@ -0,0 +1,393 @@
+//! Player votes.
+//!
+//! Players start a vote with `/vote <type> [arg]`, and vote with `/vote yes` and `/vote no`. The server
+//! handles the `kick`, `reset` and `pause` votes itself. Game modes can add their own vote types by
+//! implementing [`VoteHandler`] and returning it from
+//! [`GameMode::vote_handler`](crate::gamemode::GameMode::vote_handler).
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tracing::info;
+
+use crate::game::{PlayerId, PlayerIndex};
+use crate::gamemode::{ExitReason, GameMode, Server, ServerMut};
+use crate::server::{HQMServer, PlayerListExt, ServerPlayerData};
+
+/// Handles vote types added by a game mode.
+pub trait VoteHandler {
+    /// Returns true if the game mode handles votes of this type.
+    fn supports_vote(&self, vote_type: &str) -> bool;
+
+    /// Called when a player starts a vote of a supported type.
+    ///
+    /// Returns the text shown in chat, like "end the warmup", or an error message for the player
+    /// if the vote can't be started, for example because `arg` is invalid.
+    fn describe_vote(
+        &mut self,
+        server: Server,
+        vote_type: &str,
+        arg: &str,
+    ) -> Result<String, String>;
+
+    /// Called when a vote has passed.
+    fn vote_passed(&mut self, server: ServerMut, vote_type: &str, arg: &str);
+}
+
+/// Which players can vote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoteEligibility {
+    /// All connected players, including spectators.
+    #[default]
+    All,
+    /// Only players on a team. In kick votes, only the team mates of the player.
+    Team,
+}
+
+#[derive(Debug, Clone)]
+pub struct VoteConfiguration {
+    /// If false, /vote is disabled.
+    pub enabled: bool,
+    /// Share of the eligible players that have to vote yes for a vote to pass.
+    pub threshold: f32,
+    /// Thresholds for specific vote types, by vote type.
+    pub thresholds: HashMap<String, f32>,
+    /// How long a vote is open.
+    pub duration: Duration,
+    /// Minimum time between the end of one vote and the start of the next. Admins are not affected.
+    pub cooldown: Duration,
+    pub eligibility: VoteEligibility,
+}
+
+impl Default for VoteConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 0.5,
+            thresholds: HashMap::new(),
+            duration: Duration::from_secs(30),
+            cooldown: Duration::from_secs(60),
+            eligibility: VoteEligibility::All,
+        }
+    }
+}
+
+enum VoteKind {
+    Kick(PlayerId),
+    Reset,
+    Pause,
+    Custom,
+}
+
+struct Vote {
+    vote_type: String,
+    arg: String,
+    kind: VoteKind,
+    description: String,
+    started: DateTime<Utc>,
+    votes: HashMap<PlayerId, bool>,
+}
+
+#[derive(Default)]
+pub(crate) struct VoteState {
+    current: Option<Vote>,
+    last_ended: Option<DateTime<Utc>>,
+}
+
+/// Smallest number of yes votes needed to kick a player, so that nobody can be kicked by one player alone.
+const MIN_KICK_VOTES: usize = 2;
+
+impl HQMServer {
+    /// Handles /vote.
+    pub(crate) fn vote<B: GameMode + ?Sized>(
+        &mut self,
+        player_id: PlayerId,
+        arg: &str,
+        behaviour: &mut B,
+    ) {
+        if !self.config.votes.enabled {
+            self.state
+                .players
+                .add_directed_server_chat_message("Votes are disabled", player_id);
+            return;
+        }
+        let (vote_type, vote_arg) = arg
+            .trim()
+            .split_once(' ')
+            .map_or((arg.trim(), ""), |(a, b)| (a, b.trim()));
+        match vote_type {
+            "yes" | "y" => self.cast_vote(player_id, true, behaviour),
+            "no" | "n" => self.cast_vote(player_id, false, behaviour),
+            "" => {
+                let msg = match &self.votes.current {
+                    Some(vote) => format!("Vote to {}, /vote yes or /vote no", vote.description),
+                    None => "Usage: /vote kick ID, /vote reset, /vote pause".to_owned(),
+                };
+                self.state
+                    .players
+                    .add_directed_server_chat_message(msg, player_id);
+            }
+            _ => self.start_vote(player_id, vote_type, vote_arg, behaviour),
+        }
+    }
+
+    fn start_vote<B: GameMode + ?Sized>(
+        &mut self,
+        player_id: PlayerId,
+        vote_type: &str,
+        arg: &str,
+        behaviour: &mut B,
+    ) {
+        let Some(player) = self.state.players.players.get_player(player_id) else {
+            return;
+        };
+        let is_admin = player.is_admin;
+        let player_name = player.player_name.clone();
+        if self.votes.current.is_some() {
+            self.state
+                .players
+                .add_directed_server_chat_message("A vote is already in progress", player_id);
+            return;
+        }
+        if !is_admin
+            && self
+                .votes
+                .last_ended
+                .is_some_and(|x| self.time_since(x) < self.config.votes.cooldown)
+        {
+            self.state.players.add_directed_server_chat_message(
+                "Please wait before starting another vote",
+                player_id,
+            );
+            return;
+        }
+
+        let res = match vote_type {
+            "kick" => self.describe_kick_vote(player_id, arg),
+            "reset" => Ok((VoteKind::Reset, "reset the game".to_owned())),
+            "pause" => Ok((VoteKind::Pause, "pause the game".to_owned())),
+            _ => match behaviour.vote_handler() {
+                Some(handler) if handler.supports_vote(vote_type) => handler
+                    .describe_vote((&*self).into(), vote_type, arg)
+                    .map(|description| (VoteKind::Custom, description)),
+                _ => Err(format!("Unknown vote type {}", vote_type)),
+            },
+        };
+        let (kind, description) = match res {
+            Ok(x) => x,
+            Err(msg) => {
+                self.state
+                    .players
+                    .add_directed_server_chat_message(msg, player_id);
+                return;
+            }
+        };
+        if !self.is_eligible_voter(player_id, &kind) {
+            self.state
+                .players
+                .add_directed_server_chat_message("You can't vote on this", player_id);
+            return;
+        }
+
+        info!(
+            "{} ({}) started vote to {}",
+            player_name, player_id, description
+        );
+        let msg = format!("{} started vote to {}", player_name, description);
+        self.state.players.add_server_chat_message(msg);
+        self.state
+            .players
+            .add_server_chat_message("Type /vote yes or /vote no");
+
+        self.votes.current = Some(Vote {
+            vote_type: vote_type.to_owned(),
+            arg: arg.to_owned(),
+            kind,
+            description,
+            started: self.now(),
+            votes: HashMap::new(),
+        });
+        self.cast_vote(player_id, true, behaviour);
+    }
+
+    fn describe_kick_vote(
+        &self,
+        player_id: PlayerId,
+        arg: &str,
+    ) -> Result<(VoteKind, String), String> {
+        let target = arg
+            .parse::<PlayerIndex>()
+            .ok()
+            .and_then(|index| self.state.players.players.get_player_by_index(index));
+        match target {
+            Some((target_id, target))
+                if matches!(target.data, ServerPlayerData::NetworkPlayer { .. }) =>
+            {
+                if target_id == player_id {
+                    Err("You can't start a vote to kick yourself".to_owned())
+                } else if target.is_admin {
+                    Err("Admins can't be kicked by vote".to_owned())
+                } else {
+                    Ok((
+                        VoteKind::Kick(target_id),
+                        format!("kick {}", target.player_name),
+                    ))
+                }
+            }
+            _ => Err("Usage: /vote kick ID".to_owned()),
+        }
+    }
+
+    fn is_eligible_voter(&self, player_id: PlayerId, kind: &VoteKind) -> bool {
+        let Some(player) = self.state.players.players.get_player(player_id) else {
+            return false;
+        };
+        if !matches!(player.data, ServerPlayerData::NetworkPlayer { .. }) {
+            return false;
+        }
+        // The player who might be kicked doesn't get a say
+        if matches!(kind, VoteKind::Kick(target_id) if *target_id == player_id) {
+            return false;
+        }
+        match self.config.votes.eligibility {
+            VoteEligibility::All => true,
+            VoteEligibility::Team => match kind {
+                VoteKind::Kick(target_id) => {
+                    let target_team = self
+                        .state
+                        .players
+                        .players
+                        .get_player(*target_id)
+                        .and_then(|x| x.team());
+                    player.team().is_some() && player.team() == target_team
+                }
+                _ => player.team().is_some(),
+            },
+        }
+    }
+
+    fn cast_vote<B: GameMode + ?Sized>(
+        &mut self,
+        player_id: PlayerId,
+        yes: bool,
+        behaviour: &mut B,
+    ) {
+        let Some(vote) = &self.votes.current else {
+            self.state
+                .players
+                .add_directed_server_chat_message("No vote in progress", player_id);
+            return;
+        };
+        if !self.is_eligible_voter(player_id, &vote.kind) {
+            self.state
+                .players
+                .add_directed_server_chat_message("You can't vote on this", player_id);
+            return;
+        }
+        if let Some(vote) = &mut self.votes.current {
+            vote.votes.insert(player_id, yes);
+        }
+        self.update_vote(behaviour);
+    }
+
+    /// Time since `time` by the server clock, or zero if `time` is in the future.
+    fn time_since(&self, time: DateTime<Utc>) -> Duration {
+        (self.now() - time).to_std().unwrap_or_default()
+    }
+
+    /// Checks if the current vote has passed, failed or timed out.
+    pub(crate) fn update_vote<B: GameMode + ?Sized>(&mut self, behaviour: &mut B) {
+        let Some(vote) = &self.votes.current else {
+            return;
+        };
+        let eligible: Vec<PlayerId> = self
+            .state
+            .players
+            .players
+            .iter_players()
+            .map(|(player_id, _)| player_id)
+            .filter(|player_id| self.is_eligible_voter(*player_id, &vote.kind))
+            .collect();
+        let threshold = self
+            .config
+            .votes
+            .thresholds
+            .get(&vote.vote_type)
+            .copied()
+            .unwrap_or(self.config.votes.threshold);
+        let min_votes = match vote.kind {
+            VoteKind::Kick(_) => MIN_KICK_VOTES,
+            _ => 1,
+        };
+        let required = ((eligible.len() as f32 * threshold).ceil() as usize).max(min_votes);
+        let (yes, no) = vote
+            .votes
+            .iter()
+            .filter(|(player_id, _)| eligible.contains(player_id))
+            .fold(
+                (0, 0),
+                |(yes, no), (_, v)| {
+                    if *v {
+                        (yes + 1, no)
+                    } else {
+                        (yes, no + 1)
+                    }
+                },
+            );
+
+        let passed = yes >= required;
+        let failed = no > eligible.len().saturating_sub(required)
+            || eligible.len() < required
+            || self.time_since(vote.started) >= self.config.votes.duration;
+        if !passed && !failed {
+            return;
+        }
+        let Some(vote) = self.votes.current.take() else {
+            return;
+        };
+        self.votes.last_ended = Some(self.now());
+        info!(
+            "Vote to {} {} ({} yes, {} no)",
+            vote.description,
+            if passed { "passed" } else { "failed" },
+            yes,
+            no
+        );
+        if !passed {
+            let msg = format!("Vote to {} failed", vote.description);
+            self.state.players.add_server_chat_message(msg);
+            return;
+        }
+        let msg = format!("Vote to {} passed", vote.description);
+        self.state.players.add_server_chat_message(msg);
+
+        match vote.kind {
+            VoteKind::Kick(target_id) => {
+                if let Some(target) = self.state.players.players.get_player(target_id) {
+                    let target_name = target.player_name.clone();
+                    behaviour.before_player_exit(self.into(), target_id, ExitReason::VoteKicked);
+                    self.remove_player(target_id, true);
+                    let msg = format!("{} kicked by vote", target_name);
+                    self.state.players.add_server_chat_message(msg);
+                }
+            }
+            VoteKind::Reset => {
+                self.new_game(behaviour.get_initial_game_values());
+            }
+            VoteKind::Pause => {
+                if behaviour.handle_remote_command(self.into(), "pause", "", "vote") != Some(Ok(()))
+                {
+                    self.state
+                        .players
+                        .add_server_chat_message("The game can't be paused");
+                }
+            }
+            VoteKind::Custom => {
+                if let Some(handler) = behaviour.vote_handler() {
+                    handler.vote_passed(self.into(), &vote.vote_type, &vote.arg);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,622 @@
+//! WebSocket interface for monitoring and administrating the server remotely.
+//!
+//! When `webadmin_port` is set, the server accepts WebSocket connections on that TCP port. Clients
+//! log in by sending the text message `login PASSWORD` with the administrator password. After that,
+//! they receive the server state (players, scoreboard, pucks and recent chat) as JSON text messages,
+//! and can send commands like `kick 3` or `pause` as text messages. Each command is answered with a
+//! JSON reply that tells if the command succeeded. After a few failed logins, further logins from the
+//! same IP address are refused for a while.
+//!
+//! The WebSocket protocol is implemented here directly, as only a small part of it is needed.
+//! Fragmented messages and extensions are not supported.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use parking_lot::Mutex;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{info, warn};
+
+use crate::admin_commands::KickOptions;
+use crate::game::{PlayerId, PlayerIndex, Team};
+use crate::gamemode::GameMode;
+use crate::json::{JsonObjectWriter, ToJson};
+use crate::observer::{GameObserver, ObservedMessage, ObservedObject, ObservedTick};
+use crate::server::{HQMServer, MuteStatus, PlayerListExt};
+
+/// Ticks between state updates sent to the clients.
+const UPDATE_INTERVAL: u32 = 10;
+
+/// Number of chat messages included in the state.
+const CHAT_HISTORY_LENGTH: usize = 20;
+
+/// Largest accepted frame from a client. Commands are short, so anything larger is an error.
+const MAX_FRAME_LENGTH: u64 = 4096;
+
+/// Failed logins from one IP address before further logins are refused.
+const MAX_FAILED_LOGINS: u32 = 5;
+
+/// How long logins are refused after too many failures.
+const LOGIN_LOCKOUT: Duration = Duration::from_secs(600);
+
+/// Delay before answering a failed login, to slow down guessing.
+const FAILED_LOGIN_DELAY: Duration = Duration::from_secs(2);
+
+/// Name shown in chat messages for actions done through the web admin interface.
+const WEB_ADMIN_NAME: &str = "Web admin";
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// A command from an authenticated web admin client, handled by the server between ticks.
+pub(crate) struct WebAdminCommand {
+    peer: SocketAddr,
+    command: String,
+    arg: String,
+    reply: oneshot::Sender<Result<(), &'static str>>,
+}
+
+/// Failed logins of each IP address, shared by all connections.
+#[derive(Default)]
+struct LoginLimiter {
+    failures: HashMap<IpAddr, (u32, Instant)>,
+}
+
+impl LoginLimiter {
+    /// Returns true if logins from `ip` are refused because of earlier failures.
+    fn is_locked_out(&mut self, ip: IpAddr, now: Instant) -> bool {
+        match self.failures.get(&ip) {
+            Some(&(_, last)) if now.duration_since(last) >= LOGIN_LOCKOUT => {
+                self.failures.remove(&ip);
+                false
+            }
+            Some(&(count, _)) => count >= MAX_FAILED_LOGINS,
+            None => false,
+        }
+    }
+
+    fn failed(&mut self, ip: IpAddr, now: Instant) {
+        self.failures
+            .retain(|_, (_, last)| now.duration_since(*last) < LOGIN_LOCKOUT);
+        let entry = self.failures.entry(ip).or_insert((0, now));
+        entry.0 += 1;
+        entry.1 = now;
+    }
+
+    fn succeeded(&mut self, ip: IpAddr) {
+        self.failures.remove(&ip);
+    }
+}
+
+fn password_matches(entered: &str, password: &str) -> bool {
+    ring::constant_time::verify_slices_are_equal(entered.as_bytes(), password.as_bytes()).is_ok()
+}
+
+/// Starts listening for web admin connections on `address`.
+///
+/// Returns the observer that produces the state sent to the clients. Commands from the clients are sent to `commands`.
+pub(crate) async fn start(
+    address: SocketAddr,
+    password: String,
+    commands: mpsc::Sender<WebAdminCommand>,
+) -> io::Result<WebAdminState> {
+    let listener = TcpListener::bind(address).await?;
+    info!(
+        "Web admin interface listening at address {:?}",
+        listener.local_addr()?
+    );
+    let (sender, receiver) = watch::channel(String::new());
+    let password: Arc<str> = password.into();
+    let logins = Arc::new(Mutex::new(LoginLimiter::default()));
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Could not accept web admin connection: {}", e);
+                    continue;
+                }
+            };
+            let password = password.clone();
+            let logins = logins.clone();
+            let state = receiver.clone();
+            let commands = commands.clone();
+            tokio::spawn(async move {
+                let result =
+                    handle_connection(stream, peer, password, logins, state, commands).await;
+                if let Err(e) = result {
+                    info!("Web admin connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok(WebAdminState {
+        players: HashMap::new(),
+        chat: VecDeque::new(),
+        ticks: 0,
+        sender,
+    })
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    password: Arc<str>,
+    logins: Arc<Mutex<LoginLimiter>>,
+    mut state: watch::Receiver<String>,
+    commands: mpsc::Sender<WebAdminCommand>,
+) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let key = tokio::time::timeout(Duration::from_secs(10), read_handshake(&mut read))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Handshake timed out"))?;
+    let key = match key {
+        Ok(key) => key,
+        Err(e) => {
+            let _ = write
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                .await;
+            return Err(e);
+        }
+    };
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    );
+    write.write_all(response.as_bytes()).await?;
+
+    // Frames are read in a separate task, as reading a frame can't be cancelled halfway
+    let (frame_sender, mut frames) = mpsc::channel(4);
+    tokio::spawn(async move {
+        loop {
+            let frame = read_frame(&mut read).await;
+            let end = !matches!(frame, Ok(Frame::Text(_) | Frame::Ping(_) | Frame::Other));
+            if frame_sender.send(frame).await.is_err() || end {
+                break;
+            }
+        }
+    });
+
+    let mut logged_in = false;
+    loop {
+        tokio::select! {
+            frame = frames.recv() => {
+                let text = match frame {
+                    Some(Ok(Frame::Text(text))) => text,
+                    Some(Ok(Frame::Ping(payload))) => {
+                        write_frame(&mut write, OPCODE_PONG, &payload).await?;
+                        continue;
+                    }
+                    Some(Ok(Frame::Other)) => continue,
+                    Some(Ok(Frame::Close)) | None => {
+                        let _ = write_frame(&mut write, OPCODE_CLOSE, &[]).await;
+                        return Ok(());
+                    }
+                    Some(Err(e)) => return Err(e),
+                };
+                let (command, arg) = text.trim().split_once(' ').unwrap_or((text.trim(), ""));
+                let result = if command == "login" {
+                    if logins.lock().is_locked_out(peer.ip(), Instant::now()) {
+                        info!("Web admin login from {} refused after too many failed logins", peer);
+                        write_reply(&mut write, Err("Too many failed logins, try again later")).await?;
+                        let _ = write_frame(&mut write, OPCODE_CLOSE, &[]).await;
+                        return Ok(());
+                    } else if password_matches(arg, &password) {
+                        info!("Web admin logged in from {}", peer);
+                        logins.lock().succeeded(peer.ip());
+                        logged_in = true;
+                        // Send the current state right away
+                        state.mark_changed();
+                        Ok(())
+                    } else {
+                        info!("Web admin login from {} failed, entered wrong password", peer);
+                        logins.lock().failed(peer.ip(), Instant::now());
+                        tokio::time::sleep(FAILED_LOGIN_DELAY).await;
+                        write_reply(&mut write, Err("Wrong administrator password")).await?;
+                        let _ = write_frame(&mut write, OPCODE_CLOSE, &[]).await;
+                        return Ok(());
+                    }
+                } else if !logged_in {
+                    Err("Not logged in")
+                } else {
+                    let (reply, reply_receiver) = oneshot::channel();
+                    let command = WebAdminCommand {
+                        peer,
+                        command: command.to_owned(),
+                        arg: arg.to_owned(),
+                        reply,
+                    };
+                    if commands.send(command).await.is_err() {
+                        return Ok(());
+                    }
+                    reply_receiver.await.unwrap_or(Err("Server is shutting down"))
+                };
+                write_reply(&mut write, result).await?;
+            }
+            res = state.changed(), if logged_in => {
+                if res.is_err() {
+                    return Ok(());
+                }
+                let json = state.borrow_and_update().clone();
+                if !json.is_empty() {
+                    write_frame(&mut write, OPCODE_TEXT, json.as_bytes()).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Reads the HTTP upgrade request and returns the value of the `Sec-WebSocket-Key` header.
+async fn read_handshake<R: AsyncRead + Unpin>(read: &mut BufReader<R>) -> io::Result<String> {
+    let mut key = None;
+    let mut line = String::new();
+    for _ in 0..64 {
+        line.clear();
+        if read.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return key.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Not a WebSocket request")
+            });
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_owned());
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Request headers too long",
+    ))
+}
+
+fn accept_key(key: &str) -> String {
+    let mut context = ring::digest::Context::new(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY);
+    context.update(key.as_bytes());
+    context.update(WEBSOCKET_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(context.finish())
+}
+
+enum Frame {
+    Text(String),
+    Ping(Vec<u8>),
+    Close,
+    /// Frames we don't care about, like pongs and binary messages.
+    Other,
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(read: &mut R) -> io::Result<Frame> {
+    let mut header = [0u8; 2];
+    read.read_exact(&mut header).await?;
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+    let len = match header[1] & 0x7F {
+        126 => read.read_u16().await? as u64,
+        127 => read.read_u64().await?,
+        len => len as u64,
+    };
+    // Frames from clients must always be masked
+    if !masked || len > MAX_FRAME_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid frame"));
+    }
+    let mut mask = [0u8; 4];
+    read.read_exact(&mut mask).await?;
+    let mut payload = vec![0u8; len as usize];
+    read.read_exact(&mut payload).await?;
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+    Ok(match opcode {
+        OPCODE_TEXT => Frame::Text(
+            String::from_utf8(payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        ),
+        OPCODE_CLOSE => Frame::Close,
+        OPCODE_PING => Frame::Ping(payload),
+        _ => Frame::Other,
+    })
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(
+    write: &mut W,
+    opcode: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        frame.push(126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        frame.push(127);
+        frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    frame.extend_from_slice(payload);
+    write.write_all(&frame).await
+}
+
+async fn write_reply<W: AsyncWrite + Unpin>(
+    write: &mut W,
+    result: Result<(), &'static str>,
+) -> io::Result<()> {
+    let mut out = String::new();
+    JsonObjectWriter::new(&mut out)
+        .field("type", "reply")
+        .field("ok", &result.is_ok())
+        .field("error", &result.err())
+        .finish();
+    write_frame(write, OPCODE_TEXT, out.as_bytes()).await
+}
+
+struct WebAdminPlayer {
+    name: String,
+    team: Option<Team>,
+}
+
+struct WebAdminChatMessage {
+    player_name: Option<String>,
+    message: String,
+}
+
+/// Observer that collects the state sent to the web admin clients.
+pub(crate) struct WebAdminState {
+    players: HashMap<PlayerId, WebAdminPlayer>,
+    chat: VecDeque<WebAdminChatMessage>,
+    ticks: u32,
+    sender: watch::Sender<String>,
+}
+
+impl GameObserver for WebAdminState {
+    fn tick(&mut self, tick: &ObservedTick) {
+        for message in tick.messages() {
+            match message {
+                ObservedMessage::PlayerUpdate {
+                    player_id,
+                    player_name: Some(name),
+                    object,
+                } => {
+                    self.players.insert(
+                        player_id,
+                        WebAdminPlayer {
+                            name: name.to_owned(),
+                            team: object.map(|(_, team)| team),
+                        },
+                    );
+                }
+                ObservedMessage::PlayerUpdate {
+                    player_id,
+                    player_name: None,
+                    ..
+                } => {
+                    self.players.remove(&player_id);
+                }
+                ObservedMessage::Chat { player_id, message } => {
+                    let player_name = player_id
+                        .and_then(|player_id| self.players.get(&player_id))
+                        .map(|player| player.name.clone());
+                    if self.chat.len() >= CHAT_HISTORY_LENGTH {
+                        self.chat.pop_front();
+                    }
+                    self.chat.push_back(WebAdminChatMessage {
+                        player_name,
+                        message: message.to_owned(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        self.ticks += 1;
+        if self.ticks < UPDATE_INTERVAL {
+            return;
+        }
+        self.ticks = 0;
+
+        let mut players: Vec<_> = self.players.iter().collect();
+        players.sort_by_key(|(player_id, _)| player_id.index().get());
+        let players: Vec<_> = players
+            .into_iter()
+            .map(|(player_id, player)| PlayerJson(player_id.index(), player))
+            .collect();
+        let pucks: Vec<_> = tick
+            .objects()
+            .filter_map(|(_, object)| match object {
+                ObservedObject::Puck { pos, .. } => Some(vec![pos.x, pos.y, pos.z]),
+                _ => None,
+            })
+            .collect();
+        let chat: Vec<_> = self.chat.iter().collect();
+
+        let scoreboard = tick.scoreboard();
+        let mut out = String::new();
+        JsonObjectWriter::new(&mut out)
+            .field("type", "state")
+            .field("game_id", &tick.game_id())
+            .field("period", &scoreboard.period)
+            .field("time", &scoreboard.time)
+            .field("red_score", &scoreboard.red_score)
+            .field("blue_score", &scoreboard.blue_score)
+            .field("game_over", &scoreboard.game_over)
+            .field("players", &players)
+            .field("pucks", &pucks)
+            .field("chat", &chat)
+            .finish();
+        self.sender.send_if_modified(|current| {
+            if *current != out {
+                *current = out;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+struct PlayerJson<'a>(PlayerIndex, &'a WebAdminPlayer);
+
+impl ToJson for PlayerJson<'_> {
+    fn write_json(&self, out: &mut String) {
+        let team = self.1.team.map(|team| match team {
+            Team::Red => "red",
+            Team::Blue => "blue",
+        });
+        JsonObjectWriter::new(out)
+            .field("index", &self.0.get())
+            .field("name", &self.1.name)
+            .field("team", &team)
+            .finish();
+    }
+}
+
+impl ToJson for WebAdminChatMessage {
+    fn write_json(&self, out: &mut String) {
+        JsonObjectWriter::new(out)
+            .field("player", &self.player_name)
+            .field("message", &self.message)
+            .finish();
+    }
+}
+
+impl HQMServer {
+    pub(crate) fn handle_web_admin_command<B: GameMode>(
+        &mut self,
+        command: WebAdminCommand,
+        behaviour: &mut B,
+    ) {
+        let WebAdminCommand {
+            peer,
+            command,
+            arg,
+            reply,
+        } = command;
+        let player_index = arg
+            .parse::<PlayerIndex>()
+            .map_err(|_| "Invalid player index");
+        let result = match command.as_str() {
+            "kick" | "ban" => {
+                // The player index can be followed by the duration of a ban and a reason
+                let (index, rest) = arg.split_once(' ').unwrap_or((&arg, ""));
+                index
+                    .parse::<PlayerIndex>()
+                    .map_err(|_| "Invalid player index")
+                    .and_then(|x| {
+                        let options = KickOptions::parse(command == "ban", rest)?;
+                        self.remote_kick(WEB_ADMIN_NAME, peer, x, options)
+                    })
+            }
+            "mute" => player_index.and_then(|x| self.web_admin_mute(peer, x, true)),
+            "unmute" => player_index.and_then(|x| self.web_admin_mute(peer, x, false)),
+            _ => match behaviour.handle_remote_command(self.into(), &command, &arg, WEB_ADMIN_NAME)
+            {
+                Some(Ok(())) => {
+                    info!("Web admin ({}) used command {} {}", peer, command, arg);
+                    Ok(())
+                }
+                Some(Err(e)) => Err(e),
+                None => Err("Unknown command"),
+            },
+        };
+        let _ = reply.send(result);
+    }
+
+    /// Kicks or bans a player on behalf of a remote administrator. `admin_name` is shown in chat.
+    pub(crate) fn remote_kick(
+        &mut self,
+        admin_name: &str,
+        peer: SocketAddr,
+        kick_player_index: PlayerIndex,
+        options: KickOptions,
+    ) -> Result<(), &'static str> {
+        let kick_player_id = self
+            .state
+            .players
+            .players
+            .get_player_by_index(kick_player_index)
+            .ok_or("No such player")?
+            .0;
+        self.kick(kick_player_id, admin_name, &peer, options)
+    }
+
+    fn web_admin_mute(
+        &mut self,
+        peer: SocketAddr,
+        mute_player_index: PlayerIndex,
+        mute: bool,
+    ) -> Result<(), &'static str> {
+        let (mute_player_id, mute_player) = self
+            .state
+            .players
+            .players
+            .get_player_mut_by_index(mute_player_index)
+            .ok_or("No such player")?;
+        let old_status = mute_player.is_muted;
+        let msg = if mute {
+            mute_player.is_muted = MuteStatus::Muted;
+            info!(
+                "Web admin ({}) muted {} ({})",
+                peer, mute_player.player_name, mute_player_id
+            );
+            format!("{} muted by {}", mute_player.player_name, WEB_ADMIN_NAME)
+        } else {
+            mute_player.is_muted = MuteStatus::NotMuted;
+            info!(
+                "Web admin ({}) unmuted {} ({})",
+                peer, mute_player.player_name, mute_player_id
+            );
+            format!("{} unmuted by {}", mute_player.player_name, WEB_ADMIN_NAME)
+        };
+        if mute || old_status == MuteStatus::Muted {
+            self.state.players.add_server_chat_message(msg);
+        }
+        let status = if mute {
+            MuteStatus::Muted
+        } else {
+            MuteStatus::NotMuted
+        };
+        self.store_mute(mute_player_id, status, None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_logins_lock_out() {
+        let mut logins = LoginLimiter::default();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let other = IpAddr::from([10, 0, 0, 2]);
+        let start = Instant::now();
+        for _ in 0..MAX_FAILED_LOGINS {
+            assert!(!logins.is_locked_out(ip, start));
+            logins.failed(ip, start);
+        }
+        assert!(logins.is_locked_out(ip, start));
+        assert!(!logins.is_locked_out(other, start));
+        assert!(!logins.is_locked_out(ip, start + LOGIN_LOCKOUT));
+
+        logins.failed(ip, start);
+        logins.succeeded(ip);
+        assert!(!logins.is_locked_out(ip, start));
+
+        assert!(password_matches("secret", "secret"));
+        assert!(!password_matches("secre", "secret"));
+    }
+}